@@ -0,0 +1,113 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Compares encoding and decoding through `Wrap` with the `parity-scale-codec` derives
+//!
+//! Run with `cargo bench --bench codec`. Each shape is benchmarked as `derive` (the type's own
+//! `Encode`/`Decode`) and `wrap` (its `Serialize`/`Deserialize` through `Wrap`). Decoding is also
+//! benchmarked with `decode_all`, which reads slices directly instead of going through `Input`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use parity_scale_codec::{Decode, Encode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_scale_wrap::{impl_infallible_serialize, Wrap};
+use std::{collections::BTreeMap, hint::black_box};
+
+#[derive(Debug, Decode, Deserialize, Encode, PartialEq, Serialize)]
+struct Header {
+    version: u8,
+    flags: u16,
+    height: u64,
+    parent: [u8; 32],
+    extra: Option<u32>,
+}
+
+impl_infallible_serialize!(Header { u8, u16, u64, [u8; 32], Option<u32> });
+
+#[derive(Debug, Decode, Deserialize, Encode, PartialEq, Serialize)]
+enum Event {
+    Transfer { from: [u8; 32], to: [u8; 32], amount: u64 },
+    Note(String),
+    Tick,
+}
+
+impl_infallible_serialize!(Event { [u8; 32], u64, String });
+
+#[derive(Debug, Decode, Deserialize, Encode, PartialEq, Serialize)]
+struct Block {
+    header: Header,
+    events: Vec<Event>,
+    tags: Vec<(u16, Vec<u8>)>,
+}
+
+impl_infallible_serialize!(Block { Header, Vec<Event>, Vec<(u16, Vec<u8>)> });
+
+fn integers() -> Vec<u64> {
+    (0..100_000u64).map(|i| i.wrapping_mul(0x9e37_79b9_7f4a_7c15)).collect()
+}
+
+fn strings() -> Vec<String> {
+    (0..10_000).map(|i| "s".repeat(i % 64)).collect()
+}
+
+fn block() -> Block {
+    let header = Header { version: 1, flags: 2, height: 1 << 40, parent: [7; 32], extra: None };
+    let events = (0..1000)
+        .map(|i| match i % 3 {
+            0 => Event::Transfer { from: [i as u8; 32], to: [!(i as u8); 32], amount: i },
+            1 => Event::Note(format!("note number {}", i)),
+            _ => Event::Tick,
+        })
+        .collect();
+    let tags = (0..64).map(|i| (i, vec![i as u8; i as usize])).collect();
+    Block { header, events, tags }
+}
+
+fn map() -> BTreeMap<u32, Vec<u8>> {
+    (0..10_000u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761), vec![i as u8; (i % 16) as usize]))
+        .collect()
+}
+
+fn bench_shape<T>(c: &mut Criterion, shape: &str, value: &T)
+where
+    T: Decode + DeserializeOwned + Encode + PartialEq + Serialize,
+    for<'a> Wrap<&'a T>: Encode,
+{
+    let encoded = value.encode();
+    assert_eq!(Wrap(value).encode(), encoded);
+    assert!(Wrap::<T>::decode(&mut &*encoded).unwrap().0 == *value);
+    assert!(serde_scale_wrap::decode_all::<T>(&encoded).unwrap() == *value);
+
+    let mut group = c.benchmark_group("encode");
+    group.throughput(Throughput::Bytes(encoded.len() as u64));
+    group.bench_function(BenchmarkId::new("derive", shape), |b| {
+        b.iter(|| black_box(value).encode())
+    });
+    group.bench_function(BenchmarkId::new("wrap", shape), |b| {
+        b.iter(|| Wrap(black_box(value)).encode())
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Bytes(encoded.len() as u64));
+    group.bench_function(BenchmarkId::new("derive", shape), |b| {
+        b.iter(|| T::decode(&mut black_box(&*encoded)).unwrap())
+    });
+    group.bench_function(BenchmarkId::new("wrap", shape), |b| {
+        b.iter(|| Wrap::<T>::decode(&mut black_box(&*encoded)).unwrap())
+    });
+    group.bench_function(BenchmarkId::new("decode_all", shape), |b| {
+        b.iter(|| serde_scale_wrap::decode_all::<T>(black_box(&encoded)).unwrap())
+    });
+    group.finish();
+}
+
+fn codec(c: &mut Criterion) {
+    bench_shape(c, "vec_u64", &integers());
+    bench_shape(c, "vec_string", &strings());
+    bench_shape(c, "nested_struct", &block());
+    bench_shape(c, "map", &map());
+}
+
+criterion_group!(benches, codec);
+criterion_main!(benches);
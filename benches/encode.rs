@@ -0,0 +1,78 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Measures the time to encode values to a `Vec<u8>`
+//!
+//! Run with `cargo bench --bench encode`.
+
+use parity_scale_codec::Encode;
+use serde::Serialize;
+use serde_scale_wrap::{impl_infallible_serialize, Wrap};
+use std::{
+    collections::BTreeMap,
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+#[derive(Serialize)]
+struct Header {
+    version: u8,
+    flags: u16,
+    height: u64,
+    parent: [u8; 32],
+    extra: Option<u32>,
+}
+
+impl_infallible_serialize!(Header { u8, u16, u64, [u8; 32], Option<u32> });
+
+#[derive(Serialize)]
+enum Event {
+    Transfer { from: [u8; 32], to: [u8; 32], amount: u64 },
+    Note(String),
+    Tick,
+}
+
+impl_infallible_serialize!(Event { [u8; 32], u64, String });
+
+#[derive(Serialize)]
+struct Block {
+    header: Header,
+    events: Vec<Event>,
+    tags: BTreeMap<u16, Vec<u8>>,
+}
+
+impl_infallible_serialize!(Block { Header, Vec<Event>, BTreeMap<u16, Vec<u8>> });
+
+fn block() -> Block {
+    let header = Header { version: 1, flags: 2, height: 1 << 40, parent: [7; 32], extra: None };
+    let events = (0..64)
+        .map(|i| match i % 3 {
+            0 => Event::Transfer { from: [i as u8; 32], to: [!(i as u8); 32], amount: i },
+            1 => Event::Note(format!("note number {}", i)),
+            _ => Event::Tick,
+        })
+        .collect();
+    let tags = (0..16).map(|i| (i, vec![i as u8; i as usize])).collect();
+    Block { header, events, tags }
+}
+
+fn bench<T: Encode>(name: &str, value: &T) {
+    let mut iterations = 1u32;
+    loop {
+        let start = Instant::now();
+        for _ in 0..iterations {
+            black_box(black_box(value).encode());
+        }
+        let elapsed = start.elapsed();
+        if elapsed >= Duration::from_millis(500) {
+            println!("{}: {:?}/iter", name, elapsed / iterations);
+            break;
+        }
+        iterations *= 2;
+    }
+}
+
+fn main() {
+    let header = Header { version: 1, flags: 2, height: 3, parent: [4; 32], extra: Some(5) };
+    bench("header", &Wrap(header));
+    bench("block", &Wrap(block()));
+}
@@ -0,0 +1,12 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+#![no_main]
+
+use serde_scale_wrap::fuzzing::fuzz_decode;
+use serde_scale_wrap_fuzz::Composite;
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    let _ = fuzz_decode::<Composite>(data);
+    let _ = fuzz_decode::<String>(data);
+    let _ = fuzz_decode::<Vec<Vec<u8>>>(data);
+});
@@ -0,0 +1,11 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+#![no_main]
+
+use arbitrary::Unstructured;
+use serde_scale_wrap::fuzzing::fuzz_roundtrip;
+use serde_scale_wrap_fuzz::Composite;
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    let _ = fuzz_roundtrip::<Composite>(&mut Unstructured::new(data));
+});
@@ -0,0 +1,32 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Types exercised by the fuzz targets
+
+use arbitrary::Arbitrary;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Arbitrary, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Composite {
+    pub id: u64,
+    pub name: String,
+    pub flag: Option<bool>,
+    pub payload: Vec<u8>,
+    pub entries: BTreeMap<u16, Entry>,
+    pub pair: (i8, char),
+    pub kind: Kind,
+}
+
+#[derive(Arbitrary, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Entry {
+    pub values: Vec<i32>,
+    pub note: Option<String>,
+}
+
+#[derive(Arbitrary, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Kind {
+    Unit,
+    Newtype(u32),
+    Tuple(u8, String),
+    Struct { a: bool, b: Vec<Option<u16>> },
+}
@@ -0,0 +1,514 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Adapters between `parity-scale-codec` and `serde-scale` streams
+//!
+//! [`OutputToWrite`] lets a `serde_scale::Serializer` write to an `Output`, and `InputToRead`,
+//! requiring the `alloc` feature, lets a `serde_scale::Deserializer` read from an `Input`. `Wrap`
+//! uses them to encode and decode.
+//!
+//! [`WriteToOutput`] and [`ReadToInput`] go the other way, letting types implementing
+//! `Encode`/`Decode` write to a `serde_scale::Write` and read from a `serde_scale::Read`.
+//!
+//! `OutputToWrite` and `WriteToOutput` require the `encode` feature, and `InputToRead` and
+//! `ReadToInput` the `decode` feature.
+//!
+//! Values (de)serialized with `serde_scale` directly are encoded as `serde_scale` does, without
+//! the workarounds `Wrap` applies to maps and struct variants.
+
+#[cfg(feature = "decode")]
+use crate::scale::Read;
+#[cfg(feature = "encode")]
+use crate::scale::Write;
+#[cfg(feature = "encode")]
+use core::convert::Infallible;
+#[cfg(feature = "decode")]
+use core::marker::PhantomData;
+#[cfg(feature = "decode")]
+use parity_scale_codec::Input;
+#[cfg(feature = "encode")]
+use parity_scale_codec::Output;
+
+#[cfg(all(feature = "alloc", feature = "decode"))]
+use crate::{checked::read_checked, scale::Bytes, DecodeBuffer};
+
+/// `serde_scale::Write` writing to an `Output`
+///
+/// Bytes are forwarded to the output as they are written, without buffering. Writing cannot fail
+/// as `Output::write` is infallible.
+///
+/// Several values can be serialized back to back into the same output:
+///
+/// ```rust
+/// use serde::Serialize;
+/// use serde_scale_wrap::{adapters::OutputToWrite, serde_scale};
+///
+/// let mut encoded = Vec::new();
+/// let mut serializer = serde_scale::Serializer::new(OutputToWrite::new(&mut encoded));
+/// 7u8.serialize(&mut serializer).unwrap();
+/// "foo".serialize(&mut serializer).unwrap();
+/// assert_eq!(encoded, b"\x07\x0cfoo");
+/// ```
+#[cfg(feature = "encode")]
+#[derive(Debug)]
+pub struct OutputToWrite<'a, O: ?Sized>(&'a mut O);
+
+#[cfg(feature = "encode")]
+impl<'a, O: Output + ?Sized> OutputToWrite<'a, O> {
+    /// Returns a `Write` writing to `output`
+    pub fn new(output: &'a mut O) -> Self {
+        OutputToWrite(output)
+    }
+
+    /// Returns the underlying output
+    pub fn into_inner(self) -> &'a mut O {
+        self.0
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<O: Output + ?Sized> Write for OutputToWrite<'_, O> {
+    type Error = Infallible;
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Infallible> {
+        self.0.write(bytes);
+        Ok(())
+    }
+}
+
+/// `serde_scale::Read` reading from an `Input`
+///
+/// Errors from the input are returned as is. Reading more bytes than `Input::remaining_len`
+/// reports fails before anything is read, and reads after which the remaining length did not
+/// decrease by the number of bytes requested fail, as [`CheckedInput`](crate::CheckedInput) does.
+///
+/// `read_exact` reads straight into the destination. `read_map`, which deserializes strings and
+/// byte sequences, copies them to an internal [`DecodeBuffer`] reused across reads. This buffer
+/// grows as bytes are read rather than to the requested length upfront, so that a forged length
+/// prefix cannot trigger a huge allocation, and shrinks back once a read made it grow past its
+/// high-water mark.
+///
+/// Reads of zero bytes (e.g. of empty strings) succeed without touching the input or the
+/// buffer, so that values with an empty encoding (e.g. `()`) decode from an empty or exhausted
+/// input. Any other read past the end of the input fails with the error of the input, or with
+/// "Not enough data to fill buffer" if its remaining length is known to be too short.
+///
+/// ```rust
+/// use serde::Deserialize;
+/// use serde_scale_wrap::{adapters::InputToRead, serde_scale};
+///
+/// let mut input = &b"\x07\x0cfoo\x2a"[..];
+/// let mut deserializer = serde_scale::Deserializer::new(InputToRead::new(&mut input));
+/// let decoded = <(u8, String)>::deserialize(&mut deserializer).unwrap();
+/// assert_eq!(decoded, (7, "foo".to_owned()));
+/// assert_eq!(input, b"\x2a");
+/// ```
+#[cfg(all(feature = "alloc", feature = "decode"))]
+#[derive(Debug)]
+pub struct InputToRead<'a, I: ?Sized> {
+    input: &'a mut I,
+    buffer: DecodeBuffer,
+}
+
+#[cfg(all(feature = "alloc", feature = "decode"))]
+impl<'a, I: Input + ?Sized> InputToRead<'a, I> {
+    /// Returns a `Read` reading from `input`
+    pub fn new(input: &'a mut I) -> Self {
+        InputToRead { input, buffer: DecodeBuffer::new() }
+    }
+
+    /// Returns a mutable reference to the underlying input
+    pub fn get_mut(&mut self) -> &mut I {
+        self.input
+    }
+
+    /// Returns the underlying input
+    pub fn into_inner(self) -> &'a mut I {
+        self.input
+    }
+
+    #[cfg(all(test, feature = "encode"))]
+    pub(crate) fn buffer_capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+}
+
+#[cfg(all(feature = "alloc", feature = "decode"))]
+impl<'de, I: Input + ?Sized> Read<'de> for InputToRead<'_, I> {
+    type Error = parity_scale_codec::Error;
+
+    fn read_map<R, F>(&mut self, n: usize, f: F) -> Result<R, Self::Error>
+    where
+        F: FnOnce(Bytes<'de, '_>) -> R,
+    {
+        if n == 0 {
+            return Ok(f(Bytes::Temporary(&[])));
+        }
+        self.buffer.extend_from_input(self.input, n)?;
+        let result = f(Bytes::Temporary(self.buffer.as_unread_slice()));
+        self.buffer.consume(n);
+        Ok(result)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+        read_checked(self.input, buf)
+    }
+}
+
+/// `Output` writing to a `serde_scale::Write`
+///
+/// As `Output::write` cannot fail, the first error returned by the writer is stashed and any
+/// later write is ignored, so that the writer never receives an encoding with a hole in it. The
+/// error is returned by [`WriteToOutput::finish`].
+///
+/// ```rust
+/// use parity_scale_codec::Encode;
+/// use serde_scale_wrap::adapters::WriteToOutput;
+///
+/// let mut written = Vec::new();
+/// let mut output = WriteToOutput::new(&mut written);
+/// (7u8, 0x0302u16).encode_to(&mut output);
+/// output.finish()?;
+/// assert_eq!(written, [7, 2, 3]);
+/// # Ok::<_, core::convert::Infallible>(())
+/// ```
+#[cfg(feature = "encode")]
+#[derive(Debug)]
+pub struct WriteToOutput<W: Write> {
+    writer: W,
+    error: Option<W::Error>,
+}
+
+#[cfg(feature = "encode")]
+impl<W: Write> WriteToOutput<W> {
+    /// Returns an `Output` writing to `writer`
+    pub fn new(writer: W) -> Self {
+        WriteToOutput { writer, error: None }
+    }
+
+    /// Returns the first error returned by the writer, if any
+    pub fn finish(self) -> Result<(), W::Error> {
+        self.error.map_or(Ok(()), Err)
+    }
+
+    /// Returns a reference to the underlying writer
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Returns a mutable reference to the underlying writer
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<W: Write> Output for WriteToOutput<W> {
+    fn write(&mut self, bytes: &[u8]) {
+        if self.error.is_none() {
+            self.error = self.writer.write(bytes).err();
+        }
+    }
+}
+
+/// `Input` reading from a `serde_scale::Read`
+///
+/// The remaining length of the input is unknown. As `parity_scale_codec::Error` cannot hold the
+/// error returned by the reader, it is stashed and can be retrieved with
+/// [`ReadToInput::take_error`] after decoding fails.
+///
+/// ```rust
+/// use parity_scale_codec::Decode;
+/// use serde_scale_wrap::adapters::ReadToInput;
+///
+/// let mut input = ReadToInput::new(&b"\x07\x02\x03"[..]);
+/// assert_eq!(<(u8, u16)>::decode(&mut input)?, (7, 0x0302));
+/// assert!(u8::decode(&mut input).is_err());
+/// assert!(input.take_error().is_some());
+/// # Ok::<_, parity_scale_codec::Error>(())
+/// ```
+#[cfg(feature = "decode")]
+#[derive(Debug)]
+pub struct ReadToInput<'de, R: Read<'de>> {
+    reader: R,
+    error: Option<R::Error>,
+    marker: PhantomData<&'de ()>,
+}
+
+#[cfg(feature = "decode")]
+impl<'de, R: Read<'de>> ReadToInput<'de, R> {
+    /// Returns an `Input` reading from `reader`
+    pub fn new(reader: R) -> Self {
+        ReadToInput { reader, error: None, marker: PhantomData }
+    }
+
+    /// Returns the last error returned by the reader, if any, and forgets it
+    pub fn take_error(&mut self) -> Option<R::Error> {
+        self.error.take()
+    }
+
+    /// Returns a reference to the underlying reader
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Returns a mutable reference to the underlying reader
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Returns the underlying reader
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<'de, R: Read<'de>> Input for ReadToInput<'de, R> {
+    fn remaining_len(&mut self) -> Result<Option<usize>, parity_scale_codec::Error> {
+        Ok(None)
+    }
+
+    fn read(&mut self, into: &mut [u8]) -> Result<(), parity_scale_codec::Error> {
+        self.reader.read_exact(into).map_err(|e| {
+            self.error = Some(e);
+            "Failed to read from serde_scale::Read".into()
+        })
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::{OutputToWrite, ReadToInput, WriteToOutput};
+    use parity_scale_codec::{Compact, Decode, Encode, Output};
+    use serde::Serialize;
+    use crate::scale::{EndOfInput, Write};
+
+    struct CountingOutput(usize);
+
+    impl Output for CountingOutput {
+        fn write(&mut self, bytes: &[u8]) {
+            self.0 += bytes.len();
+        }
+    }
+
+    #[test]
+    fn bytes_are_forwarded_to_the_output() {
+        let mut output = CountingOutput(0);
+        let mut serializer = crate::scale::Serializer::new(OutputToWrite::new(&mut output));
+        (1u8, 2u32).serialize(&mut serializer).unwrap();
+        [3u16; 4].serialize(&mut serializer).unwrap();
+        assert_eq!(output.0, 13);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn reads_past_the_end_fail_without_consuming() {
+        use super::InputToRead;
+        use crate::scale::Read;
+
+        let mut input = &[1u8, 2, 3][..];
+        let mut read = InputToRead::new(&mut input);
+        assert!(read.read_exact(&mut [0; 4]).is_err());
+        assert!(read.read_map(4, |_| ()).is_err());
+        let mut buf = [0; 2];
+        read.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2]);
+        assert_eq!(read.read_map(1, |bytes| bytes[0]).unwrap(), 3);
+        assert!(read.into_inner().is_empty());
+    }
+
+    /// Input panicking when used
+    #[cfg(feature = "alloc")]
+    struct Untouchable;
+
+    #[cfg(feature = "alloc")]
+    impl parity_scale_codec::Input for Untouchable {
+        fn remaining_len(&mut self) -> Result<Option<usize>, parity_scale_codec::Error> {
+            panic!("Remaining length queried")
+        }
+
+        fn read(&mut self, _: &mut [u8]) -> Result<(), parity_scale_codec::Error> {
+            panic!("Input read")
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn zero_length_reads_do_not_touch_the_input_or_the_buffer() {
+        use super::InputToRead;
+        use crate::scale::Read;
+
+        let mut input = Untouchable;
+        let mut read = InputToRead::new(&mut input);
+        assert_eq!(read.read_map(0, |bytes| bytes.len()).unwrap(), 0);
+        read.read_exact(&mut []).unwrap();
+        assert_eq!(read.buffer_capacity(), 0);
+        let mut input = &[][..];
+        let mut read = InputToRead::new(&mut input);
+        assert_eq!(read.read_map(0, |bytes| bytes.len()).unwrap(), 0);
+        assert!(read.read_map(1, |_| ()).is_err());
+        assert!(read.read_exact(&mut [0]).is_err());
+        assert_eq!(read.buffer_capacity(), 0);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn empty_encodings_decode_from_empty_inputs() {
+        use crate::{decode_all, Wrap};
+        use alloc::{string::String, vec::Vec};
+        use core::marker::PhantomData;
+        use serde::Deserialize;
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Unit;
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Empty {}
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Nothing(#[serde(skip)] u8, (), [u32; 0]);
+
+        let mut input = &[][..];
+        Wrap::<()>::decode(&mut input).unwrap();
+        Wrap::<PhantomData<String>>::decode(&mut input).unwrap();
+        assert_eq!(Wrap::<Unit>::decode(&mut input).unwrap().0, Unit);
+        assert_eq!(Wrap::<Empty>::decode(&mut input).unwrap().0, Empty {});
+        assert_eq!(Wrap::<Nothing>::decode(&mut input).unwrap().0, Nothing(0, (), []));
+        assert_eq!(*Wrap::<Empty>::decode_boxed(&mut input).unwrap(), Empty {});
+        decode_all::<()>(&[]).unwrap();
+        decode_all::<PhantomData<Vec<u8>>>(&[]).unwrap();
+        assert_eq!(decode_all::<(Empty, Unit)>(&[]).unwrap(), (Empty {}, Unit));
+        assert!(decode_all::<()>(&[0]).is_err());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn non_empty_encodings_fail_on_empty_inputs() {
+        use crate::{decode_all, Error, Wrap};
+        use alloc::{string::String, vec::Vec};
+
+        let eof = parity_scale_codec::Error::from("Not enough data to fill buffer");
+        assert_eq!(Wrap::<u8>::decode(&mut &[][..]).unwrap_err(), eof);
+        assert_eq!(Wrap::<String>::decode(&mut &[][..]).unwrap_err(), eof);
+        assert_eq!(Wrap::<((), u32)>::decode(&mut &[][..]).unwrap_err(), eof);
+        let result = Wrap::<Vec<u8>>::decode_boxed(&mut &[][..]);
+        assert!(matches!(result, Err(Error::Input(e)) if e == eof));
+        assert!(matches!(decode_all::<u8>(&[]), Err(Error::Input(e)) if e == eof));
+        assert!(matches!(decode_all::<Option<()>>(&[]), Err(Error::Input(e)) if e == eof));
+    }
+
+    /// Input failing with a detailed error once its bytes are exhausted
+    #[cfg(feature = "alloc")]
+    struct FlakyLink<'a>(&'a [u8]);
+
+    #[cfg(feature = "alloc")]
+    impl FlakyLink<'_> {
+        fn error() -> parity_scale_codec::Error {
+            parity_scale_codec::Error::from("Carrier lost").chain("Link 3 dropped frame 42")
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    impl parity_scale_codec::Input for FlakyLink<'_> {
+        fn remaining_len(&mut self) -> Result<Option<usize>, parity_scale_codec::Error> {
+            Ok(None)
+        }
+
+        fn read(&mut self, into: &mut [u8]) -> Result<(), parity_scale_codec::Error> {
+            if into.len() > self.0.len() {
+                return Err(FlakyLink::error());
+            }
+            let (read, rest) = self.0.split_at(into.len());
+            into.copy_from_slice(read);
+            self.0 = rest;
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn input_errors_reach_the_caller_intact() {
+        use crate::{Error, Wrap};
+        use alloc::{string::String, vec::Vec};
+
+        type Value = (u32, String, Vec<u8>);
+
+        let encoded = crate::encode(&(7u32, String::from("frame"), [1u8, 2, 3].to_vec()));
+        for len in [2, 6, encoded.len() - 1] {
+            let result = Wrap::<Value>::decode(&mut FlakyLink(&encoded[..len]));
+            assert_eq!(result.unwrap_err(), FlakyLink::error());
+            let result = Wrap::<Value>::decode_boxed(&mut FlakyLink(&encoded[..len]));
+            assert!(matches!(result, Err(Error::Input(e)) if e == FlakyLink::error()));
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn input_error_details_are_observable() {
+        use crate::Wrap;
+        use alloc::string::{String, ToString};
+        use std::error::Error as _;
+
+        let encoded = crate::encode(&String::from("frame"));
+        let error = Wrap::<String>::decode(&mut FlakyLink(&encoded[..3])).unwrap_err();
+        assert!(error.to_string().starts_with("Link 3 dropped frame 42"), "{}", error);
+        assert_eq!(error.source().unwrap().to_string(), "Carrier lost");
+        let error = Wrap::<String>::decode_boxed(&mut FlakyLink(&encoded[..3])).unwrap_err();
+        assert!(error.to_string().contains("Link 3 dropped frame 42"), "{}", error);
+        assert_eq!(error.source().unwrap().to_string(), FlakyLink::error().to_string());
+    }
+
+    /// Writer accepting a limited number of bytes
+    struct LimitedWrite {
+        bytes: [u8; 8],
+        len: usize,
+    }
+
+    impl Write for LimitedWrite {
+        type Error = EndOfInput;
+
+        fn write(&mut self, data: &[u8]) -> Result<(), EndOfInput> {
+            let dst = self.bytes.get_mut(self.len..self.len + data.len()).ok_or(EndOfInput)?;
+            dst.copy_from_slice(data);
+            self.len += data.len();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn codec_values_roundtrip_through_serde_scale_streams() {
+        type Value = (u16, Compact<u32>, Option<bool>, [u8; 2]);
+
+        let value: Value = (0x0102, Compact(60), Some(false), [8, 9]);
+        let mut output = WriteToOutput::new(LimitedWrite { bytes: [0; 8], len: 0 });
+        value.encode_to(&mut output);
+        let written = output.get_ref().bytes;
+        assert_eq!(written[..output.get_ref().len], value.encode()[..]);
+        output.finish().unwrap();
+        let mut input = ReadToInput::new(&written[..]);
+        assert_eq!(Value::decode(&mut input).unwrap(), value);
+        assert!(input.take_error().is_none());
+    }
+
+    #[test]
+    fn write_errors_are_stashed_until_finish() {
+        let mut output = WriteToOutput::new(LimitedWrite { bytes: [0; 8], len: 0 });
+        0x0102_0304u32.encode_to(&mut output);
+        0x0506_0708_090au64.encode_to(&mut output);
+        1u8.encode_to(&mut output);
+        assert_eq!(output.get_ref().len, 4);
+        assert!(matches!(output.finish(), Err(EndOfInput)));
+    }
+
+    #[test]
+    fn read_errors_are_stashed() {
+        let mut input = ReadToInput::new(&[1, 2, 3][..]);
+        assert!(u32::decode(&mut input).is_err());
+        assert!(matches!(input.take_error(), Some(EndOfInput)));
+        assert!(input.take_error().is_none());
+        assert_eq!(input.into_inner(), [1, 2, 3]);
+    }
+}
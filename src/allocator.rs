@@ -0,0 +1,160 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+use crate::{
+    de, logging,
+    scale::{Bytes, Read},
+    Error, READ_CHUNK_LEN,
+};
+use alloc::vec::Vec;
+use core::alloc::Allocator;
+use parity_scale_codec::Input;
+use serde::de::DeserializeOwned;
+
+/// Decodes a `T` from `input` like `Wrap::<T>::decode`, allocating the scratch buffer holding
+/// strings and byte sequences as they are read from `alloc`
+///
+/// The scratch buffer is the only allocation made by this crate while decoding. The values
+/// decoded still allocate as their `Deserialize` implementations do (e.g. a `String` field
+/// allocates from the global allocator).
+///
+/// ```rust
+/// #![feature(allocator_api)]
+///
+/// use std::alloc::Global;
+///
+/// let mut input = &b"\x0cfoo\x07"[..];
+/// let decoded = serde_scale_wrap::decode_in::<(String, u8), _, _>(&mut input, Global)?;
+/// assert_eq!(decoded, ("foo".to_owned(), 7));
+/// # Ok::<_, serde_scale_wrap::Error>(())
+/// ```
+pub fn decode_in<T, I, A>(input: &mut I, alloc: A) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+    I: Input + ?Sized,
+    A: Allocator,
+{
+    logging::decode_logged(input, |input| {
+        let read = InputToReadIn { input, buffer: Vec::new_in(alloc) };
+        let mut deserializer = crate::scale::Deserializer::new(read);
+        T::deserialize(de::Deserializer::new(&mut deserializer))
+            .map_err(Error::from_deserialization)
+    })
+}
+
+/// `Read` reading from an `Input` like `InputToRead`, with a scratch buffer allocated from `A`
+struct InputToReadIn<'a, I: ?Sized, A: Allocator> {
+    input: &'a mut I,
+    buffer: Vec<u8, A>,
+}
+
+impl<'de, I: Input + ?Sized, A: Allocator> Read<'de> for InputToReadIn<'_, I, A> {
+    type Error = parity_scale_codec::Error;
+
+    fn read_map<R, F>(&mut self, n: usize, f: F) -> Result<R, Self::Error>
+    where
+        F: FnOnce(Bytes<'de, '_>) -> R,
+    {
+        if self.input.remaining_len()?.is_some_and(|remaining| n > remaining) {
+            return Err("Not enough data to fill buffer".into());
+        }
+        // `n` may come from a forged length prefix, so the buffer grows as bytes are actually
+        // read instead of being allocated upfront.
+        self.buffer.clear();
+        while self.buffer.len() < n {
+            let start = self.buffer.len();
+            let end = n.min(start + start.max(READ_CHUNK_LEN));
+            self.buffer.resize(end, 0);
+            self.input.read(&mut self.buffer[start..])?;
+        }
+        Ok(f(Bytes::Temporary(&self.buffer)))
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        if self.input.remaining_len()?.is_some_and(|remaining| buf.len() > remaining) {
+            return Err("Not enough data to fill buffer".into());
+        }
+        self.input.read(buf)
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::decode_in;
+    use crate::{encode, Error};
+    use alloc::{
+        alloc::{AllocError, Global},
+        string::String,
+        vec,
+        vec::Vec,
+    };
+    use core::{
+        alloc::{Allocator, Layout},
+        cell::Cell,
+        ptr::NonNull,
+    };
+    use serde::{Deserialize, Serialize};
+
+    /// Allocator forwarding to `Global` and counting allocations
+    #[derive(Default)]
+    struct CountingAllocator {
+        allocations: Cell<usize>,
+        live: Cell<usize>,
+        peak_bytes: Cell<usize>,
+    }
+
+    #[allow(unsafe_code)]
+    unsafe impl Allocator for &CountingAllocator {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            self.allocations.set(self.allocations.get() + 1);
+            self.live.set(self.live.get() + 1);
+            self.peak_bytes.set(self.peak_bytes.get().max(layout.size()));
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.live.set(self.live.get() - 1);
+            unsafe { Global.deallocate(ptr, layout) }
+        }
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Record {
+        id: u32,
+        name: String,
+        payload: Vec<u8>,
+    }
+
+    fn record() -> Record {
+        Record { id: 7, name: "x".repeat(5000), payload: vec![0xab; 100] }
+    }
+
+    #[test]
+    fn scratch_buffer_is_allocated_from_the_allocator() {
+        let counting = CountingAllocator::default();
+        let encoded = encode(&record());
+        let mut input = &encoded[..];
+        let decoded = decode_in::<Record, _, _>(&mut input, &counting).unwrap();
+        assert_eq!(decoded, record());
+        assert!(input.is_empty());
+        assert!(counting.allocations.get() > 0);
+        assert!(counting.peak_bytes.get() >= 5000);
+        assert_eq!(counting.live.get(), 0);
+    }
+
+    #[test]
+    fn values_without_strings_do_not_allocate() {
+        let counting = CountingAllocator::default();
+        let encoded = encode(&(1u8, [2u16; 4], Some(3u64)));
+        let decoded = decode_in::<(u8, [u16; 4], Option<u64>), _, _>(&mut &encoded[..], &counting);
+        assert_eq!(decoded.unwrap(), (1, [2; 4], Some(3)));
+        assert_eq!(counting.allocations.get(), 0);
+    }
+
+    #[test]
+    fn forged_lengths_do_not_allocate_upfront() {
+        let counting = CountingAllocator::default();
+        let result = decode_in::<String, _, _>(&mut &b"\xfe\xff\xff\xffab"[..], &counting);
+        assert!(matches!(result, Err(Error::Input(_))));
+        assert_eq!(counting.allocations.get(), 0);
+    }
+}
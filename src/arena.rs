@@ -0,0 +1,173 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+use crate::{
+    de, logging,
+    scale::{Bytes, Read},
+    Error,
+};
+use bumpalo::Bump;
+use parity_scale_codec::Input;
+use serde::Deserialize;
+
+/// Decodes a `T` from `bytes`, failing if bytes are left over, copying strings and byte sequences
+/// to `arena` so that `T` can borrow them
+///
+/// Each string or byte sequence is copied once to `arena` and `T` gets a `&'a str` or `&'a [u8]`
+/// pointing into it. The memory is reclaimed all at once when `arena` is reset or dropped, which
+/// saves an allocation per field when decoding many values. Types owning their data (e.g.
+/// `String`) still work but copy it again.
+///
+/// ```rust
+/// use bumpalo::Bump;
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize)]
+/// struct Record<'a> {
+///     id: u32,
+///     name: &'a str,
+/// }
+///
+/// let mut arena = Bump::new();
+/// for _ in 0..2 {
+///     let record = serde_scale_wrap::decode_in_arena::<Record>(b"\x07\0\0\0\x0cfoo", &arena)?;
+///     assert_eq!((record.id, record.name), (7, "foo"));
+///     arena.reset();
+/// }
+/// # Ok::<_, serde_scale_wrap::Error>(())
+/// ```
+pub fn decode_in_arena<'a, T>(bytes: &[u8], arena: &'a Bump) -> Result<T, Error>
+where
+    T: Deserialize<'a>,
+{
+    let mut input = bytes;
+    let value = logging::decode_logged(&mut input, |input| {
+        let mut deserializer = crate::scale::Deserializer::new(ArenaRead { input, arena });
+        T::deserialize(de::Deserializer::new(&mut deserializer))
+            .map_err(Error::from_deserialization)
+    })?;
+    if input.is_empty() {
+        Ok(value)
+    } else {
+        Err(Error::TrailingBytes { len: input.len() })
+    }
+}
+
+/// `Read` copying the bytes passed to `read_map` to an arena
+struct ArenaRead<'a, 'b, I: ?Sized> {
+    input: &'b mut I,
+    arena: &'a Bump,
+}
+
+impl<'a, I: Input + ?Sized> Read<'a> for ArenaRead<'a, '_, I> {
+    type Error = parity_scale_codec::Error;
+
+    fn read_map<R, F>(&mut self, n: usize, f: F) -> Result<R, Self::Error>
+    where
+        F: FnOnce(Bytes<'a, '_>) -> R,
+    {
+        // `n` may come from a forged length prefix, and the input being a slice, its length is
+        // known and checked before allocating.
+        if self.input.remaining_len()?.is_none_or(|remaining| n > remaining) {
+            return Err("Not enough data to fill buffer".into());
+        }
+        let bytes = self.arena.alloc_slice_fill_copy(n, 0);
+        self.input.read(bytes)?;
+        Ok(f(Bytes::Persistent(bytes)))
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        self.input.read(buf)
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::decode_in_arena;
+    use crate::Error;
+    use alloc::{vec, vec::Vec};
+    use bumpalo::Bump;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Record<'a> {
+        id: u64,
+        #[serde(borrow)]
+        name: &'a str,
+        #[serde(borrow)]
+        email: &'a str,
+        #[serde(borrow)]
+        tags: Vec<&'a str>,
+        #[serde(with = "serde_bytes_ref")]
+        payload: &'a [u8],
+    }
+
+    /// Serializes byte slices as byte sequences rather than as sequences of `u8`
+    mod serde_bytes_ref {
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(bytes: &&[u8], serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(bytes)
+        }
+
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<&'de [u8], D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            <&[u8]>::deserialize(deserializer)
+        }
+    }
+
+    fn record(name: &str) -> Record<'_> {
+        Record { id: 3, name, email: "a@b.c", tags: ["x", "yz"].into(), payload: &[1, 2, 3] }
+    }
+
+    fn addresses(record: &Record<'_>) -> Vec<usize> {
+        let mut addresses = vec![record.name.as_ptr(), record.email.as_ptr()];
+        addresses.push(record.payload.as_ptr());
+        addresses.extend(record.tags.iter().map(|tag| tag.as_ptr()));
+        addresses.into_iter().map(|p| p as usize).collect()
+    }
+
+    fn in_arena(arena: &mut Bump, addresses: &[usize]) -> bool {
+        let chunks = arena
+            .iter_allocated_chunks()
+            .map(|chunk| chunk.as_ptr() as usize..chunk.as_ptr() as usize + chunk.len())
+            .collect::<Vec<_>>();
+        addresses.iter().all(|a| chunks.iter().any(|chunk| chunk.contains(a)))
+    }
+
+    #[test]
+    fn strings_are_borrowed_from_the_arena() {
+        let encoded = crate::encode(&record("alice"));
+        let mut arena = Bump::new();
+        let decoded = decode_in_arena::<Record<'_>>(&encoded, &arena).unwrap();
+        assert_eq!(decoded, record("alice"));
+        let addresses = addresses(&decoded);
+        assert!(addresses.iter().all(|a| !encoded.as_ptr_range().contains(&(*a as *const u8))));
+        assert!(in_arena(&mut arena, &addresses));
+    }
+
+    #[test]
+    fn arena_can_be_reset_between_decodings() {
+        let mut arena = Bump::new();
+        let encoded = crate::encode(&record("alice"));
+        let first = addresses(&decode_in_arena::<Record<'_>>(&encoded, &arena).unwrap());
+        arena.reset();
+        let encoded = crate::encode(&record("bob"));
+        let decoded = decode_in_arena::<Record<'_>>(&encoded, &arena).unwrap();
+        assert_eq!(decoded, record("bob"));
+        let second = addresses(&decoded);
+        assert!(in_arena(&mut arena, &second));
+        assert!(first.len() == second.len());
+    }
+
+    #[test]
+    fn forged_lengths_fail_without_allocating() {
+        let arena = Bump::new();
+        let result = decode_in_arena::<&str>(&[0xfe, 0xff, 0xff, 0xff, b'a'], &arena);
+        assert!(matches!(result, Err(Error::Input(_))));
+        assert_eq!(arena.allocated_bytes(), 0);
+        let result = decode_in_arena::<&str>(b"\x04ab", &arena);
+        assert!(matches!(result, Err(Error::TrailingBytes { len: 1 })));
+    }
+}
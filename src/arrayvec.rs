@@ -0,0 +1,61 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+use crate::Wrap;
+use alloc::vec::Vec;
+use arrayvec::ArrayVec;
+use parity_scale_codec::{Encode, EncodeLike};
+
+impl<T, const N: usize> EncodeLike<Wrap<Vec<T>>> for Wrap<ArrayVec<T, N>>
+where
+    Wrap<ArrayVec<T, N>>: Encode,
+    Wrap<Vec<T>>: Encode,
+{
+}
+
+impl<T, const N: usize> EncodeLike<Wrap<ArrayVec<T, N>>> for Wrap<Vec<T>>
+where
+    Wrap<ArrayVec<T, N>>: Encode,
+    Wrap<Vec<T>>: Encode,
+{
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use crate::{decode_all, encode, Error, Wrap};
+    use alloc::{string::String, vec, vec::Vec};
+    use arrayvec::ArrayVec;
+    use parity_scale_codec::{Encode, EncodeLike};
+
+    fn store<T: EncodeLike<Wrap<Vec<u16>>>>(value: T) -> Vec<u8> {
+        value.encode()
+    }
+
+    #[test]
+    fn array_vectors_encode_like_vectors() {
+        for len in [0, 1, 4] {
+            let value = (0..len).collect::<ArrayVec<u16, 4>>();
+            let expected = value.to_vec().encode();
+            assert_eq!(encode(&value), expected);
+            assert_eq!(decode_all::<ArrayVec<u16, 4>>(&expected).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn array_vectors_and_vectors_are_interchangeable() {
+        let value = ArrayVec::from([1u16, 2, 3]);
+        assert_eq!(store(Wrap(value.clone())), store(Wrap(value.to_vec())));
+    }
+
+    #[test]
+    fn exceeding_the_capacity_fails_to_decode() {
+        let encoded = vec![String::from("a"); 3].encode();
+        let result = decode_all::<ArrayVec<String, 2>>(&encoded);
+        assert!(matches!(result, Err(Error::Deserialization(_))));
+        assert_eq!(decode_all::<ArrayVec<String, 3>>(&encoded).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn forged_lengths_fail_to_decode() {
+        assert!(decode_all::<ArrayVec<u64, 4>>(b"\xfe\xff\xff\xff\x01").is_err());
+    }
+}
@@ -0,0 +1,235 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! `axum` extractor and response for bodies holding SCALE encodings
+//!
+//! ```rust
+//! use serde_scale_wrap::axum::Scale;
+//!
+//! async fn handler(Scale((a, b)): Scale<(u32, u32)>) -> Scale<u64> {
+//!     Scale(u64::from(a) + u64::from(b))
+//! }
+//!
+//! let app = axum::Router::<()>::new().route("/add", axum::routing::post(handler));
+//! ```
+
+use crate::{decode_all, try_encode, Error};
+use ::axum::{
+    body::Bytes,
+    extract::{rejection::BytesRejection, FromRequest, Request},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use core::{
+    fmt::{self, Display},
+    ops::{Deref, DerefMut},
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::string::ToString;
+
+/// Media type of SCALE encodings
+pub const CONTENT_TYPE: &str = "application/scale";
+
+/// Extractor decoding the body of a request, and response encoding its body
+///
+/// As an extractor, it requires the `application/scale` content type and rejects bodies with bytes
+/// left over after the value. The body is read within the limit set by
+/// `axum::extract::DefaultBodyLimit`, 2 MB by default. See [`ScaleRejection`] for the statuses of
+/// failed extractions.
+///
+/// As a response, it sets the `application/scale` content type. If the value cannot be
+/// serialized, the response has status 500 and the error message as body.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Scale<T>(pub T);
+
+impl<T> Deref for Scale<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Scale<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T> From<T> for Scale<T> {
+    fn from(x: T) -> Self {
+        Scale(x)
+    }
+}
+
+impl<T, S> FromRequest<S> for Scale<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ScaleRejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, ScaleRejection> {
+        if !has_scale_content_type(req.headers()) {
+            return Err(ScaleRejection::UnsupportedContentType);
+        }
+        let bytes = Bytes::from_request(req, state).await.map_err(ScaleRejection::Body)?;
+        decode_all(&bytes).map(Scale).map_err(ScaleRejection::Decoding)
+    }
+}
+
+impl<T: Serialize> IntoResponse for Scale<T> {
+    fn into_response(self) -> Response {
+        match try_encode(&self.0) {
+            Ok(encoded) => {
+                ([(header::CONTENT_TYPE, HeaderValue::from_static(CONTENT_TYPE))], encoded)
+                    .into_response()
+            }
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        }
+    }
+}
+
+fn has_scale_content_type(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case(CONTENT_TYPE))
+}
+
+/// Reasons for failing to extract a [`Scale`]
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ScaleRejection {
+    /// The content type of the request is not `application/scale`, answered with status 415
+    UnsupportedContentType,
+    /// The body could not be read, e.g. because it exceeds the limit, answered with the status of
+    /// the inner rejection
+    Body(BytesRejection),
+    /// The body is not a valid encoding, answered with status 400 and the error message as body
+    Decoding(Error),
+}
+
+impl Display for ScaleRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScaleRejection::UnsupportedContentType => {
+                write!(f, "Expected request with `Content-Type: {}`", CONTENT_TYPE)
+            }
+            ScaleRejection::Body(e) => write!(f, "Failed to read body: {}", e),
+            ScaleRejection::Decoding(e) => write!(f, "Failed to decode body: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ScaleRejection {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScaleRejection::UnsupportedContentType => None,
+            ScaleRejection::Body(e) => Some(e),
+            ScaleRejection::Decoding(e) => Some(e),
+        }
+    }
+}
+
+impl IntoResponse for ScaleRejection {
+    fn into_response(self) -> Response {
+        let status = match &self {
+            ScaleRejection::UnsupportedContentType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ScaleRejection::Body(e) => e.status(),
+            ScaleRejection::Decoding(_) => StatusCode::BAD_REQUEST,
+        };
+        (status, self.to_string()).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Scale, CONTENT_TYPE};
+    use ::axum::{
+        body::{to_bytes, Body},
+        extract::DefaultBodyLimit,
+        http::{header, Request, StatusCode},
+        response::Response,
+        routing::post,
+        Router,
+    };
+    use serde::{Deserialize, Serialize};
+    use std::{string::String, vec, vec::Vec};
+    use tower::ServiceExt;
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Query {
+        account: String,
+        blocks: Vec<u32>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Balance {
+        account: String,
+        total: u64,
+    }
+
+    async fn balance(Scale(query): Scale<Query>) -> Scale<Balance> {
+        let total = query.blocks.iter().map(|&b| u64::from(b)).sum();
+        Scale(Balance { account: query.account, total })
+    }
+
+    fn app() -> Router {
+        Router::new().route("/balance", post(balance)).layer(DefaultBodyLimit::max(64))
+    }
+
+    async fn send(content_type: &str, body: Vec<u8>) -> Response {
+        let request = Request::post("/balance")
+            .header(header::CONTENT_TYPE, content_type)
+            .body(Body::from(body))
+            .unwrap();
+        app().oneshot(request).await.unwrap()
+    }
+
+    async fn body_of(response: Response) -> Vec<u8> {
+        to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()
+    }
+
+    fn query() -> Vec<u8> {
+        crate::encode(&Query { account: "alice".into(), blocks: vec![1, 2, 3] })
+    }
+
+    #[::tokio::test]
+    async fn values_roundtrip_through_handlers() {
+        let response = send("application/scale; charset=binary", query()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], CONTENT_TYPE);
+        let balance = crate::decode_all::<Balance>(&body_of(response).await).unwrap();
+        assert_eq!(balance, Balance { account: "alice".into(), total: 6 });
+    }
+
+    #[::tokio::test]
+    async fn oversized_bodies_are_rejected() {
+        let body = crate::encode(&Query { account: "a".repeat(100), blocks: vec![] });
+        let response = send(CONTENT_TYPE, body).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[::tokio::test]
+    async fn malformed_bodies_are_rejected_with_the_error() {
+        let response = send(CONTENT_TYPE, query()[..4].to_vec()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(body_of(response).await.starts_with(b"Failed to decode body: "));
+        let mut body = query();
+        body.push(0);
+        let response = send(CONTENT_TYPE, body).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let message = String::from_utf8(body_of(response).await).unwrap();
+        assert_eq!(
+            message,
+            "Failed to decode body: Input has 1 byte(s) left over after decoding",
+        );
+    }
+
+    #[::tokio::test]
+    async fn other_content_types_are_unsupported() {
+        let response = send("application/json", query()).await;
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+}
@@ -0,0 +1,297 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Encoding to and decoding from base64 strings
+//!
+//! Encodings use the standard alphabet and are padded. Decoding accepts padded and unpadded
+//! strings. Encoding requires the `encode` feature and decoding the `decode` feature.
+//!
+//! This module can also be used with `serde(with)` to embed the SCALE encoding of a field in
+//! another format. The field becomes a base64 string in formats reporting themselves as
+//! human-readable (e.g. JSON) and a byte sequence in other formats. SCALE keeps the `serde`
+//! default and reports itself as human-readable, so such a field nested in a `Wrap` is encoded as
+//! a string.
+//!
+//! ```rust
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Debug, Deserialize, PartialEq, Serialize)]
+//! struct Envelope {
+//!     id: u32,
+//!     #[serde(with = "serde_scale_wrap::base64")]
+//!     payload: (u8, u16),
+//! }
+//!
+//! let envelope = Envelope { id: 1, payload: (7, 0x100) };
+//! let json = serde_json::to_string(&envelope).unwrap();
+//! assert_eq!(json, r#"{"id":1,"payload":"BwAB"}"#);
+//! assert_eq!(serde_json::from_str::<Envelope>(&json).unwrap(), envelope);
+//! ```
+
+use crate::Wrap;
+use ::base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+
+#[cfg(feature = "decode")]
+use crate::{decode_all, Error};
+#[cfg(feature = "encode")]
+use crate::{encode, try_encode};
+#[cfg(feature = "encode")]
+use alloc::string::String;
+#[cfg(feature = "decode")]
+use alloc::vec::Vec;
+#[cfg(feature = "decode")]
+use core::{fmt, marker::PhantomData};
+#[cfg(feature = "decode")]
+use serde::de::{self, DeserializeOwned, Deserializer, Visitor};
+#[cfg(feature = "encode")]
+use serde::{ser, Serialize, Serializer};
+
+/// Standard alphabet, padding when encoding and requiring complete padding when decoding
+const PADDED: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::RequireCanonical),
+);
+
+/// Standard alphabet, rejecting padding when decoding
+#[cfg(feature = "decode")]
+const UNPADDED: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::RequireNone),
+);
+
+impl<T> Wrap<T> {
+    /// Returns the SCALE encoding of the wrapped value as a padded base64 string
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::Wrap;
+    ///
+    /// assert_eq!(Wrap((1u8, 0xabcdu16)).encode_base64(), "Ac2r");
+    /// assert_eq!(Wrap(0xabcdu16).encode_base64(), "zas=");
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if the serializer returns an error.
+    #[cfg(feature = "encode")]
+    pub fn encode_base64(&self) -> String
+    where
+        T: Serialize,
+    {
+        PADDED.encode(encode(&self.0))
+    }
+
+    /// Decodes a `Wrap` from a base64 string, failing if bytes are left over
+    ///
+    /// The string may omit its padding, but not only part of it.
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::{Error, Wrap};
+    ///
+    /// assert_eq!(Wrap::<u16>::decode_base64("zas=")?, 0xabcd);
+    /// assert_eq!(Wrap::<u16>::decode_base64("zas")?, 0xabcd);
+    /// assert_eq!(
+    ///     Wrap::<u16>::decode_base64("z.s=").unwrap_err().to_string(),
+    ///     "Invalid base64: Invalid character '.' at position 1",
+    /// );
+    /// # Ok::<_, Error>(())
+    /// ```
+    #[cfg(feature = "decode")]
+    pub fn decode_base64(s: &str) -> Result<Self, Error>
+    where
+        T: DeserializeOwned,
+    {
+        // Padding is all or nothing, so that e.g. `AA=` is rejected.
+        let engine = if s.ends_with('=') { &PADDED } else { &UNPADDED };
+        let bytes: Vec<u8> = engine.decode(s)?;
+        decode_all(&bytes).map(Wrap)
+    }
+}
+
+/// Serializes the SCALE encoding of `value` as a base64 string if the format is human-readable,
+/// and as bytes otherwise
+#[cfg(feature = "encode")]
+pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Serialize + ?Sized,
+    S: Serializer,
+{
+    let encoded = try_encode(value).map_err(ser::Error::custom)?;
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&PADDED.encode(encoded))
+    } else {
+        serializer.serialize_bytes(&encoded)
+    }
+}
+
+/// Deserializes a `T` from its SCALE encoding, as a base64 string if the format is human-readable
+/// and as bytes otherwise
+#[cfg(feature = "decode")]
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: DeserializeOwned,
+    D: Deserializer<'de>,
+{
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_str(EncodingVisitor(PhantomData))
+    } else {
+        deserializer.deserialize_bytes(EncodingVisitor(PhantomData))
+    }
+}
+
+/// Visitor decoding a `T` from a base64 string or from bytes
+#[cfg(feature = "decode")]
+struct EncodingVisitor<T>(PhantomData<T>);
+
+#[cfg(feature = "decode")]
+impl<T: DeserializeOwned> Visitor<'_> for EncodingVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a SCALE encoding as a base64 string or bytes")
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<T, E> {
+        Wrap::decode_base64(s).map(|wrapped| wrapped.0).map_err(E::custom)
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<T, E> {
+        decode_all(bytes).map_err(E::custom)
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use crate::{decode_all, encode, Error, Wrap};
+    use alloc::{format, string::ToString, vec, vec::Vec};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Envelope {
+        id: u8,
+        #[serde(with = "super")]
+        payload: (u32, Vec<Option<bool>>),
+    }
+
+    fn envelope() -> Envelope {
+        Envelope { id: 3, payload: (7, vec![Some(true), None]) }
+    }
+
+    #[test]
+    fn encodings_roundtrip() {
+        type Value = (u32, Vec<Option<bool>>, i16);
+
+        for wrapped in [Wrap((7, vec![Some(true), None], -2)), Wrap((0, vec![], 0))] {
+            let s = wrapped.encode_base64();
+            assert_eq!(Wrap::<Value>::decode_base64(&s).unwrap(), wrapped);
+        }
+    }
+
+    #[test]
+    fn output_is_padded() {
+        assert_eq!(Wrap(()).encode_base64(), "");
+        assert_eq!(Wrap(0xffu8).encode_base64(), "/w==");
+        assert_eq!(Wrap([0xfbu8, 0xff]).encode_base64(), "+/8=");
+    }
+
+    #[test]
+    fn padding_is_optional() {
+        assert_eq!(Wrap::<u8>::decode_base64("/w==").unwrap(), 0xff);
+        assert_eq!(Wrap::<u8>::decode_base64("/w").unwrap(), 0xff);
+        assert_eq!(Wrap::<[u8; 2]>::decode_base64("+/8").unwrap(), [0xfb, 0xff]);
+        Wrap::<()>::decode_base64("").unwrap();
+    }
+
+    #[test]
+    fn invalid_characters_are_rejected() {
+        let e = Wrap::<[u8; 3]>::decode_base64("AB-D").unwrap_err();
+        assert!(matches!(e, Error::Base64(::base64::DecodeError::InvalidByte(2, b'-'))));
+        assert_eq!(e.to_string(), "Invalid base64: Invalid character '-' at position 2");
+        let e = Wrap::<[u8; 3]>::decode_base64("ABé").unwrap_err();
+        assert_eq!(e.to_string(), "Invalid base64: Invalid byte 0xc3 at position 2");
+    }
+
+    #[test]
+    fn bad_padding_is_rejected() {
+        let e = Wrap::<u8>::decode_base64("/w=").unwrap_err();
+        assert!(matches!(e, Error::Base64(::base64::DecodeError::InvalidPadding)));
+        assert_eq!(e.to_string(), "Invalid base64: Invalid padding");
+        let e = Wrap::<u8>::decode_base64("/w=A").unwrap_err();
+        assert_eq!(e.to_string(), "Invalid base64: Invalid character '=' at position 2");
+    }
+
+    #[test]
+    fn truncated_input_is_rejected() {
+        let e = Wrap::<[u8; 3]>::decode_base64("ABCDE").unwrap_err();
+        assert_eq!(e.to_string(), "Invalid base64: Invalid length of 5 characters");
+    }
+
+    #[test]
+    fn trailing_bits_are_rejected() {
+        let e = Wrap::<u8>::decode_base64("/x").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Invalid base64: Character 'x' at position 1 does not end an encoding",
+        );
+    }
+
+    #[test]
+    fn trailing_bytes_are_rejected() {
+        let e = Wrap::<u8>::decode_base64("AQI=").unwrap_err();
+        assert!(matches!(e, Error::TrailingBytes { len: 1 }));
+    }
+
+    #[test]
+    fn fields_are_strings_in_human_readable_formats() {
+        let json = serde_json::to_string(&envelope()).unwrap();
+        let expected = Wrap(envelope().payload).encode_base64();
+        assert_eq!(json, format!(r#"{{"id":3,"payload":"{}"}}"#, expected));
+        assert_eq!(serde_json::from_str::<Envelope>(&json).unwrap(), envelope());
+    }
+
+    #[test]
+    fn fields_are_bytes_in_compact_formats() {
+        use serde_test::{assert_tokens, Configure, Token};
+
+        const PAYLOAD: &[u8] = &[7, 0, 0, 0, 8, 1, 0];
+        assert_eq!(encode(&envelope().payload), PAYLOAD);
+        assert_tokens(
+            &envelope().compact(),
+            &[
+                Token::Struct { name: "Envelope", len: 2 },
+                Token::Str("id"),
+                Token::U8(3),
+                Token::Str("payload"),
+                Token::Bytes(PAYLOAD),
+                Token::StructEnd,
+            ],
+        );
+    }
+
+    #[test]
+    fn fields_are_strings_in_scale_encodings() {
+        let encoded = encode(&envelope());
+        assert_eq!(encoded, encode(&(3u8, Wrap(envelope().payload).encode_base64())));
+        assert_eq!(decode_all::<Envelope>(&encoded).unwrap(), envelope());
+    }
+
+    #[test]
+    fn invalid_fields_are_rejected() {
+        let e = serde_json::from_str::<Envelope>(r#"{"id":3,"payload":"B*"}"#).unwrap_err();
+        assert!(e.to_string().starts_with("Invalid base64: Invalid character '*' at position 1"));
+        let e = decode_all::<Envelope>(&encode(&(3u8, "BwAAAAgBAAA="))).unwrap_err();
+        assert!(matches!(e, Error::Deserialization(_)));
+    }
+
+    #[test]
+    fn unserializable_fields_are_rejected() {
+        #[derive(Serialize)]
+        struct Unserializable {
+            #[serde(with = "super")]
+            value: u128,
+        }
+
+        assert!(serde_json::to_string(&Unserializable { value: 1 }).is_err());
+    }
+}
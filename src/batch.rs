@@ -0,0 +1,216 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Parallel encoding of batches of values with `rayon`
+//!
+//! Each thread serializes into a buffer it reuses across values, so that encoding a value does not
+//! grow a fresh vector from scratch. The encodings are identical to those returned by
+//! [`try_encode`](crate::try_encode) for each value in turn.
+
+use crate::{logging, try_serialize_to, Error};
+use core::ops::Range;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::vec::Vec;
+
+/// Number of values encoded into the same buffer by [`encode_batch_concat`]
+const CONCAT_CHUNK_LEN: usize = 64;
+
+/// Returns the encodings of `items`, in order
+///
+/// # Panics
+/// Panics if an item cannot be serialized.
+///
+/// ```rust
+/// let encoded = serde_scale_wrap::batch::encode_batch(&[1u16, 2, 3]);
+/// assert_eq!(encoded, [[1, 0], [2, 0], [3, 0]]);
+/// ```
+pub fn encode_batch<T: Serialize + Sync>(items: &[T]) -> Vec<Vec<u8>> {
+    match try_encode_batch(items) {
+        Ok(encoded) => encoded,
+        Err((index, e)) => panic!("Failed to encode item {}: {}", index, e),
+    }
+}
+
+/// Returns the encodings of `items`, in order, or the index and error of the first item that
+/// cannot be serialized
+///
+/// ```rust
+/// use serde_scale_wrap::{batch::try_encode_batch, Error};
+///
+/// let result = try_encode_batch(&[Ok(1u8), Err(1u128), Err(2)]);
+/// assert!(matches!(result, Err((1, Error::Serialization(_)))));
+/// ```
+pub fn try_encode_batch<T>(items: &[T]) -> Result<Vec<Vec<u8>>, (usize, Error)>
+where
+    T: Serialize + Sync,
+{
+    let results = items
+        .par_iter()
+        .map_init(Vec::new, |buffer, item| {
+            buffer.clear();
+            encode_to(item, buffer)?;
+            Ok(buffer.as_slice().to_vec())
+        })
+        .collect::<Vec<_>>();
+    results
+        .into_iter()
+        .enumerate()
+        .map(|(index, result)| result.map_err(|e| (index, e)))
+        .collect()
+}
+
+/// Returns the encodings of `items` concatenated in one buffer
+///
+/// # Panics
+/// Panics if an item cannot be serialized.
+///
+/// ```rust
+/// let batch = serde_scale_wrap::batch::encode_batch_concat(&["a", "bc"]);
+/// assert_eq!(batch.as_bytes(), b"\x04a\x08bc");
+/// assert_eq!(batch.get(1), Some(&b"\x08bc"[..]));
+/// ```
+pub fn encode_batch_concat<T: Serialize + Sync>(items: &[T]) -> ConcatBatch {
+    let chunks = items
+        .par_chunks(CONCAT_CHUNK_LEN)
+        .enumerate()
+        .map(|(chunk_index, chunk)| {
+            let mut bytes = Vec::new();
+            let mut ends = Vec::with_capacity(chunk.len());
+            for (i, item) in chunk.iter().enumerate() {
+                if let Err(e) = encode_to(item, &mut bytes) {
+                    let index = chunk_index * CONCAT_CHUNK_LEN + i;
+                    panic!("Failed to encode item {}: {}", index, e);
+                }
+                ends.push(bytes.len());
+            }
+            (bytes, ends)
+        })
+        .collect::<Vec<_>>();
+    let mut batch = ConcatBatch {
+        bytes: Vec::with_capacity(chunks.iter().map(|(bytes, _)| bytes.len()).sum()),
+        offsets: Vec::with_capacity(items.len() + 1),
+    };
+    batch.offsets.push(0);
+    for (bytes, ends) in chunks {
+        let start = batch.bytes.len();
+        batch.bytes.extend_from_slice(&bytes);
+        batch.offsets.extend(ends.into_iter().map(|end| start + end));
+    }
+    batch
+}
+
+fn encode_to<T: Serialize>(item: &T, buffer: &mut Vec<u8>) -> Result<(), Error> {
+    logging::encode_logged::<T, _, _>(buffer, |dst| try_serialize_to(item, dst))
+}
+
+/// Encodings of a batch of values stored back to back in one buffer
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConcatBatch {
+    bytes: Vec<u8>,
+    offsets: Vec<usize>,
+}
+
+impl ConcatBatch {
+    /// Returns the concatenated encodings
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns the number of encoded values
+    pub fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    /// Returns whether the batch is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the range of the encoding of the value at `index` in [`ConcatBatch::as_bytes`]
+    pub fn range(&self, index: usize) -> Option<Range<usize>> {
+        Some(*self.offsets.get(index)?..*self.offsets.get(index + 1)?)
+    }
+
+    /// Returns the encoding of the value at `index`
+    pub fn get(&self, index: usize) -> Option<&[u8]> {
+        self.range(index).map(|range| &self.bytes[range])
+    }
+
+    /// Returns an iterator over the encodings of the values, in order
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.offsets.windows(2).map(move |w| &self.bytes[w[0]..w[1]])
+    }
+
+    /// Returns the concatenated encodings
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_batch, encode_batch_concat, try_encode_batch};
+    use crate::Error;
+    use serde::Serialize;
+    use std::{collections::BTreeMap, string::String, vec::Vec};
+
+    #[derive(Serialize)]
+    struct Transaction {
+        nonce: u64,
+        to: String,
+        data: Vec<u8>,
+        tags: BTreeMap<u8, bool>,
+    }
+
+    fn transactions() -> Vec<Transaction> {
+        (0..3000u64)
+            .map(|i| {
+                let x = i.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 40;
+                Transaction {
+                    nonce: x,
+                    to: "a".repeat((x % 17) as usize),
+                    data: (0..x % 300).map(|b| b as u8).collect(),
+                    tags: (0..(x % 4) as u8).map(|t| (t, x % 3 == 0)).collect(),
+                }
+            })
+            .collect()
+    }
+
+    fn sequential(items: &[Transaction]) -> Vec<Vec<u8>> {
+        items.iter().map(|item| crate::try_encode(item).unwrap()).collect()
+    }
+
+    #[test]
+    fn parallel_encodings_match_sequential_ones() {
+        let items = transactions();
+        assert_eq!(encode_batch(&items), sequential(&items));
+    }
+
+    #[test]
+    fn concatenated_encodings_match_sequential_ones() {
+        let items = transactions();
+        let expected = sequential(&items);
+        let batch = encode_batch_concat(&items);
+        assert_eq!(batch.len(), items.len());
+        assert_eq!(batch.iter().collect::<Vec<_>>(), expected);
+        assert_eq!(batch.get(1234), Some(&*expected[1234]));
+        assert_eq!(batch.get(items.len()), None);
+        assert_eq!(batch.into_bytes(), expected.concat());
+    }
+
+    #[test]
+    fn first_error_is_reported_with_its_index() {
+        let items = (0..1000u32)
+            .map(|i| if i % 300 == 299 { Err(1u128) } else { Ok(i) })
+            .collect::<Vec<_>>();
+        assert!(matches!(try_encode_batch(&items), Err((299, Error::Serialization(_)))));
+    }
+
+    #[test]
+    fn empty_batches_are_supported() {
+        let batch = encode_batch_concat::<u8>(&[]);
+        assert!(batch.is_empty());
+        assert!(batch.as_bytes().is_empty());
+        assert_eq!(batch.iter().count(), 0);
+    }
+}
@@ -0,0 +1,268 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! `num-bigint` integers encoded as little-endian bytes, for use with `#[serde(with)]`
+//!
+//! The default `serde` implementations of `BigUint` and `BigInt` serialize sequences of `u32`
+//! digits, which is hard to match from other languages. These modules encode them as follows:
+//!
+//! - [`unsigned`]: A `BigUint` is encoded like a `Vec<u8>` holding its magnitude in little-endian
+//!   order, i.e. a compact length followed by the bytes, without trailing zero bytes. Zero is
+//!   encoded as the empty sequence, i.e. `0x00`.
+//! - [`signed`]: A `BigInt` is encoded as its signum as an `i8`, i.e. one byte that is `0x00` for
+//!   zero, `0x01` for positive integers and `0xff` for negative integers, followed by the
+//!   encoding of its magnitude as a `BigUint`. Zero has an empty magnitude.
+//!
+//! Every integer thus has a single encoding. Decoding fails with [`Error::Deserialization`] if the
+//! magnitude ends with a zero byte, or if the sign does not match the magnitude, e.g. a negative
+//! zero.
+//!
+//! ```rust
+//! use num_bigint::{BigInt, BigUint};
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Debug, Deserialize, PartialEq, Serialize)]
+//! struct Transfer {
+//!     #[serde(with = "serde_scale_wrap::bigint::unsigned")]
+//!     amount: BigUint,
+//!     #[serde(with = "serde_scale_wrap::bigint::signed")]
+//!     delta: BigInt,
+//! }
+//!
+//! let transfer = Transfer { amount: BigUint::from(1u128 << 64), delta: BigInt::from(-1) };
+//! let encoded = serde_scale_wrap::encode(&transfer);
+//! assert_eq!(encoded, b"\x24\x00\x00\x00\x00\x00\x00\x00\x00\x01\xff\x04\x01");
+//! assert_eq!(serde_scale_wrap::decode::<Transfer>(&encoded)?, transfer);
+//! # Ok::<_, serde_scale_wrap::Error>(())
+//! ```
+
+#[cfg(doc)]
+use crate::Error;
+#[cfg(feature = "decode")]
+use alloc::vec::Vec;
+#[cfg(feature = "decode")]
+use core::fmt;
+use num_bigint::BigUint;
+#[cfg(feature = "decode")]
+use num_bigint::{BigInt, Sign};
+#[cfg(feature = "decode")]
+use serde::{
+    de::{self, SeqAccess, Unexpected, Visitor},
+    Deserialize, Deserializer,
+};
+#[cfg(feature = "encode")]
+use serde::{Serialize, Serializer};
+
+/// `BigUint` encoded as its little-endian magnitude bytes
+pub mod unsigned {
+    use num_bigint::BigUint;
+
+    #[cfg(feature = "decode")]
+    use serde::Deserializer;
+    #[cfg(feature = "encode")]
+    use serde::Serializer;
+
+    /// Serializes the little-endian bytes of `value`, without trailing zero bytes
+    #[cfg(feature = "encode")]
+    pub fn serialize<S: Serializer>(value: &BigUint, serializer: S) -> Result<S::Ok, S::Error> {
+        super::serialize_magnitude(value, serializer)
+    }
+
+    /// Deserializes little-endian bytes, failing if the last one is zero
+    #[cfg(feature = "decode")]
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BigUint, D::Error> {
+        deserializer.deserialize_byte_buf(super::MagnitudeVisitor)
+    }
+}
+
+/// `BigInt` encoded as its signum followed by its little-endian magnitude bytes
+pub mod signed {
+    use num_bigint::BigInt;
+
+    #[cfg(feature = "decode")]
+    use serde::Deserializer;
+    #[cfg(feature = "encode")]
+    use num_bigint::Sign;
+    #[cfg(feature = "encode")]
+    use serde::{ser::SerializeTuple, Serializer};
+
+    /// Serializes the signum of `value` as an `i8`, followed by its magnitude
+    #[cfg(feature = "encode")]
+    pub fn serialize<S: Serializer>(value: &BigInt, serializer: S) -> Result<S::Ok, S::Error> {
+        let signum: i8 = match value.sign() {
+            Sign::Minus => -1,
+            Sign::NoSign => 0,
+            Sign::Plus => 1,
+        };
+        let mut tuple = serializer.serialize_tuple(2)?;
+        tuple.serialize_element(&signum)?;
+        tuple.serialize_element(&super::Magnitude(value.magnitude()))?;
+        tuple.end()
+    }
+
+    /// Deserializes a signum and a magnitude, failing if they do not match
+    #[cfg(feature = "decode")]
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BigInt, D::Error> {
+        deserializer.deserialize_tuple(2, super::SignedVisitor)
+    }
+}
+
+#[cfg(feature = "encode")]
+fn serialize_magnitude<S: Serializer>(value: &BigUint, serializer: S) -> Result<S::Ok, S::Error> {
+    // `to_bytes_le` returns `[0]` for zero.
+    if value.bits() == 0 {
+        serializer.serialize_bytes(&[])
+    } else {
+        serializer.serialize_bytes(&value.to_bytes_le())
+    }
+}
+
+/// Magnitude of a `BigInt`, serialized as a `BigUint`
+#[cfg(feature = "encode")]
+struct Magnitude<'a>(&'a BigUint);
+
+#[cfg(feature = "encode")]
+impl Serialize for Magnitude<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_magnitude(self.0, serializer)
+    }
+}
+
+/// Magnitude of a `BigInt`, deserialized as a `BigUint`
+#[cfg(feature = "decode")]
+struct MagnitudeBuf(BigUint);
+
+#[cfg(feature = "decode")]
+impl<'de> Deserialize<'de> for MagnitudeBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        unsigned::deserialize(deserializer).map(MagnitudeBuf)
+    }
+}
+
+/// Visitor of little-endian magnitude bytes
+#[cfg(feature = "decode")]
+struct MagnitudeVisitor;
+
+#[cfg(feature = "decode")]
+impl<'de> Visitor<'de> for MagnitudeVisitor {
+    type Value = BigUint;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("little-endian bytes without trailing zero bytes")
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<BigUint, E> {
+        match bytes.last() {
+            Some(0) => Err(E::invalid_value(Unexpected::Bytes(bytes), &self)),
+            _ => Ok(BigUint::from_bytes_le(bytes)),
+        }
+    }
+
+    // Formats without a byte sequence type (e.g. JSON) serialize bytes as a sequence.
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<BigUint, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        self.visit_bytes(&bytes)
+    }
+}
+
+/// Visitor of a signum followed by a magnitude
+#[cfg(feature = "decode")]
+struct SignedVisitor;
+
+#[cfg(feature = "decode")]
+impl<'de> Visitor<'de> for SignedVisitor {
+    type Value = BigInt;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a signum followed by a magnitude")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<BigInt, A::Error> {
+        let signum: i8 = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let MagnitudeBuf(magnitude) =
+            seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        let sign = match (signum, magnitude.bits()) {
+            (0, 0) => Sign::NoSign,
+            (1, 1..) => Sign::Plus,
+            (-1, 1..) => Sign::Minus,
+            _ => {
+                let expected = &"a signum matching the magnitude";
+                return Err(de::Error::invalid_value(Unexpected::Signed(signum.into()), expected));
+            }
+        };
+        Ok(BigInt::from_biguint(sign, magnitude))
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use crate::{decode_all, encode, Error};
+    use alloc::{vec, vec::Vec};
+    use num_bigint::{BigInt, BigUint};
+    use parity_scale_codec::Encode;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Unsigned(#[serde(with = "super::unsigned")] BigUint);
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Signed(#[serde(with = "super::signed")] BigInt);
+
+    fn two_to_the_64() -> BigUint {
+        BigUint::from(u64::MAX) + 1u8
+    }
+
+    fn assert_unsigned_roundtrips(value: BigUint, magnitude: &[u8]) {
+        let encoded = encode(&Unsigned(value.clone()));
+        assert_eq!(encoded, magnitude.to_vec().encode());
+        assert_eq!(decode_all::<Unsigned>(&encoded).unwrap(), Unsigned(value));
+    }
+
+    #[test]
+    fn unsigned_integers_encode_their_magnitude() {
+        assert_unsigned_roundtrips(BigUint::from(0u8), &[]);
+        assert_unsigned_roundtrips(BigUint::from(1u8), &[1]);
+        assert_unsigned_roundtrips(BigUint::from(0x0100u16), &[0, 1]);
+        assert_unsigned_roundtrips(BigUint::from(u64::MAX), &[0xff; 8]);
+        assert_unsigned_roundtrips(two_to_the_64(), &[0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_unsigned_roundtrips(two_to_the_64() + 1u8, &[1, 0, 0, 0, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn signed_integers_encode_their_signum_then_magnitude() {
+        let cases = [
+            (BigInt::from(0), 0i8, Vec::<u8>::new()),
+            (BigInt::from(1), 1, vec![1]),
+            (BigInt::from(-1), -1, vec![1]),
+            (BigInt::from(i64::MIN), -1, vec![0, 0, 0, 0, 0, 0, 0, 0x80]),
+            (-BigInt::from(two_to_the_64()), -1, vec![0, 0, 0, 0, 0, 0, 0, 0, 1]),
+        ];
+        for (value, signum, magnitude) in cases {
+            let encoded = encode(&Signed(value.clone()));
+            assert_eq!(encoded, (signum, magnitude).encode());
+            assert_eq!(decode_all::<Signed>(&encoded).unwrap(), Signed(value));
+        }
+    }
+
+    #[test]
+    fn trailing_zero_bytes_are_rejected() {
+        let two_to_the_64 = vec![0u8, 0, 0, 0, 0, 0, 0, 0, 1, 0].encode();
+        for encoded in [&b"\x04\x00"[..], b"\x08\x01\x00", &two_to_the_64] {
+            let result = decode_all::<Unsigned>(encoded);
+            assert!(matches!(result, Err(Error::Deserialization(_))));
+        }
+        let result = decode_all::<Signed>(b"\x01\x08\x01\x00");
+        assert!(matches!(result, Err(Error::Deserialization(_))));
+    }
+
+    #[test]
+    fn signs_not_matching_the_magnitude_are_rejected() {
+        for encoded in [&b"\xff\x00"[..], b"\x01\x00", b"\x00\x04\x01", b"\x02\x04\x01"] {
+            let result = decode_all::<Signed>(encoded);
+            assert!(matches!(result, Err(Error::Deserialization(_))));
+        }
+        assert_eq!(decode_all::<Signed>(b"\x00\x00").unwrap(), Signed(BigInt::from(0)));
+    }
+}
@@ -0,0 +1,109 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! `BinaryHeap` decoded like `parity-scale-codec` does, for use with `#[serde(with)]`
+//!
+//! `serde` and `parity-scale-codec` both encode a `BinaryHeap` like a `Vec` of its elements in the
+//! order of its internal buffer, which is neither sorted nor the insertion order. `Wrap` thus
+//! encodes heaps exactly like `Encode` does, but equal heaps (holding the same elements) may have
+//! different encodings, and comparing or hashing the encodings of heaps is only meaningful when
+//! they were built the same way.
+//!
+//! Decoding builds heaps differently though: `serde` pushes the elements one at a time while
+//! `parity-scale-codec` collects them and then turns them into a heap. Both hold the same
+//! elements, but their internal buffers and thus their encodings may differ. The functions of this
+//! module decode like `parity-scale-codec` does, so that a heap decoded with `Wrap` encodes back to
+//! the bytes it was decoded from, as one decoded with `Decode` does.
+//!
+//! ```rust
+//! use parity_scale_codec::{Decode, Encode};
+//! use serde::{Deserialize, Serialize};
+//! use serde_scale_wrap::Wrap;
+//! use std::collections::BinaryHeap;
+//!
+//! #[derive(Deserialize, Serialize)]
+//! struct Queue {
+//!     #[serde(with = "serde_scale_wrap::binary_heap")]
+//!     tasks: BinaryHeap<u32>,
+//! }
+//!
+//! let encoded = BinaryHeap::from(vec![1u32, 5, 3, 4, 2]).encode();
+//! let Wrap(queue) = Wrap::<Queue>::decode(&mut &*encoded)?;
+//! assert_eq!(serde_scale_wrap::encode(&queue), encoded);
+//! assert_eq!(queue.tasks.into_sorted_vec(), [1, 2, 3, 4, 5]);
+//! # Ok::<_, parity_scale_codec::Error>(())
+//! ```
+
+use alloc::collections::BinaryHeap;
+
+#[cfg(feature = "decode")]
+use alloc::vec::Vec;
+#[cfg(feature = "decode")]
+use serde::{Deserialize, Deserializer};
+#[cfg(feature = "encode")]
+use serde::{Serialize, Serializer};
+
+/// Serializes the elements of `heap` in the order of its internal buffer, as usual
+#[cfg(feature = "encode")]
+pub fn serialize<T, S>(heap: &BinaryHeap<T>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Serialize,
+    S: Serializer,
+{
+    heap.serialize(serializer)
+}
+
+/// Deserializes the elements of a heap and then turns them into a heap, like `Decode` does
+#[cfg(feature = "decode")]
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<BinaryHeap<T>, D::Error>
+where
+    T: Deserialize<'de> + Ord,
+    D: Deserializer<'de>,
+{
+    Vec::deserialize(deserializer).map(BinaryHeap::from)
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use crate::{decode_all, encode};
+    use alloc::{collections::BinaryHeap, vec, vec::Vec};
+    use parity_scale_codec::{Decode, Encode};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Deserialize, Serialize)]
+    struct Queue {
+        #[serde(with = "super")]
+        tasks: BinaryHeap<u32>,
+    }
+
+    fn shuffled() -> Vec<u32> {
+        (0..50).map(|i| (i * 37) % 50).collect()
+    }
+
+    #[test]
+    fn decoded_heaps_encode_back_to_their_bytes() {
+        let encoded = BinaryHeap::from(shuffled()).encode();
+        let queue = decode_all::<Queue>(&encoded).unwrap();
+        assert_eq!(encode(&queue), encoded);
+        let decoded = BinaryHeap::<u32>::decode(&mut &*encoded).unwrap();
+        assert_eq!(queue.tasks.into_vec(), decoded.into_vec());
+    }
+
+    #[test]
+    fn heaps_built_by_pushing_are_encoded_in_their_order() {
+        let mut tasks = BinaryHeap::new();
+        shuffled().into_iter().for_each(|task| tasks.push(task));
+        let expected = tasks.iter().copied().collect::<Vec<_>>().encode();
+        let queue = Queue { tasks };
+        assert_eq!(encode(&queue), expected);
+        assert_eq!(queue.tasks.encode(), expected);
+        let decoded = decode_all::<Queue>(&expected).unwrap().tasks;
+        assert_eq!(decoded.into_sorted_vec(), (0..50).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn empty_heaps_roundtrip() {
+        let queue = decode_all::<Queue>(&encode(&Queue { tasks: BinaryHeap::new() })).unwrap();
+        assert!(queue.tasks.is_empty());
+        assert_eq!(encode(&Queue { tasks: BinaryHeap::from(vec![7]) }), [4, 7, 0, 0, 0]);
+    }
+}
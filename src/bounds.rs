@@ -0,0 +1,119 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Single bound under which `Wrap<T>` implements both `Encode` and `Decode`
+
+#[cfg(feature = "deny-panicking-encode")]
+use crate::infallible::InfallibleSerialize;
+use serde::de::DeserializeOwned;
+#[cfg(not(feature = "deny-panicking-encode"))]
+use serde::Serialize;
+
+/// Defines `ScaleSerde` with the given supertraits, and implements it for every type having them
+macro_rules! define_scale_serde {
+    ($($bounds:tt)+) => {
+        /// Types that [`Wrap`](crate::Wrap) encodes and decodes, implemented for every such type
+        ///
+        /// `T: ScaleSerde` implies `Wrap<T>: Encode + Decode`, whatever the enabled features
+        /// require of `T` to encode (e.g. `InfallibleSerialize` with `deny-panicking-encode`).
+        /// Generic code can thus state this bound instead of the ones `Wrap` requires, including
+        /// the bounds on `Wrap<T>` that `parity-scale-codec` derives add to their implementations:
+        ///
+        /// ```rust
+        /// use parity_scale_codec::{Decode, Encode};
+        /// use serde_scale_wrap::{ScaleSerde, Wrap};
+        ///
+        /// #[derive(Decode, Encode)]
+        /// struct Versioned<T: ScaleSerde> {
+        ///     version: u8,
+        ///     value: Wrap<T>,
+        /// }
+        ///
+        /// fn roundtrip<T: ScaleSerde>(value: T) -> T {
+        ///     let encoded = Versioned { version: 1, value: Wrap(value) }.encode();
+        ///     Versioned::<T>::decode(&mut &*encoded).unwrap().value.0
+        /// }
+        ///
+        /// assert_eq!(roundtrip(vec![7u16]), [7]);
+        /// ```
+        pub trait ScaleSerde: $($bounds)+ {}
+
+        impl<T: $($bounds)+> ScaleSerde for T {}
+    };
+}
+
+#[cfg(not(feature = "deny-panicking-encode"))]
+define_scale_serde!(Serialize + DeserializeOwned);
+
+#[cfg(feature = "deny-panicking-encode")]
+define_scale_serde!(InfallibleSerialize + DeserializeOwned);
+
+#[cfg(all(test, feature = "alloc", feature = "encode", feature = "decode"))]
+mod tests {
+    /// Code written as a downstream crate would, only bounding wrapped types with `ScaleSerde`
+    mod downstream {
+        use crate::{ScaleSerde, Wrap};
+        use alloc::vec::Vec;
+        use parity_scale_codec::{Decode, Encode};
+
+        #[derive(Decode, Encode)]
+        pub(super) struct Envelope<T: ScaleSerde> {
+            pub(super) nonce: u32,
+            pub(super) payload: Wrap<T>,
+        }
+
+        #[derive(Decode, Encode)]
+        pub(super) enum Message<T: ScaleSerde, U: ScaleSerde> {
+            Single(Wrap<T>),
+            Pair(Wrap<T>, Wrap<U>),
+        }
+
+        pub(super) fn seal<T: ScaleSerde>(nonce: u32, payload: T) -> Vec<u8> {
+            Envelope { nonce, payload: Wrap(payload) }.encode()
+        }
+
+        pub(super) fn open<T: ScaleSerde>(bytes: &[u8]) -> Option<(u32, T)> {
+            let envelope = Envelope::<T>::decode(&mut &*bytes).ok()?;
+            Some((envelope.nonce, envelope.payload.0))
+        }
+
+        pub(super) fn swap<T: ScaleSerde, U: ScaleSerde>(bytes: &[u8]) -> Vec<u8> {
+            match Message::<T, U>::decode(&mut &*bytes).unwrap() {
+                Message::Single(t) => Message::<T, U>::Single(t).encode(),
+                Message::Pair(t, u) => Message::<U, T>::Pair(u, t).encode(),
+            }
+        }
+    }
+
+    use super::ScaleSerde;
+    use crate::{encode, Wrap};
+    use alloc::{string::String, vec, vec::Vec};
+    use parity_scale_codec::Encode;
+
+    fn assert_scale_serde<T: ScaleSerde>() {}
+
+    #[test]
+    fn serde_types_implement_scale_serde() {
+        assert_scale_serde::<u8>();
+        assert_scale_serde::<String>();
+        assert_scale_serde::<Vec<(u32, Option<String>)>>();
+    }
+
+    #[test]
+    fn derived_generic_containers_roundtrip() {
+        let payload = vec![String::from("alice"), String::from("bob")];
+        let sealed = downstream::seal(7, payload.clone());
+        assert_eq!(sealed, [&7u32.encode()[..], &encode(&payload)].concat());
+        assert_eq!(downstream::open::<Vec<String>>(&sealed), Some((7, payload)));
+        let envelope = downstream::Envelope { nonce: 7, payload: Wrap(1u8) };
+        assert_eq!(envelope.encode(), [7, 0, 0, 0, 1]);
+        assert_eq!(envelope.payload.0, 1);
+    }
+
+    #[test]
+    fn derived_generic_enums_roundtrip() {
+        let pair = encode(&(1u8, 3u8, String::from("x")));
+        assert_eq!(downstream::swap::<u8, String>(&pair), encode(&(1u8, String::from("x"), 3u8)));
+        let single = encode(&(0u8, 3u8));
+        assert_eq!(downstream::swap::<u8, String>(&single), single);
+    }
+}
@@ -0,0 +1,84 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Decoding straight into smart pointers
+
+use crate::{deserialize_from, Error, Wrap};
+use alloc::{boxed::Box, rc::Rc, sync::Arc};
+use parity_scale_codec::Input;
+use serde::de::DeserializeOwned;
+
+impl<T: DeserializeOwned> Wrap<T> {
+    /// Decodes a `T` from `input` into a `Box`, like `Wrap::<Box<T>>::decode` does
+    ///
+    /// The decoded value is returned on the heap, so that the caller does not need room for it
+    /// on its stack, unlike with `Box::new(Wrap::<T>::decode(input)?.0)`, which also moves it
+    /// twice. This helps with large values decoded in threads with small stacks, but `serde` still
+    /// deserializes a `T` on the stack before moving it into the box, so decoding itself needs as
+    /// much stack space as decoding an unboxed `T`.
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::Wrap;
+    ///
+    /// let encoded = serde_scale_wrap::encode(&[[7u8; 32]; 32]);
+    /// let table: Box<[[u8; 32]; 32]> = Wrap::decode_boxed(&mut &*encoded)?;
+    /// assert_eq!(table[31][31], 7);
+    /// # Ok::<_, serde_scale_wrap::Error>(())
+    /// ```
+    pub fn decode_boxed<I: Input>(input: &mut I) -> Result<Box<T>, Error> {
+        deserialize_from(input)
+    }
+
+    /// Decodes a `T` from `input` into an `Rc`, moving it from the heap like [`Wrap::decode_boxed`]
+    /// does
+    pub fn decode_rc<I: Input>(input: &mut I) -> Result<Rc<T>, Error> {
+        Wrap::decode_boxed(input).map(Rc::from)
+    }
+
+    /// Decodes a `T` from `input` into an `Arc`, moving it from the heap like
+    /// [`Wrap::decode_boxed`] does
+    pub fn decode_arc<I: Input>(input: &mut I) -> Result<Arc<T>, Error> {
+        Wrap::decode_boxed(input).map(Arc::from)
+    }
+}
+
+#[cfg(all(test, feature = "std", feature = "encode", feature = "decode"))]
+mod tests {
+    use crate::{encode, Error, Wrap};
+    use alloc::boxed::Box;
+    use serde::{Deserialize, Serialize};
+
+    /// Value of 16 KiB
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Frame {
+        id: u32,
+        pixels: [[[u8; 32]; 32]; 16],
+    }
+
+    fn frame() -> Box<Frame> {
+        let mut frame = Box::new(Frame { id: 7, pixels: [[[0; 32]; 32]; 16] });
+        frame.pixels.iter_mut().flatten().flatten().enumerate().for_each(|(i, p)| *p = i as u8);
+        frame
+    }
+
+    #[test]
+    fn large_values_are_decoded_into_boxes_in_small_threads() {
+        let encoded = encode(&*frame());
+        let decoded = std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(move || Wrap::<Frame>::decode_boxed(&mut &*encoded))
+            .unwrap()
+            .join()
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded, frame());
+    }
+
+    #[test]
+    fn values_are_decoded_into_shared_pointers() {
+        let encoded = encode(&*frame());
+        assert_eq!(*Wrap::<Frame>::decode_rc(&mut &*encoded).unwrap(), *frame());
+        assert_eq!(*Wrap::<Frame>::decode_arc(&mut &*encoded).unwrap(), *frame());
+        let result = Wrap::<Frame>::decode_arc(&mut &encoded[..100]);
+        assert!(matches!(result, Err(Error::Input(_))));
+    }
+}
@@ -0,0 +1,204 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Decoding from `std::io::BufRead` without copying bytes already in the reader's buffer
+
+use crate::{
+    de, logging,
+    options::Progress,
+    scale::{Bytes, Read},
+    DecodeOptions, Error, Wrap, READ_CHUNK_LEN,
+};
+use serde::de::DeserializeOwned;
+use std::{io::BufRead, vec::Vec};
+
+impl<T: DeserializeOwned> Wrap<T> {
+    /// Decodes a `Wrap` from `reader`
+    ///
+    /// Strings and byte sequences held entirely in the buffer of `reader` are deserialized from it
+    /// directly. Longer ones are copied to an intermediate buffer. Only the bytes of the encoding
+    /// are consumed from `reader`.
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::Wrap;
+    /// use std::io::{BufRead, BufReader};
+    ///
+    /// let mut reader = BufReader::new(&b"\x07\x0cfoo\x2a"[..]);
+    /// let Wrap(decoded) = Wrap::<(u8, String)>::decode_from_buf_read(&mut reader)?;
+    /// assert_eq!(decoded, (7, "foo".to_owned()));
+    /// assert_eq!(reader.fill_buf().unwrap(), b"\x2a");
+    /// # Ok::<_, serde_scale_wrap::Error>(())
+    /// ```
+    pub fn decode_from_buf_read<R: BufRead + ?Sized>(reader: &mut R) -> Result<Self, Error> {
+        DecodeOptions::new().decode_from_buf_read(reader).map(Wrap)
+    }
+}
+
+impl DecodeOptions {
+    /// Decodes a `T` from `reader` with these options, like `Wrap::<T>::decode_from_buf_read`
+    /// does
+    pub fn decode_from_buf_read<T, R>(&self, reader: &mut R) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+        R: BufRead + ?Sized,
+    {
+        logging::decode_counted(|count| {
+            let progress = Progress::new(self);
+            let read = BufReadToRead { reader, buffer: Vec::new(), count, progress };
+            let mut deserializer = crate::scale::Deserializer::new(read);
+            let value = de::deserialize(&mut deserializer, *self)?;
+            let read = deserializer.into_inner();
+            read.progress.finish(*read.count);
+            Ok(value)
+        })
+    }
+}
+
+struct BufReadToRead<'a, R: ?Sized> {
+    reader: &'a mut R,
+    buffer: Vec<u8>,
+    count: &'a mut usize,
+    progress: Progress,
+}
+
+impl<'de, R: BufRead + ?Sized> Read<'de> for BufReadToRead<'_, R> {
+    type Error = Error;
+
+    fn read_map<U, F>(&mut self, n: usize, f: F) -> Result<U, Error>
+    where
+        F: FnOnce(Bytes<'de, '_>) -> U,
+    {
+        let available = self.reader.fill_buf()?;
+        if let Some(bytes) = available.get(..n) {
+            let result = f(Bytes::Temporary(bytes));
+            self.reader.consume(n);
+            *self.count += n;
+            self.progress.update(*self.count);
+            return Ok(result);
+        }
+        // `n` may come from a forged length prefix, so the buffer grows as bytes are actually
+        // read instead of being allocated upfront.
+        self.buffer.clear();
+        while self.buffer.len() < n {
+            let start = self.buffer.len();
+            let end = n.min(start + start.max(READ_CHUNK_LEN));
+            self.buffer.resize(end, 0);
+            self.reader.read_exact(&mut self.buffer[start..])?;
+            *self.count += end - start;
+            self.progress.update(*self.count);
+        }
+        Ok(f(Bytes::Temporary(&self.buffer)))
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        self.reader.read_exact(buf)?;
+        *self.count += buf.len();
+        self.progress.update(*self.count);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use crate::{DecodeOptions, Error, Wrap};
+    use serde::{Deserialize, Serialize};
+    use std::{
+        cell::RefCell,
+        collections::BTreeMap,
+        io::{BufRead, BufReader, ErrorKind},
+        string::String,
+        vec,
+        vec::Vec,
+    };
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    struct Record {
+        key: String,
+        value: Vec<u8>,
+        labels: BTreeMap<String, String>,
+    }
+
+    fn records() -> Vec<Record> {
+        (0..20)
+            .map(|i| Record {
+                key: "k".repeat(i),
+                value: vec![i as u8; 3 * i],
+                labels: BTreeMap::from([("a".into(), "xyz".repeat(i % 4))]),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn buffered_decode_matches_slice_decode() {
+        type Value = (Vec<Record>, u8);
+
+        let encoded = crate::encode(&(records(), 0xabu8));
+        let expected = crate::decode::<Value>(&encoded).unwrap();
+        for capacity in [1, 2, 5, 16, 4096] {
+            let mut reader = BufReader::with_capacity(capacity, &*encoded);
+            let Wrap(decoded) = Wrap::<Value>::decode_from_buf_read(&mut reader).unwrap();
+            assert_eq!(decoded, expected);
+            assert!(reader.fill_buf().unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn only_the_encoding_is_consumed() {
+        let mut bytes = crate::encode(&records());
+        bytes.extend_from_slice(b"rest");
+        for capacity in [3, 64, 4096] {
+            let mut reader = BufReader::with_capacity(capacity, &*bytes);
+            let Wrap(decoded) = Wrap::<Vec<Record>>::decode_from_buf_read(&mut reader).unwrap();
+            assert_eq!(decoded, records());
+            let mut rest = Vec::new();
+            std::io::Read::read_to_end(&mut reader, &mut rest).unwrap();
+            assert_eq!(rest, b"rest");
+        }
+    }
+
+    #[test]
+    fn truncated_input_fails() {
+        let encoded = crate::encode(&records());
+        let mut reader = BufReader::with_capacity(8, &encoded[..encoded.len() - 1]);
+        match Wrap::<Vec<Record>>::decode_from_buf_read(&mut reader) {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), ErrorKind::UnexpectedEof),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    thread_local! {
+        static REPORTED: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn record(count: u64) {
+        REPORTED.with(|reported| reported.borrow_mut().push(count));
+    }
+
+    #[test]
+    fn progress_is_reported_at_intervals() {
+        let chunks = vec![vec![0x5a; 4096]; 256];
+        let encoded = crate::encode(&chunks);
+        assert!(encoded.len() > 1 << 20);
+        let options = DecodeOptions::new().on_progress(100_000, record);
+        let mut reader = BufReader::with_capacity(1000, &*encoded);
+        let decoded = options.decode_from_buf_read::<Vec<Vec<u8>>, _>(&mut reader).unwrap();
+        assert_eq!(decoded, chunks);
+        let reported = REPORTED.with(|reported| reported.take());
+        assert_eq!(reported.len(), encoded.len() / 100_000 + 1);
+        assert!(reported[0] >= 100_000);
+        let (&last, reported) = reported.split_last().unwrap();
+        assert_eq!(last, encoded.len() as u64);
+        assert!(reported.windows(2).all(|w| w[1] - w[0] >= 100_000));
+        assert!(reported.iter().all(|&n| n < last));
+    }
+
+    #[test]
+    fn failed_decodes_are_not_reported_as_complete() {
+        let encoded = crate::encode(&records());
+        let options = DecodeOptions::new().on_progress(1, record);
+        let result = options.decode_from_buf_read::<Vec<Record>, _>(&mut &encoded[..100]);
+        assert!(matches!(result, Err(Error::Io(_))));
+        let reported = REPORTED.with(|reported| reported.take());
+        assert!(reported.windows(2).all(|w| w[0] < w[1]));
+        assert!(reported.iter().all(|&n| n <= 100));
+    }
+}
@@ -0,0 +1,284 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+use crate::{logging, serialize_to, try_serialize_to, Error};
+use alloc::vec::Vec;
+use core::{
+    borrow::Borrow,
+    cmp::Ordering,
+    hash::{Hash, Hasher},
+    ops::Deref,
+};
+use parity_scale_codec::{Encode, EncodeLike, Output};
+use serde::Serialize;
+
+#[cfg(feature = "decode")]
+use parity_scale_codec::{Decode, Input};
+#[cfg(feature = "decode")]
+use serde::de::DeserializeOwned;
+
+/// Wrapper comparing and hashing values by their SCALE encoding
+///
+/// The encoding is computed once when wrapping and `Eq`, `Ord` and `Hash` compare and hash its
+/// bytes, so that serde-only types can be put in sets and maps, ordered as their encodings are.
+/// This order is lexicographic over the bytes and usually differs from the order of `T` (e.g.
+/// integers are encoded in little-endian). `ByEncoding` borrows as `[u8]`, letting sets and maps
+/// be queried by encoding.
+///
+/// ```rust
+/// use serde_scale_wrap::ByEncoding;
+/// use std::collections::BTreeSet;
+///
+/// let set = [256u16, 1, 2].iter().map(|&n| ByEncoding::new(n)).collect::<BTreeSet<_>>();
+/// assert_eq!(set.iter().map(|n| **n).collect::<Vec<_>>(), [256, 1, 2]);
+/// assert!(set.contains(&[1, 0][..]));
+/// ```
+#[derive(Clone, Debug)]
+pub struct ByEncoding<T> {
+    value: T,
+    encoded: Vec<u8>,
+}
+
+impl<T: Serialize> ByEncoding<T> {
+    /// Wraps `value` and computes its encoding
+    ///
+    /// # Panics
+    /// Panics if the serializer returns an error, like `Wrap` does.
+    pub fn new(value: T) -> Self {
+        let mut encoded = Vec::new();
+        serialize_to(&value, &mut encoded);
+        ByEncoding { value, encoded }
+    }
+
+    /// Wraps `value` and computes its encoding, or returns an error if it cannot be serialized
+    pub fn try_new(value: T) -> Result<Self, Error> {
+        let mut encoded = Vec::new();
+        logging::encode_logged::<T, _, _>(&mut encoded, |dst| try_serialize_to(&value, dst))?;
+        Ok(ByEncoding { value, encoded })
+    }
+}
+
+impl<T> ByEncoding<T> {
+    /// Returns the wrapped value
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    /// Returns the encoding of the wrapped value
+    pub fn encoded(&self) -> &[u8] {
+        &self.encoded
+    }
+
+    /// Returns the wrapped value
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for ByEncoding<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> AsRef<T> for ByEncoding<T> {
+    fn as_ref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> Borrow<[u8]> for ByEncoding<T> {
+    fn borrow(&self) -> &[u8] {
+        &self.encoded
+    }
+}
+
+impl<T> PartialEq for ByEncoding<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.encoded == other.encoded
+    }
+}
+
+impl<T> Eq for ByEncoding<T> {}
+
+impl<T> PartialOrd for ByEncoding<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for ByEncoding<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.encoded.cmp(&other.encoded)
+    }
+}
+
+impl<T> Hash for ByEncoding<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.encoded.hash(state)
+    }
+}
+
+/// Writes the stored encoding
+impl<T> Encode for ByEncoding<T> {
+    fn size_hint(&self) -> usize {
+        self.encoded.len()
+    }
+
+    fn encode_to<O: Output + ?Sized>(&self, dst: &mut O) {
+        dst.write(&self.encoded);
+    }
+
+    fn using_encoded<R, F: FnOnce(&[u8]) -> R>(&self, f: F) -> R {
+        f(&self.encoded)
+    }
+
+    fn encoded_size(&self) -> usize {
+        self.encoded.len()
+    }
+}
+
+impl<T> EncodeLike for ByEncoding<T> {}
+
+/// Decodes a `T` like `Wrap` does and encodes it again, so that the stored encoding is canonical
+/// even if the input was not (e.g. with lengths encoded with more bytes than necessary)
+#[cfg(feature = "decode")]
+impl<T: DeserializeOwned + Serialize> Decode for ByEncoding<T> {
+    fn decode<I: Input>(input: &mut I) -> Result<Self, parity_scale_codec::Error> {
+        let value = crate::deserialize_from::<T, _>(input)?;
+        Ok(ByEncoding::try_new(value)?)
+    }
+}
+
+/// Compares the encodings of `a` and `b` lexicographically, like `ByEncoding` does
+///
+/// Only the encoding of `a` is stored. The encoding of `b` is compared as it is produced.
+///
+/// # Panics
+/// Panics if the serializer returns an error.
+///
+/// ```rust
+/// use std::cmp::Ordering;
+///
+/// assert_eq!(serde_scale_wrap::compare_encodings(&256u16, &1u16), Ordering::Less);
+/// assert_eq!(serde_scale_wrap::compare_encodings("ab", "a"), Ordering::Greater);
+/// ```
+pub fn compare_encodings<A, B>(a: &A, b: &B) -> Ordering
+where
+    A: Serialize + ?Sized,
+    B: Serialize + ?Sized,
+{
+    let mut encoded = Vec::new();
+    serialize_to(a, &mut encoded);
+    let mut dst = ComparingOutput { expected: &encoded, ordering: Ordering::Equal };
+    serialize_to(b, &mut dst);
+    dst.ordering.then_with(|| dst.expected.len().cmp(&0))
+}
+
+/// `Output` comparing written bytes against `expected`, from the point of view of `expected`
+struct ComparingOutput<'a> {
+    expected: &'a [u8],
+    ordering: Ordering,
+}
+
+impl Output for ComparingOutput<'_> {
+    fn write(&mut self, bytes: &[u8]) {
+        if self.ordering != Ordering::Equal {
+            return;
+        }
+        let len = bytes.len().min(self.expected.len());
+        let (expected, rest) = self.expected.split_at(len);
+        self.ordering = expected.cmp(&bytes[..len]);
+        if self.ordering == Ordering::Equal && len < bytes.len() {
+            self.ordering = Ordering::Less;
+        }
+        self.expected = rest;
+    }
+}
+
+#[cfg(all(test, feature = "decode"))]
+mod tests {
+    use super::{compare_encodings, ByEncoding};
+    use crate::{encode, Error};
+    use alloc::{collections::BTreeSet, string::String, vec, vec::Vec};
+    use parity_scale_codec::{Decode, Encode};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    enum Key {
+        Id(u32),
+        Name(String),
+        Pair(u8, Vec<u16>),
+    }
+
+    fn keys() -> Vec<Key> {
+        vec![
+            Key::Name("b".into()),
+            Key::Id(256),
+            Key::Pair(1, vec![]),
+            Key::Id(1),
+            Key::Name("ab".into()),
+            Key::Pair(1, vec![0, 1]),
+            Key::Name("".into()),
+            Key::Id(0x0100_0000),
+            Key::Pair(0, vec![7]),
+        ]
+    }
+
+    #[test]
+    fn order_matches_byte_order_of_encodings() {
+        let mut wrapped = keys().into_iter().map(ByEncoding::new).collect::<Vec<_>>();
+        wrapped.sort();
+        let mut encodings = keys().iter().map(encode).collect::<Vec<_>>();
+        encodings.sort();
+        let sorted = wrapped.iter().map(|k| k.encoded().to_vec()).collect::<Vec<_>>();
+        assert_eq!(sorted, encodings);
+        assert!(sorted.iter().all(|e| wrapped.iter().any(|k| k.encode() == *e)));
+    }
+
+    #[test]
+    fn streaming_comparison_matches_byte_order() {
+        for a in keys() {
+            for b in keys() {
+                let expected = encode(&a).cmp(&encode(&b));
+                assert_eq!(compare_encodings(&a, &b), expected);
+                assert_eq!(ByEncoding::new(a.clone()).cmp(&ByEncoding::new(b.clone())), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn values_are_found_by_encoding() {
+        let set = keys().into_iter().map(ByEncoding::new).collect::<BTreeSet<_>>();
+        assert_eq!(set.len(), keys().len());
+        assert!(set.contains(&encode(&Key::Id(256))[..]));
+        assert!(!set.contains(&encode(&Key::Id(2))[..]));
+    }
+
+    #[test]
+    fn unserializable_values_are_rejected() {
+        assert!(matches!(ByEncoding::try_new(1u128), Err(Error::Serialization(_))));
+    }
+
+    #[test]
+    fn decoding_stores_the_canonical_encoding() {
+        let decoded = ByEncoding::<Vec<u8>>::decode(&mut &[0b0000_0001, 0][..]).unwrap();
+        assert!(decoded.is_empty());
+        assert_eq!(decoded, ByEncoding::new(Vec::new()));
+        assert_eq!(decoded.encode(), [0]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn equal_values_collide_in_hash_sets() {
+        use std::collections::HashSet;
+
+        let mut set = HashSet::new();
+        assert!(set.insert(ByEncoding::new(Key::Pair(1, vec![0, 1]))));
+        assert!(!set.insert(ByEncoding::new(Key::Pair(1, vec![0, 1]))));
+        assert!(set.insert(ByEncoding::new(Key::Pair(1, vec![1, 0]))));
+        assert!(set.contains(&encode(&Key::Pair(1, vec![0, 1]))[..]));
+        assert_eq!(set.len(), 2);
+    }
+}
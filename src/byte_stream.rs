@@ -0,0 +1,194 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Streaming of byte sequences encoded like `Vec<u8>` without holding them in memory
+
+use crate::{Error, READ_CHUNK_LEN};
+use core::convert::TryFrom;
+
+#[cfg(feature = "decode")]
+use crate::compact::read_compact_len;
+#[cfg(feature = "decode")]
+use parity_scale_codec::Input;
+#[cfg(feature = "encode")]
+use parity_scale_codec::{Compact, Encode, Output};
+#[cfg(feature = "decode")]
+use std::io::Write;
+#[cfg(feature = "encode")]
+use std::io::{ErrorKind, Read};
+
+/// Writes the encoding of the `len` bytes read from `r` to `dst`, as `Wrap<Vec<u8>>` encodes them
+///
+/// The compact length is written first, then bytes are copied from `r` to `dst` in chunks. If `r`
+/// yields fewer or more than `len` bytes, [`Error::LengthMismatch`] is returned and `dst` holds a
+/// truncated encoding. A single byte is read past the declared length to detect extra bytes.
+///
+/// ```rust
+/// use parity_scale_codec::Encode;
+/// use serde_scale_wrap::Wrap;
+///
+/// let mut encoded = Vec::new();
+/// serde_scale_wrap::encode_bytes_from_reader(3, &mut &b"foo"[..], &mut encoded)?;
+/// assert_eq!(encoded, Wrap(b"foo".to_vec()).encode());
+/// # Ok::<_, serde_scale_wrap::Error>(())
+/// ```
+#[cfg(feature = "encode")]
+pub fn encode_bytes_from_reader<R, O>(len: u64, r: &mut R, dst: &mut O) -> Result<(), Error>
+where
+    R: Read + ?Sized,
+    O: Output + ?Sized,
+{
+    let compact_len = u32::try_from(len).map_err(|_| {
+        let len = usize::try_from(len).unwrap_or(usize::MAX);
+        Error::Serialization(crate::scale::Error::CollectionTooLargeToSerialize { len })
+    })?;
+    Compact(compact_len).encode_to(dst);
+    let mut chunk = [0; READ_CHUNK_LEN];
+    let mut copied = 0;
+    while copied < len {
+        let n = (len - copied).min(chunk.len() as u64) as usize;
+        match r.read(&mut chunk[..n]) {
+            Ok(0) => return Err(Error::LengthMismatch { declared: len, actual: copied }),
+            Ok(n) => {
+                dst.write(&chunk[..n]);
+                copied += n as u64;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    loop {
+        match r.read(&mut chunk[..1]) {
+            Ok(0) => return Ok(()),
+            Ok(_) => return Err(Error::LengthMismatch { declared: len, actual: len + 1 }),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Decodes a byte sequence encoded like `Vec<u8>` from `input` and writes its bytes to `w`
+///
+/// Bytes are copied in chunks and their number is returned. Like `Wrap<Vec<u8>>`, length prefixes
+/// longer than necessary are accepted. `w` is not flushed and may have been partially written to
+/// if an error is returned.
+///
+/// ```rust
+/// let mut written = Vec::new();
+/// let len = serde_scale_wrap::decode_bytes_to_writer(&mut &b"\x0cfoo"[..], &mut written)?;
+/// assert_eq!(len, 3);
+/// assert_eq!(written, b"foo");
+/// # Ok::<_, serde_scale_wrap::Error>(())
+/// ```
+#[cfg(feature = "decode")]
+pub fn decode_bytes_to_writer<I, W>(input: &mut I, w: &mut W) -> Result<u64, Error>
+where
+    I: Input,
+    W: Write + ?Sized,
+{
+    let len = usize::try_from(read_compact_len(input)?).map_err(|_| {
+        Error::Deserialization(crate::scale::Error::CollectionTooLargeToDeserialize)
+    })?;
+    if let Some(remaining) = input.remaining_len()? {
+        if len > remaining {
+            return Err(parity_scale_codec::Error::from("Not enough data to fill buffer").into());
+        }
+    }
+    let mut chunk = [0; READ_CHUNK_LEN];
+    let mut copied = 0;
+    while copied < len {
+        let n = (len - copied).min(chunk.len());
+        input.read(&mut chunk[..n])?;
+        w.write_all(&chunk[..n])?;
+        copied += n;
+    }
+    Ok(len as u64)
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::{decode_bytes_to_writer, encode_bytes_from_reader};
+    use crate::{Error, Wrap};
+    use parity_scale_codec::{Decode, Encode};
+    use std::{io::Read, vec::Vec};
+
+    const PAYLOAD_LEN: usize = 10 * 1024 * 1024;
+
+    /// Synthetic file yielding at most 1000 bytes per read
+    struct File {
+        len: usize,
+        position: usize,
+    }
+
+    impl File {
+        fn new(len: usize) -> Self {
+            File { len, position: 0 }
+        }
+    }
+
+    impl Read for File {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(1000).min(self.len - self.position);
+            for (i, b) in buf[..n].iter_mut().enumerate() {
+                *b = byte_at(self.position + i);
+            }
+            self.position += n;
+            Ok(n)
+        }
+    }
+
+    fn byte_at(i: usize) -> u8 {
+        (i % 251) as u8
+    }
+
+    fn payload() -> Vec<u8> {
+        (0..PAYLOAD_LEN).map(byte_at).collect()
+    }
+
+    #[test]
+    fn streamed_encoding_matches_vec_encoding() {
+        let mut encoded = Vec::new();
+        let mut file = File::new(PAYLOAD_LEN);
+        encode_bytes_from_reader(PAYLOAD_LEN as u64, &mut file, &mut encoded).unwrap();
+        assert_eq!(encoded, Wrap(payload()).encode());
+    }
+
+    #[test]
+    fn streamed_decoding_matches_vec_decoding() {
+        let mut encoded = Wrap(payload()).encode();
+        encoded.push(0xff);
+        let mut input = &*encoded;
+        let mut written = Vec::new();
+        let len = decode_bytes_to_writer(&mut input, &mut written).unwrap();
+        assert_eq!(len, PAYLOAD_LEN as u64);
+        assert_eq!(input, [0xff]);
+        assert_eq!(written, Wrap::<Vec<u8>>::decode(&mut &*encoded).unwrap().0);
+    }
+
+    #[test]
+    fn readers_of_the_wrong_length_are_rejected() {
+        let mut encoded = Vec::new();
+        let result = encode_bytes_from_reader(2500, &mut File::new(2000), &mut encoded);
+        assert!(matches!(result, Err(Error::LengthMismatch { declared: 2500, actual: 2000 })));
+        let result = encode_bytes_from_reader(2500, &mut File::new(3000), &mut Vec::new());
+        assert!(matches!(result, Err(Error::LengthMismatch { declared: 2500, actual: 2501 })));
+    }
+
+    #[test]
+    fn truncated_input_fails_before_writing() {
+        let encoded = Wrap(payload()[..5000].to_vec()).encode();
+        let mut written = Vec::new();
+        let result = decode_bytes_to_writer(&mut &encoded[..4000], &mut written);
+        assert!(matches!(result, Err(Error::Input(_))));
+        assert!(written.is_empty());
+    }
+
+    #[test]
+    fn non_canonical_lengths_are_accepted_like_vec_decoding() {
+        for encoded in [&[1, 0][..], &[2, 0, 0, 0], &[3, 0, 0, 0, 0], &[5, 0, b'x']] {
+            let mut written = Vec::new();
+            let len = decode_bytes_to_writer(&mut &*encoded, &mut written).unwrap();
+            let Wrap(decoded) = Wrap::<Vec<u8>>::decode(&mut &*encoded).unwrap();
+            assert_eq!((len, &written), (decoded.len() as u64, &decoded));
+        }
+    }
+}
@@ -0,0 +1,229 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Encoding into `bytes::BytesMut` and decoding from `bytes::Buf` without copying to a contiguous
+//! buffer
+//!
+//! [`BytesOutput`] adapts `BytesMut` to `Output`, and `BytesMut` implements [`TryOutput`].
+//! [`BufInput`] adapts any `Buf` to `Input`, reading across the chunks of non-contiguous buffers.
+//! The former require the `encode` feature and the latter the `decode` feature.
+//!
+//! ```rust
+//! use bytes::{Buf, Bytes};
+//! use parity_scale_codec::Decode;
+//! use serde_scale_wrap::{bytes::BufInput, Wrap};
+//!
+//! let encoded = Wrap((7u32, "foo")).encode_to_bytes();
+//! let (head, tail) = (encoded.slice(..2), encoded.slice(2..));
+//! let mut input = BufInput::new(head.chain(tail));
+//! let Wrap(decoded) = Wrap::<(u32, String)>::decode(&mut input)?;
+//! assert_eq!(decoded, (7, "foo".to_owned()));
+//! # Ok::<_, parity_scale_codec::Error>(())
+//! ```
+
+#[cfg(feature = "encode")]
+use crate::{serialize_to, Error, TryOutput, Wrap};
+#[cfg(feature = "decode")]
+use bytes::Buf;
+#[cfg(feature = "encode")]
+use bytes::{BufMut, Bytes, BytesMut};
+#[cfg(feature = "decode")]
+use parity_scale_codec::Input;
+#[cfg(feature = "encode")]
+use parity_scale_codec::Output;
+#[cfg(feature = "encode")]
+use serde::Serialize;
+
+#[cfg(feature = "encode")]
+impl TryOutput for BytesMut {
+    fn try_write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// `Output` appending to a `BytesMut`
+///
+/// ```rust
+/// use bytes::BytesMut;
+/// use parity_scale_codec::Encode;
+/// use serde_scale_wrap::{bytes::BytesOutput, Wrap};
+///
+/// let mut encoded = BytesMut::new();
+/// Wrap(7u16).encode_to(&mut BytesOutput::new(&mut encoded));
+/// assert_eq!(encoded, &[7, 0][..]);
+/// ```
+#[cfg(feature = "encode")]
+#[derive(Debug)]
+pub struct BytesOutput<'a> {
+    bytes: &'a mut BytesMut,
+}
+
+#[cfg(feature = "encode")]
+impl<'a> BytesOutput<'a> {
+    /// Returns an `Output` appending to `bytes`
+    pub fn new(bytes: &'a mut BytesMut) -> Self {
+        BytesOutput { bytes }
+    }
+}
+
+#[cfg(feature = "encode")]
+impl Output for BytesOutput<'_> {
+    fn write(&mut self, bytes: &[u8]) {
+        self.bytes.put_slice(bytes);
+    }
+
+    fn push_byte(&mut self, byte: u8) {
+        self.bytes.put_u8(byte);
+    }
+}
+
+/// `Input` reading from a `Buf`, possibly made of several chunks
+///
+/// Reads spanning several chunks are supported, so a `Buf` like `bytes::buf::Chain` can be
+/// decoded from without being flattened first.
+#[cfg(feature = "decode")]
+#[derive(Debug)]
+pub struct BufInput<B> {
+    buf: B,
+}
+
+#[cfg(feature = "decode")]
+impl<B: Buf> BufInput<B> {
+    /// Returns an `Input` reading from `buf`
+    pub fn new(buf: B) -> Self {
+        BufInput { buf }
+    }
+
+    /// Returns a reference to the underlying buffer
+    pub fn get_ref(&self) -> &B {
+        &self.buf
+    }
+
+    /// Returns the underlying buffer, advanced past the bytes read
+    pub fn into_inner(self) -> B {
+        self.buf
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<B: Buf> Input for BufInput<B> {
+    fn remaining_len(&mut self) -> Result<Option<usize>, parity_scale_codec::Error> {
+        Ok(Some(self.buf.remaining()))
+    }
+
+    fn read(&mut self, into: &mut [u8]) -> Result<(), parity_scale_codec::Error> {
+        if into.len() > self.buf.remaining() {
+            return Err("Not enough data to fill buffer".into());
+        }
+        self.buf.copy_to_slice(into);
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> Result<u8, parity_scale_codec::Error> {
+        if self.buf.has_remaining() {
+            Ok(self.buf.get_u8())
+        } else {
+            Err("Not enough data to fill buffer".into())
+        }
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<T> Wrap<T> {
+    /// Returns the SCALE encoding of the wrapped value as `Bytes`
+    ///
+    /// # Panics
+    /// Panics if the serializer returns an error.
+    pub fn encode_to_bytes(&self) -> Bytes
+    where
+        T: Serialize,
+    {
+        let mut encoded = BytesMut::new();
+        serialize_to(&self.0, &mut BytesOutput::new(&mut encoded));
+        encoded.freeze()
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::{BufInput, BytesOutput};
+    use crate::{Error, Wrap};
+    use alloc::{collections::BTreeMap, string::String, vec, vec::Vec};
+    use bytes::{Buf, Bytes, BytesMut};
+    use parity_scale_codec::{Decode, Input, Output};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    struct Message {
+        id: u64,
+        name: String,
+        tags: BTreeMap<u8, Vec<u16>>,
+        flag: bool,
+    }
+
+    fn message() -> Message {
+        Message {
+            id: 0x0102_0304_0506_0708,
+            name: "segmented".into(),
+            tags: (0..4).map(|k| (k, vec![u16::from(k); k.into()])).collect(),
+            flag: true,
+        }
+    }
+
+    fn segments(encoded: &[u8], cuts: &[usize]) -> impl Buf {
+        let mut buf: alloc::boxed::Box<dyn Buf> = alloc::boxed::Box::new(Bytes::new());
+        let mut start = 0;
+        for &end in cuts.iter().chain(&[encoded.len()]) {
+            let segment = Bytes::copy_from_slice(&encoded[start..end]);
+            buf = alloc::boxed::Box::new(buf.chain(segment));
+            start = end;
+        }
+        buf
+    }
+
+    #[test]
+    fn multi_chunk_decode_matches_contiguous_one() {
+        let encoded = Wrap(message()).encode_to_bytes();
+        let contiguous = Wrap::<Message>::decode(&mut &*encoded).unwrap();
+        let mut input = BufInput::new(segments(&encoded, &[1, 3, 4, 10, 11, 12, 20]));
+        let Wrap(segmented) = Wrap::<Message>::decode(&mut input).unwrap();
+        assert_eq!(segmented, contiguous.0);
+        assert_eq!(segmented, message());
+        assert!(!input.get_ref().has_remaining());
+    }
+
+    #[test]
+    fn reads_span_chunk_boundaries() {
+        let mut input = BufInput::new(segments(&[1, 2, 3, 4, 5], &[1, 2, 4]));
+        assert_eq!(input.remaining_len().unwrap(), Some(5));
+        let mut buf = [0; 4];
+        input.read(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3, 4]);
+        assert_eq!(input.read_byte().unwrap(), 5);
+        assert!(input.read_byte().is_err());
+    }
+
+    #[test]
+    fn truncated_input_fails_without_consuming() {
+        let mut input = BufInput::new(segments(&[1, 2, 3], &[1]));
+        assert!(input.read(&mut [0; 4]).is_err());
+        assert_eq!(input.into_inner().remaining(), 3);
+    }
+
+    #[test]
+    fn bytes_encoding_matches_vec_one() {
+        assert_eq!(Wrap(message()).encode_to_bytes(), crate::encode(&message()));
+    }
+
+    #[test]
+    fn bytes_mut_accepts_encodings() {
+        let mut encoded = BytesMut::new();
+        Wrap(message()).try_encode_to(&mut encoded).unwrap();
+        BytesOutput::new(&mut encoded).push_byte(9);
+        let mut input = BufInput::new(encoded.freeze());
+        Wrap::<Message>::decode(&mut input).unwrap();
+        assert_eq!(input.read_byte().unwrap(), 9);
+        let result = Wrap::<Message>::decode_with_scratch(&mut input, &mut []);
+        assert!(matches!(result, Err(Error::Input(_))));
+    }
+}
@@ -0,0 +1,244 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+use crate::{decode_all, Error};
+use alloc::{collections::BTreeMap, sync::Arc};
+use serde::de::DeserializeOwned;
+
+/// Decoder caching the values it decodes, keyed by their encoding
+///
+/// The least recently used entries are evicted once the cache holds more than `max_entries`
+/// entries or more than `max_bytes` bytes of encodings. The size of the decoded values is not
+/// accounted for. Encodings longer than `max_bytes` are decoded but never cached.
+///
+/// Values are shared: every hit returns an `Arc` pointing to the same value, which is dropped
+/// once it was evicted and every `Arc` returned for it was dropped. Interior mutability in `T` is
+/// therefore visible to every holder, and an owned copy can be obtained with
+/// `Arc::unwrap_or_clone` if `T: Clone`.
+///
+/// Inputs are decoded with [`decode_all`], so only bytes holding exactly one encoding are cached
+/// and an input with bytes left over fails to decode even if its beginning is cached.
+///
+/// ```rust
+/// use serde_scale_wrap::CachedDecoder;
+///
+/// let mut decoder = CachedDecoder::<(u8, String)>::new(100, 1 << 20);
+/// let first = decoder.decode_cached(b"\x07\x0cfoo")?;
+/// let second = decoder.decode_cached(b"\x07\x0cfoo")?;
+/// assert_eq!(*second, (7, "foo".to_owned()));
+/// assert!(std::sync::Arc::ptr_eq(&first, &second));
+/// assert_eq!((decoder.hits(), decoder.misses()), (1, 1));
+/// assert!(decoder.decode_cached(b"\x07\x0cfoo\x00").is_err());
+/// # Ok::<_, serde_scale_wrap::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct CachedDecoder<T> {
+    entries: BTreeMap<Arc<[u8]>, Entry<T>>,
+    /// Keys of the entries, by time of last use
+    recency: BTreeMap<u64, Arc<[u8]>>,
+    /// Time of the next use
+    clock: u64,
+    max_entries: usize,
+    max_bytes: usize,
+    bytes: usize,
+    hits: u64,
+    misses: u64,
+}
+
+#[derive(Debug)]
+struct Entry<T> {
+    value: Arc<T>,
+    last_used: u64,
+}
+
+impl<T> CachedDecoder<T> {
+    /// Returns an empty cache holding at most `max_entries` entries and `max_bytes` bytes of
+    /// encodings
+    pub fn new(max_entries: usize, max_bytes: usize) -> Self {
+        CachedDecoder {
+            entries: BTreeMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            max_entries,
+            max_bytes,
+            bytes: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Returns the value decoded from `bytes`, decoding it only if it is not cached
+    ///
+    /// Errors are not cached, and decoding the same invalid bytes again fails again.
+    pub fn decode_cached(&mut self, bytes: &[u8]) -> Result<Arc<T>, Error>
+    where
+        T: DeserializeOwned,
+    {
+        let now = self.tick();
+        if let Some(entry) = self.entries.get_mut(bytes) {
+            self.hits += 1;
+            let key = self.recency.remove(&entry.last_used).expect("Entries are ordered by use");
+            self.recency.insert(now, key);
+            entry.last_used = now;
+            return Ok(Arc::clone(&entry.value));
+        }
+        self.misses += 1;
+        let value = Arc::new(decode_all::<T>(bytes)?);
+        let len = bytes.len();
+        if self.max_entries > 0 && len <= self.max_bytes {
+            while self.entries.len() >= self.max_entries || self.bytes + len > self.max_bytes {
+                self.evict_oldest();
+            }
+            let key = Arc::<[u8]>::from(bytes);
+            self.bytes += len;
+            self.recency.insert(now, Arc::clone(&key));
+            self.entries.insert(key, Entry { value: Arc::clone(&value), last_used: now });
+        }
+        Ok(value)
+    }
+
+    /// Returns the number of calls to `decode_cached` that found the value in the cache
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Returns the number of calls to `decode_cached` that decoded the input, including failed
+    /// ones
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Returns the number of cached values
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether no values are cached
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the total length of the encodings of the cached values
+    pub fn cached_bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Removes every cached value, keeping the counters
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+        self.bytes = 0;
+    }
+
+    fn tick(&mut self) -> u64 {
+        let now = self.clock;
+        self.clock += 1;
+        now
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some((_, key)) = self.recency.pop_first() {
+            self.bytes -= key.len();
+            self.entries.remove(&key);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::CachedDecoder;
+    use crate::{encode, Error};
+    use alloc::{string::String, sync::Arc, vec::Vec};
+
+    fn encoded(n: u32) -> Vec<u8> {
+        encode(&(n, String::from("value")))
+    }
+
+    fn cache(max_entries: usize, max_bytes: usize) -> CachedDecoder<(u32, String)> {
+        CachedDecoder::new(max_entries, max_bytes)
+    }
+
+    #[test]
+    fn repeated_inputs_hit() {
+        let mut decoder = cache(10, 1000);
+        let first = decoder.decode_cached(&encoded(1)).unwrap();
+        assert_eq!(*first, (1, "value".into()));
+        let second = decoder.decode_cached(&encoded(1)).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        decoder.decode_cached(&encoded(2)).unwrap();
+        assert_eq!((decoder.hits(), decoder.misses()), (1, 2));
+        assert_eq!((decoder.len(), decoder.cached_bytes()), (2, 2 * encoded(1).len()));
+    }
+
+    #[test]
+    fn least_recently_used_entries_are_evicted_first() {
+        let mut decoder = cache(2, 1000);
+        decoder.decode_cached(&encoded(1)).unwrap();
+        decoder.decode_cached(&encoded(2)).unwrap();
+        decoder.decode_cached(&encoded(1)).unwrap();
+        decoder.decode_cached(&encoded(3)).unwrap();
+        assert_eq!(decoder.len(), 2);
+        decoder.decode_cached(&encoded(1)).unwrap();
+        assert_eq!((decoder.hits(), decoder.misses()), (2, 3));
+        decoder.decode_cached(&encoded(2)).unwrap();
+        assert_eq!((decoder.hits(), decoder.misses()), (2, 4));
+    }
+
+    #[test]
+    fn byte_budget_evicts_entries() {
+        let len = encoded(1).len();
+        let mut decoder = cache(100, 2 * len + 1);
+        for n in 0..3 {
+            decoder.decode_cached(&encoded(n)).unwrap();
+        }
+        assert_eq!((decoder.len(), decoder.cached_bytes()), (2, 2 * len));
+        decoder.decode_cached(&encoded(0)).unwrap();
+        assert_eq!(decoder.hits(), 0);
+        decoder.decode_cached(&encoded(2)).unwrap();
+        assert_eq!(decoder.hits(), 1);
+    }
+
+    #[test]
+    fn oversized_inputs_are_not_cached() {
+        let mut decoder = cache(100, 4);
+        for _ in 0..2 {
+            assert_eq!(*decoder.decode_cached(&encoded(1)).unwrap(), (1, "value".into()));
+        }
+        assert_eq!((decoder.hits(), decoder.misses()), (0, 2));
+        assert!(decoder.is_empty());
+        let mut decoder = cache(0, 1000);
+        decoder.decode_cached(&encoded(1)).unwrap();
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn trailing_bytes_never_hit() {
+        let mut decoder = cache(10, 1000);
+        decoder.decode_cached(&encoded(1)).unwrap();
+        let mut longer = encoded(1);
+        longer.push(0);
+        let result = decoder.decode_cached(&longer);
+        assert!(matches!(result, Err(Error::TrailingBytes { len: 1 })));
+        assert!(decoder.decode_cached(&encoded(1)[..4]).is_err());
+        assert_eq!((decoder.hits(), decoder.misses(), decoder.len()), (0, 3, 1));
+    }
+
+    #[test]
+    fn errors_are_not_cached() {
+        let mut decoder = cache(10, 1000);
+        for _ in 0..2 {
+            assert!(decoder.decode_cached(b"\x01").is_err());
+        }
+        assert_eq!((decoder.misses(), decoder.len()), (2, 0));
+    }
+
+    #[test]
+    fn evicted_values_stay_alive_while_shared() {
+        let mut decoder = cache(1, 1000);
+        let first = decoder.decode_cached(&encoded(1)).unwrap();
+        decoder.decode_cached(&encoded(2)).unwrap();
+        assert_eq!(Arc::strong_count(&first), 1);
+        assert_eq!(*first, (1, "value".into()));
+        decoder.clear();
+        assert_eq!((decoder.len(), decoder.cached_bytes()), (0, 0));
+    }
+}
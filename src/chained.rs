@@ -0,0 +1,163 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+use parity_scale_codec::Input;
+
+/// `Input` reading from a sequence of byte slices as if they were concatenated
+///
+/// Reads spanning several fragments are supported, so fragmented payloads can be decoded without
+/// being copied into a contiguous buffer first.
+///
+/// ```rust
+/// use parity_scale_codec::Decode;
+/// use serde_scale_wrap::{ChainedInput, Wrap};
+///
+/// let fragments: [&[u8]; 3] = [b"\x07\x00", b"\x00\x00\x0c", b"foo"];
+/// let mut input = ChainedInput::new(&fragments);
+/// let Wrap(decoded) = Wrap::<(u32, String)>::decode(&mut input)?;
+/// assert_eq!(decoded, (7, "foo".to_owned()));
+/// # Ok::<_, parity_scale_codec::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct ChainedInput<'a> {
+    current: &'a [u8],
+    fragments: &'a [&'a [u8]],
+    last: &'a [u8],
+}
+
+impl<'a> ChainedInput<'a> {
+    /// Returns an `Input` reading from `fragments` in order
+    pub fn new(fragments: &'a [&'a [u8]]) -> Self {
+        ChainedInput { current: &[], fragments, last: &[] }
+    }
+
+    /// Returns an `Input` reading from `first`, then from `second`
+    ///
+    /// This suits the two halves of a ring buffer, like those returned by `VecDeque::as_slices`.
+    ///
+    /// ```rust
+    /// use std::collections::VecDeque;
+    /// use serde_scale_wrap::{ChainedInput, Wrap};
+    ///
+    /// let mut ring = VecDeque::with_capacity(4);
+    /// ring.extend([0, 0, 0, 9]);
+    /// ring.drain(..3);
+    /// ring.extend([1, 0]);
+    /// let (first, second) = ring.as_slices();
+    /// let Wrap(decoded) = Wrap::<u16>::decode_with_scratch(
+    ///     &mut ChainedInput::from_halves(first, second),
+    ///     &mut [],
+    /// )?;
+    /// assert_eq!(decoded, 0x0109);
+    /// # Ok::<_, serde_scale_wrap::Error>(())
+    /// ```
+    pub fn from_halves(first: &'a [u8], second: &'a [u8]) -> Self {
+        ChainedInput { current: first, fragments: &[], last: second }
+    }
+
+    /// Returns the number of bytes left to read
+    pub fn remaining(&self) -> usize {
+        self.current.len()
+            + self.fragments.iter().map(|fragment| fragment.len()).sum::<usize>()
+            + self.last.len()
+    }
+
+    /// Returns whether all bytes were read
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Moves to the next non-empty fragment if the current one has been read entirely
+    fn advance(&mut self) {
+        while self.current.is_empty() {
+            match self.fragments.split_first() {
+                Some((&next, rest)) => {
+                    self.current = next;
+                    self.fragments = rest;
+                }
+                None => {
+                    self.current = core::mem::take(&mut self.last);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl Input for ChainedInput<'_> {
+    fn remaining_len(&mut self) -> Result<Option<usize>, parity_scale_codec::Error> {
+        Ok(Some(self.remaining()))
+    }
+
+    fn read(&mut self, into: &mut [u8]) -> Result<(), parity_scale_codec::Error> {
+        if into.len() > self.remaining() {
+            return Err("Not enough data to fill buffer".into());
+        }
+        let mut into = into;
+        while !into.is_empty() {
+            self.advance();
+            let len = into.len().min(self.current.len());
+            let (read, rest) = self.current.split_at(len);
+            into[..len].copy_from_slice(read);
+            self.current = rest;
+            into = &mut into[len..];
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::ChainedInput;
+    use crate::Wrap;
+    use parity_scale_codec::Input;
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn values_straddling_fragments_decode_like_contiguous_ones() {
+        use parity_scale_codec::Decode;
+        use std::{string::String, vec::Vec};
+
+        type Value = (u64, String, Vec<u16>, bool);
+
+        let value: Value = (0x0102_0304_0506_0708, "straddling".into(), [1, 2, 300].into(), true);
+        let encoded = crate::encode(&value);
+        let contiguous = Wrap::<Value>::decode(&mut &*encoded).unwrap();
+        for cuts in [&[3, 9, 10][..], &[1, 2, 3, 4, 5, 6, 7, 8, 13], &[0, 0, 20, 20]] {
+            let mut fragments = Vec::new();
+            let mut start = 0;
+            for &end in cuts.iter().chain(&[encoded.len()]) {
+                fragments.push(&encoded[start..end]);
+                start = end;
+            }
+            let mut input = ChainedInput::new(&fragments);
+            assert_eq!(Wrap::<Value>::decode(&mut input).unwrap(), contiguous);
+            assert!(input.is_empty());
+        }
+    }
+
+    #[test]
+    fn reads_span_fragment_boundaries() {
+        let fragments: [&[u8]; 4] = [&[1, 2], &[], &[3], &[4, 5, 6]];
+        let mut input = ChainedInput::new(&fragments);
+        assert_eq!(input.remaining_len().unwrap(), Some(6));
+        let mut buf = [0; 4];
+        input.read(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3, 4]);
+        assert_eq!(input.remaining_len().unwrap(), Some(2));
+        assert!(input.read(&mut [0; 3]).is_err());
+        let mut buf = [0; 2];
+        input.read(&mut buf).unwrap();
+        assert_eq!(buf, [5, 6]);
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn halves_are_read_in_order() {
+        let mut input = ChainedInput::from_halves(&[1, 0], &[0, 0, 2]);
+        assert_eq!(input.remaining(), 5);
+        let Wrap(n) = Wrap::<u32>::decode_with_scratch(&mut input, &mut []).unwrap();
+        assert_eq!(n, 1);
+        assert_eq!(input.read_byte().unwrap(), 2);
+        assert!(input.read_byte().is_err());
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+use parity_scale_codec::Input;
+
+/// `Input` checking that the wrapped input fills the buffers passed to `read`
+///
+/// `Input::read` must fill the whole buffer or fail, but some implementations return `Ok` after
+/// filling part of it, leaving stale bytes that then decode into wrong values. When the wrapped
+/// input knows its remaining length, `CheckedInput` compares it before and after each read, and
+/// fails if it did not decrease by the length of the buffer. Violations by inputs whose remaining
+/// length is unknown cannot be detected.
+///
+/// `Wrap` performs the same check on the inputs it decodes from. `CheckedInput` extends it to
+/// other decoders, e.g. `Decode` implementations of `parity-scale-codec`.
+///
+/// ```rust
+/// use parity_scale_codec::Decode;
+/// use serde_scale_wrap::CheckedInput;
+///
+/// let mut input = CheckedInput::new(&b"\x07\x00\x00\x00"[..]);
+/// assert_eq!(u32::decode(&mut input)?, 7);
+/// assert!(input.into_inner().is_empty());
+/// # Ok::<_, parity_scale_codec::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct CheckedInput<I> {
+    inner: I,
+}
+
+impl<I: Input> CheckedInput<I> {
+    /// Returns an `Input` reading from `inner` and checking its reads
+    pub fn new(inner: I) -> Self {
+        CheckedInput { inner }
+    }
+
+    /// Returns a reference to the underlying input
+    pub fn get_ref(&self) -> &I {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying input
+    pub fn get_mut(&mut self) -> &mut I {
+        &mut self.inner
+    }
+
+    /// Returns the underlying input
+    pub fn into_inner(self) -> I {
+        self.inner
+    }
+}
+
+impl<I: Input> Input for CheckedInput<I> {
+    fn remaining_len(&mut self) -> Result<Option<usize>, parity_scale_codec::Error> {
+        self.inner.remaining_len()
+    }
+
+    fn read(&mut self, into: &mut [u8]) -> Result<(), parity_scale_codec::Error> {
+        read_checked(&mut self.inner, into)
+    }
+}
+
+/// Fills `into` from `input`, failing if the remaining length of `input` shows that it read a
+/// different number of bytes
+pub(crate) fn read_checked<I: Input + ?Sized>(
+    input: &mut I,
+    into: &mut [u8],
+) -> Result<(), parity_scale_codec::Error> {
+    let before = input.remaining_len()?;
+    if matches!(before, Some(remaining) if into.len() > remaining) {
+        return Err("Not enough data to fill buffer".into());
+    }
+    input.read(into)?;
+    match (before, input.remaining_len()?) {
+        (Some(before), Some(after)) if before - into.len() != after => {
+            Err("Input did not fill the buffer".into())
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(all(test, feature = "alloc", feature = "encode", feature = "decode"))]
+mod tests {
+    use super::CheckedInput;
+    use crate::{encode, Error, Wrap};
+    use alloc::{string::String, vec, vec::Vec};
+    use parity_scale_codec::{Decode, Input};
+
+    /// Input contradicting the contract of `Input::read` by reading at most 2 bytes at a time
+    struct ShortReads<'a>(&'a [u8]);
+
+    impl Input for ShortReads<'_> {
+        fn remaining_len(&mut self) -> Result<Option<usize>, parity_scale_codec::Error> {
+            Ok(Some(self.0.len()))
+        }
+
+        fn read(&mut self, into: &mut [u8]) -> Result<(), parity_scale_codec::Error> {
+            let len = into.len().min(2).min(self.0.len());
+            into[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn short_fills_fail_to_decode() {
+        let encoded = encode(&0x0102_0304u32);
+        let result = Wrap::<u32>::decode(&mut ShortReads(&encoded));
+        assert_eq!(result.unwrap_err(), "Input did not fill the buffer".into());
+        let encoded = encode(&(vec![7u8; 3], String::from("truncated")));
+        let result = Wrap::<(Vec<u8>, String)>::decode_boxed(&mut ShortReads(&encoded));
+        assert!(matches!(result, Err(Error::Input(_))));
+        let result = Wrap::<(u8, u16)>::decode_with_scratch(&mut ShortReads(&[1, 2, 0]), &mut []);
+        assert_eq!(result.unwrap().0, (1, 2));
+        let result = Wrap::<u32>::decode_with_scratch(&mut ShortReads(&[1, 2, 3, 4]), &mut []);
+        assert!(matches!(result, Err(Error::Input(_))));
+    }
+
+    #[test]
+    fn checked_inputs_reject_short_fills_for_any_decoder() {
+        let mut input = CheckedInput::new(ShortReads(&[4, 3, 2, 1]));
+        assert!(u32::decode(&mut input).is_err());
+        let mut input = CheckedInput::new(ShortReads(&[4, 3, 2, 1]));
+        assert_eq!(<(u16, u16)>::decode(&mut input).unwrap(), (0x0304, 0x0102));
+        assert!(input.get_mut().0.is_empty());
+    }
+
+    #[test]
+    fn well_behaved_inputs_are_unaffected() {
+        let encoded = encode(&(7u8, String::from("foo")));
+        let mut input = CheckedInput::new(&encoded[..]);
+        let Wrap(decoded) = Wrap::<(u8, String)>::decode(&mut input).unwrap();
+        assert_eq!(decoded, (7, "foo".into()));
+        let result = Wrap::<u32>::decode(&mut CheckedInput::new(&[1u8, 2][..]));
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,216 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Encodings split into chunks of bounded size, e.g. for stores limiting the size of values
+
+use crate::{Error, Wrap};
+use alloc::vec::Vec;
+
+#[cfg(feature = "decode")]
+use crate::{deserialize_from, ChainedInput};
+#[cfg(feature = "encode")]
+use crate::{try_serialize_to_try_output, TryOutput};
+#[cfg(feature = "encode")]
+use core::iter::FusedIterator;
+#[cfg(feature = "decode")]
+use serde::de::DeserializeOwned;
+#[cfg(feature = "encode")]
+use serde::Serialize;
+
+impl<T> Wrap<T> {
+    /// Returns an iterator over the SCALE encoding of the wrapped value split into chunks of
+    /// `chunk_size` bytes, the last one being shorter if needed
+    ///
+    /// The chunks concatenated are the encoding, and none of them is empty, so an empty encoding
+    /// has no chunks. Only one chunk is held in memory at a time: each chunk is produced by
+    /// serializing the value again, skipping the bytes of the previous chunks and stopping when
+    /// the chunk is full. Producing all `n` chunks of an encoding thus serializes the value `n`
+    /// times, and `Serialize` must write the same bytes every time.
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::Wrap;
+    ///
+    /// let chunks = Wrap(("foo", 7u8)).encode_chunks(3).collect::<Vec<_>>();
+    /// assert_eq!(chunks, [&b"\x0cfo"[..], b"o\x07"]);
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `chunk_size` is 0. The iterator panics if the serializer returns an error.
+    #[cfg(feature = "encode")]
+    pub fn encode_chunks(&self, chunk_size: usize) -> impl Iterator<Item = Vec<u8>> + '_
+    where
+        T: Serialize,
+    {
+        assert!(chunk_size > 0, "Chunks must not be empty");
+        Chunks { value: &self.0, chunk_size, offset: Some(0) }
+    }
+
+    /// Decodes a `Wrap` from the concatenation of `chunks`, failing if bytes are left over
+    ///
+    /// This reverses [`Wrap::encode_chunks`], and chunks are not copied into a contiguous buffer.
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::{Error, Wrap};
+    ///
+    /// let chunks = [&b"\x0cfo"[..], b"o\x07"];
+    /// assert_eq!(Wrap::<(String, u8)>::decode_from_chunks(chunks)?, ("foo".to_owned(), 7));
+    /// assert!(Wrap::<(String, u8)>::decode_from_chunks(&chunks[..1]).is_err());
+    /// # Ok::<_, Error>(())
+    /// ```
+    #[cfg(feature = "decode")]
+    pub fn decode_from_chunks<I>(chunks: I) -> Result<Self, Error>
+    where
+        T: DeserializeOwned,
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let chunks = chunks.into_iter().collect::<Vec<_>>();
+        let fragments = chunks.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+        let mut input = ChainedInput::new(&fragments);
+        let value = deserialize_from(&mut input)?;
+        if input.is_empty() {
+            Ok(Wrap(value))
+        } else {
+            Err(Error::TrailingBytes { len: input.remaining() })
+        }
+    }
+}
+
+/// Iterator returned by [`Wrap::encode_chunks`]
+#[cfg(feature = "encode")]
+struct Chunks<'a, T> {
+    value: &'a T,
+    chunk_size: usize,
+    /// Offset of the next chunk in the encoding, or `None` once the last chunk was returned
+    offset: Option<usize>,
+}
+
+#[cfg(feature = "encode")]
+impl<T: Serialize> Iterator for Chunks<'_, T> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        let offset = self.offset?;
+        let mut dst =
+            ChunkOutput { skip: offset, chunk: Vec::new(), len: self.chunk_size, full: false };
+        match try_serialize_to_try_output(self.value, &mut dst) {
+            Ok(()) => self.offset = None,
+            Err(_) if dst.full => self.offset = Some(offset + self.chunk_size),
+            Err(e) => panic!("{}", e),
+        }
+        Some(dst.chunk).filter(|chunk| !chunk.is_empty())
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<T: Serialize> FusedIterator for Chunks<'_, T> {}
+
+/// `TryOutput` skipping the first `skip` bytes, then keeping the next `len` bytes
+///
+/// Writing past these bytes fails to stop serialization, after keeping the bytes that fit and
+/// setting `full`.
+#[cfg(feature = "encode")]
+struct ChunkOutput {
+    skip: usize,
+    chunk: Vec<u8>,
+    len: usize,
+    full: bool,
+}
+
+#[cfg(feature = "encode")]
+impl TryOutput for ChunkOutput {
+    fn try_write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let skipped = self.skip.min(bytes.len());
+        self.skip -= skipped;
+        let bytes = &bytes[skipped..];
+        let kept = bytes.len().min(self.len - self.chunk.len());
+        self.chunk.extend_from_slice(&bytes[..kept]);
+        if kept < bytes.len() {
+            self.full = true;
+            return Err(Error::CapacityExceeded { capacity: self.len });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use crate::{encode, Error, Wrap};
+    use alloc::{collections::BTreeMap, string::String, vec, vec::Vec};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    struct Snapshot {
+        version: u32,
+        names: Vec<String>,
+        counts: BTreeMap<u16, u64>,
+        blob: Vec<u8>,
+    }
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            version: 3,
+            names: vec!["alpha".into(), "beta".into()],
+            counts: vec![(1, 10), (500, u64::MAX)].into_iter().collect(),
+            blob: (0..=255).collect(),
+        }
+    }
+
+    #[test]
+    fn chunks_concatenate_to_the_encoding() {
+        let encoded = encode(&snapshot());
+        for chunk_size in [1, 7, encoded.len(), encoded.len() + 1, usize::MAX] {
+            let chunks = Wrap(snapshot()).encode_chunks(chunk_size).collect::<Vec<_>>();
+            assert_eq!(chunks.concat(), encoded);
+            let (last, full) = chunks.split_last().unwrap();
+            assert!(full.iter().all(|chunk| chunk.len() == chunk_size));
+            assert!(!last.is_empty() && last.len() <= chunk_size);
+            assert_eq!(Wrap::<Snapshot>::decode_from_chunks(&chunks).unwrap(), snapshot());
+        }
+    }
+
+    #[test]
+    fn larger_chunks_hold_the_whole_encoding() {
+        let chunks = Wrap(snapshot()).encode_chunks(1 << 20).collect::<Vec<_>>();
+        assert_eq!(chunks, [encode(&snapshot())]);
+    }
+
+    #[test]
+    fn chunk_count_is_rounded_up() {
+        let len = encode(&snapshot()).len();
+        assert_eq!(Wrap(snapshot()).encode_chunks(7).count(), len.div_ceil(7));
+        assert_eq!(Wrap([0u8; 14]).encode_chunks(7).count(), 2);
+    }
+
+    #[test]
+    fn empty_encodings_have_no_chunks() {
+        assert_eq!(Wrap(()).encode_chunks(7).count(), 0);
+        Wrap::<()>::decode_from_chunks(Vec::<Vec<u8>>::new()).unwrap();
+    }
+
+    #[test]
+    fn missing_middle_chunk_is_detected() {
+        let mut chunks = Wrap(snapshot()).encode_chunks(7).collect::<Vec<_>>();
+        chunks.remove(chunks.len() / 2);
+        assert!(Wrap::<Snapshot>::decode_from_chunks(&chunks).is_err());
+    }
+
+    #[test]
+    fn extra_chunks_are_rejected() {
+        let mut chunks = Wrap(snapshot()).encode_chunks(7).collect::<Vec<_>>();
+        chunks.push(vec![1, 2]);
+        let result = Wrap::<Snapshot>::decode_from_chunks(&chunks);
+        assert!(matches!(result, Err(Error::TrailingBytes { len: 2 })));
+    }
+
+    #[test]
+    #[should_panic(expected = "Chunks must not be empty")]
+    fn empty_chunks_are_rejected() {
+        let _ = Wrap(1u8).encode_chunks(0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn unserializable_values_panic() {
+        let _ = Wrap(1u128).encode_chunks(4).next();
+    }
+}
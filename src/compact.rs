@@ -0,0 +1,166 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Compact lengths, as prefixed to sequences, strings and maps
+
+#[cfg(feature = "decode")]
+use crate::Error;
+#[cfg(feature = "decode")]
+use parity_scale_codec::Input;
+#[cfg(feature = "encode")]
+use parity_scale_codec::{Compact, Encode, Output};
+
+/// Writes the canonical `Compact<u64>` encoding of `len` to `dst`
+///
+/// This lets a payload be written right after its length without building it first. This matches
+/// how `Wrap` prefixes sequences, strings and maps only for lengths below 2^30: `serde-scale` 0.2
+/// writes an invalid first byte for longer lengths, which this function does not reproduce.
+///
+/// ```rust
+/// use parity_scale_codec::Encode;
+/// use serde_scale_wrap::{write_compact_len, Wrap};
+///
+/// let mut encoded = Vec::new();
+/// write_compact_len(&mut encoded, 3);
+/// encoded.extend_from_slice(b"foo");
+/// assert_eq!(encoded, Wrap("foo").encode());
+/// ```
+#[cfg(feature = "encode")]
+pub fn write_compact_len<O: Output + ?Sized>(dst: &mut O, len: u64) {
+    Compact(len).encode_to(dst);
+}
+
+/// Reads a compact-encoded length from `input`, as `Wrap` decodes the prefixes of sequences,
+/// strings and maps
+///
+/// Like `Wrap`, and unlike `Compact<u64>`, encodings longer than necessary are accepted. Lengths
+/// encoded with more than 8 bytes fail with `Error::Deserialization`.
+///
+/// ```rust
+/// use parity_scale_codec::Encode;
+/// use serde_scale_wrap::{read_compact_len, Wrap};
+///
+/// let encoded = Wrap(vec![7u8; 300]).encode();
+/// let mut input = &encoded[..];
+/// assert_eq!(read_compact_len(&mut input)?, 300);
+/// assert_eq!(input, &[7; 300][..]);
+/// # Ok::<_, serde_scale_wrap::Error>(())
+/// ```
+#[cfg(feature = "decode")]
+pub fn read_compact_len<I: Input + ?Sized>(input: &mut I) -> Result<u64, Error> {
+    let head = input.read_byte()?;
+    let low = u64::from(head >> 2);
+    match head & 0b11 {
+        0b00 => Ok(low),
+        0b01 => Ok(low | u64::from(input.read_byte()?) << 6),
+        0b10 => {
+            let mut high = [0; 4];
+            input.read(&mut high[..3])?;
+            Ok(low | u64::from(u32::from_le_bytes(high)) << 6)
+        }
+        _ => {
+            let len = usize::from(head >> 2) + 4;
+            if len > 8 {
+                return Err(Error::Deserialization(
+                    crate::scale::Error::CollectionTooLargeToDeserialize,
+                ));
+            }
+            let mut bytes = [0; 8];
+            input.read(&mut bytes[..len])?;
+            Ok(u64::from_le_bytes(bytes))
+        }
+    }
+}
+
+#[cfg(all(test, feature = "alloc", feature = "encode", feature = "decode"))]
+mod tests {
+    use super::{read_compact_len, write_compact_len};
+    use crate::{adapters::OutputToWrite, Error, Wrap};
+    use alloc::vec::Vec;
+    use parity_scale_codec::{Compact, Encode};
+    use serde::{ser::SerializeSeq, Serializer as _};
+
+    const LENGTHS: [u64; 17] = [
+        0,
+        1,
+        0x3f,
+        0x40,
+        0x3fff,
+        0x4000,
+        0x3fff_ffff,
+        0x4000_0000,
+        0xffff_ffff,
+        0x1_0000_0000,
+        0xff_ffff_ffff,
+        0x100_0000_0000,
+        0xffff_ffff_ffff,
+        0x1_0000_0000_0000,
+        0xff_ffff_ffff_ffff,
+        0x100_0000_0000_0000,
+        u64::MAX,
+    ];
+
+    fn serde_scale_prefix(len: u64) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        let mut serializer = crate::scale::Serializer::new(OutputToWrite::new(&mut encoded));
+        (&mut serializer).serialize_seq(Some(len as usize)).unwrap().end().unwrap();
+        encoded
+    }
+
+    #[test]
+    fn lengths_are_encoded_like_serde_scale_does() {
+        for &len in &LENGTHS {
+            let mut encoded = Vec::new();
+            write_compact_len(&mut encoded, len);
+            assert_eq!(encoded, Compact(len).encode());
+            // `serde-scale` 0.2 writes an invalid first byte for lengths of 2^30 or more.
+            if len < 0x4000_0000 {
+                assert_eq!(encoded, serde_scale_prefix(len));
+            }
+        }
+    }
+
+    #[test]
+    fn lengths_roundtrip_in_every_width_class() {
+        let widths = LENGTHS.iter().map(|&len| Compact(len).encode().len());
+        assert_eq!(widths.collect::<Vec<_>>(), [1, 1, 1, 2, 2, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9]);
+        for &len in &LENGTHS {
+            let mut encoded = Vec::new();
+            write_compact_len(&mut encoded, len);
+            encoded.push(0xaa);
+            let mut input = &encoded[..];
+            assert_eq!(read_compact_len(&mut input).unwrap(), len);
+            assert_eq!(input, [0xaa]);
+        }
+    }
+
+    #[test]
+    fn lengths_prefix_wrapped_byte_sequences() {
+        let bytes = (0..20_000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let encoded = Wrap(&bytes).encode();
+        let mut input = &encoded[..];
+        assert_eq!(read_compact_len(&mut input).unwrap(), bytes.len() as u64);
+        assert_eq!(input, &bytes[..]);
+        let mut written = Vec::new();
+        write_compact_len(&mut written, bytes.len() as u64);
+        written.extend_from_slice(&bytes);
+        assert_eq!(crate::decode_all::<Vec<u8>>(&written).unwrap(), bytes);
+    }
+
+    #[test]
+    fn non_canonical_lengths_are_accepted_like_wrap_does() {
+        let encoded = [0b0000_0001, 0, 0xaa];
+        assert_eq!(read_compact_len(&mut &encoded[..]).unwrap(), 0);
+        assert!(crate::decode::<Vec<u8>>(&encoded).unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_lengths_are_rejected() {
+        assert!(matches!(read_compact_len(&mut &[][..]), Err(Error::Input(_))));
+        assert!(matches!(read_compact_len(&mut &[0b10, 0][..]), Err(Error::Input(_))));
+        let result = read_compact_len(&mut &[0b0001_0111, 0, 0, 0, 0, 0, 0, 0, 0, 0][..]);
+        assert!(matches!(
+            result,
+            Err(Error::Deserialization(crate::scale::Error::CollectionTooLargeToDeserialize)),
+        ));
+    }
+}
@@ -0,0 +1,298 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Compressed encodings of large values
+//!
+//! [`CompressedWrap`] encodes its value like [`Wrap`](crate::Wrap) and compresses the result. The
+//! encoding is made of:
+//! - The [`Compression::ID`] byte of the compression algorithm
+//! - The compact-encoded length of the uncompressed encoding
+//! - The compact-encoded length of the compressed bytes
+//! - The compressed bytes
+//!
+//! The lengths let a `CompressedWrap` be followed by other data, e.g. as a field of an encoded
+//! struct. Decoding rejects uncompressed lengths greater than the `MAX_LEN` parameter before
+//! allocating anything, to protect against decompression bombs.
+//!
+//! [`Lz4`] is provided, and other algorithms (e.g. zstd) can be used by implementing
+//! [`Compression`]. `CompressedWrap` implements `Encode` with the `encode` feature and `Decode`
+//! with the `decode` feature.
+//!
+//! ```rust
+//! use parity_scale_codec::{Decode, Encode};
+//! use serde_scale_wrap::compressed::CompressedWrap;
+//!
+//! let snapshot = vec![7u32; 10_000];
+//! let encoded = CompressedWrap::<_>::new(snapshot.clone()).encode();
+//! assert!(encoded.len() < 1_000);
+//! let decoded = CompressedWrap::<Vec<u32>>::decode(&mut &encoded[..])?;
+//! assert_eq!(decoded.value, snapshot);
+//! # Ok::<_, parity_scale_codec::Error>(())
+//! ```
+
+use alloc::vec::Vec;
+use core::{
+    fmt::{self, Debug},
+    marker::PhantomData,
+};
+
+#[cfg(feature = "decode")]
+use crate::read_compact_len;
+#[cfg(feature = "encode")]
+use crate::write_compact_len;
+#[cfg(feature = "decode")]
+use parity_scale_codec::{Decode, Input};
+#[cfg(feature = "encode")]
+use parity_scale_codec::{Encode, EncodeLike, Output};
+#[cfg(feature = "decode")]
+use serde::de::DeserializeOwned;
+#[cfg(feature = "encode")]
+use serde::Serialize;
+
+/// Default maximum uncompressed length accepted when decoding a [`CompressedWrap`], in bytes
+pub const DEFAULT_MAX_UNCOMPRESSED_LEN: usize = 8 * 1024 * 1024;
+
+/// Compression algorithm used by [`CompressedWrap`]
+pub trait Compression {
+    /// Identifier of the algorithm, written before the compressed bytes
+    const ID: u8;
+
+    /// Returns the compressed `bytes`
+    fn compress(bytes: &[u8]) -> Vec<u8>;
+
+    /// Returns an upper bound of the compressed length of `uncompressed_len` bytes
+    ///
+    /// Longer compressed data is rejected before being read.
+    fn max_compressed_len(uncompressed_len: usize) -> usize;
+
+    /// Returns the decompressed `compressed` bytes, expected to be `uncompressed_len` bytes long
+    ///
+    /// Implementations must not allocate more than `uncompressed_len` bytes for the result, which
+    /// is checked against the maximum length before this is called.
+    fn decompress(
+        compressed: &[u8],
+        uncompressed_len: usize,
+    ) -> Result<Vec<u8>, parity_scale_codec::Error>;
+}
+
+/// LZ4 block compression, with identifier 1
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Lz4;
+
+impl Compression for Lz4 {
+    const ID: u8 = 1;
+
+    fn compress(bytes: &[u8]) -> Vec<u8> {
+        lz4_flex::block::compress(bytes)
+    }
+
+    fn max_compressed_len(uncompressed_len: usize) -> usize {
+        lz4_flex::block::get_maximum_output_size(uncompressed_len)
+    }
+
+    fn decompress(
+        compressed: &[u8],
+        uncompressed_len: usize,
+    ) -> Result<Vec<u8>, parity_scale_codec::Error> {
+        lz4_flex::block::decompress(compressed, uncompressed_len)
+            .map_err(|_| "Invalid LZ4 data".into())
+    }
+}
+
+/// Wrapper encoding its value like `Wrap` and compressing the result with `C`
+///
+/// Decoding fails if the uncompressed encoding is longer than `MAX_LEN` bytes. Encoding does not
+/// check this limit.
+pub struct CompressedWrap<T, C = Lz4, const MAX_LEN: usize = DEFAULT_MAX_UNCOMPRESSED_LEN> {
+    /// Wrapped value
+    pub value: T,
+    compression: PhantomData<fn() -> C>,
+}
+
+impl<T, C, const MAX_LEN: usize> CompressedWrap<T, C, MAX_LEN> {
+    /// Wraps `value`
+    pub fn new(value: T) -> Self {
+        CompressedWrap { value, compression: PhantomData }
+    }
+
+    /// Returns the wrapped value
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T, C, const MAX_LEN: usize> From<T> for CompressedWrap<T, C, MAX_LEN> {
+    fn from(value: T) -> Self {
+        CompressedWrap::new(value)
+    }
+}
+
+impl<T: Clone, C, const MAX_LEN: usize> Clone for CompressedWrap<T, C, MAX_LEN> {
+    fn clone(&self) -> Self {
+        CompressedWrap::new(self.value.clone())
+    }
+}
+
+impl<T: Debug, C, const MAX_LEN: usize> Debug for CompressedWrap<T, C, MAX_LEN> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CompressedWrap").field(&self.value).finish()
+    }
+}
+
+impl<T: PartialEq, C, const MAX_LEN: usize> PartialEq for CompressedWrap<T, C, MAX_LEN> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<T: Eq, C, const MAX_LEN: usize> Eq for CompressedWrap<T, C, MAX_LEN> {}
+
+#[cfg(feature = "encode")]
+impl<T, C, const MAX_LEN: usize> Encode for CompressedWrap<T, C, MAX_LEN>
+where
+    T: Serialize,
+    C: Compression,
+{
+    /// # Panics
+    /// Panics if the serializer returns an error, like `Wrap` does.
+    fn encode_to<O: Output + ?Sized>(&self, dst: &mut O) {
+        let encoded = crate::encode(&self.value);
+        let compressed = C::compress(&encoded);
+        dst.push_byte(C::ID);
+        write_compact_len(dst, encoded.len() as u64);
+        write_compact_len(dst, compressed.len() as u64);
+        dst.write(&compressed);
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<T: Serialize, C: Compression, const MAX_LEN: usize> EncodeLike
+    for CompressedWrap<T, C, MAX_LEN>
+{
+}
+
+#[cfg(feature = "decode")]
+impl<T, C, const MAX_LEN: usize> Decode for CompressedWrap<T, C, MAX_LEN>
+where
+    T: DeserializeOwned,
+    C: Compression,
+{
+    fn decode<I: Input>(input: &mut I) -> Result<Self, parity_scale_codec::Error> {
+        if input.read_byte()? != C::ID {
+            return Err("Unexpected compression algorithm".into());
+        }
+        let uncompressed_len = read_len(input, MAX_LEN, "Uncompressed length exceeds maximum")?;
+        let max_compressed_len = C::max_compressed_len(uncompressed_len);
+        let compressed_len =
+            read_len(input, max_compressed_len, "Compressed length exceeds maximum")?;
+        let mut compressed = alloc::vec![0; compressed_len];
+        input.read(&mut compressed)?;
+        let encoded = C::decompress(&compressed, uncompressed_len)?;
+        if encoded.len() != uncompressed_len {
+            return Err("Uncompressed length mismatch".into());
+        }
+        Ok(CompressedWrap::new(crate::decode_all(&encoded)?))
+    }
+}
+
+/// Reads a compact length, failing with `message` if it is greater than `max`
+#[cfg(feature = "decode")]
+fn read_len<I: Input>(
+    input: &mut I,
+    max: usize,
+    message: &'static str,
+) -> Result<usize, parity_scale_codec::Error> {
+    let len = read_compact_len(input)?;
+    if len <= max as u64 {
+        Ok(len as usize)
+    } else {
+        Err(message.into())
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::{Compression, CompressedWrap, Lz4};
+    use crate::write_compact_len;
+    use alloc::{string::String, vec, vec::Vec};
+    use parity_scale_codec::{Decode, Encode};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    struct Snapshot {
+        height: u64,
+        accounts: Vec<(String, u64)>,
+    }
+
+    fn snapshot() -> Snapshot {
+        let accounts = (0..5_000u64).map(|i| (String::from("account"), i % 4));
+        Snapshot { height: 42, accounts: accounts.collect() }
+    }
+
+    fn header(uncompressed_len: u64, compressed_len: u64) -> Vec<u8> {
+        let mut encoded = vec![Lz4::ID];
+        write_compact_len(&mut encoded, uncompressed_len);
+        write_compact_len(&mut encoded, compressed_len);
+        encoded
+    }
+
+    #[test]
+    fn large_repetitive_payload_roundtrips() {
+        let value = snapshot();
+        let uncompressed = crate::encode(&value);
+        let encoded = CompressedWrap::<_>::new(value.clone()).encode();
+        assert!(encoded.len() * 20 < uncompressed.len());
+        let mut input = &encoded[..];
+        assert_eq!(CompressedWrap::<Snapshot>::decode(&mut input).unwrap().value, value);
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn header_precedes_compressed_bytes() {
+        let encoded = CompressedWrap::<_>::new(vec![0u8; 100]).encode();
+        let compressed = Lz4::compress(&crate::encode(&vec![0u8; 100]));
+        let mut expected = header(102, compressed.len() as u64);
+        expected.extend_from_slice(&compressed);
+        assert_eq!(encoded, expected);
+    }
+
+    #[test]
+    fn compressed_values_can_be_followed_by_other_data() {
+        let encoded = (CompressedWrap::<_>::new(String::from("foo")), 7u8).encode();
+        let (decoded, n) = <(CompressedWrap<String>, u8)>::decode(&mut &encoded[..]).unwrap();
+        assert_eq!((decoded.value.as_str(), n), ("foo", 7));
+    }
+
+    #[test]
+    fn forged_huge_uncompressed_length_is_rejected() {
+        let mut encoded = header(u64::MAX, 4);
+        encoded.extend_from_slice(&[0; 4]);
+        assert!(CompressedWrap::<Vec<u8>>::decode(&mut &encoded[..]).is_err());
+        let encoded = CompressedWrap::<_>::new(vec![0u8; 100]).encode();
+        assert!(CompressedWrap::<Vec<u8>, Lz4, 101>::decode(&mut &encoded[..]).is_err());
+        assert!(CompressedWrap::<Vec<u8>, Lz4, 102>::decode(&mut &encoded[..]).is_ok());
+    }
+
+    #[test]
+    fn forged_huge_compressed_length_is_rejected() {
+        let encoded = header(4, u64::from(u32::MAX));
+        assert!(CompressedWrap::<u32>::decode(&mut &encoded[..]).is_err());
+    }
+
+    #[test]
+    fn truncated_compressed_data_is_rejected() {
+        let encoded = CompressedWrap::<_>::new(snapshot()).encode();
+        let result = CompressedWrap::<Snapshot>::decode(&mut &encoded[..encoded.len() - 1]);
+        assert!(result.is_err());
+        let compressed = Lz4::compress(&crate::encode(&snapshot()));
+        let mut forged = header(crate::encode(&snapshot()).len() as u64, 10);
+        forged.extend_from_slice(&compressed[..10]);
+        assert!(CompressedWrap::<Snapshot>::decode(&mut &forged[..]).is_err());
+    }
+
+    #[test]
+    fn other_algorithms_are_rejected() {
+        let mut encoded = CompressedWrap::<_>::new(1u8).encode();
+        encoded[0] = 2;
+        assert!(CompressedWrap::<u8>::decode(&mut &encoded[..]).is_err());
+    }
+}
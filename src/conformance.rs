@@ -0,0 +1,482 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Assertions checking that [`Wrap`] conforms to `parity-scale-codec`
+//!
+//! These helpers are meant to be used in tests. They panic with hex dumps of the encodings
+//! involved when a check fails, highlighting the first byte that differs.
+//!
+//! With the `proptest` feature, [`check_conformance!`](crate::check_conformance) runs these
+//! assertions on values generated by `proptest`.
+//!
+//! This module is available in this crate's tests and, for downstream crates, with the `testing`
+//! feature.
+//!
+//! # Supported `serde` attributes
+//! SCALE is positional: fields are neither named nor counted, so that a field serialized without
+//! being deserialized (or the other way around) shifts every following field. [`find_asymmetry`]
+//! and [`check_symmetry`] report the first such field by name.
+//!
+//! - `rename`, `rename_all`, `alias`, `default`, `bound` and `transparent` do not change
+//!   positions and are supported.
+//! - `with`, `serialize_with` and `deserialize_with` are supported as long as both sides agree.
+//! - `skip` skips the field on both sides. It is supported, but the field comes back with its
+//!   default value.
+//! - `skip_serializing`, `skip_serializing_if` and `skip_deserializing` make the two sides
+//!   disagree and corrupt the fields that follow.
+//! - `flatten`, as well as internally tagged, adjacently tagged and untagged enums, need a
+//!   self-describing format and are not supported.
+
+mod symmetry;
+pub mod vectors;
+
+#[cfg(any(test, feature = "proptest"))]
+pub use symmetry::{check_symmetry, check_symmetry_with};
+pub use symmetry::{assert_symmetric, find_asymmetry, Asymmetry};
+
+use crate::Wrap;
+use alloc::string::String;
+use core::fmt::{self, Debug, Display, Write as _};
+use parity_scale_codec::{Decode, DecodeAll, Encode};
+#[cfg(any(test, feature = "proptest"))]
+use proptest::{
+    arbitrary::{any, Arbitrary},
+    strategy::Strategy,
+    test_runner::{Config, TestRunner},
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Asserts that `value` survives encoding with `Wrap` followed by decoding with `Wrap`
+///
+/// Decoding must consume all the bytes produced by encoding.
+///
+/// # Panics
+/// Panics if decoding fails, does not consume all bytes or yields a value different from `value`.
+pub fn assert_roundtrip<T>(value: &T)
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    let encoded = crate::to_vec(value);
+    let mut input = &*encoded;
+    let decoded = match Wrap::<T>::decode(&mut input) {
+        Ok(Wrap(x)) => x,
+        Err(e) => panic!(
+            "Failed to decode {:?}: {}\nencoding:\n{}",
+            value,
+            e,
+            HexDump::new(&encoded, None),
+        ),
+    };
+    if !input.is_empty() {
+        panic!(
+            "Decoding {:?} left {} byte(s) unconsumed\nencoding:\n{}",
+            value,
+            input.len(),
+            HexDump::new(&encoded, Some(encoded.len() - input.len())),
+        );
+    }
+    if decoded != *value {
+        panic!(
+            "Roundtrip changed the value\noriginal: {:?}\ndecoded: {:?}\nencoding:\n{}",
+            value,
+            decoded,
+            HexDump::new(&encoded, None),
+        );
+    }
+}
+
+/// Asserts that `Wrap` encodes `value` exactly like its `Encode` implementation does
+///
+/// # Panics
+/// Panics if the encodings differ.
+pub fn assert_matches_codec<T>(value: &T)
+where
+    T: Serialize + Encode + Debug + ?Sized,
+{
+    let wrapped = crate::to_vec(value);
+    let expected = value.encode();
+    if let Some(difference) = explain_difference(&wrapped, &expected) {
+        panic!(
+            "Encodings of {:?} differ at offset {:#x}\n{}Wrap:\n{}Encode:\n{}",
+            value,
+            difference.offset(),
+            difference,
+            HexDump::new(&wrapped, Some(difference.offset())),
+            HexDump::new(&expected, Some(difference.offset())),
+        );
+    }
+}
+
+/// Asserts that `value` matches its `Encode` implementation and roundtrips through `Wrap`
+///
+/// This combines [`assert_matches_codec`] and [`assert_roundtrip`], and also checks that `Wrap`
+/// decodes the output of `Encode` back to `value`.
+///
+/// # Panics
+/// Panics if any of the checks fails.
+pub fn assert_conforms<T>(value: &T)
+where
+    T: Serialize + DeserializeOwned + Encode + PartialEq + Debug,
+{
+    assert_matches_codec(value);
+    assert_roundtrip(value);
+    let encoded = value.encode();
+    match Wrap::<T>::decode(&mut &*encoded) {
+        Ok(Wrap(ref x)) if x == value => {}
+        Ok(Wrap(x)) => panic!(
+            "Decoding the output of `Encode` with `Wrap` changed the value\noriginal: {:?}\n\
+                decoded: {:?}\nencoding:\n{}",
+            value,
+            x,
+            HexDump::new(&encoded, None),
+        ),
+        Err(e) => panic!(
+            "Failed to decode the output of `Encode` for {:?} with `Wrap`: {}\nencoding:\n{}",
+            value,
+            e,
+            HexDump::new(&encoded, None),
+        ),
+    }
+}
+
+/// Asserts that no strict prefix of `bytes` decodes as a `T`
+///
+/// `bytes` is typically a valid encoding of a `T`. A prefix decoding successfully means that a
+/// truncated payload can be mistaken for a different value.
+///
+/// # Panics
+/// Panics, reporting the length of the prefix, if a strict prefix decodes successfully with
+/// `decode_all`.
+pub fn assert_rejects_truncations<T>(bytes: &[u8])
+where
+    T: DeserializeOwned + Debug,
+{
+    for len in 0..bytes.len() {
+        if let Ok(Wrap(x)) = Wrap::<T>::decode_all(&bytes[..len]) {
+            panic!(
+                "Prefix of {} byte(s) out of {} decoded successfully as {:?}\nencoding:\n{}",
+                len,
+                bytes.len(),
+                x,
+                HexDump::new(bytes, Some(len)),
+            );
+        }
+    }
+}
+
+/// Asserts that `bytes` followed by garbage does not decode as a `T` with `decode_all`
+///
+/// Between 1 and 8 garbage bytes are appended, each combination being checked.
+///
+/// # Panics
+/// Panics, reporting the number of bytes appended, if an extended encoding decodes successfully.
+pub fn assert_rejects_extensions<T>(bytes: &[u8])
+where
+    T: DeserializeOwned + Debug,
+{
+    const GARBAGE: [u8; 8] = [0x00, 0xff, 0x01, 0x80, 0x7f, 0x04, 0xfe, 0x03];
+    let mut extended = bytes.to_vec();
+    for (i, &garbage) in GARBAGE.iter().enumerate() {
+        extended.push(garbage);
+        if let Ok(Wrap(x)) = Wrap::<T>::decode_all(&extended) {
+            panic!(
+                "Encoding extended with {} byte(s) decoded successfully as {:?}\nencoding:\n{}",
+                i + 1,
+                x,
+                HexDump::new(&extended, Some(bytes.len())),
+            );
+        }
+    }
+}
+
+/// Number of values [`check_conformance`] checks
+#[cfg(any(test, feature = "proptest"))]
+pub const DEFAULT_CASES: u32 = 256;
+
+/// Checks values of type `T` generated by `proptest` with [`assert_conforms`]
+///
+/// Values are generated with the `Arbitrary` strategy of `T`.
+///
+/// # Panics
+/// Panics with the smallest failing value found if any value does not conform.
+#[cfg(any(test, feature = "proptest"))]
+pub fn check_conformance<T>()
+where
+    T: Arbitrary + Serialize + DeserializeOwned + Encode + PartialEq + Debug,
+{
+    check_conformance_with(any::<T>());
+}
+
+/// Checks values generated by `strategy` with [`assert_conforms`]
+///
+/// # Panics
+/// Panics with the smallest failing value found if any value does not conform.
+#[cfg(any(test, feature = "proptest"))]
+pub fn check_conformance_with<T, S>(strategy: S)
+where
+    T: Serialize + DeserializeOwned + Encode + PartialEq + Debug,
+    S: Strategy<Value = T>,
+{
+    let config = Config {
+        cases: DEFAULT_CASES,
+        failure_persistence: None,
+        ..Config::default()
+    };
+    let result = TestRunner::new(config).run(&strategy, |value| {
+        assert_conforms(&value);
+        Ok(())
+    });
+    if let Err(e) = result {
+        panic!("{}", e);
+    }
+}
+
+/// Checks values of a type generated by `proptest` with
+/// [`assert_conforms`](crate::conformance::assert_conforms)
+///
+/// Values are generated with the `Arbitrary` strategy of the type, or with the strategy given as
+/// second argument.
+///
+/// ```rust
+/// use proptest::{collection::vec, prelude::any};
+/// use serde_scale_wrap::check_conformance;
+///
+/// check_conformance!(Vec<(u8, Option<u32>)>);
+/// check_conformance!(Vec<u16>, vec(any::<u16>(), 60..70));
+/// ```
+#[cfg(any(test, feature = "proptest"))]
+#[macro_export]
+macro_rules! check_conformance {
+    ($t:ty) => {
+        $crate::conformance::check_conformance::<$t>()
+    };
+    ($t:ty, $strategy:expr) => {
+        $crate::conformance::check_conformance_with::<$t, _>($strategy)
+    };
+}
+
+fn first_difference(a: &[u8], b: &[u8]) -> Option<usize> {
+    a.iter()
+        .zip(b)
+        .position(|(x, y)| x != y)
+        .or_else(|| if a.len() == b.len() { None } else { Some(a.len().min(b.len())) })
+}
+
+/// Number of bytes shown on each side of the first difference
+const CONTEXT: usize = 8;
+
+/// Locates the first byte where an encoding produced by `Wrap` and one produced by `Encode`
+/// diverge
+///
+/// Returns `None` if the encodings are identical. A difference in length counts as a divergence
+/// at the end of the shorter encoding.
+///
+/// ```rust
+/// use parity_scale_codec::Encode;
+/// use serde_scale_wrap::{conformance::explain_difference, Wrap};
+///
+/// let value = Some(false);
+/// let wrapped = Wrap(&value).encode();
+/// let encoded = value.encode();
+/// let difference = explain_difference(&wrapped, &encoded).unwrap();
+/// assert_eq!(difference.offset(), 0);
+/// println!("{}", difference);
+/// ```
+pub fn explain_difference<'a>(wrap_bytes: &'a [u8], codec_bytes: &'a [u8]) -> Option<Difference<'a>> {
+    first_difference(wrap_bytes, codec_bytes).map(|offset| Difference {
+        offset,
+        wrap: wrap_bytes,
+        codec: codec_bytes,
+    })
+}
+
+/// First divergence between an encoding produced by `Wrap` and one produced by `Encode`
+///
+/// The `Display` implementation renders the bytes surrounding the divergence from both sides,
+/// aligned and with the first differing byte highlighted.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Difference<'a> {
+    offset: usize,
+    wrap: &'a [u8],
+    codec: &'a [u8],
+}
+
+impl<'a> Difference<'a> {
+    /// Returns the offset of the first byte that differs
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the length of the encoding produced by `Wrap`
+    pub fn wrap_len(&self) -> usize {
+        self.wrap.len()
+    }
+
+    /// Returns the length of the encoding produced by `Encode`
+    pub fn codec_len(&self) -> usize {
+        self.codec.len()
+    }
+
+    /// Returns the offset of the first byte returned by the `*_context` methods
+    pub fn context_start(&self) -> usize {
+        self.offset.saturating_sub(CONTEXT)
+    }
+
+    /// Returns the bytes produced by `Wrap` around the difference
+    pub fn wrap_context(&self) -> &'a [u8] {
+        self.context(self.wrap)
+    }
+
+    /// Returns the bytes produced by `Encode` around the difference
+    pub fn codec_context(&self) -> &'a [u8] {
+        self.context(self.codec)
+    }
+
+    fn context(&self, bytes: &'a [u8]) -> &'a [u8] {
+        let start = self.context_start().min(bytes.len());
+        let end = (self.offset + CONTEXT + 1).min(bytes.len());
+        &bytes[start..end]
+    }
+
+    fn fmt_side(&self, f: &mut fmt::Formatter<'_>, label: &str, bytes: &[u8]) -> fmt::Result {
+        let start = self.context_start();
+        write!(f, "{:<6} @{:#06x}:", label, start)?;
+        let end = (self.offset + CONTEXT + 1).min(self.wrap.len().max(self.codec.len()));
+        for offset in start..end {
+            let byte = bytes.get(offset);
+            match (offset == self.offset, byte) {
+                (true, Some(b)) => write!(f, " [{:02x}]", b)?,
+                (true, None) => f.write_str(" [..]")?,
+                (false, Some(b)) => write!(f, " {:02x}", b)?,
+                (false, None) => f.write_str(" ..")?,
+            }
+        }
+        writeln!(f)
+    }
+}
+
+impl Display for Difference<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "First difference at offset {:#x} (Wrap: {} byte(s), Encode: {} byte(s))",
+            self.offset,
+            self.wrap.len(),
+            self.codec.len(),
+        )?;
+        self.fmt_side(f, "Wrap", self.wrap)?;
+        self.fmt_side(f, "Encode", self.codec)
+    }
+}
+
+/// Hex dump of bytes, 16 per line, with an optional highlighted offset
+struct HexDump<'a> {
+    bytes: &'a [u8],
+    highlight: Option<usize>,
+}
+
+impl<'a> HexDump<'a> {
+    fn new(bytes: &'a [u8], highlight: Option<usize>) -> Self {
+        HexDump { bytes, highlight }
+    }
+}
+
+impl Display for HexDump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.bytes.is_empty() {
+            return writeln!(f, "    <empty>");
+        }
+        for (i, line) in self.bytes.chunks(16).enumerate() {
+            let mut s = String::new();
+            for (j, byte) in line.iter().enumerate() {
+                let offset = i * 16 + j;
+                if j > 0 {
+                    s.push(' ');
+                }
+                if Some(offset) == self.highlight {
+                    write!(s, "[{:02x}]", byte)?;
+                } else {
+                    write!(s, "{:02x}", byte)?;
+                }
+            }
+            if self.highlight == Some(self.bytes.len()) && (i + 1) * 16 >= self.bytes.len() {
+                s.push_str(" [..]");
+            }
+            writeln!(f, "    {:04x}: {}", i * 16, s)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assert_matches_codec, explain_difference, HexDump};
+    use alloc::{string::ToString, vec};
+    use crate::Wrap;
+    use parity_scale_codec::Encode;
+
+    #[test]
+    fn hex_dump_highlights_offset() {
+        let dump = HexDump::new(&[1, 2, 3], Some(1)).to_string();
+        assert_eq!(dump, "    0000: 01 [02] 03\n");
+    }
+
+    #[test]
+    fn hex_dump_highlights_end() {
+        let dump = HexDump::new(&[1, 2], Some(2)).to_string();
+        assert_eq!(dump, "    0000: 01 02 [..]\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "differ at offset 0x0")]
+    fn option_bool_does_not_match_codec() {
+        assert_matches_codec(&Some(false));
+    }
+
+    #[test]
+    #[should_panic(expected = "minimal failing input: Some(\n    false,\n)")]
+    fn check_conformance_reports_minimal_failure() {
+        crate::check_conformance!(Option<bool>);
+    }
+
+    #[test]
+    fn identical_encodings_have_no_difference() {
+        assert_eq!(explain_difference(&[1, 2], &[1, 2]), None);
+    }
+
+    #[test]
+    fn option_bool_difference_is_explained() {
+        let value = (7u8, Some(true));
+        let wrapped = Wrap(&value).encode();
+        let encoded = value.encode();
+        let difference = explain_difference(&wrapped, &encoded).unwrap();
+        assert_eq!(difference.offset(), 2);
+        assert_eq!(difference.wrap_len(), 2);
+        assert_eq!(difference.codec_len(), 3);
+        assert_eq!(difference.wrap_context(), [7, 1]);
+        assert_eq!(difference.codec_context(), [7, 1, 1]);
+        assert_eq!(
+            difference.to_string(),
+            "First difference at offset 0x2 (Wrap: 2 byte(s), Encode: 3 byte(s))\n\
+                Wrap   @0x0000: 07 01 [..]\n\
+                Encode @0x0000: 07 01 [01]\n",
+        );
+    }
+
+    #[test]
+    fn length_difference_is_explained() {
+        let wrapped = vec![0u8; 20];
+        let mut encoded = wrapped.clone();
+        encoded.push(9);
+        let difference = explain_difference(&wrapped, &encoded).unwrap();
+        assert_eq!(difference.offset(), 20);
+        assert_eq!(difference.context_start(), 12);
+        assert_eq!(difference.wrap_context(), [0; 8]);
+        assert_eq!(difference.codec_context(), [0, 0, 0, 0, 0, 0, 0, 0, 9]);
+        assert_eq!(
+            difference.to_string(),
+            "First difference at offset 0x14 (Wrap: 20 byte(s), Encode: 21 byte(s))\n\
+                Wrap   @0x000c: 00 00 00 00 00 00 00 00 [..]\n\
+                Encode @0x000c: 00 00 00 00 00 00 00 00 [09]\n",
+        );
+    }
+}
@@ -0,0 +1,461 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Detection of `serde` attributes making serialization and deserialization asymmetric
+
+use super::HexDump;
+#[cfg(any(test, feature = "proptest"))]
+use super::DEFAULT_CASES;
+use crate::{de, dump, Error, SliceRead};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{
+    cell::RefCell,
+    fmt::{self, Debug, Display},
+};
+#[cfg(any(test, feature = "proptest"))]
+use proptest::{
+    arbitrary::{any, Arbitrary},
+    strategy::Strategy,
+    test_runner::{Config, TestRunner},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Returns the first divergence between how `value` serializes and how its type deserializes,
+/// or `None` if `value` roundtrips through `Wrap`
+///
+/// The fields each struct and struct variant serializes, as recorded by
+/// [`annotate`](crate::dump::annotate), are compared with the fields their `Deserialize`
+/// implementations expect, in the order they are met, so that the first field the two sides
+/// disagree on is reported by its path (e.g. `items[1].nickname`). If every struct matches,
+/// decoding must succeed, consume the whole encoding and yield `value`.
+///
+/// ```rust
+/// use serde::{Deserialize, Serialize};
+/// use serde_scale_wrap::conformance::find_asymmetry;
+///
+/// #[derive(Deserialize, PartialEq, Serialize)]
+/// struct Profile {
+///     id: u32,
+///     #[serde(skip_serializing_if = "Option::is_none")]
+///     nickname: Option<String>,
+///     score: u16,
+/// }
+///
+/// let named = Profile { id: 1, nickname: Some("bob".into()), score: 2 };
+/// assert!(find_asymmetry(&named).is_none());
+/// let anonymous = Profile { id: 1, nickname: None, score: 2 };
+/// assert_eq!(find_asymmetry(&anonymous).unwrap().path(), "nickname");
+/// ```
+///
+/// # Panics
+/// Panics if `value` cannot be serialized (e.g. because it holds a floating point number).
+pub fn find_asymmetry<T>(value: &T) -> Option<Asymmetry>
+where
+    T: Serialize + DeserializeOwned + PartialEq,
+{
+    let annotated = dump::annotate(value);
+    let structs = RefCell::new(Vec::new());
+    let mut input = annotated.bytes();
+    let decoded = decode_recording_structs::<T>(&mut input, &structs);
+    let serialized = serialized_structs(&annotated);
+    let expected = structs.into_inner();
+    let mismatch = serialized
+        .iter()
+        .zip(&expected)
+        .find_map(|((path, fields), expected)| compare_fields(path, fields, expected));
+    if mismatch.is_some() {
+        return mismatch;
+    }
+    let (path, cause) = match decoded {
+        Err(e) => (String::new(), Cause::DecodingFailed(e.to_string())),
+        Ok(_) if !input.is_empty() => (String::new(), Cause::TrailingBytes(input.len())),
+        Ok(decoded) if decoded != *value => {
+            let path = first_changed_path(&annotated, &dump::annotate(&decoded));
+            (path.unwrap_or_default(), Cause::ValueChanged)
+        }
+        Ok(_) => return None,
+    };
+    Some(Asymmetry { path, cause })
+}
+
+/// Asserts that `value` serializes and deserializes symmetrically, as [`find_asymmetry`] checks
+///
+/// # Panics
+/// Panics with the path of the first field the serialization and deserialization of `value`
+/// disagree on, if any.
+pub fn assert_symmetric<T>(value: &T)
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    if let Some(asymmetry) = find_asymmetry(value) {
+        let encoded = crate::to_vec(value);
+        panic!("{}\nvalue: {:?}\nencoding:\n{}", asymmetry, value, HexDump::new(&encoded, None));
+    }
+}
+
+/// Checks values of type `T` generated by `proptest` with [`assert_symmetric`]
+///
+/// Values are generated with the `Arbitrary` strategy of `T`, so that optional fields are both
+/// present and absent, e.g. to catch fields only serialized under some condition.
+///
+/// # Panics
+/// Panics with the smallest asymmetric value found, if any, and the path of its offending field.
+#[cfg(any(test, feature = "proptest"))]
+pub fn check_symmetry<T>()
+where
+    T: Arbitrary + Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    check_symmetry_with(any::<T>());
+}
+
+/// Checks values generated by `strategy` with [`assert_symmetric`]
+///
+/// # Panics
+/// Panics with the smallest asymmetric value found, if any, and the path of its offending field.
+#[cfg(any(test, feature = "proptest"))]
+pub fn check_symmetry_with<T, S>(strategy: S)
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+    S: Strategy<Value = T>,
+{
+    let config = Config {
+        cases: DEFAULT_CASES,
+        failure_persistence: None,
+        ..Config::default()
+    };
+    let result = TestRunner::new(config).run(&strategy, |value| {
+        assert_symmetric(&value);
+        Ok(())
+    });
+    if let Err(e) = result {
+        panic!("{}", e);
+    }
+}
+
+/// Divergence between the serialization and the deserialization of a value
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Asymmetry {
+    path: String,
+    cause: Cause,
+}
+
+impl Asymmetry {
+    /// Returns the path of the offending field, e.g. `inner.items[0].name`
+    ///
+    /// The path is empty if no field could be singled out, e.g. when decoding fails although
+    /// every struct has the same fields on both sides.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl Display for Asymmetry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = &self.path;
+        match &self.cause {
+            Cause::NotSerialized => write!(
+                f,
+                "Field `{}` is expected by `Deserialize` but was not serialized (e.g. because of \
+                    `skip_serializing` or `skip_serializing_if`)",
+                path,
+            ),
+            Cause::NotDeserialized => write!(
+                f,
+                "Field `{}` was serialized but is not expected by `Deserialize` (e.g. because of \
+                    `skip_deserializing`)",
+                path,
+            ),
+            Cause::Reordered => write!(
+                f,
+                "Field `{}` is expected by `Deserialize` where another field was serialized",
+                path,
+            ),
+            Cause::DecodingFailed(e) => write!(f, "Decoding failed: {}", e),
+            Cause::TrailingBytes(len) => write!(f, "Decoding left {} byte(s) unconsumed", len),
+            Cause::ValueChanged if path.is_empty() => f.write_str(
+                "Roundtrip changed the value but not its encoding (e.g. because a field skipped \
+                    with `skip` did not hold its default value)",
+            ),
+            Cause::ValueChanged => write!(f, "Roundtrip changed the value, starting at `{}`", path),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Asymmetry {}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Cause {
+    /// The field is expected where it was not serialized
+    NotSerialized,
+    /// The field was serialized where it is not expected
+    NotDeserialized,
+    /// The field is expected where another one was serialized
+    Reordered,
+    DecodingFailed(String),
+    TrailingBytes(usize),
+    ValueChanged,
+}
+
+/// Decodes a `T` from `input`, appending the fields of the structs deserialized to `structs`
+fn decode_recording_structs<'de, T: Deserialize<'de>>(
+    input: &mut &'de [u8],
+    structs: &RefCell<Vec<&'static [&'static str]>>,
+) -> Result<T, Error> {
+    let mut deserializer = crate::scale::Deserializer::new(SliceRead(input));
+    T::deserialize(de::Deserializer::recording_structs(&mut deserializer, structs))
+        .map_err(Error::from_deserialization)
+}
+
+/// Returns the path of each entry, in the order of the entries
+fn entry_paths(annotated: &dump::Annotated) -> Vec<String> {
+    let mut parents = Vec::<String>::new();
+    annotated
+        .entries()
+        .iter()
+        .map(|entry| {
+            parents.truncate(entry.depth());
+            let path = match parents.last() {
+                None => entry.label().into(),
+                Some(parent) if parent.is_empty() || entry.label().starts_with('[') => {
+                    format!("{}{}", parent, entry.label())
+                }
+                Some(parent) => format!("{}.{}", parent, entry.label()),
+            };
+            parents.push(path.clone());
+            path
+        })
+        .collect()
+}
+
+/// Returns the path and the serialized fields of each struct and struct variant, in order
+fn serialized_structs(annotated: &dump::Annotated) -> Vec<(String, Vec<&str>)> {
+    let entries = annotated.entries();
+    entries
+        .iter()
+        .zip(entry_paths(annotated))
+        .enumerate()
+        .filter(|(_, (entry, _))| entry.is_struct())
+        .map(|(i, (entry, path))| {
+            let fields = entries[i + 1..]
+                .iter()
+                .take_while(|child| child.depth() > entry.depth())
+                .filter(|child| child.depth() == entry.depth() + 1 && child.label() != "<variant>")
+                .map(dump::Entry::label)
+                .collect();
+            (path, fields)
+        })
+        .collect()
+}
+
+/// Compares the fields a struct at `path` serialized with the ones its `Deserialize`
+/// implementation expects
+fn compare_fields(path: &str, serialized: &[&str], expected: &[&str]) -> Option<Asymmetry> {
+    if serialized == expected {
+        return None;
+    }
+    let index = serialized
+        .iter()
+        .zip(expected)
+        .position(|(s, e)| s != e)
+        .unwrap_or_else(|| serialized.len().min(expected.len()));
+    let (field, cause) = match (serialized.get(index), expected.get(index)) {
+        (_, Some(e)) if !serialized.contains(e) => (*e, Cause::NotSerialized),
+        (Some(s), _) if !expected.contains(s) => (*s, Cause::NotDeserialized),
+        (_, Some(e)) => (*e, Cause::Reordered),
+        (Some(s), None) => (*s, Cause::NotDeserialized),
+        (None, None) => unreachable!("Field lists differ at an index past both of them"),
+    };
+    let path = if path.is_empty() { field.into() } else { format!("{}.{}", path, field) };
+    Some(Asymmetry { path, cause })
+}
+
+/// Returns the path of the first leaf whose bytes or label changed between two annotations
+fn first_changed_path(original: &dump::Annotated, decoded: &dump::Annotated) -> Option<String> {
+    let leaves = |annotated: &dump::Annotated| {
+        let entries = annotated.entries().iter().zip(entry_paths(annotated));
+        let bytes = annotated.bytes();
+        entries
+            .filter(|(entry, _)| entry.is_leaf())
+            .map(|(entry, path)| (path, bytes[entry.range()].to_vec()))
+            .collect::<Vec<_>>()
+    };
+    let (original, decoded) = (leaves(original), leaves(decoded));
+    if original == decoded {
+        return None;
+    }
+    let index = original
+        .iter()
+        .zip(&decoded)
+        .position(|(a, b)| a != b)
+        .unwrap_or_else(|| original.len().min(decoded.len()));
+    original.get(index).or_else(|| decoded.get(index)).map(|(path, _)| path.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_symmetry, find_asymmetry};
+    use alloc::{
+        boxed::Box,
+        string::{String, ToString},
+        vec,
+        vec::Vec,
+    };
+    use proptest::{
+        arbitrary::{any, Arbitrary},
+        strategy::{BoxedStrategy, Strategy},
+    };
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    struct Profile {
+        id: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        nickname: Option<String>,
+        score: u16,
+    }
+
+    impl Arbitrary for Profile {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(_: ()) -> Self::Strategy {
+            any::<(u32, Option<String>, u16)>()
+                .prop_map(|(id, nickname, score)| Profile { id, nickname, score })
+                .boxed()
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Account {
+        #[serde(rename = "identifier")]
+        id: u32,
+        display_name: String,
+        #[serde(default)]
+        tags: Vec<String>,
+        kind: Kind,
+    }
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    enum Kind {
+        User { admin: bool },
+        Service(u8),
+    }
+
+    impl Arbitrary for Account {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(_: ()) -> Self::Strategy {
+            let kind = (any::<bool>(), any::<Option<u8>>()).prop_map(|(admin, service)| {
+                service.map_or(Kind::User { admin }, Kind::Service)
+            });
+            (any::<u32>(), any::<String>(), any::<Vec<String>>(), kind)
+                .prop_map(|(id, display_name, tags, kind)| Account { id, display_name, tags, kind })
+                .boxed()
+        }
+    }
+
+    fn profile(nickname: Option<&str>) -> Profile {
+        Profile { id: 7, nickname: nickname.map(Into::into), score: 3 }
+    }
+
+    #[test]
+    fn symmetric_types_pass() {
+        check_symmetry::<Account>();
+        check_symmetry::<Vec<(u8, Option<String>)>>();
+        assert_eq!(find_asymmetry(&profile(Some("bob"))), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Field `nickname` is expected by `Deserialize` but was not")]
+    fn conditionally_serialized_fields_are_pinpointed() {
+        check_symmetry::<Profile>();
+    }
+
+    #[test]
+    fn nested_fields_are_pinpointed_by_path() {
+        let asymmetry = find_asymmetry(&vec![profile(Some("bob")), profile(None)]).unwrap();
+        assert_eq!(asymmetry.path(), "[1].nickname");
+        let asymmetry = find_asymmetry(&(1u8, Box::new(profile(None)))).unwrap();
+        assert_eq!(asymmetry.path(), "1.nickname");
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    enum Event {
+        Renamed {
+            id: u32,
+            #[serde(skip_deserializing)]
+            previous: String,
+            name: String,
+        },
+    }
+
+    #[test]
+    fn fields_skipped_when_deserializing_are_pinpointed() {
+        let event = Event::Renamed { id: 1, previous: "a".into(), name: "b".into() };
+        let asymmetry = find_asymmetry(&event).unwrap();
+        assert_eq!(asymmetry.path(), "previous");
+        assert!(asymmetry.to_string().contains("`skip_deserializing`"));
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Cached {
+        id: u32,
+        #[serde(skip)]
+        hits: u32,
+    }
+
+    #[test]
+    fn skipped_fields_only_diverge_when_not_defaulted() {
+        assert_eq!(find_asymmetry(&Cached { id: 1, hits: 0 }), None);
+        let asymmetry = find_asymmetry(&Cached { id: 1, hits: 5 }).unwrap();
+        assert_eq!(asymmetry.path(), "");
+        assert!(asymmetry.to_string().contains("but not its encoding"));
+    }
+
+    struct ShortTuple(u8, u8);
+
+    impl Serialize for ShortTuple {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            self.0.serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for ShortTuple {
+        fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            <(u8, u8)>::deserialize(deserializer).map(|(a, b)| ShortTuple(a, b))
+        }
+    }
+
+    impl PartialEq for ShortTuple {
+        fn eq(&self, other: &Self) -> bool {
+            (self.0, self.1) == (other.0, other.1)
+        }
+    }
+
+    /// Byte serialized incremented, as a buggy custom implementation could
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Offset(u8);
+
+    impl Serialize for Offset {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            self.0.wrapping_add(1).serialize(serializer)
+        }
+    }
+
+    #[test]
+    fn failures_without_struct_mismatches_are_reported() {
+        let asymmetry = find_asymmetry(&ShortTuple(1, 2)).unwrap();
+        assert_eq!(asymmetry.path(), "");
+        assert!(asymmetry.to_string().starts_with("Decoding failed"));
+        let asymmetry = find_asymmetry(&(7u8, Offset(1))).unwrap();
+        assert_eq!(asymmetry.path(), "1");
+        assert!(asymmetry.to_string().contains("starting at `1`"));
+        assert_eq!(find_asymmetry(&(7u8, 2u8, 3u8)), None);
+    }
+}
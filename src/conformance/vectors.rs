@@ -0,0 +1,363 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Golden test vectors of the bytes produced by [`Wrap`]
+//!
+//! A fixture is a UTF-8 text made of one line per vector, each line holding three fields
+//! separated by tabs:
+//! - the name of the vector,
+//! - the `Debug` representation of the value,
+//! - the lowercase hexadecimal representation of its encoding, without any prefix.
+//!
+//! Lines starting with `#` and empty lines are ignored. This makes fixtures easy to review, to
+//! commit and to consume from other languages.
+//!
+//! ```rust
+//! use serde_scale_wrap::conformance::vectors::{verify_vectors, Vectors};
+//!
+//! let mut vectors = Vectors::new();
+//! vectors.push("answer", 42u32).push("greeting", String::from("hi"));
+//! let fixture = vectors.to_fixture();
+//! assert!(fixture.contains("answer\t42\t2a000000\n"));
+//! verify_vectors(&fixture, &vectors).unwrap();
+//! ```
+
+use crate::Wrap;
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt::{self, Debug, Display, Write as _};
+use parity_scale_codec::DecodeAll;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Fixture of the built-in vectors returned by [`primitives`]
+pub const PRIMITIVES_FIXTURE: &str = include_str!("../../vectors/primitives.txt");
+
+/// Vector of a named value and its encoding
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Vector {
+    name: String,
+    debug: String,
+    hex: String,
+}
+
+impl Vector {
+    /// Returns the name of the vector
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the `Debug` representation of the value
+    pub fn debug(&self) -> &str {
+        &self.debug
+    }
+
+    /// Returns the lowercase hexadecimal representation of the encoding
+    pub fn hex(&self) -> &str {
+        &self.hex
+    }
+}
+
+impl Display for Vector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}\t{}\t{}", self.name, self.debug, self.hex)
+    }
+}
+
+/// Function checking that bytes decode to the value a vector was built from
+type Check = Box<dyn Fn(&[u8]) -> bool>;
+
+/// Ordered collection of vectors able to check the values they were built from
+#[derive(Default)]
+pub struct Vectors {
+    entries: Vec<(Vector, Check)>,
+}
+
+impl Vectors {
+    /// Returns an empty collection
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a vector for `value`
+    ///
+    /// # Panics
+    /// Panics if `name` contains a tab or a line break, if it starts with `#`, if the `Debug`
+    /// representation of `value` spans several lines, or if a vector with the same name exists.
+    pub fn push<T>(&mut self, name: &str, value: T) -> &mut Self
+    where
+        T: Serialize + DeserializeOwned + PartialEq + Debug + 'static,
+    {
+        assert!(
+            !name.contains(&['\t', '\n', '\r'][..]) && !name.starts_with('#') && !name.is_empty(),
+            "Invalid vector name {:?}",
+            name,
+        );
+        assert!(self.get(name).is_none(), "Duplicate vector name {:?}", name);
+        let debug = format!("{:?}", value);
+        assert!(!debug.contains(&['\t', '\n', '\r'][..]), "Invalid representation {:?}", debug);
+        let vector = Vector {
+            name: name.into(),
+            debug,
+            hex: to_hex(&crate::to_vec(&value)),
+        };
+        let check = move |bytes: &[u8]| {
+            matches!(Wrap::<T>::decode_all(bytes), Ok(Wrap(ref x)) if *x == value)
+        };
+        self.entries.push((vector, Box::new(check)));
+        self
+    }
+
+    /// Returns the vectors in insertion order
+    pub fn iter(&self) -> impl Iterator<Item = &Vector> {
+        self.entries.iter().map(|(v, _)| v)
+    }
+
+    /// Returns the vector with the given name
+    pub fn get(&self, name: &str) -> Option<&Vector> {
+        self.iter().find(|v| v.name == name)
+    }
+
+    /// Returns the fixture made of these vectors
+    pub fn to_fixture(&self) -> String {
+        let mut fixture = String::from("# name\tdebug\thex\n");
+        for vector in self.iter() {
+            fixture.push_str(&vector.to_string());
+        }
+        fixture
+    }
+}
+
+impl Debug for Vectors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// Parses a fixture
+pub fn parse_fixture(fixture: &str) -> Result<Vec<Vector>, VectorError> {
+    fixture
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| {
+            let mut fields = line.split('\t');
+            match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                (Some(name), Some(debug), Some(hex), None) => Ok(Vector {
+                    name: name.into(),
+                    debug: debug.into(),
+                    hex: hex.into(),
+                }),
+                _ => Err(VectorError::Malformed { line: i + 1 }),
+            }
+        })
+        .collect()
+}
+
+/// Checks a fixture against the vectors it is expected to hold
+///
+/// Each vector of the fixture must exist in `expected` with the same representation and
+/// encoding, and its hexadecimal encoding must decode back to the original value. All vectors in
+/// `expected` must be present in the fixture.
+pub fn verify_vectors(fixture: &str, expected: &Vectors) -> Result<(), VectorError> {
+    let parsed = parse_fixture(fixture)?;
+    for vector in &parsed {
+        let (known, check) = expected
+            .entries
+            .iter()
+            .find(|(v, _)| v.name == vector.name)
+            .ok_or_else(|| VectorError::Unexpected { name: vector.name.clone() })?;
+        let mismatch = |reason| VectorError::Mismatch { name: vector.name.clone(), reason };
+        let bytes = from_hex(&vector.hex).ok_or_else(|| mismatch(MismatchReason::InvalidHex))?;
+        if !check(&bytes) {
+            return Err(mismatch(MismatchReason::Decoding));
+        }
+        if known.hex != vector.hex {
+            return Err(mismatch(MismatchReason::Encoding));
+        }
+        if known.debug != vector.debug {
+            return Err(mismatch(MismatchReason::Representation));
+        }
+    }
+    match expected.iter().find(|v| parsed.iter().all(|p| p.name != v.name)) {
+        Some(missing) => Err(VectorError::Missing { name: missing.name.clone() }),
+        None => Ok(()),
+    }
+}
+
+/// Returns vectors for primitive types and basic containers
+///
+/// Their fixture is [`PRIMITIVES_FIXTURE`], also available as `vectors/primitives.txt` in this
+/// crate's repository.
+pub fn primitives() -> Vectors {
+    let mut vectors = Vectors::new();
+    vectors
+        .push("bool false", false)
+        .push("bool true", true)
+        .push("u8 zero", 0u8)
+        .push("u8 max", u8::MAX)
+        .push("u16", 0x1234u16)
+        .push("u16 max", u16::MAX)
+        .push("u32", 0x1234_5678u32)
+        .push("u32 max", u32::MAX)
+        .push("u64", 0x0123_4567_89ab_cdefu64)
+        .push("u64 max", u64::MAX)
+        .push("i8 min", i8::MIN)
+        .push("i8 minus one", -1i8)
+        .push("i16 min", i16::MIN)
+        .push("i32 min", i32::MIN)
+        .push("i32 minus one", -1i32)
+        .push("i64 min", i64::MIN)
+        .push("i64 max", i64::MAX)
+        .push("unit", ())
+        .push("string empty", String::new())
+        .push("string ascii", String::from("scale"))
+        .push("string unicode", String::from("€🦀"))
+        .push("option none", None::<u32>)
+        .push("option some", Some(7u32))
+        .push("option bool none", None::<bool>)
+        .push("option bool true", Some(true))
+        .push("option bool false", Some(false))
+        .push("vec empty", Vec::<u16>::new())
+        .push("vec u16", vec![1u16, 2, 3])
+        .push("vec len 63", vec![0u8; 63])
+        .push("vec len 64", vec![0u8; 64])
+        .push("tuple", (1u8, 2u16, String::from("a")))
+        .push("array", [1u8, 2, 3, 4]);
+    vectors
+}
+
+/// Error returned when parsing or verifying a fixture fails
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum VectorError {
+    /// Line does not have exactly three tab-separated fields
+    Malformed {
+        /// One-based line number
+        line: usize,
+    },
+    /// Fixture holds a vector that is not expected
+    Unexpected {
+        /// Name of the vector
+        name: String,
+    },
+    /// Fixture lacks an expected vector
+    Missing {
+        /// Name of the vector
+        name: String,
+    },
+    /// Fixture holds a vector that does not match what is expected
+    Mismatch {
+        /// Name of the vector
+        name: String,
+        /// What does not match
+        reason: MismatchReason,
+    },
+}
+
+/// Reason why a vector does not match
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MismatchReason {
+    /// Encoding is not valid hexadecimal
+    InvalidHex,
+    /// Encoding does not decode to the expected value
+    Decoding,
+    /// Encoding differs from the encoding of the expected value
+    Encoding,
+    /// `Debug` representation differs from the one of the expected value
+    Representation,
+}
+
+impl Display for VectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VectorError::Malformed { line } => {
+                write!(f, "Line {} does not have 3 tab-separated fields", line)
+            }
+            VectorError::Unexpected { name } => write!(f, "Unexpected vector {:?}", name),
+            VectorError::Missing { name } => write!(f, "Missing vector {:?}", name),
+            VectorError::Mismatch { name, reason } => {
+                let reason = match reason {
+                    MismatchReason::InvalidHex => "encoding is not valid hexadecimal",
+                    MismatchReason::Decoding => "encoding does not decode to the expected value",
+                    MismatchReason::Encoding => "encoding differs",
+                    MismatchReason::Representation => "representation differs",
+                };
+                write!(f, "Vector {:?} does not match: {}", name, reason)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for VectorError {}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut s, b| {
+        let _ = write!(s, "{:02x}", b);
+        s
+    })
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|d| u8::from_str_radix(d, 16).ok()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_fixture, primitives, verify_vectors, MismatchReason, VectorError, Vectors,
+        PRIMITIVES_FIXTURE,
+    };
+
+    #[test]
+    fn primitives_match_committed_fixture() {
+        assert_eq!(primitives().to_fixture(), PRIMITIVES_FIXTURE);
+        verify_vectors(PRIMITIVES_FIXTURE, &primitives()).unwrap();
+    }
+
+    #[test]
+    fn fixture_lines_have_three_fields() {
+        let vectors = parse_fixture("# comment\n\nfoo\t3\t03\n").unwrap();
+        assert_eq!(vectors.len(), 1);
+        assert_eq!(vectors[0].name(), "foo");
+        assert_eq!(vectors[0].debug(), "3");
+        assert_eq!(vectors[0].hex(), "03");
+        assert_eq!(parse_fixture("foo\t3\n"), Err(VectorError::Malformed { line: 1 }));
+    }
+
+    #[test]
+    fn tampered_encoding_is_rejected() {
+        let mut vectors = Vectors::new();
+        vectors.push("x", 3u16);
+        let mismatch = |reason| Err(VectorError::Mismatch { name: "x".into(), reason });
+        assert_eq!(verify_vectors("x\t3\t0400\n", &vectors), mismatch(MismatchReason::Decoding));
+        assert_eq!(verify_vectors("x\t3\t030\n", &vectors), mismatch(MismatchReason::InvalidHex));
+        assert_eq!(
+            verify_vectors("x\t4\t0300\n", &vectors),
+            mismatch(MismatchReason::Representation),
+        );
+    }
+
+    #[test]
+    fn missing_and_unexpected_vectors_are_rejected() {
+        let mut vectors = Vectors::new();
+        vectors.push("x", 3u8);
+        assert_eq!(
+            verify_vectors("", &vectors),
+            Err(VectorError::Missing { name: "x".into() }),
+        );
+        assert_eq!(
+            verify_vectors("x\t3\t03\ny\t3\t03\n", &vectors),
+            Err(VectorError::Unexpected { name: "y".into() }),
+        );
+    }
+}
@@ -0,0 +1,168 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+#[cfg(feature = "decode")]
+use parity_scale_codec::Input;
+#[cfg(feature = "encode")]
+use parity_scale_codec::Output;
+
+/// `Output` counting the bytes written to it and discarding them
+///
+/// ```rust
+/// use parity_scale_codec::Encode;
+/// use serde_scale_wrap::{CountingOutput, Wrap};
+///
+/// let mut output = CountingOutput::new();
+/// Wrap((7u32, vec![1u8, 2, 3])).encode_to(&mut output);
+/// assert_eq!(output.bytes_written(), 8);
+/// ```
+#[cfg(feature = "encode")]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CountingOutput {
+    count: usize,
+}
+
+#[cfg(feature = "encode")]
+impl CountingOutput {
+    /// Returns an `Output` that has not been written to
+    pub fn new() -> Self {
+        CountingOutput { count: 0 }
+    }
+
+    /// Returns the number of bytes written so far
+    pub fn bytes_written(&self) -> usize {
+        self.count
+    }
+}
+
+#[cfg(feature = "encode")]
+impl Output for CountingOutput {
+    fn write(&mut self, bytes: &[u8]) {
+        self.count += bytes.len();
+    }
+}
+
+/// `Input` counting the bytes read from the input it borrows
+///
+/// The remaining length is the one of the underlying input. Only successful reads are counted.
+///
+/// ```rust
+/// use parity_scale_codec::Decode;
+/// use serde_scale_wrap::{CountingInput, Wrap};
+///
+/// let encoded = serde_scale_wrap::encode(&(7u32, "foo"));
+/// let mut slice = &encoded[..];
+/// let mut input = CountingInput::new(&mut slice);
+/// let Wrap(decoded) = Wrap::<(u32, String)>::decode(&mut input)?;
+/// assert_eq!((decoded.0, &*decoded.1, input.bytes_read()), (7, "foo", 8));
+/// # Ok::<_, parity_scale_codec::Error>(())
+/// ```
+#[cfg(feature = "decode")]
+#[derive(Debug)]
+pub struct CountingInput<'a, I: ?Sized> {
+    inner: &'a mut I,
+    count: usize,
+}
+
+#[cfg(feature = "decode")]
+impl<'a, I: Input + ?Sized> CountingInput<'a, I> {
+    /// Returns an `Input` reading from `inner` and counting the bytes read
+    pub fn new(inner: &'a mut I) -> Self {
+        CountingInput { inner, count: 0 }
+    }
+
+    /// Returns the number of bytes read so far
+    pub fn bytes_read(&self) -> usize {
+        self.count
+    }
+
+    /// Returns a mutable reference to the underlying input
+    ///
+    /// Bytes read directly from it are not counted.
+    pub fn get_mut(&mut self) -> &mut I {
+        self.inner
+    }
+
+    /// Returns the underlying input
+    pub fn into_inner(self) -> &'a mut I {
+        self.inner
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<I: Input + ?Sized> Input for CountingInput<'_, I> {
+    fn remaining_len(&mut self) -> Result<Option<usize>, parity_scale_codec::Error> {
+        self.inner.remaining_len()
+    }
+
+    fn read(&mut self, into: &mut [u8]) -> Result<(), parity_scale_codec::Error> {
+        self.inner.read(into)?;
+        self.count += into.len();
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> Result<u8, parity_scale_codec::Error> {
+        let byte = self.inner.read_byte()?;
+        self.count += 1;
+        Ok(byte)
+    }
+}
+
+#[cfg(all(test, feature = "alloc", feature = "encode", feature = "decode"))]
+mod tests {
+    use super::{CountingInput, CountingOutput};
+    use crate::{adapters::ReadToInput, Wrap};
+    use alloc::{string::String, vec, vec::Vec};
+    use parity_scale_codec::{Compact, Decode, Encode, Input, Output};
+
+    #[test]
+    fn written_bytes_are_counted() {
+        let mut output = CountingOutput::new();
+        assert_eq!(output.bytes_written(), 0);
+        output.write(&[1, 2, 3]);
+        output.push_byte(4);
+        output.write(&[]);
+        assert_eq!(output.bytes_written(), 4);
+        let value = (vec![String::from("a"); 64], Some(1u64));
+        Wrap(&value).encode_to(&mut output);
+        assert_eq!(output.bytes_written(), 4 + Wrap(&value).encode().len());
+    }
+
+    #[test]
+    fn read_bytes_are_counted_and_remaining_len_follows() {
+        let bytes = [1u8, 2, 3, 4, 5, 6, 7];
+        let mut slice = &bytes[..];
+        let mut input = CountingInput::new(&mut slice);
+        assert_eq!(input.remaining_len().unwrap(), Some(7));
+        assert_eq!(input.read_byte().unwrap(), 1);
+        let mut buf = [0; 4];
+        input.read(&mut buf).unwrap();
+        assert_eq!((buf, input.bytes_read()), ([2, 3, 4, 5], 5));
+        assert_eq!(input.remaining_len().unwrap(), Some(7 - input.bytes_read()));
+        assert!(input.read(&mut buf).is_err());
+        assert_eq!(input.bytes_read(), 5);
+        assert_eq!(input.into_inner(), &[6, 7]);
+    }
+
+    #[test]
+    fn inputs_of_unknown_length_are_counted() {
+        let encoded = (Compact(1000u32), 7u8).encode();
+        let mut reader = ReadToInput::new(&encoded[..]);
+        let mut input = CountingInput::new(&mut reader);
+        assert_eq!(input.remaining_len().unwrap(), None);
+        let Compact(len) = Compact::<u32>::decode(&mut input).unwrap();
+        assert_eq!((len, input.bytes_read()), (1000, 2));
+        assert_eq!(u8::decode(input.get_mut()).unwrap(), 7);
+        assert_eq!(input.bytes_read(), 2);
+    }
+
+    #[test]
+    fn decoding_counts_the_encoding() {
+        let value: (u16, Vec<Option<bool>>) = (3, vec![Some(true), None]);
+        let encoded = [Wrap(&value).encode(), vec![0xff]].concat();
+        let mut slice = &encoded[..];
+        let mut input = CountingInput::new(&mut slice);
+        let Wrap(decoded) = Wrap::<(u16, Vec<Option<bool>>)>::decode(&mut input).unwrap();
+        assert_eq!(decoded, value);
+        assert_eq!(input.bytes_read(), encoded.len() - 1);
+    }
+}
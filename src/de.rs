@@ -0,0 +1,893 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Deserializer forwarding to `serde_scale` while fixing its divergences from `parity-scale-codec`
+//!
+//! `serde_scale` expects struct variants to be prefixed with their number of fields, but neither
+//! its own serializer nor `parity-scale-codec` writes one. Struct variants are therefore
+//! deserialized as tuple variants. With the `float` feature, floating point numbers are
+//! deserialized from the unsigned integers holding their IEEE-754 bits. Everything else is
+//! forwarded, with visitors, seeds and accessors wrapped so that nested values are deserialized
+//! with this deserializer and the same `DecodeOptions` too. With `DecodeOptions::lossy_strings`,
+//! strings are deserialized as byte sequences and converted lossily. For
+//! `Wrap::decode_backward_compatible`, struct fields past the end of the input are reported
+//! missing. With `DecodeOptions::unknown_variant_fallback`, the unknown variants of an enum
+//! deserialized with `fallback::deserialize` are skipped and reported to it. With
+//! `decode_interned`, `Interned` strings are deserialized through the interner. An `OpenEnum`
+//! takes the whole payload it spans, and rejects unknown variants anywhere else. The size hints
+//! of sequences and maps are capped to `DecodeOptions::max_preallocation`, as their lengths are
+//! read from the input and may be forged to make visitors reserving them (e.g. `SmallVec`'s)
+//! allocate huge buffers. With `DecodeOptions::map_keys`, map keys are deserialized through a
+//! `KeyDeserializer` rejecting the shapes that are not accepted. With
+//! `DecodeOptions::legacy_map_layout`, map keys are read after the byte that version 0.3.0
+//! prefixed them with.
+
+use crate::{map_keys::KeyDeserializer, DecodeOptions, MapKeyShapes};
+use core::{cell::Cell, fmt};
+use serde::de::{DeserializeSeed, EnumAccess, MapAccess, SeqAccess, VariantAccess, Visitor};
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+#[cfg(feature = "alloc")]
+use core::{cell::RefCell, convert::TryFrom};
+#[cfg(feature = "alloc")]
+use serde::de::{Deserialize, IntoDeserializer};
+#[cfg(feature = "std")]
+use alloc::sync::Arc;
+
+/// Function interning the strings it is given
+#[cfg(feature = "std")]
+type InternFn<'a> = dyn Fn(&str) -> Arc<str> + 'a;
+
+/// Deserializes a `T` from `inner` with `options`
+///
+/// Failures caused by a map key rejected by `DecodeOptions::map_keys` are reported as
+/// `Error::UnsupportedMapKey`.
+#[cfg(feature = "alloc")]
+pub(crate) fn deserialize<'de, T, D, E>(
+    inner: D,
+    options: DecodeOptions,
+) -> Result<T, crate::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de, Error = crate::scale::Error<E>>,
+    E: Into<crate::Error>,
+{
+    let rejected_key = Cell::new(None);
+    let context = Context { rejected_key: Some(&rejected_key), ..Context::new(options) };
+    T::deserialize(Deserializer::with_context(inner, context)).map_err(|e| {
+        match rejected_key.get() {
+            Some(type_name) => crate::Error::UnsupportedMapKey { type_name },
+            None => crate::Error::from_deserialization(e),
+        }
+    })
+}
+
+/// State shared by a deserializer and the deserializers of nested values
+#[derive(Clone, Copy)]
+pub(crate) struct Context<'a> {
+    options: DecodeOptions,
+    /// Remaining input length, if struct fields past the end of the input are reported missing
+    remaining: Option<&'a Cell<usize>>,
+    /// Index of the unknown variant met, if the enum being deserialized falls back on unknown
+    /// variants
+    #[cfg(feature = "alloc")]
+    unknown: Option<&'a Cell<Option<u32>>>,
+    /// Fields of the structs and struct variants deserialized, in order, if they are recorded
+    #[cfg(feature = "alloc")]
+    structs: Option<&'a RefCell<Vec<&'static [&'static str]>>>,
+    /// Length of the input, if the value being deserialized is a payload spanning all of it
+    #[cfg(feature = "alloc")]
+    payload_len: Option<usize>,
+    /// Whether the enum being deserialized is the known variant of an `OpenEnum` outside of a
+    /// payload, whose unknown variants are rejected
+    #[cfg(feature = "alloc")]
+    open: bool,
+    /// Function interning the `Interned` strings, if they are interned
+    #[cfg(feature = "std")]
+    interner: Option<&'a InternFn<'a>>,
+    /// Type of the first map key rejected by `DecodeOptions::map_keys`, if it is reported
+    rejected_key: Option<&'a Cell<Option<&'static str>>>,
+}
+
+impl Context<'_> {
+    /// Returns the context of a value deserialized with `options` and no other state
+    fn new(options: DecodeOptions) -> Self {
+        Context {
+            options,
+            remaining: None,
+            #[cfg(feature = "alloc")]
+            unknown: None,
+            #[cfg(feature = "alloc")]
+            structs: None,
+            #[cfg(feature = "alloc")]
+            payload_len: None,
+            #[cfg(feature = "alloc")]
+            open: false,
+            #[cfg(feature = "std")]
+            interner: None,
+            rejected_key: None,
+        }
+    }
+
+    /// Returns the context of the values nested in the value being deserialized
+    fn nested(self) -> Self {
+        Context {
+            #[cfg(feature = "alloc")]
+            unknown: None,
+            #[cfg(feature = "alloc")]
+            payload_len: None,
+            #[cfg(feature = "alloc")]
+            open: false,
+            ..self
+        }
+    }
+
+    /// Records the fields of a struct or struct variant about to be deserialized, if requested
+    fn record_struct(&self, fields: &'static [&'static str]) {
+        #[cfg(feature = "alloc")]
+        if let Some(structs) = self.structs {
+            structs.borrow_mut().push(fields);
+        }
+        #[cfg(not(feature = "alloc"))]
+        let _ = fields;
+    }
+}
+
+pub(crate) struct Deserializer<'a, D> {
+    inner: D,
+    context: Context<'a>,
+}
+
+impl<D> Deserializer<'static, D> {
+    pub(crate) fn new(inner: D) -> Self {
+        Deserializer::with_options(inner, DecodeOptions::default())
+    }
+
+    pub(crate) fn with_options(inner: D, options: DecodeOptions) -> Self {
+        Deserializer::with_context(inner, Context::new(options))
+    }
+}
+
+impl<'a, D> Deserializer<'a, D> {
+    /// Returns a deserializer reporting struct fields as missing when `remaining` is 0 before
+    /// they are deserialized
+    ///
+    /// `remaining` must be kept up to date by the reader of `inner`.
+    #[cfg(feature = "alloc")]
+    pub(crate) fn with_missing_trailing_fields(inner: D, remaining: &'a Cell<usize>) -> Self {
+        let options = DecodeOptions::default();
+        let context = Context { remaining: Some(remaining), ..Context::new(options) };
+        Deserializer::with_context(inner, context)
+    }
+
+    /// Returns a deserializer of a value whose encoding is the whole input, of length `len`,
+    /// e.g. the payload of a `LengthPrefixed`
+    ///
+    /// This lets a `RawScale` value at the root take all the bytes.
+    #[cfg(feature = "alloc")]
+    pub(crate) fn for_payload(inner: D, len: usize) -> Self {
+        let options = DecodeOptions::default();
+        let context = Context { payload_len: Some(len), ..Context::new(options) };
+        Deserializer::with_context(inner, context)
+    }
+
+    /// Returns a deserializer appending the fields of every struct and struct variant it
+    /// deserializes to `structs`, as their `Deserialize` implementations expect them
+    #[cfg(any(all(test, feature = "alloc", feature = "encode"), feature = "testing"))]
+    pub(crate) fn recording_structs(
+        inner: D,
+        structs: &'a RefCell<Vec<&'static [&'static str]>>,
+    ) -> Self {
+        let options = DecodeOptions::default();
+        let context = Context { structs: Some(structs), ..Context::new(options) };
+        Deserializer::with_context(inner, context)
+    }
+
+    /// Returns a deserializer interning the `Interned` strings it deserializes with `interner`
+    #[cfg(feature = "std")]
+    pub(crate) fn with_interner(inner: D, interner: &'a InternFn<'a>) -> Self {
+        let options = DecodeOptions::default();
+        let context = Context { interner: Some(interner), ..Context::new(options) };
+        Deserializer::with_context(inner, context)
+    }
+
+    fn with_context(inner: D, context: Context<'a>) -> Self {
+        Deserializer { inner, context }
+    }
+}
+
+macro_rules! forward_deserialize {
+    ($($(#[$attr:meta])* $method:ident($($arg:ident: $arg_ty:ty),*),)*) => {
+        $(
+            $(#[$attr])*
+            fn $method<V>(self, $($arg: $arg_ty,)* visitor: V) -> Result<V::Value, Self::Error>
+            where
+                V: Visitor<'de>,
+            {
+                self.inner.$method($($arg,)* Wrapped(visitor, self.context.nested()))
+            }
+        )*
+    };
+}
+
+impl<'de, D: serde::Deserializer<'de>> serde::Deserializer<'de> for Deserializer<'_, D> {
+    type Error = D::Error;
+
+    forward_deserialize! {
+        deserialize_any(),
+        deserialize_bool(),
+        deserialize_i8(),
+        deserialize_i16(),
+        deserialize_i32(),
+        deserialize_i64(),
+        deserialize_i128(),
+        deserialize_u8(),
+        deserialize_u16(),
+        deserialize_u32(),
+        deserialize_u64(),
+        deserialize_u128(),
+        #[cfg(not(feature = "float"))]
+        deserialize_f32(),
+        #[cfg(not(feature = "float"))]
+        deserialize_f64(),
+        deserialize_char(),
+        deserialize_bytes(),
+        deserialize_byte_buf(),
+        deserialize_option(),
+        deserialize_unit(),
+        deserialize_unit_struct(name: &'static str),
+        deserialize_seq(),
+        deserialize_tuple(len: usize),
+        deserialize_tuple_struct(name: &'static str, len: usize),
+        deserialize_map(),
+        deserialize_identifier(),
+        deserialize_ignored_any(),
+    }
+
+    #[cfg(feature = "float")]
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.inner.deserialize_u32(FloatBits(visitor))
+    }
+
+    #[cfg(feature = "float")]
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.inner.deserialize_u64(FloatBits(visitor))
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        #[cfg(feature = "alloc")]
+        if name == crate::fallback::NAME && self.context.options.unknown_variant_fallback {
+            return visitor.visit_seq(FallbackSeq::new(self.inner, self.context.nested()));
+        }
+        #[cfg(feature = "alloc")]
+        if name == crate::raw::NAME {
+            let context = self.context.nested();
+            return match self.context.payload_len {
+                Some(len) => self.inner.deserialize_tuple(len, Wrapped(visitor, context)),
+                None => Err(serde::de::Error::custom(crate::raw::OUTSIDE_PAYLOAD)),
+            };
+        }
+        #[cfg(feature = "alloc")]
+        if name == crate::open_enum::NAME {
+            let context = self.context.nested();
+            return match self.context.payload_len {
+                Some(len) => self.inner.deserialize_tuple(len, Wrapped(visitor, context)),
+                None => visitor.visit_newtype_struct(Deserializer::with_context(
+                    self.inner,
+                    Context { open: true, ..context },
+                )),
+            };
+        }
+        #[cfg(feature = "std")]
+        if let (crate::interned::NAME, Some(interner)) = (name, self.context.interner) {
+            return self.inner.deserialize_str(Intern(visitor, interner));
+        }
+        self.inner.deserialize_newtype_struct(name, Wrapped(visitor, self.context.nested()))
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let context = self.context.nested();
+        context.record_struct(fields);
+        match context.remaining {
+            Some(remaining) => {
+                self.inner.deserialize_struct(name, fields, Fields(visitor, context, remaining))
+            }
+            None => self.inner.deserialize_struct(name, fields, Wrapped(visitor, context)),
+        }
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let context = self.context.nested();
+        #[cfg(feature = "alloc")]
+        if let Some(unknown) = self.context.unknown {
+            let visitor = FallbackEnum { inner: visitor, unknown, len: variants.len() };
+            return self.inner.deserialize_enum(name, variants, Wrapped(visitor, context));
+        }
+        #[cfg(feature = "alloc")]
+        if self.context.open {
+            let visitor = OpenEnumVariants { inner: visitor, len: variants.len() };
+            return self.inner.deserialize_enum(name, variants, Wrapped(visitor, context));
+        }
+        self.inner.deserialize_enum(name, variants, Wrapped(visitor, context))
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        #[cfg(feature = "alloc")]
+        if self.context.options.lossy_strings {
+            return self.inner.deserialize_bytes(LossyStr(visitor));
+        }
+        self.inner.deserialize_str(Wrapped(visitor, self.context.nested()))
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        #[cfg(feature = "alloc")]
+        if self.context.options.lossy_strings {
+            return self.inner.deserialize_byte_buf(LossyStr(visitor));
+        }
+        self.inner.deserialize_string(Wrapped(visitor, self.context.nested()))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+}
+
+/// Visitor passing the floating point number whose IEEE-754 bits it visits to the inner visitor
+#[cfg(feature = "float")]
+struct FloatBits<V>(V);
+
+#[cfg(feature = "float")]
+impl<'de, V: Visitor<'de>> Visitor<'de> for FloatBits<V> {
+    type Value = V::Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.expecting(f)
+    }
+
+    fn visit_u32<E: serde::de::Error>(self, v: u32) -> Result<Self::Value, E> {
+        self.0.visit_f32(f32::from_bits(v))
+    }
+
+    fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        self.0.visit_f64(f64::from_bits(v))
+    }
+}
+
+/// Visitor passing the string whose bytes it visits to the inner visitor, replacing invalid UTF-8
+#[cfg(feature = "alloc")]
+struct LossyStr<V>(V);
+
+#[cfg(feature = "alloc")]
+impl<'de, V: Visitor<'de>> Visitor<'de> for LossyStr<V> {
+    type Value = V::Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.expecting(f)
+    }
+
+    fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        match alloc::string::String::from_utf8_lossy(v) {
+            alloc::borrow::Cow::Borrowed(s) => self.0.visit_str(s),
+            alloc::borrow::Cow::Owned(s) => self.0.visit_string(s),
+        }
+    }
+
+    fn visit_borrowed_bytes<E: serde::de::Error>(self, v: &'de [u8]) -> Result<Self::Value, E> {
+        match core::str::from_utf8(v) {
+            Ok(s) => self.0.visit_borrowed_str(s),
+            Err(_) => self.0.visit_string(alloc::string::String::from_utf8_lossy(v).into_owned()),
+        }
+    }
+
+    fn visit_byte_buf<E: serde::de::Error>(self, v: alloc::vec::Vec<u8>) -> Result<Self::Value, E> {
+        match alloc::string::String::from_utf8(v) {
+            Ok(s) => self.0.visit_string(s),
+            Err(e) => self.visit_bytes(e.as_bytes()),
+        }
+    }
+}
+
+/// Visitor interning the string it visits and passing it to the visitor of an `Interned`
+#[cfg(feature = "std")]
+struct Intern<'a, V>(V, &'a InternFn<'a>);
+
+#[cfg(feature = "std")]
+impl<'de, V: Visitor<'de>> Visitor<'de> for Intern<'_, V> {
+    type Value = V::Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.expecting(f)
+    }
+
+    fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        crate::interned::visit_interned(self.0, (self.1)(v))
+    }
+}
+
+/// Visitor of struct fields, reporting fields past the end of the input as missing
+///
+/// Such fields are then given their default value by derived `Deserialize` implementations if
+/// they are marked with `#[serde(default)]`.
+struct Fields<'a, T>(T, Context<'a>, &'a Cell<usize>);
+
+impl<'de, V: Visitor<'de>> Visitor<'de> for Fields<'_, V> {
+    type Value = V::Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.expecting(f)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+        self.0.visit_seq(Fields(Wrapped(seq, self.1), self.1, self.2))
+    }
+}
+
+impl<'de, A: SeqAccess<'de>> SeqAccess<'de> for Fields<'_, A> {
+    type Error = A::Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, A::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if self.2.get() == 0 {
+            return Ok(None);
+        }
+        self.0.next_element_seed(seed)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.0.size_hint()
+    }
+}
+
+/// Sequence holding the value of a field deserialized with `fallback::deserialize`, or nothing
+/// followed by the index of the unknown variant met instead
+#[cfg(feature = "alloc")]
+struct FallbackSeq<'a, D> {
+    /// Deserializer of the value, until it is deserialized
+    inner: Option<D>,
+    context: Context<'a>,
+    /// Index of the unknown variant met, until it is deserialized
+    unknown: Option<u32>,
+}
+
+#[cfg(feature = "alloc")]
+impl<'a, D> FallbackSeq<'a, D> {
+    fn new(inner: D, context: Context<'a>) -> Self {
+        FallbackSeq { inner: Some(inner), context, unknown: None }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'de, D: serde::Deserializer<'de>> SeqAccess<'de> for FallbackSeq<'_, D> {
+    type Error = D::Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, D::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        let inner = match self.inner.take() {
+            Some(inner) => inner,
+            None => {
+                let index = self.unknown.take();
+                return index.map(|i| seed.deserialize(i.into_deserializer())).transpose();
+            }
+        };
+        let unknown = Cell::new(None);
+        let context = Context { unknown: Some(&unknown), ..self.context };
+        match seed.deserialize(Deserializer::with_context(inner, context)) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) => match unknown.get() {
+                Some(index) => {
+                    self.unknown = Some(index);
+                    Ok(None)
+                }
+                None => Err(e),
+            },
+        }
+    }
+}
+
+/// Visitor of an enum, skipping unknown variants and reporting their index in `unknown`
+///
+/// Deserializing an unknown variant fails after setting `unknown`, so that `FallbackSeq` turns
+/// the failure into the index.
+#[cfg(feature = "alloc")]
+struct FallbackEnum<'a, T> {
+    /// Visitor or access of the enum
+    inner: T,
+    unknown: &'a Cell<Option<u32>>,
+    /// Number of known variants
+    len: usize,
+}
+
+#[cfg(feature = "alloc")]
+impl<'de, V: Visitor<'de>> Visitor<'de> for FallbackEnum<'_, V> {
+    type Value = V::Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.expecting(f)
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        let FallbackEnum { inner, unknown, len } = self;
+        inner.visit_enum(FallbackEnum { inner: data, unknown, len })
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'de, A: EnumAccess<'de>> EnumAccess<'de> for FallbackEnum<'_, A> {
+    type Error = A::Error;
+    type Variant = A::Variant;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, A::Variant), A::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let FallbackEnum { inner, unknown, len } = self;
+        let (index, variant) = inner.variant_seed(VariantIndex)?;
+        if usize::try_from(index).is_ok_and(|i| i < len) {
+            return Ok((seed.deserialize(index.into_deserializer())?, variant));
+        }
+        // The fields of unknown variants are assumed to be a single `LengthPrefixed`.
+        variant.newtype_variant_seed(SkippedBytes)?;
+        unknown.set(Some(index));
+        Err(serde::de::Error::custom("Unknown variant"))
+    }
+}
+
+/// Visitor of the known variant of an `OpenEnum` outside of a payload, rejecting unknown
+/// variants as their fields cannot be told apart from the bytes following them
+#[cfg(feature = "alloc")]
+struct OpenEnumVariants<T> {
+    /// Visitor or access of the enum
+    inner: T,
+    /// Number of known variants
+    len: usize,
+}
+
+#[cfg(feature = "alloc")]
+impl<'de, V: Visitor<'de>> Visitor<'de> for OpenEnumVariants<V> {
+    type Value = V::Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.expecting(f)
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        let OpenEnumVariants { inner, len } = self;
+        inner.visit_enum(OpenEnumVariants { inner: data, len })
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'de, A: EnumAccess<'de>> EnumAccess<'de> for OpenEnumVariants<A> {
+    type Error = A::Error;
+    type Variant = A::Variant;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, A::Variant), A::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let OpenEnumVariants { inner, len } = self;
+        let (index, variant) = inner.variant_seed(VariantIndex)?;
+        if usize::try_from(index).is_ok_and(|i| i < len) {
+            return Ok((seed.deserialize(index.into_deserializer())?, variant));
+        }
+        Err(serde::de::Error::custom(crate::open_enum::OutsidePayload(index)))
+    }
+}
+
+/// Seed of the index of an enum variant
+#[cfg(feature = "alloc")]
+struct VariantIndex;
+
+#[cfg(feature = "alloc")]
+impl<'de> DeserializeSeed<'de> for VariantIndex {
+    type Value = u32;
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, d: D) -> Result<u32, D::Error> {
+        d.deserialize_identifier(self)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Visitor<'_> for VariantIndex {
+    type Value = u32;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a variant index")
+    }
+
+    fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<u32, E> {
+        u32::try_from(v).map_err(|_| E::invalid_value(serde::de::Unexpected::Unsigned(v), &self))
+    }
+}
+
+/// Seed of a byte sequence, ignoring its bytes
+#[cfg(feature = "alloc")]
+struct SkippedBytes;
+
+#[cfg(feature = "alloc")]
+impl<'de> DeserializeSeed<'de> for SkippedBytes {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, d: D) -> Result<(), D::Error> {
+        d.deserialize_bytes(self)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Visitor<'_> for SkippedBytes {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a byte sequence")
+    }
+
+    fn visit_bytes<E: serde::de::Error>(self, _: &[u8]) -> Result<(), E> {
+        Ok(())
+    }
+}
+
+struct Wrapped<'a, T>(T, Context<'a>);
+
+macro_rules! forward_visit {
+    ($($(#[$attr:meta])* $method:ident($ty:ty),)*) => {
+        $(
+            $(#[$attr])*
+            fn $method<E: serde::de::Error>(self, v: $ty) -> Result<Self::Value, E> {
+                self.0.$method(v)
+            }
+        )*
+    };
+}
+
+impl<'de, V: Visitor<'de>> Visitor<'de> for Wrapped<'_, V> {
+    type Value = V::Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.expecting(f)
+    }
+
+    forward_visit! {
+        visit_bool(bool),
+        visit_i8(i8),
+        visit_i16(i16),
+        visit_i32(i32),
+        visit_i64(i64),
+        visit_i128(i128),
+        visit_u8(u8),
+        visit_u16(u16),
+        visit_u32(u32),
+        visit_u64(u64),
+        visit_u128(u128),
+        visit_f32(f32),
+        visit_f64(f64),
+        visit_char(char),
+        visit_str(&str),
+        visit_borrowed_str(&'de str),
+        #[cfg(feature = "alloc")]
+        visit_string(alloc::string::String),
+        visit_bytes(&[u8]),
+        visit_borrowed_bytes(&'de [u8]),
+        #[cfg(feature = "alloc")]
+        visit_byte_buf(alloc::vec::Vec<u8>),
+    }
+
+    fn visit_none<E: serde::de::Error>(self) -> Result<Self::Value, E> {
+        self.0.visit_none()
+    }
+
+    fn visit_some<D: serde::Deserializer<'de>>(self, d: D) -> Result<Self::Value, D::Error> {
+        self.0.visit_some(Deserializer::with_context(d, self.1))
+    }
+
+    fn visit_unit<E: serde::de::Error>(self) -> Result<Self::Value, E> {
+        self.0.visit_unit()
+    }
+
+    fn visit_newtype_struct<D>(self, d: D) -> Result<Self::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        self.0.visit_newtype_struct(Deserializer::with_context(d, self.1))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+        self.0.visit_seq(Wrapped(seq, self.1))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        self.0.visit_map(Wrapped(map, self.1))
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        self.0.visit_enum(Wrapped(data, self.1))
+    }
+}
+
+impl<'de, S: DeserializeSeed<'de>> DeserializeSeed<'de> for Wrapped<'_, S> {
+    type Value = S::Value;
+
+    fn deserialize<D>(self, d: D) -> Result<Self::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        self.0.deserialize(Deserializer::with_context(d, self.1))
+    }
+}
+
+impl<'de, A: SeqAccess<'de>> SeqAccess<'de> for Wrapped<'_, A> {
+    type Error = A::Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, A::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.0.next_element_seed(Wrapped(seed, self.1))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.0.size_hint().map(|len| len.min(self.1.options.max_preallocation))
+    }
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for Wrapped<'_, A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, A::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        let context = self.1;
+        match (context.options.map_keys, context.options.legacy_map_layout) {
+            (Some(shapes), false) => self.0.next_key_seed(KeySeed(seed, shapes, context)),
+            (None, false) => self.0.next_key_seed(Wrapped(seed, context)),
+            (Some(shapes), true) => {
+                self.0.next_key_seed(LegacyKeySeed(KeySeed(seed, shapes, context)))
+            }
+            (None, true) => self.0.next_key_seed(LegacyKeySeed(Wrapped(seed, context))),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, A::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        self.0.next_value_seed(Wrapped(seed, self.1))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.0.size_hint().map(|len| len.min(self.1.options.max_preallocation))
+    }
+}
+
+/// Compact-encoded `2` prefixing map keys in the layout written by version 0.3.0
+const LEGACY_KEY_PREFIX: u8 = 0x08;
+
+/// Seed of a map key prefixed like version 0.3.0 did, read as a tuple of the prefix and the key
+struct LegacyKeySeed<S>(S);
+
+impl<'de, S: DeserializeSeed<'de>> DeserializeSeed<'de> for LegacyKeySeed<S> {
+    type Value = S::Value;
+
+    fn deserialize<D>(self, d: D) -> Result<S::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        d.deserialize_tuple(2, self)
+    }
+}
+
+impl<'de, S: DeserializeSeed<'de>> Visitor<'de> for LegacyKeySeed<S> {
+    type Value = S::Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a map key prefixed like version 0.3.0 did")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<S::Value, A::Error> {
+        match seq.next_element::<u8>()? {
+            Some(LEGACY_KEY_PREFIX) => {}
+            Some(prefix) => {
+                return Err(serde::de::Error::invalid_value(
+                    serde::de::Unexpected::Unsigned(u64::from(prefix)),
+                    &"the 0x08 byte prefixing map keys in the 0.3.0 layout",
+                ));
+            }
+            None => return Err(serde::de::Error::invalid_length(0, &self)),
+        }
+        let expected = "a map key after its prefix";
+        seq.next_element_seed(self.0)?
+            .ok_or_else(|| serde::de::Error::invalid_length(1, &expected))
+    }
+}
+
+/// Seed of a map key whose shape must be one of `shapes`
+struct KeySeed<'a, S>(S, MapKeyShapes, Context<'a>);
+
+impl<'de, S: DeserializeSeed<'de>> DeserializeSeed<'de> for KeySeed<'_, S> {
+    type Value = S::Value;
+
+    fn deserialize<D>(self, d: D) -> Result<S::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let KeySeed(seed, shapes, context) = self;
+        let inner = Deserializer::with_context(d, context);
+        seed.deserialize(KeyDeserializer::new::<S::Value>(inner, shapes, context.rejected_key))
+    }
+}
+
+impl<'a, 'de, A: EnumAccess<'de>> EnumAccess<'de> for Wrapped<'a, A> {
+    type Error = A::Error;
+    type Variant = Wrapped<'a, A::Variant>;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), A::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let context = self.1;
+        let (value, variant) = self.0.variant_seed(Wrapped(seed, context))?;
+        Ok((value, Wrapped(variant, context)))
+    }
+}
+
+impl<'de, A: VariantAccess<'de>> VariantAccess<'de> for Wrapped<'_, A> {
+    type Error = A::Error;
+
+    fn unit_variant(self) -> Result<(), A::Error> {
+        self.0.unit_variant()
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, A::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.0.newtype_variant_seed(Wrapped(seed, self.1))
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value, A::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.0.tuple_variant(len, Wrapped(visitor, self.1))
+    }
+
+    fn struct_variant<V>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, A::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.1.record_struct(fields);
+        // Struct variants are encoded like tuple variants, without any length prefix.
+        self.0.tuple_variant(fields.len(), Wrapped(visitor, self.1))
+    }
+}
@@ -0,0 +1,333 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+use crate::{checked::read_checked, Error, READ_CHUNK_LEN};
+#[cfg(feature = "tokio")]
+use ::tokio::io::{AsyncRead, AsyncReadExt};
+use alloc::vec::Vec;
+use parity_scale_codec::Input;
+
+/// Buffer of bytes read from an `Input` and not consumed yet
+///
+/// Bytes are appended with [`DecodeBuffer::extend_from_input`], read with
+/// [`DecodeBuffer::as_unread_slice`] and released with [`DecodeBuffer::consume`]. The buffer grows
+/// as bytes are actually read rather than to the requested length upfront, so that a forged length
+/// prefix cannot trigger a huge allocation, and never holds more than its maximum capacity. Once
+/// all its bytes are consumed, a buffer whose capacity grew past its high-water mark (e.g. to read
+/// a single large string) shrinks back to its initial capacity.
+///
+/// [`InputToRead`](crate::adapters::InputToRead), [`StreamingDecoder`](crate::StreamingDecoder)
+/// and the `decode_from_async` methods of the `tokio` feature buffer the bytes they read in a
+/// `DecodeBuffer`.
+///
+/// ```rust
+/// use serde_scale_wrap::DecodeBuffer;
+///
+/// let mut input = &b"\x07\x0cfoo"[..];
+/// let mut buffer = DecodeBuffer::new().max_capacity(8);
+/// buffer.extend_from_input(&mut input, 4)?;
+/// assert_eq!(buffer.as_unread_slice(), b"\x07\x0cfo");
+/// buffer.consume(2);
+/// assert_eq!((buffer.as_unread_slice(), buffer.offset()), (&b"fo"[..], 2));
+/// assert!(buffer.extend_from_input(&mut input, 7).is_err());
+/// # Ok::<_, serde_scale_wrap::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct DecodeBuffer {
+    bytes: Vec<u8>,
+    /// Index in `bytes` of the first unread byte
+    start: usize,
+    /// Number of bytes consumed since the buffer was created
+    offset: usize,
+    initial_capacity: usize,
+    max_capacity: usize,
+    high_water: usize,
+}
+
+impl DecodeBuffer {
+    /// Capacity past which buffers shrink once emptied by default
+    pub const DEFAULT_HIGH_WATER: usize = 64 * 1024;
+
+    /// Returns an empty buffer, not allocating until bytes are read
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    /// Returns an empty buffer with room for `initial_capacity` bytes
+    ///
+    /// The maximum capacity is unlimited and the high-water mark is
+    /// [`DecodeBuffer::DEFAULT_HIGH_WATER`].
+    pub fn with_capacity(initial_capacity: usize) -> Self {
+        DecodeBuffer {
+            bytes: Vec::with_capacity(initial_capacity),
+            start: 0,
+            offset: 0,
+            initial_capacity,
+            max_capacity: usize::MAX,
+            high_water: Self::DEFAULT_HIGH_WATER,
+        }
+    }
+
+    /// Sets how many unread bytes the buffer may hold
+    pub fn max_capacity(mut self, max: usize) -> Self {
+        self.max_capacity = max;
+        self
+    }
+
+    /// Sets the capacity past which the buffer shrinks back to its initial capacity once all its
+    /// bytes are consumed
+    pub fn high_water(mut self, high_water: usize) -> Self {
+        self.high_water = high_water;
+        self
+    }
+
+    /// Reads `n` bytes from `input` and appends them to the unread bytes
+    ///
+    /// This fails before reading anything with [`Error::BufferFull`] if the unread bytes would
+    /// exceed the maximum capacity, and with [`Error::Input`] if the remaining length of `input`
+    /// is known to be too short. It also fails with `Error::Input` if `input` fails to read, in
+    /// which case `input` may have been partially read but the unread bytes are left unchanged.
+    /// Reading zero bytes does not touch `input`.
+    pub fn extend_from_input<I>(&mut self, input: &mut I, n: usize) -> Result<(), Error>
+    where
+        I: Input + ?Sized,
+    {
+        if n == 0 {
+            return Ok(());
+        }
+        self.check_room(n)?;
+        if input.remaining_len()?.is_some_and(|remaining| n > remaining) {
+            return Err(Error::Input("Not enough data to fill buffer".into()));
+        }
+        let len = self.compact();
+        while let Some(chunk) = self.next_chunk(len, len + n) {
+            if let Err(e) = read_checked(input, chunk) {
+                self.bytes.truncate(len);
+                return Err(Error::Input(e));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads `n` bytes from `r` and appends them to the unread bytes, failing like
+    /// `extend_from_input` does, with [`Error::Io`] if `r` fails to read
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn extend_from_async_read<R>(
+        &mut self,
+        r: &mut R,
+        n: usize,
+    ) -> Result<(), Error>
+    where
+        R: AsyncRead + Unpin + ?Sized,
+    {
+        if n == 0 {
+            return Ok(());
+        }
+        self.check_room(n)?;
+        let len = self.compact();
+        while let Some(chunk) = self.next_chunk(len, len + n) {
+            if let Err(e) = r.read_exact(chunk).await {
+                self.bytes.truncate(len);
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if `n` more unread bytes would exceed the maximum capacity
+    fn check_room(&self, n: usize) -> Result<(), Error> {
+        let len = self.len();
+        if n > self.max_capacity.saturating_sub(len) {
+            return Err(Error::BufferFull { needed: len.saturating_add(n), max: self.max_capacity });
+        }
+        Ok(())
+    }
+
+    /// Moves the unread bytes to the front and returns their number
+    fn compact(&mut self) -> usize {
+        if self.start > 0 {
+            self.bytes.drain(..self.start);
+            self.start = 0;
+        }
+        self.bytes.len()
+    }
+
+    /// Appends the next chunk of zeros to overwrite with bytes read, on the way from `len` to `end`
+    /// bytes, or returns `None` if there are `end` bytes already
+    ///
+    /// Chunks double in size from `READ_CHUNK_LEN`, so that the buffer only grows as fast as bytes
+    /// are actually read.
+    fn next_chunk(&mut self, len: usize, end: usize) -> Option<&mut [u8]> {
+        let start = self.bytes.len();
+        if start >= end {
+            return None;
+        }
+        let chunk_end = end.min(start + (start - len).max(READ_CHUNK_LEN));
+        self.bytes.resize(chunk_end, 0);
+        Some(&mut self.bytes[start..])
+    }
+
+    /// Releases the first `n` unread bytes
+    ///
+    /// # Panics
+    /// Panics if fewer than `n` bytes are unread.
+    pub fn consume(&mut self, n: usize) {
+        assert!(n <= self.len(), "Consumed {} byte(s) out of {}", n, self.len());
+        self.start += n;
+        self.offset += n;
+        if self.start == self.bytes.len() {
+            self.bytes.clear();
+            self.start = 0;
+            if self.bytes.capacity() > self.high_water {
+                self.bytes.shrink_to(self.initial_capacity);
+            }
+        }
+    }
+
+    /// Returns the bytes read and not consumed yet
+    pub fn as_unread_slice(&self) -> &[u8] {
+        &self.bytes[self.start..]
+    }
+
+    /// Returns the number of bytes read and not consumed yet
+    pub fn len(&self) -> usize {
+        self.bytes.len() - self.start
+    }
+
+    /// Returns whether all bytes read were consumed
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of bytes consumed since the buffer was created, which is the offset in
+    /// the input of the first unread byte if all bytes of the input went through the buffer
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the number of bytes the buffer can hold without reallocating
+    pub fn capacity(&self) -> usize {
+        self.bytes.capacity()
+    }
+}
+
+impl Default for DecodeBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::DecodeBuffer;
+    use crate::{Error, READ_CHUNK_LEN};
+    use alloc::vec::Vec;
+    use parity_scale_codec::Input;
+
+    fn bytes(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    /// Input of unknown length
+    struct Unsized<'a>(&'a [u8]);
+
+    impl Input for Unsized<'_> {
+        fn remaining_len(&mut self) -> Result<Option<usize>, parity_scale_codec::Error> {
+            Ok(None)
+        }
+
+        fn read(&mut self, into: &mut [u8]) -> Result<(), parity_scale_codec::Error> {
+            Input::read(&mut self.0, into)
+        }
+    }
+
+    #[test]
+    fn buffer_grows_as_bytes_are_read() {
+        let data = bytes(3 * READ_CHUNK_LEN);
+        let mut input = Unsized(&data[..READ_CHUNK_LEN + 1]);
+        let mut buffer = DecodeBuffer::new();
+        assert_eq!(buffer.capacity(), 0);
+        assert!(buffer.extend_from_input(&mut input, 1 << 30).is_err());
+        assert!(buffer.capacity() < 4 * READ_CHUNK_LEN);
+        assert!(buffer.is_empty());
+        let mut input = &data[..];
+        buffer.extend_from_input(&mut input, 10).unwrap();
+        buffer.extend_from_input(&mut input, 2 * READ_CHUNK_LEN).unwrap();
+        assert_eq!(buffer.as_unread_slice(), &data[..2 * READ_CHUNK_LEN + 10]);
+        assert!(buffer.capacity() >= 2 * READ_CHUNK_LEN + 10);
+    }
+
+    #[test]
+    fn buffer_shrinks_after_spike() {
+        let data = bytes(10_000);
+        let mut input = &data[..];
+        let mut buffer = DecodeBuffer::with_capacity(16).high_water(1000);
+        buffer.extend_from_input(&mut input, 8000).unwrap();
+        assert!(buffer.capacity() >= 8000);
+        buffer.consume(7999);
+        assert!(buffer.capacity() >= 8000);
+        buffer.consume(1);
+        assert_eq!(buffer.capacity(), 16);
+        buffer.extend_from_input(&mut input, 500).unwrap();
+        buffer.consume(500);
+        let capacity = buffer.capacity();
+        assert!((500..=1000).contains(&capacity));
+        buffer.extend_from_input(&mut input, 100).unwrap();
+        buffer.consume(100);
+        assert_eq!(buffer.capacity(), capacity);
+    }
+
+    #[test]
+    fn max_capacity_is_enforced() {
+        let data = bytes(100);
+        let mut input = &data[..];
+        let mut buffer = DecodeBuffer::new().max_capacity(16);
+        buffer.extend_from_input(&mut input, 10).unwrap();
+        let result = buffer.extend_from_input(&mut input, 7);
+        assert!(matches!(result, Err(Error::BufferFull { needed: 17, max: 16 })));
+        assert_eq!(input.len(), 90);
+        buffer.consume(4);
+        buffer.extend_from_input(&mut input, 10).unwrap();
+        assert_eq!(buffer.as_unread_slice(), &data[4..20]);
+        assert!(matches!(
+            buffer.extend_from_input(&mut input, usize::MAX),
+            Err(Error::BufferFull { needed: usize::MAX, max: 16 })
+        ));
+    }
+
+    #[test]
+    fn offset_accounts_for_consumed_bytes() {
+        let data = bytes(50);
+        let mut input = &data[..];
+        let mut buffer = DecodeBuffer::new();
+        buffer.extend_from_input(&mut input, 20).unwrap();
+        buffer.consume(5);
+        assert_eq!(buffer.offset(), 5);
+        buffer.extend_from_input(&mut input, 10).unwrap();
+        buffer.consume(12);
+        assert_eq!(buffer.offset(), 17);
+        assert_eq!(buffer.as_unread_slice(), &data[17..30]);
+        buffer.consume(13);
+        buffer.extend_from_input(&mut input, 3).unwrap();
+        assert_eq!((buffer.offset(), buffer.as_unread_slice()), (30, &data[30..33]));
+    }
+
+    #[test]
+    fn failed_reads_leave_unread_bytes_unchanged() {
+        let mut input = &[1u8, 2, 3][..];
+        let mut buffer = DecodeBuffer::new();
+        buffer.extend_from_input(&mut input, 2).unwrap();
+        assert!(matches!(buffer.extend_from_input(&mut input, 2), Err(Error::Input(_))));
+        assert_eq!((buffer.as_unread_slice(), input), (&[1, 2][..], &[3][..]));
+        let mut input = Unsized(&[3, 4]);
+        assert!(matches!(buffer.extend_from_input(&mut input, 3), Err(Error::Input(_))));
+        assert_eq!(buffer.as_unread_slice(), [1, 2]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn consuming_more_than_unread_panics() {
+        let mut buffer = DecodeBuffer::new();
+        buffer.extend_from_input(&mut &[1u8, 2][..], 2).unwrap();
+        buffer.consume(3);
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+use crate::{adapters::InputToRead, de, DecodeOptions, Error};
+use parity_scale_codec::Input;
+use serde::de::DeserializeOwned;
+
+/// Deserializer reading several values one after the other from the same `Input`
+///
+/// Each value is decoded like `Wrap::<T>::decode` does, but the buffer used to read strings and
+/// byte sequences is shared by all values. Unlike `Decode::decode`, this does not log decodings.
+///
+/// ```rust
+/// use serde_scale_wrap::WrapDeserializer;
+///
+/// let mut input = &b"\x07\x0cfoo"[..];
+/// let mut deserializer = WrapDeserializer::new(&mut input);
+/// assert_eq!(deserializer.deserialize::<u8>()?, 7);
+/// assert_eq!(deserializer.deserialize::<String>()?, "foo");
+/// assert!(deserializer.is_empty());
+/// # Ok::<_, serde_scale_wrap::Error>(())
+/// ```
+pub struct WrapDeserializer<'a, I: ?Sized> {
+    read: InputToRead<'a, I>,
+    options: DecodeOptions,
+}
+
+impl<'a, I: Input + ?Sized> WrapDeserializer<'a, I> {
+    /// Returns a deserializer reading from `input`
+    pub fn new(input: &'a mut I) -> Self {
+        WrapDeserializer::with_options(input, DecodeOptions::default())
+    }
+
+    /// Returns a deserializer reading from `input` and decoding values with `options`
+    pub fn with_options(input: &'a mut I, options: DecodeOptions) -> Self {
+        WrapDeserializer { read: InputToRead::new(input), options }
+    }
+
+    /// Reads and decodes the next value
+    pub fn deserialize<T: DeserializeOwned>(&mut self) -> Result<T, Error> {
+        let mut deserializer = crate::scale::Deserializer::new(&mut self.read);
+        de::deserialize(&mut deserializer, self.options)
+    }
+
+    /// Returns whether the input is known to have been read entirely
+    ///
+    /// This returns `false` if `Input::remaining_len` fails or does not know the remaining length.
+    pub fn is_empty(&mut self) -> bool {
+        matches!(self.read.get_mut().remaining_len(), Ok(Some(0)))
+    }
+
+    /// Returns the underlying input
+    pub fn into_inner(self) -> &'a mut I {
+        self.read.into_inner()
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::WrapDeserializer;
+    use crate::Wrap;
+    use alloc::{string::String, vec, vec::Vec};
+    use parity_scale_codec::Decode;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Header {
+        version: u8,
+        kind: String,
+        len: u32,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Body {
+        entries: Vec<(u16, Vec<u8>)>,
+        checksum: Option<u64>,
+    }
+
+    fn blob() -> Vec<u8> {
+        let header = Header { version: 1, kind: "batch".into(), len: 2 };
+        let body = Body { entries: vec![(1, vec![0xaa; 300]), (2, vec![])], checksum: Some(9) };
+        let mut blob = crate::try_to_vec(&header).unwrap();
+        blob.extend(crate::try_to_vec(&body).unwrap());
+        blob
+    }
+
+    #[test]
+    fn consecutive_values_decode_like_independent_decodes() {
+        let blob = blob();
+        let mut independent = &*blob;
+        let Wrap(header) = Wrap::<Header>::decode(&mut independent).unwrap();
+        let Wrap(body) = Wrap::<Body>::decode(&mut independent).unwrap();
+        let mut input = &*blob;
+        let mut deserializer = WrapDeserializer::new(&mut input);
+        assert_eq!(deserializer.deserialize::<Header>().unwrap(), header);
+        assert!(!deserializer.is_empty());
+        assert_eq!(deserializer.deserialize::<Body>().unwrap(), body);
+        assert!(deserializer.is_empty());
+        assert!(deserializer.deserialize::<u8>().is_err());
+    }
+
+    #[test]
+    fn buffer_is_reused_across_values() {
+        let mut blob = Vec::new();
+        for len in [200, 100, 200] {
+            blob.extend(crate::try_to_vec(&"a".repeat(len)).unwrap());
+        }
+        let mut input = &*blob;
+        let mut deserializer = WrapDeserializer::new(&mut input);
+        deserializer.deserialize::<String>().unwrap();
+        let capacity = deserializer.read.buffer_capacity();
+        assert!(capacity >= 200);
+        deserializer.deserialize::<String>().unwrap();
+        deserializer.deserialize::<String>().unwrap();
+        assert_eq!(deserializer.read.buffer_capacity(), capacity);
+        assert!(deserializer.into_inner().is_empty());
+    }
+}
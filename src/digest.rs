@@ -0,0 +1,404 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Hashing of encodings without buffering them
+//!
+//! [`HashingOutput`] feeds the bytes written to it to a hasher. It implements `Output` and
+//! [`TryOutput`], and [`Wrap::encode_hash`] hashes the SCALE encoding of a value with it. The hash
+//! equals the one of the encoded bytes.
+//!
+//! ```rust
+//! use blake2::{digest::consts::U32, Blake2b, Digest};
+//! use serde_scale_wrap::Wrap;
+//!
+//! type Blake2b256 = Blake2b<U32>;
+//!
+//! let value = (7u32, "foo");
+//! let hash = Wrap(&value).encode_hash::<Blake2b256>();
+//! assert_eq!(hash, Blake2b256::digest(serde_scale_wrap::encode(&value)));
+//! ```
+//!
+//! [`HashingInput`] goes the other way, feeding the bytes read from it to a hasher, and
+//! [`Wrap::decode_verified`] checks the hash of the bytes a value is decoded from in one pass.
+//! Hashing encodings requires the `encode` feature and hashing inputs the `decode` feature.
+//!
+//! [`Hashed`] and [`HashedConcat`] hash the components of storage keys built with
+//! [`KeyBuilder`](crate::KeyBuilder), like the hashers of Substrate storage maps do, and require
+//! the `alloc` and `encode` features.
+
+use crate::Wrap;
+use digest::Digest;
+
+#[cfg(any(feature = "encode", all(feature = "alloc", feature = "decode")))]
+use crate::Error;
+#[cfg(feature = "encode")]
+use crate::{serialize_to, TryOutput};
+#[cfg(feature = "decode")]
+use parity_scale_codec::Input;
+#[cfg(feature = "encode")]
+use parity_scale_codec::Output;
+#[cfg(all(feature = "alloc", feature = "encode"))]
+use crate::KeyHasher;
+#[cfg(all(feature = "alloc", feature = "encode"))]
+use alloc::vec::Vec;
+#[cfg(all(feature = "alloc", feature = "encode"))]
+use core::marker::PhantomData;
+#[cfg(all(feature = "alloc", feature = "decode"))]
+use serde::de::DeserializeOwned;
+#[cfg(feature = "encode")]
+use serde::Serialize;
+
+/// `Output` updating a hasher with the bytes written to it
+///
+/// ```rust
+/// use blake2::Blake2s256;
+/// use parity_scale_codec::Encode;
+/// use serde_scale_wrap::{digest::HashingOutput, Wrap};
+///
+/// let mut output = HashingOutput::new(Blake2s256::default());
+/// Wrap(1u8).encode_to(&mut output);
+/// Wrap(2u16).encode_to(&mut output);
+/// assert_eq!(output.finalize(), Wrap((1u8, 2u16)).encode_hash::<Blake2s256>());
+/// ```
+#[cfg(feature = "encode")]
+#[derive(Clone, Debug, Default)]
+pub struct HashingOutput<D> {
+    hasher: D,
+}
+
+#[cfg(feature = "encode")]
+impl<D: Digest> HashingOutput<D> {
+    /// Returns an `Output` updating `hasher`
+    pub fn new(hasher: D) -> Self {
+        HashingOutput { hasher }
+    }
+
+    /// Returns the hash of the bytes written
+    pub fn finalize(self) -> digest::Output<D> {
+        self.hasher.finalize()
+    }
+
+    /// Returns the hasher
+    pub fn into_inner(self) -> D {
+        self.hasher
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<D: Digest> Output for HashingOutput<D> {
+    fn write(&mut self, bytes: &[u8]) {
+        self.hasher.update(bytes);
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<D: Digest> TryOutput for HashingOutput<D> {
+    fn try_write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.hasher.update(bytes);
+        Ok(())
+    }
+}
+
+/// `Input` updating a hasher with the bytes read from it
+///
+/// Every byte returned by a read is hashed, whether the decoder uses it or not. Bytes left in the
+/// underlying input are not.
+///
+/// ```rust
+/// use blake2::{Blake2s256, Digest};
+/// use parity_scale_codec::Decode;
+/// use serde_scale_wrap::{digest::HashingInput, Wrap};
+///
+/// let encoded = serde_scale_wrap::encode(&(1u8, 2u16));
+/// let mut bytes = &encoded[..];
+/// let mut input = HashingInput::new(&mut bytes, Blake2s256::new());
+/// assert_eq!(Wrap::<u8>::decode(&mut input)?, 1);
+/// assert_eq!(Wrap::<u16>::decode(&mut input)?, 2);
+/// assert_eq!(input.finalize(), Blake2s256::digest(&encoded));
+/// # Ok::<_, parity_scale_codec::Error>(())
+/// ```
+#[cfg(feature = "decode")]
+#[derive(Debug)]
+pub struct HashingInput<'a, I: ?Sized, D> {
+    input: &'a mut I,
+    hasher: D,
+}
+
+#[cfg(feature = "decode")]
+impl<'a, I: Input + ?Sized, D: Digest> HashingInput<'a, I, D> {
+    /// Returns an `Input` reading from `input` and updating `hasher`
+    pub fn new(input: &'a mut I, hasher: D) -> Self {
+        HashingInput { input, hasher }
+    }
+
+    /// Returns the hash of the bytes read
+    pub fn finalize(self) -> digest::Output<D> {
+        self.hasher.finalize()
+    }
+
+    /// Returns the hasher
+    pub fn into_inner(self) -> D {
+        self.hasher
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<I: Input + ?Sized, D: Digest> Input for HashingInput<'_, I, D> {
+    fn remaining_len(&mut self) -> Result<Option<usize>, parity_scale_codec::Error> {
+        self.input.remaining_len()
+    }
+
+    fn read(&mut self, into: &mut [u8]) -> Result<(), parity_scale_codec::Error> {
+        self.input.read(into)?;
+        self.hasher.update(&*into);
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> Result<u8, parity_scale_codec::Error> {
+        let byte = self.input.read_byte()?;
+        self.hasher.update([byte]);
+        Ok(byte)
+    }
+
+    fn descend_ref(&mut self) -> Result<(), parity_scale_codec::Error> {
+        self.input.descend_ref()
+    }
+
+    fn ascend_ref(&mut self) {
+        self.input.ascend_ref()
+    }
+}
+
+impl<T> Wrap<T> {
+    /// Decodes a `Wrap` from `input`, failing with [`Error::DigestMismatch`] if the hash of the
+    /// bytes it was decoded from is not `expected`
+    ///
+    /// The bytes are hashed as they are decoded, without being buffered. Bytes following the
+    /// value in `input` are not read. Decoding errors take precedence over digest mismatches.
+    ///
+    /// ```rust
+    /// use blake2::{Blake2s256, Digest};
+    /// use serde_scale_wrap::{Error, Wrap};
+    ///
+    /// let encoded = serde_scale_wrap::encode("foo");
+    /// let hash = Blake2s256::digest(&encoded);
+    /// let decoded = Wrap::<String>::decode_verified::<Blake2s256, _>(&mut &encoded[..], &hash)?;
+    /// assert_eq!(decoded, "foo".to_owned());
+    /// let result = Wrap::<String>::decode_verified::<Blake2s256, _>(&mut &b"\x0cbar"[..], &hash);
+    /// assert!(matches!(result, Err(Error::DigestMismatch)));
+    /// # Ok::<_, Error>(())
+    /// ```
+    #[cfg(all(feature = "alloc", feature = "decode"))]
+    pub fn decode_verified<D, I>(input: &mut I, expected: &[u8]) -> Result<Self, Error>
+    where
+        T: DeserializeOwned,
+        D: Digest,
+        I: Input + ?Sized,
+    {
+        let mut input = HashingInput::new(input, D::new());
+        let value = crate::deserialize_from(&mut input)?;
+        if input.finalize()[..] == *expected {
+            Ok(Wrap(value))
+        } else {
+            Err(Error::DigestMismatch)
+        }
+    }
+
+    /// Returns the hash of the SCALE encoding of the wrapped value, computed without buffering the
+    /// encoding
+    ///
+    /// # Panics
+    /// Panics if the serializer returns an error.
+    #[cfg(feature = "encode")]
+    pub fn encode_hash<D: Digest>(&self) -> digest::Output<D>
+    where
+        T: Serialize,
+    {
+        let mut output = HashingOutput::new(D::new());
+        serialize_to(&self.0, &mut output);
+        output.finalize()
+    }
+}
+
+/// [`KeyHasher`] replacing key components with their hash, like the `Blake2_128` hasher of
+/// Substrate storage maps does with `Blake2b<U16>`
+///
+/// ```rust
+/// use blake2::{digest::consts::U16, Blake2b, Digest};
+/// use serde_scale_wrap::{digest::Hashed, KeyBuilder};
+///
+/// type Blake2b128 = Blake2b<U16>;
+///
+/// let key = KeyBuilder::new(b"p").push_hashed(&Hashed::<Blake2b128>::new(), &7u32).finish();
+/// assert_eq!(key[1..], Blake2b128::digest([7, 0, 0, 0])[..]);
+/// ```
+#[cfg(all(feature = "alloc", feature = "encode"))]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Hashed<D>(PhantomData<fn() -> D>);
+
+#[cfg(all(feature = "alloc", feature = "encode"))]
+impl<D> Hashed<D> {
+    /// Returns a hasher of key components
+    pub fn new() -> Self {
+        Hashed(PhantomData)
+    }
+}
+
+#[cfg(all(feature = "alloc", feature = "encode"))]
+impl<D: Digest> KeyHasher for Hashed<D> {
+    fn hash_component(&self, key: &mut Vec<u8>, start: usize) {
+        let hash = D::digest(&key[start..]);
+        key.truncate(start);
+        key.extend_from_slice(&hash);
+    }
+}
+
+/// [`KeyHasher`] prefixing key components with their hash, like the `Blake2_128Concat` hasher of
+/// Substrate storage maps does with `Blake2b<U16>`
+///
+/// The components can be read back from the key, after skipping the hash.
+#[cfg(all(feature = "alloc", feature = "encode"))]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HashedConcat<D>(PhantomData<fn() -> D>);
+
+#[cfg(all(feature = "alloc", feature = "encode"))]
+impl<D> HashedConcat<D> {
+    /// Returns a hasher of key components
+    pub fn new() -> Self {
+        HashedConcat(PhantomData)
+    }
+}
+
+#[cfg(all(feature = "alloc", feature = "encode"))]
+impl<D: Digest> KeyHasher for HashedConcat<D> {
+    fn hash_component(&self, key: &mut Vec<u8>, start: usize) {
+        let hash = D::digest(&key[start..]);
+        key.splice(start..start, hash);
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::{HashingInput, HashingOutput};
+    use crate::Wrap;
+    use blake2::{Blake2s256, Digest};
+    use parity_scale_codec::Output;
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn streaming_hash_matches_buffered_one() {
+        use alloc::{collections::BTreeMap, string::String, vec::Vec};
+        use blake2::Blake2b512;
+
+        let value: Vec<(u32, String, BTreeMap<u16, Vec<u8>>)> = (0..2_000u32)
+            .map(|i| {
+                let entries = (0..8u16).map(|k| (k, alloc::vec![i as u8; k.into()])).collect();
+                (i, alloc::format!("item {}", i), entries)
+            })
+            .collect();
+        let encoded = crate::encode(&value);
+        assert!(encoded.len() > 100_000);
+        assert_eq!(Wrap(&value).encode_hash::<Blake2b512>(), Blake2b512::digest(&encoded));
+    }
+
+    #[test]
+    fn streaming_hash_matches_hash_of_written_bytes() {
+        let mut output = HashingOutput::new(Blake2s256::new());
+        output.write(&[3, 0, 0, 0, 1]);
+        assert_eq!(output.finalize(), Wrap((3u32, true)).encode_hash::<Blake2s256>());
+    }
+
+    #[test]
+    fn hashing_output_composes_with_other_encodings() {
+        let mut output = HashingOutput::<Blake2s256>::default();
+        Wrap((1u8, 'a')).try_encode_to(&mut output).unwrap();
+        parity_scale_codec::Encode::encode_to(&7u64, &mut output);
+        Wrap::new(-1i8).encode_infallible_to(&mut output);
+        let expected = Wrap(((1u8, 'a'), 7u64, -1i8)).encode_hash::<Blake2s256>();
+        assert_eq!(output.finalize(), expected);
+    }
+
+    #[test]
+    fn hashing_input_hashes_bytes_served_to_decoder() {
+        let bytes = [3, 0, 0, 0, 1, 0xaa];
+        let mut input = &bytes[..];
+        let mut hashing = HashingInput::new(&mut input, Blake2s256::new());
+        let decoded = Wrap::<(u32, bool)>::decode_with_scratch(&mut hashing, &mut []).unwrap();
+        assert_eq!(decoded, (3, true));
+        assert_eq!(hashing.finalize(), Blake2s256::digest(&bytes[..5]));
+        assert_eq!(input, [0xaa]);
+    }
+
+    #[cfg(feature = "alloc")]
+    mod alloc_tests {
+        use crate::{Error, Wrap};
+        use alloc::string::String;
+        use blake2::{Blake2s256, Digest};
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct Transfer {
+            from: String,
+            to: String,
+            amount: u64,
+        }
+
+        fn transfer() -> Transfer {
+            Transfer { from: "alice".into(), to: "bob".into(), amount: 100 }
+        }
+
+        fn verify(bytes: &[u8], expected: &[u8]) -> Result<Transfer, Error> {
+            Wrap::decode_verified::<Blake2s256, _>(&mut &*bytes, expected).map(Wrap::into_inner)
+        }
+
+        #[test]
+        fn matching_payload_is_decoded() {
+            let encoded = crate::encode(&transfer());
+            let hash = Blake2s256::digest(&encoded);
+            assert_eq!(verify(&encoded, &hash).unwrap(), transfer());
+            let mut extended = encoded.clone();
+            extended.push(0xaa);
+            let mut input = &extended[..];
+            let decoded = Wrap::<Transfer>::decode_verified::<Blake2s256, _>(&mut input, &hash);
+            assert_eq!(decoded.unwrap().0, transfer());
+            assert_eq!(input, [0xaa]);
+        }
+
+        #[test]
+        fn tampered_payload_is_rejected() {
+            let encoded = crate::encode(&transfer());
+            let hash = Blake2s256::digest(&encoded);
+            let mut tampered = encoded.clone();
+            *tampered.last_mut().unwrap() ^= 1;
+            assert!(matches!(verify(&tampered, &hash), Err(Error::DigestMismatch)));
+            assert!(matches!(verify(&encoded, &hash[1..]), Err(Error::DigestMismatch)));
+            let truncated = &encoded[..encoded.len() - 1];
+            assert!(matches!(verify(truncated, &hash), Err(Error::Input(_))));
+            let other = Transfer { amount: 101, ..transfer() };
+            assert!(matches!(verify(&crate::encode(&other), &hash), Err(Error::DigestMismatch)));
+        }
+
+        #[test]
+        fn hashed_key_components_match_naive_keys() {
+            use crate::{
+                digest::{Hashed, HashedConcat},
+                KeyBuilder,
+            };
+            use blake2::{digest::consts::U16, Blake2b};
+
+            type Blake2b128 = Blake2b<U16>;
+
+            let (account, name) = (42u64, String::from("alice"));
+            let key = KeyBuilder::new(b"Pallet")
+                .push_hashed(&Hashed::<Blake2b128>::new(), &account)
+                .push_hashed(&HashedConcat::<Blake2b128>::new(), &name)
+                .push(&7u8)
+                .finish();
+            let mut naive = b"Pallet".to_vec();
+            naive.extend(Blake2b128::digest(crate::encode(&account)));
+            naive.extend(Blake2b128::digest(crate::encode(&name)));
+            naive.extend(crate::encode(&name));
+            naive.push(7);
+            assert_eq!(key, naive);
+        }
+    }
+}
@@ -0,0 +1,966 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Annotated dumps of encodings, labelling the bytes of each field
+//!
+//! [`annotate`] records the byte range of every part of a value, and [`size_breakdown`] sums the
+//! sizes of its fields, e.g. to find what makes an encoding too large.
+//!
+//! ```rust
+//! use serde::Serialize;
+//! use serde_scale_wrap::dump::annotate;
+//!
+//! #[derive(Serialize)]
+//! struct Account {
+//!     id: u32,
+//!     balances: Vec<u64>,
+//! }
+//!
+//! let annotated = annotate(&Account { id: 7, balances: vec![1] });
+//! assert_eq!(
+//!     annotated.to_string(),
+//!     "Account @ 0x0..0xd = 07000000 04010000...
+//!   id: u32 @ 0x0..0x4 = 07000000
+//!   balances: seq @ 0x4..0xd = 04010000 00000000...
+//!     <len>: compact @ 0x4..0x5 = 04
+//!     [0]: u64 @ 0x5..0xd = 01000000 00000000
+//! ",
+//! );
+//! ```
+
+use crate::{adapters::OutputToWrite, ser, try_serialize_to};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{
+    cmp::Reverse,
+    fmt::{self, Display},
+    ops::Range,
+};
+use serde::{
+    ser::{
+        SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant, SerializeTuple,
+        SerializeTupleStruct, SerializeTupleVariant,
+    },
+    Serialize,
+};
+
+/// Encodes `value` like [`Wrap`](crate::Wrap) does, recording the bytes of each part of it
+///
+/// # Panics
+/// Panics if `value` cannot be serialized (e.g. because it holds a floating point number).
+pub fn annotate<T: Serialize + ?Sized>(value: &T) -> Annotated {
+    let mut state = State::default();
+    let node = Node {
+        state: &mut state,
+        depth: 0,
+        label: String::new(),
+    };
+    if let Err(e) = value.serialize(node) {
+        panic!("Failed to annotate value: {}", e);
+    }
+    Annotated {
+        bytes: state.bytes,
+        entries: state.entries,
+    }
+}
+
+/// Encoding of a value with the byte range of each of its parts
+///
+/// The `Display` implementation renders one line per entry, indented by depth.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Annotated {
+    bytes: Vec<u8>,
+    entries: Vec<Entry>,
+}
+
+impl Annotated {
+    /// Returns the encoding
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns the entries in depth-first order, parents before their children
+    ///
+    /// The children of an entry tile its range, and leaves tile the whole encoding.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+}
+
+impl Display for Annotated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in &self.entries {
+            write!(f, "{:1$}", "", entry.depth * 2)?;
+            if !entry.label.is_empty() {
+                write!(f, "{}: ", entry.label)?;
+            }
+            write!(f, "{} @ {:#x}..{:#x}", entry.kind, entry.range.start, entry.range.end)?;
+            let bytes = &self.bytes[entry.range.clone()];
+            if !bytes.is_empty() {
+                f.write_str(" = ")?;
+                for (i, b) in bytes.iter().take(SHOWN_BYTES).enumerate() {
+                    if i > 0 && i % 4 == 0 {
+                        f.write_str(" ")?;
+                    }
+                    write!(f, "{:02x}", b)?;
+                }
+                if bytes.len() > SHOWN_BYTES {
+                    f.write_str("...")?;
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Maximum number of bytes shown for each entry
+const SHOWN_BYTES: usize = 8;
+
+/// Part of an annotated encoding
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+    depth: usize,
+    label: String,
+    kind: String,
+    range: Range<usize>,
+    leaf: bool,
+    structure: bool,
+}
+
+impl Entry {
+    /// Returns the nesting depth, 0 for the annotated value itself
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns the label of this entry within its parent
+    ///
+    /// This is the field name for struct fields, `[i]` for sequence elements, `i` for tuple
+    /// elements, and `<...>` for length prefixes and tags. It is empty for the annotated value.
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Returns the kind of this entry, e.g. `u32`, `seq` or the name of a struct
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    /// Returns the range of bytes this entry occupies in the encoding
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    /// Returns whether this entry has no children
+    pub fn is_leaf(&self) -> bool {
+        self.leaf
+    }
+
+    /// Returns whether this entry is a struct or a struct variant, labelling its fields by name
+    ///
+    /// The children of a struct variant start with its `<variant>` tag.
+    pub fn is_struct(&self) -> bool {
+        self.structure
+    }
+}
+
+/// Returns the encoded size of `value` broken down by field, like [`annotate`] labels them
+///
+/// The elements of sequences and the entries of maps are aggregated into one child labelled
+/// `[..]` per kind of element (e.g. per enum variant), after the length prefix, so that the
+/// breakdown of a large collection stays short. The size of a node is the sum of the sizes of its
+/// children, if any, and the size of the root is the length of the encoding.
+///
+/// ```rust
+/// use serde::Serialize;
+/// use serde_scale_wrap::dump::size_breakdown;
+///
+/// #[derive(Serialize)]
+/// struct Block {
+///     number: u32,
+///     extrinsics: Vec<Vec<u8>>,
+/// }
+///
+/// let block = Block { number: 7, extrinsics: vec![vec![0; 100], vec![0; 50]] };
+/// let breakdown = size_breakdown(&block);
+/// assert_eq!(breakdown.size(), serde_scale_wrap::encode(&block).len());
+/// let largest = breakdown.largest_child().unwrap();
+/// assert_eq!((largest.label(), largest.size()), ("extrinsics", 154));
+/// assert_eq!(largest.element_count(), Some(2));
+/// ```
+///
+/// # Panics
+/// Panics if `value` cannot be serialized (e.g. because it holds a floating point number).
+pub fn size_breakdown<T: Serialize + ?Sized>(value: &T) -> SizeBreakdown {
+    let annotated = annotate(value);
+    let mut entries = annotated.entries.into_iter().peekable();
+    let root = entries.next().expect("Annotations always have a root entry");
+    SizeBreakdown::build(root, &mut entries)
+}
+
+/// Encoded size of a value and of each of its parts
+///
+/// The `Display` implementation renders a table with one row per node, indented by depth, the
+/// children of each node being sorted by decreasing size.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SizeBreakdown {
+    label: String,
+    kind: String,
+    size: usize,
+    count: usize,
+    element_count: Option<usize>,
+    children: Vec<SizeBreakdown>,
+}
+
+impl SizeBreakdown {
+    fn build<I>(entry: Entry, entries: &mut core::iter::Peekable<I>) -> Self
+    where
+        I: Iterator<Item = Entry>,
+    {
+        let mut children = Vec::new();
+        while let Some(child) = entries.next_if(|child| child.depth > entry.depth) {
+            children.push(SizeBreakdown::build(child, entries));
+        }
+        let mut node = SizeBreakdown {
+            label: entry.label,
+            kind: entry.kind,
+            size: entry.range.len(),
+            count: 1,
+            element_count: None,
+            children,
+        };
+        if node.kind == "seq" || node.kind == "map" {
+            node.aggregate_elements();
+        }
+        node
+    }
+
+    /// Merges the elements of a sequence or map into one child per kind of element
+    fn aggregate_elements(&mut self) {
+        let (prefixes, elements) = self
+            .children
+            .drain(..)
+            .partition::<Vec<_>, _>(|child| child.label.starts_with('<'));
+        self.element_count = Some(elements.len());
+        self.children = prefixes;
+        let first_element = self.children.len();
+        for mut element in elements {
+            element.label = "[..]".into();
+            match self.children[first_element..].iter_mut().find(|child| child.matches(&element)) {
+                Some(child) => child.merge(element),
+                None => self.children.push(element),
+            }
+        }
+    }
+
+    fn matches(&self, other: &SizeBreakdown) -> bool {
+        self.label == other.label
+            && self.kind == other.kind
+            && self.children.is_empty() == other.children.is_empty()
+    }
+
+    fn merge(&mut self, other: SizeBreakdown) {
+        self.size += other.size;
+        self.count += other.count;
+        self.element_count = self.element_count.zip(other.element_count).map(|(a, b)| a + b);
+        for other_child in other.children {
+            match self.children.iter_mut().find(|child| child.matches(&other_child)) {
+                Some(child) => child.merge(other_child),
+                None => self.children.push(other_child),
+            }
+        }
+    }
+
+    /// Returns the label of this node within its parent, as [`Entry::label`] does
+    ///
+    /// Aggregated elements of sequences and maps are labelled `[..]`.
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Returns the kind of this node, e.g. `u32`, `seq` or the name of a struct
+    ///
+    /// The kind of an enum is the name of the enum followed by the active variant, e.g.
+    /// `Call::Transfer`.
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    /// Returns the number of bytes of all values this node accounts for
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the number of values this node accounts for
+    ///
+    /// This is 1 except for aggregated elements, for which this is the number of elements.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the number of elements of a sequence or entries of a map, `None` for other kinds
+    pub fn element_count(&self) -> Option<usize> {
+        self.element_count
+    }
+
+    /// Returns the children of this node, in encoding order
+    pub fn children(&self) -> &[SizeBreakdown] {
+        &self.children
+    }
+
+    /// Returns the first of the largest children of this node
+    pub fn largest_child(&self) -> Option<&SizeBreakdown> {
+        self.children.iter().rev().max_by_key(|child| child.size)
+    }
+
+    fn fmt_rows(&self, f: &mut fmt::Formatter<'_>, depth: usize, total: usize) -> fmt::Result {
+        let permille = (self.size * 1000).checked_div(total).unwrap_or(1000);
+        write!(f, "{:>8} {:>4}.{}%  {:4$}", self.size, permille / 10, permille % 10, "", depth * 2)?;
+        if !self.label.is_empty() {
+            write!(f, "{}: ", self.label)?;
+        }
+        f.write_str(&self.kind)?;
+        if let Some(element_count) = self.element_count {
+            write!(f, " of {}", element_count)?;
+        }
+        if self.count > 1 {
+            write!(f, " (x{})", self.count)?;
+        }
+        writeln!(f)?;
+        let mut children = self.children.iter().collect::<Vec<_>>();
+        children.sort_by_key(|child| Reverse(child.size));
+        children.into_iter().try_for_each(|child| child.fmt_rows(f, depth + 1, total))
+    }
+}
+
+impl Display for SizeBreakdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:>8} {:>7}  part", "bytes", "share")?;
+        self.fmt_rows(f, 0, self.size)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    bytes: Vec<u8>,
+    entries: Vec<Entry>,
+}
+
+impl State {
+    fn push(&mut self, depth: usize, label: String, kind: String, start: usize, leaf: bool) -> usize {
+        self.entries.push(Entry {
+            depth,
+            label,
+            kind,
+            range: start..self.bytes.len(),
+            leaf,
+            structure: false,
+        });
+        self.entries.len() - 1
+    }
+
+    /// Writes what `f` serializes with `serde_scale` directly, e.g. a length prefix or a tag
+    fn write_raw<F>(&mut self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut crate::scale::Serializer<OutputToWrite<'_, Vec<u8>>>) -> RawResult,
+    {
+        let mut serializer = crate::scale::Serializer::new(OutputToWrite::new(&mut self.bytes));
+        f(&mut serializer).map_err(|e| Error(e.to_string()))
+    }
+}
+
+type RawResult = Result<(), crate::scale::Error<core::convert::Infallible>>;
+
+struct Node<'a> {
+    state: &'a mut State,
+    depth: usize,
+    label: String,
+}
+
+impl<'a> Node<'a> {
+    fn leaf<T: Serialize + ?Sized>(self, kind: &str, v: &T) -> Result<(), Error> {
+        let start = self.state.bytes.len();
+        try_serialize_to(v, &mut self.state.bytes).map_err(|e| Error(e.to_string()))?;
+        self.state.push(self.depth, self.label, kind.into(), start, true);
+        Ok(())
+    }
+
+    fn raw_leaf<F>(self, kind: String, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut crate::scale::Serializer<OutputToWrite<'_, Vec<u8>>>) -> RawResult,
+    {
+        let start = self.state.bytes.len();
+        self.state.write_raw(f)?;
+        self.state.push(self.depth, self.label, kind, start, true);
+        Ok(())
+    }
+
+    fn open(self, kind: String) -> Compound<'a> {
+        let start = self.state.bytes.len();
+        let index = self.state.push(self.depth, self.label, kind, start, false);
+        Compound {
+            state: self.state,
+            depth: self.depth + 1,
+            index,
+            count: 0,
+            pair: None,
+        }
+    }
+}
+
+struct Compound<'a> {
+    state: &'a mut State,
+    depth: usize,
+    index: usize,
+    count: usize,
+    pair: Option<usize>,
+}
+
+impl Compound<'_> {
+    fn prefix<F>(&mut self, label: &str, kind: &str, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut crate::scale::Serializer<OutputToWrite<'_, Vec<u8>>>) -> RawResult,
+    {
+        let start = self.state.bytes.len();
+        self.state.write_raw(f)?;
+        self.state.push(self.depth, label.into(), kind.into(), start, true);
+        Ok(())
+    }
+
+    fn child<T: Serialize + ?Sized>(&mut self, label: String, value: &T) -> Result<(), Error> {
+        self.count += 1;
+        let depth = self.depth + usize::from(self.pair.is_some());
+        value.serialize(Node {
+            state: self.state,
+            depth,
+            label,
+        })
+    }
+
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let label = format!("[{}]", self.count);
+        self.child(label, value)
+    }
+
+    fn position<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let label = self.count.to_string();
+        self.child(label, value)
+    }
+
+    fn close(self) -> Result<(), Error> {
+        self.state.entries[self.index].range.end = self.state.bytes.len();
+        Ok(())
+    }
+
+    /// Marks the entry as a struct, whose children are labelled with field names
+    fn into_struct(self) -> Self {
+        self.state.entries[self.index].structure = true;
+        self
+    }
+}
+
+impl<'a> serde::Serializer for Node<'a> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Compound<'a>;
+    type SerializeTuple = Compound<'a>;
+    type SerializeTupleStruct = Compound<'a>;
+    type SerializeTupleVariant = Compound<'a>;
+    type SerializeMap = Compound<'a>;
+    type SerializeStruct = Compound<'a>;
+    type SerializeStructVariant = Compound<'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), Error> {
+        self.leaf("bool", &v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), Error> {
+        self.leaf("i8", &v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), Error> {
+        self.leaf("i16", &v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), Error> {
+        self.leaf("i32", &v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), Error> {
+        self.leaf("i64", &v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), Error> {
+        self.leaf("u8", &v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), Error> {
+        self.leaf("u16", &v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), Error> {
+        self.leaf("u32", &v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), Error> {
+        self.leaf("u64", &v)
+    }
+
+    #[cfg(not(feature = "float"))]
+    fn serialize_f32(self, _: f32) -> Result<(), Error> {
+        Err(Error("Floating point numbers are not supported".into()))
+    }
+
+    #[cfg(not(feature = "float"))]
+    fn serialize_f64(self, _: f64) -> Result<(), Error> {
+        Err(Error("Floating point numbers are not supported".into()))
+    }
+
+    #[cfg(feature = "float")]
+    fn serialize_f32(self, v: f32) -> Result<(), Error> {
+        self.leaf("f32", &v)
+    }
+
+    #[cfg(feature = "float")]
+    fn serialize_f64(self, v: f64) -> Result<(), Error> {
+        self.leaf("f64", &v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), Error> {
+        self.leaf("char", &v)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), Error> {
+        self.leaf("str", v)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), Error> {
+        self.raw_leaf("bytes".into(), |s| s.serialize_bytes(v))
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        self.raw_leaf("Option".into(), |s| s.serialize_none())
+    }
+
+    fn serialize_some<T>(self, v: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        if let Some(b) = ser::as_bool(v) {
+            return self.raw_leaf("Option<bool>".into(), |s| s.serialize_u8(if b { 1 } else { 2 }));
+        }
+        let mut compound = self.open("Option".into());
+        compound.prefix("<some>", "u8", |s| s.serialize_u8(1))?;
+        compound.child("some".into(), v)?;
+        compound.close()
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        self.raw_leaf("()".into(), |s| s.serialize_unit())
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<(), Error> {
+        self.raw_leaf(name.into(), |s| s.serialize_unit_struct(name))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+    ) -> Result<(), Error> {
+        self.raw_leaf(format!("{}::{}", name, variant), |s| {
+            s.serialize_unit_variant(name, variant_index, variant)
+        })
+    }
+
+    fn serialize_newtype_struct<T>(self, name: &'static str, value: &T) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        let mut compound = self.open(name.into());
+        compound.position(value)?;
+        compound.close()
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        let mut compound = self.serialize_tuple_variant(name, variant_index, variant, 1)?;
+        compound.position(value)?;
+        compound.close()
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Compound<'a>, Error> {
+        let mut compound = self.open("seq".into());
+        compound.prefix("<len>", "compact", |s| s.serialize_seq(len).map(drop))?;
+        Ok(compound)
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<Compound<'a>, Error> {
+        Ok(self.open("tuple".into()))
+    }
+
+    fn serialize_tuple_struct(self, name: &'static str, _: usize) -> Result<Compound<'a>, Error> {
+        Ok(self.open(name.into()))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        _: usize,
+    ) -> Result<Compound<'a>, Error> {
+        let mut compound = self.open(format!("{}::{}", name, variant));
+        compound.prefix("<variant>", "u8", |s| {
+            s.serialize_unit_variant(name, variant_index, variant)
+        })?;
+        Ok(compound)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Compound<'a>, Error> {
+        // The length prefix of a map is the same as the one of a sequence.
+        let mut compound = self.open("map".into());
+        compound.prefix("<len>", "compact", |s| s.serialize_seq(len).map(drop))?;
+        Ok(compound)
+    }
+
+    fn serialize_struct(self, name: &'static str, _: usize) -> Result<Compound<'a>, Error> {
+        Ok(self.open(name.into()).into_struct())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Compound<'a>, Error> {
+        let compound = self.serialize_tuple_variant(name, variant_index, variant, len)?;
+        Ok(compound.into_struct())
+    }
+}
+
+impl SerializeSeq for Compound<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.close()
+    }
+}
+
+impl SerializeTuple for Compound<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.position(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.close()
+    }
+}
+
+impl SerializeTupleStruct for Compound<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.position(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.close()
+    }
+}
+
+impl SerializeTupleVariant for Compound<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.position(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.close()
+    }
+}
+
+impl SerializeMap for Compound<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        let start = self.state.bytes.len();
+        let label = format!("[{}]", self.count);
+        self.pair = Some(self.state.push(self.depth, label, "entry".into(), start, false));
+        self.child("key".into(), key)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        // Keys and values are counted together, so the count is rewound to index entries.
+        self.count -= 1;
+        self.child("value".into(), value)?;
+        if let Some(pair) = self.pair.take() {
+            self.state.entries[pair].range.end = self.state.bytes.len();
+        }
+        Ok(())
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.close()
+    }
+}
+
+impl SerializeStruct for Compound<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.child(key.into(), value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.close()
+    }
+}
+
+impl SerializeStructVariant for Compound<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.child(key.into(), value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.close()
+    }
+}
+
+#[derive(Debug)]
+struct Error(String);
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl serde::ser::StdError for Error {}
+
+impl serde::ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{annotate, size_breakdown, Annotated, SizeBreakdown};
+    use alloc::{collections::BTreeMap, string::String, string::ToString, vec, vec::Vec};
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    struct Owner {
+        name: String,
+        active: bool,
+    }
+
+    #[derive(Serialize)]
+    enum Kind {
+        Savings { rate: u16 },
+        Checking(u8, Option<bool>),
+    }
+
+    #[derive(Serialize)]
+    struct Account {
+        id: u32,
+        owner: Owner,
+        balances: Vec<u64>,
+        kinds: Vec<Kind>,
+        limits: BTreeMap<u8, Option<u32>>,
+    }
+
+    fn account() -> Account {
+        Account {
+            id: 7,
+            owner: Owner { name: "joe".into(), active: true },
+            balances: vec![1, 2],
+            kinds: vec![Kind::Savings { rate: 3 }, Kind::Checking(4, Some(false))],
+            limits: vec![(1, None), (2, Some(5))].into_iter().collect(),
+        }
+    }
+
+    fn assert_tiled(annotated: &Annotated) {
+        let entries = annotated.entries();
+        let mut offset = 0;
+        for entry in entries.iter().filter(|entry| entry.is_leaf()) {
+            assert_eq!(entry.range().start, offset, "Gap or overlap before {:?}", entry);
+            offset = entry.range().end;
+        }
+        assert_eq!(offset, annotated.bytes().len());
+        for (i, parent) in entries.iter().enumerate().filter(|(_, entry)| !entry.is_leaf()) {
+            let children = entries[i + 1..]
+                .iter()
+                .take_while(|entry| entry.depth() > parent.depth())
+                .filter(|entry| entry.depth() == parent.depth() + 1)
+                .collect::<Vec<_>>();
+            let mut offset = parent.range().start;
+            for child in children {
+                assert_eq!(child.range().start, offset, "Gap or overlap before {:?}", child);
+                offset = child.range().end;
+            }
+            assert_eq!(offset, parent.range().end, "Children do not tile {:?}", parent);
+        }
+    }
+
+    #[test]
+    fn annotated_bytes_are_the_encoding() {
+        let value = account();
+        assert_eq!(annotate(&value).bytes(), &*crate::to_vec(&value));
+    }
+
+    #[test]
+    fn ranges_tile_the_encoding() {
+        assert_tiled(&annotate(&account()));
+    }
+
+    #[test]
+    fn labels_follow_field_names() {
+        let annotated = annotate(&account());
+        let labels = |depth| {
+            annotated
+                .entries()
+                .iter()
+                .filter(|entry| entry.depth() == depth)
+                .map(|entry| entry.label())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(labels(1), ["id", "owner", "balances", "kinds", "limits"]);
+        assert_eq!(
+            labels(2),
+            ["name", "active", "<len>", "[0]", "[1]", "<len>", "[0]", "[1]", "<len>", "[0]", "[1]"],
+        );
+    }
+
+    #[test]
+    fn annotated_value_is_displayed() {
+        let value = (Owner { name: "a".into(), active: false }, Some(1u16));
+        assert_eq!(
+            annotate(&value).to_string(),
+            "tuple @ 0x0..0x6 = 04610001 0100\n\
+                \x20 0: Owner @ 0x0..0x3 = 046100\n\
+                \x20   name: str @ 0x0..0x2 = 0461\n\
+                \x20   active: bool @ 0x2..0x3 = 00\n\
+                \x20 1: Option @ 0x3..0x6 = 010100\n\
+                \x20   <some>: u8 @ 0x3..0x4 = 01\n\
+                \x20   some: u16 @ 0x4..0x6 = 0100\n",
+        );
+    }
+
+    #[test]
+    fn long_entries_are_truncated() {
+        let dump = annotate(&[0u8; 9]).to_string();
+        assert!(dump.starts_with("tuple @ 0x0..0x9 = 00000000 00000000...\n"), "{}", dump);
+    }
+
+    fn assert_sizes_add_up(node: &SizeBreakdown) {
+        if !node.children().is_empty() {
+            let sum = node.children().iter().map(SizeBreakdown::size).sum::<usize>();
+            assert_eq!(sum, node.size(), "Children do not add up to {:?}", node);
+        }
+        node.children().iter().for_each(assert_sizes_add_up);
+    }
+
+    #[test]
+    fn breakdown_total_is_the_encoded_size() {
+        let value = account();
+        let breakdown = size_breakdown(&value);
+        assert_eq!(breakdown.size(), crate::to_vec(&value).len());
+        assert_sizes_add_up(&breakdown);
+        let fields = breakdown.children().iter().map(|child| child.label()).collect::<Vec<_>>();
+        assert_eq!(fields, ["id", "owner", "balances", "kinds", "limits"]);
+    }
+
+    #[test]
+    fn largest_field_is_identified() {
+        #[derive(Serialize)]
+        struct Extrinsic {
+            nonce: u32,
+            signature: Option<[u8; 32]>,
+            call: Vec<u8>,
+        }
+
+        let extrinsic = Extrinsic { nonce: 1, signature: Some([0; 32]), call: vec![0; 20] };
+        let breakdown = size_breakdown(&extrinsic);
+        let largest = breakdown.largest_child().unwrap();
+        assert_eq!((largest.label(), largest.size()), ("signature", 33));
+        let unsigned = Extrinsic { signature: None, ..extrinsic };
+        let largest = size_breakdown(&unsigned).largest_child().unwrap().clone();
+        assert_eq!((largest.label(), largest.size()), ("call", 21));
+    }
+
+    #[test]
+    fn elements_are_aggregated_by_kind() {
+        let breakdown = size_breakdown(&account());
+        let kinds = &breakdown.children()[3];
+        assert_eq!(kinds.element_count(), Some(2));
+        let variants = kinds.children().iter().map(|c| (c.label(), c.kind())).collect::<Vec<_>>();
+        assert_eq!(
+            variants,
+            [("<len>", "compact"), ("[..]", "Kind::Savings"), ("[..]", "Kind::Checking")],
+        );
+        let many = (0..100u32).map(|i| (i, vec![0u8; 3])).collect::<BTreeMap<_, _>>();
+        let breakdown = size_breakdown(&many);
+        assert_eq!(breakdown.element_count(), Some(100));
+        let entries = &breakdown.children()[1];
+        assert_eq!((entries.kind(), entries.count(), entries.size()), ("entry", 100, 800));
+        let parts = entries.children().iter().map(|c| (c.label(), c.size())).collect::<Vec<_>>();
+        assert_eq!(parts, [("key", 400), ("value", 400)]);
+        assert_eq!(entries.children()[1].element_count(), Some(300));
+        assert_sizes_add_up(&breakdown);
+    }
+
+    #[test]
+    fn breakdown_is_displayed_sorted_by_size() {
+        let value = (Owner { name: "abc".into(), active: false }, vec![1u16, 2]);
+        assert_eq!(
+            size_breakdown(&value).to_string(),
+            "   bytes   share  part\n\
+            \x20     10  100.0%  tuple\n\
+            \x20      5   50.0%    0: Owner\n\
+            \x20      4   40.0%      name: str\n\
+            \x20      1   10.0%      active: bool\n\
+            \x20      5   50.0%    1: seq of 2\n\
+            \x20      4   40.0%      [..]: u16 (x2)\n\
+            \x20      1   10.0%      <len>: compact\n",
+        );
+    }
+}
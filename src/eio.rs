@@ -0,0 +1,394 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Encoding to `embedded_io::Write` and decoding from `embedded_io::Read`, e.g. on `no_std` targets
+//!
+//! [`EioOutput`] and [`EioInput`] adapt the `embedded-io` traits to `Output` and `Input`, and
+//! [`encode_to_eio`] and [`decode_from_eio`] use them to encode and decode values, returning the
+//! transport error if it fails. Encoding requires the `encode` feature and decoding the `decode`
+//! feature.
+//!
+//! ```rust
+//! use serde_scale_wrap::eio::{decode_from_eio, encode_to_eio};
+//!
+//! let mut buffer = [0; 8];
+//! let mut w = &mut buffer[..];
+//! encode_to_eio(&(7u8, "foo"), &mut w)?;
+//! let len = 8 - w.len();
+//! assert_eq!(&buffer[..len], b"\x07\x0cfoo");
+//! assert_eq!(decode_from_eio::<(u8, String), _>(&mut &buffer[..len])?, (7, "foo".to_owned()));
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::Error;
+use core::fmt::{self, Display};
+use embedded_io::ErrorType;
+
+#[cfg(feature = "encode")]
+use crate::{logging, try_serialize_to};
+#[cfg(feature = "decode")]
+use embedded_io::{ErrorKind, Read};
+#[cfg(feature = "encode")]
+use embedded_io::Write;
+#[cfg(feature = "decode")]
+use parity_scale_codec::Input;
+#[cfg(feature = "encode")]
+use parity_scale_codec::Output;
+#[cfg(feature = "encode")]
+use serde::Serialize;
+#[cfg(all(feature = "alloc", feature = "decode"))]
+use serde::de::DeserializeOwned;
+
+/// Encodes `value` and writes it to `w`
+///
+/// `w` is not flushed and may have been partially written to if an error is returned.
+///
+/// # Panics
+/// Panics if `w` returns `Ok(0)`, like `embedded_io::Write::write_all` does.
+#[cfg(feature = "encode")]
+pub fn encode_to_eio<T, W>(value: &T, w: &mut W) -> Result<(), EioError<W::Error>>
+where
+    T: Serialize + ?Sized,
+    W: Write + ?Sized,
+{
+    let mut output = EioOutput::new(w);
+    let result = logging::encode_logged::<T, _, _>(&mut output, |dst| try_serialize_to(value, dst));
+    output.finish().map_err(EioError::Transport)?;
+    result.map_err(EioError::Codec)
+}
+
+/// Reads a `T` from `r` and decodes it
+///
+/// Nothing past the encoding of the value is read from `r`, which ending in the middle of the
+/// value makes this return [`Error::Input`] in [`EioError::Codec`].
+#[cfg(all(feature = "alloc", feature = "decode"))]
+pub fn decode_from_eio<T, R>(r: &mut R) -> Result<T, EioError<R::Error>>
+where
+    T: DeserializeOwned,
+    R: Read + ?Sized,
+{
+    let mut input = EioInput::new(r);
+    let result = crate::deserialize_from(&mut input);
+    input.finish().map_err(EioError::Transport)?;
+    result.map_err(EioError::Codec)
+}
+
+/// `Output` writing to an `embedded_io::Write`, remembering the first error
+///
+/// `Output::write` cannot fail, so once a write fails, all following writes are dropped and
+/// [`EioOutput::finish`] returns the error.
+///
+/// # Panics
+/// Writing panics if the writer returns `Ok(0)`, like `embedded_io::Write::write_all` does.
+///
+/// ```rust
+/// use parity_scale_codec::Encode;
+/// use serde_scale_wrap::eio::EioOutput;
+///
+/// let mut buffer = [0; 4];
+/// let mut writer = &mut buffer[..];
+/// let mut output = EioOutput::new(&mut writer);
+/// 7u32.encode_to(&mut output);
+/// output.finish()?;
+/// assert_eq!(buffer, [7, 0, 0, 0]);
+/// # Ok::<_, embedded_io::SliceWriteError>(())
+/// ```
+#[cfg(feature = "encode")]
+#[derive(Debug)]
+pub struct EioOutput<'a, W: ErrorType + ?Sized> {
+    writer: &'a mut W,
+    error: Option<W::Error>,
+}
+
+#[cfg(feature = "encode")]
+impl<'a, W: Write + ?Sized> EioOutput<'a, W> {
+    /// Returns an `Output` writing to `writer`
+    pub fn new(writer: &'a mut W) -> Self {
+        EioOutput { writer, error: None }
+    }
+
+    /// Returns whether a write failed
+    pub fn has_failed(&self) -> bool {
+        self.error.is_some()
+    }
+
+    /// Returns the error of the write that failed, if any
+    pub fn finish(self) -> Result<(), W::Error> {
+        self.error.map_or(Ok(()), Err)
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<W: Write + ?Sized> Output for EioOutput<'_, W> {
+    fn write(&mut self, bytes: &[u8]) {
+        if self.error.is_none() {
+            self.error = self.writer.write_all(bytes).err();
+        }
+    }
+}
+
+/// `Input` reading from an `embedded_io::Read`, remembering the first error
+///
+/// Reads are repeated until the buffer is filled, since readers may return fewer bytes than
+/// asked for. Interrupted reads are retried. A reader returning no bytes is considered to have
+/// ended. Once a read fails, it and all following reads fail with a generic error and
+/// [`EioInput::finish`] returns the error of the reader.
+///
+/// ```rust
+/// use parity_scale_codec::Decode;
+/// use serde_scale_wrap::eio::EioInput;
+///
+/// let mut reader = &[7, 0, 0, 0][..];
+/// let mut input = EioInput::new(&mut reader);
+/// assert_eq!(u32::decode(&mut input).unwrap(), 7);
+/// input.finish()?;
+/// # Ok::<_, core::convert::Infallible>(())
+/// ```
+#[cfg(feature = "decode")]
+#[derive(Debug)]
+pub struct EioInput<'a, R: ErrorType + ?Sized> {
+    reader: &'a mut R,
+    error: Option<R::Error>,
+}
+
+#[cfg(feature = "decode")]
+impl<'a, R: Read + ?Sized> EioInput<'a, R> {
+    /// Returns an `Input` reading from `reader`
+    pub fn new(reader: &'a mut R) -> Self {
+        EioInput { reader, error: None }
+    }
+
+    /// Returns whether a read failed because of the reader
+    pub fn has_failed(&self) -> bool {
+        self.error.is_some()
+    }
+
+    /// Returns the error of the read that failed, if any
+    pub fn finish(self) -> Result<(), R::Error> {
+        self.error.map_or(Ok(()), Err)
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<R: Read + ?Sized> Input for EioInput<'_, R> {
+    fn remaining_len(&mut self) -> Result<Option<usize>, parity_scale_codec::Error> {
+        Ok(None)
+    }
+
+    fn read(&mut self, mut into: &mut [u8]) -> Result<(), parity_scale_codec::Error> {
+        if self.error.is_some() {
+            return Err("Reader failed".into());
+        }
+        while !into.is_empty() {
+            match self.reader.read(into) {
+                Ok(0) => return Err("Not enough data to fill buffer".into()),
+                Ok(n) => into = &mut into[n..],
+                Err(e) if embedded_io::Error::kind(&e) == ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.error = Some(e);
+                    return Err("Reader failed".into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Errors returned when encoding to a writer or decoding from a reader fails
+#[derive(Debug)]
+pub enum EioError<E> {
+    /// Encoding or decoding failed
+    Codec(Error),
+    /// The writer or reader failed
+    Transport(E),
+}
+
+impl<E: fmt::Debug> Display for EioError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EioError::Codec(e) => e.fmt(f),
+            EioError::Transport(e) => write!(f, "Transport failed: {:?}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: std::error::Error + 'static> std::error::Error for EioError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EioError::Codec(e) => Some(e),
+            EioError::Transport(e) => Some(e),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::{encode_to_eio, EioError, EioInput, EioOutput};
+    use crate::Error;
+    use embedded_io::{ErrorKind, ErrorType, Read, Write};
+    use parity_scale_codec::{Decode, Encode};
+
+    /// Reader returning one byte per read, interrupted before every byte
+    struct Trickle<'a> {
+        bytes: &'a [u8],
+        interrupted: bool,
+    }
+
+    impl<'a> Trickle<'a> {
+        fn new(bytes: &'a [u8]) -> Self {
+            Trickle { bytes, interrupted: false }
+        }
+    }
+
+    impl ErrorType for Trickle<'_> {
+        type Error = ErrorKind;
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, ErrorKind> {
+            self.interrupted = !self.interrupted;
+            if self.interrupted {
+                return Err(ErrorKind::Interrupted);
+            }
+            match (self.bytes.split_first(), buf.first_mut()) {
+                (Some((&byte, rest)), Some(dst)) => {
+                    *dst = byte;
+                    self.bytes = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    /// Writer accepting `capacity` bytes before failing, one byte per write
+    struct Limited {
+        bytes: [u8; 16],
+        len: usize,
+        capacity: usize,
+    }
+
+    impl ErrorType for Limited {
+        type Error = ErrorKind;
+    }
+
+    impl Write for Limited {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, ErrorKind> {
+            if self.len == self.capacity {
+                return Err(ErrorKind::OutOfMemory);
+            }
+            match buf.first() {
+                Some(&byte) => {
+                    self.bytes[self.len] = byte;
+                    self.len += 1;
+                    Ok(1)
+                }
+                None => Ok(0),
+            }
+        }
+
+        fn flush(&mut self) -> Result<(), ErrorKind> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn output_writes_everything_with_short_writes() {
+        let mut w = Limited { bytes: [0; 16], len: 0, capacity: 16 };
+        encode_to_eio(&(1u8, 0x0302u16, [4u8, 5]), &mut w).unwrap();
+        assert_eq!(w.bytes[..w.len], [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn output_reports_first_write_error() {
+        let mut w = Limited { bytes: [0; 16], len: 0, capacity: 3 };
+        let mut output = EioOutput::new(&mut w);
+        7u32.encode_to(&mut output);
+        assert!(output.has_failed());
+        8u8.encode_to(&mut output);
+        assert_eq!(output.finish().unwrap_err(), ErrorKind::OutOfMemory);
+        assert_eq!(w.bytes[..w.len], [7, 0, 0]);
+        let result = encode_to_eio(&1u64, &mut w);
+        assert!(matches!(result, Err(EioError::Transport(ErrorKind::OutOfMemory))));
+    }
+
+    #[test]
+    fn serialization_errors_are_codec_errors() {
+        let mut buffer = [0; 32];
+        let result = encode_to_eio(&(1u8, 1u128), &mut &mut buffer[..]);
+        assert!(matches!(result, Err(EioError::Codec(Error::Serialization(_)))));
+    }
+
+    #[test]
+    fn input_fills_buffers_from_one_byte_reads() {
+        let mut r = Trickle::new(&[7, 0, 0, 0, 1, 2]);
+        let mut input = EioInput::new(&mut r);
+        assert_eq!(u32::decode(&mut input).unwrap(), 7);
+        assert_eq!(<[u8; 2]>::decode(&mut input).unwrap(), [1, 2]);
+        assert!(u8::decode(&mut input).is_err());
+        assert!(!input.has_failed());
+        input.finish().unwrap();
+    }
+
+    #[test]
+    fn input_reports_read_errors() {
+        struct Failing;
+
+        impl ErrorType for Failing {
+            type Error = ErrorKind;
+        }
+
+        impl Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> Result<usize, ErrorKind> {
+                Err(ErrorKind::ConnectionReset)
+            }
+        }
+
+        let mut r = Failing;
+        let mut input = EioInput::new(&mut r);
+        assert!(u8::decode(&mut input).is_err());
+        assert_eq!(input.finish().unwrap_err(), ErrorKind::ConnectionReset);
+    }
+
+    #[cfg(feature = "alloc")]
+    mod alloc_tests {
+        use super::Trickle;
+        use crate::{
+            eio::{decode_from_eio, encode_to_eio, EioError},
+            Error,
+        };
+        use alloc::{string::String, vec, vec::Vec};
+        use embedded_io::ErrorKind;
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct Packet {
+            id: u16,
+            payload: Vec<u8>,
+            label: String,
+        }
+
+        fn packet() -> Packet {
+            Packet { id: 9, payload: vec![1, 2, 3], label: "sensor".into() }
+        }
+
+        #[test]
+        fn values_roundtrip_through_one_byte_reads() {
+            let mut encoded = Vec::new();
+            encode_to_eio(&packet(), &mut encoded).unwrap();
+            assert_eq!(encoded, crate::encode(&packet()));
+            encoded.push(0xaa);
+            let mut r = Trickle::new(&encoded);
+            assert_eq!(decode_from_eio::<Packet, _>(&mut r).unwrap(), packet());
+            assert_eq!(r.bytes, [0xaa]);
+        }
+
+        #[test]
+        fn truncated_stream_is_a_codec_error() {
+            let encoded = crate::encode(&packet());
+            let mut r = Trickle::new(&encoded[..encoded.len() - 1]);
+            let result = decode_from_eio::<Packet, _>(&mut r);
+            assert!(matches!(result, Err(EioError::<ErrorKind>::Codec(Error::Input(_)))));
+        }
+    }
+}
@@ -0,0 +1,605 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+#[cfg(feature = "alloc")]
+use alloc::string::ToString;
+use core::{
+    convert::Infallible,
+    fmt::{self, Display},
+};
+
+/// Errors returned when encoding or decoding through `serde` fails
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The value cannot be serialized (e.g. it holds a floating point number)
+    Serialization(crate::scale::Error<Infallible>),
+    /// The input is not a valid encoding of the expected type
+    Deserialization(crate::scale::Error<Infallible>),
+    /// The input failed (e.g. it ended too early)
+    ///
+    /// This holds the error the input returned, unchanged, and `Wrap` returns it as is.
+    Input(parity_scale_codec::Error),
+    /// Bytes were left over after decoding a value from a slice
+    TrailingBytes {
+        /// Number of bytes left over
+        len: usize,
+    },
+    /// The output ran out of capacity
+    CapacityExceeded {
+        /// Capacity of the output
+        capacity: usize,
+    },
+    /// A read needed more bytes than the scratch buffer holds
+    ScratchTooSmall {
+        /// Number of bytes needed
+        needed: usize,
+        /// Size of the scratch buffer
+        available: usize,
+    },
+    /// A `DecodeBuffer` would hold more bytes than its maximum capacity
+    BufferFull {
+        /// Number of bytes the buffer would hold
+        needed: usize,
+        /// Maximum capacity of the buffer
+        max: usize,
+    },
+    /// A length-prefixed frame is longer than allowed
+    FrameTooLarge {
+        /// Length of the frame
+        len: usize,
+        /// Maximum length of a frame
+        max: usize,
+    },
+    /// A reader yielded a different number of bytes than declared
+    LengthMismatch {
+        /// Number of bytes declared
+        declared: u64,
+        /// Number of bytes read, which is `declared + 1` if the reader yielded too many bytes
+        actual: u64,
+    },
+    /// An encoding is longer than allowed
+    EncodingTooLarge {
+        /// Length the encoding reached when it was aborted, at least `max + 1`
+        len: usize,
+        /// Maximum length of the encoding
+        max: usize,
+    },
+    /// A value being encoded nests deeper than allowed
+    DepthExceeded {
+        /// Maximum nesting depth
+        max: usize,
+    },
+    /// A key of a map encoded entry by entry is not greater than the key of the previous entry
+    UnorderedKeys {
+        /// Index of the entry whose key is out of order
+        index: usize,
+    },
+    /// A versioned encoding starts with a version tag that is not registered
+    UnknownVersion {
+        /// Version tag found in the input
+        version: u8,
+    },
+    /// A `RefCell` being encoded is mutably borrowed
+    ///
+    /// Only detected with the `alloc` feature, without which this is a `Serialization` error.
+    AlreadyBorrowed {
+        /// Innermost struct field holding the `RefCell`, if any
+        field: Option<&'static str>,
+    },
+    /// A `Mutex` or `RwLock` being encoded is poisoned
+    ///
+    /// Only detected with the `alloc` feature, without which this is a `Serialization` error.
+    LockPoisoned {
+        /// Innermost struct field holding the lock, if any
+        field: Option<&'static str>,
+    },
+    /// A map has keys whose shape is not accepted by the options
+    UnsupportedMapKey {
+        /// Name of the type of the keys
+        type_name: &'static str,
+    },
+    /// The hash of the decoded bytes differs from the expected one
+    #[cfg(feature = "digest")]
+    DigestMismatch,
+    /// The fingerprint of the input differs from the one of the type being decoded
+    #[cfg(feature = "typed")]
+    FingerprintMismatch {
+        /// Fingerprint of the type being decoded
+        expected: u32,
+        /// Fingerprint found in the input
+        found: u32,
+    },
+    /// The reader or writer failed
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+    /// The string is not valid hexadecimal
+    #[cfg(feature = "hex")]
+    Hex(hex::FromHexError),
+    /// The string is not valid base64
+    #[cfg(feature = "base64")]
+    Base64(base64::DecodeError),
+}
+
+impl Error {
+    #[cfg(feature = "encode")]
+    pub(crate) fn from_serialization<W: Into<Error>>(e: crate::scale::Error<W>) -> Self {
+        match split_io(e) {
+            Ok(e) => Error::Serialization(e),
+            Err(e) => e.into(),
+        }
+    }
+
+    #[cfg(feature = "decode")]
+    pub(crate) fn from_deserialization<I: Into<Error>>(e: crate::scale::Error<I>) -> Self {
+        match split_io(e) {
+            Ok(e) => Error::Deserialization(e),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+/// Separates I/O errors from the errors originating in `serde_scale`
+#[cfg(any(feature = "encode", feature = "decode"))]
+fn split_io<E>(e: crate::scale::Error<E>) -> Result<crate::scale::Error<Infallible>, E> {
+    use crate::scale::Error as E;
+
+    Ok(match e {
+        E::Io(e) => return Err(e),
+        E::FloatingPointUnsupported => E::FloatingPointUnsupported,
+        E::TooManyVariants { enum_name, variant_name, variant_index } => {
+            E::TooManyVariants { enum_name, variant_name, variant_index }
+        }
+        E::LengthNeeded => E::LengthNeeded,
+        E::TypeMustBeKnown => E::TypeMustBeKnown,
+        E::ExpectedBoolean { found } => E::ExpectedBoolean { found },
+        E::InvalidCharacter { found } => E::InvalidCharacter { found },
+        E::CollectionTooLargeToSerialize { len } => E::CollectionTooLargeToSerialize { len },
+        E::CollectionTooLargeToDeserialize => E::CollectionTooLargeToDeserialize,
+        E::InvalidUnicode(e) => E::InvalidUnicode(e),
+        E::InvalidOption { found_discriminant } => E::InvalidOption { found_discriminant },
+        E::Other(e) => E::Other(e),
+    })
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Serialization(e) => write!(f, "Serialization failed: {}", e),
+            Error::Deserialization(e) => write!(f, "Deserialization failed: {}", e),
+            Error::Input(e) => write!(f, "Input error: {}", e),
+            Error::TrailingBytes { len } => {
+                write!(f, "Input has {} byte(s) left over after decoding", len)
+            }
+            Error::CapacityExceeded { capacity } => {
+                write!(f, "Output capacity of {} byte(s) exceeded", capacity)
+            }
+            Error::ScratchTooSmall { needed, available } => write!(
+                f,
+                "Reading {} byte(s) exceeds the {} byte(s) of the scratch buffer",
+                needed, available,
+            ),
+            Error::BufferFull { needed, max } => write!(
+                f,
+                "Buffering {} byte(s) exceeds the maximum capacity of {} byte(s)",
+                needed, max,
+            ),
+            Error::FrameTooLarge { len, max } => {
+                write!(f, "Frame of {} byte(s) exceeds the maximum of {} byte(s)", len, max)
+            }
+            Error::LengthMismatch { declared, actual } => write!(
+                f,
+                "Reader yielded {} byte(s) instead of the {} declared",
+                actual, declared,
+            ),
+            Error::EncodingTooLarge { len, max } => write!(
+                f,
+                "Encoding of at least {} byte(s) exceeds the maximum of {} byte(s)",
+                len, max,
+            ),
+            Error::DepthExceeded { max } => {
+                write!(f, "Value nests deeper than the maximum of {} level(s)", max)
+            }
+            Error::UnorderedKeys { index } => {
+                write!(f, "Key of map entry {} is not greater than the previous key", index)
+            }
+            Error::UnknownVersion { version } => write!(f, "Unknown version {}", version),
+            Error::AlreadyBorrowed { field: Some(field) } => {
+                write!(f, "`RefCell` in field `{}` is already mutably borrowed", field)
+            }
+            Error::AlreadyBorrowed { field: None } => {
+                f.write_str("`RefCell` is already mutably borrowed")
+            }
+            Error::LockPoisoned { field: Some(field) } => {
+                write!(f, "Lock in field `{}` is poisoned", field)
+            }
+            Error::LockPoisoned { field: None } => f.write_str("Lock is poisoned"),
+            Error::UnsupportedMapKey { type_name } => {
+                write!(f, "Map keys of type `{}` are not supported", type_name)
+            }
+            #[cfg(feature = "digest")]
+            Error::DigestMismatch => {
+                f.write_str("Digest of the input differs from the expected one")
+            }
+            #[cfg(feature = "typed")]
+            Error::FingerprintMismatch { expected, found } => write!(
+                f,
+                "Type fingerprint mismatch (expected {:#010x}, found {:#010x})",
+                expected, found,
+            ),
+            #[cfg(feature = "std")]
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            #[cfg(feature = "hex")]
+            Error::Hex(e) => write!(f, "Invalid hexadecimal: {}", e),
+            #[cfg(feature = "base64")]
+            Error::Base64(e) => write!(f, "Invalid base64: {}", Base64Error(e)),
+        }
+    }
+}
+
+/// Formats the variant and its static context, deferring to `Display` only for the messages of
+/// nested errors that are not known statically
+#[cfg(feature = "defmt")]
+impl defmt::Format for Error {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        match self {
+            Error::Serialization(e) => {
+                defmt::write!(f, "Serialization failed: {}", SerdeScaleError(e))
+            }
+            Error::Deserialization(e) => {
+                defmt::write!(f, "Deserialization failed: {}", SerdeScaleError(e))
+            }
+            Error::Input(e) => defmt::write!(f, "Input error: {}", defmt::Display2Format(e)),
+            Error::TrailingBytes { len } => {
+                defmt::write!(f, "Input has {=usize} byte(s) left over after decoding", len)
+            }
+            Error::CapacityExceeded { capacity } => {
+                defmt::write!(f, "Output capacity of {=usize} byte(s) exceeded", capacity)
+            }
+            Error::ScratchTooSmall { needed, available } => defmt::write!(
+                f,
+                "Reading {=usize} byte(s) exceeds the {=usize} byte(s) of the scratch buffer",
+                needed,
+                available,
+            ),
+            Error::BufferFull { needed, max } => defmt::write!(
+                f,
+                "Buffering {=usize} byte(s) exceeds the maximum capacity of {=usize} byte(s)",
+                needed,
+                max,
+            ),
+            Error::FrameTooLarge { len, max } => defmt::write!(
+                f,
+                "Frame of {=usize} byte(s) exceeds the maximum of {=usize} byte(s)",
+                len,
+                max,
+            ),
+            Error::LengthMismatch { declared, actual } => defmt::write!(
+                f,
+                "Reader yielded {=u64} byte(s) instead of the {=u64} declared",
+                actual,
+                declared,
+            ),
+            Error::EncodingTooLarge { len, max } => defmt::write!(
+                f,
+                "Encoding of at least {=usize} byte(s) exceeds the maximum of {=usize} byte(s)",
+                len,
+                max,
+            ),
+            Error::DepthExceeded { max } => {
+                defmt::write!(f, "Value nests deeper than the maximum of {=usize} level(s)", max)
+            }
+            Error::UnorderedKeys { index } => defmt::write!(
+                f,
+                "Key of map entry {=usize} is not greater than the previous key",
+                index,
+            ),
+            Error::UnknownVersion { version } => {
+                defmt::write!(f, "Unknown version {=u8}", version)
+            }
+            Error::AlreadyBorrowed { field: Some(field) } => defmt::write!(
+                f,
+                "`RefCell` in field `{=str}` is already mutably borrowed",
+                field,
+            ),
+            Error::AlreadyBorrowed { field: None } => {
+                defmt::write!(f, "`RefCell` is already mutably borrowed")
+            }
+            Error::LockPoisoned { field: Some(field) } => {
+                defmt::write!(f, "Lock in field `{=str}` is poisoned", field)
+            }
+            Error::LockPoisoned { field: None } => defmt::write!(f, "Lock is poisoned"),
+            Error::UnsupportedMapKey { type_name } => {
+                defmt::write!(f, "Map keys of type `{=str}` are not supported", type_name)
+            }
+            #[cfg(feature = "digest")]
+            Error::DigestMismatch => {
+                defmt::write!(f, "Digest of the input differs from the expected one")
+            }
+            #[cfg(feature = "typed")]
+            Error::FingerprintMismatch { expected, found } => defmt::write!(
+                f,
+                "Type fingerprint mismatch (expected {=u32:#010x}, found {=u32:#010x})",
+                expected,
+                found,
+            ),
+            #[cfg(feature = "std")]
+            Error::Io(e) => defmt::write!(f, "I/O error: {}", defmt::Display2Format(e)),
+            #[cfg(feature = "hex")]
+            Error::Hex(e) => defmt::write!(f, "Invalid hexadecimal: {}", defmt::Display2Format(e)),
+            #[cfg(feature = "base64")]
+            Error::Base64(e) => {
+                defmt::write!(f, "Invalid base64: {}", defmt::Display2Format(&Base64Error(e)))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+struct SerdeScaleError<'a>(&'a crate::scale::Error<Infallible>);
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for SerdeScaleError<'_> {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        use crate::scale::Error as E;
+
+        match self.0 {
+            E::FloatingPointUnsupported => defmt::write!(f, "Floating point unsupported"),
+            E::TooManyVariants { enum_name, variant_name, variant_index } => defmt::write!(
+                f,
+                "Variant {=str}::{=str} has index {=u32} above 255",
+                enum_name,
+                variant_name,
+                variant_index,
+            ),
+            E::LengthNeeded => defmt::write!(f, "Sequence length unknown"),
+            E::TypeMustBeKnown => defmt::write!(f, "Type unknown"),
+            E::ExpectedBoolean { found } => {
+                defmt::write!(f, "Expected boolean, found {=u8}", found)
+            }
+            E::InvalidCharacter { found } => {
+                defmt::write!(f, "Invalid UTF-32 codepoint {=u32}", found)
+            }
+            E::CollectionTooLargeToSerialize { len } => {
+                defmt::write!(f, "Collection of {=usize} elements too large", len)
+            }
+            E::CollectionTooLargeToDeserialize => defmt::write!(f, "Collection too large"),
+            E::InvalidUnicode(e) => {
+                defmt::write!(f, "Invalid Unicode after {=usize} byte(s)", e.valid_up_to())
+            }
+            E::InvalidOption { found_discriminant } => {
+                defmt::write!(f, "Invalid option discriminant {=u8}", found_discriminant)
+            }
+            E::Io(e) => match *e {},
+            E::Other(_) => {
+                defmt::write!(f, "{=str}", crate::scale::other_message(self.0).unwrap_or_default())
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Serialization(e) | Error::Deserialization(e) => Some(e),
+            Error::Input(e) => Some(e),
+            Error::TrailingBytes { .. }
+            | Error::CapacityExceeded { .. }
+            | Error::ScratchTooSmall { .. }
+            | Error::BufferFull { .. }
+            | Error::FrameTooLarge { .. }
+            | Error::LengthMismatch { .. }
+            | Error::EncodingTooLarge { .. }
+            | Error::DepthExceeded { .. }
+            | Error::UnorderedKeys { .. }
+            | Error::UnknownVersion { .. }
+            | Error::AlreadyBorrowed { .. }
+            | Error::LockPoisoned { .. }
+            | Error::UnsupportedMapKey { .. } => None,
+            #[cfg(feature = "digest")]
+            Error::DigestMismatch => None,
+            #[cfg(feature = "typed")]
+            Error::FingerprintMismatch { .. } => None,
+            Error::Io(e) => Some(e),
+            #[cfg(feature = "hex")]
+            Error::Hex(e) => Some(e),
+            #[cfg(feature = "base64")]
+            Error::Base64(e) => Some(e),
+        }
+    }
+}
+
+/// Converts to a codec error, chaining the detailed message when `parity-scale-codec` supports it
+///
+/// Without the `alloc` feature, only the static description of the error is kept.
+impl From<Error> for parity_scale_codec::Error {
+    #[cfg(feature = "alloc")]
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Input(e) => e,
+            Error::Serialization(e) => {
+                parity_scale_codec::Error::from("Serialization failed").chain(e.to_string())
+            }
+            Error::Deserialization(e) => {
+                parity_scale_codec::Error::from("Deserialization failed").chain(e.to_string())
+            }
+            e @ Error::TrailingBytes { .. } => {
+                parity_scale_codec::Error::from("Input too long").chain(e.to_string())
+            }
+            e @ Error::CapacityExceeded { .. } => {
+                parity_scale_codec::Error::from("Output capacity exceeded").chain(e.to_string())
+            }
+            e @ Error::ScratchTooSmall { .. } => {
+                parity_scale_codec::Error::from("Scratch buffer too small").chain(e.to_string())
+            }
+            e @ Error::BufferFull { .. } => {
+                parity_scale_codec::Error::from("Buffer full").chain(e.to_string())
+            }
+            e @ Error::FrameTooLarge { .. } => {
+                parity_scale_codec::Error::from("Frame too large").chain(e.to_string())
+            }
+            e @ Error::LengthMismatch { .. } => {
+                parity_scale_codec::Error::from("Length mismatch").chain(e.to_string())
+            }
+            e @ Error::EncodingTooLarge { .. } => {
+                parity_scale_codec::Error::from("Encoding too large").chain(e.to_string())
+            }
+            e @ Error::DepthExceeded { .. } => {
+                parity_scale_codec::Error::from("Depth exceeded").chain(e.to_string())
+            }
+            e @ Error::UnorderedKeys { .. } => {
+                parity_scale_codec::Error::from("Unordered keys").chain(e.to_string())
+            }
+            e @ Error::UnknownVersion { .. } => {
+                parity_scale_codec::Error::from("Unknown version").chain(e.to_string())
+            }
+            e @ Error::AlreadyBorrowed { .. } => {
+                parity_scale_codec::Error::from("Already borrowed").chain(e.to_string())
+            }
+            e @ Error::LockPoisoned { .. } => {
+                parity_scale_codec::Error::from("Lock poisoned").chain(e.to_string())
+            }
+            e @ Error::UnsupportedMapKey { .. } => {
+                parity_scale_codec::Error::from("Unsupported map key").chain(e.to_string())
+            }
+            #[cfg(feature = "digest")]
+            Error::DigestMismatch => "Digest mismatch".into(),
+            #[cfg(feature = "typed")]
+            e @ Error::FingerprintMismatch { .. } => {
+                parity_scale_codec::Error::from("Type fingerprint mismatch").chain(e.to_string())
+            }
+            #[cfg(feature = "std")]
+            Error::Io(e) => parity_scale_codec::Error::from("I/O error").chain(e.to_string()),
+            #[cfg(feature = "hex")]
+            Error::Hex(e) => {
+                parity_scale_codec::Error::from("Invalid hexadecimal").chain(e.to_string())
+            }
+            #[cfg(feature = "base64")]
+            Error::Base64(e) => {
+                parity_scale_codec::Error::from("Invalid base64").chain(Base64Error(&e).to_string())
+            }
+        }
+    }
+
+    #[cfg(not(feature = "alloc"))]
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Input(e) => e,
+            Error::Serialization(_) => "Serialization failed".into(),
+            Error::Deserialization(_) => "Deserialization failed".into(),
+            Error::TrailingBytes { .. } => "Input too long".into(),
+            Error::CapacityExceeded { .. } => "Output capacity exceeded".into(),
+            Error::ScratchTooSmall { .. } => "Scratch buffer too small".into(),
+            Error::BufferFull { .. } => "Buffer full".into(),
+            Error::FrameTooLarge { .. } => "Frame too large".into(),
+            Error::LengthMismatch { .. } => "Length mismatch".into(),
+            Error::EncodingTooLarge { .. } => "Encoding too large".into(),
+            Error::DepthExceeded { .. } => "Depth exceeded".into(),
+            Error::UnorderedKeys { .. } => "Unordered keys".into(),
+            Error::UnknownVersion { .. } => "Unknown version".into(),
+            Error::AlreadyBorrowed { .. } => "Already borrowed".into(),
+            Error::LockPoisoned { .. } => "Lock poisoned".into(),
+            Error::UnsupportedMapKey { .. } => "Unsupported map key".into(),
+            #[cfg(feature = "digest")]
+            Error::DigestMismatch => "Digest mismatch".into(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[cfg(feature = "hex")]
+impl From<hex::FromHexError> for Error {
+    fn from(e: hex::FromHexError) -> Self {
+        Error::Hex(e)
+    }
+}
+
+#[cfg(feature = "base64")]
+impl From<base64::DecodeError> for Error {
+    fn from(e: base64::DecodeError) -> Self {
+        Error::Base64(e)
+    }
+}
+
+/// Describes a base64 error in terms of the characters of the string
+#[cfg(feature = "base64")]
+struct Base64Error<'a>(&'a base64::DecodeError);
+
+#[cfg(feature = "base64")]
+impl Display for Base64Error<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self.0 {
+            base64::DecodeError::InvalidByte(index, b) if b.is_ascii_graphic() => {
+                write!(f, "Invalid character '{}' at position {}", char::from(b), index)
+            }
+            base64::DecodeError::InvalidByte(index, b) => {
+                write!(f, "Invalid byte {:#04x} at position {}", b, index)
+            }
+            base64::DecodeError::InvalidLength(len) => {
+                write!(f, "Invalid length of {} characters", len)
+            }
+            base64::DecodeError::InvalidLastSymbol(index, b) => write!(
+                f,
+                "Character '{}' at position {} does not end an encoding",
+                char::from(b),
+                index,
+            ),
+            base64::DecodeError::InvalidPadding => f.write_str("Invalid padding"),
+        }
+    }
+}
+
+impl From<parity_scale_codec::Error> for Error {
+    fn from(e: parity_scale_codec::Error) -> Self {
+        Error::Input(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Error;
+    use alloc::string::ToString;
+
+    #[test]
+    fn input_errors_are_unwrapped() {
+        let e = parity_scale_codec::Error::from("Not enough data");
+        assert_eq!(parity_scale_codec::Error::from(Error::Input(e.clone())), e);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn converted_errors_keep_their_message() {
+        let e = Error::Deserialization(crate::scale::Error::ExpectedBoolean { found: 3 });
+        let e = parity_scale_codec::Error::from(e);
+        assert_eq!(
+            e.to_string(),
+            "Expected boolean (0 or 1), found 3:\n\tDeserialization failed\n",
+        );
+    }
+
+    #[cfg(not(feature = "alloc"))]
+    #[test]
+    fn converted_errors_keep_their_description() {
+        let e = parity_scale_codec::Error::from(Error::TrailingBytes { len: 2 });
+        assert_eq!(e, parity_scale_codec::Error::from("Input too long"));
+    }
+
+    #[cfg(feature = "defmt")]
+    #[test]
+    fn errors_implement_format() {
+        fn assert_format<T: defmt::Format>() {}
+        assert_format::<Error>();
+    }
+
+    #[test]
+    fn trailing_bytes_are_reported() {
+        let e = Error::TrailingBytes { len: 2 };
+        assert_eq!(e.to_string(), "Input has 2 byte(s) left over after decoding");
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Extension traits to encode and decode values without mentioning [`Wrap`](crate::Wrap)
+//!
+//! ```rust
+//! use serde::{Deserialize, Serialize};
+//! use serde_scale_wrap::prelude::*;
+//!
+//! #[derive(Debug, Deserialize, PartialEq, Serialize)]
+//! struct Foo {
+//!     x: u8,
+//! }
+//!
+//! let bytes = Foo { x: 3 }.scale_encode();
+//! let foo = Foo::scale_decode(&bytes)?;
+//! assert_eq!(foo, Foo { x: 3 });
+//! # Ok::<_, serde_scale_wrap::Error>(())
+//! ```
+
+use crate::Error;
+
+#[cfg(feature = "decode")]
+use crate::deserialize_from;
+#[cfg(feature = "encode")]
+use alloc::vec::Vec;
+#[cfg(feature = "decode")]
+use parity_scale_codec::Input;
+#[cfg(feature = "decode")]
+use serde::de::DeserializeOwned;
+#[cfg(feature = "encode")]
+use serde::Serialize;
+
+/// Encoding of values implementing `Serialize` like [`Wrap`](crate::Wrap) does
+#[cfg(feature = "encode")]
+pub trait ScaleViaSerdeExt: Serialize {
+    /// Returns the SCALE encoding of `self`
+    ///
+    /// # Panics
+    /// Panics if the serializer returns an error (e.g. when attempting to serialize a floating
+    /// point number).
+    fn scale_encode(&self) -> Vec<u8> {
+        crate::encode(self)
+    }
+
+    /// Returns the SCALE encoding of `self`, or an error if `self` cannot be serialized
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::{prelude::*, Error};
+    ///
+    /// assert!(matches!(1u128.try_scale_encode(), Err(Error::Serialization(_))));
+    /// ```
+    fn try_scale_encode(&self) -> Result<Vec<u8>, Error> {
+        crate::try_encode(self)
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<T: Serialize + ?Sized> ScaleViaSerdeExt for T {}
+
+/// Decoding of values implementing `Deserialize` like [`Wrap`](crate::Wrap) does
+#[cfg(feature = "decode")]
+pub trait ScaleViaSerdeDecodeExt: DeserializeOwned + Sized {
+    /// Decodes a value from the beginning of `bytes`
+    ///
+    /// Like `Decode::decode`, the bytes following the decoded value are ignored.
+    fn scale_decode(bytes: &[u8]) -> Result<Self, Error> {
+        crate::decode(bytes)
+    }
+
+    /// Decodes a value from `input`
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::prelude::*;
+    ///
+    /// let mut input = &[1u8, 2][..];
+    /// assert_eq!(u8::scale_decode_from(&mut input)?, 1);
+    /// assert_eq!(input, [2]);
+    /// # Ok::<_, serde_scale_wrap::Error>(())
+    /// ```
+    fn scale_decode_from<I: Input>(input: &mut I) -> Result<Self, Error> {
+        deserialize_from(input)
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<T: DeserializeOwned> ScaleViaSerdeDecodeExt for T {}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::{ScaleViaSerdeDecodeExt, ScaleViaSerdeExt};
+    use crate::{Error, Wrap};
+    use alloc::{collections::BTreeMap, string::String, vec, vec::Vec};
+    use parity_scale_codec::Encode;
+
+    #[test]
+    fn values_encode_like_wrap() {
+        let map = vec![(1u8, String::from("a"))].into_iter().collect::<BTreeMap<_, _>>();
+        assert_eq!(map.scale_encode(), Wrap(&map).encode());
+        assert_eq!("abc".scale_encode(), Wrap("abc").encode());
+    }
+
+    #[test]
+    fn values_roundtrip() {
+        let value = (3u16, Some(vec![true, false]));
+        assert_eq!(<(u16, Option<Vec<bool>>)>::scale_decode(&value.scale_encode()).unwrap(), value);
+    }
+
+    #[test]
+    fn truncated_input_is_an_input_error() {
+        assert!(matches!(u32::scale_decode(&[1, 2]), Err(Error::Input(_))));
+    }
+
+    #[test]
+    fn invalid_input_is_a_deserialization_error() {
+        assert!(matches!(bool::scale_decode(&[2]), Err(Error::Deserialization(_))));
+    }
+}
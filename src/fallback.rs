@@ -0,0 +1,319 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Decoding enum variants unknown to the decoder into a fallback variant
+//!
+//! A peer running a newer version may send enum variants an older decoder does not know, failing
+//! to decode the whole message. An enum implementing [`UnknownVariantFallback`] and held by a
+//! field marked with `#[serde(with = "serde_scale_wrap::fallback")]` decodes such variants with
+//! [`UnknownVariantFallback::unknown`] instead, when decoding with
+//! [`DecodeOptions::unknown_variant_fallback`](crate::DecodeOptions::unknown_variant_fallback).
+//! Only that enum falls back, not the enums nested in it.
+//!
+//! SCALE does not record the length of the fields of a variant, so the decoder must be able to
+//! skip the fields of a variant it does not know. This requires every variant to hold a single
+//! [`LengthPrefixed`] field, which is encoded with its length and is thus skipped like a byte
+//! sequence. This applies to the variants added later too, as the fields of an unknown variant
+//! are assumed to be length-prefixed. Fields that are not length-prefixed are misread, and
+//! decoding then fails or yields garbage.
+//!
+//! ```rust
+//! use serde::{Deserialize, Serialize};
+//! use serde_scale_wrap::{DecodeOptions, Error, LengthPrefixed, UnknownVariantFallback};
+//!
+//! #[derive(Debug, Deserialize, PartialEq, Serialize)]
+//! enum Event {
+//!     Ping(LengthPrefixed<u32>),
+//!     Text(LengthPrefixed<String>),
+//!     #[serde(skip)]
+//!     Unknown(u32),
+//! }
+//!
+//! impl UnknownVariantFallback for Event {
+//!     fn unknown(index: u32) -> Self {
+//!         Event::Unknown(index)
+//!     }
+//! }
+//!
+//! #[derive(Debug, Deserialize, PartialEq, Serialize)]
+//! struct Message {
+//!     #[serde(with = "serde_scale_wrap::fallback")]
+//!     event: Event,
+//!     seq: u32,
+//! }
+//!
+//! // Variant #2 was added by a newer version and holds a length-prefixed `(u16, u16)`.
+//! let encoded = b"\x02\x10\x80\x02\xe0\x01\x07\x00\x00\x00";
+//! assert!(serde_scale_wrap::decode_all::<Message>(encoded).is_err());
+//! let options = DecodeOptions::new().unknown_variant_fallback(true);
+//! let message = options.decode_all::<Message>(encoded)?;
+//! assert_eq!(message, Message { event: Event::Unknown(2), seq: 7 });
+//! # Ok::<_, Error>(())
+//! ```
+//!
+//! In formats other than SCALE, fields marked with this module are serialized and deserialized
+//! as usual.
+
+#[cfg(feature = "encode")]
+use serde::{ser, Serialize, Serializer};
+#[cfg(feature = "decode")]
+use {
+    alloc::vec::Vec,
+    core::{fmt, marker::PhantomData},
+    serde::de::{self, Deserialize, DeserializeOwned, Deserializer, SeqAccess, Visitor},
+};
+
+/// Name of the newtype struct through which [`deserialize`] asks the deserializer of this crate
+/// to decode unknown variants with their fallback
+#[cfg(feature = "decode")]
+pub(crate) const NAME: &str = "$serde_scale_wrap::fallback";
+
+/// Enum with a variant to decode variants unknown to the decoder into
+///
+/// See the [module documentation](self).
+pub trait UnknownVariantFallback {
+    /// Returns the value standing for the unknown variant at `index`
+    fn unknown(index: u32) -> Self;
+}
+
+/// Field encoded as a byte sequence holding its SCALE encoding, i.e. prefixed with its length
+///
+/// The wrapped value is SCALE-encoded in every format, and decoded with the default options.
+///
+/// The length is part of the SCALE encoding of the enclosing value, so it is always compact-encoded
+/// like the length of a `Vec<u8>`, whatever `LengthPrefix` frames of the `frame` module and
+/// `tokio_codec::ScaleCodec` use.
+///
+/// ```rust
+/// use serde_scale_wrap::LengthPrefixed;
+///
+/// let encoded = serde_scale_wrap::encode(&LengthPrefixed((1u8, 2u16)));
+/// assert_eq!(encoded, [12, 1, 2, 0]);
+/// let decoded = serde_scale_wrap::decode_all::<LengthPrefixed<(u8, u16)>>(&encoded)?;
+/// assert_eq!(decoded.into_inner(), (1, 2));
+/// # Ok::<_, serde_scale_wrap::Error>(())
+/// ```
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct LengthPrefixed<T>(pub T);
+
+impl<T> LengthPrefixed<T> {
+    /// Returns the wrapped value
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for LengthPrefixed<T> {
+    fn from(value: T) -> Self {
+        LengthPrefixed(value)
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<T: Serialize> Serialize for LengthPrefixed<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let encoded = crate::try_encode(&self.0).map_err(ser::Error::custom)?;
+        serializer.serialize_bytes(&encoded)
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<'de, T: DeserializeOwned> Deserialize<'de> for LengthPrefixed<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_byte_buf(PayloadVisitor(PhantomData))
+    }
+}
+
+/// Visitor decoding a `LengthPrefixed<T>` from the bytes of the encoding of `T`
+#[cfg(feature = "decode")]
+struct PayloadVisitor<T>(PhantomData<T>);
+
+#[cfg(feature = "decode")]
+impl<'de, T: DeserializeOwned> Visitor<'de> for PayloadVisitor<T> {
+    type Value = LengthPrefixed<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a SCALE encoding as bytes")
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Self::Value, E> {
+        crate::decode_payload(bytes).map(LengthPrefixed).map_err(E::custom)
+    }
+
+    // Formats without a byte sequence type (e.g. JSON) serialize bytes as a sequence.
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        self.visit_bytes(&bytes)
+    }
+}
+
+/// Serializes `value` as usual
+#[cfg(feature = "encode")]
+pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Serialize + ?Sized,
+    S: Serializer,
+{
+    value.serialize(serializer)
+}
+
+/// Deserializes a `T`, decoding unknown variants with [`UnknownVariantFallback::unknown`] if
+/// enabled by the decode options
+#[cfg(feature = "decode")]
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: Deserialize<'de> + UnknownVariantFallback,
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_newtype_struct(NAME, FallbackVisitor(PhantomData))
+}
+
+/// Visitor of a `T`, or of the index of an unknown variant
+///
+/// The deserializer of this crate visits a sequence holding either the `T`, or nothing followed
+/// by the index. Other deserializers visit a newtype struct holding the `T`.
+#[cfg(feature = "decode")]
+struct FallbackVisitor<T>(PhantomData<T>);
+
+#[cfg(feature = "decode")]
+impl<'de, T> Visitor<'de> for FallbackVisitor<T>
+where
+    T: Deserialize<'de> + UnknownVariantFallback,
+{
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a value or the index of an unknown variant")
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(self, d: D) -> Result<T, D::Error> {
+        T::deserialize(d)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<T, A::Error> {
+        if let Some(value) = seq.next_element()? {
+            return Ok(value);
+        }
+        match seq.next_element()? {
+            Some(index) => Ok(T::unknown(index)),
+            None => Err(de::Error::invalid_length(0, &self)),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::{LengthPrefixed, UnknownVariantFallback};
+    use crate::{decode_all, encode, DecodeOptions, Error};
+    use alloc::{string::String, vec, vec::Vec};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    enum Event {
+        Ping(LengthPrefixed<u32>),
+        Text(LengthPrefixed<String>),
+        #[serde(skip)]
+        Unknown(u32),
+    }
+
+    impl UnknownVariantFallback for Event {
+        fn unknown(index: u32) -> Self {
+            Event::Unknown(index)
+        }
+    }
+
+    /// `Event` as extended by a newer version
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    enum NewerEvent {
+        Ping(LengthPrefixed<u32>),
+        Text(LengthPrefixed<String>),
+        Resize(LengthPrefixed<(u16, u16)>),
+        Batch(LengthPrefixed<Vec<NewerEvent>>),
+        Quit(LengthPrefixed<()>),
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Message {
+        #[serde(with = "super")]
+        event: Event,
+        seq: u32,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct NewerMessage {
+        event: NewerEvent,
+        seq: u32,
+    }
+
+    fn fallback() -> DecodeOptions {
+        DecodeOptions::new().unknown_variant_fallback(true)
+    }
+
+    fn sent(event: NewerEvent) -> Vec<u8> {
+        encode(&NewerMessage { event, seq: 0xdead_beef })
+    }
+
+    #[test]
+    fn unknown_variants_decode_into_the_fallback() {
+        let events = vec![
+            (NewerEvent::Resize(LengthPrefixed((640, 480))), 2),
+            (NewerEvent::Batch(LengthPrefixed(vec![NewerEvent::Quit(LengthPrefixed(()))])), 3),
+            (NewerEvent::Quit(LengthPrefixed(())), 4),
+        ];
+        for (event, index) in events {
+            let message = fallback().decode_all::<Message>(&sent(event)).unwrap();
+            assert_eq!(message, Message { event: Event::Unknown(index), seq: 0xdead_beef });
+        }
+    }
+
+    #[test]
+    fn known_variants_decode_as_usual() {
+        for options in [DecodeOptions::new(), fallback()] {
+            let message = options.decode_all::<Message>(&sent(NewerEvent::Ping(7.into()))).unwrap();
+            assert_eq!(message.event, Event::Ping(LengthPrefixed(7)));
+            let encoded = sent(NewerEvent::Text(LengthPrefixed("hi".into())));
+            let message = options.decode_all::<Message>(&encoded).unwrap();
+            assert_eq!(message.event, Event::Text(LengthPrefixed("hi".into())));
+            assert_eq!(message.seq, 0xdead_beef);
+        }
+    }
+
+    #[test]
+    fn unknown_variants_fail_without_the_option() {
+        let result = decode_all::<Message>(&sent(NewerEvent::Quit(LengthPrefixed(()))));
+        assert!(matches!(result, Err(Error::Deserialization(_))));
+    }
+
+    #[test]
+    fn nested_enums_do_not_fall_back() {
+        let encoded = encode(&(Some(NewerEvent::Quit(LengthPrefixed(()))), 1u8));
+        assert!(fallback().decode_all::<(Option<Event>, u8)>(&encoded).is_err());
+    }
+
+    #[test]
+    fn truncated_payloads_are_rejected() {
+        let mut encoded = sent(NewerEvent::Resize(LengthPrefixed((640, 480))));
+        encoded.truncate(4);
+        assert!(fallback().decode_all::<Message>(&encoded).is_err());
+    }
+
+    #[test]
+    fn length_prefixed_values_hold_their_encoding() {
+        let encoded = encode(&LengthPrefixed((1u8, vec![2u16])));
+        assert_eq!(encoded, [16, 1, 4, 2, 0]);
+        let decoded = decode_all::<LengthPrefixed<(u8, Vec<u16>)>>(&encoded).unwrap();
+        assert_eq!(decoded, LengthPrefixed((1, vec![2])));
+        assert!(decode_all::<LengthPrefixed<u32>>(&[12, 1, 2, 3]).is_err());
+        assert!(decode_all::<LengthPrefixed<u16>>(&[12, 1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn other_formats_are_unaffected() {
+        let message = Message { event: Event::Ping(LengthPrefixed(7)), seq: 1 };
+        let json = serde_json::to_string(&message).unwrap();
+        assert_eq!(json, r#"{"event":{"Ping":[7,0,0,0]},"seq":1}"#);
+        assert_eq!(serde_json::from_str::<Message>(&json).unwrap(), message);
+    }
+}
@@ -0,0 +1,216 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Byte and string key components encoded with a fixed width instead of a length prefix
+
+#[cfg(feature = "decode")]
+use alloc::{string::String, vec::Vec};
+#[cfg(feature = "decode")]
+use core::fmt;
+#[cfg(feature = "decode")]
+use serde::de::{self, Deserialize, Deserializer, SeqAccess, Unexpected, Visitor};
+#[cfg(feature = "encode")]
+use serde::ser::{self, Serialize, SerializeTuple, Serializer};
+
+/// String or byte sequence encoded as exactly `N` bytes, right-padded with zeros
+///
+/// `String` and `Vec<u8>` are normally encoded with a compact length prefix, so that in composite
+/// keys (e.g. the keys of double maps built with [`KeyBuilder`](crate::KeyBuilder)), the
+/// components following them start at offsets depending on their length, and keys sort by the
+/// length of the component first: `"b"` sorts before `"aa"` and `"a"` is not a byte prefix of
+/// `"ab"`. A `FixedWidthKey` has no length prefix and always takes `N` bytes, so that keys have a
+/// stable layout and sort like their components do.
+///
+/// ```rust
+/// use serde_scale_wrap::{encode, FixedWidthKey, KeyBuilder};
+///
+/// assert_eq!(encode("ab"), b"\x08ab");
+/// assert_eq!(encode(&FixedWidthKey::<_, 4>("ab")), b"ab\0\0");
+/// let key = KeyBuilder::new(b"Names").push(&FixedWidthKey::<_, 4>("ab")).push(&7u8).finish();
+/// assert_eq!(key, b"Namesab\0\0\x07");
+/// ```
+///
+/// Encoding fails with [`Error::Serialization`](crate::Error::Serialization) if the value is
+/// longer than `N` bytes, and panics through `Encode`, like other serialization errors do.
+///
+/// `FixedWidthKey<String, N>` and `FixedWidthKey<Vec<u8>, N>` decode from `N` bytes and remove
+/// the trailing zeros, so values ending with zeros do not roundtrip.
+///
+/// ```rust
+/// use serde_scale_wrap::{decode_all, FixedWidthKey};
+///
+/// let decoded = decode_all::<FixedWidthKey<String, 4>>(b"ab\0\0")?;
+/// assert_eq!(decoded.into_inner(), "ab");
+/// assert!(decode_all::<FixedWidthKey<String, 4>>(b"ab\0").is_err());
+/// # Ok::<_, serde_scale_wrap::Error>(())
+/// ```
+///
+/// Other formats serialize the value as a tuple of `N` bytes too.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct FixedWidthKey<T, const N: usize>(pub T);
+
+impl<T, const N: usize> FixedWidthKey<T, N> {
+    /// Returns the wrapped value
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T, const N: usize> From<T> for FixedWidthKey<T, N> {
+    fn from(value: T) -> Self {
+        FixedWidthKey(value)
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<T: AsRef<[u8]>, const N: usize> Serialize for FixedWidthKey<T, N> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let bytes = self.0.as_ref();
+        if bytes.len() > N {
+            return Err(ser::Error::custom(format_args!(
+                "Key component of {} byte(s) exceeds the fixed width of {} byte(s)",
+                bytes.len(),
+                N,
+            )));
+        }
+        // Tuples are encoded as their elements, without any length prefix.
+        let mut tuple = serializer.serialize_tuple(N)?;
+        for byte in bytes {
+            tuple.serialize_element(byte)?;
+        }
+        for _ in bytes.len()..N {
+            tuple.serialize_element(&0u8)?;
+        }
+        tuple.end()
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<'de, const N: usize> Deserialize<'de> for FixedWidthKey<Vec<u8>, N> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_tuple(N, PaddedVisitor::<N>).map(FixedWidthKey)
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<'de, const N: usize> Deserialize<'de> for FixedWidthKey<String, N> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = deserializer.deserialize_tuple(N, PaddedVisitor::<N>)?;
+        String::from_utf8(bytes).map(FixedWidthKey).map_err(|e| {
+            de::Error::invalid_value(Unexpected::Bytes(e.as_bytes()), &"a UTF-8 string")
+        })
+    }
+}
+
+/// Visitor of `N` bytes, returning them without their trailing zeros
+#[cfg(feature = "decode")]
+struct PaddedVisitor<const N: usize>;
+
+#[cfg(feature = "decode")]
+impl<'de, const N: usize> Visitor<'de> for PaddedVisitor<N> {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bytes right-padded with zeros", N)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+        let mut bytes = Vec::with_capacity(N);
+        while bytes.len() < N {
+            match seq.next_element()? {
+                Some(byte) => bytes.push(byte),
+                None => return Err(de::Error::invalid_length(bytes.len(), &self)),
+            }
+        }
+        let len = bytes.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+        bytes.truncate(len);
+        Ok(bytes)
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::FixedWidthKey;
+    use crate::{decode_all, encode, try_encode, Error, KeyBuilder};
+    use alloc::{
+        string::{String, ToString},
+        vec,
+        vec::Vec,
+    };
+
+    type Name = FixedWidthKey<String, 8>;
+
+    fn name(s: &str) -> Name {
+        FixedWidthKey(s.into())
+    }
+
+    #[test]
+    fn components_of_the_exact_width_are_encoded_as_is() {
+        assert_eq!(encode(&name("abcdefgh")), b"abcdefgh");
+        assert_eq!(decode_all::<Name>(b"abcdefgh").unwrap(), name("abcdefgh"));
+        let bytes = FixedWidthKey::<_, 3>(vec![1u8, 2, 3]);
+        assert_eq!(encode(&bytes), [1, 2, 3]);
+        assert_eq!(decode_all::<FixedWidthKey<Vec<u8>, 3>>(&[1, 2, 3]).unwrap(), bytes);
+    }
+
+    #[test]
+    fn shorter_components_are_padded_with_zeros() {
+        assert_eq!(encode(&name("alice")), b"alice\0\0\0");
+        assert_eq!(encode(&FixedWidthKey::<_, 4>(&b"\x01"[..])), [1, 0, 0, 0]);
+        assert_eq!(encode(&name("")), [0; 8]);
+        assert_eq!(decode_all::<Name>(b"alice\0\0\0").unwrap(), name("alice"));
+        assert_eq!(decode_all::<Name>(&[0; 8]).unwrap(), name(""));
+    }
+
+    #[test]
+    fn longer_components_are_rejected() {
+        let result = try_encode(&name("alexander"));
+        match result {
+            Err(e @ Error::Serialization(_)) => assert!(
+                e.to_string().contains("9 byte(s) exceeds the fixed width of 8 byte(s)"),
+                "{}",
+                e,
+            ),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        let result = KeyBuilder::new(b"p").try_push(&FixedWidthKey::<_, 1>("ab"));
+        assert!(matches!(result, Err(Error::Serialization(_))));
+    }
+
+    #[test]
+    fn truncated_and_invalid_components_are_rejected() {
+        assert!(matches!(decode_all::<Name>(b"alice"), Err(Error::Input(_))));
+        let result = decode_all::<Name>(b"al\xffce\0\0\0");
+        assert!(matches!(result, Err(Error::Deserialization(_))));
+    }
+
+    #[test]
+    fn composite_keys_roundtrip_through_the_key_builder() {
+        let key = KeyBuilder::new(b"Balances").push(&name("bob")).push(&7u32).finish();
+        assert_eq!(key, [&b"Balances"[..], b"bob\0\0\0\0\0", &7u32.to_le_bytes()].concat());
+        let (decoded, id) = decode_all::<(Name, u32)>(&key[b"Balances".len()..]).unwrap();
+        assert_eq!((decoded, id), (name("bob"), 7));
+    }
+
+    #[test]
+    fn composite_keys_sort_like_their_components() {
+        let key = |s: &str| KeyBuilder::new(b"p").push(&name(s)).push(&1u8).finish();
+        let mut keys = vec![key("b"), key("ab"), key("a"), key("ba")];
+        keys.sort();
+        assert_eq!(keys, [key("a"), key("ab"), key("b"), key("ba")]);
+        let prefix = KeyBuilder::new(b"p").push(&name("ab")).finish();
+        assert!(key("ab").starts_with(&prefix));
+        assert!(!key("a").starts_with(&prefix));
+        assert!(encode_unpadded("b") < encode_unpadded("ab"));
+    }
+
+    fn encode_unpadded(s: &str) -> Vec<u8> {
+        KeyBuilder::new(b"p").push(s).push(&1u8).finish()
+    }
+
+    #[test]
+    fn other_formats_roundtrip_padded_components() {
+        let json = serde_json::to_string(&FixedWidthKey::<_, 3>("a")).unwrap();
+        assert_eq!(json, "[97,0,0]");
+        assert_eq!(serde_json::from_str::<FixedWidthKey<String, 3>>(&json).unwrap().0, "a");
+    }
+}
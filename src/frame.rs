@@ -0,0 +1,429 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Blocking exchange of length-delimited frames over `std::io` streams
+//!
+//! Each value is sent as a frame made of its length followed by its SCALE encoding.
+//! [`FrameWriter`] writes frames to a `Write` and [`FrameReader`] reads them from a `Read`, e.g. a
+//! `TcpStream`. `FrameWriter` requires the `encode` feature and `FrameReader` the `decode` feature.
+//!
+//! The length is compact-encoded by default. Peers framing SCALE payloads with a 4-byte
+//! little-endian length instead are supported by the [`FixedU32`] [`LengthPrefix`], which only
+//! changes the prefix and never the encoding of the values.
+//!
+//! ```rust
+//! use serde_scale_wrap::frame::{FrameReader, FrameWriter};
+//!
+//! let mut writer = FrameWriter::new(Vec::new());
+//! writer.send(&(1u8, "ping"))?;
+//! writer.send(&(2u8, "pong"))?;
+//! let mut reader = FrameReader::new(&**writer.get_ref());
+//! assert_eq!(reader.recv::<(u8, String)>()?, Some((1, "ping".to_owned())));
+//! assert_eq!(reader.recv::<(u8, String)>()?, Some((2, "pong".to_owned())));
+//! assert_eq!(reader.recv::<(u8, String)>()?, None);
+//! # Ok::<_, serde_scale_wrap::Error>(())
+//! ```
+//!
+//! Frames must be read with the prefix they were written with. Reading them with another one
+//! misreads their lengths, which usually fails with [`Error::FrameTooLarge`] (the closer the
+//! maximum frame length is to the actual one, the more likely), or with the error of the
+//! misaligned decoding, but is not guaranteed to:
+//!
+//! ```rust
+//! use serde_scale_wrap::{
+//!     frame::{FixedU32, FrameReader, FrameWriter},
+//!     Error,
+//! };
+//!
+//! let mut writer = FrameWriter::with_length_prefix(Vec::new(), FixedU32, 1024);
+//! writer.send(&(7u32, "pong"))?;
+//! assert_eq!(writer.get_ref()[..4], [9, 0, 0, 0]);
+//! let mut reader = FrameReader::with_length_prefix(&**writer.get_ref(), FixedU32, 1024);
+//! assert_eq!(reader.recv::<(u32, String)>()?, Some((7, "pong".to_owned())));
+//!
+//! let mut writer = FrameWriter::new(Vec::new());
+//! writer.send(&(7u32, "pong"))?;
+//! let mut reader = FrameReader::with_length_prefix(&**writer.get_ref(), FixedU32, 1024);
+//! let result = reader.recv::<(u32, String)>();
+//! assert!(matches!(result, Err(Error::FrameTooLarge { len: 1828, max: 1024 })));
+//! # Ok::<_, Error>(())
+//! ```
+
+use crate::Error;
+use core::fmt::Debug;
+use parity_scale_codec::{Decode, Encode, Output};
+
+#[cfg(feature = "decode")]
+use crate::decode_all;
+#[cfg(feature = "encode")]
+use crate::try_encode;
+#[cfg(feature = "encode")]
+use core::convert::TryFrom;
+#[cfg(feature = "decode")]
+use serde::de::DeserializeOwned;
+#[cfg(feature = "encode")]
+use serde::Serialize;
+#[cfg(feature = "decode")]
+use std::io::{ErrorKind, Read};
+use std::vec::Vec;
+#[cfg(feature = "encode")]
+use std::io::Write;
+
+/// Default maximum length of a frame, excluding its length prefix, matching `LengthDelimitedCodec`
+pub const DEFAULT_MAX_FRAME_LEN: usize = 8 * 1024 * 1024;
+
+/// Encoding of the length prefixed to each frame
+///
+/// The prefix only holds the length of the frame, i.e. of the SCALE encoding of its value, and
+/// does not affect that encoding.
+pub trait LengthPrefix: Copy + Debug {
+    /// Appends the prefix of a frame of `len` bytes to `dst`
+    fn write_len<O: Output + ?Sized>(self, len: u32, dst: &mut O);
+
+    /// Returns the length in bytes of the prefix starting with `first_byte`
+    fn prefix_len(self, first_byte: u8) -> usize;
+
+    /// Returns the length of the frame whose prefix is `prefix`, which is as long as
+    /// [`LengthPrefix::prefix_len`] says
+    fn read_len(self, prefix: &[u8]) -> Result<u32, Error>;
+}
+
+/// Length prefix compact-encoded like the length of a `Vec<u8>`, as used by default
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Compact;
+
+impl LengthPrefix for Compact {
+    fn write_len<O: Output + ?Sized>(self, len: u32, dst: &mut O) {
+        parity_scale_codec::Compact(len).encode_to(dst);
+    }
+
+    fn prefix_len(self, first_byte: u8) -> usize {
+        compact_len(first_byte)
+    }
+
+    fn read_len(self, prefix: &[u8]) -> Result<u32, Error> {
+        Ok(parity_scale_codec::Compact::<u32>::decode(&mut &*prefix)?.0)
+    }
+}
+
+/// Length prefix made of 4 little-endian bytes
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct FixedU32;
+
+impl LengthPrefix for FixedU32 {
+    fn write_len<O: Output + ?Sized>(self, len: u32, dst: &mut O) {
+        len.encode_to(dst);
+    }
+
+    fn prefix_len(self, _: u8) -> usize {
+        4
+    }
+
+    fn read_len(self, prefix: &[u8]) -> Result<u32, Error> {
+        Ok(u32::decode(&mut &*prefix)?)
+    }
+}
+
+/// Writer of length-delimited frames, whose lengths are encoded as `P`
+///
+/// Frames are written without flushing the underlying writer.
+#[cfg(feature = "encode")]
+#[derive(Debug)]
+pub struct FrameWriter<W, P = Compact> {
+    writer: W,
+    prefix: P,
+    max_frame_len: usize,
+}
+
+#[cfg(feature = "encode")]
+impl<W: Write> FrameWriter<W> {
+    /// Returns a frame writer to `writer`, rejecting frames longer than [`DEFAULT_MAX_FRAME_LEN`]
+    pub fn new(writer: W) -> Self {
+        FrameWriter::with_max_frame_len(writer, DEFAULT_MAX_FRAME_LEN)
+    }
+
+    /// Returns a frame writer to `writer`, rejecting frames longer than `max_frame_len` bytes
+    pub fn with_max_frame_len(writer: W, max_frame_len: usize) -> Self {
+        FrameWriter::with_length_prefix(writer, Compact, max_frame_len)
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<W: Write, P: LengthPrefix> FrameWriter<W, P> {
+    /// Returns a frame writer to `writer`, prefixing frames with their length encoded as `prefix`
+    /// and rejecting frames longer than `max_frame_len` bytes
+    pub fn with_length_prefix(writer: W, prefix: P, max_frame_len: usize) -> Self {
+        FrameWriter { writer, prefix, max_frame_len }
+    }
+
+    /// Writes a frame holding the encoding of `value`
+    ///
+    /// Nothing is written if `value` cannot be serialized or its encoding is too long.
+    pub fn send<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let encoded = try_encode(value)?;
+        let len = frame_len(encoded.len(), self.max_frame_len)?;
+        let mut prefix = Vec::new();
+        self.prefix.write_len(len, &mut prefix);
+        self.writer.write_all(&prefix)?;
+        self.writer.write_all(&encoded)?;
+        Ok(())
+    }
+
+    /// Returns the encoding of the length of frames
+    pub fn length_prefix(&self) -> P {
+        self.prefix
+    }
+
+    /// Flushes the underlying writer
+    pub fn flush(&mut self) -> Result<(), Error> {
+        Ok(self.writer.flush()?)
+    }
+
+    /// Returns a reference to the underlying writer
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Returns a mutable reference to the underlying writer
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Returns the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reader of length-delimited frames, whose lengths are encoded as `P`
+#[cfg(feature = "decode")]
+#[derive(Debug)]
+pub struct FrameReader<R, P = Compact> {
+    reader: R,
+    prefix: P,
+    max_frame_len: usize,
+    buffer: Vec<u8>,
+}
+
+#[cfg(feature = "decode")]
+impl<R: Read> FrameReader<R> {
+    /// Returns a frame reader from `reader`, rejecting frames longer than
+    /// [`DEFAULT_MAX_FRAME_LEN`]
+    pub fn new(reader: R) -> Self {
+        FrameReader::with_max_frame_len(reader, DEFAULT_MAX_FRAME_LEN)
+    }
+
+    /// Returns a frame reader from `reader`, rejecting frames longer than `max_frame_len` bytes
+    pub fn with_max_frame_len(reader: R, max_frame_len: usize) -> Self {
+        FrameReader::with_length_prefix(reader, Compact, max_frame_len)
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<R: Read, P: LengthPrefix> FrameReader<R, P> {
+    /// Returns a frame reader from `reader`, reading the length of frames encoded as `prefix` and
+    /// rejecting frames longer than `max_frame_len` bytes
+    pub fn with_length_prefix(reader: R, prefix: P, max_frame_len: usize) -> Self {
+        FrameReader { reader, prefix, max_frame_len, buffer: Vec::new() }
+    }
+
+    /// Reads a frame and decodes the value it holds, failing if bytes are left over in the frame
+    ///
+    /// Returns `None` if the stream ends before a frame starts. A stream ending in the middle of a
+    /// frame makes this return [`Error::Io`] with the `UnexpectedEof` kind. A frame longer than
+    /// the maximum length is rejected with [`Error::FrameTooLarge`] before being read.
+    pub fn recv<T: DeserializeOwned>(&mut self) -> Result<Option<T>, Error> {
+        let first_byte = match self.read_first_byte()? {
+            Some(b) => b,
+            None => return Ok(None),
+        };
+        self.buffer.clear();
+        self.buffer.resize(self.prefix.prefix_len(first_byte), first_byte);
+        self.reader.read_exact(&mut self.buffer[1..])?;
+        let len = self.prefix.read_len(&self.buffer)? as usize;
+        if len > self.max_frame_len {
+            return Err(Error::FrameTooLarge { len, max: self.max_frame_len });
+        }
+        self.buffer.clear();
+        self.buffer.resize(len, 0);
+        self.reader.read_exact(&mut self.buffer)?;
+        decode_all(&self.buffer).map(Some)
+    }
+
+    fn read_first_byte(&mut self) -> Result<Option<u8>, Error> {
+        let mut b = [0];
+        loop {
+            match self.reader.read(&mut b) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(b[0])),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Returns the encoding of the length of frames
+    pub fn length_prefix(&self) -> P {
+        self.prefix
+    }
+
+    /// Returns a reference to the underlying reader
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Returns a mutable reference to the underlying reader
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Returns the underlying reader
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Returns the length of a frame as its prefix encodes it, checking it against `max`
+#[cfg(feature = "encode")]
+pub(crate) fn frame_len(len: usize, max: usize) -> Result<u32, Error> {
+    let max = max.min(u32::MAX as usize);
+    u32::try_from(len)
+        .ok()
+        .filter(|&n| n as usize <= max)
+        .ok_or(Error::FrameTooLarge { len, max })
+}
+
+/// Returns the length of the compact encoding starting with `first_byte`
+fn compact_len(first_byte: u8) -> usize {
+    match first_byte & 0b11 {
+        0b00 => 1,
+        0b01 => 2,
+        0b10 => 4,
+        _ => 5 + usize::from(first_byte >> 2),
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::{Compact, FixedU32, FrameReader, FrameWriter, LengthPrefix};
+    use crate::Error;
+    use serde::{Deserialize, Serialize};
+    use std::{io::ErrorKind, string::String, vec, vec::Vec};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Request {
+        id: u32,
+        path: String,
+        body: Vec<u8>,
+    }
+
+    fn requests() -> Vec<Request> {
+        vec![
+            Request { id: 1, path: "/".into(), body: vec![] },
+            Request { id: 2, path: "/upload".into(), body: vec![0xaa; 100] },
+            Request { id: 3, path: "/large".into(), body: vec![0x55; 20_000] },
+        ]
+    }
+
+    fn sent(requests: &[Request]) -> Vec<u8> {
+        sent_with(requests, Compact)
+    }
+
+    fn sent_with<P: LengthPrefix>(requests: &[Request], prefix: P) -> Vec<u8> {
+        let mut writer = FrameWriter::with_length_prefix(Vec::new(), prefix, 1 << 20);
+        for request in requests {
+            writer.send(request).unwrap();
+        }
+        writer.flush().unwrap();
+        writer.into_inner()
+    }
+
+    #[test]
+    fn frames_are_received_in_order() {
+        let stream = sent(&requests());
+        let mut reader = FrameReader::new(&*stream);
+        for request in requests() {
+            assert_eq!(reader.recv::<Request>().unwrap(), Some(request));
+        }
+        assert_eq!(reader.recv::<Request>().unwrap(), None);
+    }
+
+    #[test]
+    fn frames_are_prefixed_with_their_compact_length() {
+        let stream = sent(&requests()[..1]);
+        assert_eq!(stream, [7 << 2, 1, 0, 0, 0, 4, b'/', 0]);
+    }
+
+    #[test]
+    fn oversized_frames_are_rejected() {
+        let mut writer = FrameWriter::with_max_frame_len(Vec::new(), 200);
+        let result = writer.send(&requests()[2]);
+        assert!(matches!(result, Err(Error::FrameTooLarge { len: 20_015, max: 200 })));
+        assert!(writer.get_ref().is_empty());
+        let stream = sent(&requests());
+        let mut reader = FrameReader::with_max_frame_len(&*stream, 200);
+        reader.recv::<Request>().unwrap();
+        reader.recv::<Request>().unwrap();
+        let result = reader.recv::<Request>();
+        assert!(matches!(result, Err(Error::FrameTooLarge { len: 20_015, max: 200 })));
+    }
+
+    #[test]
+    fn eof_in_the_middle_of_a_frame_fails() {
+        let stream = sent(&requests()[..2]);
+        for len in [1, 2, stream.len() - 1] {
+            let mut reader = FrameReader::new(&stream[..len]);
+            if len > 8 {
+                reader.recv::<Request>().unwrap();
+            }
+            match reader.recv::<Request>() {
+                Err(Error::Io(e)) => assert_eq!(e.kind(), ErrorKind::UnexpectedEof),
+                result => panic!("Unexpected result: {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn eof_between_frames_ends_the_stream() {
+        assert_eq!(FrameReader::new(&[][..]).recv::<u8>().unwrap(), None);
+        let stream = sent(&requests()[..1]);
+        let mut reader = FrameReader::new(&*stream);
+        assert!(reader.recv::<Request>().unwrap().is_some());
+        assert_eq!(reader.recv::<Request>().unwrap(), None);
+    }
+
+    #[test]
+    fn frames_longer_than_their_value_are_rejected() {
+        let mut reader = FrameReader::new(&[2 << 2, 1, 2][..]);
+        assert!(matches!(reader.recv::<u8>(), Err(Error::TrailingBytes { len: 1 })));
+    }
+
+    #[test]
+    fn fixed_length_frames_are_received_in_order() {
+        let stream = sent_with(&requests(), FixedU32);
+        assert_eq!(stream[..4], [7, 0, 0, 0]);
+        let mut reader = FrameReader::with_length_prefix(&*stream, FixedU32, 1 << 20);
+        for request in requests() {
+            assert_eq!(reader.recv::<Request>().unwrap(), Some(request));
+        }
+        assert_eq!(reader.recv::<Request>().unwrap(), None);
+    }
+
+    #[test]
+    fn fixed_length_prefixes_do_not_change_the_payload() {
+        let compact = sent(&requests()[1..2]);
+        let fixed = sent_with(&requests()[1..2], FixedU32);
+        assert_eq!(compact[2..], fixed[4..]);
+        assert_eq!(fixed[..4], (compact.len() as u32 - 2).to_le_bytes());
+    }
+
+    #[test]
+    fn mixing_prefixes_fails() {
+        let stream = sent(&requests());
+        let mut reader = FrameReader::with_length_prefix(&*stream, FixedU32, 200);
+        let result = reader.recv::<Request>();
+        assert!(matches!(result, Err(Error::FrameTooLarge { len: 284, max: 200 })));
+        let stream = sent_with(&requests(), FixedU32);
+        let mut reader = FrameReader::with_max_frame_len(&*stream, 200);
+        assert!(matches!(reader.recv::<Request>(), Err(Error::Input(_))));
+    }
+}
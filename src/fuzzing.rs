@@ -0,0 +1,55 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Harness functions for fuzz targets
+//!
+//! These functions are meant to be called from `cargo fuzz` targets, e.g.:
+//!
+//! ```rust,ignore
+//! #![no_main]
+//!
+//! libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+//!     serde_scale_wrap::fuzzing::fuzz_decode::<MyType>(data);
+//! });
+//! ```
+//!
+//! The `fuzz` directory of this crate's repository holds targets exercising them.
+
+use crate::{adapters::OutputToWrite, ser, EncodeOptions, Wrap};
+use alloc::vec::Vec;
+use arbitrary::{Arbitrary, Unstructured};
+use core::fmt::Debug;
+use parity_scale_codec::{Decode, DecodeAll, Error};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Decodes arbitrary bytes as a `T`
+///
+/// This must never panic nor abort, whatever the input. Failing to decode is fine and reported
+/// as an error.
+pub fn fuzz_decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, Error> {
+    let _ = Wrap::<T>::decode_all(data);
+    Wrap::<T>::decode(&mut &*data).map(|Wrap(x)| x)
+}
+
+/// Generates a `T` and checks that it roundtrips through `Wrap`
+///
+/// Values that cannot be serialized (e.g. because they hold floating point numbers) are skipped.
+///
+/// # Panics
+/// Panics if a serialized value fails to decode or decodes to a different value.
+pub fn fuzz_roundtrip<'a, T>(u: &mut Unstructured<'a>) -> arbitrary::Result<()>
+where
+    T: Arbitrary<'a> + Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    let value = T::arbitrary(u)?;
+    let mut encoded = Vec::new();
+    let mut serializer = crate::scale::Serializer::new(OutputToWrite::new(&mut encoded));
+    let options = EncodeOptions::default();
+    if ser::serialize(&value, &mut serializer, options, crate::Error::Serialization).is_err() {
+        return Ok(());
+    }
+    match Wrap::<T>::decode_all(&encoded) {
+        Ok(Wrap(decoded)) => assert_eq!(decoded, value, "Roundtrip changed the value"),
+        Err(e) => panic!("Failed to decode {:?}: {}", value, e),
+    }
+    Ok(())
+}
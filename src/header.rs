@@ -0,0 +1,101 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Decoding of typed headers followed by opaque bytes
+
+use crate::{deserialize_from, deserialize_from_slice, CountingInput, Error, Wrap};
+use parity_scale_codec::Input;
+use serde::de::DeserializeOwned;
+
+impl<T: DeserializeOwned> Wrap<T> {
+    /// Decodes a `T` from the beginning of `bytes`, and returns it with the bytes following it
+    ///
+    /// The remainder is returned as is, e.g. to hand an opaque payload to another decoder.
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::Wrap;
+    ///
+    /// let mut message = serde_scale_wrap::encode(&(7u8, "ping"));
+    /// message.extend_from_slice(b"payload");
+    /// let ((kind, name), payload) = Wrap::<(u8, String)>::decode_header(&message)?;
+    /// assert_eq!((kind, &*name, payload), (7, "ping", &b"payload"[..]));
+    /// # Ok::<_, serde_scale_wrap::Error>(())
+    /// ```
+    pub fn decode_header(bytes: &[u8]) -> Result<(T, &[u8]), Error> {
+        let mut rest = bytes;
+        let header = deserialize_from_slice(&mut rest)?;
+        Ok((header, rest))
+    }
+
+    /// Decodes a `T` from `input` like [`Wrap::decode_header`] does, and returns it with the
+    /// number of bytes read
+    ///
+    /// Only the bytes of the header are read, leaving `input` at the beginning of the following
+    /// bytes. If an error is returned, an unspecified number of bytes may have been read.
+    pub fn decode_header_from<I: Input + ?Sized>(input: &mut I) -> Result<(T, usize), Error> {
+        let mut input = CountingInput::new(input);
+        let header = deserialize_from(&mut input)?;
+        Ok((header, input.bytes_read()))
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use crate::{adapters::ReadToInput, encode, Error, Wrap};
+    use alloc::{string::String, vec::Vec};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Header {
+        version: u8,
+        topic: String,
+        len: u32,
+    }
+
+    fn header() -> Header {
+        Header { version: 2, topic: "blocks".into(), len: 5 }
+    }
+
+    fn message(blob: &[u8]) -> Vec<u8> {
+        [encode(&header()), blob.to_vec()].concat()
+    }
+
+    #[test]
+    fn remainder_is_the_blob() {
+        let blob = [0xff, 0, 0x04, 0x2a, 0];
+        let message = message(&blob);
+        let (decoded, rest) = Wrap::<Header>::decode_header(&message).unwrap();
+        assert_eq!(decoded, header());
+        assert_eq!(rest, blob);
+    }
+
+    #[test]
+    fn remainder_can_be_empty() {
+        let message = message(&[]);
+        let (decoded, rest) = Wrap::<Header>::decode_header(&message).unwrap();
+        assert_eq!(decoded, header());
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn inputs_report_the_header_length() {
+        let blob = b"opaque payload";
+        let message = message(blob);
+        let mut input = &message[..];
+        let (decoded, len) = Wrap::<Header>::decode_header_from(&mut input).unwrap();
+        assert_eq!((decoded, len), (header(), message.len() - blob.len()));
+        assert_eq!(input, blob);
+        let mut input = ReadToInput::new(&message[..]);
+        let (decoded, len) = Wrap::<Header>::decode_header_from(&mut input).unwrap();
+        assert_eq!((decoded, len), (header(), message.len() - blob.len()));
+        assert_eq!(input.into_inner(), blob);
+    }
+
+    #[test]
+    fn truncated_headers_are_rejected() {
+        let message = message(&[]);
+        let result = Wrap::<Header>::decode_header(&message[..message.len() - 1]);
+        assert!(matches!(result, Err(Error::Input(_))));
+        let result = Wrap::<Header>::decode_header_from(&mut &message[..3]);
+        assert!(matches!(result, Err(Error::Input(_))));
+    }
+}
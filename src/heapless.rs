@@ -0,0 +1,176 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Encoding into `heapless` vectors without panicking when they are full
+//!
+//! `heapless::Vec<u8, N>` implements [`TryOutput`], so [`Wrap::try_encode_to`] returns
+//! [`Error::CapacityExceeded`] when it runs out of capacity. [`VecOutput`] adapts it to `Output`
+//! for use with any `Encode` implementation.
+//!
+//! `heapless` containers like `String<N>` and `Vec<T, N>` encode like their `alloc` counterparts.
+//! Decoding an encoding longer than their capacity fails with [`Error::Deserialization`].
+//!
+//! ```rust
+//! use core::convert::TryFrom;
+//! use heapless::{String, Vec};
+//! use serde_scale_wrap::{Error, Wrap};
+//!
+//! let name = String::<8>::try_from("foo").unwrap();
+//! let mut encoded = Vec::<u8, 8>::new();
+//! Wrap(&name).try_encode_to(&mut encoded)?;
+//! assert_eq!(encoded, b"\x0cfoo");
+//! let Wrap(decoded) = Wrap::<String<8>>::decode_with_scratch(&mut &*encoded, &mut [0; 8])?;
+//! assert_eq!(decoded, name);
+//!
+//! let mut small = Vec::<u8, 2>::new();
+//! let result = Wrap(&name).try_encode_to(&mut small);
+//! assert!(matches!(result, Err(Error::CapacityExceeded { capacity: 2 })));
+//! # Ok::<_, Error>(())
+//! ```
+
+#[cfg(doc)]
+use crate::Wrap;
+use crate::{Error, TryOutput};
+use heapless::Vec;
+use parity_scale_codec::Output;
+
+impl<const N: usize> TryOutput for Vec<u8, N> {
+    fn try_write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.extend_from_slice(bytes).map_err(|()| Error::CapacityExceeded { capacity: N })
+    }
+}
+
+/// `Output` writing to a `heapless::Vec`, remembering if it ran out of capacity
+///
+/// `Output::write` cannot fail, so once a write does not fit, it and all following writes are
+/// dropped and [`VecOutput::finish`] returns an error.
+///
+/// ```rust
+/// use heapless::Vec;
+/// use parity_scale_codec::Encode;
+/// use serde_scale_wrap::{heapless::VecOutput, Error};
+///
+/// let mut encoded = Vec::<u8, 4>::new();
+/// let mut output = VecOutput::new(&mut encoded);
+/// 7u32.encode_to(&mut output);
+/// output.finish()?;
+/// assert_eq!(encoded, [7, 0, 0, 0]);
+///
+/// let mut output = VecOutput::new(&mut encoded);
+/// 7u8.encode_to(&mut output);
+/// assert!(matches!(output.finish(), Err(Error::CapacityExceeded { capacity: 4 })));
+/// # Ok::<_, Error>(())
+/// ```
+#[derive(Debug)]
+pub struct VecOutput<'a, const N: usize> {
+    vec: &'a mut Vec<u8, N>,
+    exhausted: bool,
+}
+
+impl<'a, const N: usize> VecOutput<'a, N> {
+    /// Returns an `Output` appending to `vec`
+    pub fn new(vec: &'a mut Vec<u8, N>) -> Self {
+        VecOutput { vec, exhausted: false }
+    }
+
+    /// Returns whether a write did not fit in the vector
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted
+    }
+
+    /// Returns an error if a write did not fit in the vector
+    pub fn finish(self) -> Result<(), Error> {
+        if self.exhausted {
+            Err(Error::CapacityExceeded { capacity: N })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<const N: usize> Output for VecOutput<'_, N> {
+    fn write(&mut self, bytes: &[u8]) {
+        if !self.exhausted && self.vec.extend_from_slice(bytes).is_err() {
+            self.exhausted = true;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::VecOutput;
+    use crate::{Error, Wrap};
+    use core::convert::TryFrom;
+    use heapless::{String, Vec};
+    use parity_scale_codec::Encode;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Reading {
+        id: u16,
+        label: String<16>,
+        samples: Vec<u8, 4>,
+    }
+
+    fn reading() -> Reading {
+        Reading {
+            id: 3,
+            label: String::try_from("thermometer").unwrap(),
+            samples: Vec::from_slice(&[20, 21, 19]).unwrap(),
+        }
+    }
+
+    #[test]
+    fn struct_with_heapless_string_roundtrips() {
+        let mut encoded = Vec::<u8, 32>::new();
+        Wrap(reading()).try_encode_to(&mut encoded).unwrap();
+        let mut input = &*encoded;
+        let Wrap(decoded) = Wrap::<Reading>::decode_with_scratch(&mut input, &mut [0; 16]).unwrap();
+        assert_eq!(decoded, reading());
+        assert!(input.is_empty());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn heapless_containers_encode_like_alloc_ones() {
+        use alloc::{string::String as AllocString, vec};
+
+        let mut encoded = Vec::<u8, 32>::new();
+        Wrap(reading()).try_encode_to(&mut encoded).unwrap();
+        let expected = crate::encode(&(3u16, AllocString::from("thermometer"), vec![20u8, 21, 19]));
+        assert_eq!(encoded, *expected);
+    }
+
+    #[test]
+    fn string_longer_than_capacity_fails_to_decode() {
+        let mut encoded = Vec::<u8, 32>::new();
+        Wrap("seventeen letters").try_encode_to(&mut encoded).unwrap();
+        let result = Wrap::<String<16>>::decode_with_scratch(&mut &*encoded, &mut [0; 32]);
+        assert!(matches!(result, Err(Error::Deserialization(_))));
+    }
+
+    #[test]
+    fn vec_longer_than_capacity_fails_to_decode() {
+        let mut encoded = Vec::<u8, 32>::new();
+        Wrap(&[1u8, 2, 3, 4, 5][..]).try_encode_to(&mut encoded).unwrap();
+        let result = Wrap::<Vec<u8, 4>>::decode_with_scratch(&mut &*encoded, &mut []);
+        assert!(matches!(result, Err(Error::Deserialization(_))));
+    }
+
+    #[test]
+    fn encoding_stops_when_capacity_is_exhausted() {
+        let mut encoded = Vec::<u8, 4>::new();
+        let result = Wrap((1u32, 2u8)).try_encode_to(&mut encoded);
+        assert!(matches!(result, Err(Error::CapacityExceeded { capacity: 4 })));
+        assert_eq!(encoded, [1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn output_drops_writes_after_exhaustion() {
+        let mut encoded = Vec::<u8, 4>::new();
+        let mut output = VecOutput::new(&mut encoded);
+        (1u16, 2u32, 3u8).encode_to(&mut output);
+        assert!(output.is_exhausted());
+        assert!(matches!(output.finish(), Err(Error::CapacityExceeded { capacity: 4 })));
+        assert_eq!(encoded, [1, 0]);
+    }
+}
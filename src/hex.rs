@@ -0,0 +1,227 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Encoding to and decoding from 0x-prefixed hexadecimal strings
+
+use crate::Wrap;
+
+#[cfg(feature = "decode")]
+use crate::{decode_all, Error};
+#[cfg(feature = "encode")]
+use crate::{encode, logging, try_serialize_to};
+#[cfg(feature = "encode")]
+use alloc::string::String;
+#[cfg(feature = "decode")]
+use alloc::vec::Vec;
+#[cfg(feature = "encode")]
+use core::fmt::{self, Display};
+#[cfg(feature = "encode")]
+use parity_scale_codec::Output;
+#[cfg(feature = "decode")]
+use serde::de::DeserializeOwned;
+#[cfg(feature = "encode")]
+use serde::Serialize;
+
+#[cfg(feature = "encode")]
+const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+impl<T> Wrap<T> {
+    /// Returns the SCALE encoding of the wrapped value as a lowercase 0x-prefixed hexadecimal
+    /// string
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::Wrap;
+    ///
+    /// assert_eq!(Wrap((1u8, 0xabcdu16)).encode_hex(), "0x01cdab");
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if the serializer returns an error.
+    #[cfg(feature = "encode")]
+    pub fn encode_hex(&self) -> String
+    where
+        T: Serialize,
+    {
+        let encoded = encode(&self.0);
+        let mut s = String::with_capacity(2 + 2 * encoded.len());
+        s.push_str("0x");
+        s.push_str(&::hex::encode(encoded));
+        s
+    }
+
+    /// Decodes a `Wrap` from a hexadecimal string, failing if bytes are left over
+    ///
+    /// The string may be prefixed with `0x` or `0X` and may mix lowercase and uppercase digits.
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::{Error, Wrap};
+    ///
+    /// assert_eq!(Wrap::<u16>::decode_hex("0xCDab")?, 0xabcd);
+    /// assert_eq!(Wrap::<u16>::decode_hex("cdab")?, 0xabcd);
+    /// assert_eq!(
+    ///     Wrap::<u16>::decode_hex("0xcdag").unwrap_err().to_string(),
+    ///     "Invalid hexadecimal: Invalid character 'g' at position 5",
+    /// );
+    /// # Ok::<_, Error>(())
+    /// ```
+    #[cfg(feature = "decode")]
+    pub fn decode_hex(s: &str) -> Result<Self, Error>
+    where
+        T: DeserializeOwned,
+    {
+        let (offset, digits) = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(digits) => (2, digits),
+            None => (0, s),
+        };
+        let bytes: Vec<u8> = ::hex::decode(digits).map_err(|e| match e {
+            ::hex::FromHexError::InvalidHexCharacter { c, index } => {
+                ::hex::FromHexError::InvalidHexCharacter { c, index: index + offset }
+            }
+            e => e,
+        })?;
+        decode_all(&bytes).map(Wrap)
+    }
+
+    /// Returns an adapter displaying the SCALE encoding of the wrapped value like
+    /// [`Wrap::encode_hex`], without building an intermediate string
+    ///
+    /// If the value cannot be serialized, the digits written so far are followed by
+    /// `<unserializable: {error}>` rather than failing, so that logging the value cannot panic.
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::Wrap;
+    ///
+    /// let wrapped = Wrap((1u8, 0xabcdu16));
+    /// assert_eq!(format!("Sending {}", wrapped.encoded_hex_display()), "Sending 0x01cdab");
+    /// ```
+    #[cfg(feature = "encode")]
+    pub fn encoded_hex_display(&self) -> impl Display + '_
+    where
+        T: Serialize,
+    {
+        HexDisplay(&self.0)
+    }
+}
+
+#[cfg(feature = "encode")]
+struct HexDisplay<'a, T>(&'a T);
+
+#[cfg(feature = "encode")]
+impl<T: Serialize> Display for HexDisplay<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("0x")?;
+        let mut dst = HexOutput { f, result: Ok(()) };
+        let encoded =
+            logging::encode_logged::<T, _, _>(&mut dst, |dst| try_serialize_to(self.0, dst));
+        dst.result?;
+        match encoded {
+            Ok(()) => Ok(()),
+            Err(e) => write!(f, "<unserializable: {}>", e),
+        }
+    }
+}
+
+/// `Output` writing bytes as hexadecimal digits to a formatter
+///
+/// `Output::write` cannot fail, so the first formatting error is kept and following writes are
+/// dropped.
+#[cfg(feature = "encode")]
+struct HexOutput<'a, 'b> {
+    f: &'a mut fmt::Formatter<'b>,
+    result: fmt::Result,
+}
+
+#[cfg(feature = "encode")]
+impl Output for HexOutput<'_, '_> {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            if self.result.is_err() {
+                return;
+            }
+            let digits = [DIGITS[usize::from(b >> 4)], DIGITS[usize::from(b & 0xf)]];
+            // The digits are ASCII.
+            self.result = self.f.write_str(core::str::from_utf8(&digits).unwrap());
+        }
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use crate::{Error, Wrap};
+    use alloc::{format, string::ToString, vec, vec::Vec};
+    use serde::{ser::Error as _, Serialize, Serializer};
+
+    #[test]
+    fn prefix_is_optional() {
+        assert_eq!(Wrap::<Vec<u8>>::decode_hex("0x0801ff").unwrap(), vec![1, 0xff]);
+        assert_eq!(Wrap::<Vec<u8>>::decode_hex("0801FF").unwrap(), vec![1, 0xff]);
+        assert_eq!(Wrap::<Vec<u8>>::decode_hex("0x0801Ff").unwrap(), vec![1, 0xff]);
+    }
+
+    #[test]
+    fn prefix_can_be_uppercase() {
+        assert_eq!(Wrap::<Vec<u8>>::decode_hex("0X0801fF").unwrap(), vec![1, 0xff]);
+    }
+
+    #[test]
+    fn prefix_can_only_appear_once() {
+        let e = Wrap::<u8>::decode_hex("0x0x01").unwrap_err();
+        assert_eq!(e.to_string(), "Invalid hexadecimal: Invalid character 'x' at position 3");
+    }
+
+    #[test]
+    fn empty_payloads_roundtrip() {
+        assert_eq!(Wrap(()).encode_hex(), "0x");
+        assert_eq!(Wrap(()).encoded_hex_display().to_string(), "0x");
+        Wrap::<()>::decode_hex("0x").unwrap();
+        Wrap::<()>::decode_hex("").unwrap();
+    }
+
+    #[test]
+    fn odd_length_is_rejected() {
+        let e = Wrap::<u8>::decode_hex("0x012").unwrap_err();
+        assert!(matches!(e, Error::Hex(::hex::FromHexError::OddLength)));
+        assert_eq!(e.to_string(), "Invalid hexadecimal: Odd number of digits");
+    }
+
+    #[test]
+    fn invalid_digit_position_includes_prefix() {
+        let e = Wrap::<u16>::decode_hex("0x01z2").unwrap_err();
+        assert!(matches!(
+            e,
+            Error::Hex(::hex::FromHexError::InvalidHexCharacter { c: 'z', index: 4 }),
+        ));
+    }
+
+    #[test]
+    fn trailing_bytes_are_rejected() {
+        let e = Wrap::<u8>::decode_hex("0x0102").unwrap_err();
+        assert!(matches!(e, Error::TrailingBytes { len: 1 }));
+    }
+
+    #[test]
+    fn display_matches_string() {
+        type Value = (u32, Vec<Option<bool>>, i16);
+
+        for wrapped in [Wrap((7, vec![Some(true), None], -2)), Wrap((0, vec![], 0))] {
+            assert_eq!(wrapped.encoded_hex_display().to_string(), wrapped.encode_hex());
+            assert_eq!(Wrap::<Value>::decode_hex(&wrapped.encode_hex()).unwrap(), wrapped);
+        }
+    }
+
+    struct Unserializable;
+
+    impl Serialize for Unserializable {
+        fn serialize<S: Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+            Err(S::Error::custom("no encoding"))
+        }
+    }
+
+    #[test]
+    fn display_shows_placeholder_for_unserializable_values() {
+        let s = Wrap((7u8, Unserializable)).encoded_hex_display().to_string();
+        assert!(s.starts_with("0x07<unserializable: "), "{}", s);
+        assert!(s.ends_with(">"), "{}", s);
+        let s = format!("{}", Wrap(1u128).encoded_hex_display());
+        assert!(s.starts_with("0x<unserializable: "), "{}", s);
+    }
+}
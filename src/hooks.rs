@@ -0,0 +1,228 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Hooks notified of encodings and decodings, e.g. to collect metrics
+//!
+//! The hook installed with [`set_global_hook`] is notified of every encoding and decoding done
+//! through `Wrap` and the functions of this crate. [`encode_with_hook`] and [`decode_with_hook`]
+//! notify the given hook instead. Checking for a global hook costs a single atomic load.
+//!
+//! ```rust
+//! use core::sync::atomic::{AtomicUsize, Ordering};
+//! use serde_scale_wrap::hooks::{set_global_hook, CodecHook};
+//!
+//! struct ByteCounter(AtomicUsize);
+//!
+//! impl CodecHook for ByteCounter {
+//!     fn on_encode(&self, _: &'static str, len: usize) {
+//!         self.0.fetch_add(len, Ordering::Relaxed);
+//!     }
+//! }
+//!
+//! static ENCODED: ByteCounter = ByteCounter(AtomicUsize::new(0));
+//!
+//! set_global_hook(&ENCODED).unwrap();
+//! serde_scale_wrap::encode(&(1u8, 2u32));
+//! assert_eq!(ENCODED.0.load(Ordering::Relaxed), 5);
+//! ```
+
+use crate::Error;
+#[cfg(feature = "alloc")]
+use crate::logging::{self, Observer};
+#[cfg(all(feature = "alloc", feature = "encode"))]
+use crate::try_serialize_to;
+#[cfg(all(feature = "alloc", feature = "encode"))]
+use alloc::vec::Vec;
+use core::fmt::{self, Display};
+#[cfg(all(feature = "alloc", feature = "decode"))]
+use serde::de::DeserializeOwned;
+#[cfg(all(feature = "alloc", feature = "encode"))]
+use serde::Serialize;
+use spin::Once;
+
+/// Receiver of the outcome of encodings and decodings
+///
+/// `type_name` is the name of the encoded or decoded type as returned by
+/// `core::any::type_name`. All methods do nothing by default.
+pub trait CodecHook: Sync {
+    /// Called after `len` bytes were written encoding a value
+    fn on_encode(&self, type_name: &'static str, len: usize) {
+        let _ = (type_name, len);
+    }
+
+    /// Called after `len` bytes were read decoding a value
+    fn on_decode(&self, type_name: &'static str, len: usize) {
+        let _ = (type_name, len);
+    }
+
+    /// Called when encoding or decoding fails
+    fn on_error(&self, type_name: &'static str, error: &Error) {
+        let _ = (type_name, error);
+    }
+}
+
+static HOOK: Once<&'static dyn CodecHook> = Once::new();
+
+/// Installs the hook notified of all encodings and decodings
+///
+/// The global hook can only be installed once. Later calls return an error.
+pub fn set_global_hook(hook: &'static dyn CodecHook) -> Result<(), SetGlobalHookError> {
+    let mut installed = false;
+    HOOK.call_once(|| {
+        installed = true;
+        hook
+    });
+    if installed {
+        Ok(())
+    } else {
+        Err(SetGlobalHookError(()))
+    }
+}
+
+/// Returns the global hook, if one is installed
+#[inline]
+pub(crate) fn global_hook() -> Option<&'static dyn CodecHook> {
+    HOOK.get().copied()
+}
+
+/// Error returned when a global hook is already installed
+#[derive(Debug)]
+pub struct SetGlobalHookError(());
+
+impl Display for SetGlobalHookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("A global codec hook is already installed")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SetGlobalHookError {}
+
+/// Returns the SCALE encoding of `value` like [`encode`](crate::encode), notifying `hook` instead
+/// of the global hook
+///
+/// # Panics
+/// Panics if the serializer returns an error.
+#[cfg(all(feature = "alloc", feature = "encode"))]
+pub fn encode_with_hook<T: Serialize + ?Sized>(value: &T, hook: &dyn CodecHook) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    let observer = Observer::with_hook(hook);
+    let result = logging::encode_observed::<T, _, _>(observer, &mut encoded, |dst| {
+        try_serialize_to(value, dst)
+    });
+    if let Err(e) = result {
+        panic!("{}", e);
+    }
+    encoded
+}
+
+/// Decodes a `T` from the beginning of `bytes` like [`decode`](crate::decode), notifying `hook`
+/// instead of the global hook
+#[cfg(all(feature = "alloc", feature = "decode"))]
+pub fn decode_with_hook<T: DeserializeOwned>(
+    bytes: &[u8],
+    hook: &dyn CodecHook,
+) -> Result<T, Error> {
+    let observer = Observer::with_hook(hook);
+    logging::decode_observed(observer, &mut &*bytes, |input| {
+        crate::deserialize_from_unlogged(input)
+    })
+}
+
+#[cfg(all(test, feature = "alloc", feature = "encode", feature = "decode"))]
+mod tests {
+    use super::CodecHook;
+    use crate::{Error, Wrap};
+    use alloc::vec::Vec;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use parity_scale_codec::{Decode, Encode};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Probe {
+        id: u16,
+        flag: bool,
+    }
+
+    #[derive(Default)]
+    struct Counter {
+        encoded: AtomicUsize,
+        encoded_bytes: AtomicUsize,
+        decoded: AtomicUsize,
+        decoded_bytes: AtomicUsize,
+        errors: AtomicUsize,
+    }
+
+    impl Counter {
+        const fn new() -> Self {
+            Counter {
+                encoded: AtomicUsize::new(0),
+                encoded_bytes: AtomicUsize::new(0),
+                decoded: AtomicUsize::new(0),
+                decoded_bytes: AtomicUsize::new(0),
+                errors: AtomicUsize::new(0),
+            }
+        }
+
+        fn counts(&self) -> [usize; 5] {
+            [
+                self.encoded.load(Ordering::SeqCst),
+                self.encoded_bytes.load(Ordering::SeqCst),
+                self.decoded.load(Ordering::SeqCst),
+                self.decoded_bytes.load(Ordering::SeqCst),
+                self.errors.load(Ordering::SeqCst),
+            ]
+        }
+    }
+
+    // Other tests encode and decode concurrently, so only `Probe` is counted.
+    fn is_probe(type_name: &str) -> bool {
+        type_name == core::any::type_name::<Probe>()
+    }
+
+    impl CodecHook for Counter {
+        fn on_encode(&self, type_name: &'static str, len: usize) {
+            if is_probe(type_name) {
+                self.encoded.fetch_add(1, Ordering::SeqCst);
+                self.encoded_bytes.fetch_add(len, Ordering::SeqCst);
+            }
+        }
+
+        fn on_decode(&self, type_name: &'static str, len: usize) {
+            if is_probe(type_name) {
+                self.decoded.fetch_add(1, Ordering::SeqCst);
+                self.decoded_bytes.fetch_add(len, Ordering::SeqCst);
+            }
+        }
+
+        fn on_error(&self, type_name: &'static str, _: &Error) {
+            if is_probe(type_name) {
+                self.errors.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    static GLOBAL: Counter = Counter::new();
+
+    #[test]
+    fn global_hook_counts_operations() {
+        super::set_global_hook(&GLOBAL).unwrap();
+        assert!(super::set_global_hook(&GLOBAL).is_err());
+        let mut encoded = Vec::new();
+        Wrap(Probe { id: 1, flag: true }).encode_to(&mut encoded);
+        Wrap(Probe { id: 2, flag: false }).encode_to(&mut encoded);
+        let mut input = &*encoded;
+        Wrap::<Probe>::decode(&mut input).unwrap();
+        assert!(Wrap::<Probe>::decode(&mut &[0, 0, 2][..]).is_err());
+        assert_eq!(GLOBAL.counts(), [2, 6, 1, 3, 1]);
+    }
+
+    #[test]
+    fn per_call_hook_counts_operations() {
+        let counter = Counter::default();
+        let encoded = super::encode_with_hook(&Probe { id: 3, flag: true }, &counter);
+        let decoded = super::decode_with_hook::<Probe>(&encoded, &counter).unwrap();
+        assert_eq!(decoded, Probe { id: 3, flag: true });
+        assert!(super::decode_with_hook::<Probe>(&encoded[..2], &counter).is_err());
+        assert_eq!(counter.counts(), [1, 3, 1, 3, 1]);
+    }
+}
@@ -0,0 +1,189 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Support for `indexmap` maps and sets, encoded in insertion order
+//!
+//! `IndexMap` and `IndexSet` encode like `BTreeMap` and `BTreeSet` do, as a compact length
+//! followed by their entries, but in insertion order instead of sorted order. Decoding inserts the
+//! entries in the order they were encoded, so that a decoded map iterates like the encoded one and
+//! encodes back to the same bytes. Unlike `HashMap`, whose order depends on its hasher, the
+//! encoding of an `IndexMap` is thus deterministic, but maps with the same entries inserted in
+//! different orders have different encodings. [`canonical`] encodes maps with their entries sorted
+//! by the encodings of their keys, so that they only depend on the entries.
+//!
+//! `Wrap<IndexMap<K, V, S>>` and `Wrap<IndexSet<T, S>>` implement `DecodeLength`, reading the
+//! number of entries from an encoding without decoding them.
+//!
+//! ```rust
+//! use indexmap::IndexMap;
+//! use parity_scale_codec::DecodeLength;
+//! use serde_scale_wrap::Wrap;
+//!
+//! let map = IndexMap::from([("b", 2u8), ("a", 1)]);
+//! let encoded = serde_scale_wrap::encode(&map);
+//! assert_eq!(encoded, b"\x08\x04b\x02\x04a\x01");
+//! assert_eq!(<Wrap<IndexMap<String, u8>>>::len(&encoded)?, 2);
+//! let decoded = serde_scale_wrap::decode::<IndexMap<String, u8>>(&encoded)?;
+//! assert_eq!(decoded.keys().collect::<Vec<_>>(), ["b", "a"]);
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+#[cfg(feature = "decode")]
+use crate::{read_compact_len, Error, Wrap};
+#[cfg(feature = "decode")]
+use core::convert::TryFrom;
+#[cfg(feature = "decode")]
+use indexmap::{IndexMap, IndexSet};
+#[cfg(feature = "decode")]
+use parity_scale_codec::DecodeLength;
+
+#[cfg(feature = "decode")]
+impl<K, V, S> DecodeLength for Wrap<IndexMap<K, V, S>> {
+    fn len(self_encoded: &[u8]) -> Result<usize, parity_scale_codec::Error> {
+        decode_len(self_encoded)
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<T, S> DecodeLength for Wrap<IndexSet<T, S>> {
+    fn len(self_encoded: &[u8]) -> Result<usize, parity_scale_codec::Error> {
+        decode_len(self_encoded)
+    }
+}
+
+/// Reads the compact length prefixed to an encoding
+#[cfg(feature = "decode")]
+fn decode_len(mut encoded: &[u8]) -> Result<usize, parity_scale_codec::Error> {
+    let len = read_compact_len(&mut encoded)?;
+    usize::try_from(len).map_err(|_| {
+        Error::Deserialization(crate::scale::Error::CollectionTooLargeToDeserialize).into()
+    })
+}
+
+/// `IndexMap` encoded with its entries sorted by the encodings of their keys, for use with
+/// `#[serde(with)]`
+///
+/// Keys are sorted by their SCALE encodings even when serializing to another format. Decoding
+/// keeps the entries in the order they were encoded, i.e. sorted if they were encoded by this
+/// module.
+///
+/// ```rust
+/// use indexmap::IndexMap;
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Deserialize, Serialize)]
+/// struct Balances {
+///     #[serde(with = "serde_scale_wrap::indexmap::canonical")]
+///     accounts: IndexMap<u16, u64>,
+/// }
+///
+/// let first = Balances { accounts: IndexMap::from([(1, 10), (256, 20)]) };
+/// let second = Balances { accounts: IndexMap::from([(256, 20), (1, 10)]) };
+/// let encoded = serde_scale_wrap::encode(&first);
+/// assert_eq!(encoded, serde_scale_wrap::encode(&second));
+/// let decoded = serde_scale_wrap::decode::<Balances>(&encoded)?;
+/// assert_eq!(decoded.accounts.keys().collect::<Vec<_>>(), [&256, &1]);
+/// # Ok::<_, serde_scale_wrap::Error>(())
+/// ```
+pub mod canonical {
+    #[cfg(feature = "decode")]
+    use core::hash::{BuildHasher, Hash};
+    use indexmap::IndexMap;
+    #[cfg(feature = "decode")]
+    use serde::{Deserialize, Deserializer};
+
+    #[cfg(feature = "encode")]
+    use crate::try_serialize_to;
+    #[cfg(feature = "encode")]
+    use alloc::vec::Vec;
+    #[cfg(feature = "encode")]
+    use serde::{ser::Error as _, Serialize, Serializer};
+
+    /// Serializes the entries of `map` sorted by the encodings of their keys
+    #[cfg(feature = "encode")]
+    pub fn serialize<K, V, H, S>(map: &IndexMap<K, V, H>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        K: Serialize,
+        V: Serialize,
+        S: Serializer,
+    {
+        let mut entries = map
+            .iter()
+            .map(|(key, value)| {
+                let mut encoded = Vec::new();
+                try_serialize_to(key, &mut encoded).map(|()| (encoded, key, value))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(S::Error::custom)?;
+        entries.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+        serializer.collect_map(entries.into_iter().map(|(_, key, value)| (key, value)))
+    }
+
+    /// Deserializes the entries of a map in the order they were encoded
+    #[cfg(feature = "decode")]
+    pub fn deserialize<'de, K, V, H, D>(deserializer: D) -> Result<IndexMap<K, V, H>, D::Error>
+    where
+        K: Deserialize<'de> + Eq + Hash,
+        V: Deserialize<'de>,
+        H: BuildHasher + Default,
+        D: Deserializer<'de>,
+    {
+        IndexMap::deserialize(deserializer)
+    }
+}
+
+#[cfg(all(test, feature = "std", feature = "encode", feature = "decode"))]
+mod tests {
+    use crate::{decode_all, encode, Wrap};
+    use alloc::{string::String, vec, vec::Vec};
+    use indexmap::{IndexMap, IndexSet};
+    use parity_scale_codec::{DecodeLength, Encode};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Deserialize, Serialize)]
+    struct Canonical {
+        #[serde(with = "super::canonical")]
+        map: IndexMap<u32, String>,
+    }
+
+    fn entries() -> Vec<(u32, String)> {
+        vec![(3, "c".into()), (1, "a".into()), (256, "d".into()), (2, "b".into())]
+    }
+
+    #[test]
+    fn maps_encode_their_entries_in_insertion_order() {
+        let map = entries().into_iter().collect::<IndexMap<_, _>>();
+        let expected = entries().encode();
+        assert_eq!(encode(&map), expected);
+        let decoded = decode_all::<IndexMap<u32, String>>(&expected).unwrap();
+        assert_eq!(decoded.into_iter().collect::<Vec<_>>(), entries());
+    }
+
+    #[test]
+    fn sets_encode_their_elements_in_insertion_order() {
+        let set = IndexSet::from([5u8, 1, 3]);
+        assert_eq!(encode(&set), vec![5u8, 1, 3].encode());
+        let decoded = decode_all::<IndexSet<u8>>(&encode(&set)).unwrap();
+        assert_eq!(decoded.into_iter().collect::<Vec<_>>(), [5, 1, 3]);
+    }
+
+    #[test]
+    fn lengths_are_decoded_without_decoding_entries() {
+        let map = entries().into_iter().collect::<IndexMap<_, _>>();
+        assert_eq!(<Wrap<IndexMap<u32, String>>>::len(&encode(&map)).unwrap(), 4);
+        let set = (0..100u8).collect::<IndexSet<_>>();
+        assert_eq!(<Wrap<IndexSet<u8>>>::len(&encode(&set)).unwrap(), 100);
+        assert!(<Wrap<IndexSet<u8>>>::len(&[]).is_err());
+    }
+
+    #[test]
+    fn insertion_order_matters_unless_canonical() {
+        let first = entries().into_iter().collect::<IndexMap<_, _>>();
+        let second = entries().into_iter().rev().collect::<IndexMap<_, _>>();
+        assert_eq!(first, second);
+        assert_ne!(encode(&first), encode(&second));
+        let first = encode(&Canonical { map: first });
+        assert_eq!(first, encode(&Canonical { map: second }));
+        let decoded = decode_all::<Canonical>(&first).unwrap();
+        assert_eq!(decoded.map.keys().collect::<Vec<_>>(), [&256, &1, &2, &3]);
+    }
+}
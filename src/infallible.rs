@@ -0,0 +1,259 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Encoding of types whose serialization cannot fail
+//!
+//! Serializing through `serde-scale` fails for floating point numbers, 128-bit integers, enums
+//! with more than 256 variants, sequences of unknown length and `Serialize` implementations
+//! returning custom errors. `Encode::encode_to` panics in these cases.
+//!
+//! [`InfallibleSerialize`] marks types whose serialization cannot fail, and
+//! [`Wrap::encode_infallible_to`] and `encode_infallible` only accept such types, so that call
+//! sites are checked by the compiler not to reach the panic.
+//!
+//! ```rust
+//! use serde::Serialize;
+//! use serde_scale_wrap::{impl_infallible_serialize, Wrap};
+//!
+//! #[derive(Serialize)]
+//! struct Account {
+//!     id: u32,
+//!     name: String,
+//!     tags: Vec<(u8, Option<String>)>,
+//! }
+//!
+//! impl_infallible_serialize!(Account { u32, String, Vec<(u8, Option<String>)> });
+//!
+//! let account = Account { id: 1, name: "alice".into(), tags: Vec::new() };
+//! let mut encoded = Vec::new();
+//! Wrap(&account).encode_infallible_to(&mut encoded);
+//! assert_eq!(encoded, serde_scale_wrap::encode_infallible(&account));
+//! ```
+//!
+//! Types holding floating point numbers cannot be marked:
+//!
+//! ```rust,compile_fail
+//! use serde::Serialize;
+//! use serde_scale_wrap::impl_infallible_serialize;
+//!
+//! #[derive(Serialize)]
+//! struct Measure {
+//!     value: f64,
+//! }
+//!
+//! impl_infallible_serialize!(Measure { f64 });
+//! ```
+//!
+//! With the `deny-panicking-encode` feature, `Encode` for `Wrap<T>` also requires
+//! `T: InfallibleSerialize`, so that any call to `Encode` methods is known not to panic. Types
+//! that cannot be marked can still be encoded with the fallible functions and methods, e.g.
+//! [`try_encode`](crate::try_encode), but `Wrap` no longer satisfies `Encode` bounds for them,
+//! e.g. of `substrate` storage items. The free function [`encode`](crate::encode) and
+//! [`Wrap::to_bytes`] still accept any `Serialize` type.
+//!
+//! Marked types implement `Encode` in all configurations:
+//!
+//! ```rust
+//! use parity_scale_codec::Encode;
+//! use serde_scale_wrap::Wrap;
+//!
+//! assert_eq!(Wrap((7u8, String::from("a"))).encode(), b"\x07\x04a");
+//! ```
+//!
+//! Other types only implement it without the `deny-panicking-encode` feature, which is the only
+//! feature changing the bounds of `Encode` for `Wrap`:
+//!
+#![cfg_attr(feature = "deny-panicking-encode", doc = "```rust,compile_fail")]
+#![cfg_attr(not(feature = "deny-panicking-encode"), doc = "```rust,no_run")]
+//! use parity_scale_codec::Encode;
+//! use serde_scale_wrap::Wrap;
+//!
+//! Wrap(1.5f32).encode();
+//! ```
+//!
+//! The fallible functions accept them in all configurations:
+//!
+//! ```rust
+//! assert!(serde_scale_wrap::try_encode(&(1u8, 2u128)).is_err());
+//! ```
+
+use crate::{logging, try_serialize_to, Wrap};
+#[cfg(feature = "alloc")]
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    string::String,
+    vec::Vec,
+};
+use core::marker::PhantomData;
+use parity_scale_codec::Output;
+use serde::Serialize;
+
+/// Types whose serialization through `serde-scale` cannot fail
+///
+/// Implementations promise that `Serialize::serialize` never returns an error when used by
+/// [`Wrap`]. Only implement this trait for types whose serialized fields all implement it, that
+/// have at most 256 variants if they are enums, and that do not customize their serialization with
+/// fallible code (e.g. with `#[serde(serialize_with = "...")]`).
+/// [`impl_infallible_serialize!`](crate::impl_infallible_serialize) checks the field types.
+///
+/// Breaking this promise does not cause undefined behavior but makes the infallible encoding
+/// functions panic.
+pub trait InfallibleSerialize: Serialize {}
+
+/// Implements [`InfallibleSerialize`](crate::infallible::InfallibleSerialize) for a type,
+/// checking that the listed types of its fields implement it
+///
+/// All the types of the serialized fields must be listed, including those of all the variants of
+/// an enum.
+///
+/// ```rust
+/// use serde::Serialize;
+/// use serde_scale_wrap::impl_infallible_serialize;
+///
+/// #[derive(Serialize)]
+/// enum Shape {
+///     Point,
+///     Circle { radius: u32 },
+///     Polygon(Vec<(i32, i32)>),
+/// }
+///
+/// impl_infallible_serialize!(Shape { u32, Vec<(i32, i32)> });
+/// ```
+#[macro_export]
+macro_rules! impl_infallible_serialize {
+    ($t:ty { $($field:ty),* $(,)? }) => {
+        impl $crate::infallible::InfallibleSerialize for $t {}
+
+        const _: fn() = || {
+            fn assert_infallible<T: $crate::infallible::InfallibleSerialize + ?Sized>() {}
+            $(assert_infallible::<$field>();)*
+        };
+    };
+}
+
+impl<T: Serialize> Wrap<T> {
+    /// Encodes the wrapped value to `dst`, statically known not to fail
+    pub fn encode_infallible_to<O: Output + ?Sized>(&self, dst: &mut O)
+    where
+        T: InfallibleSerialize,
+    {
+        encode_infallible_to(&self.0, dst)
+    }
+}
+
+pub(crate) fn encode_infallible_to<T, O>(value: &T, dst: &mut O)
+where
+    T: InfallibleSerialize + ?Sized,
+    O: Output + ?Sized,
+{
+    if let Err(e) = logging::encode_logged::<T, _, _>(dst, |dst| try_serialize_to(value, dst)) {
+        unreachable!("`InfallibleSerialize` implementation is wrong: {}", e);
+    }
+}
+
+macro_rules! impl_infallible_serialize_for {
+    ($($t:ty),* $(,)?) => {
+        $(
+            impl InfallibleSerialize for $t {}
+        )*
+    };
+}
+
+impl_infallible_serialize_for!(bool, char, u8, u16, u32, u64, i8, i16, i32, i64, (), str);
+
+#[cfg(feature = "alloc")]
+impl_infallible_serialize_for!(String);
+
+impl<T: ?Sized> InfallibleSerialize for PhantomData<T> {}
+
+macro_rules! impl_infallible_serialize_for_generics {
+    ($(<$($p:ident $(: $bound:ident)?),+> $t:ty,)*) => {
+        $(
+            impl<$($p: InfallibleSerialize $(+ $bound)?),+> InfallibleSerialize for $t {}
+        )*
+    };
+}
+
+impl_infallible_serialize_for_generics! {
+    <T> Option<T>,
+    <T> [T],
+    <A> (A,),
+    <A, B> (A, B),
+    <A, B, C> (A, B, C),
+    <A, B, C, D> (A, B, C, D),
+    <A, B, C, D, E> (A, B, C, D, E),
+    <A, B, C, D, E, F> (A, B, C, D, E, F),
+}
+
+#[cfg(feature = "alloc")]
+impl_infallible_serialize_for_generics! {
+    <T> Vec<T>,
+    <T> VecDeque<T>,
+    <T> BTreeSet<T>,
+    <K, V> BTreeMap<K, V>,
+}
+
+impl<T: InfallibleSerialize + ?Sized> InfallibleSerialize for &T {}
+impl<T: InfallibleSerialize + ?Sized> InfallibleSerialize for &mut T {}
+#[cfg(feature = "alloc")]
+impl<T: InfallibleSerialize + ?Sized> InfallibleSerialize for Box<T> {}
+impl<T: InfallibleSerialize> InfallibleSerialize for Wrap<T> {}
+
+macro_rules! impl_infallible_serialize_for_arrays {
+    ($($n:literal)*) => {
+        $(
+            impl<T: InfallibleSerialize> InfallibleSerialize for [T; $n] {}
+        )*
+    };
+}
+
+impl_infallible_serialize_for_arrays! {
+    0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31 32
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Wrap;
+    use parity_scale_codec::Output;
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    struct Reading {
+        id: u16,
+        samples: [i32; 3],
+        label: Option<&'static str>,
+    }
+
+    crate::impl_infallible_serialize!(Reading { u16, [i32; 3], Option<&'static str> });
+
+    struct FixedOutput {
+        bytes: [u8; 32],
+        len: usize,
+    }
+
+    impl Output for FixedOutput {
+        fn write(&mut self, bytes: &[u8]) {
+            self.bytes[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+            self.len += bytes.len();
+        }
+    }
+
+    #[test]
+    fn infallible_encoding_matches_fallible_one() {
+        let reading = Reading { id: 7, samples: [1, -1, 2], label: Some("ok") };
+        let mut dst = FixedOutput { bytes: [0; 32], len: 0 };
+        Wrap(&reading).encode_infallible_to(&mut dst);
+        let mut expected = FixedOutput { bytes: [0; 32], len: 0 };
+        crate::try_serialize_to(&reading, &mut expected).unwrap();
+        assert_eq!(dst.bytes[..dst.len], expected.bytes[..expected.len]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn alloc_containers_encode_infallibly() {
+        use alloc::{collections::BTreeMap, string::String, vec};
+
+        let value = (vec![String::from("a")], BTreeMap::from([(1u8, vec![true])]));
+        assert_eq!(crate::encode_infallible(&value), crate::encode(&value));
+    }
+}
@@ -0,0 +1,397 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Deduplicating repeated strings while decoding, for use with `#[serde(with)]`
+//!
+//! Batches of values often hold the same short strings many times (e.g. the names of the pallets
+//! and calls of chain events), and decoding each of them into its own `String` allocates as many
+//! copies. [`decode_interned`] decodes with a [`StringInterner`] and the [`Interned`] strings and
+//! `Arc<str>` fields marked with `#[serde(with = "serde_scale_wrap::interned")]` are then looked
+//! up in it, so that equal strings share one allocation. [`HashInterner`] is the default
+//! interner.
+//!
+//! ```rust
+//! use serde::{Deserialize, Serialize};
+//! use serde_scale_wrap::{HashInterner, Interned};
+//! use std::sync::Arc;
+//!
+//! #[derive(Deserialize, Serialize)]
+//! struct Event {
+//!     pallet: Interned,
+//!     #[serde(with = "serde_scale_wrap::interned")]
+//!     method: Arc<str>,
+//! }
+//!
+//! let event = |method: &str| Event { pallet: "Balances".into(), method: method.into() };
+//! let batch = vec![event("Transfer"), event("Deposit"), event("Transfer")];
+//! let encoded = serde_scale_wrap::encode(&batch);
+//! let mut interner = HashInterner::new();
+//! let decoded = serde_scale_wrap::decode_interned::<Vec<Event>, _>(&encoded, &mut interner)?;
+//! assert!(Arc::ptr_eq(&decoded[0].pallet.0, &decoded[2].pallet.0));
+//! assert!(Arc::ptr_eq(&decoded[0].method, &decoded[2].method));
+//! assert_eq!(interner.len(), 3);
+//! # Ok::<_, serde_scale_wrap::Error>(())
+//! ```
+//!
+//! Interned strings are encoded like `str`. Other decoding functions and formats decode each of
+//! them into its own allocation. `Rc<str>` cannot be interned, as the interner hands out `Arc`s.
+
+use alloc::sync::Arc;
+use core::{borrow::Borrow, fmt, ops::Deref};
+use std::collections::HashSet;
+
+#[cfg(feature = "decode")]
+use crate::{de, logging, Error, SliceRead};
+#[cfg(feature = "decode")]
+use core::cell::RefCell;
+#[cfg(feature = "decode")]
+use serde::de::{Deserialize, DeserializeOwned, Deserializer, Visitor};
+#[cfg(feature = "encode")]
+use serde::{Serialize, Serializer};
+
+/// Name of the newtype struct `Interned` deserializes, recognized by the deserializer of this
+/// crate
+#[cfg(feature = "decode")]
+pub(crate) const NAME: &str = "$serde_scale_wrap::Interned";
+
+/// Store of strings returning a shared copy of the strings it is given
+pub trait StringInterner {
+    /// Returns a string equal to `s`, the same allocation for equal strings
+    fn intern(&mut self, s: &str) -> Arc<str>;
+}
+
+impl<T: StringInterner + ?Sized> StringInterner for &mut T {
+    fn intern(&mut self, s: &str) -> Arc<str> {
+        (**self).intern(s)
+    }
+}
+
+/// String interner keeping every string it is given in a `HashSet`
+///
+/// The strings are kept until the interner is dropped or cleared, even if no decoded value
+/// refers to them anymore.
+#[derive(Clone, Debug, Default)]
+pub struct HashInterner {
+    strings: HashSet<Arc<str>>,
+}
+
+impl HashInterner {
+    /// Returns an empty interner
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of distinct strings interned
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    /// Indicates if no string was interned
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    /// Forgets all interned strings
+    pub fn clear(&mut self) {
+        self.strings.clear();
+    }
+}
+
+impl StringInterner for HashInterner {
+    fn intern(&mut self, s: &str) -> Arc<str> {
+        if let Some(interned) = self.strings.get(s) {
+            return interned.clone();
+        }
+        let interned = Arc::<str>::from(s);
+        self.strings.insert(interned.clone());
+        interned
+    }
+}
+
+/// Shared string deduplicated when decoded with [`decode_interned`]
+///
+/// See the [module documentation](crate::interned).
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Interned(pub Arc<str>);
+
+impl Interned {
+    /// Returns the shared string
+    pub fn into_inner(self) -> Arc<str> {
+        self.0
+    }
+}
+
+impl Deref for Interned {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Interned {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for Interned {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<Arc<str>> for Interned {
+    fn from(s: Arc<str>) -> Self {
+        Interned(s)
+    }
+}
+
+impl From<&str> for Interned {
+    fn from(s: &str) -> Self {
+        Interned(s.into())
+    }
+}
+
+impl fmt::Display for Interned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(feature = "encode")]
+impl Serialize for Interned {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<'de> Deserialize<'de> for Interned {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_newtype_struct(NAME, InternedVisitor)
+    }
+}
+
+/// Serializes an `Arc<str>` like `str`
+#[cfg(feature = "encode")]
+pub fn serialize<S: Serializer>(s: &Arc<str>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(s)
+}
+
+/// Deserializes an `Arc<str>`, interning it when decoding with [`decode_interned`]
+#[cfg(feature = "decode")]
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Arc<str>, D::Error> {
+    Interned::deserialize(deserializer).map(Interned::into_inner)
+}
+
+/// Decodes a `T` from `bytes`, failing if bytes are left over, interning its [`Interned`] strings
+/// with `interner`
+///
+/// See the [module documentation](crate::interned).
+#[cfg(feature = "decode")]
+pub fn decode_interned<T, I>(bytes: &[u8], interner: &mut I) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+    I: StringInterner + ?Sized,
+{
+    let mut input = bytes;
+    let interner = RefCell::new(interner);
+    let intern = |s: &str| interner.borrow_mut().intern(s);
+    let value = logging::decode_counted(|count| {
+        let len = input.len();
+        let mut deserializer = crate::scale::Deserializer::new(SliceRead(&mut input));
+        let result = T::deserialize(de::Deserializer::with_interner(&mut deserializer, &intern))
+            .map_err(Error::from_deserialization);
+        *count = len - input.len();
+        result
+    })?;
+    if input.is_empty() {
+        Ok(value)
+    } else {
+        Err(Error::TrailingBytes { len: input.len() })
+    }
+}
+
+#[cfg(feature = "decode")]
+std::thread_local! {
+    /// String just interned by the deserializer of this crate, taken by the visitor of `Interned`
+    static HANDED_OVER: RefCell<Option<Arc<str>>> = const { RefCell::new(None) };
+}
+
+/// Visits the interned string `s` with the visitor of an `Interned`, which takes it instead of
+/// copying it
+///
+/// Visitors only get a `&str`, so `s` is handed over through a thread-local, and the visitor only
+/// takes it if it visits the very same bytes.
+#[cfg(feature = "decode")]
+pub(crate) fn visit_interned<'de, V, E>(visitor: V, s: Arc<str>) -> Result<V::Value, E>
+where
+    V: Visitor<'de>,
+    E: serde::de::Error,
+{
+    HANDED_OVER.with(|slot| *slot.borrow_mut() = Some(s.clone()));
+    let result = visitor.visit_str(&s);
+    HANDED_OVER.with(|slot| slot.borrow_mut().take());
+    result
+}
+
+/// Visitor of an `Interned`
+#[cfg(feature = "decode")]
+struct InternedVisitor;
+
+#[cfg(feature = "decode")]
+impl<'de> Visitor<'de> for InternedVisitor {
+    type Value = Interned;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a string")
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(self, d: D) -> Result<Interned, D::Error> {
+        d.deserialize_str(self)
+    }
+
+    fn visit_str<E: serde::de::Error>(self, s: &str) -> Result<Interned, E> {
+        let handed_over = HANDED_OVER.with(|slot| {
+            let mut slot = slot.borrow_mut();
+            match &*slot {
+                Some(interned) if core::ptr::eq(&**interned, s) => slot.take(),
+                _ => None,
+            }
+        });
+        Ok(Interned(handed_over.unwrap_or_else(|| s.into())))
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::{HashInterner, Interned, StringInterner};
+    use crate::{decode_all, decode_interned, encode, Error};
+    use serde::{Deserialize, Serialize};
+    use std::{
+        collections::BTreeMap,
+        format,
+        string::{String, ToString},
+        sync::Arc,
+        vec::Vec,
+    };
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    struct Event {
+        pallet: Interned,
+        #[serde(with = "crate::interned")]
+        method: Arc<str>,
+        index: u32,
+        labels: BTreeMap<Interned, Option<Interned>>,
+    }
+
+    const PALLETS: [&str; 3] = ["Balances", "System", "Staking"];
+    const METHODS: [&str; 4] = ["Transfer", "Deposit", "Withdraw", "ExtrinsicSuccess"];
+
+    fn batch(len: u32) -> Vec<Event> {
+        (0..len)
+            .map(|index| Event {
+                pallet: PALLETS[index as usize % 3].into(),
+                method: METHODS[index as usize % 4].into(),
+                index,
+                labels: BTreeMap::from([("phase".into(), Some("apply".into()))]),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn repeated_strings_share_one_allocation() {
+        let events = batch(1000);
+        let mut interner = HashInterner::new();
+        let decoded = decode_interned::<Vec<Event>, _>(&encode(&events), &mut interner).unwrap();
+        assert_eq!(decoded, events);
+        assert_eq!(interner.len(), 3 + 4 + 2);
+        for (event, next) in decoded.iter().zip(&decoded[12..]) {
+            assert!(Arc::ptr_eq(&event.pallet.0, &next.pallet.0));
+            assert!(Arc::ptr_eq(&event.method, &next.method));
+        }
+        let (key, value) = decoded[0].labels.iter().next().unwrap();
+        let (other_key, other_value) = decoded[999].labels.iter().next().unwrap();
+        assert!(Arc::ptr_eq(&key.0, &other_key.0));
+        assert!(Arc::ptr_eq(&value.as_ref().unwrap().0, &other_value.as_ref().unwrap().0));
+    }
+
+    #[test]
+    fn interning_saves_allocations() {
+        let encoded = encode(&batch(1000));
+        let (decoded, plain) = crate::tests::allocation_count(|| decode_all::<Vec<Event>>(&encoded));
+        assert_eq!(decoded.unwrap().len(), 1000);
+        let mut interner = HashInterner::new();
+        let (decoded, interned) = crate::tests::allocation_count(|| {
+            decode_interned::<Vec<Event>, _>(&encoded, &mut interner)
+        });
+        assert_eq!(decoded.unwrap().len(), 1000);
+        // Each event allocates a label map either way, and 4 strings when not interning.
+        assert!(plain >= 5000, "{} allocations", plain);
+        assert!(interned <= 1000 + 50, "{} allocations", interned);
+    }
+
+    #[test]
+    fn interners_are_reused_across_decodings() {
+        let mut interner = HashInterner::new();
+        let encoded = encode(&batch(10));
+        let first = decode_interned::<Vec<Event>, _>(&encoded, &mut interner).unwrap();
+        let second = decode_interned::<Vec<Event>, _>(&encoded, &mut interner).unwrap();
+        assert!(Arc::ptr_eq(&first[0].method, &second[0].method));
+        interner.clear();
+        let third = decode_interned::<Vec<Event>, _>(&encoded, &mut interner).unwrap();
+        assert!(!Arc::ptr_eq(&first[0].method, &third[0].method));
+    }
+
+    /// Interner counting the strings it is given, through a trait object
+    struct Counting(HashInterner, usize);
+
+    impl StringInterner for Counting {
+        fn intern(&mut self, s: &str) -> Arc<str> {
+            self.1 += 1;
+            self.0.intern(s)
+        }
+    }
+
+    #[test]
+    fn custom_interners_see_every_interned_string() {
+        let mut interner = Counting(HashInterner::new(), 0);
+        let dyn_interner: &mut dyn StringInterner = &mut interner;
+        let decoded = decode_interned::<Vec<Event>, _>(&encode(&batch(10)), dyn_interner).unwrap();
+        assert_eq!(decoded.len(), 10);
+        assert_eq!(interner.1, 10 * 4);
+    }
+
+    #[test]
+    fn interned_strings_encode_and_decode_like_strings() {
+        let events = batch(5);
+        let encoded = encode(&events);
+        let plain = events
+            .iter()
+            .map(|e| {
+                let labels: BTreeMap<String, Option<String>> = e
+                    .labels
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.as_ref().map(|v| v.to_string())))
+                    .collect();
+                (e.pallet.to_string(), e.method.to_string(), e.index, labels)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(encoded, encode(&plain));
+        assert_eq!(decode_all::<Vec<Event>>(&encoded).unwrap(), events);
+        let json = serde_json::to_string(&events[0]).unwrap();
+        assert_eq!(serde_json::from_str::<Event>(&json).unwrap(), events[0]);
+        assert!(json.starts_with(&format!(r#"{{"pallet":"{}""#, PALLETS[0])));
+    }
+
+    #[test]
+    fn invalid_and_trailing_input_is_rejected() {
+        let mut interner = HashInterner::new();
+        let result = decode_interned::<Interned, _>(b"\x08\xff\xfe", &mut interner);
+        assert!(matches!(result, Err(Error::Deserialization(_))));
+        assert!(interner.is_empty());
+        let result = decode_interned::<Interned, _>(b"\x04a\x00", &mut interner);
+        assert!(matches!(result, Err(Error::TrailingBytes { len: 1 })));
+        assert_eq!(interner.len(), 1);
+    }
+}
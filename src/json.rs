@@ -0,0 +1,134 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Transcoding between JSON and SCALE through a type acting as the schema
+//!
+//! Values go through the `serde` implementations of the type, so its attributes decide how strict
+//! transcoding is, e.g. `#[serde(deny_unknown_fields)]` rejects unknown JSON fields. Bytes left
+//! over after the SCALE encoding are always rejected.
+//!
+//! ```rust
+//! use serde::{Deserialize, Serialize};
+//! use serde_scale_wrap::json::{json_to_scale, scale_to_json};
+//!
+//! #[derive(Deserialize, Serialize)]
+//! struct Transfer {
+//!     to: String,
+//!     amount: u64,
+//! }
+//!
+//! let encoded = json_to_scale::<Transfer>(r#"{"to":"bob","amount":5}"#)?;
+//! assert_eq!(encoded, b"\x0cbob\x05\0\0\0\0\0\0\0");
+//! assert_eq!(scale_to_json::<Transfer>(&encoded)?, r#"{"to":"bob","amount":5}"#);
+//! # Ok::<_, serde_scale_wrap::json::TranscodeError>(())
+//! ```
+
+use crate::{decode_all, try_encode, Error};
+use core::fmt::{self, Display};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{string::String, vec::Vec};
+
+/// Returns the SCALE encoding of the `T` held by `json`
+pub fn json_to_scale<T>(json: &str) -> Result<Vec<u8>, TranscodeError>
+where
+    T: DeserializeOwned + Serialize,
+{
+    let value = serde_json::from_str::<T>(json).map_err(TranscodeError::Json)?;
+    try_encode(&value).map_err(TranscodeError::Scale)
+}
+
+/// Returns the JSON representation of the `T` encoded in `bytes`
+pub fn scale_to_json<T>(bytes: &[u8]) -> Result<String, TranscodeError>
+where
+    T: DeserializeOwned + Serialize,
+{
+    let value = decode_all::<T>(bytes).map_err(TranscodeError::Scale)?;
+    serde_json::to_string(&value).map_err(TranscodeError::Json)
+}
+
+/// Errors returned when transcoding fails
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum TranscodeError {
+    /// The JSON side failed (invalid JSON, or value without JSON representation)
+    Json(serde_json::Error),
+    /// The SCALE side failed (invalid encoding, or value that cannot be encoded)
+    Scale(Error),
+}
+
+impl Display for TranscodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranscodeError::Json(e) => write!(f, "JSON transcoding failed: {}", e),
+            TranscodeError::Scale(e) => write!(f, "SCALE transcoding failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TranscodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TranscodeError::Json(e) => Some(e),
+            TranscodeError::Scale(e) => Some(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{json_to_scale, scale_to_json, TranscodeError};
+    use crate::Error;
+    use serde::{Deserialize, Serialize};
+    use std::{collections::BTreeMap, string::String, vec::Vec};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    #[serde(deny_unknown_fields)]
+    struct Block {
+        number: u32,
+        author: Option<String>,
+        extrinsics: Vec<Extrinsic>,
+        digest: BTreeMap<String, u8>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    enum Extrinsic {
+        Timestamp(u64),
+        Transfer { from: String, to: String, amount: u64 },
+        Remark,
+    }
+
+    const BLOCK: &str = concat!(
+        r#"{"number":7,"author":null,"extrinsics":[{"Timestamp":1600000000},"#,
+        r#"{"Transfer":{"from":"alice","to":"bob","amount":12}},"Remark"],"#,
+        r#""digest":{"aura":1,"babe":2}}"#,
+    );
+
+    #[test]
+    fn json_roundtrips_through_scale() {
+        let encoded = json_to_scale::<Block>(BLOCK).unwrap();
+        let block = serde_json::from_str::<Block>(BLOCK).unwrap();
+        assert_eq!(encoded, crate::encode(&block));
+        assert_eq!(scale_to_json::<Block>(&encoded).unwrap(), BLOCK);
+    }
+
+    #[test]
+    fn unknown_fields_follow_serde_attributes() {
+        let json = BLOCK.replacen(r#"{"number":7"#, r#"{"number":7,"parent":6"#, 1);
+        assert!(matches!(json_to_scale::<Block>(&json), Err(TranscodeError::Json(_))));
+        let json = BLOCK.replacen(r#""to":"bob""#, r#""to":"bob","fee":1"#, 1);
+        assert!(json_to_scale::<Block>(&json).is_ok());
+    }
+
+    #[test]
+    fn trailing_scale_bytes_are_rejected() {
+        let mut encoded = json_to_scale::<Block>(BLOCK).unwrap();
+        encoded.push(0);
+        let result = scale_to_json::<Block>(&encoded);
+        assert!(matches!(result, Err(TranscodeError::Scale(Error::TrailingBytes { len: 1 }))));
+    }
+
+    #[test]
+    fn values_without_scale_encoding_fail_on_the_scale_side() {
+        let result = json_to_scale::<(u8, u128)>("[1, 2]");
+        assert!(matches!(result, Err(TranscodeError::Scale(Error::Serialization(_)))));
+    }
+}
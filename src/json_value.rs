@@ -0,0 +1,301 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! `serde(with)` module encoding `serde_json::Value` fields as a tagged enum
+//!
+//! `Value` only deserializes from self-describing formats, which SCALE is not. This module encodes
+//! a `Value` as an enum instead, with a variant index byte followed by the payload:
+//!
+//! | Index | Variant | Payload                                                 |
+//! |-------|---------|---------------------------------------------------------|
+//! | 0     | Null    | None                                                    |
+//! | 1     | Bool    | `bool`                                                  |
+//! | 2     | Number  | Index byte, then `u64` (0), `i64` (1) or `f64` bits (2) |
+//! | 3     | String  | `str`                                                   |
+//! | 4     | Array   | Sequence of values                                      |
+//! | 5     | Object  | Map from `str` to values                                |
+//!
+//! Non-negative integers use the `u64` variant and negative integers the `i64` one. Other numbers
+//! are encoded as the little-endian bytes of the IEEE-754 representation of their `f64` value,
+//! regardless of the `float` feature, and roundtrip bit for bit, including `-0.0`. `Value` cannot
+//! hold NaN or infinities, so decoding their representation fails.
+//!
+//! ⚠ Object entries are encoded in iteration order, which is the order of the keys unless the
+//! `preserve_order` feature of `serde_json` is enabled, in which case it is their insertion order.
+//! Equal objects thus only have the same encoding without `preserve_order`. Entries can be decoded
+//! in any order, and a repeated key keeps its last value.
+//!
+//! ```rust
+//! use serde::{Deserialize, Serialize};
+//! use serde_json::{json, Value};
+//!
+//! #[derive(Debug, Deserialize, PartialEq, Serialize)]
+//! struct Event {
+//!     id: u32,
+//!     #[serde(with = "serde_scale_wrap::json_value")]
+//!     metadata: Value,
+//! }
+//!
+//! let event = Event { id: 1, metadata: json!({ "tags": ["a"], "retries": 2 }) };
+//! let encoded = serde_scale_wrap::encode(&event);
+//! assert_eq!(serde_scale_wrap::decode_all::<Event>(&encoded)?, event);
+//! # Ok::<_, serde_scale_wrap::Error>(())
+//! ```
+
+use core::fmt;
+use serde::{
+    de::{self, EnumAccess, MapAccess, VariantAccess, Visitor},
+    ser::{SerializeMap, SerializeSeq},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::{Map, Number, Value};
+use std::{string::String, vec::Vec};
+
+/// Serializes `value` as a tagged enum
+pub fn serialize<S: Serializer>(value: &Value, serializer: S) -> Result<S::Ok, S::Error> {
+    TaggedRef(value).serialize(serializer)
+}
+
+/// Deserializes a `Value` from a tagged enum
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Value, D::Error> {
+    Tagged::deserialize(deserializer).map(|tagged| tagged.0)
+}
+
+const NAME: &str = "Value";
+const VARIANTS: &[&str] = &["Null", "Bool", "Number", "String", "Array", "Object"];
+
+/// Variant of `Value`
+#[derive(Deserialize)]
+#[serde(variant_identifier)]
+enum Tag {
+    Null,
+    Bool,
+    Number,
+    String,
+    Array,
+    Object,
+}
+
+/// Representation of `Number`
+#[derive(Deserialize, Serialize)]
+enum TaggedNumber {
+    PosInt(u64),
+    NegInt(i64),
+    Float(u64),
+}
+
+/// `Value` serializing as a tagged enum
+struct TaggedRef<'a>(&'a Value);
+
+impl Serialize for TaggedRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Value::Null => serializer.serialize_unit_variant(NAME, 0, "Null"),
+            Value::Bool(b) => serializer.serialize_newtype_variant(NAME, 1, "Bool", b),
+            Value::Number(n) => {
+                let n = if let Some(n) = n.as_u64() {
+                    TaggedNumber::PosInt(n)
+                } else if let Some(n) = n.as_i64() {
+                    TaggedNumber::NegInt(n)
+                } else {
+                    let n = n.as_f64().ok_or_else(|| serde::ser::Error::custom(NotF64(n)))?;
+                    TaggedNumber::Float(n.to_bits())
+                };
+                serializer.serialize_newtype_variant(NAME, 2, "Number", &n)
+            }
+            Value::String(s) => serializer.serialize_newtype_variant(NAME, 3, "String", s),
+            Value::Array(items) => {
+                serializer.serialize_newtype_variant(NAME, 4, "Array", &TaggedArray(items))
+            }
+            Value::Object(entries) => {
+                serializer.serialize_newtype_variant(NAME, 5, "Object", &TaggedObject(entries))
+            }
+        }
+    }
+}
+
+/// Error message for numbers without `f64` representation (e.g. with `arbitrary_precision`)
+struct NotF64<'a>(&'a Number);
+
+impl fmt::Display for NotF64<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is not representable as an f64", self.0)
+    }
+}
+
+struct TaggedArray<'a>(&'a [Value]);
+
+impl Serialize for TaggedArray<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.0.len()))?;
+        for item in self.0 {
+            seq.serialize_element(&TaggedRef(item))?;
+        }
+        seq.end()
+    }
+}
+
+struct TaggedObject<'a>(&'a Map<String, Value>);
+
+impl Serialize for TaggedObject<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in self.0 {
+            map.serialize_entry(key, &TaggedRef(value))?;
+        }
+        map.end()
+    }
+}
+
+/// `Value` deserializing from a tagged enum
+struct Tagged(Value);
+
+impl<'de> Deserialize<'de> for Tagged {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_enum(NAME, VARIANTS, TaggedVisitor).map(Tagged)
+    }
+}
+
+struct TaggedVisitor;
+
+impl<'de> Visitor<'de> for TaggedVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a tagged JSON value")
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Value, A::Error> {
+        let (tag, variant) = data.variant()?;
+        Ok(match tag {
+            Tag::Null => {
+                variant.unit_variant()?;
+                Value::Null
+            }
+            Tag::Bool => Value::Bool(variant.newtype_variant()?),
+            Tag::Number => {
+                let n = match variant.newtype_variant()? {
+                    TaggedNumber::PosInt(n) => n.into(),
+                    TaggedNumber::NegInt(n) => n.into(),
+                    TaggedNumber::Float(bits) => {
+                        let n = f64::from_bits(bits);
+                        Number::from_f64(n).ok_or_else(|| {
+                            de::Error::invalid_value(de::Unexpected::Float(n), &"a finite number")
+                        })?
+                    }
+                };
+                Value::Number(n)
+            }
+            Tag::String => Value::String(variant.newtype_variant()?),
+            Tag::Array => {
+                let items = variant.newtype_variant::<Vec<Tagged>>()?;
+                Value::Array(items.into_iter().map(|item| item.0).collect())
+            }
+            Tag::Object => Value::Object(variant.newtype_variant::<TaggedEntries>()?.0),
+        })
+    }
+}
+
+struct TaggedEntries(Map<String, Value>);
+
+impl<'de> Deserialize<'de> for TaggedEntries {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(TaggedEntriesVisitor).map(TaggedEntries)
+    }
+}
+
+struct TaggedEntriesVisitor;
+
+impl<'de> Visitor<'de> for TaggedEntriesVisitor {
+    type Value = Map<String, Value>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a map of tagged JSON values")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+        let mut entries = Map::new();
+        while let Some((key, Tagged(value))) = access.next_entry()? {
+            entries.insert(key, value);
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use crate::{decode_all, encode, Error};
+    use serde::{Deserialize, Serialize};
+    use serde_json::{json, Value};
+    use std::{string::String, vec};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Json(#[serde(with = "super")] Value);
+
+    fn nested() -> Value {
+        json!({
+            "null": null,
+            "flags": [true, false],
+            "numbers": [0, 1, u64::MAX, -1, i64::MIN, 0.5, -0.0, 1e300, f64::MIN_POSITIVE],
+            "text": "héllo",
+            "deep": [[[{ "a": [{}, [], ""] }]], { "b": { "c": { "d": [null, 3] } } }],
+        })
+    }
+
+    #[test]
+    fn nested_values_roundtrip() {
+        let value = nested();
+        let decoded = decode_all::<Json>(&encode(&Json(value.clone()))).unwrap();
+        assert_eq!(decoded.0, value);
+        let numbers = decoded.0["numbers"].as_array().unwrap();
+        assert_eq!(numbers[6].as_f64().unwrap().to_bits(), (-0.0f64).to_bits());
+    }
+
+    #[test]
+    fn values_are_tagged() {
+        let encoded = encode(&Json(json!([null, true, -2, 1.5, "a", { "k": 0 }])));
+        let mut expected = vec![4, 24, 0, 1, 1, 2, 1];
+        expected.extend_from_slice(&(-2i64).to_le_bytes());
+        expected.extend_from_slice(&[2, 2]);
+        expected.extend_from_slice(&1.5f64.to_bits().to_le_bytes());
+        expected.extend_from_slice(&[3, 4, b'a', 5, 4, 4, b'k', 2, 0]);
+        expected.extend_from_slice(&0u64.to_le_bytes());
+        assert_eq!(encoded, expected);
+    }
+
+    #[test]
+    fn object_keys_are_encoded_in_map_order() {
+        let mut forward = serde_json::Map::new();
+        let mut backward = serde_json::Map::new();
+        for key in ["a", "b", "c"].iter() {
+            forward.insert(String::from(*key), Value::from(*key));
+        }
+        for key in ["c", "b", "a"].iter() {
+            backward.insert(String::from(*key), Value::from(*key));
+        }
+        // Insertion order is kept if another crate enables `preserve_order`.
+        let sorted = backward.keys().next().map(String::as_str) == Some("a");
+        let forward = encode(&Json(forward.into()));
+        let backward = encode(&Json(backward.into()));
+        assert_eq!(forward == backward, sorted);
+        assert_eq!(decode_all::<Json>(&forward).unwrap(), decode_all::<Json>(&backward).unwrap());
+    }
+
+    #[test]
+    fn non_finite_floats_are_rejected() {
+        for &bits in &[f64::NAN.to_bits(), f64::INFINITY.to_bits()] {
+            let mut encoded = vec![2, 2];
+            encoded.extend_from_slice(&bits.to_le_bytes());
+            let result = decode_all::<Json>(&encoded);
+            assert!(matches!(result, Err(Error::Deserialization(_))));
+        }
+    }
+
+    #[test]
+    fn unknown_tags_are_rejected() {
+        assert!(matches!(decode_all::<Json>(&[6]), Err(Error::Deserialization(_))));
+        assert!(matches!(decode_all::<Json>(&[2, 3, 0]), Err(Error::Deserialization(_))));
+        let truncated = encode(&Json(nested()));
+        let result = decode_all::<Json>(&truncated[..truncated.len() - 1]);
+        assert!(matches!(result, Err(Error::Input(_))));
+    }
+}
@@ -0,0 +1,209 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+use crate::{serialize_to, try_serialize_to, Error};
+use alloc::vec::Vec;
+use parity_scale_codec::Output;
+use serde::Serialize;
+
+/// Builder of storage keys, appending the encodings of key components to a prefix
+///
+/// Components are serialized straight into the key, without encoding them to intermediate
+/// buffers. The key equals the prefix followed by the encodings of the components, hashed by
+/// [`KeyBuilder::push_hashed`] for hashed components.
+///
+/// ```rust
+/// use serde_scale_wrap::KeyBuilder;
+///
+/// let key = KeyBuilder::new(b"prefix").push(&7u8).push("foo").finish();
+/// assert_eq!(key, b"prefix\x07\x0cfoo");
+/// ```
+#[derive(Clone, Debug)]
+pub struct KeyBuilder {
+    key: Vec<u8>,
+}
+
+impl KeyBuilder {
+    /// Returns a builder of keys starting with `prefix`
+    pub fn new(prefix: &[u8]) -> Self {
+        KeyBuilder::with_capacity(prefix, 0)
+    }
+
+    /// Returns a builder of keys starting with `prefix`, reserving room for `capacity` more bytes
+    pub fn with_capacity(prefix: &[u8], capacity: usize) -> Self {
+        let mut key = Vec::with_capacity(prefix.len() + capacity);
+        key.extend_from_slice(prefix);
+        KeyBuilder { key }
+    }
+
+    /// Appends the encoding of `component`
+    ///
+    /// # Panics
+    /// Panics if the serializer returns an error, like `Wrap` does.
+    pub fn push<T: Serialize + ?Sized>(mut self, component: &T) -> Self {
+        serialize_to(component, &mut self.key);
+        self
+    }
+
+    /// Appends the encoding of `component`, or returns an error if it cannot be serialized
+    pub fn try_push<T: Serialize + ?Sized>(mut self, component: &T) -> Result<Self, Error> {
+        try_serialize_to(component, &mut self.key)?;
+        Ok(self)
+    }
+
+    /// Appends the encoding of `component` hashed by `hasher`
+    ///
+    /// # Panics
+    /// Panics if the serializer returns an error, like `Wrap` does.
+    pub fn push_hashed<H, T>(mut self, hasher: &H, component: &T) -> Self
+    where
+        H: KeyHasher + ?Sized,
+        T: Serialize + ?Sized,
+    {
+        let start = self.key.len();
+        serialize_to(component, &mut self.key);
+        hasher.hash_component(&mut self.key, start);
+        self
+    }
+
+    /// Returns the key
+    pub fn finish(self) -> Vec<u8> {
+        self.key
+    }
+}
+
+/// Hashing of key components, e.g. as the hashers of Substrate storage maps do
+///
+/// Closures taking the key and the offset of the component implement it:
+///
+/// ```rust
+/// use serde_scale_wrap::KeyBuilder;
+///
+/// let reverse = |key: &mut Vec<u8>, start: usize| key[start..].reverse();
+/// let key = KeyBuilder::new(b"p").push_hashed(&reverse, &0x0102u16).finish();
+/// assert_eq!(key, b"p\x01\x02");
+/// ```
+///
+/// With the `digest` feature, `digest::Hashed` and `digest::HashedConcat` hash with
+/// `digest::Digest` hashers.
+pub trait KeyHasher {
+    /// Replaces `key[start..]`, the encoding of a component, with its hashed form
+    fn hash_component(&self, key: &mut Vec<u8>, start: usize);
+}
+
+impl<F: Fn(&mut Vec<u8>, usize) + ?Sized> KeyHasher for F {
+    fn hash_component(&self, key: &mut Vec<u8>, start: usize) {
+        self(key, start)
+    }
+}
+
+/// Returns `prefix` followed by the encoding of `components`, allocating once
+///
+/// Tuples of components are encoded as the concatenation of the encodings of their elements. The
+/// length of the key is measured by serializing `components` without writing them, before
+/// serializing them into the key.
+///
+/// ```rust
+/// let key = serde_scale_wrap::encode_key(b"prefix", &(7u8, "foo"));
+/// assert_eq!(key, b"prefix\x07\x0cfoo");
+/// ```
+///
+/// # Panics
+/// Panics if the serializer returns an error, like `Wrap` does.
+pub fn encode_key<T: Serialize + ?Sized>(prefix: &[u8], components: &T) -> Vec<u8> {
+    let mut len = LenOutput(0);
+    serialize_to(components, &mut len);
+    KeyBuilder::with_capacity(prefix, len.0).push(components).finish()
+}
+
+/// Returns `prefix` followed by the encoding of `components` like [`encode_key`], or returns an
+/// error if they cannot be serialized
+pub fn try_encode_key<T>(prefix: &[u8], components: &T) -> Result<Vec<u8>, Error>
+where
+    T: Serialize + ?Sized,
+{
+    let mut len = LenOutput(0);
+    try_serialize_to(components, &mut len)?;
+    Ok(KeyBuilder::with_capacity(prefix, len.0).try_push(components)?.finish())
+}
+
+/// `Output` counting the bytes written to it
+struct LenOutput(usize);
+
+impl Output for LenOutput {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0 += bytes.len();
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::{encode_key, try_encode_key, KeyBuilder};
+    use crate::{encode, Error};
+    use alloc::{string::String, vec, vec::Vec};
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    struct Account {
+        id: u64,
+        name: String,
+    }
+
+    fn naive(prefix: &[u8], components: &[Vec<u8>]) -> Vec<u8> {
+        let mut key = prefix.to_vec();
+        for component in components {
+            key.extend(component);
+        }
+        key
+    }
+
+    #[test]
+    fn keys_concatenate_two_components() {
+        let name = String::from("alice");
+        let expected = naive(b"Balances", &[encode(&3u32), encode(&name)]);
+        assert_eq!(encode_key(b"Balances", &(3u32, &name)), expected);
+        assert_eq!(KeyBuilder::new(b"Balances").push(&3u32).push(&name).finish(), expected);
+    }
+
+    #[test]
+    fn keys_concatenate_three_components() {
+        let account = Account { id: 9, name: "bob".into() };
+        let tags = vec![String::from("a"), String::from("bc")];
+        let expected =
+            naive(b"Pallet:Item", &[encode(&account), encode(&tags), encode(&Some(5u16))]);
+        let key = encode_key(b"Pallet:Item", &(&account, &tags, Some(5u16)));
+        assert_eq!(key, expected);
+        assert_eq!(key.capacity(), key.len());
+        let built = KeyBuilder::new(b"Pallet:Item").push(&account).push(&tags).push(&Some(5u16));
+        assert_eq!(built.finish(), expected);
+    }
+
+    #[test]
+    fn empty_components_leave_the_prefix() {
+        assert_eq!(encode_key(b"prefix", &()), b"prefix");
+        assert_eq!(encode_key(b"", &7u8), [7]);
+    }
+
+    #[test]
+    fn hashers_replace_the_component_encoding() {
+        let checksum = |key: &mut Vec<u8>, start: usize| {
+            let sum = key[start..].iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+            key.truncate(start);
+            key.push(sum);
+        };
+        let key = KeyBuilder::new(b"p").push_hashed(&checksum, "ab").push(&1u8).finish();
+        assert_eq!(key, [b'p', 8 + b'a' + b'b', 1]);
+    }
+
+    #[test]
+    fn failed_pushes_are_reported() {
+        assert!(matches!(try_encode_key(b"p", &(1u8, 2u128)), Err(Error::Serialization(_))));
+        assert!(KeyBuilder::new(b"p").try_push(&1u128).is_err());
+        assert_eq!(try_encode_key(b"p", &(1u8, 2u64)).unwrap(), encode_key(b"p", &(1u8, 2u64)));
+    }
+
+    #[test]
+    #[should_panic]
+    fn unserializable_components_panic() {
+        let _ = encode_key(b"p", &1u128);
+    }
+}
@@ -0,0 +1,292 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+use crate::Error;
+use alloc::vec::Vec;
+use core::{cell::OnceCell, fmt};
+use parity_scale_codec::{Encode, EncodeLike, Output};
+use serde::{
+    de::{self, Deserializer, SeqAccess, Visitor},
+    Deserialize, Serialize, Serializer,
+};
+
+#[cfg(feature = "decode")]
+use parity_scale_codec::{Decode, Input};
+#[cfg(feature = "decode")]
+use serde::de::DeserializeOwned;
+
+/// Encoding of a `T` kept as bytes and decoded on first access
+///
+/// A `LazyWrap<T>` holds the SCALE encoding of a `T` and is encoded as a byte sequence holding
+/// it, i.e. prefixed with its length, like [`LengthPrefixed<T>`](crate::LengthPrefixed) is. The
+/// length lets `Decode` and `Deserialize` capture the bytes of the value without decoding them,
+/// so that skipping a field whose value is never looked at costs a copy of its bytes. The value is
+/// decoded like `Wrap` does by the first call to [`LazyWrap::get`] succeeding, and then cached.
+/// Malformed bytes are accepted when capturing the encoding and only fail when decoding it.
+/// `Encode` and `Serialize` write the captured bytes again, even if they are malformed.
+///
+/// ```rust
+/// use parity_scale_codec::Decode;
+/// use serde::{Deserialize, Serialize};
+/// use serde_scale_wrap::{LazyWrap, Wrap};
+///
+/// #[derive(Deserialize, Serialize)]
+/// struct Message {
+///     kind: u8,
+///     body: LazyWrap<Vec<String>>,
+/// }
+///
+/// let encoded = b"\x01\x14\x04\x0cfoo";
+/// let Wrap(message) = Wrap::<Message>::decode(&mut &encoded[..])?;
+/// assert_eq!(message.body.as_bytes(), b"\x04\x0cfoo");
+/// assert_eq!(message.body.get().unwrap(), &["foo"]);
+/// # Ok::<_, parity_scale_codec::Error>(())
+/// ```
+///
+/// The value is cached in a [`OnceCell`], so that `LazyWrap` is not `Sync`.
+#[derive(Clone)]
+pub struct LazyWrap<T> {
+    bytes: Vec<u8>,
+    value: OnceCell<T>,
+}
+
+impl<T> LazyWrap<T> {
+    /// Returns a `LazyWrap` holding `bytes`, expected to be the encoding of a `T`
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        LazyWrap { bytes, value: OnceCell::new() }
+    }
+
+    /// Returns the encoding of the value
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns the encoding of the value
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Returns whether the value was decoded
+    pub fn is_decoded(&self) -> bool {
+        self.value.get().is_some()
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<T: Serialize> LazyWrap<T> {
+    /// Encodes `value` and returns a `LazyWrap` holding its encoding and the value itself
+    ///
+    /// # Panics
+    /// Panics if the serializer returns an error, like `Wrap` does.
+    pub fn new(value: T) -> Self {
+        let bytes = crate::encode(&value);
+        LazyWrap { bytes, value: OnceCell::from(value) }
+    }
+
+    /// Encodes `value` and returns a `LazyWrap` holding its encoding and the value itself, or
+    /// returns an error if it cannot be serialized
+    pub fn try_new(value: T) -> Result<Self, Error> {
+        let bytes = crate::try_encode(&value)?;
+        Ok(LazyWrap { bytes, value: OnceCell::from(value) })
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<T: DeserializeOwned> LazyWrap<T> {
+    /// Returns the value, decoding it if this is the first call to succeed
+    ///
+    /// Decoding fails if bytes are left over. Errors are not cached, and a failed call decodes
+    /// again.
+    pub fn get(&self) -> Result<&T, Error> {
+        if let Some(value) = self.value.get() {
+            return Ok(value);
+        }
+        let value = crate::decode_all(&self.bytes)?;
+        Ok(self.value.get_or_init(|| value))
+    }
+
+    /// Returns the value, decoding it if it was not already
+    pub fn into_inner(self) -> Result<T, Error> {
+        match self.value.into_inner() {
+            Some(value) => Ok(value),
+            None => crate::decode_all(&self.bytes),
+        }
+    }
+}
+
+impl<T> fmt::Debug for LazyWrap<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LazyWrap")
+            .field("bytes", &self.bytes)
+            .field("decoded", &self.is_decoded())
+            .finish()
+    }
+}
+
+/// Compares the encodings, regardless of whether the values were decoded
+impl<T> PartialEq for LazyWrap<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+impl<T> Eq for LazyWrap<T> {}
+
+/// Writes the encoding of the value prefixed with its length
+impl<T> Encode for LazyWrap<T> {
+    fn size_hint(&self) -> usize {
+        self.bytes.size_hint()
+    }
+
+    fn encode_to<O: Output + ?Sized>(&self, dst: &mut O) {
+        self.bytes.encode_to(dst)
+    }
+}
+
+impl<T> EncodeLike for LazyWrap<T> {}
+
+/// Reads the encoding of the value prefixed with its length, without decoding it
+#[cfg(feature = "decode")]
+impl<T> Decode for LazyWrap<T> {
+    fn decode<I: Input>(input: &mut I) -> Result<Self, parity_scale_codec::Error> {
+        Vec::<u8>::decode(input).map(LazyWrap::from_bytes)
+    }
+}
+
+impl<T> Serialize for LazyWrap<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.bytes)
+    }
+}
+
+/// Captures the encoding of the value without decoding it
+impl<'de, T> Deserialize<'de> for LazyWrap<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_byte_buf(BytesVisitor).map(LazyWrap::from_bytes)
+    }
+}
+
+/// Visitor of the encoding held by a `LazyWrap`
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a SCALE encoding as bytes")
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+        Ok(bytes.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<Vec<u8>, E> {
+        Ok(bytes)
+    }
+
+    // Formats without a byte sequence type (e.g. JSON) serialize bytes as a sequence.
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(bytes)
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::LazyWrap;
+    use crate::{decode_all, encode, Error, LengthPrefixed, Wrap};
+    use alloc::{borrow::ToOwned, format, string::String, vec, vec::Vec};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use parity_scale_codec::{Decode, Encode};
+    use serde::{Deserialize, Deserializer, Serialize};
+
+    static DECODED: AtomicUsize = AtomicUsize::new(0);
+
+    /// `u32` counting how many times it is deserialized
+    #[derive(Debug, PartialEq, Serialize)]
+    struct Counted(u32);
+
+    impl<'de> Deserialize<'de> for Counted {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            DECODED.fetch_add(1, Ordering::SeqCst);
+            u32::deserialize(deserializer).map(Counted)
+        }
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Message {
+        kind: u8,
+        body: LazyWrap<(String, Counted)>,
+        tail: u16,
+    }
+
+    fn message() -> Message {
+        Message { kind: 1, body: LazyWrap::new(("body".into(), Counted(7))), tail: 0xbeef }
+    }
+
+    #[test]
+    fn values_are_decoded_once_on_access() {
+        let encoded = encode(&message());
+        DECODED.store(0, Ordering::SeqCst);
+        let decoded = decode_all::<Message>(&encoded).unwrap();
+        assert_eq!((decoded.kind, decoded.tail), (1, 0xbeef));
+        assert!(!decoded.body.is_decoded());
+        assert_eq!(DECODED.load(Ordering::SeqCst), 0);
+        assert_eq!(decoded.body.get().unwrap(), &("body".into(), Counted(7)));
+        assert_eq!(decoded.body.get().unwrap().1, Counted(7));
+        assert_eq!(DECODED.load(Ordering::SeqCst), 1);
+        assert_eq!(decoded.body.into_inner().unwrap().0, "body");
+        assert_eq!(DECODED.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn values_are_encoded_with_their_length() {
+        let body = ("body".to_owned(), 7u32);
+        let lazy = LazyWrap::new(body.clone());
+        assert_eq!(lazy.as_bytes(), encode(&body));
+        assert!(lazy.is_decoded());
+        assert_eq!(encode(&lazy), encode(&LengthPrefixed(body.clone())));
+        assert_eq!(lazy.encode(), encode(&lazy));
+        let decoded = LazyWrap::<(String, u32)>::decode(&mut &lazy.encode()[..]).unwrap();
+        assert_eq!(decoded, lazy);
+        assert_eq!(decoded.into_inner().unwrap(), body);
+    }
+
+    #[test]
+    fn malformed_bytes_fail_on_access() {
+        let encoded = encode(&(1u8, LazyWrap::<String>::from_bytes(vec![0x10, b'a']), 2u8));
+        let (kind, body, tail) = decode_all::<(u8, LazyWrap<String>, u8)>(&encoded).unwrap();
+        assert_eq!((kind, tail), (1, 2));
+        for _ in 0..2 {
+            assert!(matches!(body.get(), Err(Error::Input(_))));
+        }
+        assert!(!body.is_decoded());
+        assert_eq!(Wrap((1u8, body.clone(), 2u8)).encode(), encoded);
+        let trailing = LazyWrap::<u8>::from_bytes(vec![1, 2]);
+        assert!(matches!(trailing.into_inner(), Err(Error::TrailingBytes { len: 1 })));
+    }
+
+    #[test]
+    fn truncated_encodings_are_rejected() {
+        assert!(LazyWrap::<String>::decode(&mut &b"\x10ab"[..]).is_err());
+        assert!(decode_all::<LazyWrap<String>>(b"\x10ab").is_err());
+    }
+
+    #[test]
+    fn other_formats_hold_the_bytes() {
+        let json = serde_json::to_string(&LazyWrap::new(0x0102u16)).unwrap();
+        assert_eq!(json, "[2,1]");
+        let lazy = serde_json::from_str::<LazyWrap<u16>>(&json).unwrap();
+        assert_eq!(*lazy.get().unwrap(), 0x0102);
+    }
+
+    #[test]
+    fn debug_shows_the_bytes() {
+        let lazy = LazyWrap::<u16>::from_bytes(Vec::from([1, 2]));
+        assert_eq!(format!("{:?}", lazy), "LazyWrap { bytes: [1, 2], decoded: false }");
+        lazy.get().unwrap();
+        assert_eq!(format!("{:?}", lazy), "LazyWrap { bytes: [1, 2], decoded: true }");
+    }
+}
@@ -22,6 +22,7 @@
 //!     x: i32,
 //!     s: String,
 //! }
+//! # serde_scale_wrap::impl_infallible_serialize!(Foo { i32, String });
 //!
 //! let original = Foo { x: 3, s: "foo".into() };
 //! let serialized = Wrap(&original).encode();
@@ -29,14 +30,235 @@
 //! assert_eq!(original, deserialized);
 //! ```
 //!
+//! One-off conversions can skip the wrapper:
+//!
+//! ```rust
+//! # extern crate alloc;
+//! # use alloc::string::String;
+//! # use serde::{Deserialize, Serialize};
+//! # #[derive(Debug, Deserialize, PartialEq, Serialize)]
+//! # struct Foo {
+//! #     x: i32,
+//! #     s: String,
+//! # }
+//! let original = Foo { x: 3, s: "foo".into() };
+//! let serialized = serde_scale_wrap::encode(&original);
+//! let deserialized = serde_scale_wrap::decode_all::<Foo>(&serialized)?;
+//! assert_eq!(original, deserialized);
+//! # Ok::<_, serde_scale_wrap::Error>(())
+//! ```
+//!
 //! # Conformance
 //! ⚠ `Option<bool>` is serialized as a single byte according to the SCALE encoding, which differs
 //! from the result of `Encode::encode` -- `Encode` expects `OptionBool` to be used instead.
 //!
+//! Maps and struct variants are encoded and decoded like `parity-scale-codec` does, working around
+//! `serde-scale` which prefixes map keys with a spurious byte and expects struct variants to be
+//! prefixed with their number of fields. ⚠ Version 0.3.0 wrote maps with that spurious byte, and
+//! `DecodeOptions::legacy_map_layout` decodes them. See `CHANGELOG.md` for both layouts.
+//!
+//! `VecDeque`, `LinkedList` and `BinaryHeap` are encoded like `Vec`, in the order of their elements
+//! for the first two and of the internal buffer of the heap for the last one, like
+//! `parity-scale-codec` does. Decoding a `BinaryHeap` may yield a different buffer than `Decode`
+//! does though, which the `binary_heap` module avoids.
+//!
+//! `Range` and `RangeInclusive` are encoded as their start followed by their end, and decoded
+//! without checking that the start does not exceed the end, like `parity-scale-codec` does.
+//!
+//! ⚠ With the `float` feature, `f32` and `f64` are encoded as the 4 and 8 little-endian bytes of
+//! their IEEE-754 representation. `parity-scale-codec` has no floating point implementations, so
+//! this encoding is not standard and other SCALE implementations may not decode it, or not the same
+//! way. Keep floats out of data shared with other implementations, e.g. consensus-critical data.
+//!
+//! ⚠ The `json_value` module encodes objects in the iteration order of their entries, which depends
+//! on the `preserve_order` feature of `serde_json`. Equal objects only have the same encoding when
+//! their keys are sorted, i.e. without `preserve_order`.
+//!
+//! # Performance
+//! Going through `serde` costs a function call per primitive where derived `Encode`/`Decode`
+//! implementations copy sequences of integers at once. `cargo bench --bench codec` compares both on
+//! sequences of integers, sequences of strings, a nested struct and a map. Encoding through `Wrap`
+//! takes up to 5 times as long as the derives, sequences of integers being the worst case, and
+//! about as long for strings.
+//!
+//! `decode` and `decode_all` read slices directly and are the fastest way to decode, taking up to
+//! 4 times as long as the derives, and even less time for strings. `Wrap::decode` goes through
+//! `Input`, whose implementation for slices cannot be inlined, and is up to 13 times slower than the
+//! derives.
+//!
+//! `Wrap::using_encoded`, which Substrate hashes storage keys with, encodes to a 64-byte stack
+//! buffer and only allocates for longer encodings.
+//!
+//! # Substrate storage
+//! With the default features, `Wrap<K>` and `Wrap<V>` can be the keys and values of storage maps.
+//! Keys can be passed as `&Wrap<K>` or as `Wrap<&K>`, which avoids moving or cloning them. Pallets
+//! generating their metadata need the `scale-info` feature, making `Wrap<T>` implement `TypeInfo`
+//! like `T` does, and pallets generating their storage info need the `max-encoded-len` feature,
+//! making `Wrap<T>` implement `MaxEncodedLen` if `T` implements `MaxWrappedLen`. Appending to a
+//! value and decoding its length require `Vec<Wrap<T>>` values, which encode like `Wrap<Vec<T>>`.
+//! `cargo test --features scale-info,max-encoded-len --test storage_map` goes through this workflow
+//! with a storage map reimplementing the trait bounds of `frame-support`. It needs the `std`,
+//! `encode` and `decode` features, `parity-scale-codec` only implementing `Encode` for `String`
+//! with `std`, and supports any other feature. With `deny-panicking-encode`, keys and values must
+//! also implement `InfallibleSerialize`.
+//!
+//! # Unsafe code
+//! This crate is `#![forbid(unsafe_code)]` unless the `unsafe-optimizations` feature is enabled, so
+//! that depending on it adds no `unsafe` code of its own. That feature enables the only `unsafe`
+//! code, `Wrap::from_ref`, `Wrap::from_mut`, `Wrap::wrap_slice` and `Wrap::peel_slice`, which view
+//! values as `Wrap` without copying them through `repr(transparent)` casts.
+//!
 //! # Features
-//! `no_std` is supported by disabling default features.
+//! `no_std` is supported by disabling default features and enabling `serde-scale-0-2`, `encode` and
+//! `decode` again, or only one of the last two. Without `alloc`, `Wrap` still implements `Encode`,
+//! writing straight to the `Output`, and decodes with `Wrap::decode_with_scratch`.
+//! `parity-scale-codec` itself always depends on the `alloc` crate though.
 //!
-//! - `std`: Support for `std`. It is enabled by default.
+//! `encode` and `decode` are additive and can be enabled separately to build only the half of the
+//! crate a program uses, e.g. firmware encoding telemetry without ever decoding it. Other features
+//! only provide their encoding or decoding parts when the corresponding feature is enabled, except
+//! those implying both. The ignored `feature_matrix_builds` test checks that the following
+//! configurations build, and runs with `cargo test --lib feature_matrix -- --ignored`:
+//!
+//! ```sh
+//! cargo check --lib --no-default-features --features serde-scale-0-2
+//! cargo check --lib --no-default-features --features encode,serde-scale-0-2
+//! cargo check --lib --no-default-features --features decode,serde-scale-0-2
+//! cargo check --lib --no-default-features --features alloc,encode,serde-scale-0-2
+//! cargo check --lib --no-default-features --features alloc,decode,serde-scale-0-2
+//! cargo check --lib --no-default-features --features std,encode,serde-scale-0-2
+//! cargo check --lib --no-default-features --features std,decode,serde-scale-0-2
+//! cargo check --lib --no-default-features --features std,encode,decode,serde-scale-0-1
+//! cargo check --lib --no-default-features --features alloc,debug-roundtrip-check,serde-scale-0-2
+//! cargo check --lib --no-default-features --features max-encoded-len,serde-scale-0-2
+//! ```
+//!
+//! Features adding or restricting trait implementations for `Wrap` are also checked not to break
+//! the test suite, the ignored `feature_matrix_tests_pass` test running it with each of the
+//! following:
+//!
+//! ```sh
+//! cargo test --features debug-roundtrip-check
+//! cargo test --features debug-roundtrip-check,max-encoded-len
+//! cargo test --features deny-panicking-encode
+//! ```
+//!
+//! - `std`: Support for `std`, required by `Wrap::decode_from_buf_read`,
+//!   `DecodeOptions::on_progress`, `encode_bytes_from_reader`, `decode_bytes_to_writer`, the
+//!   `frame` module and the `interned` module deduplicating decoded strings. It is enabled by
+//!   default and implies `alloc`.
+//! - `encode`: `Encode` and `EncodeLike` for `Wrap`, the encoding functions and methods,
+//!   `CountingOutput`, `EncodeOptions`, `KeyBuilder`, `TryOutput` and the output adapters, and with
+//!   `decode`, the `ScaleSerde` bound. It is enabled by default and implied by `axum`,
+//!   `debug-roundtrip-check`, `deny-panicking-encode`, `fuzzing`, `json`, `rayon`, `testing`,
+//!   `tokio-codec`, `typed` and `wasm`.
+//! - `decode`: `Decode` for `Wrap`, the decoding functions and methods, `CheckedInput`,
+//!   `CountingInput`, `DecodeBuffer`, `DecodeOptions`, `MapDecoder`, `StreamingDecoder`,
+//!   `VersionedDecoder`, `WrapDeserializer` and the input adapters. It is enabled by default and
+//!   implied by `allocator-api`, `axum`, `bumpalo`, `cache`, `debug-roundtrip-check`, `fuzzing`,
+//!   `json`, `testing`, `tokio-codec`, `typed` and `wasm`.
+//! - `alloc`: Support for the `alloc` crate, required by `Decode` for `Wrap`, the `encode`/`decode`
+//!   functions, the extension traits and the `dump` module. It is implied by `debug-roundtrip-check`,
+//!   `allocator-api`, `arrayvec`, `base64`, `bigint`, `bumpalo`, `bytes`, `cache`, `fuzzing`,
+//!   `hex`, `indexmap`, `smallvec`, `testing` and `wasm`.
+//! - `allocator-api`: `decode_in`, decoding with the scratch buffer allocated from a custom
+//!   allocator. It requires a nightly compiler for the unstable `allocator_api` feature and implies
+//!   `alloc` and `decode`.
+//! - `arrayvec`: `EncodeLike` between `Wrap<arrayvec::ArrayVec<T, N>>` and `Wrap<Vec<T>>`, which
+//!   encode alike. Decoding more than `N` elements fails with `Error::Deserialization`. It implies
+//!   `alloc`.
+//! - `axum`: `axum` module with `Scale`, an extractor and response for `application/scale` bodies.
+//!   It implies `std`, `encode` and `decode`.
+//! - `base64`: `Wrap::encode_base64` and `Wrap::decode_base64`, and `base64` module for
+//!   `serde(with)` embedding encodings as base64 strings in human-readable formats. It implies
+//!   `alloc`.
+//! - `bigint`: `bigint` module for `serde(with)` encoding `num_bigint::BigUint` and `BigInt` as
+//!   their little-endian magnitude bytes, with a single encoding per integer. It implies `alloc`.
+//! - `bumpalo`: `decode_in_arena`, decoding borrowed strings and byte sequences into a
+//!   `bumpalo::Bump` arena. It implies `alloc` and `decode`.
+//! - `bytes`: `bytes` module encoding into `bytes::BytesMut` and decoding from any `bytes::Buf`,
+//!   and `Wrap::encode_to_bytes`. It implies `alloc`.
+//! - `cache`: `CachedDecoder`, decoding through an LRU cache of shared values keyed by their
+//!   encoding. It implies `alloc` and `decode`.
+//! - `debug-roundtrip-check`: `roundtrip_check` module with `CheckedWrap`, encoding its value like
+//!   `Wrap` and, in debug builds, decoding what it encoded and panicking if the value changed,
+//!   catching asymmetric `serde` attributes. Wrapped types must implement
+//!   `roundtrip_check::RoundtripCheck`. `Wrap` is unaffected and never runs the check, as doing so
+//!   in `Encode::encode_to` would require every type wrapped anywhere in the dependency tree to
+//!   implement `RoundtripCheck`, making the feature non-additive.
+//! - `defmt`: `defmt::Format` for `Error`, and `defmt::debug!` logging of decoding failures with
+//!   the byte offset at which they occurred.
+//! - `deny-panicking-encode`: `Encode` and `EncodeLike` for `Wrap<T>` require
+//!   `T: infallible::InfallibleSerialize`, so that encoding through `Encode` cannot panic. `Wrap`
+//!   then no longer satisfies `Encode` bounds (e.g. of `substrate` storage items) for other types,
+//!   which can still be encoded with the fallible functions and methods. It implies `encode`.
+//! - `digest`: `digest` module hashing encodings with `digest::Digest` hashers without buffering
+//!   them, `Wrap::encode_hash`, `Wrap::decode_verified` checking the hash of decoded bytes, and
+//!   the `Hashed` and `HashedConcat` storage key hashers.
+//! - `embedded-io`: `eio` module encoding to `embedded_io::Write` and decoding from
+//!   `embedded_io::Read`, with adapters usable without `alloc`.
+//! - `erased-output`: Serialization writes to `dyn Output` so that it is compiled once per encoded
+//!   type instead of once per encoded type and output type. This reduces code size at the expense of
+//!   speed.
+//! - `float`: `f32` and `f64` are encoded as their IEEE-754 bits instead of failing to serialize.
+//!   Decoding is bit-exact, including NaN payloads. See [Conformance](#conformance).
+//! - `fuzzing`: `fuzzing` module with harness functions for `cargo fuzz` targets.
+//! - `heapless`: `heapless` module encoding into `heapless::Vec` with `Wrap::try_encode_to`, failing
+//!   instead of panicking when it is full.
+//! - `hex`: `Wrap::encode_hex`, `Wrap::decode_hex` and `Wrap::encoded_hex_display` to convert
+//!   to and from 0x-prefixed hexadecimal strings. It implies `alloc`.
+//! - `hooks`: `hooks` module notifying a global or per-call `CodecHook` of the type name and
+//!   number of bytes of every encoding and decoding, and of failures, e.g. to collect metrics.
+//! - `indexmap`: `indexmap` module implementing `DecodeLength` for wrapped `IndexMap`s and
+//!   `IndexSet`s, which encode in insertion order, and encoding maps sorted by the encodings of
+//!   their keys with `indexmap::canonical`. It implies `alloc`.
+//! - `json`: `json` module transcoding between JSON and SCALE through a type implementing
+//!   `Serialize`/`Deserialize`. It implies `std`, `encode` and `decode`.
+//! - `json-value`: `json_value` module encoding `serde_json::Value` fields as a tagged enum. It
+//!   implies `std`.
+//! - `lz4`: `compressed` module with `CompressedWrap`, encoding its value like `Wrap` and
+//!   compressing the result with LZ4 or another `Compression` algorithm, and limiting the
+//!   decompressed length when decoding.
+//! - `max-encoded-len`: `MaxWrappedLen` trait making `Wrap<T>` implement `MaxEncodedLen`. It
+//!   implies `encode`.
+//! - `proptest`: `check_conformance!` macro and `conformance::check_symmetry` checking values
+//!   generated by `proptest` against their `Encode`/`Decode` implementations and for symmetry. It
+//!   implies `testing`.
+//! - `rayon`: `batch` module encoding slices of values in parallel with `rayon`, to separate
+//!   buffers or to one contiguous buffer. It implies `std` and `encode`.
+//! - `scale-info`: `type_info` module synthesizing `scale-info` metadata for types implementing
+//!   `Deserialize`, by tracing them with `serde-reflection`. It implies `std`.
+//! - `serde-scale-0-1`: Builds on `serde_scale` 0.1 instead of 0.2, e.g. for dependency trees
+//!   pinned to it, and requires disabling default features. The API of this crate is the same with
+//!   either version, `serde_scale` being re-exported so that the `Read` and `Write` implemented by
+//!   the adapters can be named.
+//!   `cargo test --no-default-features --features std,encode,decode,serde-scale-0-1` runs the test
+//!   suite against it. ⚠ `serde_scale` 0.1 fails to build without `std` if any crate in the
+//!   dependency tree enables `serde/std`.
+//! - `serde-scale-0-2`: Builds on `serde_scale` 0.2. It is enabled by default, and exactly one of
+//!   it and `serde-scale-0-1` must be enabled, only the selected version of `serde_scale` being a
+//!   dependency.
+//! - `smallvec`: `EncodeLike` between `Wrap<smallvec::SmallVec<A>>` and `Wrap<Vec<A::Item>>`,
+//!   which encode alike whether the small vector is inline or spilled. It implies `alloc`.
+//! - `testing`: `conformance` module with assertions to check `Wrap` against `Encode`/`Decode`
+//!   implementations and to find asymmetric `serde` attributes in tests.
+//! - `tokio`: `Wrap::encode_to_async` and the `decode_from_async` methods of `Wrap` and
+//!   `DecodeOptions` to encode to `tokio::io::AsyncWrite` and decode from `tokio::io::AsyncRead`.
+//!   It implies `std`.
+//! - `tokio-codec`: `tokio_codec` module with `ScaleCodec`, a `tokio_util::codec` encoder and
+//!   decoder of length-prefixed frames for `Framed` transports. It implies `bytes`, `std`, `encode`
+//!   and `decode`.
+//! - `tracing`: `encode` and `decode` spans with the `serde_scale_wrap` target, recording the type
+//!   name, the number of bytes written or read and the error if any, along with `debug` events on
+//!   success and `warn` events on failure.
+//! - `typed`: `typed` module with `TypedWrap`, prefixing encodings with a fingerprint of the
+//!   structure of their type so that decoding them as another type fails. It implies `std`,
+//!   `encode` and `decode`.
+//! - `unsafe-optimizations`: `Wrap::from_ref`, `Wrap::from_mut`, `Wrap::wrap_slice` and
+//!   `Wrap::peel_slice`, lifting `#![forbid(unsafe_code)]` (see [Unsafe code](#unsafe-code)).
+//! - `wasm`: `wasm` module converting values to and from `js_sys::Uint8Array`s holding their
+//!   encodings, and `impl_wasm_scale!` adding `toScale` and `fromScale` methods to types exported
+//!   with `#[wasm_bindgen]`. It implies `alloc`, `encode` and `decode`.
 //!
 //! 🔖 Features enabled in build dependencies and proc-macros are also enabled for normal
 //! dependencies, which may cause `serde` to have its `std` feature on when it is not desired.
@@ -59,15 +281,268 @@
 //! - [serde-scale](https://crates.io/crates/serde-scale): SCALE encoding with `serde`
 
 #![deny(warnings)]
+#![cfg_attr(not(any(feature = "unsafe-optimizations", test)), forbid(unsafe_code))]
+// Tests need `unsafe` to install counting allocators, so they only deny it elsewhere.
+#![cfg_attr(all(test, not(feature = "unsafe-optimizations")), deny(unsafe_code))]
 #![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(feature = "allocator-api", feature(allocator_api))]
 
+#[cfg(any(feature = "alloc", test))]
 extern crate alloc;
 
+#[cfg(any(feature = "encode", feature = "decode"))]
+pub mod adapters;
+#[cfg(feature = "allocator-api")]
+mod allocator;
+#[cfg(feature = "bumpalo")]
+mod arena;
+#[cfg(all(feature = "arrayvec", feature = "encode"))]
+mod arrayvec;
+#[cfg(all(feature = "base64", any(feature = "encode", feature = "decode")))]
+pub mod base64;
+#[cfg(feature = "axum")]
+pub mod axum;
+#[cfg(feature = "rayon")]
+pub mod batch;
+#[cfg(all(feature = "bigint", any(feature = "encode", feature = "decode")))]
+pub mod bigint;
+#[cfg(all(feature = "alloc", any(feature = "encode", feature = "decode")))]
+pub mod binary_heap;
+#[cfg(all(feature = "encode", feature = "decode"))]
+mod bounds;
+#[cfg(all(feature = "alloc", feature = "decode"))]
+mod boxed;
+#[cfg(all(feature = "std", feature = "decode"))]
+mod buf_read;
+#[cfg(all(feature = "alloc", feature = "encode"))]
+mod by_encoding;
+#[cfg(all(feature = "std", any(feature = "encode", feature = "decode")))]
+mod byte_stream;
+#[cfg(all(feature = "bytes", any(feature = "encode", feature = "decode")))]
+pub mod bytes;
+#[cfg(feature = "cache")]
+mod cache;
+#[cfg(feature = "decode")]
+mod chained;
+#[cfg(feature = "decode")]
+mod checked;
+#[cfg(all(feature = "alloc", any(feature = "encode", feature = "decode")))]
+mod chunks;
+mod compact;
+#[cfg(feature = "lz4")]
+pub mod compressed;
+#[cfg(any(feature = "encode", feature = "decode"))]
+mod counting;
+#[cfg(any(
+    all(test, feature = "alloc", feature = "encode", feature = "decode"),
+    feature = "testing",
+))]
+pub mod conformance;
+#[cfg(feature = "decode")]
+mod de;
+#[cfg(all(feature = "alloc", feature = "decode"))]
+mod decode_buffer;
+#[cfg(all(feature = "alloc", feature = "decode"))]
+mod deserializer;
+#[cfg(all(feature = "digest", any(feature = "encode", feature = "decode")))]
+pub mod digest;
+#[cfg(all(feature = "alloc", feature = "encode"))]
+pub mod dump;
+#[cfg(all(feature = "embedded-io", any(feature = "encode", feature = "decode")))]
+pub mod eio;
+mod error;
+#[cfg(all(feature = "std", any(feature = "encode", feature = "decode")))]
+pub mod frame;
+#[cfg(all(feature = "alloc", any(feature = "encode", feature = "decode")))]
+pub mod ext;
+#[cfg(all(feature = "alloc", any(feature = "encode", feature = "decode")))]
+pub mod fallback;
+#[cfg(all(feature = "alloc", any(feature = "encode", feature = "decode")))]
+mod fixed_width;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+#[cfg(all(feature = "alloc", feature = "decode"))]
+mod header;
+#[cfg(all(feature = "heapless", feature = "encode"))]
+pub mod heapless;
+#[cfg(all(feature = "hex", any(feature = "encode", feature = "decode")))]
+mod hex;
+#[cfg(all(feature = "hooks", any(feature = "encode", feature = "decode")))]
+pub mod hooks;
+#[cfg(all(feature = "indexmap", any(feature = "encode", feature = "decode")))]
+pub mod indexmap;
+#[cfg(feature = "encode")]
+pub mod infallible;
+#[cfg(all(feature = "std", any(feature = "encode", feature = "decode")))]
+pub mod interned;
+#[cfg(feature = "json")]
+pub mod json;
+#[cfg(feature = "json-value")]
+pub mod json_value;
+#[cfg(all(feature = "alloc", feature = "encode"))]
+mod key;
+#[cfg(all(feature = "alloc", any(feature = "encode", feature = "decode")))]
+mod lazy;
+#[cfg(feature = "encode")]
+mod limited;
+#[cfg(any(feature = "encode", feature = "decode"))]
+mod logging;
+#[cfg(all(feature = "alloc", feature = "decode"))]
+mod map_decoder;
+#[cfg(feature = "encode")]
+mod map_encoder;
+#[cfg(any(feature = "encode", feature = "decode"))]
+mod map_keys;
+#[cfg(feature = "max-encoded-len")]
+mod max_len;
+#[cfg(all(feature = "alloc", any(feature = "encode", feature = "decode")))]
+mod open_enum;
+#[cfg(any(feature = "encode", feature = "decode"))]
+mod options;
+#[cfg(all(feature = "alloc", any(feature = "encode", feature = "decode")))]
+pub mod prelude;
+#[cfg(all(feature = "alloc", any(feature = "encode", feature = "decode")))]
+mod raw;
+#[cfg(all(feature = "alloc", feature = "encode", feature = "decode"))]
+pub mod remote;
+#[cfg(feature = "debug-roundtrip-check")]
+pub mod roundtrip_check;
+mod scale;
+#[cfg(feature = "decode")]
+mod scratch;
+#[cfg(feature = "encode")]
+mod ser;
+#[cfg(feature = "encode")]
+mod serializer;
+#[cfg(all(feature = "smallvec", feature = "encode"))]
+mod smallvec;
+#[cfg(all(feature = "alloc", any(feature = "encode", feature = "decode")))]
+mod storage_option;
+#[cfg(all(feature = "alloc", feature = "decode"))]
+mod streaming;
+#[cfg(all(feature = "tokio", any(feature = "encode", feature = "decode")))]
+mod tokio;
+#[cfg(feature = "tokio-codec")]
+pub mod tokio_codec;
+#[cfg(feature = "scale-info")]
+pub mod type_info;
+#[cfg(feature = "typed")]
+pub mod typed;
+#[cfg(all(feature = "alloc", feature = "decode"))]
+mod versioned;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+#[cfg(feature = "encode")]
+use adapters::OutputToWrite;
+#[cfg(all(feature = "alloc", feature = "decode"))]
+use adapters::InputToRead;
+#[cfg(all(feature = "alloc", feature = "encode"))]
 use alloc::vec::Vec;
-use core::convert::Infallible;
-use parity_scale_codec::{Decode, Encode, EncodeLike, Error, Input, Output};
+use core::{
+    borrow::{Borrow, BorrowMut},
+    cmp::Ordering,
+    fmt::{self, Display},
+    iter::FromIterator,
+    ops::{Deref, DerefMut},
+    str::FromStr,
+};
 use serde::{Deserialize, Serialize};
-use serde_scale::{Bytes, Read, Write};
+
+#[cfg(feature = "encode")]
+use crate::scale::Write;
+#[cfg(feature = "encode")]
+use parity_scale_codec::{Encode, EncodeLike, Output};
+
+#[cfg(all(feature = "alloc", feature = "decode"))]
+use parity_scale_codec::{Decode, Input};
+#[cfg(all(feature = "alloc", feature = "encode"))]
+use infallible::InfallibleSerialize;
+#[cfg(all(feature = "alloc", feature = "decode"))]
+use serde::de::DeserializeOwned;
+
+#[cfg(feature = "allocator-api")]
+pub use allocator::decode_in;
+#[cfg(feature = "bumpalo")]
+pub use arena::decode_in_arena;
+#[cfg(all(feature = "encode", feature = "decode"))]
+pub use bounds::ScaleSerde;
+#[cfg(all(feature = "alloc", feature = "encode"))]
+pub use by_encoding::{compare_encodings, ByEncoding};
+#[cfg(all(feature = "std", feature = "decode"))]
+pub use byte_stream::decode_bytes_to_writer;
+#[cfg(all(feature = "std", feature = "encode"))]
+pub use byte_stream::encode_bytes_from_reader;
+#[cfg(feature = "cache")]
+pub use cache::CachedDecoder;
+#[cfg(feature = "decode")]
+pub use chained::ChainedInput;
+#[cfg(feature = "decode")]
+pub use checked::CheckedInput;
+#[cfg(feature = "decode")]
+pub use compact::read_compact_len;
+#[cfg(feature = "encode")]
+pub use compact::write_compact_len;
+#[cfg(feature = "decode")]
+pub use counting::CountingInput;
+#[cfg(feature = "encode")]
+pub use counting::CountingOutput;
+#[cfg(all(feature = "alloc", feature = "decode"))]
+pub use decode_buffer::DecodeBuffer;
+#[cfg(all(feature = "alloc", feature = "decode"))]
+pub use deserializer::WrapDeserializer;
+pub use error::Error;
+#[cfg(all(feature = "alloc", any(feature = "encode", feature = "decode")))]
+pub use fallback::{LengthPrefixed, UnknownVariantFallback};
+#[cfg(all(feature = "alloc", any(feature = "encode", feature = "decode")))]
+pub use fixed_width::FixedWidthKey;
+#[cfg(all(feature = "std", feature = "decode"))]
+pub use interned::decode_interned;
+#[cfg(all(feature = "std", any(feature = "encode", feature = "decode")))]
+pub use interned::{HashInterner, Interned, StringInterner};
+#[cfg(all(feature = "alloc", feature = "encode"))]
+pub use key::{encode_key, try_encode_key, KeyBuilder, KeyHasher};
+#[cfg(all(feature = "alloc", any(feature = "encode", feature = "decode")))]
+pub use lazy::LazyWrap;
+#[cfg(feature = "encode")]
+pub use limited::LimitedOutput;
+#[cfg(all(feature = "alloc", feature = "decode"))]
+pub use map_decoder::MapDecoder;
+#[cfg(feature = "encode")]
+pub use map_encoder::{encode_map_iter, encode_map_iter_checked};
+#[cfg(any(feature = "encode", feature = "decode"))]
+pub use map_keys::MapKeyShapes;
+#[cfg(feature = "max-encoded-len")]
+pub use max_len::MaxWrappedLen;
+#[cfg(all(feature = "alloc", any(feature = "encode", feature = "decode")))]
+pub use open_enum::OpenEnum;
+#[cfg(feature = "decode")]
+pub use options::DecodeOptions;
+#[cfg(feature = "encode")]
+pub use options::EncodeOptions;
+#[cfg(all(feature = "alloc", any(feature = "encode", feature = "decode")))]
+pub use raw::RawScale;
+#[cfg(feature = "encode")]
+pub use serializer::WrapSerializer;
+#[cfg(all(feature = "alloc", any(feature = "encode", feature = "decode")))]
+pub use storage_option::StorageOptionWrap;
+#[cfg(all(feature = "alloc", feature = "decode"))]
+pub use streaming::StreamingDecoder;
+#[cfg(all(feature = "alloc", feature = "decode"))]
+pub use versioned::VersionedDecoder;
+
+/// `serde_scale`, in the version selected by the `serde-scale-0-1` and `serde-scale-0-2` features
+///
+/// The adapters of this crate implement the `Read` and `Write` of this version, and the errors of
+/// this crate hold its `Error`.
+#[cfg(not(feature = "serde-scale-0-1"))]
+pub use serde_scale;
+/// `serde_scale`, in the version selected by the `serde-scale-0-1` and `serde-scale-0-2` features
+///
+/// The adapters of this crate implement the `Read` and `Write` of this version, and the errors of
+/// this crate hold its `Error`.
+#[cfg(feature = "serde-scale-0-1")]
+pub use serde_scale_0_1 as serde_scale;
 
 /// Wrapper for types serializable with `serde` to support serialization with `Encode`/`Decode`
 ///
@@ -76,96 +551,2099 @@ use serde_scale::{Bytes, Read, Write};
 ///
 /// ⚠ The `Encode` implementation panics if the serializer returns an error (e.g. when attempting
 /// to serialize a floating point number) because `Encode` methods do not return `Result`.
+///
+/// This includes a `RefCell` that is mutably borrowed and a `Mutex` or `RwLock` that is
+/// poisoned, which `serde` cannot serialize. The panic message names the innermost struct field
+/// holding them, and `try_encode` returns them as [`Error::AlreadyBorrowed`] and
+/// [`Error::LockPoisoned`].
+///
+/// `Wrap` is transparent to `serde`: `Wrap<T>` serializes and deserializes exactly like `T` in
+/// any format, including formats that represent newtype structs explicitly.
 #[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(transparent)]
+#[repr(transparent)]
 pub struct Wrap<T>(pub T);
 
-impl<T: Serialize> Encode for Wrap<T> {
+impl<T> Wrap<T> {
+    /// Wraps `value`
+    ///
+    /// This can be used in `const` contexts:
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::Wrap;
+    ///
+    /// static WRAPPED: Wrap<u32> = Wrap::new(7);
+    /// assert_eq!(WRAPPED.into_inner(), 7);
+    /// ```
+    pub const fn new(value: T) -> Self {
+        Wrap(value)
+    }
+
+    /// Returns the wrapped value
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::Wrap;
+    ///
+    /// assert_eq!(Wrap::new("foo").into_inner(), "foo");
+    /// ```
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+
+    /// Returns a reference to the wrapped value
+    pub fn get(&self) -> &T {
+        &self.0
+    }
+
+    /// Returns a mutable reference to the wrapped value
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::Wrap;
+    ///
+    /// let mut wrapped = Wrap::new(1);
+    /// *wrapped.get_mut() += 1;
+    /// assert_eq!(*wrapped.get(), 2);
+    /// ```
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+
+    /// Views a reference to a value as a reference to a `Wrap`, without copying
+    ///
+    /// This requires the `unsafe-optimizations` feature. `Wrap(&value)` encodes and decodes like
+    /// `Wrap(value)` does and needs no `unsafe` code.
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::Wrap;
+    ///
+    /// let wrapped: &Wrap<u32> = Wrap::from_ref(&7);
+    /// assert_eq!(wrapped.0, 7);
+    /// ```
+    #[cfg(feature = "unsafe-optimizations")]
+    pub fn from_ref(value: &T) -> &Self {
+        // SAFETY: `Wrap<T>` is `repr(transparent)` over `T`, so both have the same layout and
+        // `&T` can be reinterpreted as `&Wrap<T>` with the same lifetime.
+        unsafe { &*(value as *const T as *const Self) }
+    }
+
+    /// Views a mutable reference to a value as a mutable reference to a `Wrap`, without copying
+    ///
+    /// This requires the `unsafe-optimizations` feature.
+    #[cfg(feature = "unsafe-optimizations")]
+    pub fn from_mut(value: &mut T) -> &mut Self {
+        // SAFETY: `Wrap<T>` is `repr(transparent)` over `T`, and the exclusive borrow is carried
+        // over to the result.
+        unsafe { &mut *(value as *mut T as *mut Self) }
+    }
+
+    /// Views a slice of values as a slice of `Wrap`, without copying
+    ///
+    /// This requires the `unsafe-optimizations` feature. `Wrap(values)` encodes the values like
+    /// the slice of `Wrap` does and needs no `unsafe` code.
+    ///
+    /// ```rust
+    /// use parity_scale_codec::Encode;
+    /// use serde_scale_wrap::Wrap;
+    ///
+    /// let values = vec![1u8, 2];
+    /// assert_eq!(Wrap::wrap_slice(&values).encode(), values.encode());
+    /// ```
+    #[cfg(feature = "unsafe-optimizations")]
+    pub fn wrap_slice(values: &[T]) -> &[Self] {
+        // SAFETY: `Wrap<T>` is `repr(transparent)` over `T`, so `[T]` and `[Wrap<T>]` have the
+        // same layout and the same number of elements fits in the same memory.
+        unsafe { &*(values as *const [T] as *const [Self]) }
+    }
+
+    /// Views a slice of `Wrap` as a slice of the wrapped values, without copying
+    ///
+    /// This requires the `unsafe-optimizations` feature.
+    #[cfg(feature = "unsafe-optimizations")]
+    pub fn peel_slice(wrapped: &[Self]) -> &[T] {
+        // SAFETY: `Wrap<T>` is `repr(transparent)` over `T`, so `[Wrap<T>]` and `[T]` have the
+        // same layout.
+        unsafe { &*(wrapped as *const [Self] as *const [T]) }
+    }
+
+    /// Returns the SCALE encoding of the wrapped value
+    ///
     /// # Panics
     /// Panics if the serializer returns an error (e.g. when attempting to serialize a floating
     /// point number).
-    fn encode_to<O: Output + ?Sized>(&self, dst: &mut O) {
-        let mut serializer = serde_scale::Serializer::new(OutputToWrite(dst));
-        self.0.serialize(&mut serializer).unwrap();
+    #[cfg(all(feature = "alloc", feature = "encode"))]
+    pub fn to_bytes(&self) -> Vec<u8>
+    where
+        T: Serialize,
+    {
+        encode(&self.0)
     }
-}
 
-impl<T: Serialize> EncodeLike for Wrap<T> {}
+    /// Encodes the wrapped value to `dst`, or returns an error if it cannot be serialized or `dst`
+    /// fails to accept the bytes (e.g. when it runs out of capacity)
+    ///
+    /// Encoding stops at the first error, leaving `dst` partially written to. Unlike
+    /// `Encode::encode_to`, this never panics. See [`EncodeOptions`] to change the maximum nesting
+    /// depth.
+    #[cfg(feature = "encode")]
+    pub fn try_encode_to<O: TryOutput + ?Sized>(&self, dst: &mut O) -> Result<(), Error>
+    where
+        T: Serialize,
+    {
+        logging::encode_logged::<T, _, _>(dst, |dst| try_serialize_to_try_output(&self.0, dst))
+    }
 
-impl<'de, T: Deserialize<'de>> Decode for Wrap<T> {
-    fn decode<I: Input>(input: &mut I) -> Result<Self, Error> {
-        let mut deserializer = serde_scale::Deserializer::new(InputToRead::new(input));
-        match T::deserialize(&mut deserializer) {
-            Ok(x) => Ok(Wrap(x)),
-            Err(serde_scale::Error::Io(e)) => Err(e),
-            Err(_) => Err("Deserialization failed".into()),
+    /// Returns the SCALE encoding of the wrapped value, or an error if it is longer than `max`
+    /// bytes or cannot be serialized
+    ///
+    /// Serialization stops as soon as the encoding exceeds `max` bytes, and no more than `max`
+    /// bytes are allocated for it. Like [`Wrap::try_encode_to`], this never panics.
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::{Error, Wrap};
+    ///
+    /// assert_eq!(Wrap([1u8; 4]).encode_bounded(4)?, [1; 4]);
+    /// let result = Wrap(vec![1u8; 4]).encode_bounded(4);
+    /// assert!(matches!(result, Err(Error::EncodingTooLarge { len: 5, max: 4 })));
+    /// # Ok::<_, Error>(())
+    /// ```
+    #[cfg(all(feature = "alloc", feature = "encode"))]
+    pub fn encode_bounded(&self, max: usize) -> Result<Vec<u8>, Error>
+    where
+        T: Serialize,
+    {
+        let mut dst = limited::BoundedVec::new(max);
+        self.try_encode_to(&mut dst)?;
+        Ok(dst.bytes)
+    }
+
+    /// Returns whether the SCALE encoding of the wrapped value is `bytes`, without allocating
+    ///
+    /// The encoding is compared to `bytes` as it is produced, and serialization stops at the
+    /// first difference. This returns `false` if the wrapped value cannot be serialized, where
+    /// `Encode::encode` would panic.
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::Wrap;
+    ///
+    /// assert!(Wrap("foo").encoded_eq(b"\x0cfoo"));
+    /// assert!(!Wrap("foo").encoded_eq(b"\x0cfo"));
+    /// ```
+    #[cfg(feature = "encode")]
+    pub fn encoded_eq(&self, bytes: &[u8]) -> bool
+    where
+        T: Serialize,
+    {
+        let mut dst = ComparingOutput(bytes);
+        try_serialize_to_try_output(&self.0, &mut dst).is_ok() && dst.0.is_empty()
+    }
+
+    /// Decodes a `Wrap` from `bytes`, failing if bytes are left over
+    ///
+    /// `TryFrom<&[u8]>` cannot be implemented because it would conflict with the implementation
+    /// derived from `From<T>`, so this is its equivalent.
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::{Error, Wrap};
+    ///
+    /// assert_eq!(Wrap::<u8>::from_bytes(&[1]).unwrap(), 1);
+    /// assert!(matches!(Wrap::<u8>::from_bytes(&[1, 2]), Err(Error::TrailingBytes { len: 1 })));
+    /// ```
+    #[cfg(all(feature = "alloc", feature = "decode"))]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error>
+    where
+        T: DeserializeOwned,
+    {
+        decode_all(bytes).map(Wrap)
+    }
+
+    /// Decodes a `Wrap` from `bytes` encoded with an older version of `T` lacking trailing struct
+    /// fields, failing if bytes are left over
+    ///
+    /// This follows these rules:
+    /// - When the input ends right before a field of a struct with named fields, this field and
+    ///   all the following ones are reported missing. This also holds for the remaining fields of
+    ///   the enclosing structs, if any.
+    /// - A missing field takes its default value if it is marked with `#[serde(default)]` or if
+    ///   its struct is. Otherwise, decoding fails with `Error::Deserialization`, as
+    ///   `Deserialize` implementations are then expected to do.
+    /// - Fields of tuples, tuple structs, struct variants and elements of sequences and maps are
+    ///   never reported missing, and the input ending anywhere else than before a struct field
+    ///   fails with `Error::Input` as usual.
+    ///
+    /// Complete encodings thus decode like [`Wrap::from_bytes`] does.
+    ///
+    /// ```rust
+    /// use serde::Deserialize;
+    /// use serde_scale_wrap::Wrap;
+    ///
+    /// #[derive(Debug, Deserialize, PartialEq)]
+    /// struct Account {
+    ///     id: u8,
+    ///     #[serde(default)]
+    ///     name: String,
+    /// }
+    ///
+    /// let account = Wrap::<Account>::decode_backward_compatible(&[7])?.into_inner();
+    /// assert_eq!(account, Account { id: 7, name: String::new() });
+    /// # Ok::<_, serde_scale_wrap::Error>(())
+    /// ```
+    #[cfg(all(feature = "alloc", feature = "decode"))]
+    pub fn decode_backward_compatible(bytes: &[u8]) -> Result<Self, Error>
+    where
+        T: DeserializeOwned,
+    {
+        let mut input = bytes;
+        let value = deserialize_backward_compatible(&mut input)?;
+        if input.is_empty() {
+            Ok(Wrap(value))
+        } else {
+            Err(Error::TrailingBytes { len: input.len() })
         }
     }
+
+    /// Decodes a `T` from `input` into `place`, reusing its allocations
+    ///
+    /// `place` is overwritten as if a fresh `T` were decoded, but the buffers of its strings and
+    /// sequences are reused when large enough. If an error is returned, `place` is left in a valid
+    /// but unspecified state. Derived implementations only do this with the `deserialize_in_place`
+    /// feature of `serde_derive`, and otherwise overwrite `place` with a fresh value.
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::Wrap;
+    ///
+    /// let mut names = Vec::<String>::with_capacity(4);
+    /// let encoded = serde_scale_wrap::encode(&["foo", "bar"][..]);
+    /// Wrap::decode_in_place(&mut &*encoded, &mut names)?;
+    /// assert_eq!(names, ["foo", "bar"]);
+    /// assert!(names.capacity() >= 4);
+    /// # Ok::<_, serde_scale_wrap::Error>(())
+    /// ```
+    #[cfg(all(feature = "alloc", feature = "decode"))]
+    pub fn decode_in_place<I: Input>(input: &mut I, place: &mut T) -> Result<(), Error>
+    where
+        T: DeserializeOwned,
+    {
+        deserialize_in_place_from(input, place)
+    }
+
+    /// Applies `f` to the wrapped value
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::Wrap;
+    ///
+    /// assert_eq!(Wrap::new(3).map(|x| x.to_string()), Wrap::new("3".to_string()));
+    /// ```
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> Wrap<U> {
+        Wrap(f(self.0))
+    }
+
+    /// Returns a `Wrap` borrowing the wrapped value
+    ///
+    /// This encodes like `self` without cloning the wrapped value. `AsRef::as_ref` is still
+    /// available to get a plain reference.
+    ///
+    /// ```rust
+    /// use parity_scale_codec::Encode;
+    /// use serde_scale_wrap::Wrap;
+    ///
+    /// let wrapped = Wrap::new(vec![1u8, 2]);
+    /// assert_eq!(wrapped.as_ref().encode(), wrapped.encode());
+    /// ```
+    pub fn as_ref(&self) -> Wrap<&T> {
+        Wrap(&self.0)
+    }
+
+    /// Returns a `Wrap` mutably borrowing the wrapped value
+    ///
+    /// `AsMut::as_mut` is still available to get a plain mutable reference.
+    pub fn as_mut(&mut self) -> Wrap<&mut T> {
+        Wrap(&mut self.0)
+    }
+}
+
+impl<T> From<T> for Wrap<T> {
+    fn from(value: T) -> Self {
+        Wrap(value)
+    }
 }
 
-struct OutputToWrite<'a, O: ?Sized>(&'a mut O);
+impl<T: Display> Display for Wrap<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
 
-impl<O: Output + ?Sized> Write for OutputToWrite<'_, O> {
-    type Error = Infallible;
+impl<T: FromStr> FromStr for Wrap<T> {
+    type Err = T::Err;
 
-    fn write(&mut self, bytes: &[u8]) -> Result<(), Infallible> {
-        self.0.write(bytes);
-        Ok(())
+    fn from_str(s: &str) -> Result<Self, T::Err> {
+        s.parse().map(Wrap)
+    }
+}
+
+/// Returns the SCALE encoding of the wrapped value, like [`Wrap::to_bytes`]
+///
+/// # Panics
+/// Panics if the serializer returns an error (e.g. when attempting to serialize a floating point
+/// number).
+#[cfg(all(feature = "alloc", feature = "encode"))]
+impl<T: Serialize> From<&Wrap<T>> for Vec<u8> {
+    fn from(wrapped: &Wrap<T>) -> Self {
+        wrapped.to_bytes()
+    }
+}
+
+impl<C: IntoIterator> IntoIterator for Wrap<C> {
+    type Item = C::Item;
+    type IntoIter = C::IntoIter;
+
+    fn into_iter(self) -> C::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a, C> IntoIterator for &'a Wrap<C>
+where
+    &'a C: IntoIterator,
+{
+    type Item = <&'a C as IntoIterator>::Item;
+    type IntoIter = <&'a C as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a, C> IntoIterator for &'a mut Wrap<C>
+where
+    &'a mut C: IntoIterator,
+{
+    type Item = <&'a mut C as IntoIterator>::Item;
+    type IntoIter = <&'a mut C as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        (&mut self.0).into_iter()
+    }
+}
+
+impl<A, C: FromIterator<A>> FromIterator<A> for Wrap<C> {
+    fn from_iter<I: IntoIterator<Item = A>>(iter: I) -> Self {
+        Wrap(iter.into_iter().collect())
+    }
+}
+
+impl<A, C: Extend<A>> Extend<A> for Wrap<C> {
+    fn extend<I: IntoIterator<Item = A>>(&mut self, iter: I) {
+        self.0.extend(iter)
+    }
+}
+
+/// Compares the wrapped value with an unwrapped one
+///
+/// The symmetric comparison of `T` with `Wrap<T>` is not possible because of coherence rules.
+///
+/// ```rust
+/// use serde_scale_wrap::Wrap;
+///
+/// assert_eq!(Wrap(3), 3);
+/// assert!(Wrap(3) < 4);
+/// ```
+impl<T: PartialEq> PartialEq<T> for Wrap<T> {
+    fn eq(&self, other: &T) -> bool {
+        self.0 == *other
+    }
+}
+
+/// Compares the wrapped value with an unwrapped one
+impl<T: PartialOrd> PartialOrd<T> for Wrap<T> {
+    fn partial_cmp(&self, other: &T) -> Option<Ordering> {
+        self.0.partial_cmp(other)
+    }
+}
+
+impl<T> Deref for Wrap<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Wrap<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T> AsRef<T> for Wrap<T> {
+    fn as_ref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> AsMut<T> for Wrap<T> {
+    fn as_mut(&mut self) -> &mut T {
+        &mut self.0
     }
 }
 
-struct InputToRead<'a, I: ?Sized> {
-    input: &'a mut I,
-    buffer: Vec<u8>,
+/// `Eq`, `Ord` and `Hash` for `Wrap<T>` are derived and thus consistent with those of `T`, as
+/// required by `Borrow`.
+impl<T> Borrow<T> for Wrap<T> {
+    fn borrow(&self) -> &T {
+        &self.0
+    }
+}
+
+/// `Eq`, `Ord` and `Hash` for `Wrap<T>` are derived and thus consistent with those of `T`, as
+/// required by `BorrowMut`.
+impl<T> BorrowMut<T> for Wrap<T> {
+    fn borrow_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
 }
 
-impl<'a, I: Input + ?Sized> InputToRead<'a, I> {
-    fn new(input: &'a mut I) -> Self {
-        InputToRead {
-            input,
-            buffer: Vec::new(),
+/// Implements `Encode`, `EncodeLike` and `MaxEncodedLen` for `Wrap<T>`, with `T` bound by the
+/// given traits
+#[cfg(feature = "encode")]
+macro_rules! impl_encode {
+    ($($bounds:tt)+) => {
+        impl<T: $($bounds)+> Encode for Wrap<T> {
+            /// # Panics
+            /// Panics if the serializer returns an error (e.g. when attempting to serialize a
+            /// floating point number, a mutably borrowed `RefCell` or a value nesting deeper than
+            /// [`EncodeOptions::DEFAULT_MAX_DEPTH`]).
+            fn encode_to<O: Output + ?Sized>(&self, dst: &mut O) {
+                serialize_to(&self.0, dst);
+            }
+
+            /// Encodes to a stack buffer, only allocating if the encoding is longer than 64 bytes
+            #[cfg(feature = "alloc")]
+            fn using_encoded<R, F: FnOnce(&[u8]) -> R>(&self, f: F) -> R {
+                let mut dst = StackOutput::new();
+                self.encode_to(&mut dst);
+                f(dst.as_slice())
+            }
+        }
+
+        impl<T: $($bounds)+> EncodeLike for Wrap<T> {}
+
+        /// Lets a borrowed value be passed where a `Wrap<T>` is expected, e.g. as the key of a
+        /// storage map
+        impl<'a, T: $($bounds)+> EncodeLike<Wrap<T>> for Wrap<&'a T> where Wrap<&'a T>: Encode {}
+
+        #[cfg(feature = "max-encoded-len")]
+        impl<T: $($bounds)+ + MaxWrappedLen> parity_scale_codec::MaxEncodedLen for Wrap<T> {
+            fn max_encoded_len() -> usize {
+                T::max_wrapped_len()
+            }
         }
+    };
+}
+
+#[cfg(all(feature = "encode", not(feature = "deny-panicking-encode")))]
+impl_encode!(Serialize);
+
+#[cfg(feature = "deny-panicking-encode")]
+impl_encode!(infallible::InfallibleSerialize);
+
+/// Returns the SCALE encoding of `value`, like `Wrap(value).encode()`
+///
+/// # Panics
+/// Panics if the serializer returns an error (e.g. when attempting to serialize a floating point
+/// number).
+#[cfg(all(feature = "alloc", feature = "encode"))]
+pub fn encode<T: Serialize + ?Sized>(value: &T) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    serialize_to(value, &mut encoded);
+    encoded
+}
+
+/// Returns the SCALE encoding of `value`, statically known not to fail
+///
+/// See the [`infallible`] module.
+///
+/// ```rust,compile_fail
+/// serde_scale_wrap::encode_infallible(&(1u8, 1.5f32));
+/// ```
+#[cfg(all(feature = "alloc", feature = "encode"))]
+pub fn encode_infallible<T: InfallibleSerialize + ?Sized>(value: &T) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    infallible::encode_infallible_to(value, &mut encoded);
+    encoded
+}
+
+/// Returns the SCALE encoding of `value`, or an error if it cannot be serialized
+#[cfg(all(feature = "alloc", feature = "encode"))]
+pub fn try_encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+    let mut encoded = Vec::new();
+    logging::encode_logged::<T, _, _>(&mut encoded, |dst| try_serialize_to(value, dst))?;
+    Ok(encoded)
+}
+
+/// Decodes a `T` from the beginning of `bytes`, like `Wrap::<T>::decode`
+///
+/// The bytes following the decoded value are ignored. See [`decode_all`] to reject them.
+#[cfg(all(feature = "alloc", feature = "decode"))]
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    deserialize_from_slice(&mut &*bytes)
+}
+
+/// Decodes a `T` from `bytes`, failing if bytes are left over
+///
+/// ```rust
+/// use serde_scale_wrap::{decode_all, Error};
+///
+/// assert_eq!(decode_all::<u8>(&[1]).unwrap(), 1);
+/// assert!(matches!(decode_all::<u8>(&[1, 2]), Err(Error::TrailingBytes { len: 1 })));
+/// ```
+#[cfg(all(feature = "alloc", feature = "decode"))]
+pub fn decode_all<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    let mut input = bytes;
+    let value = deserialize_from_slice(&mut input)?;
+    if input.is_empty() {
+        Ok(value)
+    } else {
+        Err(Error::TrailingBytes { len: input.len() })
+    }
+}
+
+/// Serializes `value` to `dst` like `Wrap` does
+///
+/// # Panics
+/// Panics if the serializer returns an error.
+#[cfg(feature = "encode")]
+fn serialize_to<T: Serialize + ?Sized, O: Output + ?Sized>(value: &T, dst: &mut O) {
+    if let Err(e) = logging::encode_logged::<T, _, _>(dst, |dst| try_serialize_to(value, dst)) {
+        panic!("{}", e);
+    }
+}
+
+/// Serializes `value` to `dst` like `Wrap` does
+///
+/// `dst` may have been partially written to if an error is returned.
+#[cfg(all(feature = "encode", not(feature = "erased-output")))]
+fn try_serialize_to<T, O>(value: &T, dst: &mut O) -> Result<(), Error>
+where
+    T: Serialize + ?Sized,
+    O: Output + ?Sized,
+{
+    let mut serializer = crate::scale::Serializer::new(OutputToWrite::new(dst));
+    ser::serialize(value, &mut serializer, EncodeOptions::default(), Error::Serialization)
+}
+
+/// Serializes `value` to `dst` like `Wrap` does
+///
+/// `dst` may have been partially written to if an error is returned.
+#[cfg(all(feature = "encode", feature = "erased-output"))]
+fn try_serialize_to<T, O>(value: &T, dst: &mut O) -> Result<(), Error>
+where
+    T: Serialize + ?Sized,
+    O: Output + ?Sized,
+{
+    try_serialize_to_erased(value, &mut ErasedOutput(dst))
+}
+
+// Serializing to `dyn Output` instantiates the serializer once per `T` instead of once per `T` and
+// output type. Encoding 4 types to 3 kinds of output in a size-optimized build with LTO shrinks the
+// encoding code from 8.2 KB to 6.9 KB, but encoding to a `Vec<u8>` becomes over twice as slow
+// (see `benches/encode.rs`) as every write goes through a virtual call.
+#[cfg(all(feature = "encode", feature = "erased-output"))]
+fn try_serialize_to_erased<T>(value: &T, dst: &mut dyn Output) -> Result<(), Error>
+where
+    T: Serialize + ?Sized,
+{
+    let mut serializer = crate::scale::Serializer::new(OutputToWrite::new(dst));
+    ser::serialize(value, &mut serializer, EncodeOptions::default(), Error::Serialization)
+}
+
+/// Serializes `value` to `dst` like [`Wrap::try_encode_to`] does
+#[cfg(feature = "encode")]
+fn try_serialize_to_try_output<T, O>(value: &T, dst: &mut O) -> Result<(), Error>
+where
+    T: Serialize + ?Sized,
+    O: TryOutput + ?Sized,
+{
+    try_serialize_to_try_output_with(value, dst, EncodeOptions::default())
+}
+
+/// Serializes `value` to `dst` like [`Wrap::try_encode_to`] does, with `options`
+#[cfg(all(feature = "encode", not(feature = "erased-output")))]
+fn try_serialize_to_try_output_with<T, O>(
+    value: &T,
+    dst: &mut O,
+    options: EncodeOptions,
+) -> Result<(), Error>
+where
+    T: Serialize + ?Sized,
+    O: TryOutput + ?Sized,
+{
+    let mut serializer = crate::scale::Serializer::new(TryOutputToWrite(dst));
+    ser::serialize(value, &mut serializer, options, Error::from_serialization)
+}
+
+/// Serializes `value` to `dst` like [`Wrap::try_encode_to`] does, with `options`
+#[cfg(all(feature = "encode", feature = "erased-output"))]
+fn try_serialize_to_try_output_with<T, O>(
+    value: &T,
+    mut dst: &mut O,
+    options: EncodeOptions,
+) -> Result<(), Error>
+where
+    T: Serialize + ?Sized,
+    O: TryOutput + ?Sized,
+{
+    try_serialize_to_try_output_erased(value, &mut dst, options)
+}
+
+#[cfg(all(feature = "encode", feature = "erased-output"))]
+fn try_serialize_to_try_output_erased<T>(
+    value: &T,
+    dst: &mut dyn TryOutput,
+    options: EncodeOptions,
+) -> Result<(), Error>
+where
+    T: Serialize + ?Sized,
+{
+    let mut serializer = crate::scale::Serializer::new(TryOutputToWrite(dst));
+    ser::serialize(value, &mut serializer, options, Error::from_serialization)
+}
+
+/// Returns the encoding of `value` produced by `Wrap`
+#[cfg(any(
+    all(test, feature = "alloc", feature = "encode"),
+    feature = "testing",
+    all(feature = "debug-roundtrip-check", debug_assertions),
+))]
+fn try_to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+    let mut encoded = Vec::new();
+    try_serialize_to(value, &mut encoded)?;
+    Ok(encoded)
+}
+
+/// Deserializes a `T` from `input` like `Wrap` does
+#[cfg(all(feature = "alloc", feature = "decode"))]
+fn deserialize_from<'de, T, I>(input: &mut I) -> Result<T, Error>
+where
+    T: Deserialize<'de>,
+    I: Input + ?Sized,
+{
+    logging::decode_logged(input, |input| deserialize_from_unlogged(input))
+}
+
+/// Deserializes a `T` from `input` into `place` like `Wrap` does, reusing its allocations
+#[cfg(all(feature = "alloc", feature = "decode"))]
+fn deserialize_in_place_from<'de, T, I>(input: &mut I, place: &mut T) -> Result<(), Error>
+where
+    T: Deserialize<'de>,
+    I: Input + ?Sized,
+{
+    logging::decode_logged(input, |input| {
+        let mut deserializer = crate::scale::Deserializer::new(InputToRead::new(input));
+        T::deserialize_in_place(de::Deserializer::new(&mut deserializer), &mut *place)
+            .map_err(Error::from_deserialization)?;
+        // Logged as decoding a `&mut T`
+        Ok(place)
+    })
+    .map(|_| ())
+}
+
+/// Deserializes a `T` from `input` like `Wrap` does, without logging
+#[cfg(all(feature = "alloc", feature = "decode"))]
+fn deserialize_from_unlogged<'de, T, I>(input: &mut I) -> Result<T, Error>
+where
+    T: Deserialize<'de>,
+    I: Input + ?Sized,
+{
+    deserialize_from_unlogged_with(input, DecodeOptions::default())
+}
+
+/// Deserializes a `T` from `input` with `options`, without logging
+#[cfg(all(feature = "alloc", feature = "decode"))]
+fn deserialize_from_unlogged_with<'de, T, I>(
+    input: &mut I,
+    options: DecodeOptions,
+) -> Result<T, Error>
+where
+    T: Deserialize<'de>,
+    I: Input + ?Sized,
+{
+    let mut deserializer = crate::scale::Deserializer::new(InputToRead::new(input));
+    de::deserialize(&mut deserializer, options)
+}
+
+/// Deserializes a `T` from `input` like `Wrap` does, advancing `input` past the bytes read
+///
+/// This is faster than going through `Input`, whose implementation for slices cannot be inlined.
+#[cfg(all(feature = "alloc", feature = "decode"))]
+fn deserialize_from_slice<'de, T: Deserialize<'de>>(input: &mut &'de [u8]) -> Result<T, Error> {
+    deserialize_from_slice_with(input, DecodeOptions::default())
+}
+
+/// Deserializes a `T` from `input` with `options`, advancing `input` past the bytes read
+#[cfg(all(feature = "alloc", feature = "decode"))]
+fn deserialize_from_slice_with<'de, T>(
+    input: &mut &'de [u8],
+    options: DecodeOptions,
+) -> Result<T, Error>
+where
+    T: Deserialize<'de>,
+{
+    let len = input.len();
+    logging::decode_counted(|count| {
+        let mut deserializer = crate::scale::Deserializer::new(SliceRead(&mut *input));
+        let result = de::deserialize(&mut deserializer, options);
+        *count = len - input.len();
+        result
+    })
+}
+
+/// Decodes a `T` whose encoding is the whole of `bytes`, e.g. the payload of a `LengthPrefixed`,
+/// failing if bytes are left over
+///
+/// Unlike `decode_all`, a `RawScale` or `OpenEnum` value at the root takes all the bytes instead of
+/// failing.
+#[cfg(all(feature = "alloc", feature = "decode"))]
+fn decode_payload<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    let mut input = bytes;
+    let value = logging::decode_counted(|count| {
+        let mut deserializer = crate::scale::Deserializer::new(SliceRead(&mut input));
+        let result = T::deserialize(de::Deserializer::for_payload(&mut deserializer, bytes.len()))
+            .map_err(Error::from_deserialization);
+        *count = bytes.len() - input.len();
+        result
+    })?;
+    if input.is_empty() {
+        Ok(value)
+    } else {
+        Err(Error::TrailingBytes { len: input.len() })
+    }
+}
+
+/// Deserializes a `T` from `input` like `Wrap::decode_backward_compatible` does, advancing `input`
+/// past the bytes read
+#[cfg(all(feature = "alloc", feature = "decode"))]
+fn deserialize_backward_compatible<'de, T>(input: &mut &'de [u8]) -> Result<T, Error>
+where
+    T: Deserialize<'de>,
+{
+    let len = input.len();
+    let remaining = core::cell::Cell::new(len);
+    logging::decode_counted(|count| {
+        let read = TrackedSliceRead { read: SliceRead(&mut *input), remaining: &remaining };
+        let mut deserializer = crate::scale::Deserializer::new(read);
+        let result = T::deserialize(de::Deserializer::with_missing_trailing_fields(
+            &mut deserializer,
+            &remaining,
+        ))
+        .map_err(Error::from_deserialization);
+        *count = len - input.len();
+        result
+    })
+}
+
+/// Returns the encoding of `value` produced by `Wrap`
+///
+/// # Panics
+/// Panics if the serializer returns an error.
+#[cfg(any(all(test, feature = "alloc", feature = "encode"), feature = "testing"))]
+fn to_vec<T: Serialize + ?Sized>(value: &T) -> Vec<u8> {
+    match try_to_vec(value) {
+        Ok(encoded) => encoded,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+/// Requires the `alloc` feature. Without it, [`Wrap::decode_with_scratch`] decodes into a
+/// caller-provided buffer instead.
+#[cfg(all(feature = "alloc", feature = "decode"))]
+impl<'de, T: Deserialize<'de>> Decode for Wrap<T> {
+    fn decode<I: Input>(input: &mut I) -> Result<Self, parity_scale_codec::Error> {
+        Ok(Wrap(deserialize_from(input)?))
+    }
+}
+
+/// Destination of [`Wrap::try_encode_to`], able to reject bytes
+#[cfg(feature = "encode")]
+pub trait TryOutput {
+    /// Writes `bytes` or returns an error
+    ///
+    /// Nothing should be written if an error is returned.
+    fn try_write(&mut self, bytes: &[u8]) -> Result<(), Error>;
+}
+
+#[cfg(feature = "encode")]
+impl<O: TryOutput + ?Sized> TryOutput for &mut O {
+    fn try_write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        (**self).try_write(bytes)
     }
 }
 
-impl<'a, 'de, I: Input + ?Sized> Read<'de> for InputToRead<'a, I> {
+#[cfg(feature = "encode")]
+struct TryOutputToWrite<'a, O: ?Sized>(&'a mut O);
+
+#[cfg(feature = "encode")]
+impl<O: TryOutput + ?Sized> Write for TryOutputToWrite<'_, O> {
     type Error = Error;
 
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.0.try_write(bytes)
+    }
+}
+
+/// `Read` reading from a slice, which it advances past the bytes read
+///
+/// Strings and byte sequences are borrowed from the slice instead of being copied to a buffer.
+#[cfg(all(feature = "alloc", feature = "decode"))]
+struct SliceRead<'a, 'de>(&'a mut &'de [u8]);
+
+#[cfg(all(feature = "alloc", feature = "decode"))]
+impl<'de> SliceRead<'_, 'de> {
+    #[inline]
+    fn take(&mut self, n: usize) -> Result<&'de [u8], parity_scale_codec::Error> {
+        let bytes: &'de [u8] = self.0;
+        if n > bytes.len() {
+            return Err("Not enough data to fill buffer".into());
+        }
+        let (taken, rest) = bytes.split_at(n);
+        *self.0 = rest;
+        Ok(taken)
+    }
+}
+
+#[cfg(all(feature = "alloc", feature = "decode"))]
+impl<'de> crate::scale::Read<'de> for SliceRead<'_, 'de> {
+    type Error = parity_scale_codec::Error;
+
+    #[inline]
     fn read_map<R, F>(&mut self, n: usize, f: F) -> Result<R, Self::Error>
     where
-        F: FnOnce(Bytes<'de, '_>) -> R,
+        F: FnOnce(crate::scale::Bytes<'de, '_>) -> R,
     {
-        self.buffer.resize(n, 0);
-        self.input.read(&mut self.buffer)?;
-        Ok(f(Bytes::Temporary(&self.buffer)))
+        self.take(n).map(|bytes| f(crate::scale::Bytes::Persistent(bytes)))
+    }
+
+    #[inline]
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        buf.copy_from_slice(self.take(buf.len())?);
+        Ok(())
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use alloc::string::String;
-    use crate::Wrap;
-    use parity_scale_codec::{Decode, Encode};
-    use serde::{Deserialize, Serialize};
+/// `SliceRead` keeping track of the remaining length for other users
+#[cfg(all(feature = "alloc", feature = "decode"))]
+struct TrackedSliceRead<'a, 'b, 'de> {
+    read: SliceRead<'a, 'de>,
+    remaining: &'b core::cell::Cell<usize>,
+}
 
-    #[derive(Debug, Deserialize, PartialEq, Serialize)]
-    struct Foo {
-        x: i32,
-        s: String,
+#[cfg(all(feature = "alloc", feature = "decode"))]
+impl<'de> crate::scale::Read<'de> for TrackedSliceRead<'_, '_, 'de> {
+    type Error = parity_scale_codec::Error;
+
+    fn read_map<R, F>(&mut self, n: usize, f: F) -> Result<R, Self::Error>
+    where
+        F: FnOnce(crate::scale::Bytes<'de, '_>) -> R,
+    {
+        let result = self.read.read_map(n, f);
+        self.remaining.set(self.read.0.len());
+        result
     }
 
-    #[test]
-    fn foo_roundtrips() {
-        let original = Foo { x: 3, s: "foo".into() };
-        let serialized = Wrap(&original).encode();
-        let Wrap(deserialized) = Wrap::<Foo>::decode(&mut &*serialized).unwrap();
-        assert_eq!(original, deserialized);
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        let result = self.read.read_exact(buf);
+        self.remaining.set(self.read.0.len());
+        result
     }
+}
 
-    #[test]
-    fn foo_is_correctly_serialized() {
-        let original = Foo { x: 3, s: "foo".into() };
-        let wrapped_serialized = Wrap(&original).encode();
-        let serialized = serde_scale::to_vec(&original).unwrap();
-        assert_eq!(wrapped_serialized, serialized);
+/// `TryOutput` rejecting bytes that differ from the next expected ones
+///
+/// The error it returns is only meant to stop serialization and is discarded.
+#[cfg(feature = "encode")]
+struct ComparingOutput<'a>(&'a [u8]);
+
+#[cfg(feature = "encode")]
+impl TryOutput for ComparingOutput<'_> {
+    fn try_write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        match self.0.strip_prefix(bytes) {
+            Some(rest) => {
+                self.0 = rest;
+                Ok(())
+            }
+            None => Err(Error::CapacityExceeded { capacity: self.0.len() }),
+        }
+    }
+}
+
+/// `Output` forwarding to a possibly unsized output, so that it can be used as `dyn Output`
+#[cfg(all(feature = "encode", feature = "erased-output"))]
+struct ErasedOutput<'a, O: ?Sized>(&'a mut O);
+
+#[cfg(all(feature = "encode", feature = "erased-output"))]
+impl<O: Output + ?Sized> Output for ErasedOutput<'_, O> {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.write(bytes)
+    }
+
+    fn push_byte(&mut self, byte: u8) {
+        self.0.push_byte(byte)
+    }
+}
+
+/// Length of the stack buffer `Wrap::using_encoded` encodes to
+#[cfg(all(feature = "alloc", feature = "encode"))]
+const STACK_OUTPUT_LEN: usize = 64;
+
+/// `Output` writing to a stack buffer, moving to a `Vec` if the buffer is too small
+#[cfg(all(feature = "alloc", feature = "encode"))]
+struct StackOutput {
+    stack: [u8; STACK_OUTPUT_LEN],
+    len: usize,
+    heap: Option<Vec<u8>>,
+}
+
+#[cfg(all(feature = "alloc", feature = "encode"))]
+impl StackOutput {
+    fn new() -> Self {
+        StackOutput { stack: [0; STACK_OUTPUT_LEN], len: 0, heap: None }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        match &self.heap {
+            Some(heap) => heap,
+            None => &self.stack[..self.len],
+        }
+    }
+}
+
+#[cfg(all(feature = "alloc", feature = "encode"))]
+impl Output for StackOutput {
+    fn write(&mut self, bytes: &[u8]) {
+        if let Some(heap) = &mut self.heap {
+            heap.extend_from_slice(bytes);
+        } else if let Some(dst) = self.stack.get_mut(self.len..self.len + bytes.len()) {
+            dst.copy_from_slice(bytes);
+            self.len += bytes.len();
+        } else {
+            let mut heap = Vec::with_capacity(2 * (self.len + bytes.len()));
+            heap.extend_from_slice(&self.stack[..self.len]);
+            heap.extend_from_slice(bytes);
+            self.heap = Some(heap);
+        }
+    }
+}
+
+/// Number of bytes read at once when the length of the input is unknown
+#[cfg(any(
+    all(feature = "alloc", feature = "decode"),
+    all(feature = "std", feature = "encode"),
+))]
+const READ_CHUNK_LEN: usize = 4096;
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod core_tests {
+    use crate::Wrap;
+    use parity_scale_codec::{Encode, Output};
+
+    struct FixedOutput {
+        bytes: [u8; 16],
+        len: usize,
+    }
+
+    impl Output for FixedOutput {
+        fn write(&mut self, bytes: &[u8]) {
+            self.bytes[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+            self.len += bytes.len();
+        }
+    }
+
+    #[test]
+    fn encoding_to_fixed_buffer_works() {
+        let mut dst = FixedOutput { bytes: [0; 16], len: 0 };
+        Wrap((1u8, 0x0302u16, Some(4u32))).encode_to(&mut dst);
+        assert_eq!(dst.bytes[..dst.len], [1, 2, 3, 1, 4, 0, 0, 0]);
+    }
+
+    #[test]
+    fn fixed_buffer_roundtrips_with_scratch() {
+        let value = (7u64, [1u8, 2, 3], (true, -2i16));
+        let mut dst = FixedOutput { bytes: [0; 16], len: 0 };
+        Wrap(value).encode_to(&mut dst);
+        let mut input = &dst.bytes[..dst.len];
+        let decoded = Wrap::<(u64, [u8; 3], (bool, i16))>::decode_with_scratch(&mut input, &mut []);
+        assert_eq!(decoded.unwrap(), value);
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn encoded_eq_matches_comparison_of_encodings() {
+        let value = Wrap((1u8, 0x0302u16, [4u8, 5, 6], Some(7u32)));
+        let mut dst = FixedOutput { bytes: [0; 16], len: 0 };
+        value.encode_to(&mut dst);
+        let encoded = &dst.bytes[..dst.len];
+        assert!(value.encoded_eq(encoded));
+        for len in 0..encoded.len() {
+            assert!(!value.encoded_eq(&encoded[..len]));
+        }
+        assert!(!value.encoded_eq(&dst.bytes[..dst.len + 1]));
+        for i in 0..encoded.len() {
+            let mut differing = dst.bytes;
+            differing[i] ^= 0x80;
+            assert!(!value.encoded_eq(&differing[..dst.len]));
+        }
+    }
+
+    #[test]
+    fn encoded_eq_is_false_for_unserializable_values() {
+        assert!(!Wrap(1u128).encoded_eq(&[]));
+        assert!(!Wrap(1u128).encoded_eq(&[1; 16]));
+    }
+}
+
+// Public types are checked with concrete parameters that are themselves `Send`, `Sync` and
+// `Unpin`, so that a field making a type lose these traits (e.g. a raw pointer) fails to build.
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod auto_trait_tests {
+    use crate::adapters::{OutputToWrite, ReadToInput, WriteToOutput};
+    use crate::*;
+
+    fn assert_send<T: Send>() {}
+    fn assert_sync<T: Sync>() {}
+    fn assert_unpin<T: Unpin>() {}
+
+    fn assert_auto_traits<T: Send + Sync + Unpin>() {
+        assert_send::<T>();
+        assert_sync::<T>();
+        assert_unpin::<T>();
+    }
+
+    #[test]
+    fn core_types_are_send_sync_and_unpin() {
+        assert_auto_traits::<Wrap<u8>>();
+        assert_auto_traits::<Wrap<&[u8]>>();
+        assert_auto_traits::<Error>();
+        assert_auto_traits::<EncodeOptions>();
+        assert_auto_traits::<DecodeOptions>();
+        assert_auto_traits::<MapKeyShapes>();
+        assert_auto_traits::<ChainedInput<'static>>();
+        assert_auto_traits::<CheckedInput<&[u8]>>();
+        assert_auto_traits::<CountingInput<'static, &[u8]>>();
+        assert_auto_traits::<CountingOutput>();
+        assert_auto_traits::<LimitedOutput<'static, CountingOutput>>();
+        assert_auto_traits::<WrapSerializer<'static, CountingOutput>>();
+        assert_auto_traits::<OutputToWrite<'static, CountingOutput>>();
+        assert_auto_traits::<ReadToInput<'static, &[u8]>>();
+        assert_auto_traits::<WriteToOutput<OutputToWrite<'static, CountingOutput>>>();
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn allocating_types_are_send_sync_and_unpin() {
+        use alloc::vec::Vec;
+
+        assert_auto_traits::<ByEncoding<Vec<u8>>>();
+        assert_auto_traits::<DecodeBuffer>();
+        assert_auto_traits::<FixedWidthKey<Vec<u8>, 4>>();
+        assert_auto_traits::<KeyBuilder>();
+        assert_auto_traits::<LengthPrefixed<Vec<u8>>>();
+        assert_auto_traits::<OpenEnum<Vec<u8>>>();
+        assert_auto_traits::<RawScale>();
+        assert_auto_traits::<StorageOptionWrap<Vec<u8>>>();
+        assert_auto_traits::<WrapDeserializer<'static, &[u8]>>();
+        assert_auto_traits::<MapDecoder<'static, Vec<u8>, u8, &[u8]>>();
+        assert_auto_traits::<StreamingDecoder<Vec<u8>>>();
+        assert_auto_traits::<VersionedDecoder<Vec<u8>>>();
+        assert_auto_traits::<WriteToOutput<Vec<u8>>>();
+        assert_auto_traits::<crate::adapters::InputToRead<'static, &[u8]>>();
+        assert_auto_traits::<dump::Annotated>();
+        assert_auto_traits::<dump::Entry>();
+        assert_auto_traits::<dump::SizeBreakdown>();
+    }
+
+    // `LazyWrap` caches its value in a `OnceCell`, so it is deliberately not `Sync`.
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn lazy_wraps_are_send_and_unpin() {
+        assert_send::<LazyWrap<u8>>();
+        assert_unpin::<LazyWrap<u8>>();
+    }
+
+    #[cfg(feature = "cache")]
+    #[test]
+    fn cached_decoders_are_send_sync_and_unpin() {
+        assert_auto_traits::<CachedDecoder<u8>>();
+    }
+}
+
+// `parity-scale-codec` only implements `Encode` for `String` with its `std` feature.
+#[cfg(all(test, feature = "std", feature = "encode", feature = "decode"))]
+mod tests {
+    use alloc::{
+        collections::{BTreeMap, BinaryHeap, LinkedList, VecDeque},
+        format,
+        string::{String, ToString},
+        vec,
+        vec::Vec,
+    };
+    use crate::{
+        check_conformance,
+        conformance::{
+            assert_conforms, assert_rejects_extensions, assert_rejects_truncations,
+            assert_roundtrip,
+        },
+        Wrap,
+    };
+    use core::ops::{Range, RangeInclusive};
+    use parity_scale_codec::{Decode, Encode, Error, Input, Output};
+    use proptest::{
+        collection::{btree_map, vec},
+        prelude::{any, prop_oneof, Arbitrary, BoxedStrategy, Just, Strategy},
+    };
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Foo {
+        x: i32,
+        s: String,
+    }
+
+    crate::impl_infallible_serialize!(Foo { i32, String });
+
+    #[test]
+    fn foo_roundtrips() {
+        let original = Foo { x: 3, s: "foo".into() };
+        let serialized = Wrap(&original).encode();
+        let Wrap(deserialized) = Wrap::<Foo>::decode(&mut &*serialized).unwrap();
+        assert_eq!(original, deserialized);
+    }
+
+    #[test]
+    fn foo_is_correctly_serialized() {
+        let original = Foo { x: 3, s: "foo".into() };
+        let wrapped_serialized = Wrap(&original).encode();
+        let serialized = crate::scale::to_vec(&original).unwrap();
+        assert_eq!(wrapped_serialized, serialized);
+    }
+
+    #[test]
+    fn foo_roundtrips_with_helper() {
+        assert_roundtrip(&Foo { x: 3, s: "foo".into() });
+    }
+
+    static WRAPPED: Wrap<u32> = Wrap::new(7);
+
+    #[test]
+    fn wrapped_static_is_usable() {
+        assert_eq!(*WRAPPED.get(), 7);
+        assert_eq!(WRAPPED.encode(), 7u32.encode());
+    }
+
+    #[test]
+    fn value_converts_into_wrap() {
+        let wrapped: Wrap<String> = String::from("foo").into();
+        assert_eq!(wrapped.into_inner(), "foo");
+    }
+
+    #[test]
+    fn borrowed_wrap_encodes_like_owned_wrap() {
+        let wrapped = Wrap(Foo { x: 3, s: "foo".into() });
+        assert_eq!(wrapped.as_ref().encode(), wrapped.encode());
+    }
+
+    #[test]
+    fn mutably_borrowed_wrap_modifies_wrapped_value() {
+        let mut wrapped = Wrap(3u8);
+        *wrapped.as_mut().0 += 1;
+        assert_eq!(wrapped, Wrap(4));
+    }
+
+    #[test]
+    fn map_changes_payload_type() {
+        let wrapped = Wrap(Foo { x: 3, s: "foo".into() }).map(|foo| (foo.x, foo.s.len() as u8));
+        assert_eq!(wrapped, Wrap((3, 3)));
+        assert_eq!(wrapped.encode(), (3i32, 3u8).encode());
+    }
+
+    #[test]
+    fn wrap_compares_with_unwrapped_value() {
+        assert!(Wrap(3u8) == 3);
+        assert!(Wrap(3u8) != 4);
+        assert!(Wrap(3u8) < 4);
+        assert!(Wrap(String::from("b")) > String::from("a"));
+        assert!(Wrap(3u8) == Wrap(3));
+    }
+
+    #[test]
+    fn decoded_wrap_is_asserted_equal_to_unwrapped_value() {
+        let original = Foo { x: 3, s: "foo".into() };
+        let decoded = Wrap::<Foo>::decode(&mut &*Wrap(&original).encode()).unwrap();
+        assert_eq!(decoded, original);
+    }
+
+    #[test]
+    fn wraps_sort_by_mixed_comparison() {
+        let mut wraps = vec![Wrap(3u32), Wrap(1), Wrap(2)];
+        wraps.sort_by(|a, b| a.partial_cmp(&b.0).unwrap());
+        assert_eq!(wraps, [Wrap(1), Wrap(2), Wrap(3)]);
+        assert_eq!(wraps.binary_search_by(|w| w.partial_cmp(&2).unwrap()), Ok(1));
+    }
+
+    #[test]
+    fn wrap_is_displayed_like_wrapped_value() {
+        assert_eq!(Wrap(42u32).to_string(), "42");
+        assert_eq!(format!("{:>4}", Wrap("ab")), "  ab");
+    }
+
+    #[test]
+    fn wrap_is_parsed_like_wrapped_value() {
+        assert_eq!("42".parse::<Wrap<u32>>(), Ok(Wrap(42)));
+        assert_eq!("x".parse::<Wrap<u32>>(), "x".parse::<u32>().map(Wrap));
+    }
+
+    #[test]
+    fn wrap_debug_output_is_unchanged() {
+        assert_eq!(format!("{:?}", Wrap("a")), "Wrap(\"a\")");
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Outer {
+        foo: Wrap<Foo>,
+        n: Wrap<u8>,
+    }
+
+    #[test]
+    fn wrap_is_transparent_to_serde_scale() {
+        let foo = Foo { x: 3, s: "foo".into() };
+        let wrapped = crate::scale::to_vec(&Wrap(&foo)).unwrap();
+        assert_eq!(wrapped, crate::scale::to_vec(&foo).unwrap());
+        let Wrap(decoded) = crate::scale::from_slice::<Wrap<Foo>>(&wrapped).unwrap();
+        assert_eq!(decoded, foo);
+    }
+
+    #[test]
+    fn wrap_is_transparent_to_serde_json() {
+        let outer = Outer { foo: Wrap(Foo { x: 3, s: "foo".into() }), n: Wrap(1) };
+        let json = serde_json::to_string(&outer).unwrap();
+        assert_eq!(json, r#"{"foo":{"x":3,"s":"foo"},"n":1}"#);
+        assert_eq!(serde_json::from_str::<Outer>(&json).unwrap(), outer);
+    }
+
+    #[cfg(feature = "unsafe-optimizations")]
+    #[test]
+    fn reference_is_viewed_as_wrap() {
+        let foo = Foo { x: 3, s: "foo".into() };
+        let wrapped = Wrap::from_ref(&foo);
+        assert!(core::ptr::eq(&wrapped.0, &foo));
+        assert_eq!(wrapped.encode(), Wrap(&foo).encode());
+    }
+
+    #[cfg(feature = "unsafe-optimizations")]
+    #[test]
+    fn mutable_reference_is_viewed_as_wrap() {
+        let mut x = 3u8;
+        Wrap::from_mut(&mut x).0 += 1;
+        assert_eq!(x, 4);
+    }
+
+    #[cfg(feature = "unsafe-optimizations")]
+    #[test]
+    fn slices_are_viewed_as_wrapped_and_back() {
+        let foos = vec![Foo { x: 1, s: "a".into() }, Foo { x: 2, s: "b".into() }];
+        let wrapped = Wrap::wrap_slice(&foos);
+        assert_eq!(wrapped.len(), 2);
+        assert_eq!(wrapped.encode(), Wrap(&foos).encode());
+        let peeled = Wrap::peel_slice(wrapped);
+        assert!(core::ptr::eq(peeled, &*foos));
+        assert!(Wrap::<u8>::wrap_slice(&[]).is_empty());
+    }
+
+    #[test]
+    fn free_functions_roundtrip() {
+        let original = Foo { x: 3, s: "foo".into() };
+        let encoded = crate::encode(&original);
+        assert_eq!(encoded, Wrap(&original).encode());
+        assert_eq!(crate::try_encode(&original).unwrap(), encoded);
+        assert_eq!(crate::decode::<Foo>(&encoded).unwrap(), original);
+        assert_eq!(crate::decode_all::<Foo>(&encoded).unwrap(), original);
+    }
+
+    #[test]
+    fn free_functions_report_crate_errors() {
+        assert!(matches!(crate::try_encode(&1u128), Err(crate::Error::Serialization(_))));
+        assert!(matches!(crate::decode::<u16>(&[1]), Err(crate::Error::Input(_))));
+        assert_eq!(crate::decode::<u8>(&[1, 2]).unwrap(), 1);
+        assert!(matches!(
+            crate::decode_all::<u8>(&[1, 2, 3]),
+            Err(crate::Error::TrailingBytes { len: 2 }),
+        ));
+    }
+
+    #[cfg(not(feature = "float"))]
+    #[test]
+    fn floats_fail_to_serialize_by_default() {
+        assert!(matches!(crate::try_encode(&1.5f32), Err(crate::Error::Serialization(_))));
+        assert!(matches!(crate::decode::<f64>(&[0; 8]), Err(crate::Error::Deserialization(_))));
+    }
+
+    #[cfg(feature = "float")]
+    #[test]
+    fn floats_roundtrip_bit_exactly() {
+        let f32s = [
+            0.0,
+            -0.0,
+            1.5,
+            f32::MIN_POSITIVE / 2.0,
+            f32::from_bits(1),
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+            f32::NAN,
+            f32::from_bits(0x7fa0_0001),
+            f32::from_bits(0xffc1_2345),
+        ];
+        for &x in &f32s {
+            let encoded = crate::encode(&x);
+            assert_eq!(encoded, x.to_bits().to_le_bytes());
+            assert_eq!(crate::decode_all::<f32>(&encoded).unwrap().to_bits(), x.to_bits());
+        }
+        let f64s = [
+            0.0,
+            -0.0,
+            -2.25,
+            f64::MIN_POSITIVE / 2.0,
+            f64::from_bits(1),
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NAN,
+            f64::from_bits(0x7ff4_0000_0000_0001),
+            f64::from_bits(0xfff8_dead_beef_0000),
+        ];
+        for &x in &f64s {
+            let encoded = Wrap(x).encode();
+            assert_eq!(encoded, x.to_bits().to_le_bytes());
+            assert_eq!(crate::decode_all::<f64>(&encoded).unwrap().to_bits(), x.to_bits());
+        }
+    }
+
+    #[cfg(feature = "float")]
+    #[test]
+    fn floats_nested_in_values_roundtrip() {
+        let value = (vec![(1u8, -0.0f32)], Some(f64::NEG_INFINITY));
+        let encoded = crate::encode(&value);
+        assert_eq!(encoded.len(), 1 + 5 + 1 + 8);
+        let decoded = crate::decode_all::<(Vec<(u8, f32)>, Option<f64>)>(&encoded).unwrap();
+        assert!(decoded.0[0].1.is_sign_negative());
+        assert_eq!(decoded, value);
+    }
+
+    #[test]
+    fn wrap_converts_into_bytes() {
+        fn from<X>(x: X) -> Vec<u8>
+        where
+            Vec<u8>: From<X>,
+        {
+            Vec::from(x)
+        }
+        fn into<'a, T>(value: &'a T) -> Vec<u8>
+        where
+            &'a T: Into<Vec<u8>>,
+        {
+            value.into()
+        }
+        let wrapped = Wrap(Foo { x: 3, s: "foo".into() });
+        assert_eq!(from(&wrapped), wrapped.encode());
+        assert_eq!(into(&wrapped), wrapped.encode());
+        assert_eq!(wrapped.to_bytes(), wrapped.encode());
+    }
+
+    #[test]
+    fn from_bytes_rejects_trailing_bytes() {
+        let original = Foo { x: 3, s: "foo".into() };
+        let mut bytes = Wrap(&original).encode();
+        assert_eq!(Wrap::<Foo>::from_bytes(&bytes).unwrap(), original);
+        bytes.push(0);
+        assert!(matches!(
+            Wrap::<Foo>::from_bytes(&bytes),
+            Err(crate::Error::TrailingBytes { len: 1 }),
+        ));
+    }
+
+    #[test]
+    fn wrapped_collection_is_iterated() {
+        let mut wrapped = Wrap(vec![1u8, 2, 3]);
+        for x in &mut wrapped {
+            *x *= 2;
+        }
+        let mut sum = 0;
+        for x in &wrapped {
+            sum += x;
+        }
+        assert_eq!(sum, 12);
+        assert_eq!(wrapped.into_iter().collect::<Vec<_>>(), [2, 4, 6]);
+    }
+
+    #[test]
+    fn wrapped_collection_is_collected_and_extended() {
+        let mut wrapped = (0u8..2).map(|i| (i, u32::from(i))).collect::<Wrap<BTreeMap<_, _>>>();
+        wrapped.extend(vec![(5, 50)]);
+        assert_eq!(wrapped.encode(), wrapped.0.encode());
+        assert_eq!(wrapped.keys().copied().collect::<Vec<_>>(), [0, 1, 5]);
+    }
+
+    #[test]
+    fn inner_value_is_reachable_through_deref() {
+        let mut wrapped = Wrap(Foo { x: 3, s: "foo".into() });
+        assert_eq!(wrapped.s.len(), 3);
+        wrapped.x += 1;
+        assert_eq!(wrapped.0.x, 4);
+    }
+
+    #[test]
+    fn wrap_satisfies_as_ref_bounds() {
+        fn len(s: impl AsRef<String>) -> usize {
+            s.as_ref().len()
+        }
+        fn clear(mut s: impl AsMut<String>) -> String {
+            s.as_mut().clear();
+            s.as_mut().clone()
+        }
+        assert_eq!(len(Wrap(String::from("ab"))), 2);
+        assert_eq!(clear(Wrap(String::from("ab"))), "");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn wrapped_set_is_searchable_by_inner_value() {
+        use std::collections::HashSet;
+
+        let set = vec![Wrap(1u32), Wrap(5)].into_iter().collect::<HashSet<_>>();
+        assert!(set.contains(&5));
+        assert!(!set.contains(&2));
+    }
+
+    #[test]
+    fn integers_conform() {
+        assert_conforms(&0u8);
+        assert_conforms(&-3i16);
+        assert_conforms(&0xdead_beefu32);
+        assert_conforms(&i64::MIN);
+        assert_conforms(&u64::MAX);
+    }
+
+    #[test]
+    fn bool_conforms() {
+        assert_conforms(&true);
+        assert_conforms(&false);
+    }
+
+    #[test]
+    fn string_conforms() {
+        assert_conforms(&String::new());
+        assert_conforms(&String::from("foo"));
+    }
+
+    #[test]
+    fn option_conforms() {
+        assert_conforms(&None::<u32>);
+        assert_conforms(&Some(3u32));
+    }
+
+    #[test]
+    fn vec_conforms() {
+        assert_conforms(&vec![1u16, 2, 3]);
+        assert_conforms(&vec![0u8; 100]);
+    }
+
+    #[test]
+    fn vec_deque_conforms_in_logical_order() {
+        let mut ring = VecDeque::with_capacity(8);
+        ring.extend(0u32..6);
+        ring.drain(..4);
+        ring.extend(6..11);
+        let (front, back) = ring.as_slices();
+        assert!(!front.is_empty() && !back.is_empty());
+        let expected = (4u32..11).collect::<Vec<_>>();
+        assert_eq!(crate::encode(&ring), expected.encode());
+        assert_conforms(&ring);
+        assert_eq!(crate::decode_all::<VecDeque<u32>>(&expected.encode()).unwrap(), ring);
+    }
+
+    #[test]
+    fn linked_list_conforms() {
+        let list = (0u32..5).rev().collect::<LinkedList<_>>();
+        assert_eq!(crate::encode(&list), vec![4u32, 3, 2, 1, 0].encode());
+        assert_conforms(&list);
+        assert_conforms(&LinkedList::<u32>::new());
+    }
+
+    #[test]
+    fn binary_heap_conforms_in_buffer_order() {
+        let heap = BinaryHeap::from(vec![3u32, 9, 1, 7, 5]);
+        let buffer = heap.clone().into_vec();
+        assert_eq!(crate::encode(&heap), buffer.encode());
+        assert_eq!(crate::encode(&heap), heap.encode());
+        let decoded = crate::decode_all::<BinaryHeap<u32>>(&heap.encode()).unwrap();
+        assert_eq!(decoded.into_sorted_vec(), [1, 3, 5, 7, 9]);
+        let decoded = crate::decode_all::<BinaryHeap<u32>>(&buffer.encode()).unwrap();
+        assert_eq!(decoded.into_sorted_vec(), heap.into_sorted_vec());
+    }
+
+    #[test]
+    fn tuple_conforms() {
+        assert_conforms(&(1u8, String::from("a"), vec![Some(2u64)]));
+    }
+
+    #[test]
+    fn tuples_up_to_arity_8_conform() {
+        assert_conforms(&(1u8,));
+        assert_conforms(&(1u8, 2u16));
+        assert_conforms(&(1u8, 2u16, 3u32));
+        assert_conforms(&(1u8, 2u16, 3u32, 4u64));
+        assert_conforms(&(1u8, 2u16, 3u32, 4u64, true));
+        assert_conforms(&(1u8, 2u16, 3u32, 4u64, true, String::from("six")));
+        assert_conforms(&(1u8, 2u16, 3u32, 4u64, true, String::from("six"), Some(7i8)));
+        let eight = (1u8, 2u16, 3u32, 4u64, true, String::from("six"), Some(7i8), vec![8i16]);
+        assert_conforms(&eight);
+        assert_eq!(crate::encode(&eight), eight.encode());
+    }
+
+    #[test]
+    fn ranges_conform_as_start_then_end() {
+        assert_conforms(&(3u32..10));
+        assert_eq!(crate::encode(&(3u32..10)), (3u32, 10u32).encode());
+        assert_conforms(&(5u64..=u64::MAX));
+        assert_eq!(crate::encode(&(5u64..=9)), (5u64, 9u64).encode());
+    }
+
+    #[test]
+    fn inverted_ranges_are_decoded_as_data() {
+        let encoded = (10u32, 3u32).encode();
+        #[allow(clippy::reversed_empty_ranges)]
+        let inverted = 10u32..3;
+        assert_eq!(crate::decode_all::<Range<u32>>(&encoded).unwrap(), inverted);
+        assert_eq!(Range::<u32>::decode(&mut &*encoded).unwrap(), inverted);
+        let encoded = (10u64, 3u64).encode();
+        let decoded = crate::decode_all::<RangeInclusive<u64>>(&encoded).unwrap();
+        assert_eq!(decoded, RangeInclusive::<u64>::decode(&mut &*encoded).unwrap());
+        assert_eq!((decoded.start(), decoded.end(), decoded.is_empty()), (&10, &3, true));
+    }
+
+    #[test]
+    fn map_conforms() {
+        let map = (0u8..3).map(|i| (i, u32::from(i) * 100)).collect::<BTreeMap<_, _>>();
+        assert_conforms(&map);
+        assert_conforms(&BTreeMap::<u8, u8>::new());
+    }
+
+    #[test]
+    fn nested_map_conforms() {
+        let inner = (0u16..2).map(|i| (i, String::from("x"))).collect::<BTreeMap<_, _>>();
+        assert_conforms(&Some(vec![(inner, 1u8)]));
+    }
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    enum Shape {
+        Empty,
+        Circle(u32),
+        Rect { w: u16, h: u16 },
+        Group(Vec<Shape>),
+    }
+
+    crate::impl_infallible_serialize!(Shape { u32, u16, Vec<Shape> });
+
+    // Equivalent of `#[derive(Encode, Decode)]`, which this crate does not depend on.
+    impl Encode for Shape {
+        fn encode_to<O: Output + ?Sized>(&self, dst: &mut O) {
+            match self {
+                Shape::Empty => dst.push_byte(0),
+                Shape::Circle(r) => {
+                    dst.push_byte(1);
+                    r.encode_to(dst);
+                }
+                Shape::Rect { w, h } => {
+                    dst.push_byte(2);
+                    w.encode_to(dst);
+                    h.encode_to(dst);
+                }
+                Shape::Group(shapes) => {
+                    dst.push_byte(3);
+                    shapes.encode_to(dst);
+                }
+            }
+        }
+    }
+
+    impl Decode for Shape {
+        fn decode<I: Input>(input: &mut I) -> Result<Self, Error> {
+            match input.read_byte()? {
+                0 => Ok(Shape::Empty),
+                1 => Ok(Shape::Circle(Decode::decode(input)?)),
+                2 => Ok(Shape::Rect { w: Decode::decode(input)?, h: Decode::decode(input)? }),
+                3 => Ok(Shape::Group(Decode::decode(input)?)),
+                _ => Err("Invalid shape".into()),
+            }
+        }
+    }
+
+    impl Arbitrary for Shape {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Shape>;
+
+        fn arbitrary_with(_: ()) -> Self::Strategy {
+            let leaf = prop_oneof![
+                Just(Shape::Empty),
+                any::<u32>().prop_map(Shape::Circle),
+                (any::<u16>(), any::<u16>()).prop_map(|(w, h)| Shape::Rect { w, h }),
+            ];
+            leaf.prop_recursive(4, 32, 4, |inner| {
+                vec(inner, 0..4).prop_map(Shape::Group)
+            })
+            .boxed()
+        }
+    }
+
+    #[test]
+    fn random_integers_conform() {
+        check_conformance!(u8);
+        check_conformance!(u16);
+        check_conformance!(u32);
+        check_conformance!(u64);
+        check_conformance!(i8);
+        check_conformance!(i16);
+        check_conformance!(i32);
+        check_conformance!(i64);
+    }
+
+    #[test]
+    fn random_strings_conform() {
+        check_conformance!(String);
+    }
+
+    #[test]
+    fn random_options_conform() {
+        check_conformance!(Option<u32>);
+        check_conformance!(Option<String>);
+        check_conformance!(Option<Option<u8>>);
+    }
+
+    #[test]
+    fn random_vecs_conform() {
+        check_conformance!(Vec<u8>);
+        check_conformance!(Vec<Vec<u16>>);
+        check_conformance!(Vec<bool>);
+        // Lengths crossing the boundary between the one-byte and two-byte compact encodings
+        check_conformance!(Vec<u8>, vec(any::<u8>(), 60..70));
+    }
+
+    #[test]
+    fn random_tuples_conform() {
+        check_conformance!((u8, String));
+        check_conformance!((bool, Vec<u64>, Option<i16>, [u8; 4]));
+    }
+
+    #[test]
+    fn random_maps_conform() {
+        check_conformance!(BTreeMap<u32, String>);
+        check_conformance!(
+            BTreeMap<String, BTreeMap<u8, Vec<u8>>>,
+            btree_map(any::<String>(), btree_map(any::<u8>(), vec(any::<u8>(), 0..8), 0..8), 0..8)
+        );
+    }
+
+    #[test]
+    fn random_nested_enums_conform() {
+        check_conformance!(Shape);
+        check_conformance!(Vec<(Shape, Option<Shape>)>);
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Nested {
+        id: u32,
+        foo: Foo,
+        shapes: Vec<Shape>,
+        tags: BTreeMap<String, Option<u16>>,
+    }
+
+    crate::impl_infallible_serialize!(
+        Nested { u32, Foo, Vec<Shape>, BTreeMap<String, Option<u16>> }
+    );
+
+    fn nested() -> Nested {
+        Nested {
+            id: 7,
+            foo: Foo { x: -1, s: "bar".into() },
+            shapes: vec![Shape::Circle(3), Shape::Group(vec![Shape::Empty])],
+            tags: vec![(String::from("a"), Some(1)), (String::from("b"), None)]
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn nested_truncations_are_rejected() {
+        assert_rejects_truncations::<Nested>(&Wrap(nested()).encode());
+    }
+
+    #[test]
+    fn nested_extensions_are_rejected() {
+        assert_rejects_extensions::<Nested>(&Wrap(nested()).encode());
+    }
+
+    #[test]
+    fn slices_decode_like_inputs() {
+        let encoded = Wrap(nested()).encode();
+        for len in 0..=encoded.len() {
+            let mut input = &encoded[..len];
+            let from_input = Wrap::<Nested>::decode(&mut input).map(|Wrap(x)| x);
+            let from_slice = crate::decode::<Nested>(&encoded[..len]);
+            match (from_input, from_slice) {
+                (Ok(x), Ok(y)) => assert_eq!(x, y),
+                (Err(e), Err(f)) => assert_eq!(e, Error::from(f)),
+                (x, y) => panic!("Decoding {} byte(s) yields {:?} and {:?}", len, x, y),
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Prefix of 0 byte(s) out of 1 decoded successfully as ()")]
+    fn accepted_truncation_is_reported() {
+        assert_rejects_truncations::<()>(&[0]);
+    }
+
+    /// Input hiding its length, like a stream would
+    struct Stream<'a>(&'a [u8]);
+
+    impl Input for Stream<'_> {
+        fn remaining_len(&mut self) -> Result<Option<usize>, Error> {
+            Ok(None)
+        }
+
+        fn read(&mut self, into: &mut [u8]) -> Result<(), Error> {
+            self.0.read(into)
+        }
+    }
+
+    #[test]
+    fn forged_length_is_rejected_before_reading() {
+        let bytes = [0xfe, 0xff, 0xff, 0xff, 1, 2, 3];
+        assert!(Wrap::<Vec<u8>>::decode(&mut &bytes[..]).is_err());
+    }
+
+    #[test]
+    fn forged_length_from_stream_fails_without_allocating_it() {
+        let bytes = [0xfe, 0xff, 0xff, 0xff, 1, 2, 3];
+        assert!(Wrap::<String>::decode(&mut Stream(&bytes)).is_err());
+    }
+
+    #[test]
+    fn long_string_from_stream_roundtrips() {
+        let s = "abc".repeat(5000);
+        let encoded = Wrap(&s).encode();
+        let Wrap(decoded) = Wrap::<String>::decode(&mut Stream(&encoded)).unwrap();
+        assert_eq!(decoded, s);
+    }
+
+    fn assert_using_encoded_sees_encoding<T: Serialize>(value: &T, len: usize)
+    where
+        for<'a> Wrap<&'a T>: Encode,
+    {
+        let encoded = Wrap(value).using_encoded(|bytes| bytes.to_vec());
+        assert_eq!(encoded.len(), len);
+        assert_eq!(encoded, crate::scale::to_vec(value).unwrap());
+    }
+
+    #[test]
+    fn using_encoded_sees_encoding_shorter_than_stack_buffer() {
+        assert_using_encoded_sees_encoding(&vec![7u8; 62], 63);
+    }
+
+    #[test]
+    fn using_encoded_sees_encoding_as_long_as_stack_buffer() {
+        assert_using_encoded_sees_encoding(&vec![7u8; 63], 64);
+    }
+
+    #[test]
+    fn using_encoded_sees_encoding_longer_than_stack_buffer() {
+        assert_using_encoded_sees_encoding(&vec![7u8; 200], 202);
+        let integers = (0..25u64).map(|i| i << 40 | i).collect::<Vec<_>>();
+        assert_using_encoded_sees_encoding(&integers, 201);
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Message {
+        id: u32,
+        sender: String,
+        recipients: Vec<String>,
+        body: Vec<u8>,
+    }
+
+    crate::impl_infallible_serialize!(Message { u32, String, Vec<String>, Vec<u8> });
+
+    fn message(len: usize) -> Message {
+        Message {
+            id: len as u32,
+            sender: "s".repeat(len),
+            recipients: (0..len).map(|i| i.to_string()).collect(),
+            body: vec![len as u8; len],
+        }
+    }
+
+    #[test]
+    fn decoding_in_place_is_like_decoding_fresh_value() {
+        let mut place = message(0);
+        for &len in &[3, 20, 1, 0, 7] {
+            let encoded = Wrap(message(len)).encode();
+            Wrap::decode_in_place(&mut &*encoded, &mut place).unwrap();
+            assert_eq!(place, Wrap::<Message>::decode(&mut &*encoded).unwrap().0);
+        }
+    }
+
+    #[test]
+    fn decoding_in_place_reuses_allocations() {
+        let mut place = message(20);
+        let capacities =
+            |m: &Message| (m.sender.capacity(), m.recipients.capacity(), m.body.capacity());
+        let before = capacities(&place);
+        let pointers = (place.sender.as_ptr(), place.recipients[1].as_ptr(), place.body.as_ptr());
+        Wrap::decode_in_place(&mut &*Wrap(message(5)).encode(), &mut place).unwrap();
+        assert_eq!(place, message(5));
+        assert_eq!(capacities(&place), before);
+        let reused = (place.sender.as_ptr(), place.recipients[1].as_ptr(), place.body.as_ptr());
+        assert_eq!(reused, pointers);
+    }
+
+    #[test]
+    fn decoding_in_place_fails_on_truncated_input() {
+        let mut place = message(2);
+        let encoded = Wrap(message(4)).encode();
+        let result = Wrap::decode_in_place(&mut &encoded[..encoded.len() - 1], &mut place);
+        assert!(matches!(result, Err(crate::Error::Input(_))));
+    }
+
+    #[derive(Serialize)]
+    struct AccountV1 {
+        id: u32,
+        name: String,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct AccountV2 {
+        id: u32,
+        name: String,
+        #[serde(default)]
+        score: u64,
+    }
+
+    crate::impl_infallible_serialize!(AccountV2 { u32, String, u64 });
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Registry {
+        version: u8,
+        owner: AccountV2,
+        #[serde(default)]
+        accounts: Vec<AccountV2>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Strict {
+        id: u32,
+        name: String,
+        score: u64,
+    }
+
+    fn account_v1() -> Vec<u8> {
+        Wrap(AccountV1 { id: 7, name: "alice".into() }).to_bytes()
+    }
+
+    #[test]
+    fn old_payloads_decode_with_default_trailing_fields() {
+        let decoded = Wrap::<AccountV2>::decode_backward_compatible(&account_v1()).unwrap();
+        assert_eq!(decoded.0, AccountV2 { id: 7, name: "alice".into(), score: 0 });
+        assert!(Wrap::<AccountV2>::from_bytes(&account_v1()).is_err());
+    }
+
+    #[test]
+    fn complete_payloads_decode_backward_compatibly_as_usual() {
+        let account = AccountV2 { id: 7, name: "alice".into(), score: 9 };
+        let encoded = Wrap(&account).encode();
+        assert_eq!(Wrap::<AccountV2>::decode_backward_compatible(&encoded).unwrap().0, account);
+        let mut extended = encoded.clone();
+        extended.push(0);
+        let result = Wrap::<AccountV2>::decode_backward_compatible(&extended);
+        assert!(matches!(result, Err(crate::Error::TrailingBytes { len: 1 })));
+    }
+
+    #[test]
+    fn missing_fields_of_nested_structs_take_their_defaults() {
+        let mut encoded = vec![2];
+        encoded.extend_from_slice(&account_v1());
+        let decoded = Wrap::<Registry>::decode_backward_compatible(&encoded).unwrap().0;
+        assert_eq!(decoded.owner, AccountV2 { id: 7, name: "alice".into(), score: 0 });
+        assert!(decoded.accounts.is_empty());
+    }
+
+    #[test]
+    fn missing_fields_without_defaults_are_rejected() {
+        let result = Wrap::<Strict>::decode_backward_compatible(&account_v1());
+        assert!(matches!(result, Err(crate::Error::Deserialization(_))));
+    }
+
+    #[test]
+    fn payloads_truncated_mid_field_are_rejected() {
+        let encoded = account_v1();
+        let result = Wrap::<AccountV2>::decode_backward_compatible(&encoded[..encoded.len() - 1]);
+        assert!(matches!(result, Err(crate::Error::Input(_))));
+        let mut encoded = Wrap(AccountV2 { id: 7, name: "alice".into(), score: 9 }).encode();
+        encoded.truncate(encoded.len() - 4);
+        let result = Wrap::<AccountV2>::decode_backward_compatible(&encoded);
+        assert!(matches!(result, Err(crate::Error::Input(_))));
+    }
+
+    /// Allocator counting the allocations made by each thread and tracking the largest one
+    struct CountingAllocator;
+
+    std::thread_local! {
+        static ALLOCATIONS: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+        static LARGEST_ALLOCATION: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+    }
+
+    #[allow(unsafe_code)]
+    unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+            let _ = LARGEST_ALLOCATION.try_with(|n| n.set(n.get().max(layout.size())));
+            std::alloc::System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            std::alloc::System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations<T: Serialize>(value: &T) -> usize
+    where
+        for<'a> Wrap<&'a T>: Encode,
+    {
+        let before = ALLOCATIONS.with(|n| n.get());
+        let len = Wrap(value).using_encoded(|bytes| bytes.len());
+        let allocations = ALLOCATIONS.with(|n| n.get()) - before;
+        assert_eq!(len, crate::scale::to_vec(value).unwrap().len());
+        allocations
+    }
+
+    #[test]
+    fn using_encoded_only_allocates_for_long_encodings() {
+        assert_eq!(allocations(&(7u64, [3u8; 32])), 0);
+        assert_eq!(allocations(&vec![7u8; 63]), 0);
+        assert_eq!(allocations(&vec![7u8; 64]), 1);
+        assert!(allocations(&vec![7u64; 100]) >= 1);
+    }
+
+    /// Returns the result of `f` and the number of allocations it made
+    pub(crate) fn allocation_count<R>(f: impl FnOnce() -> R) -> (R, usize) {
+        let before = ALLOCATIONS.with(|n| n.get());
+        let result = f();
+        (result, ALLOCATIONS.with(|n| n.get()) - before)
+    }
+
+    fn largest_allocation<R>(f: impl FnOnce() -> R) -> (R, usize) {
+        LARGEST_ALLOCATION.with(|n| n.set(0));
+        let result = f();
+        (result, LARGEST_ALLOCATION.with(|n| n.get()))
+    }
+
+    #[test]
+    fn bounded_encoding_within_limit_is_like_encode() {
+        let value = message(300);
+        let encoded = Wrap(&value).encode();
+        assert_eq!(Wrap(&value).encode_bounded(encoded.len()).unwrap(), encoded);
+        let max = encoded.len() - 1;
+        match Wrap(&value).encode_bounded(max) {
+            Err(crate::Error::EncodingTooLarge { len, max: m }) => assert!(m == max && len > m),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn bounded_encoding_stops_before_allocating_past_limit() {
+        let value = vec![vec![7u8; 1024]; 20 * 1024];
+        let (result, largest) = largest_allocation(|| Wrap(&value).encode_bounded(10_000));
+        assert!(matches!(result, Err(crate::Error::EncodingTooLarge { max: 10_000, .. })));
+        assert!(largest <= 10_000 + 64, "{} bytes allocated", largest);
+        let (result, largest) = largest_allocation(|| Wrap(&value[..9]).encode_bounded(10_000));
+        assert_eq!(result.unwrap().len(), 9 * 1026 + 1);
+        assert!(largest <= 10_000 + 64, "{} bytes allocated", largest);
+    }
+
+    #[test]
+    fn forged_lengths_do_not_preallocate_their_elements() {
+        // Claims 2^29 elements but holds 2 of them.
+        let forged = [&b"\x02\x00\x00\x80"[..], &[7; 16]].concat();
+        let (result, largest) = largest_allocation(|| crate::decode_all::<Vec<u64>>(&forged));
+        assert!(matches!(result, Err(crate::Error::Input(_))));
+        let max = 8 * crate::DecodeOptions::DEFAULT_MAX_PREALLOCATION;
+        assert!(largest <= max, "{} bytes allocated", largest);
+        let options = crate::DecodeOptions::new().max_preallocation(2);
+        let (result, largest) = largest_allocation(|| options.decode_all::<Vec<u64>>(&forged));
+        assert!(matches!(result, Err(crate::Error::Input(_))));
+        assert!(largest <= 64, "{} bytes allocated", largest);
+        let mut input = &forged[..];
+        let (result, largest) =
+            largest_allocation(|| options.decode_from::<Vec<u64>, _>(&mut input));
+        assert!(matches!(result, Err(crate::Error::Input(_))));
+        assert!(largest <= 64, "{} bytes allocated", largest);
+    }
+
+    /// Feature sets with which the library must build, as documented in the crate docs
+    const FEATURE_MATRIX: &[&str] = &[
+        "serde-scale-0-2",
+        "encode,serde-scale-0-2",
+        "decode,serde-scale-0-2",
+        "alloc,encode,serde-scale-0-2",
+        "alloc,decode,serde-scale-0-2",
+        "std,encode,serde-scale-0-2",
+        "std,decode,serde-scale-0-2",
+        "std,encode,decode,serde-scale-0-1",
+        "alloc,debug-roundtrip-check,serde-scale-0-2",
+        "max-encoded-len,serde-scale-0-2",
+    ];
+
+    const FEATURE_TEST_MATRIX: &[&str] = &[
+        "debug-roundtrip-check",
+        "debug-roundtrip-check,max-encoded-len",
+        "deny-panicking-encode",
+    ];
+
+    #[test]
+    #[ignore = "runs cargo for every feature set"]
+    fn feature_matrix_builds() {
+        let target_dir = concat!(env!("CARGO_MANIFEST_DIR"), "/target/feature-matrix");
+        for features in FEATURE_MATRIX {
+            let status = std::process::Command::new(env!("CARGO"))
+                .args(["check", "--lib", "--no-default-features", "--features", features])
+                .current_dir(env!("CARGO_MANIFEST_DIR"))
+                .env("CARGO_TARGET_DIR", target_dir)
+                .status()
+                .unwrap();
+            assert!(status.success(), "Build failed with features {:?}", features);
+        }
+    }
+
+    #[test]
+    #[ignore = "runs the test suite for every feature set"]
+    fn feature_matrix_tests_pass() {
+        let target_dir = concat!(env!("CARGO_MANIFEST_DIR"), "/target/feature-matrix");
+        for features in FEATURE_TEST_MATRIX {
+            let status = std::process::Command::new(env!("CARGO"))
+                .args(["test", "--features", features])
+                .current_dir(env!("CARGO_MANIFEST_DIR"))
+                .env("CARGO_TARGET_DIR", target_dir)
+                .status()
+                .unwrap();
+            assert!(status.success(), "Tests failed with features {:?}", features);
+        }
     }
 }
@@ -0,0 +1,122 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+use crate::{Error, TryOutput};
+use parity_scale_codec::Output;
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+/// `TryOutput` forwarding to an `Output` until a maximum number of bytes is reached
+///
+/// A write that would exceed the maximum fails with [`Error::EncodingTooLarge`] without being
+/// forwarded, which makes [`Wrap::try_encode_to`](crate::Wrap::try_encode_to) stop serializing.
+///
+/// ```rust
+/// use serde_scale_wrap::{Error, LimitedOutput, Wrap};
+///
+/// let mut encoded = Vec::new();
+/// Wrap("foo").try_encode_to(&mut LimitedOutput::new(&mut encoded, 4))?;
+/// assert_eq!(encoded, b"\x0cfoo");
+///
+/// let result = Wrap("foo!").try_encode_to(&mut LimitedOutput::new(&mut Vec::new(), 4));
+/// assert!(matches!(result, Err(Error::EncodingTooLarge { len: 5, max: 4 })));
+/// # Ok::<_, Error>(())
+/// ```
+#[derive(Debug)]
+pub struct LimitedOutput<'a, O: ?Sized> {
+    output: &'a mut O,
+    len: usize,
+    max: usize,
+}
+
+impl<'a, O: Output + ?Sized> LimitedOutput<'a, O> {
+    /// Returns a `TryOutput` writing at most `max` bytes to `output`
+    pub fn new(output: &'a mut O, max: usize) -> Self {
+        LimitedOutput { output, len: 0, max }
+    }
+
+    /// Returns the number of bytes written
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether no bytes were written
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the underlying output
+    pub fn into_inner(self) -> &'a mut O {
+        self.output
+    }
+}
+
+impl<O: Output + ?Sized> TryOutput for LimitedOutput<'_, O> {
+    fn try_write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let len = check_len(self.len, bytes.len(), self.max)?;
+        self.output.write(bytes);
+        self.len = len;
+        Ok(())
+    }
+}
+
+/// Returns the length after writing `n` more bytes, or an error if it exceeds `max`
+fn check_len(len: usize, n: usize, max: usize) -> Result<usize, Error> {
+    match len.checked_add(n) {
+        Some(len) if len <= max => Ok(len),
+        new_len => Err(Error::EncodingTooLarge { len: new_len.unwrap_or(usize::MAX), max }),
+    }
+}
+
+/// `TryOutput` writing at most `max` bytes to a `Vec`, never reserving more than `max` bytes
+#[cfg(feature = "alloc")]
+pub(crate) struct BoundedVec {
+    pub(crate) bytes: Vec<u8>,
+    max: usize,
+}
+
+#[cfg(feature = "alloc")]
+impl BoundedVec {
+    pub(crate) fn new(max: usize) -> Self {
+        BoundedVec { bytes: Vec::new(), max }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl TryOutput for BoundedVec {
+    fn try_write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let len = check_len(self.bytes.len(), bytes.len(), self.max)?;
+        if len > self.bytes.capacity() {
+            let capacity = (self.bytes.capacity() * 2).clamp(len, self.max);
+            self.bytes.reserve_exact(capacity - self.bytes.len());
+        }
+        self.bytes.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::LimitedOutput;
+    use crate::{Error, Wrap};
+    use alloc::{vec, vec::Vec};
+
+    #[test]
+    fn writes_up_to_the_maximum_are_forwarded() {
+        let mut encoded = Vec::new();
+        let mut output = LimitedOutput::new(&mut encoded, 6);
+        Wrap((1u8, [2u8; 5])).try_encode_to(&mut output).unwrap();
+        assert_eq!(output.len(), 6);
+        assert_eq!(encoded, [1, 2, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn serialization_stops_at_the_first_rejected_write() {
+        let mut encoded = Vec::new();
+        let mut output = LimitedOutput::new(&mut encoded, 3);
+        let result = Wrap((1u16, 2u16, vec![0u8; 1 << 20])).try_encode_to(&mut output);
+        assert!(matches!(result, Err(Error::EncodingTooLarge { len: 4, max: 3 })));
+        assert_eq!(output.len(), 2);
+        assert_eq!(encoded, [1, 0]);
+    }
+}
@@ -0,0 +1,475 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Logging of encoding and decoding with `defmt` and `tracing`, and notification of hooks
+//!
+//! With the `tracing` feature, encoding and decoding run in `encode` and `decode` spans with the
+//! `serde_scale_wrap` target. These spans hold the name of the encoded or decoded type in their
+//! `type_name` field, and record the number of bytes written or read in their `bytes` field and
+//! the error, if any, in their `error` field. A `debug` event is emitted on success and a `warn`
+//! event on failure.
+//!
+//! With the `hooks` feature, the outcome is also reported to the hook of the `Observer`.
+
+#[cfg(feature = "hooks")]
+use crate::hooks::{self, CodecHook};
+use crate::Error;
+#[cfg(all(feature = "encode", any(feature = "hooks", feature = "tracing")))]
+use crate::TryOutput;
+#[cfg(all(
+    not(feature = "hooks"),
+    any(
+        all(feature = "encode", feature = "tracing"),
+        all(feature = "decode", any(feature = "defmt", feature = "tracing")),
+    ),
+))]
+use core::marker::PhantomData;
+#[cfg(all(feature = "encode", any(feature = "hooks", feature = "tracing")))]
+use parity_scale_codec::Output;
+#[cfg(feature = "decode")]
+use parity_scale_codec::Input;
+
+#[cfg(feature = "tracing")]
+const TARGET: &str = "serde_scale_wrap";
+
+/// Hook notified of the outcome of an encoding or decoding, if any
+#[cfg(any(
+    all(feature = "encode", any(feature = "hooks", feature = "tracing")),
+    all(feature = "decode", any(feature = "defmt", feature = "hooks", feature = "tracing")),
+))]
+#[derive(Clone, Copy)]
+pub(crate) struct Observer<'a> {
+    #[cfg(feature = "hooks")]
+    hook: Option<&'a dyn CodecHook>,
+    #[cfg(not(feature = "hooks"))]
+    _hook: PhantomData<&'a ()>,
+}
+
+#[cfg(feature = "hooks")]
+impl<'a> Observer<'a> {
+    /// Returns an observer notifying the global hook, if one is installed
+    pub(crate) fn global() -> Self {
+        Observer { hook: hooks::global_hook() }
+    }
+
+    /// Returns an observer notifying `hook`
+    #[cfg(feature = "alloc")]
+    pub(crate) fn with_hook(hook: &'a dyn CodecHook) -> Self {
+        Observer { hook: Some(hook) }
+    }
+
+    #[cfg(feature = "encode")]
+    fn encoded(&self, type_name: &'static str, len: usize) {
+        if let Some(hook) = self.hook {
+            hook.on_encode(type_name, len);
+        }
+    }
+
+    #[cfg(feature = "decode")]
+    fn decoded(&self, type_name: &'static str, len: usize) {
+        if let Some(hook) = self.hook {
+            hook.on_decode(type_name, len);
+        }
+    }
+
+    fn failed(&self, type_name: &'static str, e: &Error) {
+        if let Some(hook) = self.hook {
+            hook.on_error(type_name, e);
+        }
+    }
+}
+
+#[cfg(all(
+    not(feature = "hooks"),
+    any(
+        all(feature = "encode", feature = "tracing"),
+        all(feature = "decode", any(feature = "defmt", feature = "tracing")),
+    ),
+))]
+impl Observer<'_> {
+    pub(crate) fn global() -> Self {
+        Observer { _hook: PhantomData }
+    }
+
+    #[cfg(all(feature = "encode", feature = "tracing"))]
+    #[inline(always)]
+    fn encoded(&self, _: &'static str, _: usize) {}
+
+    #[cfg(feature = "decode")]
+    #[inline(always)]
+    fn decoded(&self, _: &'static str, _: usize) {}
+
+    #[inline(always)]
+    fn failed(&self, _: &'static str, _: &Error) {}
+}
+
+/// Runs `encode` on `dst`, logging the outcome
+#[cfg(all(feature = "encode", any(feature = "hooks", feature = "tracing")))]
+pub(crate) fn encode_logged<T, O, F>(dst: &mut O, encode: F) -> Result<(), Error>
+where
+    T: ?Sized,
+    O: ?Sized,
+    F: FnOnce(&mut Counting<'_, O>) -> Result<(), Error>,
+{
+    encode_observed::<T, _, _>(Observer::global(), dst, encode)
+}
+
+/// Runs `encode` on `dst`, logging the outcome and reporting it to `observer`
+#[cfg(all(feature = "encode", any(feature = "hooks", feature = "tracing")))]
+pub(crate) fn encode_observed<T, O, F>(
+    observer: Observer<'_>,
+    dst: &mut O,
+    encode: F,
+) -> Result<(), Error>
+where
+    T: ?Sized,
+    O: ?Sized,
+    F: FnOnce(&mut Counting<'_, O>) -> Result<(), Error>,
+{
+    let type_name = core::any::type_name::<T>();
+    #[cfg(feature = "tracing")]
+    let span = tracing::debug_span!(
+        target: TARGET,
+        "encode",
+        type_name,
+        bytes = tracing::field::Empty,
+        error = tracing::field::Empty,
+    );
+    #[cfg(feature = "tracing")]
+    let _entered = span.enter();
+    let mut dst = Counting { inner: dst, count: 0 };
+    let result = encode(&mut dst);
+    #[cfg(feature = "tracing")]
+    span.record("bytes", dst.count);
+    match &result {
+        Ok(()) => {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(target: TARGET, bytes = dst.count, "Encoded");
+            observer.encoded(type_name, dst.count);
+        }
+        Err(e) => {
+            #[cfg(feature = "tracing")]
+            {
+                span.record("error", tracing::field::display(e));
+                tracing::warn!(target: TARGET, bytes = dst.count, error = %e, "Encoding failed");
+            }
+            observer.failed(type_name, e);
+        }
+    }
+    result
+}
+
+/// Runs `encode` on `dst`
+///
+/// `T` is only used by the instrumented version.
+#[cfg(all(feature = "encode", not(any(feature = "hooks", feature = "tracing"))))]
+#[allow(clippy::extra_unused_type_parameters)]
+#[inline(always)]
+pub(crate) fn encode_logged<T, O, F>(dst: &mut O, encode: F) -> Result<(), Error>
+where
+    T: ?Sized,
+    O: ?Sized,
+    F: FnOnce(&mut O) -> Result<(), Error>,
+{
+    encode(dst)
+}
+
+/// Runs `decode` on `input`, logging the outcome and the byte offset at which decoding failed
+#[cfg(all(feature = "decode", any(feature = "defmt", feature = "hooks", feature = "tracing")))]
+pub(crate) fn decode_logged<T, I, F>(input: &mut I, decode: F) -> Result<T, Error>
+where
+    I: Input + ?Sized,
+    F: FnOnce(&mut Counting<'_, I>) -> Result<T, Error>,
+{
+    decode_observed(Observer::global(), input, decode)
+}
+
+/// Runs `decode` on `input`, logging the outcome and reporting it to `observer`
+#[cfg(all(feature = "decode", any(feature = "defmt", feature = "hooks", feature = "tracing")))]
+pub(crate) fn decode_observed<T, I, F>(
+    observer: Observer<'_>,
+    input: &mut I,
+    decode: F,
+) -> Result<T, Error>
+where
+    I: Input + ?Sized,
+    F: FnOnce(&mut Counting<'_, I>) -> Result<T, Error>,
+{
+    decode_counted_observed(observer, |count| {
+        let mut input = Counting { inner: input, count: 0 };
+        let result = decode(&mut input);
+        *count = input.count;
+        result
+    })
+}
+
+/// Runs `decode`, which stores the number of bytes it read in its argument, logging the outcome
+#[cfg(all(
+    feature = "alloc",
+    feature = "decode",
+    any(feature = "defmt", feature = "hooks", feature = "tracing"),
+))]
+pub(crate) fn decode_counted<T, F>(decode: F) -> Result<T, Error>
+where
+    F: FnOnce(&mut usize) -> Result<T, Error>,
+{
+    decode_counted_observed(Observer::global(), decode)
+}
+
+#[cfg(all(feature = "decode", any(feature = "defmt", feature = "hooks", feature = "tracing")))]
+fn decode_counted_observed<T, F>(observer: Observer<'_>, decode: F) -> Result<T, Error>
+where
+    F: FnOnce(&mut usize) -> Result<T, Error>,
+{
+    let type_name = core::any::type_name::<T>();
+    #[cfg(feature = "tracing")]
+    let span = tracing::debug_span!(
+        target: TARGET,
+        "decode",
+        type_name,
+        bytes = tracing::field::Empty,
+        error = tracing::field::Empty,
+    );
+    #[cfg(feature = "tracing")]
+    let _entered = span.enter();
+    let mut count = 0;
+    let result = decode(&mut count);
+    #[cfg(feature = "tracing")]
+    span.record("bytes", count);
+    match &result {
+        Ok(_) => {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(target: TARGET, bytes = count, "Decoded");
+            observer.decoded(type_name, count);
+        }
+        Err(e) => {
+            #[cfg(feature = "defmt")]
+            defmt::debug!("Decoding `{=str}` failed at byte {=usize}: {}", type_name, count, e);
+            #[cfg(feature = "tracing")]
+            {
+                span.record("error", tracing::field::display(e));
+                tracing::warn!(target: TARGET, bytes = count, error = %e, "Decoding failed");
+            }
+            observer.failed(type_name, e);
+        }
+    }
+    result
+}
+
+/// Runs `decode` on `input`
+#[cfg(all(
+    feature = "decode",
+    not(any(feature = "defmt", feature = "hooks", feature = "tracing")),
+))]
+#[inline(always)]
+pub(crate) fn decode_logged<T, I, F>(input: &mut I, decode: F) -> Result<T, Error>
+where
+    I: Input + ?Sized,
+    F: FnOnce(&mut I) -> Result<T, Error>,
+{
+    decode(input)
+}
+
+/// Runs `decode`, which stores the number of bytes it read in its argument
+#[cfg(all(
+    feature = "alloc",
+    feature = "decode",
+    not(any(feature = "defmt", feature = "hooks", feature = "tracing")),
+))]
+#[inline(always)]
+pub(crate) fn decode_counted<T, F>(decode: F) -> Result<T, Error>
+where
+    F: FnOnce(&mut usize) -> Result<T, Error>,
+{
+    decode(&mut 0)
+}
+
+/// `Input` or output counting the bytes read from or written to it
+#[cfg(any(
+    all(feature = "encode", any(feature = "hooks", feature = "tracing")),
+    all(feature = "decode", any(feature = "defmt", feature = "hooks", feature = "tracing")),
+))]
+pub(crate) struct Counting<'a, T: ?Sized> {
+    inner: &'a mut T,
+    count: usize,
+}
+
+#[cfg(all(feature = "decode", any(feature = "defmt", feature = "hooks", feature = "tracing")))]
+impl<I: Input + ?Sized> Input for Counting<'_, I> {
+    fn remaining_len(&mut self) -> Result<Option<usize>, parity_scale_codec::Error> {
+        self.inner.remaining_len()
+    }
+
+    fn read(&mut self, into: &mut [u8]) -> Result<(), parity_scale_codec::Error> {
+        self.inner.read(into)?;
+        self.count += into.len();
+        Ok(())
+    }
+}
+
+#[cfg(all(feature = "encode", any(feature = "hooks", feature = "tracing")))]
+impl<O: Output + ?Sized> Output for Counting<'_, O> {
+    fn write(&mut self, bytes: &[u8]) {
+        self.inner.write(bytes);
+        self.count += bytes.len();
+    }
+}
+
+#[cfg(all(feature = "encode", any(feature = "hooks", feature = "tracing")))]
+impl<O: TryOutput + ?Sized> TryOutput for Counting<'_, O> {
+    fn try_write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.inner.try_write(bytes)?;
+        self.count += bytes.len();
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "tracing", feature = "std", feature = "encode", feature = "decode"))]
+mod tests {
+    use crate::Wrap;
+    use parity_scale_codec::{Decode, Encode};
+    use std::{
+        collections::BTreeMap,
+        fmt,
+        string::{String, ToString},
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Mutex,
+        },
+        vec::Vec,
+    };
+    use tracing::{
+        field::{Field, Visit},
+        span, Event, Level, Metadata, Subscriber,
+    };
+
+    type Fields = BTreeMap<&'static str, String>;
+
+    #[derive(Debug, PartialEq)]
+    struct Recorded {
+        name: &'static str,
+        target: String,
+        fields: Fields,
+        events: Vec<(Level, Fields)>,
+    }
+
+    #[derive(Default)]
+    struct Collector {
+        next_id: AtomicU64,
+        spans: Mutex<Vec<Recorded>>,
+        current: Mutex<Vec<usize>>,
+    }
+
+    struct FieldVisitor<'a>(&'a mut Fields);
+
+    impl Visit for FieldVisitor<'_> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name(), value.to_string());
+        }
+
+        fn record_u64(&mut self, field: &Field, value: u64) {
+            self.0.insert(field.name(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0.insert(field.name(), format!("{:?}", value));
+        }
+    }
+
+    impl Subscriber for Collector {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, attrs: &span::Attributes<'_>) -> span::Id {
+            let mut fields = Fields::new();
+            attrs.record(&mut FieldVisitor(&mut fields));
+            self.spans.lock().unwrap().push(Recorded {
+                name: attrs.metadata().name(),
+                target: attrs.metadata().target().to_string(),
+                fields,
+                events: Vec::new(),
+            });
+            span::Id::from_u64(self.next_id.fetch_add(1, Ordering::SeqCst) + 1)
+        }
+
+        fn record(&self, id: &span::Id, values: &span::Record<'_>) {
+            let mut spans = self.spans.lock().unwrap();
+            values.record(&mut FieldVisitor(&mut spans[id.into_u64() as usize - 1].fields));
+        }
+
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = Fields::new();
+            event.record(&mut FieldVisitor(&mut fields));
+            if let Some(&i) = self.current.lock().unwrap().last() {
+                let level = *event.metadata().level();
+                self.spans.lock().unwrap()[i].events.push((level, fields));
+            }
+        }
+
+        fn enter(&self, id: &span::Id) {
+            self.current.lock().unwrap().push(id.into_u64() as usize - 1);
+        }
+
+        fn exit(&self, _: &span::Id) {
+            self.current.lock().unwrap().pop();
+        }
+    }
+
+    fn collect<F: FnOnce()>(f: F) -> Vec<Recorded> {
+        let collector = std::sync::Arc::new(Collector::default());
+        tracing::subscriber::with_default(collector.clone(), f);
+        let spans = std::mem::take(&mut *collector.spans.lock().unwrap());
+        spans
+    }
+
+    fn fields(entries: &[(&'static str, &str)]) -> Fields {
+        entries.iter().map(|&(k, v)| (k, v.to_string())).collect()
+    }
+
+    #[test]
+    fn successful_decode_records_bytes_read() {
+        let spans = collect(|| {
+            Wrap::<(u16, bool)>::decode(&mut &[1, 0, 1, 9][..]).unwrap();
+        });
+        assert_eq!(
+            spans,
+            [Recorded {
+                name: "decode",
+                target: "serde_scale_wrap".into(),
+                fields: fields(&[("type_name", "(u16, bool)"), ("bytes", "3")]),
+                events: vec![(Level::DEBUG, fields(&[("bytes", "3"), ("message", "Decoded")]))],
+            }],
+        );
+    }
+
+    #[test]
+    fn failing_decode_records_error() {
+        let spans = collect(|| {
+            Wrap::<(u16, bool)>::decode(&mut &[1, 0, 2][..]).unwrap_err();
+        });
+        let error = "Deserialization failed: Expected boolean (0 or 1), found 2";
+        assert_eq!(
+            spans,
+            [Recorded {
+                name: "decode",
+                target: "serde_scale_wrap".into(),
+                fields: fields(&[("type_name", "(u16, bool)"), ("bytes", "3"), ("error", error)]),
+                events: vec![(
+                    Level::WARN,
+                    fields(&[("bytes", "3"), ("error", error), ("message", "Decoding failed")]),
+                )],
+            }],
+        );
+    }
+
+    #[test]
+    fn encode_records_bytes_written() {
+        let spans = collect(|| {
+            Wrap(7u32).encode();
+        });
+        assert_eq!(spans[0].name, "encode");
+        assert_eq!(spans[0].fields, fields(&[("type_name", "u32"), ("bytes", "4")]));
+    }
+}
@@ -0,0 +1,184 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+use crate::{adapters::InputToRead, de, read_compact_len, Error};
+use core::{convert::TryFrom, fmt, iter::FusedIterator, marker::PhantomData};
+use parity_scale_codec::Input;
+use serde::{de::DeserializeOwned, Deserialize};
+
+/// Iterator decoding the entries of an encoded map one at a time, without building the map
+///
+/// The entry count is read by [`MapDecoder::new`], and each call to `next` decodes the following
+/// entry, so that the entries of a large map can be filtered without holding them all. The bytes
+/// of strings and byte sequences of all entries are read into the same scratch buffer. Decoding
+/// all entries reads the same bytes from the input as decoding the whole map with `Wrap` does.
+///
+/// If the decoder is dropped early, the input is left at the beginning of the first entry not
+/// decoded. [`MapDecoder::finish`] decodes and drops the remaining entries instead, leaving the
+/// input after the map. Once an entry fails to decode, the iterator ends and the position of the
+/// input is unspecified.
+///
+/// ```rust
+/// use serde_scale_wrap::MapDecoder;
+/// use std::collections::BTreeMap;
+///
+/// let balances = (0..1000u32).map(|account| (account, u64::from(account) * 10));
+/// let encoded = serde_scale_wrap::encode(&(balances.collect::<BTreeMap<_, _>>(), true));
+/// let mut input = &encoded[..];
+/// let mut entries = MapDecoder::<u32, u64, _>::new(&mut input)?;
+/// assert_eq!(entries.remaining(), 1000);
+/// let rich = entries.by_ref().filter(|entry| matches!(entry, Ok((_, 9980..)))).count();
+/// assert_eq!(rich, 2);
+/// assert!(serde_scale_wrap::decode_all::<bool>(entries.into_inner())?);
+/// # Ok::<_, serde_scale_wrap::Error>(())
+/// ```
+pub struct MapDecoder<'a, K, V, I: ?Sized> {
+    reader: InputToRead<'a, I>,
+    remaining: usize,
+    _entries: PhantomData<fn() -> (K, V)>,
+}
+
+impl<'a, K, V, I: Input + ?Sized> MapDecoder<'a, K, V, I> {
+    /// Reads the entry count of a map from `input` and returns an iterator over its entries
+    pub fn new(input: &'a mut I) -> Result<Self, Error> {
+        let remaining = usize::try_from(read_compact_len(input)?).map_err(|_| {
+            Error::Deserialization(crate::scale::Error::CollectionTooLargeToDeserialize)
+        })?;
+        Ok(MapDecoder { reader: InputToRead::new(input), remaining, _entries: PhantomData })
+    }
+
+    /// Returns the number of entries not decoded yet
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Returns the underlying input, at the beginning of the first entry not decoded
+    pub fn into_inner(self) -> &'a mut I {
+        self.reader.into_inner()
+    }
+}
+
+impl<'a, K, V, I> MapDecoder<'a, K, V, I>
+where
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+    I: Input + ?Sized,
+{
+    /// Decodes and drops the remaining entries, and returns the underlying input after the map
+    pub fn finish(mut self) -> Result<&'a mut I, Error> {
+        for entry in &mut self {
+            entry?;
+        }
+        Ok(self.into_inner())
+    }
+}
+
+impl<K, V, I> Iterator for MapDecoder<'_, K, V, I>
+where
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+    I: Input + ?Sized,
+{
+    type Item = Result<(K, V), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.remaining = self.remaining.checked_sub(1)?;
+        let mut deserializer = crate::scale::Deserializer::new(&mut self.reader);
+        let entry = <(K, V)>::deserialize(de::Deserializer::new(&mut deserializer))
+            .map_err(Error::from_deserialization);
+        if entry.is_err() {
+            self.remaining = 0;
+        }
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining))
+    }
+}
+
+impl<K, V, I> FusedIterator for MapDecoder<'_, K, V, I>
+where
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+    I: Input + ?Sized,
+{
+}
+
+impl<K, V, I: ?Sized> fmt::Debug for MapDecoder<'_, K, V, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MapDecoder").field("remaining", &self.remaining).finish()
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::MapDecoder;
+    use crate::{decode_all, encode, Error, Wrap};
+    use alloc::{collections::BTreeMap, format, string::String, vec::Vec};
+    use parity_scale_codec::Decode;
+
+    fn accounts() -> BTreeMap<String, u64> {
+        (0..10u64).map(|i| (format!("account-{}", i), i << 40)).collect()
+    }
+
+    #[test]
+    fn empty_maps_have_no_entries() {
+        let encoded = encode(&(BTreeMap::<String, u64>::new(), 7u8));
+        let mut input = &encoded[..];
+        let mut entries = MapDecoder::<String, u64, _>::new(&mut input).unwrap();
+        assert_eq!(entries.remaining(), 0);
+        assert!(entries.next().is_none());
+        assert_eq!(decode_all::<u8>(entries.into_inner()).unwrap(), 7);
+    }
+
+    #[test]
+    fn entries_are_decoded_in_order_from_the_same_bytes_as_the_map() {
+        let encoded = encode(&(accounts(), 7u8));
+        let mut input = &encoded[..];
+        let entries = MapDecoder::<String, u64, _>::new(&mut input).unwrap();
+        let decoded = entries.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(decoded, accounts().into_iter().collect::<Vec<_>>());
+        let mut whole = &encoded[..];
+        Wrap::<BTreeMap<String, u64>>::decode(&mut whole).unwrap();
+        assert_eq!(input, whole);
+    }
+
+    #[test]
+    fn early_exits_leave_the_input_after_the_last_entry_decoded() {
+        let encoded = encode(&(accounts(), 7u8));
+        let mut input = &encoded[..];
+        let mut entries = MapDecoder::<String, u64, _>::new(&mut input).unwrap();
+        let first = entries.by_ref().take(2).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(first, accounts().into_iter().take(2).collect::<Vec<_>>());
+        assert_eq!(entries.remaining(), 8);
+        let input = entries.into_inner();
+        let Wrap(third) = Wrap::<(String, u64)>::decode(input).unwrap();
+        assert_eq!(third, ("account-2".into(), 2 << 40));
+    }
+
+    #[test]
+    fn finishing_early_skips_to_the_following_field() {
+        let encoded = encode(&(accounts(), 7u8));
+        let mut input = &encoded[..];
+        let mut entries = MapDecoder::<String, u64, _>::new(&mut input).unwrap();
+        entries.next().unwrap().unwrap();
+        entries.next().unwrap().unwrap();
+        let input = entries.finish().unwrap();
+        assert_eq!(decode_all::<u8>(input).unwrap(), 7);
+    }
+
+    #[test]
+    fn corrupted_entries_end_the_iteration() {
+        let names = (0..5u32).map(|i| (i, String::from("v"))).collect::<BTreeMap<_, _>>();
+        let mut encoded = encode(&names);
+        // Each entry is a `u32` followed by a length and one byte, after the entry count.
+        encoded[1 + 2 * 6 + 5] = 0xff;
+        let mut input = &encoded[..];
+        let mut entries = MapDecoder::<u32, String, _>::new(&mut input).unwrap();
+        assert_eq!(entries.next().unwrap().unwrap(), (0, "v".into()));
+        assert_eq!(entries.next().unwrap().unwrap(), (1, "v".into()));
+        assert!(matches!(entries.next(), Some(Err(Error::Deserialization(_)))));
+        assert_eq!(entries.remaining(), 0);
+        assert!(entries.next().is_none());
+    }
+}
@@ -0,0 +1,156 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+use crate::{try_serialize_to, write_compact_len, Error};
+use parity_scale_codec::Output;
+use serde::Serialize;
+
+/// Encodes the key/value pairs of `entries` to `dst` as a map, without collecting them
+///
+/// The entry count is written first, followed by each key and value. The encoding is the one of
+/// the map holding the entries in the order they are yielded, so that a `BTreeMap` encodes to the
+/// same bytes as its iterator does. `dst` may have been partially written to if an error is
+/// returned.
+///
+/// ```rust
+/// use std::collections::BTreeMap;
+///
+/// let rows = vec![("alice", 10u64), ("bob", 20)];
+/// let mut encoded = Vec::new();
+/// serde_scale_wrap::encode_map_iter(rows.iter().copied(), &mut encoded)?;
+/// let map = rows.into_iter().collect::<BTreeMap<_, _>>();
+/// assert_eq!(encoded, serde_scale_wrap::encode(&map));
+/// # Ok::<_, serde_scale_wrap::Error>(())
+/// ```
+///
+/// # Panics
+/// Panics if the iterator yields fewer entries than its length.
+pub fn encode_map_iter<K, V, I, O>(entries: I, dst: &mut O) -> Result<(), Error>
+where
+    K: Serialize,
+    V: Serialize,
+    I: IntoIterator<Item = (K, V)>,
+    I::IntoIter: ExactSizeIterator,
+    O: Output + ?Sized,
+{
+    encode_entries(entries, dst, |_, _| true)
+}
+
+/// Encodes the key/value pairs of `entries` to `dst` as a map like [`encode_map_iter`], failing
+/// if the keys are not in strictly ascending order
+///
+/// The keys are compared with `Ord`, as `BTreeMap` orders them, so that the encoding is the one
+/// of the `BTreeMap` holding the entries and thus canonical. This order differs from the order of
+/// the key encodings for some types, e.g. integers, which are encoded in little-endian. Encoding
+/// fails with [`Error::UnorderedKeys`] at the first key that is not greater than the previous one,
+/// e.g. a duplicate key.
+///
+/// ```rust
+/// use serde_scale_wrap::{encode_map_iter_checked, Error};
+///
+/// let mut encoded = Vec::new();
+/// encode_map_iter_checked(vec![(1u16, true), (256, false)], &mut encoded)?;
+/// assert_eq!(encoded, b"\x08\x01\x00\x01\x00\x01\x00");
+/// let result = encode_map_iter_checked(vec![(256u16, false), (1, true)], &mut Vec::new());
+/// assert!(matches!(result, Err(Error::UnorderedKeys { index: 1 })));
+/// # Ok::<_, Error>(())
+/// ```
+///
+/// # Panics
+/// Panics if the iterator yields fewer entries than its length.
+pub fn encode_map_iter_checked<K, V, I, O>(entries: I, dst: &mut O) -> Result<(), Error>
+where
+    K: Ord + Serialize,
+    V: Serialize,
+    I: IntoIterator<Item = (K, V)>,
+    I::IntoIter: ExactSizeIterator,
+    O: Output + ?Sized,
+{
+    encode_entries(entries, dst, |previous, key| previous < key)
+}
+
+/// Encodes `entries` as a map, checking each key against the previous one with `ordered`
+fn encode_entries<K, V, I, O, F>(entries: I, dst: &mut O, ordered: F) -> Result<(), Error>
+where
+    K: Serialize,
+    V: Serialize,
+    I: IntoIterator<Item = (K, V)>,
+    I::IntoIter: ExactSizeIterator,
+    O: Output + ?Sized,
+    F: Fn(&K, &K) -> bool,
+{
+    let entries = entries.into_iter();
+    let len = entries.len();
+    write_compact_len(dst, len as u64);
+    let mut previous = None;
+    let mut count = 0;
+    for (key, value) in entries.take(len) {
+        if matches!(&previous, Some(previous) if !ordered(previous, &key)) {
+            return Err(Error::UnorderedKeys { index: count });
+        }
+        try_serialize_to(&key, dst)?;
+        try_serialize_to(&value, dst)?;
+        previous = Some(key);
+        count += 1;
+    }
+    assert_eq!(count, len, "Iterator yielded fewer entries than its length");
+    Ok(())
+}
+
+#[cfg(all(test, feature = "alloc", feature = "encode", feature = "decode"))]
+mod tests {
+    use super::{encode_map_iter, encode_map_iter_checked};
+    use crate::{decode_all, encode, Error};
+    use alloc::{collections::BTreeMap, format, string::String, vec, vec::Vec};
+
+    fn balances() -> BTreeMap<String, u64> {
+        (0..20u64).map(|i| (format!("account-{:02}", i), i * 1000)).collect()
+    }
+
+    #[test]
+    fn sorted_entries_encode_like_the_collected_map() {
+        let mut encoded = Vec::new();
+        encode_map_iter(balances(), &mut encoded).unwrap();
+        assert_eq!(encoded, encode(&balances()));
+        let mut checked = Vec::new();
+        encode_map_iter_checked(balances().iter(), &mut checked).unwrap();
+        assert_eq!(checked, encoded);
+        assert_eq!(decode_all::<BTreeMap<String, u64>>(&encoded).unwrap(), balances());
+    }
+
+    #[test]
+    fn integer_keys_are_ordered_like_btree_maps() {
+        let keys = [1u32, 255, 256, 65536, u32::MAX];
+        let mut encoded = Vec::new();
+        encode_map_iter_checked(keys.iter().map(|&key| (key, ())), &mut encoded).unwrap();
+        assert_eq!(encoded, encode(&keys.iter().map(|&key| (key, ())).collect::<BTreeMap<_, _>>()));
+    }
+
+    #[test]
+    fn empty_iterators_encode_empty_maps() {
+        let mut encoded = Vec::new();
+        encode_map_iter_checked(Vec::<(u8, u8)>::new(), &mut encoded).unwrap();
+        assert_eq!(encoded, encode(&BTreeMap::<u8, u8>::new()));
+    }
+
+    #[test]
+    fn unordered_keys_are_only_rejected_when_checked() {
+        let entries = vec![("b", 2u8), ("a", 1), ("c", 3)];
+        let mut encoded = Vec::new();
+        encode_map_iter(entries.clone(), &mut encoded).unwrap();
+        assert_eq!(encoded, encode(&entries));
+        let result = encode_map_iter_checked(entries, &mut Vec::new());
+        assert!(matches!(result, Err(Error::UnorderedKeys { index: 1 })));
+    }
+
+    #[test]
+    fn duplicate_keys_are_rejected_when_checked() {
+        let result = encode_map_iter_checked(vec![(1u8, 'a'), (2, 'b'), (2, 'c')], &mut Vec::new());
+        assert!(matches!(result, Err(Error::UnorderedKeys { index: 2 })));
+    }
+
+    #[test]
+    fn serialization_failures_are_reported() {
+        let result = encode_map_iter(vec![(1u8, 2u128)], &mut Vec::new());
+        assert!(matches!(result, Err(Error::Serialization(_))));
+    }
+}
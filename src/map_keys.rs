@@ -0,0 +1,608 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Validation of the shapes of map keys
+//!
+//! Encoding probes each key with a serializer accepting only the allowed shapes before
+//! serializing it. Decoding deserializes each key through a deserializer forwarding only the
+//! allowed shapes. Newtype structs are transparent in SCALE, so they have the shape of the value
+//! they wrap.
+
+use core::fmt;
+use core::any::type_name;
+#[cfg(feature = "decode")]
+use core::cell::Cell;
+#[cfg(feature = "decode")]
+use serde::de::{DeserializeSeed, SeqAccess, Visitor};
+#[cfg(feature = "encode")]
+use serde::ser::{Impossible, Serialize, SerializeTuple};
+
+/// Shapes of map keys accepted by [`EncodeOptions::map_keys`](crate::EncodeOptions::map_keys)
+/// and [`DecodeOptions::map_keys`](crate::DecodeOptions::map_keys)
+///
+/// Maps with other keys (e.g. enums or structs) fail to encode and decode with
+/// [`Error::UnsupportedMapKey`](crate::Error::UnsupportedMapKey) naming the type of the key, so
+/// that layouts peers using `parity-scale-codec` do not expect are caught before reaching them.
+/// Newtype structs have the shape of the value they wrap. The default shapes are integers, fixed
+/// byte arrays (e.g. `[u8; 32]`) and strings.
+///
+/// ```rust
+/// use serde_scale_wrap::{EncodeOptions, Error, MapKeyShapes};
+/// use std::collections::BTreeMap;
+///
+/// let options = EncodeOptions::new().map_keys(MapKeyShapes::default());
+/// let balances = BTreeMap::from([([7u8; 32], 100u64)]);
+/// assert_eq!(options.try_encode(&balances)?, serde_scale_wrap::encode(&balances));
+/// let options = EncodeOptions::new().map_keys(MapKeyShapes::new().integers(true));
+/// let result = options.try_encode(&balances);
+/// assert!(matches!(result, Err(Error::UnsupportedMapKey { type_name: "[u8; 32]" })));
+/// # Ok::<_, Error>(())
+/// ```
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct MapKeyShapes {
+    integers: bool,
+    byte_arrays: bool,
+    strings: bool,
+}
+
+impl MapKeyShapes {
+    /// Returns shapes accepting no key at all
+    pub fn new() -> Self {
+        MapKeyShapes { integers: false, byte_arrays: false, strings: false }
+    }
+
+    /// Sets whether signed and unsigned integers are accepted
+    pub fn integers(mut self, accepted: bool) -> Self {
+        self.integers = accepted;
+        self
+    }
+
+    /// Sets whether fixed-length arrays and tuples of bytes are accepted
+    pub fn byte_arrays(mut self, accepted: bool) -> Self {
+        self.byte_arrays = accepted;
+        self
+    }
+
+    /// Sets whether strings are accepted
+    pub fn strings(mut self, accepted: bool) -> Self {
+        self.strings = accepted;
+        self
+    }
+}
+
+impl Default for MapKeyShapes {
+    fn default() -> Self {
+        MapKeyShapes { integers: true, byte_arrays: true, strings: true }
+    }
+}
+
+/// Error message when a map key does not have an accepted shape
+#[cfg(feature = "decode")]
+pub(crate) struct Unsupported(pub(crate) &'static str);
+
+#[cfg(feature = "decode")]
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Map keys of type `{}` are not supported", self.0)
+    }
+}
+
+/// Returns whether `key` has one of `shapes`
+#[cfg(feature = "encode")]
+pub(crate) fn is_supported<T: Serialize + ?Sized>(key: &T, shapes: MapKeyShapes) -> bool {
+    key.serialize(ShapeProbe::Key(shapes)).is_ok()
+}
+
+/// Returns the name of the type of a key serialized as a `T`, which maps serialize by reference
+#[cfg(feature = "encode")]
+pub(crate) fn key_type_name<T: ?Sized>() -> &'static str {
+    type_name::<T>().trim_start_matches('&')
+}
+
+/// Serializer succeeding only for keys of the accepted shapes, or for bytes
+#[cfg(feature = "encode")]
+#[derive(Clone, Copy)]
+enum ShapeProbe {
+    Key(MapKeyShapes),
+    /// Element of a byte array
+    Byte,
+}
+
+#[cfg(feature = "encode")]
+impl ShapeProbe {
+    fn accept(accepted: bool) -> Result<(), Rejected> {
+        if accepted {
+            Ok(())
+        } else {
+            Err(Rejected)
+        }
+    }
+
+    fn integer(self) -> Result<(), Rejected> {
+        Self::accept(matches!(self, ShapeProbe::Key(shapes) if shapes.integers))
+    }
+
+    fn string(self) -> Result<(), Rejected> {
+        Self::accept(matches!(self, ShapeProbe::Key(shapes) if shapes.strings))
+    }
+}
+
+/// Error of `ShapeProbe`
+#[cfg(feature = "encode")]
+#[derive(Debug)]
+struct Rejected;
+
+#[cfg(feature = "encode")]
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Unsupported map key")
+    }
+}
+
+#[cfg(feature = "encode")]
+impl serde::ser::StdError for Rejected {}
+
+#[cfg(feature = "encode")]
+impl serde::ser::Error for Rejected {
+    fn custom<T: fmt::Display>(_: T) -> Self {
+        Rejected
+    }
+}
+
+#[cfg(feature = "encode")]
+macro_rules! probe {
+    ($($method:ident($ty:ty) => $check:ident,)*) => {
+        $(
+            fn $method(self, _: $ty) -> Result<(), Rejected> {
+                self.$check()
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "encode")]
+impl serde::Serializer for ShapeProbe {
+    type Ok = ();
+    type Error = Rejected;
+    type SerializeSeq = Impossible<(), Rejected>;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Impossible<(), Rejected>;
+    type SerializeTupleVariant = Impossible<(), Rejected>;
+    type SerializeMap = Impossible<(), Rejected>;
+    type SerializeStruct = Impossible<(), Rejected>;
+    type SerializeStructVariant = Impossible<(), Rejected>;
+
+    probe! {
+        serialize_i8(i8) => integer,
+        serialize_i16(i16) => integer,
+        serialize_i32(i32) => integer,
+        serialize_i64(i64) => integer,
+        serialize_i128(i128) => integer,
+        serialize_u16(u16) => integer,
+        serialize_u32(u32) => integer,
+        serialize_u64(u64) => integer,
+        serialize_u128(u128) => integer,
+        serialize_str(&str) => string,
+    }
+
+    fn serialize_u8(self, _: u8) -> Result<(), Rejected> {
+        match self {
+            ShapeProbe::Key(_) => self.integer(),
+            ShapeProbe::Byte => Ok(()),
+        }
+    }
+
+    fn serialize_bool(self, _: bool) -> Result<(), Rejected> {
+        Err(Rejected)
+    }
+
+    fn serialize_f32(self, _: f32) -> Result<(), Rejected> {
+        Err(Rejected)
+    }
+
+    fn serialize_f64(self, _: f64) -> Result<(), Rejected> {
+        Err(Rejected)
+    }
+
+    fn serialize_char(self, _: char) -> Result<(), Rejected> {
+        Err(Rejected)
+    }
+
+    fn serialize_bytes(self, _: &[u8]) -> Result<(), Rejected> {
+        Err(Rejected)
+    }
+
+    fn serialize_none(self) -> Result<(), Rejected> {
+        Err(Rejected)
+    }
+
+    fn serialize_some<T>(self, _: &T) -> Result<(), Rejected>
+    where
+        T: Serialize + ?Sized,
+    {
+        Err(Rejected)
+    }
+
+    fn serialize_unit(self) -> Result<(), Rejected> {
+        Err(Rejected)
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<(), Rejected> {
+        Err(Rejected)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+    ) -> Result<(), Rejected> {
+        Err(Rejected)
+    }
+
+    fn serialize_newtype_struct<T>(self, _: &'static str, value: &T) -> Result<(), Rejected>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<(), Rejected>
+    where
+        T: Serialize + ?Sized,
+    {
+        Err(Rejected)
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, Rejected> {
+        Err(Rejected)
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<Self, Rejected> {
+        Self::accept(matches!(self, ShapeProbe::Key(shapes) if shapes.byte_arrays))?;
+        Ok(ShapeProbe::Byte)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleStruct, Rejected> {
+        Err(Rejected)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleVariant, Rejected> {
+        Err(Rejected)
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, Rejected> {
+        Err(Rejected)
+    }
+
+    fn serialize_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStruct, Rejected> {
+        Err(Rejected)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStructVariant, Rejected> {
+        Err(Rejected)
+    }
+
+    fn collect_str<T>(self, _: &T) -> Result<(), Rejected>
+    where
+        T: fmt::Display + ?Sized,
+    {
+        self.string()
+    }
+}
+
+/// Probe of the elements of a byte array
+#[cfg(feature = "encode")]
+impl SerializeTuple for ShapeProbe {
+    type Ok = ();
+    type Error = Rejected;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Rejected>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(ShapeProbe::Byte)
+    }
+
+    fn end(self) -> Result<(), Rejected> {
+        Ok(())
+    }
+}
+
+/// Deserializer of a map key forwarding only the accepted shapes to `inner`, or only bytes
+///
+/// Keys of other shapes are reported to `rejected` before failing.
+#[cfg(feature = "decode")]
+pub(crate) struct KeyDeserializer<'a, D> {
+    inner: D,
+    /// Accepted shapes, or `None` for the elements of a byte array
+    shapes: Option<MapKeyShapes>,
+    /// Name of the type of the key
+    type_name: &'static str,
+    rejected: Option<&'a Cell<Option<&'static str>>>,
+}
+
+#[cfg(feature = "decode")]
+impl<'a, D> KeyDeserializer<'a, D> {
+    /// Returns a deserializer of a key of type `K` with one of `shapes`
+    pub(crate) fn new<K: ?Sized>(
+        inner: D,
+        shapes: MapKeyShapes,
+        rejected: Option<&'a Cell<Option<&'static str>>>,
+    ) -> Self {
+        KeyDeserializer { inner, shapes: Some(shapes), type_name: type_name::<K>(), rejected }
+    }
+
+    fn reject<T, E: serde::de::Error>(&self) -> Result<T, E> {
+        if let Some(rejected) = self.rejected {
+            rejected.set(Some(self.type_name));
+        }
+        Err(E::custom(Unsupported(self.type_name)))
+    }
+
+    fn is_integer_accepted(&self) -> bool {
+        self.shapes.is_some_and(|shapes| shapes.integers)
+    }
+
+    fn is_string_accepted(&self) -> bool {
+        self.shapes.is_some_and(|shapes| shapes.strings)
+    }
+}
+
+#[cfg(feature = "decode")]
+macro_rules! forward_if {
+    ($($method:ident => $accepted:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+                if self.$accepted() {
+                    self.inner.$method(visitor)
+                } else {
+                    self.reject()
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "decode")]
+macro_rules! reject {
+    ($($method:ident($($arg:ty),*),)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, $(_: $arg,)* _: V) -> Result<V::Value, D::Error> {
+                self.reject()
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "decode")]
+impl<'de, D: serde::Deserializer<'de>> serde::Deserializer<'de> for KeyDeserializer<'_, D> {
+    type Error = D::Error;
+
+    forward_if! {
+        deserialize_i8 => is_integer_accepted,
+        deserialize_i16 => is_integer_accepted,
+        deserialize_i32 => is_integer_accepted,
+        deserialize_i64 => is_integer_accepted,
+        deserialize_i128 => is_integer_accepted,
+        deserialize_u16 => is_integer_accepted,
+        deserialize_u32 => is_integer_accepted,
+        deserialize_u64 => is_integer_accepted,
+        deserialize_u128 => is_integer_accepted,
+        deserialize_str => is_string_accepted,
+        deserialize_string => is_string_accepted,
+    }
+
+    reject! {
+        deserialize_any(),
+        deserialize_bool(),
+        deserialize_f32(),
+        deserialize_f64(),
+        deserialize_char(),
+        deserialize_bytes(),
+        deserialize_byte_buf(),
+        deserialize_option(),
+        deserialize_unit(),
+        deserialize_unit_struct(&'static str),
+        deserialize_seq(),
+        deserialize_tuple_struct(&'static str, usize),
+        deserialize_map(),
+        deserialize_struct(&'static str, &'static [&'static str]),
+        deserialize_enum(&'static str, &'static [&'static str]),
+        deserialize_identifier(),
+        deserialize_ignored_any(),
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        if self.shapes.is_none() || self.is_integer_accepted() {
+            self.inner.deserialize_u8(visitor)
+        } else {
+            self.reject()
+        }
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, D::Error>
+    where
+        V: Visitor<'de>,
+    {
+        // Newtype structs are encoded as the value they wrap.
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value, D::Error>
+    where
+        V: Visitor<'de>,
+    {
+        if !self.shapes.is_some_and(|shapes| shapes.byte_arrays) {
+            return self.reject();
+        }
+        let KeyDeserializer { inner, type_name, rejected, .. } = self;
+        let elements = KeyDeserializer { inner: visitor, shapes: None, type_name, rejected };
+        inner.deserialize_tuple(len, elements)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+}
+
+/// Visitor of a byte array key, deserializing each element as a byte
+#[cfg(feature = "decode")]
+impl<'de, V: Visitor<'de>> Visitor<'de> for KeyDeserializer<'_, V> {
+    type Value = V::Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.expecting(f)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<V::Value, A::Error> {
+        let KeyDeserializer { inner, type_name, rejected, .. } = self;
+        inner.visit_seq(KeyDeserializer { inner: seq, shapes: None, type_name, rejected })
+    }
+}
+
+/// Elements of a byte array key
+#[cfg(feature = "decode")]
+impl<'de, A: SeqAccess<'de>> SeqAccess<'de> for KeyDeserializer<'_, A> {
+    type Error = A::Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, A::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        let (type_name, rejected) = (self.type_name, self.rejected);
+        let seed = KeyDeserializer { inner: seed, shapes: None, type_name, rejected };
+        self.inner.next_element_seed(seed)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+/// Seed of an element of a byte array key
+#[cfg(feature = "decode")]
+impl<'de, S: DeserializeSeed<'de>> DeserializeSeed<'de> for KeyDeserializer<'_, S> {
+    type Value = S::Value;
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, d: D) -> Result<S::Value, D::Error> {
+        let KeyDeserializer { inner, type_name, rejected, .. } = self;
+        inner.deserialize(KeyDeserializer { inner: d, shapes: None, type_name, rejected })
+    }
+}
+
+#[cfg(all(test, feature = "alloc", feature = "encode", feature = "decode"))]
+mod tests {
+    use super::MapKeyShapes;
+    use crate::{encode, DecodeOptions, EncodeOptions, Error};
+    use alloc::{collections::BTreeMap, string::String};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+    enum Asset {
+        Native,
+        Token(u32),
+    }
+
+    #[derive(Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+    struct AccountId([u8; 4]);
+
+    fn strict() -> MapKeyShapes {
+        MapKeyShapes::default()
+    }
+
+    fn balances() -> BTreeMap<Asset, u64> {
+        BTreeMap::from([(Asset::Native, 100), (Asset::Token(7), 3)])
+    }
+
+    #[test]
+    fn integer_keys_are_accepted() {
+        let map = BTreeMap::from([(1u32, String::from("a")), (u32::MAX, String::from("b"))]);
+        let encoded = EncodeOptions::new().map_keys(strict()).try_encode(&map).unwrap();
+        assert_eq!(encoded, encode(&map));
+        let decoded = DecodeOptions::new().map_keys(strict()).decode_all(&encoded).unwrap();
+        assert_eq!(map, decoded);
+    }
+
+    #[test]
+    fn byte_array_and_string_keys_are_accepted() {
+        let map = BTreeMap::from([(AccountId([1; 4]), (String::from("alice"), 5u8))]);
+        let encoded = EncodeOptions::new().map_keys(strict()).try_encode(&map).unwrap();
+        let decoded = DecodeOptions::new().map_keys(strict()).decode_all(&encoded).unwrap();
+        assert_eq!(map, decoded);
+        let nested = BTreeMap::from([(String::from("alice"), map)]);
+        let encoded = EncodeOptions::new().map_keys(strict()).try_encode(&nested).unwrap();
+        let decoded = DecodeOptions::new().map_keys(strict()).decode_all(&encoded).unwrap();
+        assert_eq!(nested, decoded);
+    }
+
+    #[test]
+    fn enum_keys_are_rejected_when_strict() {
+        let result = EncodeOptions::new().map_keys(strict()).try_encode(&balances());
+        match result {
+            Err(Error::UnsupportedMapKey { type_name }) => assert!(type_name.ends_with("Asset")),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        let result = DecodeOptions::new().map_keys(strict()).decode_all::<BTreeMap<Asset, u64>>(
+            &encode(&balances()),
+        );
+        match result {
+            Err(Error::UnsupportedMapKey { type_name }) => assert!(type_name.ends_with("Asset")),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn enum_keys_are_accepted_when_lenient() {
+        let encoded = EncodeOptions::new().try_encode(&balances()).unwrap();
+        assert_eq!(encoded, encode(&balances()));
+        let decoded = DecodeOptions::new().decode_all::<BTreeMap<Asset, u64>>(&encoded).unwrap();
+        assert_eq!(decoded, balances());
+    }
+
+    #[test]
+    fn shapes_can_be_narrowed() {
+        let map = BTreeMap::from([([1u8, 2], 3u8)]);
+        let integers = MapKeyShapes::new().integers(true);
+        let result = EncodeOptions::new().map_keys(integers).try_encode(&map);
+        assert!(matches!(result, Err(Error::UnsupportedMapKey { type_name: "[u8; 2]" })));
+        let options = DecodeOptions::new().map_keys(integers);
+        let result = options.decode_all::<BTreeMap<[u8; 2], u8>>(&encode(&map));
+        assert!(matches!(result, Err(Error::UnsupportedMapKey { type_name: "[u8; 2]" })));
+        let tuples = BTreeMap::from([((1u8, 2u16), 3u8)]);
+        let result = EncodeOptions::new().map_keys(strict()).try_encode(&tuples);
+        assert!(matches!(result, Err(Error::UnsupportedMapKey { type_name: "(u8, u16)" })));
+        let options = DecodeOptions::new().map_keys(strict());
+        let result = options.decode_all::<BTreeMap<(u8, u16), u8>>(&encode(&tuples));
+        assert!(matches!(result, Err(Error::UnsupportedMapKey { type_name: "(u8, u16)" })));
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+use core::marker::PhantomData;
+
+/// Types whose encodings by `Wrap` have a maximum length, making `Wrap<Self>` implement
+/// `MaxEncodedLen`
+///
+/// `MaxEncodedLen` requires `Encode`, which types only implementing `Serialize` lack, and it cannot
+/// be implemented for `Wrap<T>` outside of this crate. Implementing this trait for `T` instead lets
+/// `Wrap<T>` key and value storage items of pallets generating their storage info. The length
+/// returned must be at least the length of the encoding of any value of the type by `Wrap`.
+///
+/// ```rust
+/// use parity_scale_codec::MaxEncodedLen;
+/// use serde::Serialize;
+/// use serde_scale_wrap::{MaxWrappedLen, Wrap};
+///
+/// #[derive(Serialize)]
+/// struct Key {
+///     account: [u8; 32],
+///     index: Option<u32>,
+/// }
+///
+/// impl MaxWrappedLen for Key {
+///     fn max_wrapped_len() -> usize {
+///         <[u8; 32]>::max_wrapped_len() + Option::<u32>::max_wrapped_len()
+///     }
+/// }
+///
+/// assert_eq!(Wrap::<Key>::max_encoded_len(), 37);
+/// ```
+pub trait MaxWrappedLen {
+    /// Returns the maximum length of the encoding of a value of this type by `Wrap`
+    fn max_wrapped_len() -> usize;
+}
+
+macro_rules! impl_fixed_len {
+    ($($ty:ty,)*) => {
+        $(
+            impl MaxWrappedLen for $ty {
+                fn max_wrapped_len() -> usize {
+                    core::mem::size_of::<$ty>()
+                }
+            }
+        )*
+    };
+}
+
+impl_fixed_len! {
+    (), bool, char, i8, i16, i32, i64, u8, u16, u32, u64,
+}
+
+impl<T> MaxWrappedLen for PhantomData<T> {
+    fn max_wrapped_len() -> usize {
+        0
+    }
+}
+
+impl<T: MaxWrappedLen> MaxWrappedLen for Option<T> {
+    fn max_wrapped_len() -> usize {
+        T::max_wrapped_len().saturating_add(1)
+    }
+}
+
+impl<T: MaxWrappedLen, const N: usize> MaxWrappedLen for [T; N] {
+    fn max_wrapped_len() -> usize {
+        T::max_wrapped_len().saturating_mul(N)
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($ty:ident),+) => {
+        impl<$($ty: MaxWrappedLen),+> MaxWrappedLen for ($($ty,)+) {
+            fn max_wrapped_len() -> usize {
+                0usize$(.saturating_add($ty::max_wrapped_len()))+
+            }
+        }
+    };
+}
+
+impl_tuple!(A);
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+
+#[cfg(all(test, feature = "alloc", feature = "decode"))]
+mod tests {
+    use super::MaxWrappedLen;
+    use crate::{encode, Wrap};
+    use parity_scale_codec::MaxEncodedLen;
+    use serde::Serialize;
+
+    fn assert_max<T: Serialize + MaxWrappedLen>(value: T) {
+        assert_eq!(encode(&value).len(), T::max_wrapped_len());
+        assert_eq!(Wrap::<T>::max_encoded_len(), T::max_wrapped_len());
+    }
+
+    #[test]
+    fn maximum_lengths_are_reached() {
+        assert_max(());
+        assert_max(true);
+        assert_max(char::MAX);
+        assert_max(i64::MIN);
+        assert_max(u16::MAX);
+        assert_max(Some(7u32));
+        assert_max([Some(1u8); 5]);
+        assert_max((1u8, [2u64; 3], Some(('a', -4i16))));
+        assert_max(core::marker::PhantomData::<u32>);
+    }
+
+    #[test]
+    fn shorter_encodings_are_below_maximum() {
+        assert!(encode(&None::<u64>).len() < Option::<u64>::max_wrapped_len());
+        assert!(encode(&[None::<u8>; 4]).len() < <[Option<u8>; 4]>::max_wrapped_len());
+    }
+
+    struct Huge;
+
+    impl MaxWrappedLen for Huge {
+        fn max_wrapped_len() -> usize {
+            usize::MAX - 1
+        }
+    }
+
+    #[test]
+    fn lengths_saturate() {
+        assert_eq!(<[Huge; 2]>::max_wrapped_len(), usize::MAX);
+        assert_eq!(<(Option<Huge>, u8)>::max_wrapped_len(), usize::MAX);
+    }
+}
@@ -0,0 +1,328 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Enums preserving the variants unknown to the decoder
+
+use alloc::vec::Vec;
+#[cfg(feature = "decode")]
+use core::{fmt, marker::PhantomData};
+#[cfg(feature = "decode")]
+use serde::de::{self, DeserializeOwned, Deserializer, SeqAccess, Visitor};
+#[cfg(feature = "encode")]
+use serde::ser::{Serialize, SerializeTuple, Serializer};
+
+/// Name of the newtype struct `OpenEnum` deserializes, recognized by the deserializer of this
+/// crate
+#[cfg(feature = "decode")]
+pub(crate) const NAME: &str = "$serde_scale_wrap::OpenEnum";
+
+/// Enum whose variants unknown to the decoder are kept as their index and encoded fields
+///
+/// Peers running a newer version may send variants an older decoder does not know, and
+/// middleboxes relaying them must re-encode them unchanged. An `OpenEnum<T>` decodes the variants
+/// of the enum `T` into [`OpenEnum::Known`], and any other variant into [`OpenEnum::Unknown`],
+/// which encodes back into the very same bytes.
+///
+/// ```rust
+/// use serde::{Deserialize, Serialize};
+/// use serde_scale_wrap::{LengthPrefixed, OpenEnum};
+///
+/// #[derive(Debug, Deserialize, PartialEq, Serialize)]
+/// enum Command {
+///     Stop,
+///     Move(u16, u16),
+/// }
+///
+/// let known = serde_scale_wrap::encode(&LengthPrefixed(Command::Move(3, 4)));
+/// let decoded = serde_scale_wrap::decode_all::<LengthPrefixed<OpenEnum<Command>>>(&known)?;
+/// assert_eq!(decoded.into_inner(), OpenEnum::Known(Command::Move(3, 4)));
+///
+/// // Variant #2 was added by a newer version and holds a `u32`.
+/// let unknown = [20, 2, 1, 0, 0, 0];
+/// let decoded = serde_scale_wrap::decode_all::<LengthPrefixed<OpenEnum<Command>>>(&unknown)?;
+/// assert_eq!(decoded.0, OpenEnum::Unknown { index: 2, payload: vec![1, 0, 0, 0] });
+/// assert_eq!(serde_scale_wrap::encode(&decoded), unknown);
+/// # Ok::<_, serde_scale_wrap::Error>(())
+/// ```
+///
+/// # Decoding
+/// SCALE does not record the length of the fields of a variant, so the fields of an unknown
+/// variant cannot be told apart from the bytes following them. Like a
+/// [`RawScale`](crate::RawScale), an unknown variant can thus only be decoded as the whole payload
+/// of a [`LengthPrefixed`](crate::LengthPrefixed), which records the length. Known variants are
+/// decoded anywhere, but an unknown variant met elsewhere fails with
+/// [`Error::Deserialization`](crate::Error::Deserialization). Known variants decoded from a
+/// payload are decoded with the default options, like the value of a `LengthPrefixed`.
+///
+/// Other formats serialize known variants like `T`, and unknown variants as a sequence holding
+/// the index followed by the bytes of the fields. They only deserialize known variants.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum OpenEnum<T> {
+    /// Variant of `T`
+    Known(T),
+    /// Variant unknown to `T`
+    Unknown {
+        /// Index of the variant
+        index: u8,
+        /// SCALE encoding of the fields of the variant
+        payload: Vec<u8>,
+    },
+}
+
+impl<T> OpenEnum<T> {
+    /// Returns the known variant, if any
+    pub fn known(self) -> Option<T> {
+        match self {
+            OpenEnum::Known(value) => Some(value),
+            OpenEnum::Unknown { .. } => None,
+        }
+    }
+
+    /// Indicates if the variant is unknown
+    pub fn is_unknown(&self) -> bool {
+        matches!(self, OpenEnum::Unknown { .. })
+    }
+}
+
+impl<T> From<T> for OpenEnum<T> {
+    fn from(value: T) -> Self {
+        OpenEnum::Known(value)
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<T: Serialize> Serialize for OpenEnum<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            OpenEnum::Known(value) => value.serialize(serializer),
+            OpenEnum::Unknown { index, payload } => {
+                // Tuples are encoded as their elements, without any length prefix.
+                let mut tuple = serializer.serialize_tuple(1 + payload.len())?;
+                tuple.serialize_element(index)?;
+                for byte in payload {
+                    tuple.serialize_element(byte)?;
+                }
+                tuple.end()
+            }
+        }
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<'de, T: DeserializeOwned> de::Deserialize<'de> for OpenEnum<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_newtype_struct(NAME, OpenVisitor(PhantomData))
+    }
+}
+
+/// Error message when decoding an unknown variant of an `OpenEnum` whose length is unknown
+#[cfg(feature = "decode")]
+pub(crate) struct OutsidePayload(pub(crate) u32);
+
+#[cfg(feature = "decode")]
+impl fmt::Display for OutsidePayload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Unknown variant #{} of an `OpenEnum` can only be decoded as the whole payload of a \
+            `LengthPrefixed`",
+            self.0,
+        )
+    }
+}
+
+/// Visitor of an `OpenEnum<T>`
+///
+/// The deserializer of this crate visits a sequence holding every byte of the payload, or a
+/// newtype struct holding a known variant outside of a payload. Other deserializers visit a
+/// newtype struct holding the `T`.
+#[cfg(feature = "decode")]
+struct OpenVisitor<T>(PhantomData<T>);
+
+#[cfg(feature = "decode")]
+impl<'de, T: DeserializeOwned> Visitor<'de> for OpenVisitor<T> {
+    type Value = OpenEnum<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("an enum variant")
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(self, d: D) -> Result<Self::Value, D::Error> {
+        T::deserialize(d).map(OpenEnum::Known)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        let index = *bytes.first().ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let len = variant_count::<T>()
+            .ok_or_else(|| de::Error::custom("`OpenEnum` can only hold an enum"))?;
+        if usize::from(index) < len {
+            return crate::decode_payload(&bytes).map(OpenEnum::Known).map_err(de::Error::custom);
+        }
+        bytes.remove(0);
+        Ok(OpenEnum::Unknown { index, payload: bytes })
+    }
+}
+
+/// Returns the number of variants of `T`, or `None` if it is not an enum
+#[cfg(feature = "decode")]
+fn variant_count<T: DeserializeOwned>() -> Option<usize> {
+    match T::deserialize(VariantProbe) {
+        Ok(_) => None,
+        Err(ProbeError(len)) => len,
+    }
+}
+
+/// Deserializer failing with the number of variants of the enum it is asked to deserialize
+#[cfg(feature = "decode")]
+struct VariantProbe;
+
+/// Error of `VariantProbe`, holding the number of variants if an enum was deserialized
+#[cfg(feature = "decode")]
+#[derive(Debug)]
+struct ProbeError(Option<usize>);
+
+#[cfg(feature = "decode")]
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Not an enum")
+    }
+}
+
+#[cfg(feature = "decode")]
+impl de::StdError for ProbeError {}
+
+#[cfg(feature = "decode")]
+impl de::Error for ProbeError {
+    fn custom<M: fmt::Display>(_: M) -> Self {
+        ProbeError(None)
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<'de> Deserializer<'de> for VariantProbe {
+    type Error = ProbeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, ProbeError> {
+        Err(ProbeError(None))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        variants: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, ProbeError> {
+        Err(ProbeError(Some(variants.len())))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map struct identifier
+        ignored_any
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::OpenEnum;
+    use crate::{decode_all, encode, Error, LengthPrefixed, Wrap};
+    use alloc::{string::{String, ToString}, vec, vec::Vec};
+    use parity_scale_codec::Decode;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    enum Command {
+        Stop,
+        Move { x: u16, y: u16 },
+        Say(String),
+    }
+
+    /// `Command` as extended by a newer version
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    enum NewerCommand {
+        Stop,
+        Move { x: u16, y: u16 },
+        Say(String),
+        Resize(u32, Vec<u8>),
+        Quit,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Envelope {
+        id: u8,
+        command: LengthPrefixed<OpenEnum<Command>>,
+        seq: u32,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct NewerEnvelope {
+        id: u8,
+        command: LengthPrefixed<NewerCommand>,
+        seq: u32,
+    }
+
+    fn sent(command: NewerCommand) -> Vec<u8> {
+        encode(&NewerEnvelope { id: 1, command: LengthPrefixed(command), seq: 0xdead_beef })
+    }
+
+    #[test]
+    fn known_variants_decode_into_the_enum() {
+        let Wrap(envelope) = Wrap::<Envelope>::decode(&mut &*sent(NewerCommand::Say("hi".into())))
+            .unwrap();
+        assert_eq!(envelope.command.into_inner(), OpenEnum::Known(Command::Say("hi".into())));
+        assert_eq!(envelope.seq, 0xdead_beef);
+        let encoded = encode(&(OpenEnum::Known(Command::Move { x: 1, y: 2 }), 7u8));
+        assert_eq!(encoded, encode(&(Command::Move { x: 1, y: 2 }, 7u8)));
+        let decoded = decode_all::<(OpenEnum<Command>, u8)>(&encoded).unwrap();
+        assert_eq!(decoded, (OpenEnum::Known(Command::Move { x: 1, y: 2 }), 7));
+    }
+
+    #[test]
+    fn unknown_variants_roundtrip_unchanged() {
+        let commands = [NewerCommand::Resize(640, vec![4, 8, 0]), NewerCommand::Quit];
+        for (command, index) in commands.iter().cloned().zip([3u8, 4]) {
+            let encoded = sent(command.clone());
+            let envelope = decode_all::<Envelope>(&encoded).unwrap();
+            let payload = encode(&command)[1..].to_vec();
+            assert_eq!(envelope.command.0, OpenEnum::Unknown { index, payload });
+            assert_eq!(envelope.seq, 0xdead_beef);
+            assert_eq!(encode(&envelope), encoded);
+            #[cfg(not(feature = "deny-panicking-encode"))]
+            assert_eq!(parity_scale_codec::Encode::encode(&Wrap(&envelope)), encoded);
+        }
+    }
+
+    #[test]
+    fn unknown_variants_outside_a_payload_are_rejected() {
+        let encoded = encode(&(NewerCommand::Resize(640, vec![]), 7u8));
+        match decode_all::<(OpenEnum<Command>, u8)>(&encoded) {
+            Err(e @ Error::Deserialization(_)) => {
+                let message = e.to_string();
+                assert!(message.contains("Unknown variant #3"), "{}", message);
+                assert!(message.contains("whole payload of a `LengthPrefixed`"), "{}", message);
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn malformed_payloads_are_rejected() {
+        assert!(decode_all::<LengthPrefixed<OpenEnum<Command>>>(&[0]).is_err());
+        assert!(decode_all::<LengthPrefixed<OpenEnum<Command>>>(&[8, 1, 1]).is_err());
+        assert!(decode_all::<LengthPrefixed<OpenEnum<Command>>>(&[8, 0, 1]).is_err());
+        assert!(decode_all::<LengthPrefixed<OpenEnum<u8>>>(&[4, 0]).is_err());
+    }
+
+    #[test]
+    fn other_formats_roundtrip_known_variants() {
+        let command = OpenEnum::Known(Command::Move { x: 1, y: 2 });
+        let json = serde_json::to_string(&command).unwrap();
+        assert_eq!(json, r#"{"Move":{"x":1,"y":2}}"#);
+        assert_eq!(serde_json::from_str::<OpenEnum<Command>>(&json).unwrap(), command);
+        let unknown = OpenEnum::<Command>::Unknown { index: 4, payload: vec![5] };
+        assert_eq!(serde_json::to_string(&unknown).unwrap(), "[4,5]");
+    }
+}
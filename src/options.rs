@@ -0,0 +1,549 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+#[cfg(any(feature = "alloc", feature = "encode"))]
+use crate::Error;
+
+#[cfg(all(feature = "alloc", feature = "encode"))]
+use crate::adapters::OutputToWrite;
+#[cfg(any(feature = "encode", feature = "decode"))]
+use crate::MapKeyShapes;
+#[cfg(feature = "encode")]
+use crate::TryOutput;
+#[cfg(all(feature = "alloc", feature = "encode"))]
+use alloc::vec::Vec;
+#[cfg(all(feature = "alloc", feature = "decode"))]
+use parity_scale_codec::Input;
+#[cfg(all(feature = "alloc", feature = "decode"))]
+use serde::de::DeserializeOwned;
+#[cfg(feature = "encode")]
+use serde::Serialize;
+
+/// Options changing how values are encoded
+///
+/// The default options encode like `Wrap` does.
+///
+/// ```rust
+/// use serde_scale_wrap::{EncodeOptions, Error};
+///
+/// let nested = Some(Some(Some(7u8)));
+/// assert_eq!(EncodeOptions::new().max_depth(3).try_encode(&nested)?, [1, 1, 1, 7]);
+/// let result = EncodeOptions::new().max_depth(2).try_encode(&nested);
+/// assert!(matches!(result, Err(Error::DepthExceeded { max: 2 })));
+/// # Ok::<_, Error>(())
+/// ```
+#[cfg(feature = "encode")]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct EncodeOptions {
+    pub(crate) max_depth: usize,
+    pub(crate) map_keys: Option<MapKeyShapes>,
+}
+
+#[cfg(feature = "encode")]
+impl EncodeOptions {
+    /// Maximum nesting depth of the default options
+    pub const DEFAULT_MAX_DEPTH: usize = 128;
+
+    /// Returns the default options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how many levels values may nest while being encoded
+    ///
+    /// Options, enum variants with fields, sequences, maps, tuples and structs each add a level,
+    /// so that `Some(vec![(1u8, 2u8)])` nests 3 levels deep. Encoding a value nesting deeper fails
+    /// with [`Error::DepthExceeded`] instead of overflowing the stack, e.g. when encoding a long
+    /// linked list. Defaults to [`EncodeOptions::DEFAULT_MAX_DEPTH`].
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Restricts the keys of maps to `shapes`
+    ///
+    /// Encoding a map whose keys have another shape fails with [`Error::UnsupportedMapKey`]
+    /// naming the type of the key. Keys of any shape are encoded by default.
+    pub fn map_keys(mut self, shapes: MapKeyShapes) -> Self {
+        self.map_keys = Some(shapes);
+        self
+    }
+
+    /// Returns the encoding of `value` with these options, like `try_encode` does
+    #[cfg(feature = "alloc")]
+    pub fn try_encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        let mut encoded = Vec::new();
+        crate::logging::encode_logged::<T, _, _>(&mut encoded, |dst| {
+            let mut serializer = crate::scale::Serializer::new(OutputToWrite::new(dst));
+            crate::ser::serialize(value, &mut serializer, *self, Error::Serialization)
+        })?;
+        Ok(encoded)
+    }
+
+    /// Writes the encoding of `value` to `dst` with these options, like `Wrap::try_encode_to`
+    /// does
+    ///
+    /// `dst` may have been partially written to if an error is returned.
+    pub fn try_encode_to<T, O>(&self, value: &T, dst: &mut O) -> Result<(), Error>
+    where
+        T: Serialize + ?Sized,
+        O: TryOutput + ?Sized,
+    {
+        crate::logging::encode_logged::<T, _, _>(dst, |dst| {
+            crate::try_serialize_to_try_output_with(value, dst, *self)
+        })
+    }
+}
+
+#[cfg(feature = "encode")]
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions { max_depth: Self::DEFAULT_MAX_DEPTH, map_keys: None }
+    }
+}
+
+/// Options changing how values are decoded
+///
+/// The default options decode like `Wrap` does.
+///
+/// ```rust
+/// use serde_scale_wrap::DecodeOptions;
+///
+/// let encoded = b"\x0ca\xffb\x07";
+/// assert!(serde_scale_wrap::decode::<(String, u8)>(encoded).is_err());
+/// let options = DecodeOptions::new().lossy_strings(true);
+/// assert_eq!(options.decode_all::<(String, u8)>(encoded)?, ("a\u{fffd}b".to_owned(), 7));
+/// # Ok::<_, serde_scale_wrap::Error>(())
+/// ```
+#[cfg(feature = "decode")]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct DecodeOptions {
+    #[cfg(feature = "alloc")]
+    pub(crate) lossy_strings: bool,
+    #[cfg(feature = "alloc")]
+    pub(crate) unknown_variant_fallback: bool,
+    pub(crate) max_preallocation: usize,
+    #[cfg(feature = "std")]
+    pub(crate) on_progress: Option<(u64, fn(u64))>,
+    pub(crate) map_keys: Option<MapKeyShapes>,
+    pub(crate) legacy_map_layout: bool,
+}
+
+#[cfg(feature = "decode")]
+impl DecodeOptions {
+    /// Maximum number of elements pre-allocated per sequence or map of the default options
+    pub const DEFAULT_MAX_PREALLOCATION: usize = 4096;
+
+    /// Returns the default options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how many elements of a sequence or map may be reserved before they are decoded
+    ///
+    /// The lengths of sequences and maps are read from the input and may be forged, e.g. to make
+    /// decoding a `Vec<u64>` from a few bytes reserve gigabytes. The size hints given to visitors
+    /// are thus capped to `max`, and collections grow as their elements are decoded beyond it.
+    /// This does not change what is decoded. Defaults to
+    /// [`DecodeOptions::DEFAULT_MAX_PREALLOCATION`].
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::DecodeOptions;
+    ///
+    /// // Claims 2^29 elements but holds a single one.
+    /// let forged = b"\x02\x00\x00\x80\x07";
+    /// let result = DecodeOptions::new().max_preallocation(0).decode::<Vec<u8>>(forged);
+    /// assert!(result.is_err());
+    /// ```
+    pub fn max_preallocation(mut self, max: usize) -> Self {
+        self.max_preallocation = max;
+        self
+    }
+
+    /// Sets whether strings with invalid UTF-8 are decoded like `String::from_utf8_lossy` does
+    ///
+    /// Invalid sequences are replaced with `U+FFFD REPLACEMENT CHARACTER` instead of failing.
+    /// Byte sequences and other values are decoded as usual. Borrowed strings (e.g. `&str`) still
+    /// fail to decode from invalid UTF-8 since the replacement cannot be borrowed from the input.
+    /// Defaults to `false`.
+    #[cfg(feature = "alloc")]
+    pub fn lossy_strings(mut self, lossy: bool) -> Self {
+        self.lossy_strings = lossy;
+        self
+    }
+
+    /// Sets whether fields deserialized with the [`fallback`](crate::fallback) module decode
+    /// unknown enum variants with `UnknownVariantFallback::unknown`
+    ///
+    /// The fields of unknown variants are skipped, assuming they are a single `LengthPrefixed`.
+    /// Defaults to `false`, failing on unknown variants.
+    #[cfg(feature = "alloc")]
+    pub fn unknown_variant_fallback(mut self, enabled: bool) -> Self {
+        self.unknown_variant_fallback = enabled;
+        self
+    }
+
+    /// Sets a function called with the number of bytes read so far when decoding from a reader
+    ///
+    /// When decoding with [`DecodeOptions::decode_from_buf_read`] or, with the `tokio` feature,
+    /// `DecodeOptions::decode_from_async`, `on_progress` is called once at least `interval` bytes
+    /// were read since the last call, and once more with the length of the encoding after
+    /// decoding succeeds, unless that length was just reported. The counts are those reported
+    /// in the logs when decoding fails. Other decoding functions read from memory and ignore
+    /// this. Defaults to no function, which costs a branch per read.
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::DecodeOptions;
+    /// use std::sync::atomic::{AtomicU64, Ordering};
+    ///
+    /// static READ: AtomicU64 = AtomicU64::new(0);
+    ///
+    /// let encoded = serde_scale_wrap::encode(&vec![String::from("snapshot"); 1000]);
+    /// let options = DecodeOptions::new().on_progress(256, |n| READ.store(n, Ordering::Relaxed));
+    /// let decoded = options.decode_from_buf_read::<Vec<String>, _>(&mut &*encoded)?;
+    /// assert_eq!(decoded.len(), 1000);
+    /// assert_eq!(READ.load(Ordering::Relaxed), encoded.len() as u64);
+    /// # Ok::<_, serde_scale_wrap::Error>(())
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn on_progress(mut self, interval: u64, on_progress: fn(u64)) -> Self {
+        self.on_progress = Some((interval.max(1), on_progress));
+        self
+    }
+
+    /// Restricts the keys of maps to `shapes`
+    ///
+    /// Decoding a map whose keys have another shape fails with
+    /// [`Error::UnsupportedMapKey`](crate::Error::UnsupportedMapKey)
+    /// naming the type of the key, before reading the key from the input. Keys of any shape are
+    /// decoded by default.
+    pub fn map_keys(mut self, shapes: MapKeyShapes) -> Self {
+        self.map_keys = Some(shapes);
+        self
+    }
+
+    /// Sets whether maps are decoded in the layout written by version 0.3.0
+    ///
+    /// Version 0.3.0 wrote maps like `serde_scale` does, prefixing every key with a compact-encoded
+    /// `2` (i.e. a `0x08` byte) that `parity-scale-codec` does not write. Maps written by 0.3.0 do
+    /// not decode as intended with the default options, and this option lets them be decoded to
+    /// be encoded again in the current layout. Decoding fails if a key is not prefixed. Defaults to
+    /// `false`.
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::DecodeOptions;
+    /// use std::collections::BTreeMap;
+    ///
+    /// // `{1: 2}` as written by 0.3.0
+    /// let legacy = b"\x04\x08\x01\x02";
+    /// let options = DecodeOptions::new().legacy_map_layout(true);
+    /// let map = options.decode_all::<BTreeMap<u8, u8>>(legacy)?;
+    /// assert_eq!(serde_scale_wrap::encode(&map), b"\x04\x01\x02");
+    /// # Ok::<_, serde_scale_wrap::Error>(())
+    /// ```
+    pub fn legacy_map_layout(mut self, legacy: bool) -> Self {
+        self.legacy_map_layout = legacy;
+        self
+    }
+
+    /// Decodes a `T` from the beginning of `bytes` with these options, like `decode` does
+    #[cfg(feature = "alloc")]
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        crate::deserialize_from_slice_with(&mut &*bytes, *self)
+    }
+
+    /// Decodes a `T` from `bytes` with these options, failing if bytes are left over, like
+    /// `decode_all` does
+    #[cfg(feature = "alloc")]
+    pub fn decode_all<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+        let mut input = bytes;
+        let value = crate::deserialize_from_slice_with(&mut input, *self)?;
+        if input.is_empty() {
+            Ok(value)
+        } else {
+            Err(Error::TrailingBytes { len: input.len() })
+        }
+    }
+
+    /// Decodes a `T` from `input` with these options, like `Wrap::<T>::decode` does
+    #[cfg(feature = "alloc")]
+    pub fn decode_from<T, I>(&self, input: &mut I) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+        I: Input + ?Sized,
+    {
+        crate::logging::decode_logged(input, |input| {
+            crate::deserialize_from_unlogged_with(input, *self)
+        })
+    }
+}
+
+#[cfg(feature = "decode")]
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            #[cfg(feature = "alloc")]
+            lossy_strings: false,
+            #[cfg(feature = "alloc")]
+            unknown_variant_fallback: false,
+            max_preallocation: Self::DEFAULT_MAX_PREALLOCATION,
+            #[cfg(feature = "std")]
+            on_progress: None,
+            map_keys: None,
+            legacy_map_layout: false,
+        }
+    }
+}
+
+/// Tracker of the number of bytes read, calling the progress function of the options
+#[cfg(all(feature = "std", feature = "decode"))]
+pub(crate) struct Progress {
+    on_progress: Option<(u64, fn(u64))>,
+    next: u64,
+    reported: Option<u64>,
+}
+
+#[cfg(all(feature = "std", feature = "decode"))]
+impl Progress {
+    pub(crate) fn new(options: &DecodeOptions) -> Self {
+        let next = options.on_progress.map_or(0, |(interval, _)| interval);
+        Progress { on_progress: options.on_progress, next, reported: None }
+    }
+
+    /// Reports `count` bytes read if at least the interval elapsed since the last report
+    #[inline]
+    pub(crate) fn update(&mut self, count: usize) {
+        if let Some((interval, on_progress)) = self.on_progress {
+            let count = count as u64;
+            if count >= self.next {
+                on_progress(count);
+                self.next = count.saturating_add(interval);
+                self.reported = Some(count);
+            }
+        }
+    }
+
+    /// Reports the `count` bytes of the decoded encoding, unless they were just reported
+    pub(crate) fn finish(self, count: usize) {
+        if let Some((_, on_progress)) = self.on_progress {
+            if self.reported != Some(count as u64) {
+                on_progress(count as u64);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "alloc", feature = "encode", feature = "decode"))]
+mod tests {
+    use super::{DecodeOptions, EncodeOptions};
+    use crate::{decode_all, encode, try_encode, Error, LimitedOutput, Wrap, WrapDeserializer};
+    use alloc::{boxed::Box, collections::BTreeMap, string::String, vec, vec::Vec};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Record {
+        id: u8,
+        name: String,
+        aliases: Vec<Option<String>>,
+        payload: Vec<u8>,
+        tags: BTreeMap<String, u32>,
+        tail: u32,
+    }
+
+    const LOSSY: &str = "a\u{fffd}b";
+
+    fn valid() -> Record {
+        let tags = vec![(String::from("k"), 9)].into_iter().collect();
+        Record {
+            id: 1,
+            name: "axb".into(),
+            aliases: vec![None, Some("axb".into())],
+            payload: vec![b'x', 0xff],
+            tags,
+            tail: 0xdead_beef,
+        }
+    }
+
+    /// Returns the encoding of `valid()` with every `x` in strings replaced with an invalid byte
+    fn corrupted() -> Vec<u8> {
+        let mut encoded = encode(&valid());
+        let payload = encoded.iter().position(|&b| b == 0xff).unwrap() - 1;
+        for (i, byte) in encoded.iter_mut().enumerate() {
+            if *byte == b'x' && i != payload {
+                *byte = 0xff;
+            }
+        }
+        encoded
+    }
+
+    fn lossy() -> DecodeOptions {
+        DecodeOptions::new().lossy_strings(true)
+    }
+
+    #[test]
+    fn invalid_strings_are_replaced_when_lossy() {
+        let decoded = lossy().decode_all::<Record>(&corrupted()).unwrap();
+        assert_eq!(decoded.name, LOSSY);
+        assert_eq!(decoded.aliases, [None, Some(LOSSY.into())]);
+        assert_eq!(decoded.payload, [b'x', 0xff]);
+        assert_eq!(decoded.tags, valid().tags);
+        assert_eq!(decoded.tail, 0xdead_beef);
+        let decoded = lossy().decode_from::<Record, _>(&mut &corrupted()[..]).unwrap();
+        assert_eq!((decoded.name.as_str(), decoded.tail), (LOSSY, 0xdead_beef));
+    }
+
+    #[test]
+    fn invalid_strings_are_rejected_by_default() {
+        let result = decode_all::<Record>(&corrupted());
+        assert!(matches!(result, Err(Error::Deserialization(_))));
+        let result = DecodeOptions::new().decode_from::<Record, _>(&mut &corrupted()[..]);
+        assert!(matches!(result, Err(Error::Deserialization(_))));
+    }
+
+    #[test]
+    fn valid_strings_decode_the_same_either_way() {
+        let encoded = encode(&valid());
+        assert_eq!(lossy().decode_all::<Record>(&encoded).unwrap(), valid());
+        assert_eq!(DecodeOptions::new().decode_all::<Record>(&encoded).unwrap(), valid());
+        let borrowed = lossy().decode::<(u8, String)>(b"\x07\x0cfoo").unwrap();
+        assert_eq!(borrowed, (7, "foo".into()));
+    }
+
+    #[test]
+    fn options_apply_to_every_value_of_a_deserializer() {
+        let mut encoded = corrupted();
+        encoded.extend_from_slice(&corrupted());
+        let mut input = &encoded[..];
+        let mut deserializer = WrapDeserializer::with_options(&mut input, lossy());
+        for _ in 0..2 {
+            assert_eq!(deserializer.deserialize::<Record>().unwrap().name, LOSSY);
+        }
+        assert!(deserializer.is_empty());
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    enum List {
+        Nil,
+        Cons(u8, Box<List>),
+    }
+
+    fn list(len: u8) -> List {
+        (0..len).fold(List::Nil, |tail, i| List::Cons(i, Box::new(tail)))
+    }
+
+    fn long_list(len: usize) -> List {
+        (0..len).fold(List::Nil, |tail, _| List::Cons(0, Box::new(tail)))
+    }
+
+    #[test]
+    fn shallow_values_encode() {
+        let encoded = try_encode(&list(10)).unwrap();
+        assert_eq!(encoded, EncodeOptions::new().try_encode(&list(10)).unwrap());
+        assert_eq!(decode_all::<List>(&encoded).unwrap(), list(10));
+    }
+
+    #[test]
+    fn deep_values_fail_to_encode() {
+        let result = try_encode(&long_list(1000));
+        assert!(matches!(result, Err(Error::DepthExceeded { max: 128 })));
+        let result = EncodeOptions::new().max_depth(999).try_encode(&long_list(1000));
+        assert!(matches!(result, Err(Error::DepthExceeded { max: 999 })));
+        let mut encoded = Vec::new();
+        let result = Wrap(long_list(1000)).try_encode_to(&mut LimitedOutput::new(&mut encoded, 64));
+        assert!(matches!(result, Err(Error::EncodingTooLarge { .. })));
+    }
+
+    #[test]
+    fn depth_counts_nested_values() {
+        let value = Some(vec![(1u8, 2u8)]);
+        assert_eq!(EncodeOptions::new().max_depth(3).try_encode(&value).unwrap(), encode(&value));
+        let result = EncodeOptions::new().max_depth(2).try_encode(&value);
+        assert!(matches!(result, Err(Error::DepthExceeded { max: 2 })));
+        assert_eq!(EncodeOptions::new().max_depth(0).try_encode(&7u8).unwrap(), [7]);
+        assert!(EncodeOptions::new().max_depth(10).try_encode(&list(10)).is_ok());
+        assert!(EncodeOptions::new().max_depth(9).try_encode(&list(10)).is_err());
+    }
+
+    #[cfg(not(feature = "deny-panicking-encode"))]
+    #[test]
+    #[should_panic(expected = "Value nests deeper than the maximum of 128 level(s)")]
+    fn deep_values_panic_through_encode() {
+        use parity_scale_codec::Encode;
+
+        let _ = Wrap(long_list(1000)).encode();
+    }
+
+    /// Returns the encoding of a `Vec<u64>` claiming `2^29` elements and holding 2 of them
+    fn forged_sequence() -> Vec<u8> {
+        [&b"\x02\x00\x00\x80"[..], &[7; 16]].concat()
+    }
+
+    #[test]
+    fn capped_preallocation_decodes_the_same() {
+        let values = (0..100u64).collect::<Vec<_>>();
+        let tags = valid().tags;
+        let encoded = encode(&(&values, &tags));
+        for max in [0, 1, 99, 100, 101, DecodeOptions::DEFAULT_MAX_PREALLOCATION] {
+            let options = DecodeOptions::new().max_preallocation(max);
+            let decoded = options.decode_all::<(Vec<u64>, BTreeMap<String, u32>)>(&encoded);
+            assert_eq!(decoded.unwrap(), (values.clone(), tags.clone()));
+        }
+        let options = DecodeOptions::new().max_preallocation(0);
+        let result = options.decode::<Vec<u64>>(&forged_sequence());
+        assert!(matches!(result, Err(Error::Input(_))));
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    enum Event {
+        Renamed { from: String, to: String },
+        Tagged(BTreeMap<String, u32>),
+    }
+
+    fn nested_maps() -> BTreeMap<u8, BTreeMap<String, u32>> {
+        let inner = valid().tags;
+        vec![(1, inner.clone()), (2, BTreeMap::new())].into_iter().collect()
+    }
+
+    fn legacy() -> DecodeOptions {
+        DecodeOptions::new().legacy_map_layout(true)
+    }
+
+    #[test]
+    fn maps_in_the_legacy_layout_decode_with_the_option() {
+        // `{1: 2, 3: 4}` as written by 0.3.0
+        let written = b"\x08\x08\x01\x02\x08\x03\x04";
+        let map = legacy().decode_all::<BTreeMap<u8, u8>>(written).unwrap();
+        assert_eq!(map, vec![(1, 2), (3, 4)].into_iter().collect());
+        assert_eq!(encode(&map), b"\x08\x01\x02\x03\x04");
+        // `serde_scale` itself still writes the legacy layout.
+        let written = crate::scale::to_vec(&nested_maps()).unwrap();
+        assert_ne!(written, encode(&nested_maps()));
+        let decoded = legacy().decode_all::<BTreeMap<u8, BTreeMap<String, u32>>>(&written);
+        assert_eq!(decoded.unwrap(), nested_maps());
+        let event = Event::Tagged(valid().tags);
+        let written = crate::scale::to_vec(&event).unwrap();
+        assert_eq!(legacy().decode_all::<Event>(&written).unwrap(), event);
+    }
+
+    #[test]
+    fn maps_in_the_legacy_layout_are_misread_by_default() {
+        let written = crate::scale::to_vec(&nested_maps()).unwrap();
+        let decoded = decode_all::<BTreeMap<u8, BTreeMap<String, u32>>>(&written);
+        assert_ne!(decoded.ok(), Some(nested_maps()));
+    }
+
+    #[test]
+    fn unprefixed_keys_are_rejected_with_the_option() {
+        let result = legacy().decode_all::<BTreeMap<u8, u8>>(b"\x04\x01\x02");
+        assert!(matches!(result, Err(Error::Deserialization(_))));
+        assert_eq!(legacy().decode_all::<BTreeMap<u8, u8>>(b"\x00").unwrap(), BTreeMap::new());
+    }
+
+    #[test]
+    fn struct_variants_written_by_serde_scale_decode() {
+        let event = Event::Renamed { from: "a".into(), to: "b".into() };
+        let written = crate::scale::to_vec(&event).unwrap();
+        assert_eq!(written, encode(&event));
+        assert_eq!(decode_all::<Event>(&written).unwrap(), event);
+        assert_eq!(legacy().decode_all::<Event>(&written).unwrap(), event);
+    }
+}
@@ -0,0 +1,14 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Traits to encode and decode values without mentioning [`Wrap`](crate::Wrap)
+//!
+//! ```rust
+//! use serde_scale_wrap::prelude::*;
+//! ```
+
+#[cfg(feature = "decode")]
+pub use crate::ext::ScaleViaSerdeDecodeExt;
+#[cfg(feature = "encode")]
+pub use crate::ext::ScaleViaSerdeExt;
+#[cfg(all(feature = "encode", feature = "decode"))]
+pub use crate::ScaleSerde;
@@ -0,0 +1,241 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Pre-encoded bytes embedded verbatim in encodings
+
+use alloc::{borrow::Cow, vec::Vec};
+#[cfg(feature = "decode")]
+use core::fmt;
+#[cfg(feature = "decode")]
+use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+#[cfg(feature = "encode")]
+use serde::ser::{Serialize, SerializeTuple, Serializer};
+
+/// Name of the newtype struct `RawScale` deserializes, recognized by the deserializer of this
+/// crate
+#[cfg(feature = "decode")]
+pub(crate) const NAME: &str = "$serde_scale_wrap::RawScale";
+
+/// Error message when decoding a `RawScale` whose length is unknown
+#[cfg(feature = "decode")]
+pub(crate) const OUTSIDE_PAYLOAD: &str =
+    "`RawScale` can only be decoded as the whole payload of a `LengthPrefixed`";
+
+/// Bytes that already are a complete SCALE encoding, written verbatim when encoding
+///
+/// This lets values encoded otherwise (e.g. signatures or calls encoded with `Encode`) be
+/// embedded in values encoded with `serde` without being copied into an intermediate encoding.
+/// The bytes are written as they are, without any length prefix, so the caller must make sure
+/// that they are the encoding of the value expected at their position.
+///
+/// ```rust
+/// use parity_scale_codec::Encode;
+/// use serde::Serialize;
+/// use serde_scale_wrap::RawScale;
+///
+/// #[derive(Serialize)]
+/// struct Extrinsic {
+///     call: RawScale,
+///     nonce: u32,
+/// }
+///
+/// let call = (7u8, vec![300u16]).encode();
+/// let extrinsic = Extrinsic { call: RawScale(call.clone().into()), nonce: 1 };
+/// assert_eq!(serde_scale_wrap::encode(&extrinsic), [call, 1u32.encode()].concat());
+/// ```
+///
+/// # Decoding
+/// SCALE encodings do not record their length, so the bytes of a `RawScale` cannot be told apart
+/// from the bytes following them. Decoding a `RawScale` is thus only supported as the whole
+/// payload of a [`LengthPrefixed`](crate::LengthPrefixed), which records the length, and fails
+/// with [`Error::Deserialization`](crate::Error::Deserialization) anywhere else:
+///
+/// ```rust
+/// use serde_scale_wrap::{Error, LengthPrefixed, RawScale};
+///
+/// let raw = RawScale::from(vec![1, 2, 3]);
+/// let encoded = serde_scale_wrap::encode(&raw);
+/// assert_eq!(encoded, [1, 2, 3]);
+/// let result = serde_scale_wrap::decode_all::<RawScale>(&encoded);
+/// assert!(matches!(result, Err(Error::Deserialization(_))));
+///
+/// let encoded = serde_scale_wrap::encode(&LengthPrefixed(raw.clone()));
+/// assert_eq!(encoded, [12, 1, 2, 3]);
+/// let decoded = serde_scale_wrap::decode_all::<LengthPrefixed<RawScale>>(&encoded)?;
+/// assert_eq!(decoded.into_inner(), raw);
+/// # Ok::<_, Error>(())
+/// ```
+///
+/// Other formats serialize the bytes as a tuple of bytes, and deserialize them from bytes or a
+/// sequence of bytes.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct RawScale(pub Cow<'static, [u8]>);
+
+impl RawScale {
+    /// Returns the encoded bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Returns the encoded bytes, copying them if they are borrowed
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0.into_owned()
+    }
+}
+
+impl From<Vec<u8>> for RawScale {
+    fn from(bytes: Vec<u8>) -> Self {
+        RawScale(Cow::Owned(bytes))
+    }
+}
+
+impl From<&'static [u8]> for RawScale {
+    fn from(bytes: &'static [u8]) -> Self {
+        RawScale(Cow::Borrowed(bytes))
+    }
+}
+
+impl AsRef<[u8]> for RawScale {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[cfg(feature = "encode")]
+impl Serialize for RawScale {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Tuples are encoded as their elements, without any length prefix.
+        let mut tuple = serializer.serialize_tuple(self.0.len())?;
+        for byte in self.0.iter() {
+            tuple.serialize_element(byte)?;
+        }
+        tuple.end()
+    }
+}
+
+#[cfg(feature = "decode")]
+impl<'de> Deserialize<'de> for RawScale {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_newtype_struct(NAME, RawVisitor)
+    }
+}
+
+/// Visitor of the bytes of a `RawScale`
+///
+/// The deserializer of this crate visits a sequence holding every byte of the payload. Other
+/// deserializers visit a newtype struct holding bytes or a sequence of bytes.
+#[cfg(feature = "decode")]
+struct RawVisitor;
+
+#[cfg(feature = "decode")]
+impl<'de> Visitor<'de> for RawVisitor {
+    type Value = RawScale;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the bytes of a SCALE encoding")
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(self, d: D) -> Result<RawScale, D::Error> {
+        d.deserialize_byte_buf(self)
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<RawScale, E> {
+        Ok(RawScale::from(bytes.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<RawScale, E> {
+        Ok(RawScale::from(bytes))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<RawScale, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(RawScale::from(bytes))
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::RawScale;
+    use crate::{decode_all, encode, Error, LengthPrefixed, Wrap};
+    use alloc::{borrow::Cow, string::{String, ToString}, vec, vec::Vec};
+    use parity_scale_codec::{Decode, Encode};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Signed {
+        signer: String,
+        signature: RawScale,
+        nonce: u32,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Envelope {
+        id: u8,
+        call: LengthPrefixed<RawScale>,
+        tip: u64,
+    }
+
+    /// Stand-in for a call encoded with `Encode`
+    fn call() -> Vec<u8> {
+        (3u8, vec![Some(500u32), None], true).encode()
+    }
+
+    #[test]
+    fn raw_bytes_are_written_verbatim() {
+        let signed = Signed {
+            signer: "alice".into(),
+            signature: RawScale(Cow::Borrowed(&[0xaa; 64])),
+            nonce: 9,
+        };
+        let expected = [&String::from("alice").encode()[..], &[0xaa; 64], &9u32.encode()].concat();
+        assert_eq!(encode(&signed), expected);
+        assert_eq!(encode(&RawScale::default()), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn raw_bytes_encode_like_the_value_they_hold() {
+        let value = (3u8, vec![Some(500u32), None], true);
+        assert_eq!(encode(&RawScale::from(call())), encode(&value));
+        assert_eq!(encode(&LengthPrefixed(RawScale::from(call()))), call().encode());
+    }
+
+    #[test]
+    fn length_prefixed_raw_bytes_roundtrip() {
+        let envelope = Envelope { id: 1, call: LengthPrefixed(call().into()), tip: 10 };
+        let encoded = encode(&envelope);
+        assert_eq!(encoded, [&[1][..], &call().encode(), &10u64.encode()].concat());
+        let Wrap(decoded) = Wrap::<Envelope>::decode(&mut &*encoded).unwrap();
+        assert_eq!(decoded, envelope);
+        assert_eq!(decoded.call.into_inner().into_bytes(), call());
+    }
+
+    #[test]
+    fn raw_bytes_without_a_length_prefix_are_not_decoded() {
+        let signed = Signed { signer: "bob".into(), signature: call().into(), nonce: 1 };
+        let result = decode_all::<Signed>(&encode(&signed));
+        match result {
+            Err(e @ Error::Deserialization(_)) => {
+                assert!(e.to_string().contains("whole payload of a `LengthPrefixed`"));
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert!(decode_all::<RawScale>(&call()).is_err());
+    }
+
+    #[test]
+    fn raw_bytes_nested_in_a_length_prefixed_value_are_not_decoded() {
+        let encoded = encode(&LengthPrefixed((7u8, RawScale::from(call()))));
+        let result = decode_all::<LengthPrefixed<(u8, RawScale)>>(&encoded);
+        assert!(matches!(result, Err(Error::Deserialization(_))));
+    }
+
+    #[test]
+    fn other_formats_roundtrip_raw_bytes() {
+        let raw = RawScale::from(vec![1, 2, 3]);
+        let json = serde_json::to_string(&raw).unwrap();
+        assert_eq!(json, "[1,2,3]");
+        assert_eq!(serde_json::from_str::<RawScale>(&json).unwrap(), raw);
+    }
+}
@@ -0,0 +1,319 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! `Encode` and `Decode` through `serde` remote definitions
+//!
+//! Types implementing neither `serde` nor `parity-scale-codec` traits can still be serialized with
+//! a [remote definition](https://serde.rs/remote-derive.html), a mirror type deriving
+//! `Serialize`/`Deserialize` with `#[serde(remote = "...")]`.
+//! [`impl_scale_for_remote!`](crate::impl_scale_for_remote) funnels `Encode`, `EncodeLike` and
+//! `Decode` through the `serialize` and `deserialize` functions of the mirror, encoding like `Wrap`
+//! does:
+//!
+//! ```rust
+//! use parity_scale_codec::{Decode, Encode};
+//! use serde::{Deserialize, Serialize};
+//!
+//! pub struct Point {
+//!     pub x: i32,
+//!     pub y: i32,
+//! }
+//!
+//! #[derive(Deserialize, Serialize)]
+//! #[serde(remote = "Point")]
+//! struct PointDef {
+//!     x: i32,
+//!     y: i32,
+//! }
+//!
+//! serde_scale_wrap::impl_scale_for_remote!(Point as PointDef);
+//!
+//! let encoded = Point { x: 1, y: -1 }.encode();
+//! assert_eq!(encoded, serde_scale_wrap::encode(&(1i32, -1i32)));
+//! let point = Point::decode(&mut &*encoded)?;
+//! assert_eq!((point.x, point.y), (1, -1));
+//! # Ok::<_, parity_scale_codec::Error>(())
+//! ```
+//!
+//! Generic types list their parameters after `impl` and their bounds in a `where` clause, which
+//! must cover what the mirror requires to serialize and deserialize:
+//!
+//! ```rust
+//! # use serde::{de::DeserializeOwned, Deserialize, Serialize};
+//! pub struct Pair<T>(pub T, pub T);
+//!
+//! #[derive(Deserialize, Serialize)]
+//! #[serde(remote = "Pair")]
+//! struct PairDef<T>(T, T);
+//!
+//! serde_scale_wrap::impl_scale_for_remote!(
+//!     impl<T> Pair<T> as PairDef<T> where T: Serialize + DeserializeOwned
+//! );
+//! ```
+//!
+//! # Orphan rule
+//! Implementing `Encode` for a type requires the type to be defined in the crate invoking the
+//! macro, e.g. a type generated by another tool into the crate. A type defined in another crate
+//! cannot get these implementations, and the macro then declares a newtype wrapping it instead,
+//! converting from and into it:
+//!
+//! ```rust
+//! use parity_scale_codec::{Decode, Encode};
+//! use serde::{Deserialize, Serialize};
+//! use std::net::Shutdown;
+//!
+//! #[derive(Deserialize, Serialize)]
+//! #[serde(remote = "Shutdown")]
+//! enum ShutdownDef {
+//!     Read,
+//!     Write,
+//!     Both,
+//! }
+//!
+//! serde_scale_wrap::impl_scale_for_remote!(
+//!     #[derive(Debug, PartialEq)]
+//!     pub struct ScaleShutdown(pub Shutdown) as ShutdownDef
+//! );
+//!
+//! assert_eq!(ScaleShutdown(Shutdown::Write).encode(), [1]);
+//! assert_eq!(ScaleShutdown::decode(&mut &[2][..])?, ScaleShutdown(Shutdown::Both));
+//! assert_eq!(Shutdown::from(ScaleShutdown(Shutdown::Read)), Shutdown::Read);
+//! # Ok::<_, parity_scale_codec::Error>(())
+//! ```
+
+#[doc(hidden)]
+pub mod __private {
+    use crate::{deserialize_from, serialize_to};
+    use core::marker::PhantomData;
+    use serde::{Deserialize, Serialize};
+
+    pub use parity_scale_codec::{Decode, Encode, EncodeLike, Error, Input, Output};
+    pub use serde::{Deserializer, Serializer};
+
+    /// Remote definition of `T`, implemented for mirrors by `impl_scale_for_remote!`
+    pub trait Remote<T> {
+        fn serialize<S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error>;
+        fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error>;
+    }
+
+    struct SerializeVia<'a, T, M>(&'a T, PhantomData<M>);
+
+    impl<T, M: Remote<T>> Serialize for SerializeVia<'_, T, M> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            M::serialize(self.0, serializer)
+        }
+    }
+
+    struct DeserializeVia<T, M>(T, PhantomData<M>);
+
+    impl<'de, T, M: Remote<T>> Deserialize<'de> for DeserializeVia<T, M> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            M::deserialize(deserializer).map(|value| DeserializeVia(value, PhantomData))
+        }
+    }
+
+    pub fn encode_to<T, M: Remote<T>, O: Output + ?Sized>(value: &T, dst: &mut O) {
+        serialize_to(&SerializeVia::<T, M>(value, PhantomData), dst)
+    }
+
+    pub fn decode<T, M: Remote<T>, I: Input + ?Sized>(input: &mut I) -> Result<T, Error> {
+        Ok(deserialize_from::<DeserializeVia<T, M>, _>(input)?.0)
+    }
+}
+
+/// Implements `Encode`, `EncodeLike` and `Decode` for a type through its `serde` remote definition
+///
+/// `Type as Mirror` implements the traits for `Type`, with an optional `impl<T, ...>` prefix and
+/// `where` clause for generic types. `struct Newtype(Type) as Mirror`, optionally preceded by
+/// attributes and a visibility, declares a newtype wrapping `Type`, convertible from and into it,
+/// and implements the traits for the newtype. Encoding panics if the mirror fails to serialize,
+/// like `Wrap` does. See the [`remote`](crate::remote) module.
+#[macro_export]
+macro_rules! impl_scale_for_remote {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident $(<$($p:ident),* $(,)?>)? ($field_vis:vis $t:ty) as $m:ty
+        $(where $($w:tt)+)?
+    ) => {
+        $(#[$attr])*
+        $vis struct $name $(<$($p),*>)? ($field_vis $t) $(where $($w)+)?;
+
+        impl<$($($p),*)?> ::core::convert::From<$t> for $name $(<$($p),*>)?
+        $(where $($w)+)?
+        {
+            fn from(value: $t) -> Self {
+                $name(value)
+            }
+        }
+
+        impl<$($($p),*)?> ::core::convert::From<$name $(<$($p),*>)?> for $t
+        $(where $($w)+)?
+        {
+            fn from(value: $name $(<$($p),*>)?) -> Self {
+                value.0
+            }
+        }
+
+        $crate::impl_scale_for_remote!(@remote [$($($p),*)?] $t, $m, [$($($w)+)?]);
+
+        impl<$($($p),*)?> $crate::remote::__private::Encode for $name $(<$($p),*>)?
+        $(where $($w)+)?
+        {
+            fn encode_to<O: $crate::remote::__private::Output + ?Sized>(&self, dst: &mut O) {
+                $crate::remote::__private::encode_to::<$t, $m, O>(&self.0, dst)
+            }
+        }
+
+        impl<$($($p),*)?> $crate::remote::__private::EncodeLike for $name $(<$($p),*>)?
+        $(where $($w)+)?
+        {}
+
+        impl<$($($p),*)?> $crate::remote::__private::Decode for $name $(<$($p),*>)?
+        $(where $($w)+)?
+        {
+            fn decode<I: $crate::remote::__private::Input>(
+                input: &mut I,
+            ) -> ::core::result::Result<Self, $crate::remote::__private::Error> {
+                $crate::remote::__private::decode::<$t, $m, I>(input).map($name)
+            }
+        }
+    };
+    (impl<$($p:ident),* $(,)?> $t:ty as $m:ty $(where $($w:tt)+)?) => {
+        $crate::impl_scale_for_remote!(@impl [$($p),*] $t, $m, [$($($w)+)?]);
+    };
+    ($t:ty as $m:ty) => {
+        $crate::impl_scale_for_remote!(@impl [] $t, $m, []);
+    };
+    (@impl [$($p:ident),*] $t:ty, $m:ty, [$($w:tt)*]) => {
+        $crate::impl_scale_for_remote!(@remote [$($p),*] $t, $m, [$($w)*]);
+
+        impl<$($p),*> $crate::remote::__private::Encode for $t where $($w)* {
+            fn encode_to<O: $crate::remote::__private::Output + ?Sized>(&self, dst: &mut O) {
+                $crate::remote::__private::encode_to::<$t, $m, O>(self, dst)
+            }
+        }
+
+        impl<$($p),*> $crate::remote::__private::EncodeLike for $t where $($w)* {}
+
+        impl<$($p),*> $crate::remote::__private::Decode for $t where $($w)* {
+            fn decode<I: $crate::remote::__private::Input>(
+                input: &mut I,
+            ) -> ::core::result::Result<Self, $crate::remote::__private::Error> {
+                $crate::remote::__private::decode::<$t, $m, I>(input)
+            }
+        }
+    };
+    (@remote [$($p:ident),*] $t:ty, $m:ty, [$($w:tt)*]) => {
+        impl<$($p),*> $crate::remote::__private::Remote<$t> for $m where $($w)* {
+            fn serialize<S: $crate::remote::__private::Serializer>(
+                value: &$t,
+                serializer: S,
+            ) -> ::core::result::Result<S::Ok, S::Error> {
+                <$m>::serialize(value, serializer)
+            }
+
+            fn deserialize<'de, D: $crate::remote::__private::Deserializer<'de>>(
+                deserializer: D,
+            ) -> ::core::result::Result<$t, D::Error> {
+                <$m>::deserialize(deserializer)
+            }
+        }
+    };
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use crate::{decode_all, encode};
+    use alloc::{string::String, vec, vec::Vec};
+    use parity_scale_codec::{Decode, Encode};
+    use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+    /// Types without any `serde` or `parity-scale-codec` implementation
+    mod foreign {
+        use alloc::{string::String, vec::Vec};
+
+        #[derive(Debug, PartialEq)]
+        pub struct Sensor {
+            pub id: u16,
+            pub label: String,
+        }
+
+        #[derive(Debug, PartialEq)]
+        pub enum Reading<T> {
+            Missing,
+            Values(Vec<T>),
+        }
+
+        #[derive(Debug, PartialEq)]
+        pub struct Celsius(pub i16);
+    }
+
+    use foreign::{Celsius, Reading, Sensor};
+
+    #[derive(Deserialize, Serialize)]
+    #[serde(remote = "Sensor")]
+    struct SensorDef {
+        id: u16,
+        label: String,
+    }
+
+    #[derive(Deserialize, Serialize)]
+    #[serde(remote = "Reading")]
+    enum ReadingDef<T> {
+        Missing,
+        Values(Vec<T>),
+    }
+
+    #[derive(Deserialize, Serialize)]
+    #[serde(remote = "Celsius")]
+    struct CelsiusDef(i16);
+
+    crate::impl_scale_for_remote!(Sensor as SensorDef);
+    crate::impl_scale_for_remote!(
+        impl<T> Reading<T> as ReadingDef<T> where T: Serialize + DeserializeOwned
+    );
+    crate::impl_scale_for_remote!(
+        #[derive(Debug, PartialEq)]
+        struct ScaleCelsius(Celsius) as CelsiusDef
+    );
+
+    #[test]
+    fn remote_types_encode_like_their_mirrors() {
+        let sensor = Sensor { id: 3, label: "hall".into() };
+        assert_eq!(sensor.encode(), encode(&(3u16, "hall")));
+        assert_eq!(Sensor::decode(&mut &*sensor.encode()).unwrap(), sensor);
+    }
+
+    #[test]
+    fn generic_remote_types_roundtrip() {
+        let reading = Reading::Values(vec![1u32, 2]);
+        assert_eq!(reading.encode(), [1, 8, 1, 0, 0, 0, 2, 0, 0, 0]);
+        assert_eq!(Reading::<u32>::decode(&mut &*reading.encode()).unwrap(), reading);
+        assert_eq!(Reading::<String>::Missing.encode(), [0]);
+        assert_eq!(Reading::<String>::decode(&mut &[0][..]).unwrap(), Reading::Missing);
+        assert!(Reading::<u8>::decode(&mut &[2][..]).is_err());
+    }
+
+    #[test]
+    fn remote_types_nest_in_derived_encodings() {
+        #[derive(Debug, Decode, Encode, PartialEq)]
+        struct Log {
+            sensor: Sensor,
+            readings: Vec<Reading<i8>>,
+        }
+
+        let log = Log {
+            sensor: Sensor { id: 1, label: "roof".into() },
+            readings: vec![Reading::Missing, Reading::Values(vec![-1])],
+        };
+        assert_eq!(Log::decode(&mut &*log.encode()).unwrap(), log);
+    }
+
+    #[test]
+    fn newtypes_wrap_remote_types() {
+        let encoded = ScaleCelsius(Celsius(-5)).encode();
+        assert_eq!(encoded, encode(&-5i16));
+        assert_eq!(ScaleCelsius::decode(&mut &*encoded).unwrap(), ScaleCelsius(Celsius(-5)));
+        assert_eq!(Celsius::from(ScaleCelsius::from(Celsius(2))), Celsius(2));
+        assert!(decode_all::<i16>(&encoded).is_ok());
+    }
+}
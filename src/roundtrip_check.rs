@@ -0,0 +1,357 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Self-check of encodings, catching asymmetric `Serialize`/`Deserialize` implementations
+//!
+//! Attributes like `#[serde(skip_serializing_if = "...")]` make a value serialize to fewer fields
+//! than its `Deserialize` implementation consumes. As SCALE is positional, every following field
+//! is then silently corrupted.
+//!
+//! [`CheckedWrap<T>`] encodes its value like [`Wrap<T>`](crate::Wrap) does. In debug builds, its
+//! [`encode_to`](parity_scale_codec::Encode::encode_to) passes the bytes it produced to
+//! [`RoundtripCheck::check_roundtrip`], which panics if they do not decode back to the original
+//! value. Release builds do not call it. `Wrap` itself never runs the check, so enabling the
+//! `debug-roundtrip-check` feature does not change which types `Wrap` encodes.
+//!
+//! `RoundtripCheck` is implemented for primitive types and standard containers, checking their
+//! encodings. Other types opt into the check with [`impl_roundtrip_check!`](crate::impl_roundtrip_check)
+//! or fall back to the no-op default method with an empty implementation:
+//!
+//! ```rust
+//! use parity_scale_codec::Encode;
+//! use serde::{Deserialize, Serialize};
+//! use serde_scale_wrap::{
+//!     impl_roundtrip_check,
+//!     roundtrip_check::{CheckedWrap, RoundtripCheck},
+//!     Wrap,
+//! };
+//!
+//! #[derive(Deserialize, PartialEq, Serialize)]
+//! struct Checked(u32);
+//!
+//! #[derive(Deserialize, Serialize)]
+//! struct Unchecked(u32);
+//!
+//! impl_roundtrip_check!(Checked);
+//! impl RoundtripCheck for Unchecked {}
+//!
+//! assert_eq!(CheckedWrap(Checked(3)).encode(), Wrap(Checked(3)).encode());
+//! assert_eq!(CheckedWrap(Unchecked(3)).encode(), Wrap(Unchecked(3)).encode());
+//! ```
+
+use crate::Wrap;
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    string::String,
+    vec::Vec,
+};
+use core::any::type_name;
+use parity_scale_codec::{Decode, DecodeAll, Encode, EncodeLike, Input, Output};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Wrapper encoding and decoding its value like `Wrap`, checking in debug builds that encodings
+/// decode back to the original value
+///
+/// A struct skipping `None` fields with `#[serde(skip_serializing_if = "Option::is_none")]` makes
+/// `encode_to` panic, as the following field is then decoded in place of the skipped one.
+///
+/// ```rust
+/// use parity_scale_codec::{Decode, Encode};
+/// use serde::{Deserialize, Serialize};
+/// use serde_scale_wrap::{impl_roundtrip_check, roundtrip_check::CheckedWrap};
+///
+/// #[derive(Debug, Deserialize, PartialEq, Serialize)]
+/// struct Transfer {
+///     note: Option<String>,
+///     amount: u32,
+/// }
+///
+/// impl_roundtrip_check!(Transfer);
+///
+/// let encoded = CheckedWrap(Transfer { note: None, amount: 3 }).encode();
+/// let CheckedWrap(decoded) = CheckedWrap::<Transfer>::decode(&mut &*encoded).unwrap();
+/// assert_eq!(decoded, Transfer { note: None, amount: 3 });
+/// ```
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct CheckedWrap<T>(pub T);
+
+impl<T: Serialize + RoundtripCheck> Encode for CheckedWrap<T> {
+    /// # Panics
+    /// Panics if the serializer returns an error, like `Wrap` does, or, in debug builds, if the
+    /// encoding does not decode back to the original value.
+    fn encode_to<O: Output + ?Sized>(&self, dst: &mut O) {
+        #[cfg(debug_assertions)]
+        {
+            let result = crate::logging::encode_logged::<T, _, _>(dst, |dst| {
+                let encoded = crate::try_to_vec(&self.0)?;
+                self.0.check_roundtrip(&encoded);
+                dst.write(&encoded);
+                Ok(())
+            });
+            if let Err(e) = result {
+                panic!("{}", e);
+            }
+        }
+        #[cfg(not(debug_assertions))]
+        crate::serialize_to(&self.0, dst);
+    }
+}
+
+impl<T: Serialize + RoundtripCheck> EncodeLike for CheckedWrap<T> {}
+
+impl<T: DeserializeOwned> Decode for CheckedWrap<T> {
+    fn decode<I: Input>(input: &mut I) -> Result<Self, parity_scale_codec::Error> {
+        Wrap::<T>::decode(input).map(|Wrap(value)| CheckedWrap(value))
+    }
+}
+
+impl<T> From<Wrap<T>> for CheckedWrap<T> {
+    fn from(Wrap(value): Wrap<T>) -> Self {
+        CheckedWrap(value)
+    }
+}
+
+/// Types whose encodings can be checked to decode back to the original value
+pub trait RoundtripCheck {
+    /// Checks that `encoded`, the encoding of `self`, decodes back to `self`
+    ///
+    /// The default implementation does nothing.
+    ///
+    /// # Panics
+    /// Implementations panic if the check fails.
+    fn check_roundtrip(&self, encoded: &[u8]) {
+        let _ = encoded;
+    }
+}
+
+/// Asserts that `encoded` decodes as a `U` equal to `value`
+///
+/// This is the check performed by the implementations of [`RoundtripCheck`] in this crate.
+///
+/// # Panics
+/// Panics, naming the type of `value`, if `encoded` does not decode as a `U` or decodes to a
+/// different value.
+pub fn assert_roundtrip_as<U, T>(value: &T, encoded: &[u8])
+where
+    U: DeserializeOwned + PartialEq<T>,
+    T: ?Sized,
+{
+    match Wrap::<U>::decode_all(encoded) {
+        Ok(Wrap(ref x)) if *x == *value => {}
+        Ok(_) => panic!(
+            "Roundtrip check failed for `{}`: decoding its encoding yields a different value",
+            type_name::<T>(),
+        ),
+        Err(e) => panic!(
+            "Roundtrip check failed for `{}`: its encoding does not decode: {}",
+            type_name::<T>(),
+            e,
+        ),
+    }
+}
+
+/// Implements [`RoundtripCheck`](crate::roundtrip_check::RoundtripCheck) for types, checking
+/// their encodings
+///
+/// The types must implement `DeserializeOwned` and `PartialEq`.
+///
+/// ```rust
+/// use serde::{Deserialize, Serialize};
+/// use serde_scale_wrap::impl_roundtrip_check;
+///
+/// #[derive(Deserialize, PartialEq, Serialize)]
+/// struct Foo(u32);
+///
+/// #[derive(Deserialize, PartialEq, Serialize)]
+/// struct Bar(Foo);
+///
+/// impl_roundtrip_check!(Foo, Bar);
+/// ```
+#[macro_export]
+macro_rules! impl_roundtrip_check {
+    ($($t:ty),* $(,)?) => {
+        $(
+            impl $crate::roundtrip_check::RoundtripCheck for $t {
+                fn check_roundtrip(&self, encoded: &[u8]) {
+                    $crate::roundtrip_check::assert_roundtrip_as::<$t, $t>(self, encoded)
+                }
+            }
+        )*
+    };
+}
+
+impl_roundtrip_check!(bool, char, u8, u16, u32, u64, i8, i16, i32, i64, (), String);
+
+// Floating point numbers are compared by their bits, so that NaN roundtrips.
+#[cfg(feature = "float")]
+impl RoundtripCheck for f32 {
+    fn check_roundtrip(&self, encoded: &[u8]) {
+        assert_roundtrip_as::<u32, u32>(&self.to_bits(), encoded)
+    }
+}
+
+#[cfg(feature = "float")]
+impl RoundtripCheck for f64 {
+    fn check_roundtrip(&self, encoded: &[u8]) {
+        assert_roundtrip_as::<u64, u64>(&self.to_bits(), encoded)
+    }
+}
+
+impl RoundtripCheck for str {
+    fn check_roundtrip(&self, encoded: &[u8]) {
+        assert_roundtrip_as::<String, str>(self, encoded)
+    }
+}
+
+impl<T: DeserializeOwned + PartialEq> RoundtripCheck for [T] {
+    fn check_roundtrip(&self, encoded: &[u8]) {
+        assert_roundtrip_as::<Vec<T>, [T]>(self, encoded)
+    }
+}
+
+impl<T: RoundtripCheck + ?Sized> RoundtripCheck for &T {
+    fn check_roundtrip(&self, encoded: &[u8]) {
+        (**self).check_roundtrip(encoded)
+    }
+}
+
+impl<T: RoundtripCheck + ?Sized> RoundtripCheck for &mut T {
+    fn check_roundtrip(&self, encoded: &[u8]) {
+        (**self).check_roundtrip(encoded)
+    }
+}
+
+impl<T: RoundtripCheck + ?Sized> RoundtripCheck for Box<T> {
+    fn check_roundtrip(&self, encoded: &[u8]) {
+        (**self).check_roundtrip(encoded)
+    }
+}
+
+macro_rules! impl_roundtrip_check_for_generics {
+    ($(<$($p:ident $(: $bound:ident)?),+> $t:ty,)*) => {
+        $(
+            impl<$($p: DeserializeOwned + PartialEq $(+ $bound)?),+> RoundtripCheck for $t {
+                fn check_roundtrip(&self, encoded: &[u8]) {
+                    assert_roundtrip_as::<Self, Self>(self, encoded)
+                }
+            }
+        )*
+    };
+}
+
+impl_roundtrip_check_for_generics! {
+    <T> Option<T>,
+    <T> Vec<T>,
+    <T> VecDeque<T>,
+    <T: Ord> BTreeSet<T>,
+    <K: Ord, V> BTreeMap<K, V>,
+    <A> (A,),
+    <A, B> (A, B),
+    <A, B, C> (A, B, C),
+    <A, B, C, D> (A, B, C, D),
+    <A, B, C, D, E> (A, B, C, D, E),
+    <A, B, C, D, E, F> (A, B, C, D, E, F),
+}
+
+#[cfg(feature = "arrayvec")]
+impl<T: DeserializeOwned + PartialEq, const N: usize> RoundtripCheck for arrayvec::ArrayVec<T, N> {
+    fn check_roundtrip(&self, encoded: &[u8]) {
+        assert_roundtrip_as::<Self, Self>(self, encoded)
+    }
+}
+
+#[cfg(feature = "indexmap")]
+impl<K, V, S> RoundtripCheck for indexmap::IndexMap<K, V, S>
+where
+    K: DeserializeOwned + Eq + core::hash::Hash,
+    V: DeserializeOwned + PartialEq,
+    S: core::hash::BuildHasher + Default,
+{
+    fn check_roundtrip(&self, encoded: &[u8]) {
+        assert_roundtrip_as::<Self, Self>(self, encoded)
+    }
+}
+
+#[cfg(feature = "indexmap")]
+impl<T, S> RoundtripCheck for indexmap::IndexSet<T, S>
+where
+    T: DeserializeOwned + Eq + core::hash::Hash,
+    S: core::hash::BuildHasher + Default,
+{
+    fn check_roundtrip(&self, encoded: &[u8]) {
+        assert_roundtrip_as::<Self, Self>(self, encoded)
+    }
+}
+
+#[cfg(feature = "smallvec")]
+impl<A> RoundtripCheck for smallvec::SmallVec<A>
+where
+    A: smallvec::Array,
+    A::Item: DeserializeOwned + PartialEq,
+{
+    fn check_roundtrip(&self, encoded: &[u8]) {
+        assert_roundtrip_as::<Self, Self>(self, encoded)
+    }
+}
+
+macro_rules! impl_roundtrip_check_for_arrays {
+    ($($n:literal)*) => {
+        $(
+            impl<T: DeserializeOwned + PartialEq> RoundtripCheck for [T; $n] {
+                fn check_roundtrip(&self, encoded: &[u8]) {
+                    assert_roundtrip_as::<Self, Self>(self, encoded)
+                }
+            }
+        )*
+    };
+}
+
+impl_roundtrip_check_for_arrays! {
+    0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31 32
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use super::CheckedWrap;
+    use crate::Wrap;
+    use alloc::{string::String, vec};
+    use parity_scale_codec::{Decode, Encode};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Asymmetric {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        note: Option<String>,
+        count: u32,
+    }
+
+    crate::impl_roundtrip_check!(Asymmetric);
+
+    #[test]
+    #[should_panic(expected = "Roundtrip check failed for `serde_scale_wrap::roundtrip_check::tests::Asymmetric`")]
+    fn skipped_field_is_caught() {
+        CheckedWrap(Asymmetric { note: None, count: 3 }).encode();
+    }
+
+    #[test]
+    fn wrap_does_not_check() {
+        let value = Asymmetric { note: None, count: 3 };
+        assert_eq!(Wrap(&value).encode(), crate::scale::to_vec(&value).unwrap());
+    }
+
+    #[test]
+    fn symmetric_value_passes() {
+        let value = Asymmetric { note: Some("a".into()), count: 3 };
+        let encoded = CheckedWrap(&value).encode();
+        assert_eq!(encoded, crate::scale::to_vec(&value).unwrap());
+        assert_eq!(CheckedWrap::<Asymmetric>::decode(&mut &*encoded).unwrap().0, value);
+    }
+
+    #[test]
+    fn containers_are_checked() {
+        CheckedWrap(vec![Some(1u8), None]).encode();
+        CheckedWrap((String::from("a"), [1u16, 2])).encode();
+        CheckedWrap(&*vec![true]).encode();
+        CheckedWrap("a").encode();
+    }
+}
@@ -0,0 +1,50 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Surface of `serde_scale` this crate uses, from the version selected by the features
+//!
+//! `serde_scale` 0.2 is used with the `serde-scale-0-2` feature, enabled by default, and 0.1 with
+//! the `serde-scale-0-1` feature. Only the selected version is a dependency. The rest of the crate
+//! only refers to `serde_scale` through this module, so that supporting another version only takes
+//! changes here.
+//!
+//! The versions differ in the bounds of the errors of `Read` and `Write` (`Debug + Display` in
+//! 0.2, `std::error::Error + 'static` in 0.1 with `std`), which the errors of this crate meet in
+//! both, and in the message held by `Error::Other`, which is read with `other_message`.
+
+#[cfg(all(feature = "serde-scale-0-1", feature = "serde-scale-0-2"))]
+compile_error!(
+    "The `serde-scale-0-1` and `serde-scale-0-2` features select the version of `serde_scale` to \
+    use and cannot be enabled together"
+);
+
+#[cfg(not(any(feature = "serde-scale-0-1", feature = "serde-scale-0-2")))]
+compile_error!(
+    "One of the `serde-scale-0-1` and `serde-scale-0-2` features must be enabled to select the \
+    version of `serde_scale` to use"
+);
+
+use crate::serde_scale as selected;
+
+pub(crate) use selected::Error;
+#[cfg(feature = "decode")]
+pub(crate) use selected::{Bytes, Deserializer, Read};
+#[cfg(feature = "encode")]
+pub(crate) use selected::{Serializer, Write};
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+pub(crate) use selected::EndOfInput;
+#[cfg(all(test, feature = "std", feature = "encode", feature = "decode"))]
+pub(crate) use selected::from_slice;
+#[cfg(all(test, feature = "alloc", feature = "encode", feature = "decode"))]
+pub(crate) use selected::to_vec;
+
+/// Returns the message held by `e` if it is `Error::Other`
+#[cfg(any(all(feature = "encode", feature = "alloc"), feature = "defmt"))]
+pub(crate) fn other_message<E>(e: &Error<E>) -> Option<&str> {
+    match e {
+        #[cfg(not(feature = "serde-scale-0-1"))]
+        Error::Other(message) => Some(message.as_str()),
+        #[cfg(feature = "serde-scale-0-1")]
+        Error::Other(message) => Some(&**message),
+        _ => None,
+    }
+}
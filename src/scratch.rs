@@ -0,0 +1,115 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Decoding with a caller-provided scratch buffer instead of an allocated one
+
+use crate::{
+    checked::read_checked,
+    de, logging,
+    scale::{Bytes, Read},
+    Error, Wrap,
+};
+use parity_scale_codec::Input;
+use serde::Deserialize;
+
+impl<'de, T: Deserialize<'de>> Wrap<T> {
+    /// Decodes a `Wrap` from `input`, using `scratch` to hold the bytes of strings and byte
+    /// sequences instead of allocating a buffer
+    ///
+    /// Values that do not hold strings or byte sequences decode with an empty scratch buffer.
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::{Error, Wrap};
+    ///
+    /// let mut scratch = [0; 4];
+    /// let Wrap(s) = Wrap::<String>::decode_with_scratch(&mut &b"\x0cfoo"[..], &mut scratch)?;
+    /// assert_eq!(s, "foo");
+    /// let result = Wrap::<String>::decode_with_scratch(&mut &b"\x14hello"[..], &mut scratch);
+    /// assert!(matches!(result, Err(Error::ScratchTooSmall { needed: 5, available: 4 })));
+    /// # Ok::<_, Error>(())
+    /// ```
+    pub fn decode_with_scratch<I: Input>(input: &mut I, scratch: &mut [u8]) -> Result<Self, Error> {
+        logging::decode_logged(input, |input| {
+            let mut deserializer =
+                crate::scale::Deserializer::new(ScratchInputToRead { input, scratch });
+            T::deserialize(de::Deserializer::new(&mut deserializer))
+                .map(Wrap)
+                .map_err(Error::from_deserialization)
+        })
+    }
+}
+
+struct ScratchInputToRead<'a, 'b, I: ?Sized> {
+    input: &'a mut I,
+    scratch: &'b mut [u8],
+}
+
+impl<'de, I: Input + ?Sized> Read<'de> for ScratchInputToRead<'_, '_, I> {
+    type Error = Error;
+
+    fn read_map<R, F>(&mut self, n: usize, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(Bytes<'de, '_>) -> R,
+    {
+        let available = self.scratch.len();
+        let buffer = self
+            .scratch
+            .get_mut(..n)
+            .ok_or(Error::ScratchTooSmall { needed: n, available })?;
+        read_checked(self.input, buffer)?;
+        Ok(f(Bytes::Temporary(buffer)))
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        Ok(read_checked(self.input, buf)?)
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use crate::{Error, Wrap};
+    use alloc::string::String;
+    use parity_scale_codec::Encode;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Header {
+        version: u8,
+        flags: u16,
+        height: u64,
+        parent: [u8; 4],
+    }
+
+    crate::impl_infallible_serialize!(Header { u8, u16, u64, [u8; 4] });
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Named {
+        id: u32,
+        name: String,
+    }
+
+    #[test]
+    fn integers_decode_with_small_scratch() {
+        let header = Header { version: 1, flags: 0x8001, height: u64::MAX, parent: [1, 2, 3, 4] };
+        let encoded = Wrap(&header).encode();
+        let mut scratch = [0; 8];
+        let mut input = &*encoded;
+        let Wrap(decoded) = Wrap::<Header>::decode_with_scratch(&mut input, &mut scratch).unwrap();
+        assert_eq!(decoded, header);
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn string_longer_than_scratch_is_rejected() {
+        let encoded = Wrap((1u32, "more than eight")).encode();
+        let mut scratch = [0; 8];
+        let result = Wrap::<Named>::decode_with_scratch(&mut &*encoded, &mut scratch);
+        assert!(matches!(result, Err(Error::ScratchTooSmall { needed: 15, available: 8 })));
+    }
+
+    #[test]
+    fn truncated_input_is_an_input_error() {
+        let mut scratch = [0; 8];
+        let result = Wrap::<Header>::decode_with_scratch(&mut &[1u8, 2][..], &mut scratch);
+        assert!(matches!(result, Err(Error::Input(_))));
+    }
+}
@@ -0,0 +1,759 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Serializer forwarding to `serde_scale` while fixing its divergences from `parity-scale-codec`
+//!
+//! `serde_scale` prefixes every map key with a spurious compact-encoded `2`, which neither matches
+//! the encoding of `BTreeMap` by `parity-scale-codec` nor roundtrips through `serde_scale`'s own
+//! deserializer. Maps are therefore serialized as sequences of alternating keys and values. With
+//! the `float` feature, floating point numbers, which `serde_scale` rejects, are serialized as the
+//! unsigned integers holding their IEEE-754 bits. Everything else is forwarded, but compound values
+//! must be serialized with this serializer all the way down so that nested maps are handled too.
+//!
+//! Options, enum variants with fields and compound values each nest one level deeper, and
+//! serialization fails past the maximum depth instead of recursing until the stack overflows.
+//!
+//! With keys restricted by `EncodeOptions::map_keys`, each map key is probed before being
+//! serialized and a key of another shape fails serialization with its type recorded.
+//!
+//! With the `alloc` feature, the errors `serde` returns for a mutably borrowed `RefCell` or a
+//! poisoned lock are recognized by their message and reported with the innermost struct field that
+//! failed to serialize.
+
+use crate::{
+    map_keys,
+    scale::{Error, Write},
+    EncodeOptions, MapKeyShapes,
+};
+use serde::{
+    ser::{
+        Impossible, SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant,
+        SerializeTuple, SerializeTupleStruct, SerializeTupleVariant,
+    },
+    Serialize,
+};
+use core::cell::Cell;
+
+/// Serializes `value` to `inner` with `options`
+///
+/// Errors are converted with `map_err`, except when the maximum depth is exceeded, a map key is
+/// not supported or a `RefCell` or lock cannot be accessed.
+pub(crate) fn serialize<T, W>(
+    value: &T,
+    inner: &mut crate::scale::Serializer<W>,
+    options: EncodeOptions,
+    map_err: fn(Error<W::Error>) -> crate::Error,
+) -> Result<(), crate::Error>
+where
+    T: Serialize + ?Sized,
+    W: Write,
+{
+    let exceeded = Cell::new(false);
+    let failed_field = Cell::new(None);
+    let rejected_key = Cell::new(None);
+    let depth = Depth {
+        remaining: options.max_depth,
+        exceeded: &exceeded,
+        failed_field: &failed_field,
+        map_keys: options.map_keys,
+        rejected_key: &rejected_key,
+    };
+    match value.serialize(Serializer { inner, depth }) {
+        Ok(()) => Ok(()),
+        Err(_) if exceeded.get() => Err(crate::Error::DepthExceeded { max: options.max_depth }),
+        Err(e) => match rejected_key.get() {
+            Some(type_name) => Err(crate::Error::UnsupportedMapKey { type_name }),
+            None => Err(inaccessible(&e, failed_field.get()).unwrap_or_else(|| map_err(e))),
+        },
+    }
+}
+
+/// Returns the error to report if `e` is the error `serde` returns for a mutably borrowed
+/// `RefCell` or a poisoned `Mutex` or `RwLock`
+#[cfg(feature = "alloc")]
+fn inaccessible<E>(e: &Error<E>, field: Option<&'static str>) -> Option<crate::Error> {
+    match crate::scale::other_message(e) {
+        Some("already mutably borrowed") => Some(crate::Error::AlreadyBorrowed { field }),
+        Some("lock poison error while serializing") => Some(crate::Error::LockPoisoned { field }),
+        _ => None,
+    }
+}
+
+/// Returns `None`, as the messages of custom errors are not kept without the `alloc` feature
+#[cfg(not(feature = "alloc"))]
+fn inaccessible<E>(_: &Error<E>, _: Option<&'static str>) -> Option<crate::Error> {
+    None
+}
+
+pub(crate) struct Serializer<'a, W> {
+    inner: &'a mut crate::scale::Serializer<W>,
+    depth: Depth<'a>,
+}
+
+/// Number of levels values can still nest, flag to set when going deeper, innermost struct field
+/// that failed to serialize, accepted shapes of map keys, and type of the rejected map key
+#[derive(Clone, Copy)]
+struct Depth<'a> {
+    remaining: usize,
+    exceeded: &'a Cell<bool>,
+    failed_field: &'a Cell<Option<&'static str>>,
+    map_keys: Option<MapKeyShapes>,
+    rejected_key: &'a Cell<Option<&'static str>>,
+}
+
+impl Depth<'_> {
+    /// Returns the depth one level deeper, or an error if it exceeds the maximum
+    fn nested<E>(self) -> Result<Self, Error<E>> {
+        match self.remaining.checked_sub(1) {
+            Some(remaining) => Ok(Depth { remaining, ..self }),
+            None => {
+                self.exceeded.set(true);
+                Err(Error::Other("Maximum nesting depth exceeded".into()))
+            }
+        }
+    }
+}
+
+impl<'a, W: Write> serde::Serializer for Serializer<'a, W> {
+    type Ok = ();
+    type Error = Error<W::Error>;
+    type SerializeSeq = Compound<'a, W>;
+    type SerializeTuple = Compound<'a, W>;
+    type SerializeTupleStruct = Compound<'a, W>;
+    type SerializeTupleVariant = Compound<'a, W>;
+    type SerializeMap = Compound<'a, W>;
+    type SerializeStruct = Compound<'a, W>;
+    type SerializeStructVariant = Compound<'a, W>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), Self::Error> {
+        self.inner.serialize_bool(v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), Self::Error> {
+        self.inner.serialize_i8(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), Self::Error> {
+        self.inner.serialize_i16(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), Self::Error> {
+        self.inner.serialize_i32(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), Self::Error> {
+        self.inner.serialize_i64(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), Self::Error> {
+        self.inner.serialize_u8(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), Self::Error> {
+        self.inner.serialize_u16(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), Self::Error> {
+        self.inner.serialize_u32(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), Self::Error> {
+        self.inner.serialize_u64(v)
+    }
+
+    #[cfg(not(feature = "float"))]
+    fn serialize_f32(self, v: f32) -> Result<(), Self::Error> {
+        self.inner.serialize_f32(v)
+    }
+
+    #[cfg(not(feature = "float"))]
+    fn serialize_f64(self, v: f64) -> Result<(), Self::Error> {
+        self.inner.serialize_f64(v)
+    }
+
+    #[cfg(feature = "float")]
+    fn serialize_f32(self, v: f32) -> Result<(), Self::Error> {
+        self.inner.serialize_u32(v.to_bits())
+    }
+
+    #[cfg(feature = "float")]
+    fn serialize_f64(self, v: f64) -> Result<(), Self::Error> {
+        self.inner.serialize_u64(v.to_bits())
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), Self::Error> {
+        self.inner.serialize_char(v)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), Self::Error> {
+        self.inner.serialize_str(v)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), Self::Error> {
+        self.inner.serialize_bytes(v)
+    }
+
+    fn serialize_none(self) -> Result<(), Self::Error> {
+        self.inner.serialize_none()
+    }
+
+    fn serialize_some<T>(self, v: &T) -> Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        // `Option<bool>` is a single byte, as `serde_scale` does.
+        if let Some(b) = as_bool(v) {
+            return self.inner.serialize_u8(if b { 1 } else { 2 });
+        }
+        let depth = self.depth.nested()?;
+        self.inner.serialize_u8(1)?;
+        v.serialize(Serializer { inner: self.inner, depth })
+    }
+
+    fn serialize_unit(self) -> Result<(), Self::Error> {
+        self.inner.serialize_unit()
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<(), Self::Error> {
+        self.inner.serialize_unit_struct(name)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+    ) -> Result<(), Self::Error> {
+        self.inner.serialize_unit_variant(name, variant_index, variant)
+    }
+
+    fn serialize_newtype_struct<T>(self, _: &'static str, value: &T) -> Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        let depth = self.depth.nested()?;
+        (&mut *self.inner).serialize_unit_variant(name, variant_index, variant)?;
+        value.serialize(Serializer { inner: self.inner, depth })
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        let depth = self.depth.nested()?;
+        (&mut *self.inner).serialize_seq(len)?;
+        Ok(Compound(self.inner, depth))
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        Ok(Compound(self.inner, self.depth.nested()?))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        Ok(Compound(self.inner, self.depth.nested()?))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        let depth = self.depth.nested()?;
+        (&mut *self.inner).serialize_unit_variant(name, variant_index, variant)?;
+        Ok(Compound(self.inner, depth))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        let depth = self.depth.nested()?;
+        // The length prefix of a map is the same as the one of a sequence.
+        (&mut *self.inner).serialize_seq(len)?;
+        Ok(Compound(self.inner, depth))
+    }
+
+    fn serialize_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        Ok(Compound(self.inner, self.depth.nested()?))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        let depth = self.depth.nested()?;
+        (&mut *self.inner).serialize_unit_variant(name, variant_index, variant)?;
+        Ok(Compound(self.inner, depth))
+    }
+}
+
+pub(crate) struct Compound<'a, W>(&'a mut crate::scale::Serializer<W>, Depth<'a>);
+
+impl<W: Write> Compound<'_, W> {
+    fn serialize<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error<W::Error>> {
+        value.serialize(Serializer { inner: &mut *self.0, depth: self.1 })
+    }
+
+    /// Serializes the value of the struct field `key`, recording it as the field that failed if no
+    /// field nested deeper did
+    fn serialize_named<T>(&mut self, key: &'static str, value: &T) -> Result<(), Error<W::Error>>
+    where
+        T: Serialize + ?Sized,
+    {
+        let result = self.serialize(value);
+        if result.is_err() && self.1.failed_field.get().is_none() {
+            self.1.failed_field.set(Some(key));
+        }
+        result
+    }
+}
+
+impl<W: Write> SerializeSeq for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error<W::Error>;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.serialize(value)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<W: Write> SerializeTuple for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error<W::Error>;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.serialize(value)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<W: Write> SerializeTupleStruct for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error<W::Error>;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.serialize(value)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<W: Write> SerializeTupleVariant for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error<W::Error>;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.serialize(value)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<W: Write> SerializeMap for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error<W::Error>;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        match self.1.map_keys {
+            Some(shapes) if !map_keys::is_supported(key, shapes) => {
+                self.1.rejected_key.set(Some(map_keys::key_type_name::<T>()));
+                Err(Error::Other("Unsupported map key".into()))
+            }
+            _ => self.serialize(key),
+        }
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.serialize(value)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<W: Write> SerializeStruct for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error<W::Error>;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.serialize_named(key, value)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<W: Write> SerializeStructVariant for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error<W::Error>;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.serialize_named(key, value)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Returns the boolean `v` serializes as, if any
+pub(crate) fn as_bool<T: Serialize + ?Sized>(v: &T) -> Option<bool> {
+    v.serialize(BoolProbe).ok()
+}
+
+/// Serializer succeeding only for booleans, to detect `Option<bool>`
+struct BoolProbe;
+
+#[derive(Debug)]
+struct NotBool;
+
+impl core::fmt::Display for NotBool {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("Not a boolean")
+    }
+}
+
+impl serde::ser::StdError for NotBool {}
+
+impl serde::ser::Error for NotBool {
+    fn custom<T: core::fmt::Display>(_: T) -> Self {
+        NotBool
+    }
+}
+
+impl serde::Serializer for BoolProbe {
+    type Ok = bool;
+    type Error = NotBool;
+    type SerializeSeq = Impossible<bool, NotBool>;
+    type SerializeTuple = Impossible<bool, NotBool>;
+    type SerializeTupleStruct = Impossible<bool, NotBool>;
+    type SerializeTupleVariant = Impossible<bool, NotBool>;
+    type SerializeMap = Impossible<bool, NotBool>;
+    type SerializeStruct = Impossible<bool, NotBool>;
+    type SerializeStructVariant = Impossible<bool, NotBool>;
+
+    fn serialize_bool(self, v: bool) -> Result<bool, NotBool> {
+        Ok(v)
+    }
+
+    fn serialize_i8(self, _: i8) -> Result<bool, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_i16(self, _: i16) -> Result<bool, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_i32(self, _: i32) -> Result<bool, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_i64(self, _: i64) -> Result<bool, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_u8(self, _: u8) -> Result<bool, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_u16(self, _: u16) -> Result<bool, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_u32(self, _: u32) -> Result<bool, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_u64(self, _: u64) -> Result<bool, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_f32(self, _: f32) -> Result<bool, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_f64(self, _: f64) -> Result<bool, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_char(self, _: char) -> Result<bool, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_str(self, _: &str) -> Result<bool, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_bytes(self, _: &[u8]) -> Result<bool, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_none(self) -> Result<bool, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_some<T>(self, _: &T) -> Result<bool, NotBool>
+    where
+        T: Serialize + ?Sized,
+    {
+        Err(NotBool)
+    }
+
+    fn serialize_unit(self) -> Result<bool, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<bool, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+    ) -> Result<bool, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_newtype_struct<T>(self, _: &'static str, _: &T) -> Result<bool, NotBool>
+    where
+        T: Serialize + ?Sized,
+    {
+        Err(NotBool)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<bool, NotBool>
+    where
+        T: Serialize + ?Sized,
+    {
+        Err(NotBool)
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleStruct, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleVariant, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStruct, NotBool> {
+        Err(NotBool)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStructVariant, NotBool> {
+        Err(NotBool)
+    }
+
+    fn collect_str<T>(self, _: &T) -> Result<bool, NotBool>
+    where
+        T: core::fmt::Display + ?Sized,
+    {
+        Err(NotBool)
+    }
+}
+
+#[cfg(all(test, feature = "std", feature = "encode", feature = "decode"))]
+mod tests {
+    use crate::{decode_all, encode, try_encode, Error, Wrap};
+    use core::cell::RefCell;
+    use parity_scale_codec::Decode;
+    use serde::{Deserialize, Serialize};
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        sync::{Mutex, RwLock},
+        vec::Vec,
+    };
+
+    #[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+    struct Counters {
+        id: u8,
+        hits: RefCell<u32>,
+    }
+
+    #[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+    struct Session {
+        user: u32,
+        counters: Counters,
+        history: Vec<RefCell<u8>>,
+    }
+
+    #[derive(Debug, Default, Deserialize, Serialize)]
+    struct Shared {
+        id: u8,
+        state: Mutex<u32>,
+        log: RwLock<Vec<u8>>,
+    }
+
+    /// Poisons `lock` by panicking while holding it
+    fn poison<T>(lock: &Mutex<T>) {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _guard = lock.lock().unwrap();
+            panic!("Poisoning the lock");
+        }));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn borrowed_ref_cells_are_reported_with_their_field() {
+        let session = Session::default();
+        let _guard = session.counters.hits.borrow_mut();
+        let result = try_encode(&session);
+        assert!(matches!(result, Err(Error::AlreadyBorrowed { field: Some("hits") })));
+        let message = result.unwrap_err().to_string();
+        assert_eq!(message, "`RefCell` in field `hits` is already mutably borrowed");
+    }
+
+    #[test]
+    fn borrowed_ref_cells_in_sequences_are_reported_with_the_enclosing_field() {
+        let history = vec![RefCell::new(1), RefCell::new(2)];
+        let session = Session { history, ..Session::default() };
+        let _guard = session.history[1].borrow_mut();
+        let result = try_encode(&session);
+        assert!(matches!(result, Err(Error::AlreadyBorrowed { field: Some("history") })));
+        let cell = RefCell::new(3u8);
+        let _guard = cell.borrow_mut();
+        assert!(matches!(try_encode(&cell), Err(Error::AlreadyBorrowed { field: None })));
+    }
+
+    #[test]
+    fn shared_borrows_do_not_prevent_encoding() {
+        let session = Session::default();
+        let _guard = session.counters.hits.borrow();
+        assert_eq!(try_encode(&session).unwrap(), encode(&Session::default()));
+    }
+
+    #[cfg(not(feature = "deny-panicking-encode"))]
+    #[test]
+    #[should_panic(expected = "`RefCell` in field `hits` is already mutably borrowed")]
+    fn encoding_borrowed_ref_cells_panics() {
+        use parity_scale_codec::Encode;
+
+        let session = Wrap(Session::default());
+        let _guard = session.0.counters.hits.borrow_mut();
+        session.encode();
+    }
+
+    #[test]
+    fn poisoned_locks_are_reported_with_their_field() {
+        let shared = Shared::default();
+        poison(&shared.state);
+        let result = try_encode(&shared);
+        assert!(matches!(result, Err(Error::LockPoisoned { field: Some("state") })));
+        assert_eq!(result.unwrap_err().to_string(), "Lock in field `state` is poisoned");
+    }
+
+    #[test]
+    fn healthy_cells_and_locks_roundtrip() {
+        let session = Session {
+            user: 7,
+            counters: Counters { id: 1, hits: RefCell::new(300) },
+            history: vec![RefCell::new(4)],
+        };
+        let encoded = try_encode(&session).unwrap();
+        assert_eq!(encoded, encode(&(7u32, 1u8, 300u32, vec![4u8])));
+        assert_eq!(Wrap::<Session>::decode(&mut &*encoded).unwrap().0, session);
+
+        let shared = Shared { id: 2, state: Mutex::new(9), log: RwLock::new(vec![5, 6]) };
+        let encoded = try_encode(&shared).unwrap();
+        let decoded = decode_all::<Shared>(&encoded).unwrap();
+        assert_eq!(decoded.id, 2);
+        assert_eq!(decoded.state.into_inner().unwrap(), 9);
+        assert_eq!(decoded.log.into_inner().unwrap(), [5, 6]);
+    }
+}
@@ -0,0 +1,111 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+use crate::{adapters::OutputToWrite, scale::Write, ser, EncodeOptions, Error};
+use core::convert::Infallible;
+use parity_scale_codec::Output;
+use serde::Serialize;
+
+/// Serializer writing the encodings of several values back to back into the same `Output`
+///
+/// The bytes written are the concatenation of what `Wrap(value).encode()` returns for each
+/// value. Unlike `Encode::encode_to`, this does not log encodings.
+///
+/// ```rust
+/// use serde_scale_wrap::WrapSerializer;
+///
+/// let mut encoded = Vec::new();
+/// let mut serializer = WrapSerializer::new(&mut encoded);
+/// serializer.serialize(&7u8)?;
+/// serializer.serialize("foo")?;
+/// assert_eq!(serializer.finish(), 5);
+/// assert_eq!(encoded, b"\x07\x0cfoo");
+/// # Ok::<_, serde_scale_wrap::Error>(())
+/// ```
+pub struct WrapSerializer<'a, O: ?Sized> {
+    serializer: crate::scale::Serializer<CountingWrite<'a, O>>,
+}
+
+impl<'a, O: Output + ?Sized> WrapSerializer<'a, O> {
+    /// Returns a serializer writing to `output`
+    pub fn new(output: &'a mut O) -> Self {
+        let write = CountingWrite { inner: OutputToWrite::new(output), count: 0 };
+        WrapSerializer { serializer: crate::scale::Serializer::new(write) }
+    }
+
+    /// Writes the encoding of `value`
+    ///
+    /// The output may have been partially written to if an error is returned.
+    pub fn serialize<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let options = EncodeOptions::default();
+        ser::serialize(value, &mut self.serializer, options, Error::Serialization)
+    }
+
+    /// Returns the number of bytes written
+    pub fn finish(self) -> usize {
+        self.serializer.into_inner().count
+    }
+}
+
+struct CountingWrite<'a, O: ?Sized> {
+    inner: OutputToWrite<'a, O>,
+    count: usize,
+}
+
+impl<O: Output + ?Sized> Write for CountingWrite<'_, O> {
+    type Error = Infallible;
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Infallible> {
+        self.count += bytes.len();
+        self.inner.write(bytes)
+    }
+}
+
+// `parity-scale-codec` only implements `Encode` for `String` with its `std` feature.
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::WrapSerializer;
+    use crate::{Error, Wrap};
+    use parity_scale_codec::Encode;
+    use serde::{Deserialize, Serialize};
+    use std::{collections::BTreeMap, string::String, vec, vec::Vec};
+
+    #[derive(Deserialize, PartialEq, Serialize)]
+    enum Operation {
+        Put { key: String, value: Vec<u8> },
+        Delete(String),
+    }
+
+    crate::impl_infallible_serialize!(Operation { String, Vec<u8> });
+
+    #[test]
+    fn interleaved_values_match_individual_encodings() {
+        let header = (1u8, 0x0102_0304u32);
+        let put = Operation::Put { key: "k".into(), value: vec![1, 2] };
+        let delete = Operation::Delete("gone".into());
+        let labels = BTreeMap::from([(1u16, String::from("one")), (2, "two".into())]);
+        let mut blob = Vec::new();
+        let mut serializer = WrapSerializer::new(&mut blob);
+        serializer.serialize(&header).unwrap();
+        serializer.serialize(&put).unwrap();
+        serializer.serialize(&labels).unwrap();
+        serializer.serialize(&delete).unwrap();
+        serializer.serialize(&Some(true)).unwrap();
+        let written = serializer.finish();
+        let mut expected = Wrap(header).encode();
+        expected.extend(Wrap(&put).encode());
+        expected.extend(Wrap(&labels).encode());
+        expected.extend(Wrap(&delete).encode());
+        expected.extend(Wrap(Some(true)).encode());
+        assert_eq!(blob, expected);
+        assert_eq!(written, expected.len());
+    }
+
+    #[test]
+    fn unserializable_values_fail() {
+        let mut blob = Vec::new();
+        let mut serializer = WrapSerializer::new(&mut blob);
+        serializer.serialize(&3u8).unwrap();
+        assert!(matches!(serializer.serialize(&1u128), Err(Error::Serialization(_))));
+        assert_eq!(serializer.finish(), 1);
+    }
+}
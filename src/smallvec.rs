@@ -0,0 +1,70 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+use crate::Wrap;
+use alloc::vec::Vec;
+use parity_scale_codec::{Encode, EncodeLike};
+use smallvec::{Array, SmallVec};
+
+impl<A: Array> EncodeLike<Wrap<Vec<A::Item>>> for Wrap<SmallVec<A>>
+where
+    Wrap<SmallVec<A>>: Encode,
+    Wrap<Vec<A::Item>>: Encode,
+{
+}
+
+impl<A: Array> EncodeLike<Wrap<SmallVec<A>>> for Wrap<Vec<A::Item>>
+where
+    Wrap<SmallVec<A>>: Encode,
+    Wrap<Vec<A::Item>>: Encode,
+{
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use crate::{decode_all, encode, Wrap};
+    use alloc::vec::Vec;
+    use parity_scale_codec::{Encode, EncodeLike};
+    use smallvec::SmallVec;
+
+    type Small = SmallVec<[u32; 4]>;
+
+    fn store<T: EncodeLike<Wrap<Vec<u32>>>>(value: T) -> Vec<u8> {
+        value.encode()
+    }
+
+    fn assert_encodes_like_vec(value: &Small) {
+        let expected = value.to_vec().encode();
+        assert_eq!(encode(value), expected);
+        let decoded = decode_all::<Small>(&expected).unwrap();
+        assert_eq!(decoded, *value);
+        assert_eq!(decoded.spilled(), value.spilled());
+    }
+
+    #[test]
+    fn inline_small_vectors_encode_like_vectors() {
+        let value = Small::from_slice(&[1, 2, 3]);
+        assert!(!value.spilled());
+        assert_encodes_like_vec(&value);
+        assert_encodes_like_vec(&Small::new());
+    }
+
+    #[test]
+    fn spilled_small_vectors_encode_like_vectors() {
+        let value = (0..100).collect::<Small>();
+        assert!(value.spilled());
+        assert_encodes_like_vec(&value);
+    }
+
+    #[test]
+    fn small_vectors_and_vectors_are_interchangeable() {
+        let value = (0..10).collect::<Small>();
+        assert_eq!(store(Wrap(value.clone())), store(Wrap(value.to_vec())));
+        let decoded = decode_all::<Vec<u32>>(&Wrap(value.clone()).encode()).unwrap();
+        assert_eq!(decoded, value.to_vec());
+    }
+
+    #[test]
+    fn forged_lengths_fail_to_decode() {
+        assert!(decode_all::<SmallVec<[u64; 4]>>(b"\xfe\xff\xff\xff\x01").is_err());
+    }
+}
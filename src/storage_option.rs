@@ -0,0 +1,210 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Optional values stored as empty or missing encodings instead of with a leading byte
+
+use crate::Wrap;
+
+#[cfg(feature = "decode")]
+use crate::{decode_all, Error};
+#[cfg(feature = "encode")]
+use crate::encode;
+#[cfg(feature = "encode")]
+use alloc::vec::Vec;
+#[cfg(feature = "encode")]
+use parity_scale_codec::{Encode, EncodeLike, Output};
+#[cfg(feature = "decode")]
+use parity_scale_codec::{Decode, Input};
+#[cfg(feature = "decode")]
+use serde::de::{Deserialize, DeserializeOwned};
+#[cfg(feature = "encode")]
+use serde::Serialize;
+
+impl<T> Wrap<T> {
+    /// Returns the encoding of the value in `opt`, or `None` if there is no value
+    ///
+    /// Unlike the encoding of `Option<T>`, the encoding of `Some(value)` is the one of `value`,
+    /// without a leading byte, and `None` has no encoding at all, e.g. to remove a storage key.
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::Wrap;
+    ///
+    /// assert_eq!(Wrap::encode_option(&Some(7u8)), Some(vec![7]));
+    /// assert_eq!(Wrap::<u8>::encode_option(&None), None);
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if the serializer returns an error, like `Wrap` does.
+    #[cfg(feature = "encode")]
+    pub fn encode_option(opt: &Option<T>) -> Option<Vec<u8>>
+    where
+        T: Serialize,
+    {
+        opt.as_ref().map(encode)
+    }
+
+    /// Decodes the value encoded in `maybe_bytes`, or returns `None` if there are no bytes
+    ///
+    /// A missing or empty encoding is decoded as `None`, and any other encoding as `Some`,
+    /// failing if bytes are left over. Values with an empty encoding (e.g. `()`) thus decode as
+    /// `None`.
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::Wrap;
+    ///
+    /// assert_eq!(Wrap::decode_option(Some(&[7][..]))?, Some(7u8));
+    /// assert_eq!(Wrap::<u8>::decode_option(Some(&[]))?, None);
+    /// assert_eq!(Wrap::<u8>::decode_option(None)?, None);
+    /// # Ok::<_, serde_scale_wrap::Error>(())
+    /// ```
+    #[cfg(feature = "decode")]
+    pub fn decode_option(maybe_bytes: Option<&[u8]>) -> Result<Option<T>, Error>
+    where
+        T: DeserializeOwned,
+    {
+        match maybe_bytes {
+            None | Some([]) => Ok(None),
+            Some(bytes) => decode_all(bytes).map(Some),
+        }
+    }
+}
+
+/// Optional value encoded as nothing when absent and as the value alone when present, as
+/// storage values whose absence is an empty encoding are
+///
+/// This differs from the encoding of `Option<T>`, which starts with a byte telling whether there
+/// is a value: `StorageOptionWrap(None)` encodes to no bytes and `StorageOptionWrap(Some(value))`
+/// encodes like `Wrap(value)`. Decoding an empty input yields `None` and any other input is
+/// decoded as `Some`, failing if bytes are left over. Inputs not knowing their remaining length
+/// (e.g. streams) are always decoded as `Some`, without checking for left over bytes.
+///
+/// Values with an empty encoding (e.g. `()` or structs without fields) are decoded as `None`, so
+/// `T` should not have any for `Some` to roundtrip.
+///
+/// ```rust
+/// use parity_scale_codec::{Decode, Encode};
+/// use serde_scale_wrap::StorageOptionWrap;
+///
+/// assert_eq!(StorageOptionWrap(Some(7u8)).encode(), [7]);
+/// assert!(StorageOptionWrap::<u8>(None).encode().is_empty());
+/// assert_eq!(Some(7u8).encode(), [1, 7]);
+/// assert_eq!(StorageOptionWrap::<u8>::decode(&mut &[][..])?, StorageOptionWrap(None));
+/// assert!(StorageOptionWrap::<u8>::decode(&mut &[7, 8][..]).is_err());
+/// # Ok::<_, parity_scale_codec::Error>(())
+/// ```
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct StorageOptionWrap<T>(pub Option<T>);
+
+impl<T> StorageOptionWrap<T> {
+    /// Returns the optional value
+    pub fn into_inner(self) -> Option<T> {
+        self.0
+    }
+}
+
+impl<T> From<Option<T>> for StorageOptionWrap<T> {
+    fn from(value: Option<T>) -> Self {
+        StorageOptionWrap(value)
+    }
+}
+
+impl<T> From<StorageOptionWrap<T>> for Option<T> {
+    fn from(value: StorageOptionWrap<T>) -> Self {
+        value.0
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<T> Encode for StorageOptionWrap<T>
+where
+    for<'a> Wrap<&'a T>: Encode,
+{
+    /// # Panics
+    /// Panics if the serializer returns an error, like `Wrap` does.
+    fn encode_to<O: Output + ?Sized>(&self, dst: &mut O) {
+        if let Some(value) = &self.0 {
+            Wrap(value).encode_to(dst);
+        }
+    }
+}
+
+#[cfg(feature = "encode")]
+impl<T> EncodeLike for StorageOptionWrap<T> where for<'a> Wrap<&'a T>: Encode {}
+
+#[cfg(feature = "decode")]
+impl<'de, T: Deserialize<'de>> Decode for StorageOptionWrap<T> {
+    fn decode<I: Input>(input: &mut I) -> Result<Self, parity_scale_codec::Error> {
+        if input.remaining_len()? == Some(0) {
+            return Ok(StorageOptionWrap(None));
+        }
+        let Wrap(value) = Wrap::decode(input)?;
+        match input.remaining_len()? {
+            Some(len) if len > 0 => Err(Error::TrailingBytes { len }.into()),
+            _ => Ok(StorageOptionWrap(Some(value))),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::StorageOptionWrap;
+    use crate::{Error, Wrap};
+    use alloc::{string::String, vec, vec::Vec};
+    use parity_scale_codec::{Decode, Encode};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    struct Account {
+        nonce: u32,
+        name: String,
+    }
+
+    crate::impl_infallible_serialize!(Account { u32, String });
+
+    fn account() -> Account {
+        Account { nonce: 3, name: "alice".into() }
+    }
+
+    #[test]
+    fn absent_values_encode_to_nothing() {
+        assert_eq!(Wrap::<Account>::encode_option(&None), None);
+        assert_eq!(StorageOptionWrap::<Account>(None).encode(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn present_values_encode_without_a_leading_byte() {
+        let encoded = Wrap(account()).encode();
+        assert_eq!(Wrap::encode_option(&Some(account())), Some(encoded.clone()));
+        assert_eq!(StorageOptionWrap(Some(account())).encode(), encoded);
+        assert_eq!(Wrap(Some(account())).encode(), [&[1][..], &encoded].concat());
+    }
+
+    #[test]
+    fn missing_and_empty_encodings_decode_as_absent() {
+        assert_eq!(Wrap::<Account>::decode_option(None).unwrap(), None);
+        assert_eq!(Wrap::<Account>::decode_option(Some(&[])).unwrap(), None);
+        let decoded = StorageOptionWrap::<Account>::decode(&mut &[][..]).unwrap();
+        assert_eq!(decoded, StorageOptionWrap(None));
+    }
+
+    #[test]
+    fn other_encodings_decode_as_present() {
+        let encoded = Wrap(account()).encode();
+        assert_eq!(Wrap::<Account>::decode_option(Some(&encoded)).unwrap(), Some(account()));
+        let decoded = StorageOptionWrap::<Account>::decode(&mut &*encoded).unwrap();
+        assert_eq!(decoded.into_inner(), Some(account()));
+    }
+
+    #[test]
+    fn left_over_bytes_are_rejected() {
+        let encoded = [Wrap(account()).encode(), vec![0]].concat();
+        let result = Wrap::<Account>::decode_option(Some(&encoded));
+        assert!(matches!(result, Err(Error::TrailingBytes { len: 1 })));
+        assert!(StorageOptionWrap::<Account>::decode(&mut &*encoded).is_err());
+    }
+
+    #[test]
+    fn values_with_empty_encodings_decode_as_absent() {
+        assert_eq!(Wrap::encode_option(&Some(())), Some(Vec::new()));
+        assert_eq!(Wrap::<()>::decode_option(Some(&[])).unwrap(), None);
+    }
+}
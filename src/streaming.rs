@@ -0,0 +1,278 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+use crate::{logging, DecodeBuffer, Error};
+use core::{fmt, marker::PhantomData};
+use parity_scale_codec::Input;
+use serde::de::DeserializeOwned;
+
+/// Decoder of values whose encodings arrive in fragments, e.g. from a non-blocking socket
+///
+/// [`StreamingDecoder::push`] takes the fragments as they arrive and returns the value once its
+/// encoding is complete. Only the bytes of the value are buffered: whenever decoding needs bytes
+/// that have not arrived yet, exactly the missing bytes are taken from the next fragments and
+/// decoding starts over from the beginning of the bytes buffered so far. Bytes pushed after the
+/// end of the value are left to the caller, [`StreamingDecoder::consumed`] telling where the
+/// value ends in the last fragment pushed.
+///
+/// The bytes of the value are buffered in a [`DecodeBuffer`], which
+/// [`StreamingDecoder::with_buffer`] can limit. Fragments shorter than the missing bytes are
+/// buffered without decoding, so that a string or byte sequence arriving one byte at a time is
+/// decoded a few times, and not once per byte. Values requiring many small reads (e.g. long
+/// sequences of numbers) arriving in small fragments are still decoded in quadratic time in their
+/// length, each read failing for lack of bytes making decoding start over; prefer length-delimited
+/// frames decoded with [`decode_all`](crate::decode_all) for these.
+///
+/// ```rust
+/// use serde_scale_wrap::StreamingDecoder;
+///
+/// let mut decoder = StreamingDecoder::<(u16, String)>::new();
+/// assert_eq!(decoder.push(b"\x07\x00\x0cf")?, None);
+/// let value = decoder.push(b"oo\x08\x00")?;
+/// assert_eq!(value, Some((7, "foo".to_owned())));
+/// assert_eq!(decoder.consumed(), 2);
+/// # Ok::<_, serde_scale_wrap::Error>(())
+/// ```
+pub struct StreamingDecoder<T> {
+    buffered: DecodeBuffer,
+    /// Number of bytes to buffer before decoding again
+    missing: usize,
+    /// Number of bytes of the last fragment pushed that were taken
+    consumed: usize,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T> StreamingDecoder<T> {
+    /// Returns a decoder waiting for the first byte of a value
+    pub fn new() -> Self {
+        Self::with_buffer(DecodeBuffer::new())
+    }
+
+    /// Returns a decoder buffering the bytes of incomplete values in `buffer`
+    ///
+    /// [`StreamingDecoder::push`] fails with [`Error::BufferFull`] if the encoding of a value
+    /// does not fit in the maximum capacity of `buffer`.
+    ///
+    /// ```rust
+    /// use serde_scale_wrap::{DecodeBuffer, Error, StreamingDecoder};
+    ///
+    /// let buffer = DecodeBuffer::new().max_capacity(3);
+    /// let mut decoder = StreamingDecoder::<String>::with_buffer(buffer);
+    /// assert_eq!(decoder.push(b"\x0cfo")?, None);
+    /// assert!(matches!(decoder.push(b"o"), Err(Error::BufferFull { needed: 4, max: 3 })));
+    /// # Ok::<_, serde_scale_wrap::Error>(())
+    /// ```
+    pub fn with_buffer(buffer: DecodeBuffer) -> Self {
+        StreamingDecoder { buffered: buffer, missing: 0, consumed: 0, _value: PhantomData }
+    }
+
+    /// Returns the number of bytes of the last fragment pushed that belong to the value being
+    /// decoded
+    ///
+    /// After `push` returns a value, the bytes of the last fragment from this offset on belong to
+    /// the next values and must be pushed again. After `push` returns `None`, the whole fragment
+    /// was taken. After `push` fails, the bytes up to this offset made the value invalid.
+    pub fn consumed(&self) -> usize {
+        self.consumed
+    }
+
+    /// Returns the number of bytes of the incomplete value buffered so far
+    pub fn buffered(&self) -> usize {
+        self.buffered.len()
+    }
+
+    /// Discards the bytes buffered so far, waiting for the first byte of a value again
+    pub fn reset(&mut self) {
+        self.buffered.consume(self.buffered.len());
+        self.missing = 0;
+    }
+}
+
+impl<T: DeserializeOwned> StreamingDecoder<T> {
+    /// Takes the bytes of `fragment` belonging to the value being decoded and returns the value if
+    /// its encoding is complete
+    ///
+    /// Returns `Ok(None)` if more bytes are needed, and fails as soon as the bytes buffered
+    /// cannot be the beginning of an encoding. After returning a value or failing, the decoder
+    /// is reset and waits for the first byte of the next value.
+    pub fn push(&mut self, fragment: &[u8]) -> Result<Option<T>, Error> {
+        let mut rest = fragment;
+        loop {
+            let taken = self.missing.min(rest.len());
+            self.consumed = fragment.len() - rest.len();
+            if let Err(e) = self.buffered.extend_from_input(&mut &rest[..taken], taken) {
+                self.reset();
+                return Err(e);
+            }
+            rest = &rest[taken..];
+            self.missing -= taken;
+            self.consumed = fragment.len() - rest.len();
+            if self.missing > 0 {
+                return Ok(None);
+            }
+            let mut input = PartialInput { bytes: self.buffered.as_unread_slice(), missing: 0 };
+            match crate::deserialize_from_unlogged(&mut input) {
+                Err(_) if input.missing > 0 && rest.is_empty() => {
+                    self.missing = input.missing;
+                    return Ok(None);
+                }
+                Err(_) if input.missing > 0 => self.missing = input.missing,
+                result => {
+                    let len = self.buffered.len();
+                    self.reset();
+                    return logging::decode_counted(|count| {
+                        *count = len;
+                        result
+                    })
+                    .map(Some);
+                }
+            }
+        }
+    }
+}
+
+impl<T> Default for StreamingDecoder<T> {
+    fn default() -> Self {
+        StreamingDecoder::new()
+    }
+}
+
+impl<T> fmt::Debug for StreamingDecoder<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamingDecoder")
+            .field("buffered", &self.buffered.len())
+            .field("missing", &self.missing)
+            .finish()
+    }
+}
+
+/// `Input` over the bytes read so far, recording how many more bytes a failed read needed
+pub(crate) struct PartialInput<'a> {
+    pub(crate) bytes: &'a [u8],
+    pub(crate) missing: usize,
+}
+
+impl Input for PartialInput<'_> {
+    fn remaining_len(&mut self) -> Result<Option<usize>, parity_scale_codec::Error> {
+        Ok(None)
+    }
+
+    fn read(&mut self, into: &mut [u8]) -> Result<(), parity_scale_codec::Error> {
+        if into.len() > self.bytes.len() {
+            self.missing = into.len() - self.bytes.len();
+            return Err("Not enough data to fill buffer".into());
+        }
+        let (read, rest) = self.bytes.split_at(into.len());
+        into.copy_from_slice(read);
+        self.bytes = rest;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::StreamingDecoder;
+    use crate::{encode, DecodeBuffer, Error};
+    use alloc::{collections::BTreeMap, string::String, vec, vec::Vec};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    struct Message {
+        id: u32,
+        body: String,
+        tags: Vec<(u8, Option<String>)>,
+        headers: BTreeMap<String, u64>,
+    }
+
+    fn message(id: u32) -> Message {
+        Message {
+            id,
+            body: "hello".repeat(id as usize * 30),
+            tags: vec![(1, None), (2, Some("two".into()))],
+            headers: BTreeMap::from([("length".into(), u64::from(id) << 40)]),
+        }
+    }
+
+    #[test]
+    fn values_are_decoded_one_byte_at_a_time() {
+        let encoded = encode(&message(3));
+        let mut decoder = StreamingDecoder::<Message>::new();
+        let (last, bytes) = encoded.split_last().unwrap();
+        for byte in bytes {
+            assert_eq!(decoder.push(&[*byte]).unwrap(), None);
+            assert_eq!(decoder.consumed(), 1);
+        }
+        assert_eq!(decoder.buffered(), encoded.len() - 1);
+        assert_eq!(decoder.push(&[*last]).unwrap(), Some(message(3)));
+        assert_eq!((decoder.consumed(), decoder.buffered()), (1, 0));
+    }
+
+    #[test]
+    fn values_are_decoded_from_uneven_halves() {
+        let encoded = encode(&message(2));
+        for split in [1, 5, encoded.len() / 3, encoded.len() - 1] {
+            let mut decoder = StreamingDecoder::<Message>::new();
+            assert_eq!(decoder.push(&encoded[..split]).unwrap(), None);
+            assert_eq!(decoder.push(&encoded[split..]).unwrap(), Some(message(2)));
+            assert_eq!(decoder.consumed(), encoded.len() - split);
+        }
+    }
+
+    #[test]
+    fn trailing_bytes_are_left_to_the_caller() {
+        let stream = [encode(&message(1)), encode(&message(2)), encode(&message(3))].concat();
+        for fragment_len in [1, 7, 100, stream.len()] {
+            let mut decoder = StreamingDecoder::<Message>::new();
+            let mut decoded = Vec::new();
+            for mut fragment in stream.chunks(fragment_len) {
+                while let Some(value) = decoder.push(fragment).unwrap() {
+                    decoded.push(value);
+                    fragment = &fragment[decoder.consumed()..];
+                }
+            }
+            assert_eq!(decoded, [message(1), message(2), message(3)]);
+            assert_eq!(decoder.buffered(), 0);
+        }
+    }
+
+    #[test]
+    fn values_without_bytes_are_decoded_at_once() {
+        let mut decoder = StreamingDecoder::<()>::new();
+        assert_eq!(decoder.push(b"\x01").unwrap(), Some(()));
+        assert_eq!(decoder.consumed(), 0);
+    }
+
+    #[test]
+    fn invalid_prefixes_fail_early() {
+        let mut decoder = StreamingDecoder::<(bool, String)>::new();
+        let result = decoder.push(b"\x02\x0cfoo");
+        assert!(matches!(result, Err(Error::Deserialization(_))));
+        assert_eq!(decoder.consumed(), 1);
+        assert_eq!(decoder.push(b"\x01\x04").unwrap(), None);
+        assert_eq!(decoder.push(b"a\x00").unwrap(), Some((true, "a".into())));
+        assert_eq!(decoder.consumed(), 1);
+    }
+
+    #[test]
+    fn forged_lengths_are_buffered_as_bytes_arrive() {
+        let mut decoder = StreamingDecoder::<Vec<u8>>::new();
+        assert_eq!(decoder.push(b"\xfe\xff\xff\xff").unwrap(), None);
+        assert_eq!(decoder.push(&[0; 100]).unwrap(), None);
+        assert_eq!(decoder.buffered(), 104);
+        decoder.reset();
+        assert_eq!(decoder.push(b"\x08ab").unwrap(), Some(b"ab".to_vec()));
+        assert_eq!(decoder.consumed(), 3);
+    }
+
+    #[test]
+    fn values_longer_than_the_buffer_are_rejected() {
+        let encoded = encode(&message(1));
+        let buffer = DecodeBuffer::new().max_capacity(encoded.len() - 1);
+        let mut decoder = StreamingDecoder::<Message>::with_buffer(buffer);
+        let result = decoder.push(&encoded);
+        assert!(matches!(result, Err(Error::BufferFull { .. })));
+        assert_eq!(decoder.buffered(), 0);
+        let buffer = DecodeBuffer::new().max_capacity(encoded.len());
+        let mut decoder = StreamingDecoder::<Message>::with_buffer(buffer);
+        assert_eq!(decoder.push(&encoded).unwrap(), Some(message(1)));
+    }
+}
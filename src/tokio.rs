@@ -0,0 +1,224 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Encoding to `AsyncWrite` and decoding from `AsyncRead`
+
+use crate::{Error, Wrap};
+
+#[cfg(feature = "encode")]
+use crate::try_encode;
+#[cfg(feature = "decode")]
+use crate::{logging, options::Progress, streaming::PartialInput, DecodeBuffer, DecodeOptions};
+#[cfg(feature = "decode")]
+use ::tokio::io::AsyncRead;
+#[cfg(feature = "encode")]
+use ::tokio::io::{AsyncWrite, AsyncWriteExt};
+#[cfg(feature = "decode")]
+use serde::de::DeserializeOwned;
+#[cfg(feature = "encode")]
+use serde::Serialize;
+
+impl<T> Wrap<T> {
+    /// Encodes the wrapped value and writes it to `w`
+    ///
+    /// The encoding is built in memory before being written, and `w` is not flushed.
+    ///
+    /// # Cancellation safety
+    /// If the future is dropped before completion, part of the encoding may have been written to
+    /// `w`, leaving the stream in the middle of a value.
+    ///
+    /// ```rust
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), serde_scale_wrap::Error> {
+    /// use serde_scale_wrap::Wrap;
+    ///
+    /// let mut written = Vec::new();
+    /// Wrap((7u8, "foo")).encode_to_async(&mut written).await?;
+    /// assert_eq!(written, b"\x07\x0cfoo");
+    /// let Wrap(decoded) = Wrap::<(u8, String)>::decode_from_async(&mut &*written).await?;
+    /// assert_eq!(decoded, (7, "foo".to_owned()));
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "encode")]
+    pub async fn encode_to_async<W>(&self, w: &mut W) -> Result<(), Error>
+    where
+        T: Serialize,
+        W: AsyncWrite + Unpin + ?Sized,
+    {
+        let encoded = try_encode(&self.0)?;
+        w.write_all(&encoded).await?;
+        Ok(())
+    }
+
+    /// Reads a value from `r` and decodes it
+    ///
+    /// Nothing past the encoding of the value is read from `r`. Whenever decoding needs bytes that
+    /// have not been read yet, exactly the missing bytes are read and decoding starts over from the
+    /// beginning of the bytes read so far. Values requiring many small reads (e.g. long sequences
+    /// of numbers) are thus decoded in quadratic time; prefer reading length-delimited frames and
+    /// decoding them with [`decode_all`](crate::decode_all) for these.
+    ///
+    /// The bytes read are buffered in a [`DecodeBuffer`], growing as bytes
+    /// arrive rather than to the length prefixes read upfront. A stream ending in the middle of the
+    /// value makes this return [`Error::Io`] with the `UnexpectedEof` kind.
+    ///
+    /// # Cancellation safety
+    /// If the future is dropped before completion, the bytes already read from `r` are lost and
+    /// the stream can no longer be decoded from.
+    #[cfg(feature = "decode")]
+    pub async fn decode_from_async<R>(r: &mut R) -> Result<Self, Error>
+    where
+        T: DeserializeOwned,
+        R: AsyncRead + Unpin + ?Sized,
+    {
+        DecodeOptions::new().decode_from_async(r).await.map(Wrap)
+    }
+}
+
+#[cfg(feature = "decode")]
+impl DecodeOptions {
+    /// Reads a `T` from `r` and decodes it with these options, like
+    /// `Wrap::<T>::decode_from_async` does
+    pub async fn decode_from_async<T, R>(&self, r: &mut R) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+        R: AsyncRead + Unpin + ?Sized,
+    {
+        let mut progress = Progress::new(self);
+        let mut buffered = DecodeBuffer::new();
+        let result = loop {
+            let mut input = PartialInput { bytes: buffered.as_unread_slice(), missing: 0 };
+            match crate::deserialize_from_unlogged_with(&mut input, *self) {
+                Err(_) if input.missing > 0 => {
+                    let missing = input.missing;
+                    if let Err(e) = buffered.extend_from_async_read(r, missing).await {
+                        break Err(e);
+                    }
+                    progress.update(buffered.len());
+                }
+                result => break result,
+            }
+        };
+        if result.is_ok() {
+            progress.finish(buffered.len());
+        }
+        logging::decode_counted(|count| {
+            *count = buffered.len();
+            result
+        })
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use crate::{DecodeOptions, Error, Wrap};
+    use ::tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use serde::{Deserialize, Serialize};
+    use std::{
+        cell::RefCell, collections::BTreeMap, io::ErrorKind, string::String, vec, vec::Vec,
+    };
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    struct Message {
+        id: u32,
+        body: String,
+        attachments: Vec<Vec<u8>>,
+        headers: BTreeMap<String, bool>,
+    }
+
+    fn message(id: u32) -> Message {
+        Message {
+            id,
+            body: "hello over tcp".repeat(id as usize),
+            attachments: vec![vec![1; 40], vec![]],
+            headers: BTreeMap::from([("urgent".into(), id & 1 == 0)]),
+        }
+    }
+
+    #[::tokio::test]
+    async fn split_reads_decode_consecutive_values() {
+        let (mut client, mut server) = ::tokio::io::duplex(3);
+        let writer = async move {
+            for id in 0..3 {
+                Wrap(message(id)).encode_to_async(&mut client).await.unwrap();
+            }
+            client.write_u8(0xff).await.unwrap();
+        };
+        let reader = async move {
+            let mut decoded = Vec::new();
+            for _ in 0..3 {
+                decoded.push(Wrap::<Message>::decode_from_async(&mut server).await.unwrap().0);
+            }
+            (decoded, server.read_u8().await.unwrap())
+        };
+        let ((), (decoded, trailer)) = ::tokio::join!(writer, reader);
+        assert_eq!(decoded, (0..3).map(message).collect::<Vec<_>>());
+        assert_eq!(trailer, 0xff);
+    }
+
+    #[::tokio::test]
+    async fn truncated_stream_fails() {
+        let encoded = crate::encode(&message(2));
+        let (mut client, mut server) = ::tokio::io::duplex(4096);
+        client.write_all(&encoded[..encoded.len() - 1]).await.unwrap();
+        drop(client);
+        match Wrap::<Message>::decode_from_async(&mut server).await {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), ErrorKind::UnexpectedEof),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[::tokio::test]
+    async fn forged_lengths_fail_once_the_stream_ends() {
+        let encoded = [&b"\xfe\xff\xff\xff"[..], &[7; 100]].concat();
+        match Wrap::<Vec<u8>>::decode_from_async(&mut &*encoded).await {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), ErrorKind::UnexpectedEof),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[::tokio::test]
+    async fn invalid_encodings_fail_without_reading_further() {
+        let mut input = &[1, 0, 0, 0, 2, 9][..];
+        let result = Wrap::<(u32, bool)>::decode_from_async(&mut input).await;
+        assert!(matches!(result, Err(Error::Deserialization(_))));
+        assert_eq!(input, [9]);
+    }
+
+    #[::tokio::test]
+    async fn unserializable_values_are_not_written() {
+        let mut written = Vec::new();
+        let result = Wrap((1u8, 1u128)).encode_to_async(&mut written).await;
+        assert!(matches!(result, Err(Error::Serialization(_))));
+        assert!(written.is_empty());
+    }
+
+    thread_local! {
+        static REPORTED: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn record(count: u64) {
+        REPORTED.with(|reported| reported.borrow_mut().push(count));
+    }
+
+    #[::tokio::test]
+    async fn progress_is_reported_at_intervals() {
+        // Strings are read in large chunks, unlike the elements of a `Vec<u8>`.
+        let chunks = vec!["z".repeat(1 << 16); 16];
+        let encoded = crate::encode(&(chunks.clone(), 7u8));
+        let len = encoded.len() as u64;
+        let (mut client, mut server) = ::tokio::io::duplex(4096);
+        let writer = async move { client.write_all(&encoded).await.unwrap() };
+        let options = DecodeOptions::new().on_progress(300_000, record);
+        let reader = options.decode_from_async::<(Vec<String>, u8), _>(&mut server);
+        let ((), decoded) = ::tokio::join!(writer, reader);
+        assert_eq!(decoded.unwrap(), (chunks, 7));
+        let reported = REPORTED.with(|reported| reported.take());
+        assert_eq!(reported.len(), 4);
+        let (&last, reported) = reported.split_last().unwrap();
+        assert_eq!(last, len);
+        assert!(reported[0] >= 300_000);
+        assert!(reported.windows(2).all(|w| w[1] - w[0] >= 300_000));
+        assert!(reported.iter().all(|&n| n < last));
+    }
+}
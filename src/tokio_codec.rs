@@ -0,0 +1,262 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! `tokio_util::codec` implementation for framed transports
+//!
+//! [`ScaleCodec`] sends each value as a frame made of its length followed by its SCALE encoding,
+//! so that `tokio_util::codec::Framed` turns a byte stream into a stream and sink of values. The
+//! length is encoded as a [`LengthPrefix`], compact by default, like the frames of the
+//! [`frame`](crate::frame) module, so that either side can use blocking or asynchronous I/O.
+//!
+//! ```rust
+//! use futures_util::{SinkExt, StreamExt};
+//! use serde_scale_wrap::tokio_codec::ScaleCodec;
+//! use tokio_util::codec::Framed;
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> Result<(), serde_scale_wrap::Error> {
+//! let (client, server) = tokio::io::duplex(64);
+//! let mut client = Framed::new(client, ScaleCodec::<(u8, String)>::default());
+//! let mut server = Framed::new(server, ScaleCodec::<(u8, String)>::default());
+//! client.send((1, "ping".to_owned())).await?;
+//! assert_eq!(server.next().await.unwrap()?, (1, "ping".to_owned()));
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{bytes::BytesOutput, decode_all, frame::frame_len, try_encode, Error};
+use bytes::{Buf, BufMut, BytesMut};
+use core::{fmt, marker::PhantomData};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio_util::codec::{Decoder, Encoder};
+
+pub use crate::frame::{Compact, FixedU32, LengthPrefix, DEFAULT_MAX_FRAME_LEN};
+
+/// Codec sending values of type `T` as frames made of their length encoded as `P` and their SCALE
+/// encoding
+///
+/// Decoding fails with [`Error::FrameTooLarge`] as soon as the prefix of a frame exceeding the
+/// maximum length is read, without buffering the frame, and with [`Error::TrailingBytes`] if
+/// the frame is longer than the value it holds.
+pub struct ScaleCodec<T, P = Compact> {
+    prefix: P,
+    max_frame_len: usize,
+    marker: PhantomData<fn(T) -> T>,
+}
+
+impl<T, P: LengthPrefix> ScaleCodec<T, P> {
+    /// Returns a codec prefixing frames with their length encoded as `prefix`, and rejecting
+    /// frames longer than `max_frame_len` bytes
+    pub fn new(prefix: P, max_frame_len: usize) -> Self {
+        ScaleCodec { prefix, max_frame_len, marker: PhantomData }
+    }
+
+    /// Returns the encoding of the length of frames
+    pub fn length_prefix(&self) -> P {
+        self.prefix
+    }
+
+    /// Returns the maximum length of a frame, excluding its length prefix
+    pub fn max_frame_len(&self) -> usize {
+        self.max_frame_len
+    }
+}
+
+/// Returns a codec using compact length prefixes and [`DEFAULT_MAX_FRAME_LEN`]
+impl<T> Default for ScaleCodec<T> {
+    fn default() -> Self {
+        ScaleCodec::new(Compact, DEFAULT_MAX_FRAME_LEN)
+    }
+}
+
+impl<T, P: LengthPrefix> Clone for ScaleCodec<T, P> {
+    fn clone(&self) -> Self {
+        ScaleCodec::new(self.prefix, self.max_frame_len)
+    }
+}
+
+impl<T, P: LengthPrefix> fmt::Debug for ScaleCodec<T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScaleCodec")
+            .field("prefix", &self.prefix)
+            .field("max_frame_len", &self.max_frame_len)
+            .finish()
+    }
+}
+
+impl<T: Serialize, P: LengthPrefix> Encoder<T> for ScaleCodec<T, P> {
+    type Error = Error;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<(), Error> {
+        let encoded = try_encode(&item)?;
+        let len = frame_len(encoded.len(), self.max_frame_len)?;
+        dst.reserve(5 + encoded.len());
+        self.prefix.write_len(len, &mut BytesOutput::new(dst));
+        dst.put_slice(&encoded);
+        Ok(())
+    }
+}
+
+impl<T: DeserializeOwned, P: LengthPrefix> Decoder for ScaleCodec<T, P> {
+    type Item = T;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<T>, Error> {
+        let prefix_len = match src.first() {
+            Some(&b) => self.prefix.prefix_len(b),
+            None => return Ok(None),
+        };
+        let prefix = match src.get(..prefix_len) {
+            Some(prefix) => prefix,
+            None => return Ok(None),
+        };
+        let len = self.prefix.read_len(prefix)? as usize;
+        if len > self.max_frame_len {
+            return Err(Error::FrameTooLarge { len, max: self.max_frame_len });
+        }
+        if src.len() < prefix_len + len {
+            src.reserve(prefix_len + len - src.len());
+            return Ok(None);
+        }
+        src.advance(prefix_len);
+        let frame = src.split_to(len);
+        decode_all(&frame).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Compact, FixedU32, LengthPrefix, ScaleCodec};
+    use crate::frame::{FrameReader, FrameWriter};
+    use crate::Error;
+    use bytes::BytesMut;
+    use futures_util::{SinkExt, StreamExt};
+    use serde::{Deserialize, Serialize};
+    use std::{string::String, vec, vec::Vec};
+    use tokio_util::codec::{Decoder, Encoder, Framed};
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    struct Message {
+        id: u32,
+        payload: Vec<u8>,
+        note: Option<String>,
+    }
+
+    fn messages() -> Vec<Message> {
+        vec![
+            Message { id: 1, payload: vec![], note: None },
+            Message { id: 2, payload: vec![7; 300], note: Some("split".into()) },
+            Message { id: 3, payload: vec![1, 2, 3], note: Some(String::new()) },
+        ]
+    }
+
+    async fn pump<P>(codec: ScaleCodec<Message, P>, max_buf_size: usize) -> Vec<Message>
+    where
+        P: LengthPrefix + Unpin,
+    {
+        let (client, server) = ::tokio::io::duplex(max_buf_size);
+        let mut client = Framed::new(client, codec.clone());
+        let server = Framed::new(server, codec);
+        let send = async move {
+            for message in messages() {
+                client.send(message).await.unwrap();
+            }
+        };
+        let receive = server.take(3).map(Result::unwrap).collect::<Vec<_>>();
+        ::tokio::join!(send, receive).1
+    }
+
+    #[::tokio::test]
+    async fn messages_go_through_framed_transports() {
+        assert_eq!(pump(ScaleCodec::new(Compact, 1024), 4096).await, messages());
+        assert_eq!(pump(ScaleCodec::new(FixedU32, 1024), 4096).await, messages());
+    }
+
+    #[::tokio::test]
+    async fn messages_split_across_small_reads_are_reassembled() {
+        assert_eq!(pump(ScaleCodec::new(Compact, 1024), 3).await, messages());
+        assert_eq!(pump(ScaleCodec::new(FixedU32, 1024), 3).await, messages());
+    }
+
+    #[test]
+    fn prefixes_encode_the_frame_length() {
+        let message = Message { id: 1, payload: vec![], note: None };
+        let mut dst = BytesMut::new();
+        ScaleCodec::new(Compact, 64).encode(message.clone(), &mut dst).unwrap();
+        assert_eq!(dst, &[6 << 2, 1, 0, 0, 0, 0, 0][..]);
+        let mut dst = BytesMut::new();
+        ScaleCodec::new(FixedU32, 64).encode(message, &mut dst).unwrap();
+        assert_eq!(dst, &[6, 0, 0, 0, 1, 0, 0, 0, 0, 0][..]);
+    }
+
+    #[test]
+    fn oversized_frames_are_rejected_without_buffering() {
+        let mut codec = ScaleCodec::<Message, _>::new(FixedU32, 16);
+        let mut src = BytesMut::from(&[17, 0, 0, 0, 1][..]);
+        let result = codec.decode(&mut src);
+        assert!(matches!(result, Err(Error::FrameTooLarge { len: 17, max: 16 })));
+        let message = Message { id: 1, payload: vec![0; 12], note: None };
+        let result = codec.encode(message, &mut BytesMut::new());
+        assert!(matches!(result, Err(Error::FrameTooLarge { len: 18, max: 16 })));
+    }
+
+    #[test]
+    fn incomplete_frames_are_awaited() {
+        let mut codec = ScaleCodec::<(u8, u16)>::default();
+        let mut src = BytesMut::new();
+        for &b in &[3 << 2, 1, 2] {
+            src.extend_from_slice(&[b]);
+            assert!(codec.decode(&mut src).unwrap().is_none());
+        }
+        src.extend_from_slice(&[0, 9]);
+        assert_eq!(codec.decode(&mut src).unwrap(), Some((1, 2)));
+        assert_eq!(src, &[9][..]);
+    }
+
+    #[test]
+    fn frames_longer_than_their_value_are_rejected() {
+        let mut codec = ScaleCodec::<u8>::default();
+        let mut src = BytesMut::from(&[2 << 2, 1, 2][..]);
+        assert!(matches!(codec.decode(&mut src), Err(Error::TrailingBytes { len: 1 })));
+    }
+
+    #[test]
+    fn frames_written_by_frame_writers_are_decoded() {
+        let mut writer = FrameWriter::with_length_prefix(Vec::new(), FixedU32, 1024);
+        for message in messages() {
+            writer.send(&message).unwrap();
+        }
+        let mut src = BytesMut::from(&writer.get_ref()[..]);
+        let mut codec = ScaleCodec::<Message, _>::new(FixedU32, 1024);
+        for message in messages() {
+            assert_eq!(codec.decode(&mut src).unwrap(), Some(message));
+        }
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn frames_encoded_by_the_codec_are_read_by_frame_readers() {
+        let mut dst = BytesMut::new();
+        let mut codec = ScaleCodec::new(FixedU32, 1024);
+        for message in messages() {
+            codec.encode(message, &mut dst).unwrap();
+        }
+        let mut reader = FrameReader::with_length_prefix(&dst[..], FixedU32, 1024);
+        for message in messages() {
+            assert_eq!(reader.recv::<Message>().unwrap(), Some(message));
+        }
+        assert_eq!(reader.recv::<Message>().unwrap(), None);
+    }
+
+    #[test]
+    fn mixing_prefixes_fails() {
+        let message = Message { id: 1, payload: vec![], note: None };
+        let mut compact = BytesMut::new();
+        ScaleCodec::new(Compact, 64).encode(message.clone(), &mut compact).unwrap();
+        let result = ScaleCodec::<Message, _>::new(FixedU32, 64).decode(&mut compact);
+        assert!(matches!(result, Err(Error::FrameTooLarge { len: 280, max: 64 })));
+        let mut fixed = BytesMut::new();
+        ScaleCodec::new(FixedU32, 64).encode(message, &mut fixed).unwrap();
+        let result = ScaleCodec::<Message, _>::new(Compact, 64).decode(&mut fixed);
+        assert!(matches!(result, Err(Error::Input(_))));
+    }
+}
@@ -0,0 +1,589 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! `scale-info` metadata synthesized from the structure of `serde` types
+//!
+//! Types that only implement `Serialize`/`Deserialize` have no `TypeInfo` implementation, but
+//! `serde-reflection` can trace their `Deserialize` implementation to find out how they are
+//! structured. [`type_info_of`] turns this structure into the portable metadata of the types as
+//! `Wrap` encodes them:
+//!
+//! - Structs and tuple structs become composites, enums become variants.
+//! - Sequences, tuples, arrays and primitives map to the corresponding definitions.
+//! - Options and maps are described like `scale-info` describes `Option` and `BTreeMap`.
+//! - Recursive types refer to themselves by id.
+//!
+//! ```rust
+//! use scale_info::TypeDef;
+//! use serde::Deserialize;
+//!
+//! #[derive(Deserialize)]
+//! struct Transfer {
+//!     to: String,
+//!     amount: u64,
+//! }
+//!
+//! let registry = serde_scale_wrap::type_info::type_info_of::<Transfer>()?;
+//! let transfer = registry.resolve(0).unwrap();
+//! assert_eq!(transfer.path().segments(), ["Transfer"]);
+//! assert!(matches!(transfer.type_def(), TypeDef::Composite(_)));
+//! # Ok::<_, serde_scale_wrap::type_info::ReflectionError>(())
+//! ```
+//!
+//! Some information is not available through `serde`, and some types cannot be described. Paths
+//! only hold the name the type is known as to `serde`, without any module, and fields have no
+//! type name. Generic types have no type parameters, and two instantiations of a generic type that
+//! differ fail with [`ReflectionError::Tracing`] as they are known under the same name. Types
+//! without a SCALE encoding (e.g. `u128`, or floating point numbers without the `float` feature)
+//! and types deserialized through `deserialize_any` (e.g. `#[serde(untagged)]` enums) fail too.
+//!
+//! Enums are traced one variant at a time, which `serde-reflection` only does for the outermost
+//! type. Enums nested in other types need to be traced first with [`TypeReflector::trace`].
+//!
+//! Types that do implement `TypeInfo` (e.g. derived next to `Serialize` and `Deserialize`) describe
+//! their own structure, and `Wrap<T>` implements `TypeInfo` like `T` does, e.g. to key and value
+//! storage items whose metadata is generated statically.
+
+use core::convert::TryFrom;
+use parity_scale_codec::{Compact, Decode, Encode};
+use crate::Wrap;
+use scale_info::{PortableRegistry, TypeInfo};
+use serde::de::DeserializeOwned;
+use serde_reflection::{
+    ContainerFormat, Format, Named, Registry, Tracer, TracerConfig, VariantFormat,
+};
+use std::{
+    fmt::{self, Display},
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+/// Describes `Wrap<T>` as `T`, whose `TypeInfo` implementation must match its `serde` structure
+impl<T: TypeInfo + 'static> TypeInfo for Wrap<T> {
+    type Identity = T;
+
+    fn type_info() -> scale_info::Type {
+        T::type_info()
+    }
+}
+
+/// Returns the metadata of `T` in a registry where it has the id 0
+///
+/// This is `TypeReflector::new().type_info_of::<T>()`.
+pub fn type_info_of<T: DeserializeOwned>() -> Result<PortableRegistry, ReflectionError> {
+    TypeReflector::new().type_info_of::<T>()
+}
+
+/// Tracer of `Deserialize` implementations, synthesizing `scale-info` metadata
+///
+/// ```rust
+/// use serde::Deserialize;
+/// use serde_scale_wrap::type_info::{type_info_of, TypeReflector};
+///
+/// #[derive(Deserialize)]
+/// enum Shape {
+///     Circle { radius: u32 },
+///     Square(u32),
+/// }
+///
+/// #[derive(Deserialize)]
+/// struct Scene {
+///     shapes: Vec<Shape>,
+/// }
+///
+/// assert!(type_info_of::<Scene>().is_err());
+/// let registry = TypeReflector::new().trace::<Shape>()?.type_info_of::<Scene>()?;
+/// assert_eq!(registry.types().len(), 4);
+/// # Ok::<_, serde_scale_wrap::type_info::ReflectionError>(())
+/// ```
+pub struct TypeReflector {
+    tracer: Tracer,
+}
+
+impl TypeReflector {
+    /// Returns a reflector that has not traced any type yet
+    pub fn new() -> Self {
+        TypeReflector { tracer: Tracer::new(TracerConfig::default()) }
+    }
+
+    /// Traces `T`, going through all its variants if it is an enum
+    ///
+    /// The metadata of `T` ends up in the registry only if the type passed to
+    /// [`TypeReflector::type_info_of`] refers to it.
+    pub fn trace<T: DeserializeOwned>(mut self) -> Result<Self, ReflectionError> {
+        self.tracer.trace_simple_type::<T>().map_err(ReflectionError::Tracing)?;
+        Ok(self)
+    }
+
+    /// Traces `T` and returns its metadata in a registry where it has the id 0
+    pub fn type_info_of<T>(mut self) -> Result<PortableRegistry, ReflectionError>
+    where
+        T: DeserializeOwned,
+    {
+        let (format, _) =
+            self.tracer.trace_simple_type::<T>().map_err(ReflectionError::Tracing)?;
+        let containers = self.tracer.registry().map_err(ReflectionError::Tracing)?;
+        let mut builder =
+            RegistryBuilder { containers: &containers, ids: Vec::new(), types: Vec::new() };
+        builder.id_of(&format)?;
+        let types = builder.types.into_iter().enumerate().map(|(id, ty)| TypeEntry {
+            id: Compact(id as u32),
+            ty: ty.expect("Type definitions are built before returning"),
+        });
+        let encoded = types.collect::<Vec<_>>().encode();
+        let registry = PortableRegistry::decode(&mut &*encoded)
+            .expect("Synthesized metadata is a valid encoding of a registry");
+        Ok(registry)
+    }
+}
+
+impl Default for TypeReflector {
+    fn default() -> Self {
+        TypeReflector::new()
+    }
+}
+
+/// Errors returned when synthesizing metadata fails
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ReflectionError {
+    /// Tracing the `Deserialize` implementation failed (e.g. it goes through `deserialize_any`, or
+    /// holds an enum that was not traced)
+    Tracing(serde_reflection::Error),
+    /// The type holds values that `Wrap` cannot encode
+    Unsupported {
+        /// Description of the values
+        what: &'static str,
+    },
+}
+
+impl Display for ReflectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReflectionError::Tracing(e) => write!(f, "Tracing failed: {}", e),
+            ReflectionError::Unsupported { what } => write!(f, "Cannot describe {}", what),
+        }
+    }
+}
+
+impl std::error::Error for ReflectionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReflectionError::Tracing(e) => Some(e),
+            ReflectionError::Unsupported { .. } => None,
+        }
+    }
+}
+
+/// Builder of type definitions, assigning ids in the order the types are first referred to
+struct RegistryBuilder<'a> {
+    containers: &'a Registry,
+    ids: Vec<(Format, u32)>,
+    types: Vec<Option<Type>>,
+}
+
+impl RegistryBuilder<'_> {
+    fn id_of(&mut self, format: &Format) -> Result<u32, ReflectionError> {
+        if let Some((_, id)) = self.ids.iter().find(|(f, _)| f == format) {
+            return Ok(*id);
+        }
+        let id = self.types.len() as u32;
+        // The id is assigned before building the definition so that recursive types can refer
+        // to themselves.
+        self.ids.push((format.clone(), id));
+        self.types.push(None);
+        let ty = self.build(format)?;
+        self.types[id as usize] = Some(ty);
+        Ok(id)
+    }
+
+    fn build(&mut self, format: &Format) -> Result<Type, ReflectionError> {
+        let primitive = |primitive| Ok(Type::anonymous(TypeDef::Primitive(primitive)));
+        match format {
+            Format::TypeName(name) => self.build_container(name),
+            Format::Unit => Ok(Type::anonymous(TypeDef::Tuple(Vec::new()))),
+            Format::Bool => primitive(Primitive::Bool),
+            Format::I8 => primitive(Primitive::I8),
+            Format::I16 => primitive(Primitive::I16),
+            Format::I32 => primitive(Primitive::I32),
+            Format::I64 => primitive(Primitive::I64),
+            Format::U8 => primitive(Primitive::U8),
+            Format::U16 => primitive(Primitive::U16),
+            Format::U32 => primitive(Primitive::U32),
+            Format::U64 => primitive(Primitive::U64),
+            Format::I128 | Format::U128 => Err(unsupported("128-bit integers")),
+            #[cfg(feature = "float")]
+            Format::F32 => primitive(Primitive::U32),
+            #[cfg(feature = "float")]
+            Format::F64 => primitive(Primitive::U64),
+            #[cfg(not(feature = "float"))]
+            Format::F32 | Format::F64 => Err(unsupported("floating point numbers")),
+            Format::Char => primitive(Primitive::Char),
+            Format::Str => primitive(Primitive::Str),
+            Format::Bytes => {
+                let item = self.id_of(&Format::U8)?;
+                Ok(Type::anonymous(TypeDef::Sequence(Compact(item))))
+            }
+            Format::Option(inner) => {
+                let some = self.field(None, inner)?;
+                Ok(Type {
+                    path: vec!["Option".into()],
+                    type_params: vec![self.param("T", inner)?],
+                    def: TypeDef::Variant(vec![
+                        Variant::new("None", Vec::new(), 0),
+                        Variant::new("Some", vec![some], 1),
+                    ]),
+                    docs: Vec::new(),
+                })
+            }
+            Format::Seq(item) => {
+                let item = self.id_of(item)?;
+                Ok(Type::anonymous(TypeDef::Sequence(Compact(item))))
+            }
+            Format::Map { key, value } => {
+                // Maps are encoded as sequences of pairs.
+                let pair = Format::Tuple(vec![(**key).clone(), (**value).clone()]);
+                let pairs = self.field(None, &Format::Seq(Box::new(pair)))?;
+                let type_params = vec![self.param("K", key)?, self.param("V", value)?];
+                Ok(Type {
+                    path: vec!["BTreeMap".into()],
+                    type_params,
+                    def: TypeDef::Composite(vec![pairs]),
+                    docs: Vec::new(),
+                })
+            }
+            Format::Tuple(items) => {
+                let items = items.iter().map(|item| self.id_of(item).map(Compact));
+                Ok(Type::anonymous(TypeDef::Tuple(items.collect::<Result<_, _>>()?)))
+            }
+            Format::TupleArray { content, size } => {
+                let len = u32::try_from(*size).map_err(|_| unsupported("arrays that long"))?;
+                let item = self.id_of(content)?;
+                Ok(Type::anonymous(TypeDef::Array(len, Compact(item))))
+            }
+            Format::Variable(_) => Err(unsupported("types that were not fully traced")),
+        }
+    }
+
+    fn build_container(&mut self, name: &str) -> Result<Type, ReflectionError> {
+        let container = self
+            .containers
+            .get(name)
+            .ok_or_else(|| unsupported("types that were not fully traced"))?;
+        let def = match container {
+            ContainerFormat::UnitStruct => TypeDef::Composite(Vec::new()),
+            ContainerFormat::NewTypeStruct(inner) => {
+                TypeDef::Composite(self.unnamed_fields(core::slice::from_ref(&**inner))?)
+            }
+            ContainerFormat::TupleStruct(items) => TypeDef::Composite(self.unnamed_fields(items)?),
+            ContainerFormat::Struct(fields) => TypeDef::Composite(self.named_fields(fields)?),
+            ContainerFormat::Enum(variants) => {
+                let variants = variants.iter().map(|(&index, variant)| {
+                    let index = u8::try_from(index)
+                        .map_err(|_| unsupported("enums with over 256 variants"))?;
+                    let fields = match &variant.value {
+                        VariantFormat::Unit => Vec::new(),
+                        VariantFormat::NewType(inner) => {
+                            self.unnamed_fields(core::slice::from_ref(&**inner))?
+                        }
+                        VariantFormat::Tuple(items) => self.unnamed_fields(items)?,
+                        VariantFormat::Struct(fields) => self.named_fields(fields)?,
+                        VariantFormat::Variable(_) => {
+                            return Err(unsupported("types that were not fully traced"))
+                        }
+                    };
+                    Ok(Variant::new(&variant.name, fields, index))
+                });
+                TypeDef::Variant(variants.collect::<Result<_, _>>()?)
+            }
+        };
+        Ok(Type { path: vec![name.to_string()], type_params: Vec::new(), def, docs: Vec::new() })
+    }
+
+    fn unnamed_fields(&mut self, items: &[Format]) -> Result<Vec<Field>, ReflectionError> {
+        items.iter().map(|item| self.field(None, item)).collect()
+    }
+
+    fn named_fields(&mut self, fields: &[Named<Format>]) -> Result<Vec<Field>, ReflectionError> {
+        fields.iter().map(|field| self.field(Some(field.name.clone()), &field.value)).collect()
+    }
+
+    fn field(&mut self, name: Option<String>, format: &Format) -> Result<Field, ReflectionError> {
+        let ty = Compact(self.id_of(format)?);
+        Ok(Field { name, ty, type_name: None, docs: Vec::new() })
+    }
+
+    fn param(&mut self, name: &str, format: &Format) -> Result<TypeParameter, ReflectionError> {
+        let ty = Some(Compact(self.id_of(format)?));
+        Ok(TypeParameter { name: name.into(), ty })
+    }
+}
+
+fn unsupported(what: &'static str) -> ReflectionError {
+    ReflectionError::Unsupported { what }
+}
+
+// The following types mirror the encoding of the portable types of `scale-info`, whose
+// definitions cannot be built directly.
+
+#[derive(Encode)]
+struct TypeEntry {
+    id: Compact<u32>,
+    ty: Type,
+}
+
+#[derive(Encode)]
+struct Type {
+    path: Vec<String>,
+    type_params: Vec<TypeParameter>,
+    def: TypeDef,
+    docs: Vec<String>,
+}
+
+impl Type {
+    fn anonymous(def: TypeDef) -> Self {
+        Type { path: Vec::new(), type_params: Vec::new(), def, docs: Vec::new() }
+    }
+}
+
+#[derive(Encode)]
+struct TypeParameter {
+    name: String,
+    ty: Option<Compact<u32>>,
+}
+
+#[derive(Encode)]
+enum TypeDef {
+    #[codec(index = 0)]
+    Composite(Vec<Field>),
+    #[codec(index = 1)]
+    Variant(Vec<Variant>),
+    #[codec(index = 2)]
+    Sequence(Compact<u32>),
+    #[codec(index = 3)]
+    Array(u32, Compact<u32>),
+    #[codec(index = 4)]
+    Tuple(Vec<Compact<u32>>),
+    #[codec(index = 5)]
+    Primitive(Primitive),
+}
+
+#[derive(Encode)]
+struct Field {
+    name: Option<String>,
+    ty: Compact<u32>,
+    type_name: Option<String>,
+    docs: Vec<String>,
+}
+
+#[derive(Encode)]
+struct Variant {
+    name: String,
+    fields: Vec<Field>,
+    index: u8,
+    docs: Vec<String>,
+}
+
+impl Variant {
+    fn new(name: &str, fields: Vec<Field>, index: u8) -> Self {
+        Variant { name: name.into(), fields, index, docs: Vec::new() }
+    }
+}
+
+#[derive(Clone, Copy, Encode)]
+enum Primitive {
+    #[codec(index = 0)]
+    Bool,
+    #[codec(index = 1)]
+    Char,
+    #[codec(index = 2)]
+    Str,
+    #[codec(index = 3)]
+    U8,
+    #[codec(index = 4)]
+    U16,
+    #[codec(index = 5)]
+    U32,
+    #[codec(index = 6)]
+    U64,
+    #[codec(index = 9)]
+    I8,
+    #[codec(index = 10)]
+    I16,
+    #[codec(index = 11)]
+    I32,
+    #[codec(index = 12)]
+    I64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{type_info_of, ReflectionError, TypeReflector};
+    use scale_info::{
+        form::PortableForm, Field, MetaType, PortableRegistry, Registry, TypeDef, TypeInfo,
+    };
+    use serde::{Deserialize, Serialize};
+    use std::{boxed::Box, collections::BTreeMap, string::String, vec::Vec};
+
+    #[derive(Deserialize, Serialize, TypeInfo)]
+    struct Block {
+        header: Header,
+        events: Vec<Event>,
+        parent: [u8; 4],
+        author: Option<String>,
+        tags: BTreeMap<u16, Vec<u8>>,
+        pair: (bool, char),
+        nothing: (),
+        id: Id,
+        marker: Marker,
+    }
+
+    #[derive(Deserialize, Serialize, TypeInfo)]
+    struct Header {
+        version: u8,
+        height: u64,
+        delta: i32,
+    }
+
+    #[derive(Deserialize, Serialize, TypeInfo)]
+    enum Event {
+        Transfer { from: u32, to: u32, amount: u64 },
+        Note(String),
+        Pair(u8, i16),
+        Tick,
+    }
+
+    #[derive(Deserialize, Serialize, TypeInfo)]
+    struct Id(u32);
+
+    #[derive(Deserialize, Serialize, TypeInfo)]
+    struct Marker;
+
+    #[derive(Deserialize, Serialize, TypeInfo)]
+    enum List {
+        Nil,
+        Cons(u8, Box<List>),
+    }
+
+    fn derived<T: TypeInfo + 'static>() -> (PortableRegistry, u32) {
+        let mut registry = Registry::new();
+        let id = registry.register_type(&MetaType::new::<T>()).id();
+        (registry.into(), id)
+    }
+
+    /// Asserts that the types have the same shape, ignoring module paths, field type names and
+    /// docs, which `serde` does not expose
+    fn assert_same_shape(
+        (reflected, reflected_id): (&PortableRegistry, u32),
+        (derived, derived_id): (&PortableRegistry, u32),
+        compared: &mut Vec<(u32, u32)>,
+    ) {
+        if compared.contains(&(reflected_id, derived_id)) {
+            return;
+        }
+        compared.push((reflected_id, derived_id));
+        let left = reflected.resolve(reflected_id).unwrap();
+        let right = derived.resolve(derived_id).unwrap();
+        assert_eq!(left.path().ident(), right.path().ident());
+        let mut same_shapes = |left: u32, right: u32| {
+            assert_same_shape((reflected, left), (derived, right), compared)
+        };
+        let mut same_fields = |left: &[Field<PortableForm>], right: &[Field<PortableForm>]| {
+            assert_eq!(left.len(), right.len());
+            for (left, right) in left.iter().zip(right) {
+                assert_eq!(left.name(), right.name());
+                same_shapes(left.ty().id(), right.ty().id());
+            }
+        };
+        match (left.type_def(), right.type_def()) {
+            (TypeDef::Composite(left), TypeDef::Composite(right)) => {
+                same_fields(left.fields(), right.fields())
+            }
+            (TypeDef::Variant(left), TypeDef::Variant(right)) => {
+                assert_eq!(left.variants().len(), right.variants().len());
+                for (left, right) in left.variants().iter().zip(right.variants()) {
+                    assert_eq!((left.name(), left.index()), (right.name(), right.index()));
+                    same_fields(left.fields(), right.fields());
+                }
+            }
+            (TypeDef::Sequence(left), TypeDef::Sequence(right)) => {
+                same_shapes(left.type_param().id(), right.type_param().id())
+            }
+            (TypeDef::Array(left), TypeDef::Array(right)) => {
+                assert_eq!(left.len(), right.len());
+                same_shapes(left.type_param().id(), right.type_param().id());
+            }
+            (TypeDef::Tuple(left), TypeDef::Tuple(right)) => {
+                assert_eq!(left.fields().len(), right.fields().len());
+                for (left, right) in left.fields().iter().zip(right.fields()) {
+                    same_shapes(left.id(), right.id());
+                }
+            }
+            (TypeDef::Primitive(left), TypeDef::Primitive(right)) => assert_eq!(left, right),
+            (left, right) => panic!("{:?} and {:?} differ", left, right),
+        }
+        let left = left.type_params();
+        let right = right.type_params();
+        assert_eq!(left.len(), right.len());
+        for (left, right) in left.iter().zip(right) {
+            assert_eq!(left.name(), right.name());
+            same_shapes(left.ty().unwrap().id(), right.ty().unwrap().id());
+        }
+    }
+
+    #[test]
+    fn nested_struct_is_described_like_derive_does() {
+        let reflected = TypeReflector::new().trace::<Event>().unwrap();
+        let reflected = reflected.type_info_of::<Block>().unwrap();
+        let (derived, id) = derived::<Block>();
+        assert_same_shape((&reflected, 0), (&derived, id), &mut Vec::new());
+        assert_eq!(reflected.types().len(), derived.types().len());
+    }
+
+    #[test]
+    fn recursive_enum_refers_to_itself() {
+        let reflected = type_info_of::<List>().unwrap();
+        let (derived, id) = derived::<List>();
+        assert_same_shape((&reflected, 0), (&derived, id), &mut Vec::new());
+        assert_eq!(reflected.types().len(), 2);
+        let cons = match reflected.resolve(0).unwrap().type_def() {
+            TypeDef::Variant(list) => &list.variants()[1],
+            def => panic!("Unexpected definition {:?}", def),
+        };
+        assert_eq!(cons.fields()[1].ty().id(), 0);
+    }
+
+    #[test]
+    fn untraced_nested_enum_is_rejected() {
+        let result = type_info_of::<Block>();
+        assert!(matches!(result, Err(ReflectionError::Tracing(_))));
+    }
+
+    #[test]
+    fn values_without_scale_encoding_are_rejected() {
+        let result = type_info_of::<(u8, u128)>();
+        assert!(matches!(result, Err(ReflectionError::Unsupported { what: "128-bit integers" })));
+        #[derive(Deserialize, Serialize)]
+        #[serde(untagged)]
+        enum Untagged {
+            Number(u32),
+            Text(String),
+        }
+        assert!(matches!(type_info_of::<Untagged>(), Err(ReflectionError::Tracing(_))));
+    }
+
+    #[cfg(not(feature = "float"))]
+    #[test]
+    fn floats_are_rejected() {
+        let result = type_info_of::<Vec<f32>>();
+        assert!(matches!(result, Err(ReflectionError::Unsupported { .. })));
+    }
+
+    #[cfg(feature = "float")]
+    #[test]
+    fn floats_are_described_as_their_bits() {
+        let reflected = type_info_of::<(f32, f64)>().unwrap();
+        let (derived, id) = derived::<(u32, u64)>();
+        assert_same_shape((&reflected, 0), (&derived, id), &mut Vec::new());
+    }
+}
@@ -0,0 +1,469 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Encodings prefixed with a fingerprint of the structure of their type
+//!
+//! Bytes encoding a value of one type may happen to decode as a value of another type when their
+//! layouts line up. [`TypedWrap`] encodes its value like [`Wrap`](crate::Wrap) after a 4-byte
+//! fingerprint of the type, and decoding fails with [`Error::FingerprintMismatch`] if the
+//! fingerprint in the input is not the one of the type being decoded.
+//!
+//! ```rust
+//! use parity_scale_codec::{Decode, Encode};
+//! use serde::{Deserialize, Serialize};
+//! use serde_scale_wrap::typed::TypedWrap;
+//!
+//! #[derive(Debug, Deserialize, PartialEq, Serialize)]
+//! struct Deposit {
+//!     account: u32,
+//!     amount: u32,
+//! }
+//!
+//! #[derive(Debug, Deserialize, PartialEq, Serialize)]
+//! struct Withdrawal {
+//!     amount: u64,
+//! }
+//!
+//! let encoded = TypedWrap(Deposit { account: 1, amount: 2 }).encode();
+//! assert_eq!(TypedWrap::<Deposit>::decode(&mut &encoded[..])?.0.amount, 2);
+//! assert!(TypedWrap::<Withdrawal>::decode(&mut &encoded[..]).is_err());
+//! # Ok::<_, parity_scale_codec::Error>(())
+//! ```
+//!
+//! # Fingerprint
+//!
+//! The fingerprint only depends on the structure of the type as seen by `serde`, so it is the
+//! same across compilations, platforms and versions of this crate. It is computed by tracing the
+//! `Deserialize` implementation of the type with `serde-reflection`, like the `type_info` module
+//! does, and is the 32-bit FNV-1a hash of the SCALE encoding of a description of the structure.
+//! It is written in little-endian order. The description of a type is a tag byte, followed by:
+//!
+//! | Tag  | Type           | Followed by                                                     |
+//! |------|----------------|-----------------------------------------------------------------|
+//! | 0    | `()`           |                                                                 |
+//! | 1    | `bool`         |                                                                 |
+//! | 2-6  | `i8` to `i128` |                                                                 |
+//! | 7-11 | `u8` to `u128` |                                                                 |
+//! | 12   | `f32`          |                                                                 |
+//! | 13   | `f64`          |                                                                 |
+//! | 14   | `char`         |                                                                 |
+//! | 15   | String         |                                                                 |
+//! | 16   | Bytes          |                                                                 |
+//! | 17   | Option         | Description of the content                                      |
+//! | 18   | Sequence       | Description of the items                                        |
+//! | 19   | Map            | Descriptions of the keys and values                             |
+//! | 20   | Tuple          | Number of items and their descriptions                          |
+//! | 21   | Array          | Length and description of the items                             |
+//! | 22   | Unit struct    |                                                                 |
+//! | 23   | Newtype struct | Description of the content                                      |
+//! | 24   | Tuple struct   | Number of fields and their descriptions                         |
+//! | 25   | Struct         | Number of fields, and the name and description of each          |
+//! | 26   | Enum           | Number of variants, and the index, name and description of each |
+//! | 27   | Recursion      | Number of enclosing structs and enums between it and its type   |
+//!
+//! Variants are described by a tag byte, 0 for unit variants, 1 for newtype variants followed by
+//! the description of the content, and 2 and 3 for tuple and struct variants followed by their
+//! fields like tuple structs and structs. Numbers, lengths and indices are compact-encoded, and
+//! names are encoded as strings.
+//!
+//! ⚠ Names of structs and enums are not part of the fingerprint, so that renaming or moving a type
+//! keeps its fingerprint. Two types with the same fields, variants and names thereof are thus
+//! indistinguishable (e.g. `struct Meters(u32)` and `struct Seconds(u32)`).
+//!
+//! Types that cannot be traced cannot be fingerprinted either, like types deserialized through
+//! `deserialize_any` and types holding enums other than the outermost type, which
+//! `serde-reflection` only traces one variant of. Encoding a `TypedWrap` of such a type panics
+//! and decoding it fails. [`fingerprint_of`] returns the error instead.
+//!
+//! Tracing happens every time a `TypedWrap` is encoded or decoded, which costs more than encoding
+//! or decoding most values.
+
+use crate::{serialize_to, Error};
+use parity_scale_codec::{Compact, Decode, Encode, EncodeLike, Input, Output};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_reflection::{
+    ContainerFormat, Format, Named, Registry, Tracer, TracerConfig, VariantFormat,
+};
+use std::vec::Vec;
+
+/// Wrapper encoding its value like `Wrap`, prefixed with the fingerprint of `T`
+///
+/// See the [module documentation](self) for how the fingerprint is computed.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct TypedWrap<T>(pub T);
+
+impl<T> TypedWrap<T> {
+    /// Returns the wrapped value
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for TypedWrap<T> {
+    fn from(value: T) -> Self {
+        TypedWrap(value)
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Encode for TypedWrap<T> {
+    /// # Panics
+    /// Panics if `T` cannot be fingerprinted or if the serializer returns an error.
+    fn encode_to<O: Output + ?Sized>(&self, dst: &mut O) {
+        let fingerprint = fingerprint_of::<T>().unwrap_or_else(|e| {
+            panic!("Cannot fingerprint {}: {}", core::any::type_name::<T>(), e)
+        });
+        fingerprint.encode_to(dst);
+        serialize_to(&self.0, dst);
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> EncodeLike for TypedWrap<T> {}
+
+impl<T: DeserializeOwned> Decode for TypedWrap<T> {
+    fn decode<I: Input>(input: &mut I) -> Result<Self, parity_scale_codec::Error> {
+        let expected = fingerprint_of::<T>().map_err(|e| {
+            parity_scale_codec::Error::from("Type cannot be fingerprinted").chain(e.to_string())
+        })?;
+        let found = u32::decode(input)?;
+        if found != expected {
+            return Err(Error::FingerprintMismatch { expected, found }.into());
+        }
+        Ok(TypedWrap(crate::deserialize_from(input)?))
+    }
+}
+
+/// Returns the fingerprint `TypedWrap<T>` prefixes encodings with
+///
+/// ```rust
+/// use serde_scale_wrap::typed::fingerprint_of;
+///
+/// assert_eq!(fingerprint_of::<(u8, String)>()?, fingerprint_of::<(u8, String)>()?);
+/// assert_ne!(fingerprint_of::<(u8, String)>()?, fingerprint_of::<(String, u8)>()?);
+/// # Ok::<_, serde_reflection::Error>(())
+/// ```
+pub fn fingerprint_of<T: DeserializeOwned>() -> Result<u32, serde_reflection::Error> {
+    // Wrap (de)serializers report themselves as human-readable, which the tracer must mimic.
+    let mut tracer = Tracer::new(TracerConfig::default().is_human_readable(true));
+    let (format, _) = tracer.trace_simple_type::<T>()?;
+    let containers = tracer.registry()?;
+    let mut describer =
+        Describer { containers: &containers, enclosing: Vec::new(), dst: Fnv1a::new() };
+    describer.format(&format)?;
+    Ok(describer.dst.0)
+}
+
+/// Writer of the description of a type, as specified in the module documentation
+struct Describer<'a> {
+    containers: &'a Registry,
+    /// Names of the structs and enums being described, from the outermost
+    enclosing: Vec<&'a str>,
+    dst: Fnv1a,
+}
+
+impl<'a> Describer<'a> {
+    fn format(&mut self, format: &'a Format) -> Result<(), serde_reflection::Error> {
+        match format {
+            Format::TypeName(name) => return self.container(name),
+            Format::Unit => self.tag(0),
+            Format::Bool => self.tag(1),
+            Format::I8 => self.tag(2),
+            Format::I16 => self.tag(3),
+            Format::I32 => self.tag(4),
+            Format::I64 => self.tag(5),
+            Format::I128 => self.tag(6),
+            Format::U8 => self.tag(7),
+            Format::U16 => self.tag(8),
+            Format::U32 => self.tag(9),
+            Format::U64 => self.tag(10),
+            Format::U128 => self.tag(11),
+            Format::F32 => self.tag(12),
+            Format::F64 => self.tag(13),
+            Format::Char => self.tag(14),
+            Format::Str => self.tag(15),
+            Format::Bytes => self.tag(16),
+            Format::Option(inner) => {
+                self.tag(17);
+                self.format(inner)?;
+            }
+            Format::Seq(item) => {
+                self.tag(18);
+                self.format(item)?;
+            }
+            Format::Map { key, value } => {
+                self.tag(19);
+                self.format(key)?;
+                self.format(value)?;
+            }
+            Format::Tuple(items) => {
+                self.tag(20);
+                self.formats(items)?;
+            }
+            Format::TupleArray { content, size } => {
+                self.tag(21);
+                self.len(*size);
+                self.format(content)?;
+            }
+            Format::Variable(_) => return Err(serde_reflection::Error::UnknownFormat),
+        }
+        Ok(())
+    }
+
+    fn container(&mut self, name: &'a str) -> Result<(), serde_reflection::Error> {
+        if let Some(depth) = self.enclosing.iter().rev().position(|&n| n == name) {
+            self.tag(27);
+            self.len(depth);
+            return Ok(());
+        }
+        let container = self
+            .containers
+            .get(name)
+            .ok_or_else(|| serde_reflection::Error::UnknownFormatInContainer(name.into()))?;
+        self.enclosing.push(name);
+        match container {
+            ContainerFormat::UnitStruct => self.tag(22),
+            ContainerFormat::NewTypeStruct(inner) => {
+                self.tag(23);
+                self.format(inner)?;
+            }
+            ContainerFormat::TupleStruct(items) => {
+                self.tag(24);
+                self.formats(items)?;
+            }
+            ContainerFormat::Struct(fields) => {
+                self.tag(25);
+                self.fields(fields)?;
+            }
+            ContainerFormat::Enum(variants) => {
+                self.tag(26);
+                self.len(variants.len());
+                for (&index, variant) in variants {
+                    Compact(index).encode_to(&mut self.dst);
+                    variant.name.encode_to(&mut self.dst);
+                    self.variant(&variant.value)?;
+                }
+            }
+        }
+        self.enclosing.pop();
+        Ok(())
+    }
+
+    fn variant(&mut self, variant: &'a VariantFormat) -> Result<(), serde_reflection::Error> {
+        match variant {
+            VariantFormat::Unit => self.tag(0),
+            VariantFormat::NewType(inner) => {
+                self.tag(1);
+                self.format(inner)?;
+            }
+            VariantFormat::Tuple(items) => {
+                self.tag(2);
+                self.formats(items)?;
+            }
+            VariantFormat::Struct(fields) => {
+                self.tag(3);
+                self.fields(fields)?;
+            }
+            VariantFormat::Variable(_) => return Err(serde_reflection::Error::UnknownFormat),
+        }
+        Ok(())
+    }
+
+    fn formats(&mut self, formats: &'a [Format]) -> Result<(), serde_reflection::Error> {
+        self.len(formats.len());
+        formats.iter().try_for_each(|format| self.format(format))
+    }
+
+    fn fields(&mut self, fields: &'a [Named<Format>]) -> Result<(), serde_reflection::Error> {
+        self.len(fields.len());
+        fields.iter().try_for_each(|field| {
+            field.name.encode_to(&mut self.dst);
+            self.format(&field.value)
+        })
+    }
+
+    fn tag(&mut self, tag: u8) {
+        self.dst.push_byte(tag);
+    }
+
+    fn len(&mut self, len: usize) {
+        Compact(len as u64).encode_to(&mut self.dst);
+    }
+}
+
+/// `Output` computing the 32-bit FNV-1a hash of the bytes written
+struct Fnv1a(u32);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Fnv1a(0x811c_9dc5)
+    }
+}
+
+impl Output for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ u32::from(b)).wrapping_mul(0x0100_0193);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fingerprint_of, Fnv1a, TypedWrap};
+    use crate::Error;
+    use parity_scale_codec::{Decode, Encode, Output};
+    use serde::{Deserialize, Serialize};
+    use std::{boxed::Box, string::String, vec, vec::Vec};
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    struct Order {
+        id: u32,
+        items: Vec<(String, u16)>,
+        note: Option<String>,
+        status: Status,
+    }
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    enum Status {
+        Pending,
+        Shipped { tracking: String },
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Refund {
+        id: u32,
+        amount: u32,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Credit {
+        id: u32,
+        amount: u32,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Payment {
+        id: u32,
+        amount: u64,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    enum List {
+        Nil,
+        Cons(u8, Box<List>),
+    }
+
+    fn order() -> Order {
+        Order {
+            id: 7,
+            items: vec![("pen".into(), 2)],
+            note: None,
+            status: Status::Shipped { tracking: "x1".into() },
+        }
+    }
+
+    #[test]
+    fn fnv1a_matches_reference_values() {
+        let hash = |bytes: &[u8]| {
+            let mut dst = Fnv1a::new();
+            dst.write(bytes);
+            dst.0
+        };
+        assert_eq!(hash(b""), 0x811c_9dc5);
+        assert_eq!(hash(b"a"), 0xe40c_292c);
+        assert_eq!(hash(b"foobar"), 0xbf9c_f968);
+    }
+
+    #[test]
+    fn values_roundtrip() {
+        let encoded = TypedWrap(Refund { id: 1, amount: 2 }).encode();
+        assert_eq!(&encoded[..4], fingerprint_of::<Refund>().unwrap().to_le_bytes());
+        assert_eq!(&encoded[4..], crate::encode(&Refund { id: 1, amount: 2 }));
+        let decoded = TypedWrap::<Refund>::decode(&mut &encoded[..]).unwrap();
+        assert_eq!(decoded.0, Refund { id: 1, amount: 2 });
+    }
+
+    #[test]
+    fn recursive_types_roundtrip() {
+        let list = List::Cons(1, Box::new(List::Cons(2, Box::new(List::Nil))));
+        let encoded = TypedWrap(list).encode();
+        let decoded = TypedWrap::<List>::decode(&mut &encoded[..]).unwrap();
+        assert_eq!(decoded.0, List::Cons(1, Box::new(List::Cons(2, Box::new(List::Nil)))));
+    }
+
+    #[test]
+    fn structurally_different_types_are_told_apart() {
+        let encoded = TypedWrap(Payment { id: 1, amount: 2 }).encode();
+        // The payload alone would decode as a `(Refund, u32)`.
+        assert!(crate::decode_all::<(Refund, u32)>(&encoded[4..]).is_ok());
+        let e = <TypedWrap<(Refund, u32)>>::decode(&mut &encoded[..]).unwrap_err();
+        let expected = Error::FingerprintMismatch {
+            expected: fingerprint_of::<(Refund, u32)>().unwrap(),
+            found: fingerprint_of::<Payment>().unwrap(),
+        };
+        assert_eq!(e, parity_scale_codec::Error::from(expected));
+    }
+
+    #[test]
+    fn mismatch_message_shows_both_fingerprints() {
+        let e = Error::FingerprintMismatch { expected: 0x0102_0304, found: 0xa0b_0c0d };
+        assert_eq!(
+            e.to_string(),
+            "Type fingerprint mismatch (expected 0x01020304, found 0x0a0b0c0d)",
+        );
+    }
+
+    #[test]
+    fn field_names_and_order_matter() {
+        #[derive(Deserialize)]
+        struct Renamed {
+            #[allow(dead_code)]
+            key: u32,
+            #[allow(dead_code)]
+            amount: u32,
+        }
+
+        #[derive(Deserialize)]
+        struct Swapped {
+            #[allow(dead_code)]
+            amount: u32,
+            #[allow(dead_code)]
+            id: u32,
+        }
+
+        let refund = fingerprint_of::<Refund>().unwrap();
+        assert_ne!(fingerprint_of::<Renamed>().unwrap(), refund);
+        assert_ne!(fingerprint_of::<Swapped>().unwrap(), refund);
+    }
+
+    /// `Refund` and `Credit` only differ by name, which is not part of the fingerprint, so their
+    /// encodings are interchangeable.
+    #[test]
+    fn structurally_identical_types_are_indistinguishable() {
+        assert_eq!(fingerprint_of::<Refund>().unwrap(), fingerprint_of::<Credit>().unwrap());
+        let encoded = TypedWrap(Refund { id: 1, amount: 2 }).encode();
+        let decoded = TypedWrap::<Credit>::decode(&mut &encoded[..]).unwrap();
+        assert_eq!(decoded.0, Credit { id: 1, amount: 2 });
+    }
+
+    /// Fingerprints are part of encodings and must not change.
+    #[test]
+    fn fingerprints_are_stable() {
+        assert_eq!(fingerprint_of::<()>().unwrap(), 0x050c_5d1f);
+        assert_eq!(fingerprint_of::<Vec<u8>>().unwrap(), 0xbaa3_2e76);
+        assert_eq!(fingerprint_of::<Refund>().unwrap(), 0x1f18_0fd7);
+        assert_eq!(fingerprint_of::<Status>().unwrap(), 0xdc06_99f4);
+        assert_eq!(fingerprint_of::<List>().unwrap(), 0xeb17_07a5);
+    }
+
+    #[test]
+    fn nested_enums_cannot_be_fingerprinted() {
+        assert!(fingerprint_of::<Order>().is_err());
+        let result = TypedWrap::<Order>::decode(&mut &TypedWrap(1u8).encode()[..]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot fingerprint")]
+    fn encoding_untraceable_types_panics() {
+        let _ = TypedWrap(order()).encode();
+    }
+}
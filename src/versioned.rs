@@ -0,0 +1,189 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+use crate::{deserialize_from, Error};
+use alloc::{boxed::Box, collections::BTreeMap};
+use core::fmt;
+use parity_scale_codec::Input;
+use serde::de::DeserializeOwned;
+
+type DecodeVersion<T> = dyn Fn(&mut dyn Input) -> Result<T, Error> + Send + Sync;
+
+/// Decoder of encodings starting with a version tag, mapping each version to a common type
+///
+/// A versioned encoding is a `u8` tag followed by the encoding of the layout of this version, as
+/// encoding `(tag, value)` with `Wrap` produces. Each version is registered with the type of its
+/// layout and a function migrating it to `T`, usually the latest layout. Decoding fails with
+/// [`Error::UnknownVersion`] if the tag is not registered.
+///
+/// ```rust
+/// use serde::{Deserialize, Serialize};
+/// use serde_scale_wrap::{Error, VersionedDecoder};
+///
+/// #[derive(Deserialize, Serialize)]
+/// struct AccountV1 {
+///     balance: u32,
+/// }
+///
+/// #[derive(Debug, Deserialize, PartialEq, Serialize)]
+/// struct Account {
+///     balance: u64,
+///     nonce: u32,
+/// }
+///
+/// let decoder = VersionedDecoder::new()
+///     .register(1, |v1: AccountV1| Account { balance: v1.balance.into(), nonce: 0 })
+///     .register(2, |account: Account| account);
+/// let old = serde_scale_wrap::encode(&(1u8, AccountV1 { balance: 10 }));
+/// assert_eq!(decoder.decode_all(&old)?, Account { balance: 10, nonce: 0 });
+/// let current = serde_scale_wrap::encode(&(2u8, Account { balance: 20, nonce: 3 }));
+/// assert_eq!(decoder.decode_all(&current)?, Account { balance: 20, nonce: 3 });
+/// assert!(matches!(decoder.decode_all(&[3]), Err(Error::UnknownVersion { version: 3 })));
+/// # Ok::<_, Error>(())
+/// ```
+pub struct VersionedDecoder<T> {
+    versions: BTreeMap<u8, Box<DecodeVersion<T>>>,
+}
+
+impl<T> VersionedDecoder<T> {
+    /// Returns a decoder without any registered version
+    pub fn new() -> Self {
+        VersionedDecoder { versions: BTreeMap::new() }
+    }
+
+    /// Registers `version`, decoding a `V` and migrating it to `T` with `migrate`
+    ///
+    /// # Panics
+    /// Panics if `version` is already registered.
+    pub fn register<V, F>(mut self, version: u8, migrate: F) -> Self
+    where
+        V: DeserializeOwned,
+        F: Fn(V) -> T + Send + Sync + 'static,
+    {
+        let decode = Box::new(move |input: &mut dyn Input| deserialize_from(input).map(&migrate));
+        let previous = self.versions.insert(version, decode);
+        assert!(previous.is_none(), "Version {} is registered twice", version);
+        self
+    }
+
+    /// Returns the registered versions in increasing order
+    pub fn versions(&self) -> impl Iterator<Item = u8> + '_ {
+        self.versions.keys().copied()
+    }
+
+    /// Decodes a version tag and the layout of this version from `input`, and migrates it to `T`
+    pub fn decode<I: Input>(&self, input: &mut I) -> Result<T, Error> {
+        let version = input.read_byte()?;
+        let decode = self.versions.get(&version).ok_or(Error::UnknownVersion { version })?;
+        decode(input)
+    }
+
+    /// Decodes a value from `bytes` like [`VersionedDecoder::decode`], failing if bytes are left
+    /// over
+    pub fn decode_all(&self, bytes: &[u8]) -> Result<T, Error> {
+        let mut input = bytes;
+        let value = self.decode(&mut input)?;
+        if input.is_empty() {
+            Ok(value)
+        } else {
+            Err(Error::TrailingBytes { len: input.len() })
+        }
+    }
+}
+
+impl<T> Default for VersionedDecoder<T> {
+    fn default() -> Self {
+        VersionedDecoder::new()
+    }
+}
+
+impl<T> fmt::Debug for VersionedDecoder<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VersionedDecoder").field("versions", &self.versions.keys()).finish()
+    }
+}
+
+#[cfg(all(test, feature = "encode", feature = "decode"))]
+mod tests {
+    use super::VersionedDecoder;
+    use crate::{encode, Error, Wrap};
+    use alloc::{format, string::String, vec::Vec};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Deserialize, Serialize)]
+    struct ConfigV1 {
+        name: String,
+    }
+
+    #[derive(Deserialize, Serialize)]
+    struct ConfigV2 {
+        name: String,
+        retries: u8,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Config {
+        name: String,
+        retries: u8,
+        peers: Vec<String>,
+    }
+
+    fn decoder() -> VersionedDecoder<Config> {
+        VersionedDecoder::new()
+            .register(1, |v1: ConfigV1| Config { name: v1.name, retries: 3, peers: Vec::new() })
+            .register(2, |v2: ConfigV2| Config {
+                name: v2.name,
+                retries: v2.retries,
+                peers: Vec::new(),
+            })
+            .register(3, |config: Config| config)
+    }
+
+    fn config(retries: u8, peers: &[&str]) -> Config {
+        let peers = peers.iter().map(|&peer| peer.into()).collect();
+        Config { name: "node".into(), retries, peers }
+    }
+
+    #[test]
+    fn each_version_is_migrated_to_the_latest() {
+        let decoder = decoder();
+        let v1 = encode(&(1u8, ConfigV1 { name: "node".into() }));
+        assert_eq!(decoder.decode_all(&v1).unwrap(), config(3, &[]));
+        let v2 = encode(&(2u8, ConfigV2 { name: "node".into(), retries: 5 }));
+        assert_eq!(decoder.decode_all(&v2).unwrap(), config(5, &[]));
+        let v3 = encode(&(3u8, config(7, &["a", "b"])));
+        assert_eq!(decoder.decode_all(&v3).unwrap(), config(7, &["a", "b"]));
+        assert_eq!(decoder.versions().collect::<Vec<_>>(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        let encoded = encode(&(4u8, config(7, &[])));
+        let result = decoder().decode_all(&encoded);
+        assert!(matches!(result, Err(Error::UnknownVersion { version: 4 })));
+        assert_eq!(format!("{}", result.unwrap_err()), "Unknown version 4");
+        let result = decoder().decode_all(&[]);
+        assert!(matches!(result, Err(Error::Input(_))));
+    }
+
+    #[test]
+    fn decoding_from_inputs_stops_after_the_value() {
+        let encoded = [encode(&(3u8, config(1, &["a"]))), encode(&Wrap(9u8))].concat();
+        let mut input = &encoded[..];
+        assert_eq!(decoder().decode(&mut input).unwrap(), config(1, &["a"]));
+        assert_eq!(input, [9]);
+        let result = decoder().decode_all(&encoded);
+        assert!(matches!(result, Err(Error::TrailingBytes { len: 1 })));
+    }
+
+    #[test]
+    fn layouts_not_matching_their_version_are_rejected() {
+        let encoded = encode(&(2u8, ConfigV1 { name: "node".into() }));
+        assert!(decoder().decode_all(&encoded).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "Version 1 is registered twice")]
+    fn versions_cannot_be_registered_twice() {
+        decoder().register(1, |config: Config| config);
+    }
+}
@@ -0,0 +1,161 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Passing encodings to and from JavaScript with `wasm-bindgen`
+//!
+//! [`encode_to_js`] and [`decode_from_js`] convert between values and `Uint8Array`s holding their
+//! encodings. Types exported with `#[wasm_bindgen]` get `toScale` and `fromScale` methods with
+//! [`impl_wasm_scale!`](crate::impl_wasm_scale):
+//!
+//! ```rust
+//! use serde::{Deserialize, Serialize};
+//! use wasm_bindgen::prelude::wasm_bindgen;
+//!
+//! #[derive(Deserialize, Serialize)]
+//! #[wasm_bindgen]
+//! pub struct Transfer {
+//!     pub amount: u64,
+//!     pub to: u32,
+//! }
+//!
+//! serde_scale_wrap::impl_wasm_scale!(Transfer);
+//! ```
+//!
+//! which is equivalent to:
+//!
+//! ```rust
+//! # use serde::{Deserialize, Serialize};
+//! use js_sys::Uint8Array;
+//! use wasm_bindgen::{prelude::wasm_bindgen, JsValue};
+//!
+//! # #[derive(Deserialize, Serialize)]
+//! # #[wasm_bindgen]
+//! # pub struct Transfer {
+//! #     pub amount: u64,
+//! #     pub to: u32,
+//! # }
+//! #[wasm_bindgen]
+//! impl Transfer {
+//!     #[wasm_bindgen(js_name = toScale)]
+//!     pub fn to_scale(&self) -> Result<Uint8Array, JsValue> {
+//!         serde_scale_wrap::wasm::try_encode_to_js(self)
+//!     }
+//!
+//!     #[wasm_bindgen(js_name = fromScale)]
+//!     pub fn from_scale(bytes: &Uint8Array) -> Result<Transfer, JsValue> {
+//!         serde_scale_wrap::wasm::decode_from_js(bytes)
+//!     }
+//! }
+//! ```
+
+use crate::{decode_all, encode, try_encode};
+use alloc::string::ToString;
+use js_sys::Uint8Array;
+use serde::{de::DeserializeOwned, Serialize};
+use wasm_bindgen::JsValue;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use ::wasm_bindgen::{prelude::wasm_bindgen, JsValue};
+    pub use js_sys::Uint8Array;
+
+    pub mod bindgen {
+        pub use ::wasm_bindgen::*;
+    }
+}
+
+/// Returns a `Uint8Array` holding the encoding of `value`
+///
+/// # Panics
+/// Panics if `value` cannot be serialized.
+pub fn encode_to_js<T: Serialize + ?Sized>(value: &T) -> Uint8Array {
+    Uint8Array::from(&*encode(value))
+}
+
+/// Returns a `Uint8Array` holding the encoding of `value`, or the error message as a JavaScript
+/// string if `value` cannot be serialized
+pub fn try_encode_to_js<T: Serialize + ?Sized>(value: &T) -> Result<Uint8Array, JsValue> {
+    try_encode(value).map(|encoded| Uint8Array::from(&*encoded)).map_err(to_js_error)
+}
+
+/// Decodes a `T` from the bytes of a `Uint8Array`, failing if bytes are left over
+///
+/// Errors are returned as JavaScript strings holding their message.
+pub fn decode_from_js<T: DeserializeOwned>(bytes: &Uint8Array) -> Result<T, JsValue> {
+    decode_all(&bytes.to_vec()).map_err(to_js_error)
+}
+
+fn to_js_error(e: crate::Error) -> JsValue {
+    JsValue::from_str(&e.to_string())
+}
+
+/// Implements `toScale` and `fromScale` methods for types exported with `#[wasm_bindgen]`
+///
+/// `toScale` returns a `Uint8Array` holding the encoding of the value, and the static
+/// `fromScale` decodes a value from a `Uint8Array`. Both throw the error message on failure. See
+/// the [`wasm`](crate::wasm) module.
+#[macro_export]
+macro_rules! impl_wasm_scale {
+    ($($t:ty),* $(,)?) => {
+        $(
+            const _: () = {
+                use $crate::wasm::__private::{wasm_bindgen, JsValue, Uint8Array};
+
+                #[wasm_bindgen(wasm_bindgen = $crate::wasm::__private::bindgen)]
+                impl $t {
+                    #[allow(missing_docs)]
+                    #[wasm_bindgen(js_name = toScale)]
+                    pub fn to_scale(&self) -> ::core::result::Result<Uint8Array, JsValue> {
+                        $crate::wasm::try_encode_to_js(self)
+                    }
+
+                    #[allow(missing_docs)]
+                    #[wasm_bindgen(js_name = fromScale)]
+                    pub fn from_scale(
+                        bytes: &Uint8Array,
+                    ) -> ::core::result::Result<$t, JsValue> {
+                        $crate::wasm::decode_from_js(bytes)
+                    }
+                }
+            };
+        )*
+    };
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod tests {
+    use super::{decode_from_js, encode_to_js, try_encode_to_js};
+    use alloc::{string::String, vec, vec::Vec};
+    use js_sys::Uint8Array;
+    use serde::{Deserialize, Serialize};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Order {
+        id: u64,
+        items: Vec<(String, u32)>,
+        note: Option<String>,
+    }
+
+    fn order() -> Order {
+        Order { id: 42, items: vec![("apple".into(), 3), ("pear".into(), 1)], note: None }
+    }
+
+    #[wasm_bindgen_test]
+    fn values_roundtrip_through_uint8_arrays() {
+        let bytes = encode_to_js(&order());
+        assert_eq!(bytes.to_vec(), crate::encode(&order()));
+        assert_eq!(decode_from_js::<Order>(&bytes).unwrap(), order());
+    }
+
+    #[wasm_bindgen_test]
+    fn errors_are_converted_to_strings() {
+        let e = try_encode_to_js(&1u128).unwrap_err();
+        assert!(e.as_string().unwrap().starts_with("Serialization failed"));
+        let e = decode_from_js::<Order>(&Uint8Array::from(&[1u8][..])).unwrap_err();
+        assert!(e.as_string().is_some());
+        let mut bytes = crate::encode(&order());
+        bytes.push(0);
+        let e = decode_from_js::<Order>(&Uint8Array::from(&*bytes)).unwrap_err();
+        assert_eq!(e.as_string().unwrap(), "Input has 1 byte(s) left over after decoding");
+    }
+}
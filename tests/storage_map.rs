@@ -0,0 +1,177 @@
+// Copyright (C) 2020 Stephane Raux. Distributed under the zlib license.
+
+//! Storage map workflow with wrapped keys and values
+//!
+//! `StorageMap` mirrors the trait bounds `frame-support` puts on the keys, values and key
+//! arguments of its storage maps, storing encoded entries under `Blake2_128Concat` hashed keys.
+
+#![cfg(all(feature = "std", feature = "encode", feature = "decode"))]
+
+use blake2::{digest::consts::U16, Blake2b, Digest};
+use parity_scale_codec::{Decode, DecodeLength, Encode, EncodeAppend, EncodeLike, FullCodec};
+use serde::{Deserialize, Serialize};
+use serde_scale_wrap::{impl_infallible_serialize, Wrap};
+use std::{collections::BTreeMap, marker::PhantomData};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[cfg_attr(feature = "scale-info", derive(scale_info::TypeInfo))]
+struct AccountId([u8; 32]);
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "scale-info", derive(scale_info::TypeInfo))]
+struct Balance {
+    free: u64,
+    reserved: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[cfg_attr(feature = "scale-info", derive(scale_info::TypeInfo))]
+enum Event {
+    Deposit(u64),
+    Memo(String),
+}
+
+// Lets `Wrap` implement `Encode` for the fixtures with the `deny-panicking-encode` feature.
+impl_infallible_serialize!(AccountId { [u8; 32] });
+impl_infallible_serialize!(Balance { u64 });
+impl_infallible_serialize!(Event { u64, String });
+
+#[cfg(feature = "max-encoded-len")]
+impl serde_scale_wrap::MaxWrappedLen for AccountId {
+    fn max_wrapped_len() -> usize {
+        <[u8; 32]>::max_wrapped_len()
+    }
+}
+
+#[cfg(feature = "max-encoded-len")]
+impl serde_scale_wrap::MaxWrappedLen for Balance {
+    fn max_wrapped_len() -> usize {
+        <(u64, u64)>::max_wrapped_len()
+    }
+}
+
+/// Hashes keys like `frame_support::Blake2_128Concat`, keeping the encoded key after its hash
+fn blake2_128_concat(key: &[u8]) -> Vec<u8> {
+    let mut hashed = Blake2b::<U16>::digest(key).to_vec();
+    hashed.extend_from_slice(key);
+    hashed
+}
+
+struct StorageMap<K, V> {
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+    types: PhantomData<(K, V)>,
+}
+
+impl<K: FullCodec, V: FullCodec> StorageMap<K, V> {
+    fn new() -> Self {
+        StorageMap { entries: BTreeMap::new(), types: PhantomData }
+    }
+
+    fn insert<KArg: EncodeLike<K>, VArg: EncodeLike<V>>(&mut self, key: KArg, value: VArg) {
+        let key = key.using_encoded(blake2_128_concat);
+        self.entries.insert(key, value.encode());
+    }
+
+    fn get<KArg: EncodeLike<K>>(&self, key: KArg) -> Option<V> {
+        let value = self.entries.get(&key.using_encoded(blake2_128_concat))?;
+        Some(V::decode(&mut &**value).unwrap())
+    }
+
+    fn contains_key<KArg: EncodeLike<K>>(&self, key: KArg) -> bool {
+        self.entries.contains_key(&key.using_encoded(blake2_128_concat))
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (K, V)> + '_ {
+        self.entries.iter().map(|(key, value)| {
+            let key = K::decode(&mut &key[16..]).unwrap();
+            (key, V::decode(&mut &**value).unwrap())
+        })
+    }
+
+    fn append<KArg, Item, ItemArg>(&mut self, key: KArg, item: ItemArg)
+    where
+        KArg: EncodeLike<K>,
+        Item: Encode,
+        ItemArg: EncodeLike<Item>,
+        V: EncodeAppend<Item = Item>,
+    {
+        let key = key.using_encoded(blake2_128_concat);
+        let value = self.entries.remove(&key).unwrap_or_default();
+        let value = V::append_or_new(value, Some(item)).unwrap();
+        self.entries.insert(key, value);
+    }
+
+    fn decode_len<KArg: EncodeLike<K>>(&self, key: KArg) -> Option<usize>
+    where
+        V: DecodeLength,
+    {
+        let value = self.entries.get(&key.using_encoded(blake2_128_concat))?;
+        Some(V::len(value).unwrap())
+    }
+}
+
+fn alice() -> AccountId {
+    AccountId([1; 32])
+}
+
+fn bob() -> AccountId {
+    AccountId([2; 32])
+}
+
+#[test]
+#[allow(clippy::needless_borrows_for_generic_args)]
+fn values_are_inserted_and_read_by_wrapped_keys() {
+    let mut balances = StorageMap::<Wrap<AccountId>, Wrap<Balance>>::new();
+    let (alice, bob) = (alice(), bob());
+    balances.insert(Wrap(alice), Wrap(Balance { free: 10, reserved: 0 }));
+    balances.insert(&Wrap(bob), &Wrap(Balance { free: 5, reserved: 2 }));
+    balances.insert(Wrap(&alice), Wrap(Balance { free: 7, reserved: 3 }));
+    assert_eq!(balances.get(Wrap(&alice)), Some(Wrap(Balance { free: 7, reserved: 3 })));
+    assert_eq!(balances.get(&Wrap(bob)), Some(Wrap(Balance { free: 5, reserved: 2 })));
+    assert!(!balances.contains_key(Wrap(AccountId([3; 32]))));
+    let mut entries = balances.iter().map(|(Wrap(k), Wrap(v))| (k, v)).collect::<Vec<_>>();
+    entries.sort_by_key(|&(k, _)| k);
+    assert_eq!(
+        entries,
+        [(alice, Balance { free: 7, reserved: 3 }), (bob, Balance { free: 5, reserved: 2 })],
+    );
+}
+
+#[test]
+#[allow(clippy::needless_borrows_for_generic_args)]
+fn wrapped_items_are_appended() {
+    let mut events = StorageMap::<Wrap<AccountId>, Vec<Wrap<Event>>>::new();
+    let alice = alice();
+    assert_eq!(events.decode_len(Wrap(&alice)), None);
+    events.append(Wrap(&alice), Wrap(Event::Deposit(3)));
+    events.append(Wrap(alice), &Wrap(Event::Memo("rent".into())));
+    assert_eq!(events.decode_len(Wrap(&alice)), Some(2));
+    assert_eq!(
+        events.get(Wrap(alice)),
+        Some(vec![Wrap(Event::Deposit(3)), Wrap(Event::Memo("rent".into()))]),
+    );
+    let encoded = events.get(Wrap(alice)).unwrap().encode();
+    let Wrap(decoded) = Wrap::<Vec<Event>>::decode(&mut &*encoded).unwrap();
+    assert_eq!(decoded, [Event::Deposit(3), Event::Memo("rent".into())]);
+}
+
+#[cfg(feature = "scale-info")]
+#[test]
+fn wrapped_types_describe_themselves_like_their_contents() {
+    use scale_info::{meta_type, TypeInfo};
+
+    assert_eq!(Wrap::<AccountId>::type_info(), AccountId::type_info());
+    assert_eq!(Wrap::<Event>::type_info(), Event::type_info());
+    assert_eq!(meta_type::<Wrap<Balance>>(), meta_type::<Balance>());
+}
+
+#[cfg(feature = "max-encoded-len")]
+#[test]
+fn wrapped_types_have_maximum_encoded_lengths() {
+    use parity_scale_codec::MaxEncodedLen;
+
+    assert_eq!(Wrap::<AccountId>::max_encoded_len(), 32);
+    assert_eq!(Wrap::<Balance>::max_encoded_len(), 16);
+    let balance = Wrap(Balance { free: u64::MAX, reserved: 1 });
+    assert_eq!(balance.encoded_size(), Wrap::<Balance>::max_encoded_len());
+}